@@ -52,9 +52,9 @@
 TARGET_CRATES := libc std green rustuv native flate arena glob term semver \
                  uuid serialize sync getopts collections num test time rand \
                  url log regex graphviz core rlibc alloc debug rustrt \
-                 unicode
+                 unicode netchan
 HOST_CRATES := syntax rustc rustdoc fourcc hexfloat regex_macros fmt_macros \
-	       rustc_llvm rustc_back
+	       rustc_llvm rustc_back rpc
 CRATES := $(TARGET_CRATES) $(HOST_CRATES)
 TOOLS := compiletest rustdoc rustc
 
@@ -85,11 +85,13 @@ DEPS_serialize := std log
 DEPS_term := std log
 DEPS_semver := std
 DEPS_uuid := std serialize
+DEPS_netchan := std libc serialize
 DEPS_sync := core alloc rustrt collections
 DEPS_getopts := std
 DEPS_collections := core alloc unicode
 DEPS_fourcc := rustc syntax std
 DEPS_hexfloat := rustc syntax std
+DEPS_rpc := rustc syntax std
 DEPS_num := std
 DEPS_test := std getopts serialize term time regex native:rust_test_helpers
 DEPS_time := std serialize
@@ -115,6 +117,11 @@ ONLY_RLIB_rand := 1
 ONLY_RLIB_collections := 1
 ONLY_RLIB_unicode := 1
 
+# The TLS channels of netchan link to OpenSSL, so they're only built on request
+ifdef CFG_ENABLE_NETCHAN_TLS
+RUSTFLAGS_netchan := --cfg tls
+endif
+
 ################################################################################
 # You should not need to edit below this line
 ################################################################################
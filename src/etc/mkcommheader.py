@@ -0,0 +1,128 @@
+#!/usr/bin/env python
+#
+# Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+# file at the top-level directory of this distribution and at
+# http://rust-lang.org/COPYRIGHT.
+#
+# Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+# http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+# <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+# option. This file may not be copied, modified, or distributed
+# except according to those terms.
+
+# This generates src/rt/rust_comm.h, the C declarations of the channel
+# functions exported by `std::c_comm`, from the `#[no_mangle]` functions of
+# src/libstd/c_comm.rs and their doc comments. Run it from the src directory
+# after changing that file; the generated header is checked in to git.
+#
+# With `--check`, the header is only compared with what would be generated,
+# failing if they differ. `make tidy` (and so `make check`) does this, so that
+# the header can't drift from the Rust definitions.
+#
+# It is designed to be compatible with Python 2 and 3.
+
+import re
+import sys
+
+SOURCE = "libstd/c_comm.rs"
+HEADER = "rt/rust_comm.h"
+
+TYPES = {
+    "Chan": "rust_chan",
+    "Port": "rust_port",
+    "u8": "uint8_t",
+    "size_t": "size_t",
+    "c_int": "int",
+}
+
+FN = re.compile(r'((?:\s*///[^\n]*\n)*)\s*#\[no_mangle\]\s*'
+                r'pub unsafe extern "C" fn (\w+)\(([^)]*)\)(?:\s*->\s*([^{]+?))?\s*\{')
+
+
+def c_type(ty):
+    ty = ty.strip()
+    if ty.startswith("*mut "):
+        inner = c_type(ty[5:])
+        return inner + ("*" if inner.endswith("*") else " *")
+    if ty.startswith("*const "):
+        inner = ty[7:]
+        if inner.startswith("*"):
+            return c_type(inner) + " const *"
+        return "const " + c_type(inner) + " *"
+    return TYPES[ty]
+
+
+def declaration(doc, name, args, ret):
+    lines = [l.strip()[3:].strip() for l in doc.strip().split("\n") if l.strip()]
+    out = ["/*"] + [(" * " + l).rstrip() for l in lines] + [" */"]
+    params = []
+    for arg in filter(None, [a.strip() for a in args.split(",")]):
+        pname, ty = arg.split(":", 1)
+        ty = c_type(ty)
+        sep = "" if ty.endswith("*") else " "
+        params.append(ty + sep + pname.strip())
+    ret = c_type(ret) if ret else "void"
+    sep = "" if ret.endswith("*") else " "
+    out.append("%s%s%s(%s);" % (ret, sep, name, ", ".join(params) or "void"))
+    return "\n".join(out)
+
+
+def generate():
+    src = open(SOURCE).read()
+    decls = [declaration(*m) for m in FN.findall(src)]
+    return """// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// This file was generated by src/etc/mkcommheader.py from
+// src/libstd/c_comm.rs. Do not edit it by hand.
+
+#ifndef RUST_COMM_H
+#define RUST_COMM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Neither half of a channel may be used by two threads at the same time.
+ * Give each sending thread its own `rust_chan` with `rust_chan_clone`, or
+ * guard a shared one with a mutex.
+ */
+typedef struct rust_chan rust_chan;
+typedef struct rust_port rust_port;
+
+""" + "\n\n".join(decls) + """
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUST_COMM_H */
+"""
+
+
+def main(args):
+    header = generate()
+    if "--check" in args:
+        if open(HEADER).read() != header:
+            sys.stderr.write("%s is out of date with %s, run "
+                             "src/etc/mkcommheader.py from src to update it\n"
+                             % (HEADER, SOURCE))
+            return 1
+        return 0
+    f = open(HEADER, "w")
+    f.write(header)
+    f.close()
+
+if __name__ == "__main__":
+    sys.exit(main(sys.argv[1:]))
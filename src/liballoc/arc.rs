@@ -22,6 +22,7 @@ use core::ops::{Drop, Deref};
 use core::option::{Some, None, Option};
 use core::ptr;
 use core::ptr::RawPtr;
+use core::uint;
 use heap::deallocate;
 
 /// An atomically reference counted wrapper for shared state.
@@ -108,12 +109,54 @@ impl<T: Share + Send> Arc<T> {
     /// destroyed.
     #[experimental = "Weak pointers may not belong in this module."]
     pub fn downgrade(&self) -> Weak<T> {
-        // See the clone() impl for why this is relaxed
-        self.inner().weak.fetch_add(1, atomics::Relaxed);
-        Weak { _ptr: self._ptr }
+        // See the clone() impl for why this is relaxed. The weak count may be
+        // locked by `is_unique`, in which case we wait for it to be unlocked.
+        let inner = self.inner();
+        loop {
+            let n = inner.weak.load(atomics::Relaxed);
+            if n == WEAK_LOCKED { continue }
+            if inner.weak.compare_and_swap(n, n + 1, atomics::Acquire) == n {
+                return Weak { _ptr: self._ptr }
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the contained value if this is the only
+    /// pointer to it, strong or weak.
+    #[inline]
+    #[experimental]
+    pub fn get_mut<'a>(&'a mut self) -> Option<&'a mut T> {
+        if self.is_unique() {
+            // See make_unique() for why this is ok
+            let inner = unsafe { &mut *self._ptr };
+            Some(&mut inner.data)
+        } else {
+            None
+        }
+    }
+
+    fn is_unique(&mut self) -> bool {
+        // Note that we hold a strong reference, which also counts as a weak
+        // reference. Checking the two counts one after the other would let
+        // another strong pointer downgrade and go away in between, leaving a
+        // weak pointer behind, so the weak count is locked while the strong
+        // count is checked. No weak pointers can be created meanwhile, and as
+        // there were none when locking, none can be upgraded either.
+        let inner = self.inner();
+        if inner.weak.compare_and_swap(1, WEAK_LOCKED, atomics::Acquire) != 1 {
+            return false
+        }
+        // Acquire, so that the uses of the data by other strong pointers which
+        // have since been dropped happen before our own
+        let unique = inner.strong.load(atomics::Acquire) == 1;
+        inner.weak.store(1, atomics::Release);
+        unique
     }
 }
 
+// The value of the weak count while `is_unique` checks the strong count
+static WEAK_LOCKED: uint = uint::MAX;
+
 #[unstable = "waiting on stability of Clone"]
 impl<T: Share + Send> Clone for Arc<T> {
     /// Duplicate an atomically reference counted wrapper.
@@ -156,11 +199,8 @@ impl<T: Send + Share + Clone> Arc<T> {
     #[inline]
     #[experimental]
     pub fn make_unique<'a>(&'a mut self) -> &'a mut T {
-        // Note that we hold a strong reference, which also counts as
-        // a weak reference, so we only clone if there is an
-        // additional reference of either kind.
-        if self.inner().strong.load(atomics::SeqCst) != 1 ||
-           self.inner().weak.load(atomics::SeqCst) != 1 {
+        // We only clone if there is an additional reference of either kind
+        if !self.is_unique() {
             *self = Arc::new(self.deref().clone())
         }
         // This unsafety is ok because we're guaranteed that the pointer
@@ -278,6 +318,7 @@ mod tests {
     use std::clone::Clone;
     use std::collections::MutableSeq;
     use std::comm::channel;
+    use std::iter::range;
     use std::mem::drop;
     use std::ops::Drop;
     use std::option::{Option, Some, None};
@@ -383,6 +424,23 @@ mod tests {
         assert!(cow1_weak.upgrade().is_none());
     }
 
+    #[test]
+    fn test_get_mut() {
+        let mut x = Arc::new(3u);
+        *x.get_mut().unwrap() = 4;
+        assert_eq!(*x, 4);
+
+        let y = x.clone();
+        assert!(x.get_mut().is_none());
+        drop(y);
+        assert!(x.get_mut().is_some());
+
+        let z = x.downgrade();
+        assert!(x.get_mut().is_none());
+        drop(z);
+        assert!(x.get_mut().is_some());
+    }
+
     #[test]
     fn test_live() {
         let x = Arc::new(5i);
@@ -429,4 +487,25 @@ mod tests {
         assert!(canary.load(atomics::Acquire) == 1);
         drop(arc_weak);
     }
+
+    #[test]
+    fn upgrade_racing_drop() {
+        // Upgrades happening while the last strong pointer goes away must
+        // either keep the data alive or fail, and never revive it
+        static mut canary: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+        for i in range(0u, 100) {
+            let arc = Arc::new(Canary(unsafe { &mut canary as *mut _ }));
+            let weak = arc.downgrade();
+            let (tx, rx) = channel();
+            task::spawn(proc() {
+                while weak.upgrade().is_some() {
+                    task::deschedule();
+                }
+                tx.send(());
+            });
+            drop(arc);
+            rx.recv();
+            assert_eq!(unsafe { canary.load(atomics::SeqCst) }, i + 1);
+        }
+    }
 }
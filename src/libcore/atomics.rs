@@ -13,6 +13,9 @@
 use intrinsics;
 use std::kinds::marker;
 use cell::UnsafeCell;
+use cmp::PartialEq;
+use kinds::Copy;
+use result::{Result, Ok, Err};
 
 /// An atomic boolean type.
 pub struct AtomicBool {
@@ -32,6 +35,42 @@ pub struct AtomicUint {
     nocopy: marker::NoCopy
 }
 
+/// An 8-bit unsigned atomic integer type
+pub struct AtomicU8 {
+    v: UnsafeCell<u8>,
+    nocopy: marker::NoCopy
+}
+
+/// A 16-bit unsigned atomic integer type
+pub struct AtomicU16 {
+    v: UnsafeCell<u16>,
+    nocopy: marker::NoCopy
+}
+
+/// A 32-bit unsigned atomic integer type
+pub struct AtomicU32 {
+    v: UnsafeCell<u32>,
+    nocopy: marker::NoCopy
+}
+
+/// A 64-bit unsigned atomic integer type
+///
+/// On targets without native 64-bit atomic instructions the operations are
+/// lowered by LLVM to calls into the platform's atomic support library.
+pub struct AtomicU64 {
+    v: UnsafeCell<u64>,
+    nocopy: marker::NoCopy
+}
+
+/// A 64-bit signed atomic integer type
+///
+/// On targets without native 64-bit atomic instructions the operations are
+/// lowered by LLVM to calls into the platform's atomic support library.
+pub struct AtomicI64 {
+    v: UnsafeCell<i64>,
+    nocopy: marker::NoCopy
+}
+
 /// An unsafe atomic pointer. Only supports basic atomic operations
 pub struct AtomicPtr<T> {
     p: UnsafeCell<uint>,
@@ -77,6 +116,21 @@ pub static INIT_ATOMIC_INT: AtomicInt =
 /// An `AtomicUint` initialized to `0`
 pub static INIT_ATOMIC_UINT: AtomicUint =
         AtomicUint { v: UnsafeCell { value: 0, }, nocopy: marker::NoCopy };
+/// An `AtomicU8` initialized to `0`
+pub static INIT_ATOMIC_U8: AtomicU8 =
+        AtomicU8 { v: UnsafeCell { value: 0 }, nocopy: marker::NoCopy };
+/// An `AtomicU16` initialized to `0`
+pub static INIT_ATOMIC_U16: AtomicU16 =
+        AtomicU16 { v: UnsafeCell { value: 0 }, nocopy: marker::NoCopy };
+/// An `AtomicU32` initialized to `0`
+pub static INIT_ATOMIC_U32: AtomicU32 =
+        AtomicU32 { v: UnsafeCell { value: 0 }, nocopy: marker::NoCopy };
+/// An `AtomicU64` initialized to `0`
+pub static INIT_ATOMIC_U64: AtomicU64 =
+        AtomicU64 { v: UnsafeCell { value: 0 }, nocopy: marker::NoCopy };
+/// An `AtomicI64` initialized to `0`
+pub static INIT_ATOMIC_I64: AtomicI64 =
+        AtomicI64 { v: UnsafeCell { value: 0 }, nocopy: marker::NoCopy };
 
 // NB: Needs to be -1 (0b11111111...) to make fetch_nand work correctly
 static UINT_TRUE: uint = -1;
@@ -161,6 +215,51 @@ impl AtomicBool {
         unsafe { atomic_compare_and_swap(self.v.get(), old, new, order) > 0 }
     }
 
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Returns `Ok` with the previous value if the value was updated, and `Err`
+    /// with the current value otherwise. `success` is the ordering of the
+    /// read-modify-write operation which happens when the values match, and
+    /// `failure` is the ordering of the load which happens when they don't.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange(&self, current: bool, new: bool,
+                            success: Ordering, failure: Ordering) -> Result<bool, bool> {
+        let current = if current { UINT_TRUE } else { 0 };
+        let new = if new { UINT_TRUE } else { 0 };
+
+        let res = unsafe {
+            atomic_compare_exchange(self.v.get(), current, new, success, failure)
+        };
+        match res { Ok(v) => Ok(v > 0), Err(v) => Err(v > 0) }
+    }
+
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Unlike `compare_exchange` this may fail even when the values match,
+    /// which is cheaper on some platforms. It is meant to be used in a loop
+    /// which retries until it succeeds.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange_weak(&self, current: bool, new: bool,
+                                 success: Ordering, failure: Ordering) -> Result<bool, bool> {
+        let current = if current { UINT_TRUE } else { 0 };
+        let new = if new { UINT_TRUE } else { 0 };
+
+        let res = unsafe {
+            atomic_compare_exchange_weak(self.v.get(), current, new, success, failure)
+        };
+        match res { Ok(v) => Ok(v > 0), Err(v) => Err(v > 0) }
+    }
+
     /// A logical "and" operation
     ///
     /// Performs a logical "and" operation on the current value and the
@@ -317,6 +416,39 @@ impl AtomicInt {
         unsafe { atomic_compare_and_swap(self.v.get(), old, new, order) }
     }
 
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Returns `Ok` with the previous value if the value was updated, and `Err`
+    /// with the current value otherwise. `success` is the ordering of the
+    /// read-modify-write operation which happens when the values match, and
+    /// `failure` is the ordering of the load which happens when they don't.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange(&self, current: int, new: int,
+                            success: Ordering, failure: Ordering) -> Result<int, int> {
+        unsafe { atomic_compare_exchange(self.v.get(), current, new, success, failure) }
+    }
+
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Unlike `compare_exchange` this may fail even when the values match,
+    /// which is cheaper on some platforms. It is meant to be used in a loop
+    /// which retries until it succeeds.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange_weak(&self, current: int, new: int,
+                                 success: Ordering, failure: Ordering) -> Result<int, int> {
+        unsafe { atomic_compare_exchange_weak(self.v.get(), current, new, success, failure) }
+    }
+
     /// Add to the current value, returning the previous
     ///
     /// # Examples
@@ -393,6 +525,22 @@ impl AtomicInt {
     pub fn fetch_xor(&self, val: int, order: Ordering) -> int {
         unsafe { atomic_xor(self.v.get(), val, order) }
     }
+
+    /// Bitwise nand with the current value, returning the previous
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomics::{AtomicInt, SeqCst};
+    ///
+    /// let foo = AtomicInt::new(0b101101);
+    /// assert_eq!(0b101101, foo.fetch_nand(0b110011, SeqCst));
+    /// assert_eq!(!0b100001, foo.load(SeqCst));
+    /// ```
+    #[inline]
+    pub fn fetch_nand(&self, val: int, order: Ordering) -> int {
+        unsafe { atomic_nand(self.v.get(), val, order) }
+    }
 }
 
 impl AtomicUint {
@@ -429,6 +577,39 @@ impl AtomicUint {
         unsafe { atomic_compare_and_swap(self.v.get(), old, new, order) }
     }
 
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Returns `Ok` with the previous value if the value was updated, and `Err`
+    /// with the current value otherwise. `success` is the ordering of the
+    /// read-modify-write operation which happens when the values match, and
+    /// `failure` is the ordering of the load which happens when they don't.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange(&self, current: uint, new: uint,
+                            success: Ordering, failure: Ordering) -> Result<uint, uint> {
+        unsafe { atomic_compare_exchange(self.v.get(), current, new, success, failure) }
+    }
+
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Unlike `compare_exchange` this may fail even when the values match,
+    /// which is cheaper on some platforms. It is meant to be used in a loop
+    /// which retries until it succeeds.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange_weak(&self, current: uint, new: uint,
+                                 success: Ordering, failure: Ordering) -> Result<uint, uint> {
+        unsafe { atomic_compare_exchange_weak(self.v.get(), current, new, success, failure) }
+    }
+
     /// Add to the current value, returning the previous
     ///
     /// # Examples
@@ -505,8 +686,134 @@ impl AtomicUint {
     pub fn fetch_xor(&self, val: uint, order: Ordering) -> uint {
         unsafe { atomic_xor(self.v.get(), val, order) }
     }
+
+    /// Bitwise nand with the current value, returning the previous
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomics::{AtomicUint, SeqCst};
+    ///
+    /// let foo = AtomicUint::new(0b101101);
+    /// assert_eq!(0b101101, foo.fetch_nand(0b110011, SeqCst));
+    /// assert_eq!(!0b100001, foo.load(SeqCst));
+    /// ```
+    #[inline]
+    pub fn fetch_nand(&self, val: uint, order: Ordering) -> uint {
+        unsafe { atomic_nand(self.v.get(), val, order) }
+    }
 }
 
+// The explicitly sized integers all share the API of `AtomicUint`
+macro_rules! sized_atomic_impl(
+    ($atomic:ident, $t:ty) => (
+        impl $atomic {
+            /// Create a new atomic integer
+            pub fn new(v: $t) -> $atomic {
+                $atomic { v: UnsafeCell::new(v), nocopy: marker::NoCopy }
+            }
+
+            /// Load the value
+            #[inline]
+            pub fn load(&self, order: Ordering) -> $t {
+                unsafe { atomic_load(self.v.get() as *const $t, order) }
+            }
+
+            /// Store the value
+            #[inline]
+            pub fn store(&self, val: $t, order: Ordering) {
+                unsafe { atomic_store(self.v.get(), val, order); }
+            }
+
+            /// Store a value, returning the old value
+            #[inline]
+            pub fn swap(&self, val: $t, order: Ordering) -> $t {
+                unsafe { atomic_swap(self.v.get(), val, order) }
+            }
+
+            /// If the current value is the same as expected, store a new value
+            ///
+            /// Compare the current value with `old`; if they are the same then
+            /// replace the current value with `new`. Return the previous value.
+            /// If the return value is equal to `old` then the value was updated.
+            #[inline]
+            pub fn compare_and_swap(&self, old: $t, new: $t,
+                                    order: Ordering) -> $t {
+                unsafe { atomic_compare_and_swap(self.v.get(), old, new, order) }
+            }
+
+            /// Store a value if the current value is the same as `current`,
+            /// as `AtomicUint::compare_exchange` does
+            #[inline]
+            pub fn compare_exchange(&self, current: $t, new: $t,
+                                    success: Ordering,
+                                    failure: Ordering) -> Result<$t, $t> {
+                unsafe {
+                    atomic_compare_exchange(self.v.get(), current, new,
+                                            success, failure)
+                }
+            }
+
+            /// Store a value if the current value is the same as `current`,
+            /// possibly failing spuriously, as
+            /// `AtomicUint::compare_exchange_weak` does
+            #[inline]
+            pub fn compare_exchange_weak(&self, current: $t, new: $t,
+                                         success: Ordering,
+                                         failure: Ordering) -> Result<$t, $t> {
+                unsafe {
+                    atomic_compare_exchange_weak(self.v.get(), current, new,
+                                                 success, failure)
+                }
+            }
+
+            /// Add to the current value, returning the previous, wrapping
+            /// around on overflow
+            #[inline]
+            pub fn fetch_add(&self, val: $t, order: Ordering) -> $t {
+                unsafe { atomic_add(self.v.get(), val, order) }
+            }
+
+            /// Subtract from the current value, returning the previous,
+            /// wrapping around on overflow
+            #[inline]
+            pub fn fetch_sub(&self, val: $t, order: Ordering) -> $t {
+                unsafe { atomic_sub(self.v.get(), val, order) }
+            }
+
+            /// Bitwise and with the current value, returning the previous
+            #[inline]
+            pub fn fetch_and(&self, val: $t, order: Ordering) -> $t {
+                unsafe { atomic_and(self.v.get(), val, order) }
+            }
+
+            /// Bitwise or with the current value, returning the previous
+            #[inline]
+            pub fn fetch_or(&self, val: $t, order: Ordering) -> $t {
+                unsafe { atomic_or(self.v.get(), val, order) }
+            }
+
+            /// Bitwise xor with the current value, returning the previous
+            #[inline]
+            pub fn fetch_xor(&self, val: $t, order: Ordering) -> $t {
+                unsafe { atomic_xor(self.v.get(), val, order) }
+            }
+
+            /// Bitwise nand with the current value, returning the previous
+            #[inline]
+            pub fn fetch_nand(&self, val: $t, order: Ordering) -> $t {
+                unsafe { atomic_nand(self.v.get(), val, order) }
+            }
+        }
+    )
+)
+
+sized_atomic_impl!(AtomicU8, u8)
+sized_atomic_impl!(AtomicU16, u16)
+sized_atomic_impl!(AtomicU32, u32)
+sized_atomic_impl!(AtomicU64, u64)
+sized_atomic_impl!(AtomicI64, i64)
+
 impl<T> AtomicPtr<T> {
     /// Create a new `AtomicPtr`
     pub fn new(p: *mut T) -> AtomicPtr<T> {
@@ -545,6 +852,47 @@ impl<T> AtomicPtr<T> {
                                     new as uint, order) as *mut T
         }
     }
+
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Returns `Ok` with the previous value if the value was updated, and `Err`
+    /// with the current value otherwise. `success` is the ordering of the
+    /// read-modify-write operation which happens when the values match, and
+    /// `failure` is the ordering of the load which happens when they don't.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange(&self, current: *mut T, new: *mut T,
+                            success: Ordering, failure: Ordering) -> Result<*mut T, *mut T> {
+        let res = unsafe {
+            atomic_compare_exchange(self.p.get(), current as uint, new as uint,
+                                    success, failure)
+        };
+        match res { Ok(p) => Ok(p as *mut T), Err(p) => Err(p as *mut T) }
+    }
+
+    /// Store a value if the current value is the same as `current`
+    ///
+    /// Unlike `compare_exchange` this may fail even when the values match,
+    /// which is cheaper on some platforms. It is meant to be used in a loop
+    /// which retries until it succeeds.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `failure` is `Release` or `AcqRel`, or is stronger than
+    /// `success`.
+    #[inline]
+    pub fn compare_exchange_weak(&self, current: *mut T, new: *mut T,
+                                 success: Ordering, failure: Ordering) -> Result<*mut T, *mut T> {
+        let res = unsafe {
+            atomic_compare_exchange_weak(self.p.get(), current as uint, new as uint,
+                                         success, failure)
+        };
+        match res { Ok(p) => Ok(p as *mut T), Err(p) => Err(p as *mut T) }
+    }
 }
 
 #[inline]
@@ -611,6 +959,86 @@ unsafe fn atomic_compare_and_swap<T>(dst: *mut T, old:T, new:T, order: Ordering)
     }
 }
 
+// Checks the failure ordering of a compare-and-exchange, which only performs a
+// load when it fails
+#[inline]
+fn check_failure_ordering(success: Ordering, failure: Ordering) {
+    match (success, failure) {
+        (_, Release) => fail!("there is no such thing as a release failure ordering"),
+        (_, AcqRel) => fail!("there is no such thing as an acquire/release failure ordering"),
+        (Relaxed, Acquire) | (Release, Acquire) |
+        (Relaxed, SeqCst) | (Release, SeqCst) | (Acquire, SeqCst) | (AcqRel, SeqCst) =>
+            fail!("a failure ordering can't be stronger than a success ordering"),
+        _ => {}
+    }
+}
+
+#[inline]
+#[cfg(not(stage0))]
+unsafe fn atomic_compare_exchange<T: Copy + PartialEq>(dst: *mut T, old: T, new: T,
+                                                       success: Ordering,
+                                                       failure: Ordering)
+                                                       -> Result<T, T> {
+    check_failure_ordering(success, failure);
+    let val = match (success, failure) {
+        (Acquire, Acquire) => intrinsics::atomic_cxchg_acq(dst, old, new),
+        (Acquire, _)       => intrinsics::atomic_cxchg_acq_failrelaxed(dst, old, new),
+        (Release, _)       => intrinsics::atomic_cxchg_rel(dst, old, new),
+        (AcqRel, Acquire)  => intrinsics::atomic_cxchg_acqrel(dst, old, new),
+        (AcqRel, _)        => intrinsics::atomic_cxchg_acqrel_failrelaxed(dst, old, new),
+        (Relaxed, _)       => intrinsics::atomic_cxchg_relaxed(dst, old, new),
+        (SeqCst, Acquire)  => intrinsics::atomic_cxchg_failacq(dst, old, new),
+        (SeqCst, Relaxed)  => intrinsics::atomic_cxchg_failrelaxed(dst, old, new),
+        (SeqCst, _)        => intrinsics::atomic_cxchg(dst, old, new),
+    };
+    if val == old { Ok(val) } else { Err(val) }
+}
+
+// NOTE(stage0): Remove after snapshot. The previous compiler only knows
+// about the default failure orderings, which are at least as strong.
+#[inline]
+#[cfg(stage0)]
+unsafe fn atomic_compare_exchange<T: Copy + PartialEq>(dst: *mut T, old: T, new: T,
+                                                       success: Ordering,
+                                                       failure: Ordering)
+                                                       -> Result<T, T> {
+    check_failure_ordering(success, failure);
+    let val = atomic_compare_and_swap(dst, old, new, success);
+    if val == old { Ok(val) } else { Err(val) }
+}
+
+#[inline]
+#[cfg(not(stage0))]
+unsafe fn atomic_compare_exchange_weak<T>(dst: *mut T, old: T, new: T,
+                                          success: Ordering,
+                                          failure: Ordering) -> Result<T, T> {
+    check_failure_ordering(success, failure);
+    let (val, ok) = match (success, failure) {
+        (Acquire, Acquire) => intrinsics::atomic_cxchgweak_acq(dst, old, new),
+        (Acquire, _)       => intrinsics::atomic_cxchgweak_acq_failrelaxed(dst, old, new),
+        (Release, _)       => intrinsics::atomic_cxchgweak_rel(dst, old, new),
+        (AcqRel, Acquire)  => intrinsics::atomic_cxchgweak_acqrel(dst, old, new),
+        (AcqRel, _)        => intrinsics::atomic_cxchgweak_acqrel_failrelaxed(dst, old, new),
+        (Relaxed, _)       => intrinsics::atomic_cxchgweak_relaxed(dst, old, new),
+        (SeqCst, Acquire)  => intrinsics::atomic_cxchgweak_failacq(dst, old, new),
+        (SeqCst, Relaxed)  => intrinsics::atomic_cxchgweak_failrelaxed(dst, old, new),
+        (SeqCst, _)        => intrinsics::atomic_cxchgweak(dst, old, new),
+    };
+    if ok { Ok(val) } else { Err(val) }
+}
+
+// NOTE(stage0): Remove after snapshot. The strong form is a valid
+// implementation of the weak one.
+#[inline]
+#[cfg(stage0)]
+unsafe fn atomic_compare_exchange_weak<T: Copy + PartialEq>(dst: *mut T, old: T,
+                                                            new: T,
+                                                            success: Ordering,
+                                                            failure: Ordering)
+                                                            -> Result<T, T> {
+    atomic_compare_exchange(dst, old, new, success, failure)
+}
+
 #[inline]
 unsafe fn atomic_and<T>(dst: *mut T, val: T, order: Ordering) -> T {
     match order {
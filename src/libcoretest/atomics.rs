@@ -20,6 +20,61 @@ fn bool_() {
     assert_eq!(a.compare_and_swap(false, true, SeqCst), false);
 }
 
+#[test]
+fn compare_exchange() {
+    let a = AtomicUint::new(1);
+    assert_eq!(a.compare_exchange(1, 2, SeqCst, Relaxed), Ok(1));
+    assert_eq!(a.compare_exchange(1, 3, AcqRel, Acquire), Err(2));
+    assert_eq!(a.compare_exchange(2, 3, Release, Relaxed), Ok(2));
+    assert_eq!(a.load(SeqCst), 3);
+
+    let b = AtomicBool::new(false);
+    assert_eq!(b.compare_exchange(true, false, SeqCst, SeqCst), Err(false));
+    assert_eq!(b.compare_exchange(false, true, Acquire, Relaxed), Ok(false));
+    assert!(b.load(SeqCst));
+
+    let mut x = 0i;
+    let p = AtomicPtr::new(0 as *mut int);
+    assert_eq!(p.compare_exchange(0 as *mut int, &mut x, SeqCst, Acquire),
+               Ok(0 as *mut int));
+    assert_eq!(p.load(SeqCst), &mut x as *mut int);
+}
+
+#[test]
+fn compare_exchange_weak() {
+    let a = AtomicInt::new(0);
+    for _ in range(0i, 10) {
+        let mut cur = a.load(Relaxed);
+        loop {
+            match a.compare_exchange_weak(cur, cur * 2 + 1, AcqRel, Relaxed) {
+                Ok(_) => break,
+                Err(v) => cur = v,
+            }
+        }
+    }
+    assert_eq!(a.load(SeqCst), 1023);
+
+    let x = AtomicU8::new(5);
+    loop {
+        if x.compare_exchange_weak(5, 6, Relaxed, Relaxed).is_ok() { break }
+    }
+    assert_eq!(x.compare_exchange_weak(5, 7, SeqCst, SeqCst), Err(6));
+}
+
+#[test]
+#[should_fail]
+fn compare_exchange_release_failure() {
+    let a = AtomicUint::new(0);
+    let _ = a.compare_exchange(0, 1, SeqCst, Release);
+}
+
+#[test]
+#[should_fail]
+fn compare_exchange_stronger_failure() {
+    let a = AtomicUint::new(0);
+    let _ = a.compare_exchange(0, 1, Acquire, SeqCst);
+}
+
 #[test]
 fn bool_and() {
     let a = AtomicBool::new(true);
@@ -48,6 +103,13 @@ fn uint_xor() {
     assert_eq!(x.load(SeqCst), 0xf731 ^ 0x137f);
 }
 
+#[test]
+fn uint_nand() {
+    let x = AtomicUint::new(0xf731);
+    assert_eq!(x.fetch_nand(0x137f, SeqCst), 0xf731);
+    assert_eq!(x.load(SeqCst), !(0xf731 & 0x137f));
+}
+
 #[test]
 fn int_and() {
     let x = AtomicInt::new(0xf731);
@@ -69,9 +131,66 @@ fn int_xor() {
     assert_eq!(x.load(SeqCst), 0xf731 ^ 0x137f);
 }
 
+#[test]
+fn int_nand() {
+    let x = AtomicInt::new(0xf731);
+    assert_eq!(x.fetch_nand(0x137f, SeqCst), 0xf731);
+    assert_eq!(x.load(SeqCst), !(0xf731 & 0x137f));
+}
+
+#[test]
+fn sized_bitwise() {
+    let x = AtomicU8::new(0b1100);
+    assert_eq!(x.fetch_or(0b0011, SeqCst), 0b1100);
+    assert_eq!(x.fetch_and(0b1010, SeqCst), 0b1111);
+    assert_eq!(x.fetch_xor(0b0110, SeqCst), 0b1010);
+    assert_eq!(x.fetch_nand(0b1111, SeqCst), 0b1100);
+    assert_eq!(x.load(SeqCst), 0b11110011);
+}
+
+#[test]
+fn sized_wrap() {
+    let x = AtomicU8::new(0xff);
+    assert_eq!(x.fetch_add(2, SeqCst), 0xff);
+    assert_eq!(x.load(SeqCst), 1);
+    assert_eq!(x.fetch_sub(2, SeqCst), 1);
+    assert_eq!(x.load(SeqCst), 0xff);
+
+    let x = AtomicU16::new(0xffff);
+    assert_eq!(x.fetch_add(1, SeqCst), 0xffff);
+    assert_eq!(x.load(SeqCst), 0);
+}
+
+#[test]
+fn sized_cas() {
+    let x = AtomicU32::new(7);
+    assert_eq!(x.compare_and_swap(6, 8, SeqCst), 7);
+    assert_eq!(x.compare_and_swap(7, 8, SeqCst), 7);
+    assert_eq!(x.swap(9, SeqCst), 8);
+    x.store(10, SeqCst);
+    assert_eq!(x.load(SeqCst), 10);
+}
+
+#[test]
+fn sized_64() {
+    let x = AtomicU64::new(1 << 40);
+    assert_eq!(x.fetch_add(1 << 40, SeqCst), 1 << 40);
+    assert_eq!(x.fetch_or(1, SeqCst), 1 << 41);
+    assert_eq!(x.load(SeqCst), (1 << 41) | 1);
+
+    let x = AtomicI64::new(-1);
+    assert_eq!(x.fetch_sub(1 << 40, SeqCst), -1);
+    assert_eq!(x.fetch_and(!1, SeqCst), -1 - (1 << 40));
+    assert_eq!(x.fetch_xor(-1, SeqCst), -2 - (1 << 40));
+    assert_eq!(x.load(SeqCst), 1 + (1 << 40));
+}
+
 static mut S_BOOL : AtomicBool = INIT_ATOMIC_BOOL;
 static mut S_INT  : AtomicInt  = INIT_ATOMIC_INT;
 static mut S_UINT : AtomicUint = INIT_ATOMIC_UINT;
+static mut S_U8   : AtomicU8   = INIT_ATOMIC_U8;
+static mut S_U64  : AtomicU64  = INIT_ATOMIC_U64;
+static mut S_I64  : AtomicI64  = INIT_ATOMIC_I64;
 
 #[test]
 fn static_init() {
@@ -79,5 +198,8 @@ fn static_init() {
         assert!(!S_BOOL.load(SeqCst));
         assert!(S_INT.load(SeqCst) == 0);
         assert!(S_UINT.load(SeqCst) == 0);
+        assert!(S_U8.load(SeqCst) == 0);
+        assert!(S_U64.load(SeqCst) == 0);
+        assert!(S_I64.load(SeqCst) == 0);
     }
 }
@@ -237,6 +237,7 @@ use std::sync::deque;
 use std::task::{TaskBuilder, Spawner};
 
 use sched::{Shutdown, Scheduler, SchedHandle, TaskFromFriend, PinnedTask, NewNeighbor};
+use sched::Retire;
 use sleeper_list::SleeperList;
 use stack::StackPool;
 use task::GreenTask;
@@ -292,7 +293,8 @@ macro_rules! green_start( ($f:ident) => (
 /// * `main` - The initial procedure to run inside of the M:N scheduling pool.
 ///            Once this procedure exits, the scheduling pool will begin to shut
 ///            down. The entire pool (and this function) will only return once
-///            all child tasks have finished executing.
+///            all child tasks have finished executing, and the shutdown hooks
+///            of `rt::hooks` have run.
 ///
 /// # Return value
 ///
@@ -319,7 +321,9 @@ pub fn start(argc: int, argv: *const *const u8,
 /// code.
 ///
 /// This function will not return until all schedulers in the associated pool
-/// have returned.
+/// have returned. The exit hooks of `rt::hooks` are called as soon as the main
+/// task returns, and the shutdown hooks are run on a task of the pool once all
+/// of the other tasks have exited.
 pub fn run(event_loop_factory: fn() -> Box<rtio::EventLoop + Send>,
            main: proc():Send) -> int {
     // Create a scheduler pool and spawn the main task into this pool. We will
@@ -338,6 +342,18 @@ pub fn run(event_loop_factory: fn() -> Box<rtio::EventLoop + Send>,
     if rx.recv().is_err() {
         os::set_exit_status(rt::DEFAULT_ERROR_CODE);
     }
+    // The other tasks may still be running, which the exit hooks may want to
+    // tell of before the pool waits for them
+    rt::hooks::run_exit_hooks();
+
+    // The shutdown hooks run once the other tasks have exited, on a task of
+    // the pool so that they have its I/O, and the tasks they spawn are waited
+    // for before the hooks they register are run.
+    pool.wait_for_tasks();
+    rt::hooks::run_shutdown_hooks_on(|hooks| {
+        let _ = TaskBuilder::new().green(&mut pool).try(hooks);
+        pool.wait_for_tasks();
+    });
 
     // Now that we're sure all tasks are dead, shut down the pool of schedulers,
     // waiting for them all to return.
@@ -481,6 +497,33 @@ impl SchedPool {
         self.handles.get_mut(idx).send(TaskFromFriend(task));
     }
 
+    /// Pins the threads of the schedulers in this pool to CPUs, the `i`th
+    /// scheduler to the CPUs numbered in `cpus(i)`, returning whether all of
+    /// them were pinned.
+    ///
+    /// This is meant to be called right after the pool is created. See
+    /// `pin_scheduler` for pinning a single scheduler.
+    pub fn pin_schedulers(&mut self, cpus: |uint| -> Vec<uint>) -> bool {
+        let mut pinned = true;
+        for i in range(0, self.handles.len()) {
+            let rx = pin(&mut self.stack_pool, self.handles.get_mut(i), cpus(i));
+            pinned = rx.recv_opt().unwrap_or(false) && pinned;
+        }
+        pinned
+    }
+
+    /// Pins the thread of the scheduler of `handle` to the CPUs numbered in
+    /// `cpus`, blocking until it's done, and returns whether the OS pinned it.
+    /// If not, the scheduler carries on as it was.
+    ///
+    /// Tasks homed on the scheduler (see `GreenTaskBuilder::green_pinned`)
+    /// then only run on those CPUs, including after they've been woken up
+    /// from another scheduler, as woken tasks are always sent back home.
+    pub fn pin_scheduler(&mut self, handle: &mut SchedHandle,
+                         cpus: Vec<uint>) -> bool {
+        pin(&mut self.stack_pool, handle, cpus).recv_opt().unwrap_or(false)
+    }
+
     /// Spawns a new scheduler into this M:N pool. A handle is returned to the
     /// scheduler for use. The scheduler will not exit as long as this handle is
     /// active.
@@ -513,6 +556,37 @@ impl SchedPool {
         return ret;
     }
 
+    /// Returns the number of schedulers in this pool.
+    pub fn num_schedulers(&self) -> uint {
+        self.handles.len()
+    }
+
+    /// Grows or shrinks this pool to `nscheds` schedulers.
+    ///
+    /// Schedulers are added as by `spawn_sched`. Those which are taken out
+    /// are the latest ones, and they're not sent any new task, but they carry
+    /// on running the tasks they already have, as well as tasks pinned to them
+    /// with `green_pinned`. Their threads exit once they have nothing left to
+    /// do.
+    ///
+    /// # Failure
+    ///
+    /// This function fails if `nscheds` is 0.
+    pub fn resize(&mut self, nscheds: uint) {
+        assert!(nscheds > 0, "a pool needs at least one scheduler");
+        while self.handles.len() < nscheds {
+            drop(self.spawn_sched());
+        }
+        while self.handles.len() > nscheds {
+            let mut handle = self.handles.pop().unwrap();
+            self.stealers.pop();
+            handle.send(Retire);
+        }
+        if self.next_friend >= self.handles.len() {
+            self.next_friend = 0;
+        }
+    }
+
     /// Consumes the pool of schedulers, waiting for all tasks to exit and all
     /// schedulers to shut down.
     ///
@@ -524,15 +598,7 @@ impl SchedPool {
     /// native tasks or extern pools will not be waited on
     pub fn shutdown(mut self) {
         self.stealers = vec![];
-
-        // Wait for everyone to exit. We may have reached a 0-task count
-        // multiple times in the past, meaning there could be several buffered
-        // messages on the `tasks_done` port. We're guaranteed that after *some*
-        // message the current task count will be 0, so we just receive in a
-        // loop until everything is totally dead.
-        while self.task_state.active() {
-            self.tasks_done.recv();
-        }
+        self.wait_for_tasks();
 
         // Now that everyone's gone, tell everything to shut down.
         for mut handle in replace(&mut self.handles, vec![]).move_iter() {
@@ -542,6 +608,18 @@ impl SchedPool {
             thread.join();
         }
     }
+
+    // Waits for every task of the pool to exit
+    fn wait_for_tasks(&mut self) {
+        // We may have reached a 0-task count multiple times in the past,
+        // meaning there could be several buffered messages on the `tasks_done`
+        // port. We're guaranteed that after *some* message the current task
+        // count will be 0, so we just receive in a loop until everything is
+        // totally dead.
+        while self.task_state.active() {
+            self.tasks_done.recv();
+        }
+    }
 }
 
 impl TaskState {
@@ -569,6 +647,18 @@ impl TaskState {
     }
 }
 
+// The thread of a scheduler is pinned by a task pinned to it, which reports
+// back whether the OS agreed
+fn pin(stack_pool: &mut StackPool, handle: &mut SchedHandle,
+       cpus: Vec<uint>) -> Receiver<bool> {
+    let (tx, rx) = channel();
+    let task = GreenTask::new(stack_pool, None, proc() {
+        tx.send(Thread::pin_current(cpus.as_slice()));
+    });
+    handle.send(PinnedTask(task));
+    rx
+}
+
 impl Drop for SchedPool {
     fn drop(&mut self) {
         if self.threads.len() > 0 {
@@ -626,4 +716,34 @@ mod test {
         assert_eq!(res.ok().unwrap(), "Success!".to_string());
         pool.shutdown();
     }
+
+    #[test]
+    fn test_resize() {
+        let mut pool = SchedPool::new(PoolConfig { threads: 2, ..PoolConfig::new() });
+        let (tx, rx) = channel();
+        for &n in [4u, 1, 3].iter() {
+            pool.resize(n);
+            assert_eq!(pool.num_schedulers(), n);
+            for _ in range(0u, 10) {
+                let tx = tx.clone();
+                TaskBuilder::new().green(&mut pool).spawn(proc() tx.send(()));
+            }
+            for _ in range(0u, 10) { rx.recv(); }
+        }
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_pinning_refused() {
+        // No CPUs can't be pinned to, which leaves the schedulers running as
+        // they were
+        let mut pool = SchedPool::new(PoolConfig::new());
+        assert!(!pool.pin_schedulers(|_| Vec::new()));
+        let mut handle = pool.spawn_sched();
+        assert!(!pool.pin_scheduler(&mut handle, Vec::new()));
+        let res = TaskBuilder::new().green_pinned(&mut pool, &mut handle).try(proc() 1i);
+        assert_eq!(res.ok().unwrap(), 1);
+        drop(handle);
+        pool.shutdown();
+    }
 }
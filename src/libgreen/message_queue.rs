@@ -8,9 +8,18 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+//! The queue of messages sent to a scheduler by other threads
+//!
+//! Every message has to be followed by a wakeup of the scheduler's event loop,
+//! which is a system call (or a lock and a signal) that costs more than the
+//! message itself. Wakeups are therefore batched: once a producer has woken up
+//! the scheduler, the others skip waking it until the scheduler has found the
+//! queue empty, as it will see their messages before it does.
+
 use alloc::arc::Arc;
 use mpsc = std::sync::mpsc_queue;
 use std::kinds::marker;
+use std::sync::atomics::{AtomicBool, SeqCst};
 
 pub enum PopResult<T> {
     Inconsistent,
@@ -19,32 +28,55 @@ pub enum PopResult<T> {
 }
 
 pub fn queue<T: Send>() -> (Consumer<T>, Producer<T>) {
-    let a = Arc::new(mpsc::Queue::new());
+    let a = Arc::new(Queue {
+        queue: mpsc::Queue::new(),
+        woken: AtomicBool::new(false),
+    });
     (Consumer { inner: a.clone(), noshare: marker::NoShare },
      Producer { inner: a, noshare: marker::NoShare })
 }
 
+struct Queue<T> {
+    queue: mpsc::Queue<T>,
+    // Set once a producer is about to wake up the consumer, and cleared once
+    // the consumer has found the queue empty
+    woken: AtomicBool,
+}
+
 pub struct Producer<T> {
-    inner: Arc<mpsc::Queue<T>>,
+    inner: Arc<Queue<T>>,
     noshare: marker::NoShare,
 }
 
 pub struct Consumer<T> {
-    inner: Arc<mpsc::Queue<T>>,
+    inner: Arc<Queue<T>>,
     noshare: marker::NoShare,
 }
 
 impl<T: Send> Consumer<T> {
+    /// Pops a message. Once this has returned `Empty` or `Inconsistent`, the
+    /// next message pushed asks for a wakeup.
     pub fn pop(&self) -> PopResult<T> {
-        match self.inner.pop() {
+        match self.inner.queue.pop() {
+            mpsc::Data(t) => return Data(t),
+            mpsc::Empty | mpsc::Inconsistent => {}
+        }
+
+        // A producer which saw the flag before it was cleared skipped its
+        // wakeup, but its message was pushed by then, so look once more. A
+        // producer which is still pushing will see the flag cleared.
+        self.inner.woken.store(false, SeqCst);
+        match self.inner.queue.pop() {
             mpsc::Inconsistent => Inconsistent,
             mpsc::Empty => Empty,
             mpsc::Data(t) => Data(t),
         }
     }
 
+    /// Pops a message, without clearing the way for another wakeup if there
+    /// isn't one.
     pub fn casual_pop(&self) -> Option<T> {
-        match self.inner.pop() {
+        match self.inner.queue.pop() {
             mpsc::Inconsistent => None,
             mpsc::Empty => None,
             mpsc::Data(t) => Some(t),
@@ -53,8 +85,11 @@ impl<T: Send> Consumer<T> {
 }
 
 impl<T: Send> Producer<T> {
-    pub fn push(&self, t: T) {
-        self.inner.push(t);
+    /// Pushes a message, returning whether the consumer must be woken up to
+    /// see it. If not, a wakeup is already on its way.
+    pub fn push(&self, t: T) -> bool {
+        self.inner.queue.push(t);
+        !self.inner.woken.swap(true, SeqCst)
     }
 }
 
@@ -63,3 +98,25 @@ impl<T: Send> Clone for Producer<T> {
         Producer { inner: self.inner.clone(), noshare: marker::NoShare }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{queue, Data, Empty};
+
+    #[test]
+    fn batched_wakeups() {
+        let (consumer, producer) = queue();
+        assert!(producer.push(1i));
+        assert!(!producer.push(2));
+        assert!(!producer.clone().push(3));
+
+        assert_eq!(consumer.casual_pop(), Some(1));
+        // Only finding the queue empty lets the next message wake it up
+        assert!(!producer.push(4));
+        for i in range(2i, 5) {
+            match consumer.pop() { Data(n) => assert_eq!(n, i), _ => fail!() }
+        }
+        match consumer.pop() { Empty => {}, _ => fail!() }
+        assert!(producer.push(5));
+    }
+}
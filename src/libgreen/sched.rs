@@ -8,12 +8,17 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::collections::{RingBuf, Deque};
 use std::mem;
+use std::rt::hooks;
 use std::rt::local::Local;
 use std::rt::mutex::NativeMutex;
 use std::rt::rtio::{RemoteCallback, PausableIdleCallback, Callback, EventLoop};
-use std::rt::task::BlockedTask;
+use std::rt::sched_stats;
+use std::rt::task::{BlockedTask, High, Normal, Low};
 use std::rt::task::Task;
+use std::rt::time;
+use std::sync::atomics::Relaxed;
 use std::sync::deque;
 use std::raw;
 
@@ -50,6 +55,13 @@ pub struct Scheduler {
     /// Work queues for the other schedulers. These are created by
     /// cloning the core work queues.
     work_queues: Vec<deque::Stealer<Box<GreenTask>>>,
+    /// Tasks of high priority, which are run before those of the work queues.
+    /// They're only run by this scheduler, so that they never wait behind the
+    /// normal work of another one.
+    high_queue: RingBuf<Box<GreenTask>>,
+    /// Tasks of low priority, which are only run when there is no other work
+    /// in the pool to run or steal.
+    low_queue: RingBuf<Box<GreenTask>>,
     /// The queue of incoming messages from other schedulers.
     /// These are enqueued by SchedHandles after which a remote callback
     /// is triggered to handle the message.
@@ -69,6 +81,10 @@ pub struct Scheduler {
     /// A flag to indicate we've received the shutdown message and should
     /// no longer try to go to sleep, but exit instead.
     no_sleep: bool,
+    /// A flag to indicate that the scheduler was taken out of its pool. It
+    /// runs the work it still has, and then exits once nothing refers to it
+    /// any more, so it never puts itself on the sleeper list.
+    retiring: bool,
     /// The scheduler runs on a special task. When it is not running
     /// it is stored here instead of the work queue.
     sched_task: Option<Box<GreenTask>>,
@@ -91,6 +107,13 @@ pub struct Scheduler {
     /// A flag to tell the scheduler loop it needs to do some stealing
     /// in order to introduce randomness as part of a yield
     steal_for_yield: bool,
+    /// The counters of the scheduler, as reported by `rt::stats`
+    stats: sched_stats::Registration,
+    /// When the scheduler last ran out of work, if it hasn't found any since
+    idle_since: Option<u64>,
+    /// The total time spent without work, in nanoseconds. The counter only
+    /// sees whole milliseconds, which short idle periods don't add up to.
+    idle_ns: u64,
 
     // n.b. currently destructors of an object are run in top-to-bottom in order
     //      of field declaration. Due to its nature, the pausable idle callback
@@ -153,9 +176,12 @@ impl Scheduler {
             message_producer: producer,
             sleepy: false,
             no_sleep: false,
+            retiring: false,
             event_loop: event_loop,
             work_queue: work_queue,
             work_queues: work_queues,
+            high_queue: RingBuf::new(),
+            low_queue: RingBuf::new(),
             stack_pool: StackPool::new(),
             sched_task: None,
             cleanup_job: None,
@@ -165,6 +191,9 @@ impl Scheduler {
             idle_callback: None,
             yield_check_count: 0,
             steal_for_yield: false,
+            stats: sched_stats::register(),
+            idle_since: None,
+            idle_ns: 0,
             task_state: state,
         };
 
@@ -276,6 +305,7 @@ impl Scheduler {
         // Make sure that we're not lying in that the `stask` argument is indeed
         // the scheduler task for this scheduler.
         assert!(self.sched_task.is_none());
+        self.stop_idling();
 
         // Assume that we need to continue idling unless we reach the
         // end of this function without performing an action.
@@ -325,15 +355,18 @@ impl Scheduler {
         }
 
         // If we got here then there was no work to do.
+        sched.idle_since = Some(time::precise_time_ns());
+
         // Generate a SchedHandle and push it to the sleeper list so
         // somebody can wake us up later.
-        if !sched.sleepy && !sched.no_sleep {
+        if !sched.sleepy && !sched.no_sleep && !sched.retiring {
             rtdebug!("scheduler has no work to do, going to sleep");
             sched.sleepy = true;
             let handle = sched.make_handle();
             sched.sleeper_list.push(handle);
             // Since we are sleeping, deactivate the idle callback.
             sched.idle_callback.get_mut_ref().pause();
+            hooks::run_idle_hooks();
         } else {
             rtdebug!("not sleeping, already doing so or no_sleep set");
             // We may not be sleeping, but we still need to deactivate
@@ -428,6 +461,23 @@ impl Scheduler {
                 self.work_queues.push(neighbor);
                 (self, stask, false)
             }
+            Some(Retire) => {
+                rtdebug!("retiring");
+                // Our own handle may be on the sleeper list, where it would
+                // keep us alive. It can't be picked out, so wake everyone up
+                // to get it off, and the others will go back to sleep.
+                let id = self.sched_id();
+                loop {
+                    match self.sleeper_list.pop() {
+                        Some(ref handle) if handle.sched_id == id => {}
+                        Some(mut handle) => handle.send(Wake),
+                        None => break
+                    }
+                }
+                self.retiring = true;
+                self.sleepy = false;
+                (self, stask, true)
+            }
             None => (self, stask, false)
         }
     }
@@ -457,10 +507,26 @@ impl Scheduler {
     // old "no work" path which is fine.
 
     // First step in the process is to find a task. This function does
-    // that by first checking the local queue, and if there is no work
-    // there, trying to steal from the remote work queues.
+    // that by first checking the queue of high priority tasks, then the
+    // local work queue, and if there is no work there, trying to steal from
+    // the remote work queues. Low priority tasks come last.
     fn find_work(&mut self) -> Option<Box<GreenTask>> {
         rtdebug!("scheduler looking for work");
+        match self.high_queue.pop_front() {
+            Some(task) => {
+                rtdebug!("found a high priority task");
+                return Some(task)
+            }
+            None => {}
+        }
+        match self.find_normal_work() {
+            Some(task) => return Some(task),
+            None => {}
+        }
+        self.low_queue.pop_front()
+    }
+
+    fn find_normal_work(&mut self) -> Option<Box<GreenTask>> {
         if !self.steal_for_yield {
             match self.work_queue.pop() {
                 Some(task) => {
@@ -488,7 +554,7 @@ impl Scheduler {
                 None => {
                     rtdebug!("did not steal a task after yielding");
                     // Back to business
-                    return self.find_work();
+                    return self.find_normal_work();
                 }
             }
         }
@@ -501,10 +567,13 @@ impl Scheduler {
         let work_queues = &mut self.work_queues;
         let len = work_queues.len();
         let start_index = self.rng.gen_range(0, len);
+        let counters = self.stats.counters();
         for index in range(0, len).map(|i| (i + start_index) % len) {
+            counters.steals_attempted.fetch_add(1, Relaxed);
             match work_queues.get_mut(index).steal() {
                 deque::Data(task) => {
                     rtdebug!("found task by stealing");
+                    counters.steals_succeeded.fetch_add(1, Relaxed);
                     return Some(task)
                 }
                 _ => ()
@@ -580,9 +649,14 @@ impl Scheduler {
     /// to the work queue directly.
     pub fn enqueue_task(&mut self, task: Box<GreenTask>) {
 
-        // We push the task onto our local queue clone.
+        // We push the task onto our local queue clone, or onto the queue for
+        // its priority.
         assert!(!task.is_sched());
-        self.work_queue.push(task);
+        match task.priority {
+            High => self.high_queue.push_back(task),
+            Normal => self.work_queue.push(task),
+            Low => self.low_queue.push_back(task),
+        }
         match self.idle_callback {
             Some(ref mut idle) => idle.resume(),
             None => {} // allow enqueuing before the scheduler starts
@@ -616,6 +690,7 @@ impl Scheduler {
                                f: |&mut Scheduler, Box<GreenTask>|)
                                -> Box<GreenTask> {
         let f_opaque = ClosureConverter::from_fn(f);
+        self.stats.counters().context_switches.fetch_add(1, Relaxed);
 
         let current_task_dupe = &mut *current_task as *mut GreenTask;
 
@@ -809,9 +884,15 @@ impl Scheduler {
         fail!("should never return!");
     }
 
-    pub fn run_task(self: Box<Scheduler>,
+    pub fn run_task(mut self: Box<Scheduler>,
                     cur: Box<GreenTask>,
                     next: Box<GreenTask>) {
+        // A task doesn't give up the scheduler for one of lower priority, which
+        // instead waits its turn in its queue.
+        if next.priority < cur.priority {
+            self.enqueue_task(next);
+            return cur.put_with_sched(self)
+        }
         let (sched, task) =
             self.process_task(cur, next, Scheduler::switch_task);
         task.put_with_sched(sched);
@@ -877,6 +958,23 @@ impl Scheduler {
 
     pub fn sched_id(&self) -> uint { self as *const Scheduler as uint }
 
+    /// Records that a task running on this scheduler spawned another one.
+    pub fn count_spawn(&self) {
+        self.stats.counters().tasks_spawned.fetch_add(1, Relaxed);
+    }
+
+    // Adds the time since the scheduler ran out of work to its idle time
+    fn stop_idling(&mut self) {
+        match self.idle_since.take() {
+            Some(since) => {
+                self.idle_ns += time::precise_time_ns() - since;
+                let ms = (self.idle_ns / 1000000) as uint;
+                self.stats.counters().idle_ms.store(ms, Relaxed);
+            }
+            None => {}
+        }
+    }
+
     pub fn run_cleanup_job(&mut self) {
         let cleanup_job = self.cleanup_job.take_unwrap();
         cleanup_job.run(self)
@@ -905,6 +1003,7 @@ pub enum SchedMessage {
     PinnedTask(Box<GreenTask>),
     TaskFromFriend(Box<GreenTask>),
     RunOnce(Box<GreenTask>),
+    Retire,
 }
 
 pub struct SchedHandle {
@@ -915,8 +1014,12 @@ pub struct SchedHandle {
 
 impl SchedHandle {
     pub fn send(&mut self, msg: SchedMessage) {
-        self.queue.push(msg);
-        self.remote.fire();
+        // The event loop only needs waking if no one has woken it since the
+        // scheduler last emptied its queue, which saves a wakeup per message
+        // when threads send a burst of them (see `message_queue`)
+        if self.queue.push(msg) {
+            self.remote.fire();
+        }
     }
 }
 
@@ -1027,8 +1130,9 @@ mod test {
     use rustuv;
 
     use std::rt::task::TaskOpts;
-    use std::rt::task::Task;
+    use std::rt::task::{Task, High, Normal, Low};
     use std::rt::local::Local;
+    use std::task::TaskBuilder;
 
     use {TaskState, PoolConfig, SchedPool};
     use basic;
@@ -1088,6 +1192,68 @@ mod test {
         assert!(task_run_count == total);
     }
 
+    #[test]
+    fn stats_test() {
+        use std::rt;
+
+        run(proc() {
+            let id = {
+                let mut task = Local::borrow(None::<Task>);
+                let green = task.maybe_take_runtime::<GreenTask>().unwrap();
+                let id = green.sched.get_ref().stats.id();
+                task.put_runtime(green);
+                id
+            };
+            let (tx, rx) = channel();
+            for _ in range(0u, 5) {
+                let tx = tx.clone();
+                spawn(proc() tx.send(()));
+            }
+            for _ in range(0u, 5) { rx.recv(); }
+            let stats = rt::stats().move_iter().find(|s| s.id == id).unwrap();
+            assert!(stats.tasks_spawned >= 5);
+            assert!(stats.context_switches > 0);
+        });
+    }
+
+    #[test]
+    fn idle_hook_test() {
+        use std::rt::hooks;
+        use std::rt::thread::Thread;
+        use std::sync::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+
+        static mut IDLE: AtomicUint = INIT_ATOMIC_UINT;
+        fn idle() { unsafe { IDLE.fetch_add(1, SeqCst); } }
+
+        hooks::on_idle(idle);
+        // The new scheduler has nothing to do, so it goes to sleep right away
+        let pool = pool();
+        while unsafe { IDLE.load(SeqCst) } == 0 {
+            Thread::yield_now();
+        }
+        pool.shutdown();
+    }
+
+    #[test]
+    fn priorities_test() {
+        let (tx, rx) = channel();
+        let mut pool = pool();
+        let mut opts = TaskOpts::new();
+        opts.priority = High;
+        pool.spawn(opts, proc() {
+            // Children of lower priority than this task wait for it to be done
+            // but the high one runs right away, and they then go by priority
+            for &p in [Low, Normal, High].iter() {
+                let tx = tx.clone();
+                TaskBuilder::new().priority(p).spawn(proc() tx.send(Some(p)));
+            }
+            tx.send(None);
+        });
+        let order: Vec<_> = range(0u, 4).map(|_| rx.recv()).collect();
+        assert_eq!(order, vec![Some(High), None, Some(Normal), Some(Low)]);
+        pool.shutdown();
+    }
+
     #[test]
     fn multiple_task_nested_test() {
         let mut task_run_count = 0;
@@ -1467,6 +1633,37 @@ mod test {
         });
     }
 
+    #[test]
+    fn wakeups_from_threads() {
+        use std::rt::thread::Thread;
+        run(proc() {
+            // Many of these sends wake up the receiver remotely at about the
+            // same time, and their wakeups are batched
+            let (tx, rx) = channel();
+            let threads = Vec::from_fn(4, |_| {
+                let tx = tx.clone();
+                Thread::start(proc() {
+                    for i in range(0u, 10000) { tx.send(i); }
+                })
+            });
+            drop(tx);
+            assert_eq!(rx.iter().count(), 40000);
+            for t in threads.move_iter() { t.join(); }
+
+            // Every one of these is a separate wakeup
+            let (tx1, rx1) = channel();
+            let (tx2, rx2) = channel();
+            let t = Thread::start(proc() {
+                for i in range(0u, 1000) {
+                    tx1.send(i);
+                    assert_eq!(rx2.recv(), i);
+                }
+            });
+            for _ in range(0u, 1000) { tx2.send(rx1.recv()); }
+            t.join();
+        });
+    }
+
     // Regression test for a logic bug that would cause single-threaded
     // schedulers to sleep forever after yielding and stealing another task.
     #[test]
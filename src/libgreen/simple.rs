@@ -66,6 +66,9 @@ impl Runtime for SimpleTask {
             guard.signal();
         }
     }
+    fn reawaken_later(self: Box<SimpleTask>, to_wake: Box<Task>) {
+        self.reawaken(to_wake)
+    }
 
     // These functions are all unimplemented and fail as a result. This is on
     // purpose. A "simple task" is just that, a very simple task that can't
@@ -25,7 +25,7 @@ impl SleeperList {
     }
 
     pub fn push(&mut self, value: SchedHandle) {
-        assert!(self.q.push(value))
+        assert!(self.q.push(value).is_ok())
     }
 
     pub fn pop(&mut self) -> Option<SchedHandle> {
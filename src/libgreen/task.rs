@@ -26,7 +26,7 @@ use std::rt::local::Local;
 use std::rt::mutex::NativeMutex;
 use std::rt::rtio;
 use std::rt::stack;
-use std::rt::task::{Task, BlockedTask, TaskOpts};
+use std::rt::task::{Task, BlockedTask, TaskOpts, Priority, Normal};
 use std::rt;
 
 use context::Context;
@@ -67,6 +67,10 @@ pub struct GreenTask {
 
     // See the comments in the scheduler about why this is necessary
     pub nasty_deschedule_lock: NativeMutex,
+
+    /// Which of the scheduler's queues this task waits in when it's ready to
+    /// run.
+    pub priority: Priority,
 }
 
 pub enum TaskType {
@@ -166,6 +170,7 @@ impl GreenTask {
             handle: None,
             nasty_deschedule_lock: unsafe { NativeMutex::new() },
             task: Some(box Task::new()),
+            priority: Normal,
         }
     }
 
@@ -175,9 +180,10 @@ impl GreenTask {
     pub fn configure(pool: &mut StackPool,
                      opts: TaskOpts,
                      f: proc():Send) -> Box<GreenTask> {
-        let TaskOpts { name, stack_size, on_exit } = opts;
+        let TaskOpts { name, stack_size, on_exit, priority } = opts;
 
         let mut green = GreenTask::new(pool, stack_size, f);
+        green.priority = priority;
         {
             let task = green.task.get_mut_ref();
             task.name = name;
@@ -436,6 +442,35 @@ impl Runtime for GreenTask {
         }
     }
 
+    fn reawaken_later(mut self: Box<GreenTask>, to_wake: Box<Task>) {
+        self.put_task(to_wake);
+        assert!(self.sched.is_none());
+
+        // This is the same as `reawaken`, except that in the case of being in
+        // our original scheduler pool we're just enqueued on the local
+        // scheduler instead of being switched to.
+        let mut running_task: Box<Task> = match Local::try_take() {
+            Some(task) => task,
+            None => return self.reawaken_remotely()
+        };
+        match running_task.maybe_take_runtime::<GreenTask>() {
+            Some(mut running_green_task) => {
+                running_green_task.put_task(running_task);
+                let pool_id = running_green_task.sched.get_ref().pool_id;
+                if pool_id == self.pool_id {
+                    Scheduler::run_task_later(running_green_task, self);
+                } else {
+                    self.reawaken_remotely();
+                    running_green_task.put();
+                }
+            }
+            None => {
+                self.reawaken_remotely();
+                Local::put(running_task);
+            }
+        }
+    }
+
     fn spawn_sibling(mut self: Box<GreenTask>,
                      cur_task: Box<Task>,
                      opts: TaskOpts,
@@ -449,6 +484,7 @@ impl Runtime for GreenTask {
         //
         // Upon returning, our task is back in TLS and we're good to return.
         let mut sched = self.sched.take_unwrap();
+        sched.count_spawn();
         let sibling = GreenTask::configure(&mut sched.stack_pool, opts, f);
         sched.run_task(self, sibling)
     }
@@ -55,6 +55,32 @@ pub static MSG_DONTWAIT: libc::c_int = 0x40;
 
 pub static WNOHANG: libc::c_int = 1;
 
+pub static POLLIN: libc::c_short = 0x1;
+pub static POLLOUT: libc::c_short = 0x4;
+pub static POLLERR: libc::c_short = 0x8;
+pub static POLLHUP: libc::c_short = 0x10;
+
+#[repr(C)]
+pub struct pollfd {
+    pub fd: libc::c_int,
+    pub events: libc::c_short,
+    pub revents: libc::c_short,
+}
+
+#[repr(C)]
+pub struct iovec {
+    pub iov_base: *mut libc::c_void,
+    pub iov_len: libc::size_t,
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(target_os = "android")]
+pub type nfds_t = libc::c_ulong;
+#[cfg(target_os = "macos")]
+#[cfg(target_os = "ios")]
+#[cfg(target_os = "freebsd")]
+pub type nfds_t = libc::c_uint;
+
 extern {
     pub fn gettimeofday(timeval: *mut libc::timeval,
                         tzp: *mut libc::c_void) -> libc::c_int;
@@ -63,12 +89,16 @@ extern {
                   writefds: *mut fd_set,
                   errorfds: *mut fd_set,
                   timeout: *mut libc::timeval) -> libc::c_int;
+    pub fn poll(fds: *mut pollfd, nfds: nfds_t,
+                timeout: libc::c_int) -> libc::c_int;
     pub fn getsockopt(sockfd: libc::c_int,
                       level: libc::c_int,
                       optname: libc::c_int,
                       optval: *mut libc::c_void,
                       optlen: *mut libc::socklen_t) -> libc::c_int;
     pub fn ioctl(fd: libc::c_int, req: libc::c_ulong, ...) -> libc::c_int;
+    pub fn writev(fd: libc::c_int, iov: *const iovec,
+                  iovcnt: libc::c_int) -> libc::ssize_t;
 
 
     pub fn waitpid(pid: libc::pid_t, status: *mut libc::c_int,
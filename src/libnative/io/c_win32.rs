@@ -46,6 +46,12 @@ pub struct fd_set {
     fd_array: [libc::SOCKET, ..FD_SETSIZE],
 }
 
+#[repr(C)]
+pub struct WSABUF {
+    pub len: libc::c_ulong,
+    pub buf: *mut libc::c_char,
+}
+
 pub fn fd_set(set: &mut fd_set, s: libc::SOCKET) {
     set.fd_array[set.fd_count as uint] = s;
     set.fd_count += 1;
@@ -69,6 +75,12 @@ extern "system" {
                       optname: libc::c_int,
                       optval: *mut libc::c_char,
                       optlen: *mut libc::c_int) -> libc::c_int;
+    pub fn WSASend(s: libc::SOCKET, lpBuffers: *mut WSABUF,
+                   dwBufferCount: libc::DWORD,
+                   lpNumberOfBytesSent: *mut libc::DWORD,
+                   dwFlags: libc::DWORD,
+                   lpOverlapped: libc::LPOVERLAPPED,
+                   lpCompletionRoutine: *mut libc::c_void) -> libc::c_int;
 
     pub fn CancelIo(hFile: libc::HANDLE) -> libc::BOOL;
     pub fn CancelIoEx(hFile: libc::HANDLE,
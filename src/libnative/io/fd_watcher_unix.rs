@@ -0,0 +1,167 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Readiness of arbitrary file descriptors
+//!
+//! Native tasks have no event loop, so the descriptors are watched by a helper
+//! thread of their own, which sits in poll() on the descriptors which are
+//! armed along with the read end of its wakeup pipe. This is the same scheme
+//! as the timer helper thread, with poll() in place of select() so that the
+//! number of descriptors isn't limited by FD_SETSIZE.
+//!
+//! A watch is disarmed when its callback returns `false`, and it's only put
+//! back in the poll set when it's rearmed. Otherwise a descriptor which stays
+//! readable would have its callback called in a tight loop.
+//!
+//! Dropping a watch waits for the helper thread to acknowledge its removal, so
+//! that the descriptor can be closed right after without the helper thread
+//! polling a stale (or reused) descriptor.
+
+use libc;
+use std::comm;
+use std::os;
+use std::rt::rtio;
+use std::rt::rtio::IoResult;
+use std::sync::atomics;
+
+use io::c;
+use io::file::FileDesc;
+use io::helper_thread::Helper;
+
+helper_init!(static mut HELPER: Helper<Req>)
+
+pub struct FdWatcher {
+    id: uint,
+}
+
+struct Watch {
+    id: uint,
+    fd: libc::c_int,
+    events: libc::c_short,
+    armed: bool,
+    cb: Box<rtio::FdCallback + Send>,
+}
+
+#[allow(visible_private_types)]
+pub enum Req {
+    // Start watching a new descriptor
+    NewWatch(Box<Watch>),
+
+    // Put a watch back in the poll set
+    RearmWatch(uint),
+
+    // Stop watching a descriptor, and then acknowledge on the channel provided
+    RemoveWatch(uint, Sender<()>),
+}
+
+fn helper(input: libc::c_int, messages: Receiver<Req>, _: ()) {
+    let mut fd = FileDesc::new(input, true);
+    let mut watches: Vec<Box<Watch>> = vec![];
+    let mut set: Vec<c::pollfd> = vec![];
+
+    'outer: loop {
+        // The wakeup pipe always comes first, followed by the armed watches in
+        // the order they appear in `watches`
+        set.truncate(0);
+        set.push(c::pollfd { fd: input, events: c::POLLIN, revents: 0 });
+        for w in watches.iter().filter(|w| w.armed) {
+            set.push(c::pollfd { fd: w.fd, events: w.events, revents: 0 });
+        }
+
+        match unsafe {
+            c::poll(set.as_mut_ptr(), set.len() as c::nfds_t, -1)
+        } {
+            -1 if os::errno() == libc::EINTR as int => continue,
+            n if n < 0 => {
+                fail!("helper thread failed in poll() with error: {} ({})",
+                      n, os::last_os_error())
+            }
+            _ => {}
+        }
+
+        let mut ready = set.iter().skip(1);
+        for w in watches.mut_iter().filter(|w| w.armed) {
+            let revents = ready.next().unwrap().revents;
+            if revents == 0 { continue }
+            let error = revents & (c::POLLERR | c::POLLHUP) != 0;
+            w.armed = w.cb.call(error || revents & c::POLLIN != 0,
+                                error || revents & c::POLLOUT != 0);
+        }
+
+        if set.get(0).revents == 0 { continue }
+        loop {
+            match messages.try_recv() {
+                Err(comm::Disconnected) => {
+                    assert!(watches.len() == 0);
+                    break 'outer;
+                }
+
+                Ok(NewWatch(w)) => watches.push(w),
+
+                Ok(RearmWatch(id)) => {
+                    match watches.mut_iter().find(|w| w.id == id) {
+                        Some(w) => w.armed = true,
+                        None => {}
+                    }
+                }
+
+                Ok(RemoveWatch(id, ack)) => {
+                    let i = watches.iter().position(|w| w.id == id);
+                    let i = i.expect("no watch found");
+                    drop(watches.remove(i));
+                    ack.send(());
+                }
+
+                Err(..) => break
+            }
+        }
+
+        // drain the file descriptor
+        let mut buf = [0];
+        assert_eq!(fd.inner_read(buf).ok().unwrap(), 1);
+    }
+}
+
+impl FdWatcher {
+    pub fn new(fd: libc::c_int, readable: bool, writable: bool,
+               cb: Box<rtio::FdCallback + Send>) -> IoResult<FdWatcher> {
+        unsafe { HELPER.boot(|| {}, helper); }
+
+        static mut ID: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+        let id = unsafe { ID.fetch_add(1, atomics::Relaxed) };
+        let mut events = 0;
+        if readable { events |= c::POLLIN }
+        if writable { events |= c::POLLOUT }
+        unsafe {
+            HELPER.send(NewWatch(box Watch {
+                id: id,
+                fd: fd,
+                events: events,
+                armed: true,
+                cb: cb,
+            }));
+        }
+        Ok(FdWatcher { id: id })
+    }
+}
+
+impl rtio::RtioFdWatcher for FdWatcher {
+    fn rearm(&mut self) {
+        unsafe { HELPER.send(RearmWatch(self.id)); }
+    }
+}
+
+impl Drop for FdWatcher {
+    fn drop(&mut self) {
+        let (tx, rx) = channel();
+        unsafe { HELPER.send(RemoveWatch(self.id, tx)); }
+        rx.recv();
+    }
+}
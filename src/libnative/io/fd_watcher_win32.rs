@@ -0,0 +1,419 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Readiness of sockets and waitable handles on Windows
+//!
+//! Windows has no readiness notifications for sockets to speak of, only
+//! completions of overlapped operations. Readiness is therefore learned by
+//! starting a zero-byte overlapped receive (or send) on a socket: it completes
+//! once data can be received (or sent), without consuming any of it.
+//!
+//! The operations complete to an I/O completion port, on which a helper thread
+//! sits. Anything else which is waitable, such as the event of a channel's
+//! `Receiver::as_fd`, is watched with a registered wait whose callback posts a
+//! completion to the same port, so that everything is waited on in one place.
+//! The helper thread's own wakeup event is registered in the same way.
+//!
+//! Listening sockets aren't supported, as a zero-byte receive doesn't wait for
+//! connections, and accepting them with overlapped operations would change how
+//! the sockets are accepted from.
+//!
+//! An operation in flight refers to the watch which started it, so a watch is
+//! only freed once every one of its operations has completed. Dropping a watch
+//! cancels its operations, and waits for the helper thread to acknowledge
+//! their completion, after which the socket or handle can be closed.
+
+use libc;
+use libc::types::os::arch::extra::BOOLEAN;
+use std::comm;
+use std::mem;
+use std::os;
+use std::ptr;
+use std::rt::rtio;
+use std::rt::rtio::IoResult;
+use std::sync::atomics;
+
+use io::c;
+use io::helper_thread::Helper;
+use io::net;
+
+helper_init!(static mut HELPER: Helper<Req>)
+
+// The completion port of the helper thread, set once it has been booted
+static mut PORT: libc::HANDLE = 0 as libc::HANDLE;
+
+// The keys of the completions posted to the port
+static REQUEST_KEY: uint = 1;
+static SOCKET_KEY: uint = 2;
+static WAIT_KEY: uint = 3;
+
+static WT_EXECUTEONLYONCE: libc::DWORD = 0x8;
+static SO_TYPE: libc::c_int = 0x1008;
+static SO_ACCEPTCONN: libc::c_int = 0x0002;
+static WSA_IO_PENDING: libc::c_int = 997;
+static ERROR_INVALID_PARAMETER: int = 87;
+
+pub struct FdWatcher {
+    id: uint,
+}
+
+enum Source {
+    Socket(libc::SOCKET),
+    Handle(libc::HANDLE),
+}
+
+#[deriving(PartialEq)]
+enum Kind { Read, Write, Wait }
+
+// An overlapped operation, or a registered wait, of a watch. The OVERLAPPED
+// comes first so that the completion can be traced back to the operation.
+#[repr(C)]
+struct Op {
+    overlapped: libc::OVERLAPPED,
+    id: uint,
+    kind: Kind,
+    pending: bool,
+    // For waits, the registration and whether its callback has run
+    wait: libc::HANDLE,
+    fired: atomics::AtomicBool,
+}
+
+struct Watch {
+    id: uint,
+    source: Source,
+    armed: bool,
+    ops: Vec<Box<Op>>,
+    // Set once the watch has been dropped, to acknowledge its removal
+    removed: Option<Sender<()>>,
+    cb: Box<rtio::FdCallback + Send>,
+}
+
+#[allow(visible_private_types)]
+pub enum Req {
+    // Start watching a new socket or handle
+    NewWatch(Box<Watch>),
+
+    // Start the operations of a watch again
+    RearmWatch(uint),
+
+    // Stop watching, and then acknowledge on the channel provided
+    RemoveWatch(uint, Sender<()>),
+}
+
+#[repr(C)]
+struct WSABUF {
+    len: libc::c_ulong,
+    buf: *mut u8,
+}
+
+type WaitCallback = extern "system" fn(*mut libc::c_void, BOOLEAN);
+
+extern "system" {
+    fn CreateIoCompletionPort(file: libc::HANDLE, port: libc::HANDLE,
+                              key: uint, threads: libc::DWORD) -> libc::HANDLE;
+    fn GetQueuedCompletionStatus(port: libc::HANDLE, bytes: *mut libc::DWORD,
+                                 key: *mut uint,
+                                 overlapped: *mut libc::LPOVERLAPPED,
+                                 ms: libc::DWORD) -> libc::BOOL;
+    fn PostQueuedCompletionStatus(port: libc::HANDLE, bytes: libc::DWORD,
+                                  key: uint,
+                                  overlapped: libc::LPOVERLAPPED) -> libc::BOOL;
+    fn RegisterWaitForSingleObject(wait: *mut libc::HANDLE, object: libc::HANDLE,
+                                   cb: WaitCallback, ctx: *mut libc::c_void,
+                                   ms: libc::DWORD,
+                                   flags: libc::DWORD) -> libc::BOOL;
+    fn UnregisterWaitEx(wait: libc::HANDLE, event: libc::HANDLE) -> libc::BOOL;
+}
+
+#[link(name = "ws2_32")]
+extern "system" {
+    fn WSARecv(s: libc::SOCKET, bufs: *mut WSABUF, count: libc::DWORD,
+               received: *mut libc::DWORD, flags: *mut libc::DWORD,
+               overlapped: libc::LPOVERLAPPED,
+               routine: *mut libc::c_void) -> libc::c_int;
+    fn WSASend(s: libc::SOCKET, bufs: *mut WSABUF, count: libc::DWORD,
+               sent: *mut libc::DWORD, flags: libc::DWORD,
+               overlapped: libc::LPOVERLAPPED,
+               routine: *mut libc::c_void) -> libc::c_int;
+}
+
+// Runs on a thread of the system's pool whenever the helper thread is sent a
+// request, turning the wakeup into a completion
+extern "system" fn request_cb(_ctx: *mut libc::c_void, _timed_out: BOOLEAN) {
+    unsafe { PostQueuedCompletionStatus(PORT, 0, REQUEST_KEY, ptr::mut_null()); }
+}
+
+// Runs on a thread of the system's pool once the handle of a watch is
+// signaled
+extern "system" fn wait_cb(ctx: *mut libc::c_void, _timed_out: BOOLEAN) {
+    unsafe {
+        let op = ctx as *mut Op;
+        (*op).fired.store(true, atomics::SeqCst);
+        PostQueuedCompletionStatus(PORT, 0, WAIT_KEY, op as libc::LPOVERLAPPED);
+    }
+}
+
+fn helper(signal: libc::HANDLE, messages: Receiver<Req>, port: libc::HANDLE) {
+    let mut watches: Vec<Box<Watch>> = vec![];
+    let mut wakeup = 0 as libc::HANDLE;
+    assert!(unsafe {
+        RegisterWaitForSingleObject(&mut wakeup, signal, request_cb,
+                                    ptr::mut_null(), libc::INFINITE, 0)
+    } != 0);
+
+    'outer: loop {
+        let mut bytes = 0;
+        let mut key = 0;
+        let mut overlapped = ptr::mut_null();
+        let ok = unsafe {
+            GetQueuedCompletionStatus(port, &mut bytes, &mut key,
+                                      &mut overlapped, libc::INFINITE)
+        };
+        if ok == 0 && overlapped.is_null() {
+            fail!("helper thread failed in GetQueuedCompletionStatus: {}",
+                  os::last_os_error());
+        }
+
+        if key != REQUEST_KEY {
+            let op = overlapped as *mut Op;
+            let id = unsafe { (*op).id };
+            let i = watches.iter().position(|w| w.id == id);
+            let i = i.expect("no watch found");
+            complete(&mut **watches.get_mut(i), op, ok != 0);
+            if finished(&**watches.get(i)) {
+                let w = watches.remove(i).unwrap();
+                w.removed.get_ref().send(());
+            }
+            continue
+        }
+
+        loop {
+            match messages.try_recv() {
+                Err(comm::Disconnected) => {
+                    assert!(watches.len() == 0);
+                    break 'outer;
+                }
+
+                Ok(NewWatch(w)) => {
+                    watches.push(w);
+                    let w = watches.mut_last().unwrap();
+                    arm(&mut **w);
+                }
+
+                Ok(RearmWatch(id)) => {
+                    match watches.mut_iter().find(|w| w.id == id) {
+                        Some(w) => { w.armed = true; arm(&mut **w); }
+                        None => {}
+                    }
+                }
+
+                Ok(RemoveWatch(id, ack)) => {
+                    let i = watches.iter().position(|w| w.id == id);
+                    let i = i.expect("no watch found");
+                    cancel(&mut **watches.get_mut(i));
+                    watches.get_mut(i).removed = Some(ack);
+                    if finished(&**watches.get(i)) {
+                        let w = watches.remove(i).unwrap();
+                        w.removed.get_ref().send(());
+                    }
+                }
+
+                Err(..) => break
+            }
+        }
+    }
+
+    unsafe {
+        UnregisterWaitEx(wakeup, libc::INVALID_HANDLE_VALUE as libc::HANDLE);
+        libc::CloseHandle(port);
+    }
+}
+
+// Whether a watch has been dropped and none of its operations are in flight
+fn finished(w: &Watch) -> bool {
+    w.removed.is_some() && w.ops.iter().all(|op| !op.pending)
+}
+
+// Starts the operations of an armed watch which aren't already in flight. An
+// operation which fails to start is reported as the watch being ready.
+fn arm(w: &mut Watch) {
+    if !w.armed || w.removed.is_some() { return }
+    let mut error = false;
+    for op in w.ops.mut_iter().filter(|op| !op.pending) {
+        op.overlapped = unsafe { mem::zeroed() };
+        let started = match w.source {
+            Socket(s) => unsafe { start_io(s, &mut **op) },
+            Handle(h) => unsafe { start_wait(h, &mut **op) },
+        };
+        if started { op.pending = true } else { error = true }
+    }
+    if error {
+        w.armed = w.cb.call(true, true);
+    }
+}
+
+unsafe fn start_io(s: libc::SOCKET, op: &mut Op) -> bool {
+    let mut buf = WSABUF { len: 0, buf: ptr::mut_null() };
+    let mut bytes = 0;
+    let ret = if op.kind == Read {
+        let mut flags = 0;
+        WSARecv(s, &mut buf, 1, &mut bytes, &mut flags, &mut op.overlapped,
+                ptr::mut_null())
+    } else {
+        WSASend(s, &mut buf, 1, &mut bytes, 0, &mut op.overlapped,
+                ptr::mut_null())
+    };
+    // The completion is queued to the port even when it succeeds right away
+    ret == 0 || c::WSAGetLastError() == WSA_IO_PENDING
+}
+
+unsafe fn start_wait(h: libc::HANDLE, op: &mut Op) -> bool {
+    op.fired.store(false, atomics::SeqCst);
+    let ctx = op as *mut Op as *mut libc::c_void;
+    RegisterWaitForSingleObject(&mut op.wait, h, wait_cb, ctx, libc::INFINITE,
+                                WT_EXECUTEONLYONCE) != 0
+}
+
+// Handles the completion of one of the operations of a watch
+fn complete(w: &mut Watch, op: *mut Op, ok: bool) {
+    let kind = unsafe {
+        let op = &mut *op;
+        op.pending = false;
+        unregister(op);
+        op.kind
+    };
+    // Completions which were cancelled don't mean anything
+    if !w.armed || w.removed.is_some() { return }
+
+    let error = !ok;
+    let keep = match kind {
+        Read | Wait => w.cb.call(true, error),
+        Write => w.cb.call(error, true),
+    };
+    w.armed = keep;
+    arm(w);
+}
+
+// Stops the operations of a watch which is being dropped. A cancelled socket
+// operation still completes, as does a wait whose callback already ran.
+fn cancel(w: &mut Watch) {
+    for op in w.ops.mut_iter().filter(|op| op.pending) {
+        match w.source {
+            Socket(s) => unsafe {
+                c::CancelIoEx(s as libc::HANDLE, &mut op.overlapped);
+            },
+            Handle(..) => unsafe {
+                unregister(&mut **op);
+                op.pending = op.fired.load(atomics::SeqCst);
+            },
+        }
+    }
+}
+
+// Unregisters the wait of an operation, if it has one. This waits for its
+// callback to finish, if it's running.
+unsafe fn unregister(op: &mut Op) {
+    if !op.wait.is_null() {
+        UnregisterWaitEx(op.wait, libc::INVALID_HANDLE_VALUE as libc::HANDLE);
+        op.wait = 0 as libc::HANDLE;
+    }
+}
+
+// Reads an integer socket option, failing if `s` isn't a socket
+fn sockopt(s: libc::SOCKET, opt: libc::c_int) -> Option<libc::c_int> {
+    let mut val = 0 as libc::c_int;
+    let mut len = mem::size_of::<libc::c_int>() as libc::c_int;
+    match unsafe {
+        c::getsockopt(s, libc::SOL_SOCKET, opt,
+                      &mut val as *mut libc::c_int as *mut libc::c_char,
+                      &mut len)
+    } {
+        0 => Some(val),
+        _ => None,
+    }
+}
+
+impl FdWatcher {
+    /// Watches `fd`, which is either a socket or a waitable handle. Handles
+    /// are only ever readable, which is when they are signaled.
+    pub fn new(fd: libc::c_int, readable: bool, writable: bool,
+               cb: Box<rtio::FdCallback + Send>) -> IoResult<FdWatcher> {
+        unsafe {
+            HELPER.boot(|| {
+                PORT = CreateIoCompletionPort(libc::INVALID_HANDLE_VALUE as libc::HANDLE,
+                                              ptr::mut_null(), 0, 1);
+                assert!(!PORT.is_null());
+                PORT
+            }, helper);
+        }
+        net::init();
+
+        static mut ID: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+        let id = unsafe { ID.fetch_add(1, atomics::Relaxed) };
+        let op = |kind| box Op {
+            overlapped: unsafe { mem::zeroed() },
+            id: id,
+            kind: kind,
+            pending: false,
+            wait: 0 as libc::HANDLE,
+            fired: atomics::AtomicBool::new(false),
+        };
+
+        let sock = fd as libc::SOCKET;
+        let (source, ops) = if sockopt(sock, SO_TYPE).is_some() {
+            if sockopt(sock, SO_ACCEPTCONN) != Some(0) {
+                return Err(super::unimpl())
+            }
+            // A socket which was watched before is already associated with the
+            // port, and associating it again fails
+            let ret = unsafe {
+                CreateIoCompletionPort(sock as libc::HANDLE, PORT, SOCKET_KEY, 0)
+            };
+            if ret.is_null() && os::errno() != ERROR_INVALID_PARAMETER {
+                return Err(super::last_error())
+            }
+            let mut ops = vec![];
+            if readable { ops.push(op(Read)) }
+            if writable { ops.push(op(Write)) }
+            (Socket(sock), ops)
+        } else {
+            if writable && !readable {
+                return Err(super::unimpl())
+            }
+            (Handle(fd as uint as libc::HANDLE), vec![op(Wait)])
+        };
+
+        unsafe {
+            HELPER.send(NewWatch(box Watch {
+                id: id,
+                source: source,
+                armed: true,
+                ops: ops,
+                removed: None,
+                cb: cb,
+            }));
+        }
+        Ok(FdWatcher { id: id })
+    }
+}
+
+impl rtio::RtioFdWatcher for FdWatcher {
+    fn rearm(&mut self) {
+        unsafe { HELPER.send(RearmWatch(self.id)); }
+    }
+}
+
+impl Drop for FdWatcher {
+    fn drop(&mut self) {
+        let (tx, rx) = channel();
+        unsafe { HELPER.send(RemoveWatch(self.id, tx)); }
+        rx.recv();
+    }
+}
@@ -185,6 +185,10 @@ impl rtio::RtioPipe for FileDesc {
     fn set_timeout(&mut self, _t: Option<u64>) {}
     fn set_read_timeout(&mut self, _t: Option<u64>) {}
     fn set_write_timeout(&mut self, _t: Option<u64>) {}
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        super::watch_readable(self.fd(), cb)
+    }
 }
 
 impl rtio::RtioTTY for FileDesc {
@@ -218,6 +218,10 @@ impl rtio::RtioPipe for FileDesc {
     fn set_timeout(&mut self, _t: Option<u64>) {}
     fn set_read_timeout(&mut self, _t: Option<u64>) {}
     fn set_write_timeout(&mut self, _t: Option<u64>) {}
+    fn watch_readable(&mut self, _cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        Err(super::unimpl())
+    }
 }
 
 impl rtio::RtioTTY for FileDesc {
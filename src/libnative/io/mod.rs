@@ -73,6 +73,18 @@ pub mod pipe;
 #[path = "tty_win32.rs"]
 mod tty;
 
+#[cfg(unix)]
+#[path = "fd_watcher_unix.rs"]
+mod fd_watcher;
+
+#[cfg(windows)]
+#[path = "fd_watcher_win32.rs"]
+mod fd_watcher;
+
+#[cfg(unix)]
+#[path = "signal_unix.rs"]
+mod signal;
+
 #[cfg(unix)]    #[path = "c_unix.rs"]  mod c;
 #[cfg(windows)] #[path = "c_win32.rs"] mod c;
 
@@ -95,6 +107,22 @@ fn last_error() -> IoError {
     }
 }
 
+// Watches a descriptor of this module for readability
+#[cfg(unix)]
+fn watch_readable(fd: c_int, cb: Box<rtio::FdCallback + Send>)
+                  -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+    fd_watcher::FdWatcher::new(fd, true, false, cb).map(|w| {
+        box w as Box<rtio::RtioFdWatcher + Send>
+    })
+}
+#[cfg(windows)]
+fn watch_readable(sock: libc::SOCKET, cb: Box<rtio::FdCallback + Send>)
+                  -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+    fd_watcher::FdWatcher::new(sock as c_int, true, false, cb).map(|w| {
+        box w as Box<rtio::RtioFdWatcher + Send>
+    })
+}
+
 // unix has nonzero values as errors
 fn mkerr_libc(ret: libc::c_int) -> IoResult<()> {
     if ret != 0 {
@@ -310,8 +338,23 @@ impl rtio::IoFactory for IoFactory {
             })
         }
     }
-    fn signal(&mut self, _signal: int, _cb: Box<rtio::Callback>)
+    #[cfg(unix)]
+    fn signal(&mut self, signum: int, cb: Box<rtio::Callback + Send>)
+              -> IoResult<Box<rtio::RtioSignal + Send>> {
+        signal::Signal::new(signum, cb).map(|s| {
+            box s as Box<rtio::RtioSignal + Send>
+        })
+    }
+    #[cfg(windows)]
+    fn signal(&mut self, _signal: int, _cb: Box<rtio::Callback + Send>)
               -> IoResult<Box<rtio::RtioSignal + Send>> {
         Err(unimpl())
     }
+    fn fd_watch(&mut self, fd: c_int, readable: bool, writable: bool,
+                cb: Box<rtio::FdCallback + Send>)
+                -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        fd_watcher::FdWatcher::new(fd, readable, writable, cb).map(|w| {
+            box w as Box<rtio::RtioFdWatcher + Send>
+        })
+    }
 }
@@ -10,6 +10,7 @@
 
 use alloc::arc::Arc;
 use libc;
+use std::cmp;
 use std::mem;
 use std::rt::mutex;
 use std::rt::rtio;
@@ -66,6 +67,13 @@ fn ip_to_inaddr(ip: rtio::IpAddr) -> InAddr {
     }
 }
 
+fn is_multicast(ip: rtio::IpAddr) -> bool {
+    match ip {
+        rtio::Ipv4Addr(a, _, _, _) => a >= 224 && a <= 239,
+        rtio::Ipv6Addr(a, _, _, _, _, _, _, _) => a >> 8 == 0xff,
+    }
+}
+
 fn addr_to_sockaddr(addr: rtio::SocketAddr) -> (libc::sockaddr_storage, uint) {
     unsafe {
         let storage: libc::sockaddr_storage = mem::zeroed();
@@ -352,6 +360,39 @@ impl TcpStream {
 #[cfg(windows)] type wrlen = libc::c_int;
 #[cfg(not(windows))] type wrlen = libc::size_t;
 
+// The most buffers which are sent at once, which is well under IOV_MAX
+static MAX_IOVS: uint = 64;
+
+// Sends as much of `bufs` as can be sent at once, with a single call
+#[cfg(unix)]
+unsafe fn send_bufs(fd: sock_t, bufs: &[&[u8]]) -> libc::c_int {
+    let iov: Vec<c::iovec> = bufs.iter().map(|b| {
+        c::iovec {
+            iov_base: b.as_ptr() as *mut libc::c_void,
+            iov_len: b.len() as libc::size_t,
+        }
+    }).collect();
+    c::writev(fd, iov.as_ptr(), iov.len() as libc::c_int) as libc::c_int
+}
+
+#[cfg(windows)]
+unsafe fn send_bufs(fd: sock_t, bufs: &[&[u8]]) -> libc::c_int {
+    use std::ptr;
+
+    let mut wsabufs: Vec<c::WSABUF> = bufs.iter().map(|b| {
+        c::WSABUF {
+            len: b.len() as libc::c_ulong,
+            buf: b.as_ptr() as *mut libc::c_char,
+        }
+    }).collect();
+    let mut sent = 0;
+    match c::WSASend(fd, wsabufs.as_mut_ptr(), wsabufs.len() as libc::DWORD,
+                     &mut sent, 0, ptr::mut_null(), ptr::mut_null()) {
+        0 => sent as libc::c_int,
+        _ => -1,
+    }
+}
+
 impl rtio::RtioTcpStream for TcpStream {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
         let fd = self.fd();
@@ -381,6 +422,42 @@ impl rtio::RtioTcpStream for TcpStream {
             Err(e) => Err(e)
         }
     }
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> IoResult<()> {
+        // Timeouts are only implemented by write()
+        if self.write_deadline != 0 {
+            for buf in bufs.iter() { try!(self.write(*buf)) }
+            return Ok(())
+        }
+        let fd = self.fd();
+        let mut bufs: Vec<&[u8]> = bufs.iter().map(|b| *b)
+                                       .filter(|b| b.len() > 0).collect();
+        let mut first = 0;
+        while first < bufs.len() {
+            let n = retry(|| unsafe {
+                send_bufs(fd, bufs.slice_from(first).slice_to(
+                    cmp::min(bufs.len() - first, MAX_IOVS)))
+            });
+            if n == -1 {
+                // The socket is nonblocking while another task has a timeout
+                if !util::wouldblock() { return Err(last_error()) }
+                try!(util::await(fd, None, util::Writable));
+                continue
+            }
+            // Skip what was sent, which may end partway into a buffer
+            let mut n = n as uint;
+            while n > 0 {
+                let b = *bufs.get(first);
+                if n >= b.len() {
+                    n -= b.len();
+                    first += 1;
+                } else {
+                    *bufs.get_mut(first) = b.slice_from(n);
+                    n = 0;
+                }
+            }
+        }
+        Ok(())
+    }
     fn peer_name(&mut self) -> IoResult<rtio::SocketAddr> {
         sockname(self.fd(), libc::getpeername)
     }
@@ -423,6 +500,10 @@ impl rtio::RtioTcpStream for TcpStream {
     fn set_write_timeout(&mut self, timeout: Option<u64>) {
         self.write_deadline = timeout.map(|a| ::io::timer::now() + a).unwrap_or(0);
     }
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        super::watch_readable(self.fd(), cb)
+    }
 }
 
 impl rtio::RtioSocket for TcpStream {
@@ -543,6 +624,10 @@ impl rtio::RtioTcpAcceptor for TcpAcceptor {
     fn set_timeout(&mut self, timeout: Option<u64>) {
         self.deadline = timeout.map(|a| ::io::timer::now() + a).unwrap_or(0);
     }
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        super::watch_readable(self.fd(), cb)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -564,6 +649,13 @@ impl UdpSocket {
             write_deadline: 0,
         };
 
+        // Several sockets can be bound to a multicast group, so that each of
+        // them receives its datagrams, as libuv allows.
+        if cfg!(unix) && is_multicast(addr.ip) {
+            try!(setsockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR,
+                            1 as libc::c_int));
+        }
+
         let (addr, len) = addr_to_sockaddr(addr);
         let addrp = &addr as *const _ as *const libc::sockaddr;
         let len = len as libc::socklen_t;
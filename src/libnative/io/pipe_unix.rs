@@ -200,6 +200,10 @@ impl rtio::RtioPipe for UnixStream {
     fn set_write_timeout(&mut self, timeout: Option<u64>) {
         self.write_deadline = timeout.map(|a| ::io::timer::now() + a).unwrap_or(0);
     }
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        super::watch_readable(self.fd(), cb)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -271,6 +275,10 @@ impl rtio::RtioUnixAcceptor for UnixAcceptor {
     fn set_timeout(&mut self, timeout: Option<u64>) {
         self.deadline = timeout.map(|a| ::io::timer::now() + a).unwrap_or(0);
     }
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        super::watch_readable(self.fd(), cb)
+    }
 }
 
 impl Drop for UnixListener {
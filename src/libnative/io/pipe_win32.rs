@@ -547,6 +547,10 @@ impl rtio::RtioPipe for UnixStream {
     fn set_write_timeout(&mut self, timeout: Option<u64>) {
         self.write_deadline = timeout.map(|a| ::io::timer::now() + a).unwrap_or(0);
     }
+    fn watch_readable(&mut self, _cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        Err(super::unimpl())
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -709,5 +713,9 @@ impl rtio::RtioUnixAcceptor for UnixAcceptor {
     fn set_timeout(&mut self, timeout: Option<u64>) {
         self.deadline = timeout.map(|i| i + ::io::timer::now()).unwrap_or(0);
     }
+    fn watch_readable(&mut self, _cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        Err(super::unimpl())
+    }
 }
 
@@ -0,0 +1,260 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Signal handling for native tasks
+//!
+//! As with SIGCHLD in the process module, signals are turned into something a
+//! thread can wait on with the self-pipe trick: the signal handler writes the
+//! number of the signal to a nonblocking pipe, and a helper thread reads the
+//! pipe and calls the callbacks of the signal. If the pipe is full, the write
+//! is dropped and the signal is coalesced with those already in the pipe. A
+//! signal is also delivered only once to each callback for each time the pipe
+//! is drained, however many times it was caught in the meantime.
+//!
+//! The handler of a signal is installed by the helper thread when the first
+//! callback for it is registered, and the previous handler is put back when
+//! the last one is dropped (unless someone else has installed a handler on top
+//! of ours in the meantime). Registering waits for the helper thread, so that
+//! a signal raised right after it is caught.
+//!
+//! A previous handler which isn't `SIG_DFL` or `SIG_IGN` is called by our
+//! handler, so that the SIGCHLD handler of the process module keeps working.
+//! Handlers installed with `SA_SIGINFO` aren't chained.
+
+use libc;
+use std::comm;
+use std::mem;
+use std::os;
+use std::ptr;
+use std::rt::rtio;
+use std::rt::rtio::IoResult;
+use std::sync::atomics;
+
+use io::c;
+use io::file::FileDesc;
+use io::helper_thread::Helper;
+use io::util;
+
+helper_init!(static mut HELPER: Helper<Req>)
+
+// The writing half of the self-pipe
+static mut WRITE_FD: libc::c_int = -1;
+
+// The handlers which were installed before ours, by signal number, or 0
+static NSIG: uint = 65;
+static mut OLD_HANDLERS: [uint, ..NSIG] = [0, ..NSIG];
+
+pub struct Signal {
+    id: uint,
+}
+
+struct Registration {
+    id: uint,
+    signum: libc::c_int,
+    cb: Box<rtio::Callback + Send>,
+}
+
+#[allow(visible_private_types)]
+pub enum Req {
+    // Register a new callback, acknowledging once its handler is installed
+    NewSignal(Box<Registration>, Sender<IoResult<()>>),
+
+    // Remove a callback, and then acknowledge on the channel provided
+    RemoveSignal(uint, Sender<()>),
+}
+
+fn helper(input: libc::c_int, messages: Receiver<Req>, read_fd: libc::c_int) {
+    let mut fd = FileDesc::new(input, true);
+    let mut pipe = FileDesc::new(read_fd, true);
+    let mut registrations: Vec<Box<Registration>> = vec![];
+    // The signals we've installed a handler for, with the previous handlers
+    let mut installed: Vec<(libc::c_int, c::sigaction)> = vec![];
+
+    'outer: loop {
+        let mut set = [
+            c::pollfd { fd: input, events: c::POLLIN, revents: 0 },
+            c::pollfd { fd: read_fd, events: c::POLLIN, revents: 0 },
+        ];
+        match unsafe { c::poll(set.as_mut_ptr(), 2, -1) } {
+            -1 if os::errno() == libc::EINTR as int => continue,
+            n if n < 0 => {
+                fail!("helper thread failed in poll() with error: {} ({})",
+                      n, os::last_os_error())
+            }
+            _ => {}
+        }
+
+        if set[1].revents != 0 {
+            // Each signal is delivered once per drain, see above
+            let mut caught = [false, ..NSIG];
+            let mut buf = [0u8, ..64];
+            loop {
+                match pipe.inner_read(buf) {
+                    Ok(0) | Err(..) => break,
+                    Ok(n) => {
+                        for &signum in buf.slice_to(n).iter() {
+                            caught[signum as uint] = true;
+                        }
+                    }
+                }
+            }
+            for r in registrations.mut_iter() {
+                if caught[r.signum as uint] { r.cb.call() }
+            }
+        }
+
+        if set[0].revents == 0 { continue }
+        loop {
+            match messages.try_recv() {
+                Err(comm::Disconnected) => {
+                    assert!(registrations.len() == 0);
+                    break 'outer;
+                }
+
+                Ok(NewSignal(r, ack)) => {
+                    let signum = r.signum;
+                    if !installed.iter().any(|&(s, _)| s == signum) {
+                        match install(signum) {
+                            Ok(old) => installed.push((signum, old)),
+                            Err(e) => { ack.send(Err(e)); continue }
+                        }
+                    }
+                    registrations.push(r);
+                    ack.send(Ok(()));
+                }
+
+                Ok(RemoveSignal(id, ack)) => {
+                    let i = registrations.iter().position(|r| r.id == id);
+                    let r = registrations.remove(i.expect("no signal found"));
+                    let signum = r.unwrap().signum;
+                    if !registrations.iter().any(|r| r.signum == signum) {
+                        let i = installed.iter().position(|&(s, _)| s == signum);
+                        let (_, old) = installed.remove(i.unwrap()).unwrap();
+                        uninstall(signum, old);
+                    }
+                    ack.send(());
+                }
+
+                Err(..) => break
+            }
+        }
+
+        // drain the file descriptor
+        let mut buf = [0];
+        assert_eq!(fd.inner_read(buf).ok().unwrap(), 1);
+    }
+
+    unsafe {
+        let _ = libc::close(WRITE_FD);
+        WRITE_FD = -1;
+    }
+}
+
+// Sets up the self-pipe. This is run before the helper thread is spawned, so
+// the pipe is there before any handler is installed.
+fn init() -> libc::c_int {
+    unsafe {
+        let mut pipes = [0, ..2];
+        assert_eq!(libc::pipe(pipes.as_mut_ptr()), 0);
+        util::set_nonblocking(pipes[0], true).ok().unwrap();
+        util::set_nonblocking(pipes[1], true).ok().unwrap();
+        WRITE_FD = pipes[1];
+        pipes[0]
+    }
+}
+
+fn install(signum: libc::c_int) -> IoResult<c::sigaction> {
+    if signum <= 0 || signum as uint >= NSIG {
+        return Err(rtio::IoError {
+            code: libc::EINVAL as uint,
+            extra: 0,
+            detail: None,
+        })
+    }
+    unsafe {
+        // The previous handler is published before ours is installed, as our
+        // handler chains to it from the moment it's installed (a SIGCHLD for
+        // the process helper mustn't be lost in between).
+        let mut old: c::sigaction = mem::zeroed();
+        if c::sigaction(signum, ptr::null(), &mut old) != 0 {
+            return Err(super::last_error())
+        }
+        OLD_HANDLERS[signum as uint] = if old.sa_flags & c::SA_SIGINFO != 0 {
+            0
+        } else {
+            mem::transmute(old.sa_handler)
+        };
+
+        let mut new: c::sigaction = mem::zeroed();
+        new.sa_handler = signal_handler;
+        new.sa_flags = c::SA_RESTART;
+        if c::sigaction(signum, &new, ptr::mut_null()) != 0 {
+            OLD_HANDLERS[signum as uint] = 0;
+            return Err(super::last_error())
+        }
+        Ok(old)
+    }
+}
+
+fn uninstall(signum: libc::c_int, old: c::sigaction) {
+    unsafe {
+        let mut cur: c::sigaction = mem::zeroed();
+        assert_eq!(c::sigaction(signum, ptr::null(), &mut cur), 0);
+        let ours: extern fn(libc::c_int) = signal_handler;
+        let cur: uint = mem::transmute(cur.sa_handler);
+        if cur == mem::transmute(ours) {
+            assert_eq!(c::sigaction(signum, &old, ptr::mut_null()), 0);
+            OLD_HANDLERS[signum as uint] = 0;
+        }
+    }
+}
+
+// The signal handler, which must be async-signal-safe! See the SIGCHLD handler
+// in the process module for why the write may be dropped.
+extern fn signal_handler(signum: libc::c_int) {
+    let msg = signum as u8;
+    unsafe {
+        libc::write(WRITE_FD, &msg as *const _ as *const libc::c_void, 1);
+
+        let old = OLD_HANDLERS[signum as uint];
+        if old > libc::SIG_IGN as uint {
+            let old: extern fn(libc::c_int) = mem::transmute(old);
+            old(signum);
+        }
+    }
+}
+
+impl Signal {
+    pub fn new(signum: int, cb: Box<rtio::Callback + Send>) -> IoResult<Signal> {
+        unsafe { HELPER.boot(init, helper); }
+
+        static mut ID: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+        let id = unsafe { ID.fetch_add(1, atomics::Relaxed) };
+        let (tx, rx) = channel();
+        unsafe {
+            HELPER.send(NewSignal(box Registration {
+                id: id,
+                signum: signum as libc::c_int,
+                cb: cb,
+            }, tx));
+        }
+        rx.recv().map(|()| Signal { id: id })
+    }
+}
+
+impl rtio::RtioSignal for Signal {}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        let (tx, rx) = channel();
+        unsafe { HELPER.send(RemoveSignal(self.id, tx)); }
+        rx.recv();
+    }
+}
@@ -277,6 +277,12 @@ impl rtio::RtioTimer for Timer {
 
         unsafe { HELPER.send(NewTimer(inner)); }
     }
+
+    fn cancel(&mut self) {
+        let mut inner = self.inner();
+        inner.cb = None;
+        self.inner = Some(inner);
+    }
 }
 
 impl Drop for Timer {
@@ -175,6 +175,10 @@ impl rtio::RtioTimer for Timer {
         unsafe { HELPER.send(NewTimer(self.obj, cb, false)) }
         self.on_worker = true;
     }
+
+    fn cancel(&mut self) {
+        self.remove();
+    }
 }
 
 impl Drop for Timer {
@@ -101,7 +101,7 @@ pub fn lang_start(main: *const u8, argc: int, argv: *const *const u8) -> int {
 /// for this function to not overflow its stack.
 ///
 /// This function will only return once *all* native threads in the system have
-/// exited.
+/// exited, and the shutdown hooks of `rt::hooks` have run.
 pub fn start(argc: int, argv: *const *const u8, main: proc()) -> int {
     let something_around_the_top_of_the_stack = 1;
     let addr = &something_around_the_top_of_the_stack as *const int;
@@ -140,6 +140,7 @@ pub fn start(argc: int, argv: *const *const u8, main: proc()) -> int {
             rt::stack::record_stack_bounds(my_stack_bottom, my_stack_top);
         }
         exit_code = Some(run(main.take_unwrap()));
+        rt::hooks::run_shutdown_hooks();
     }).destroy());
     unsafe { rt::cleanup(); }
     // If the exit code wasn't set, then the task block must have failed.
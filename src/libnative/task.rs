@@ -51,14 +51,15 @@ fn ops() -> Box<Ops> {
 /// Spawns a function with the default configuration
 #[deprecated = "use the native method of NativeTaskBuilder instead"]
 pub fn spawn(f: proc():Send) {
-    spawn_opts(TaskOpts { name: None, stack_size: None, on_exit: None }, f)
+    spawn_opts(TaskOpts::new(), f)
 }
 
 /// Spawns a new task given the configuration options and a procedure to run
 /// inside the task.
 #[deprecated = "use the native method of NativeTaskBuilder instead"]
 pub fn spawn_opts(opts: TaskOpts, f: proc():Send) {
-    let TaskOpts { name, stack_size, on_exit } = opts;
+    // The OS does the scheduling, so the priority is ignored
+    let TaskOpts { name, stack_size, on_exit, priority: _ } = opts;
 
     let mut task = box Task::new();
     task.name = name;
@@ -263,6 +264,11 @@ impl rt::Runtime for Ops {
         }
     }
 
+    // Native tasks are scheduled by the OS, so there's no switch to defer
+    fn reawaken_later(self: Box<Ops>, to_wake: Box<Task>) {
+        self.reawaken(to_wake)
+    }
+
     fn spawn_sibling(self: Box<Ops>,
                      mut cur_task: Box<Task>,
                      opts: TaskOpts,
@@ -0,0 +1,311 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The wire formats of network channels
+//!
+//! A codec turns each message into the payload of a frame, and back. The
+//! framing itself is the same whatever the codec, so a channel can speak the
+//! format of an existing service by implementing `Encode` and `Decode` for
+//! its messages. Both ends of a channel must of course use the same codec.
+//!
+//! `Ebml` is the default. `Json` is slower and bigger, but the messages can
+//! be read when debugging a connection. `Raw` sends messages which are byte
+//! buffers as they are, without copying them: each of the buffers which make
+//! up a message is written straight to the connection, and a message which is
+//! received as a `Vec<u8>` is the buffer it was read into.
+//!
+//! The payload of a frame is read into memory before it's decoded, so a
+//! frame whose length is above the codec's `max_frame` is refused, as if the
+//! connection had failed. That's 16MB unless the codec is wrapped in a
+//! `Limit`.
+
+use std::io::{IoResult, IoError, MemWriter, OtherIoError};
+use std::mem;
+use std::str;
+use serialize::{Encodable, Decodable};
+use serialize::{ebml, json};
+use serialize::ebml::{reader, writer};
+
+/// The EBML encoder which messages are encoded with by `Ebml`.
+pub type EbmlEncoder = writer::Encoder<'static, MemWriter>;
+
+/// The EBML decoder which messages are decoded with by `Ebml`.
+pub type EbmlDecoder = reader::Decoder<'static>;
+
+/// The JSON encoder which messages are encoded with by `Json`.
+pub type JsonEncoder = json::Encoder<'static>;
+
+/// Encodes messages of type `T` into the payloads of frames.
+pub trait Encode<T> {
+    /// Encodes `msg`.
+    fn encode(&self, msg: &T) -> IoResult<Vec<u8>>;
+
+    /// Returns the buffers which make up the payload of `msg`, if they can be
+    /// written as they are. The payload is then written from them with
+    /// vectored I/O, and `encode` isn't called.
+    fn io_slices<'a>(&self, _msg: &'a T) -> Option<Vec<&'a [u8]>> { None }
+
+    /// The largest payload of a frame which is sent, in bytes. A message
+    /// which is bigger is an `InvalidInput` error, before any of it is sent,
+    /// so that the frames after it still make it through.
+    fn max_frame(&self) -> uint { DEFAULT_MAX_FRAME }
+}
+
+/// The largest payload of a frame which is sent or read by default, in bytes.
+pub static DEFAULT_MAX_FRAME: uint = 16 * 1024 * 1024;
+
+/// Decodes messages of type `T` from the payloads of frames.
+pub trait Decode<T> {
+    /// Decodes a message from `buf`, which is the whole payload of a frame.
+    fn decode(&self, buf: &[u8]) -> IoResult<T>;
+
+    /// Decodes a message from `buf` like `decode`, taking ownership of the
+    /// buffer so that it can be reused by the message.
+    fn decode_owned(&self, buf: Vec<u8>) -> IoResult<T> {
+        self.decode(buf.as_slice())
+    }
+
+    /// The largest payload of a frame which is read, in bytes. A frame which
+    /// is announced as bigger is an `InvalidInput` error, before anything is
+    /// allocated for it.
+    fn max_frame(&self) -> uint { DEFAULT_MAX_FRAME }
+}
+
+/// Messages which are made of byte buffers, which can be sent with `Raw`.
+pub trait ToIoSlices {
+    /// Returns the buffers of the message, in order.
+    fn to_io_slices<'a>(&'a self) -> Vec<&'a [u8]>;
+}
+
+impl ToIoSlices for Vec<u8> {
+    fn to_io_slices<'a>(&'a self) -> Vec<&'a [u8]> { vec![self.as_slice()] }
+}
+
+impl ToIoSlices for Vec<Vec<u8>> {
+    fn to_io_slices<'a>(&'a self) -> Vec<&'a [u8]> {
+        self.iter().map(|v| v.as_slice()).collect()
+    }
+}
+
+/// A codec which can service both directions of a channel of `T`.
+///
+/// This is implemented for every sendable and cloneable type which
+/// implements `Encode<T>` and `Decode<T>`.
+pub trait Codec<T>: Encode<T> + Decode<T> + Clone + Send {}
+
+impl<T, C: Encode<T> + Decode<T> + Clone + Send> Codec<T> for C {}
+
+/// The EBML codec.
+#[deriving(Clone)]
+pub struct Ebml;
+
+/// A codec which refuses to send or read the frames whose payload is bigger
+/// than `max_frame` bytes, and is otherwise `codec`.
+///
+/// ```rust,no_run
+/// extern crate netchan;
+///
+/// use netchan::codec::{Ebml, Limit};
+///
+/// fn main() {
+///     let codec = Limit { codec: Ebml, max_frame: 4096 };
+///     let (tx, rx): (Sender<int>, Receiver<int>) =
+///         netchan::connect_with("127.0.0.1", 8080, codec).unwrap();
+///     tx.send(1);
+///     println!("{}", rx.recv());
+/// }
+/// ```
+#[deriving(Clone)]
+pub struct Limit<C> {
+    /// The codec which messages are encoded and decoded with.
+    pub codec: C,
+    /// The largest payload of a frame which is sent or read, in bytes.
+    pub max_frame: uint,
+}
+
+/// The JSON codec, which sends each message as a JSON document.
+#[deriving(Clone)]
+pub struct Json;
+
+/// The codec of raw byte buffers.
+///
+/// Any message which implements `ToIoSlices` can be sent with this codec, and
+/// the whole payload is received as a single `Vec<u8>`.
+#[deriving(Clone)]
+pub struct Raw;
+
+impl<T: Encodable<EbmlEncoder, IoError>> Encode<T> for Ebml {
+    fn encode(&self, msg: &T) -> IoResult<Vec<u8>> {
+        let mut m = MemWriter::new();
+        // FIXME(14302) remove the transmute and unsafe block.
+        unsafe {
+            let mut encoder = writer::Encoder::new(&mut m);
+            try!(msg.encode(mem::transmute(&mut encoder)));
+        }
+        Ok(m.unwrap())
+    }
+}
+
+impl<T: Send + Decodable<EbmlDecoder, ebml::Error>> Decode<T> for Ebml {
+    fn decode(&self, buf: &[u8]) -> IoResult<T> {
+        // FIXME(14302) remove the transmute and unsafe block. The decoded
+        // message is sendable, so it doesn't borrow from `buf`.
+        let doc = ebml::Doc::new(unsafe { mem::transmute(buf) });
+        let mut decoder = reader::Decoder::new(doc);
+        Decodable::decode(&mut decoder).map_err(|e| match e {
+            ebml::IoError(e) => e,
+            e => decode_error(format!("{}", e)),
+        })
+    }
+}
+
+impl<T: Encodable<JsonEncoder, IoError>> Encode<T> for Json {
+    fn encode(&self, msg: &T) -> IoResult<Vec<u8>> {
+        let mut m = MemWriter::new();
+        // FIXME(14302) remove the transmute and unsafe block.
+        unsafe {
+            let mut encoder = json::Encoder::new(&mut m as &mut Writer);
+            try!(msg.encode(mem::transmute(&mut encoder)));
+        }
+        Ok(m.unwrap())
+    }
+}
+
+impl<T: Decodable<json::Decoder, json::DecoderError>> Decode<T> for Json {
+    fn decode(&self, buf: &[u8]) -> IoResult<T> {
+        let s = match str::from_utf8(buf) {
+            Some(s) => s,
+            None => return Err(decode_error("invalid UTF-8".to_string())),
+        };
+        json::decode(s).map_err(|e| decode_error(format!("{}", e)))
+    }
+}
+
+impl<T: ToIoSlices> Encode<T> for Raw {
+    fn encode(&self, msg: &T) -> IoResult<Vec<u8>> {
+        let mut ret = Vec::new();
+        for s in msg.to_io_slices().iter() {
+            ret.push_all(*s);
+        }
+        Ok(ret)
+    }
+
+    fn io_slices<'a>(&self, msg: &'a T) -> Option<Vec<&'a [u8]>> {
+        Some(msg.to_io_slices())
+    }
+}
+
+impl Decode<Vec<u8>> for Raw {
+    fn decode(&self, buf: &[u8]) -> IoResult<Vec<u8>> {
+        Ok(buf.to_vec())
+    }
+
+    fn decode_owned(&self, buf: Vec<u8>) -> IoResult<Vec<u8>> {
+        Ok(buf)
+    }
+}
+
+impl<T, C: Encode<T>> Encode<T> for Limit<C> {
+    fn encode(&self, msg: &T) -> IoResult<Vec<u8>> {
+        self.codec.encode(msg)
+    }
+
+    fn io_slices<'a>(&self, msg: &'a T) -> Option<Vec<&'a [u8]>> {
+        self.codec.io_slices(msg)
+    }
+
+    fn max_frame(&self) -> uint { self.max_frame }
+}
+
+impl<T, C: Decode<T>> Decode<T> for Limit<C> {
+    fn decode(&self, buf: &[u8]) -> IoResult<T> {
+        self.codec.decode(buf)
+    }
+
+    fn decode_owned(&self, buf: Vec<u8>) -> IoResult<T> {
+        self.codec.decode_owned(buf)
+    }
+
+    fn max_frame(&self) -> uint { self.max_frame }
+}
+
+fn decode_error(detail: String) -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "message could not be decoded",
+        detail: Some(detail),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::IoResult;
+
+    use super::{Encode, Decode, Ebml, Json, Raw, Limit, DEFAULT_MAX_FRAME};
+
+    #[deriving(Encodable, Decodable, PartialEq, Show)]
+    struct Point {
+        x: int,
+        y: int,
+    }
+
+    #[test]
+    fn ebml() {
+        let p = Point { x: 1, y: -2 };
+        let buf = Ebml.encode(&p).unwrap();
+        let q: Point = Ebml.decode(buf.as_slice()).unwrap();
+        assert_eq!(p, q);
+    }
+
+    #[test]
+    fn json() {
+        let p = Point { x: 1, y: -2 };
+        let buf = Json.encode(&p).unwrap();
+        assert_eq!(buf.as_slice(), "{\"x\":1,\"y\":-2}".as_bytes());
+        let q: Point = Json.decode(buf.as_slice()).unwrap();
+        assert_eq!(p, q);
+    }
+
+    #[test]
+    fn raw() {
+        let msg = vec![vec![1u8, 2], vec![], vec![3]];
+        let slices = Raw.io_slices(&msg).unwrap();
+        let slices: Vec<Vec<u8>> = slices.iter().map(|s| s.to_vec()).collect();
+        assert_eq!(slices, msg);
+        assert_eq!(Raw.encode(&msg).unwrap(), vec![1, 2, 3]);
+        assert!(Ebml.io_slices(&msg).is_none());
+
+        let buf = vec![1u8, 2, 3];
+        let ptr = buf.as_ptr();
+        let received: Vec<u8> = Raw.decode_owned(buf).unwrap();
+        assert_eq!(received.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn json_invalid() {
+        let p: IoResult<Point> = Json.decode("{\"x\":1}".as_bytes());
+        assert!(p.is_err());
+        let p: IoResult<Point> = Json.decode([0xff]);
+        assert!(p.is_err());
+    }
+
+    fn max_frame<D: Decode<Vec<u8>>>(codec: &D) -> uint { codec.max_frame() }
+
+    #[test]
+    fn limit() {
+        assert_eq!(max_frame(&Raw), DEFAULT_MAX_FRAME);
+        let codec = Limit { codec: Raw, max_frame: 10 };
+        assert_eq!(max_frame(&codec), 10);
+        let msg = vec![1u8, 2];
+        assert_eq!(codec.io_slices(&msg).unwrap(), vec![msg.as_slice()]);
+        let received: Vec<u8> = codec.decode_owned(msg.clone()).unwrap();
+        assert_eq!(received, msg);
+    }
+}
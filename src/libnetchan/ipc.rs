@@ -0,0 +1,759 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Typed channels between processes
+
+`ipc::channel` creates a channel over a Unix-domain socket pair. Its two ends
+are returned as an `IpcSender` and an `IpcReceiver`, which own the descriptors
+of the socket and can be handed to another process before they're opened into
+an ordinary `Sender` or `Receiver`:
+
+* A child process inherits an end with `Command::extra_io`, and takes it back
+  with `from_fd`.
+* An end can be sent as a message on another IPC channel, or as part of one,
+  in which case its descriptor is passed along with the message. The end is
+  consumed by the send, and comes out of the other side as a new end in the
+  receiving process.
+
+Messages are framed as in `netchan`, with a count of the descriptors passed
+with each of them, and are refused (or left out, when they're too long to be
+sent) in the same way when they're longer than the codec's `max_frame`. Once
+opened, the ends are serviced by a task which waits for the socket with an
+`FdWatcher`, so IPC channels work under both runtimes. They're only available
+on Unix.
+
+Ends can be sent with the `Ebml` and `Json` codecs. Messages which contain an
+end must implement `Encodable` and `Decodable` for the encoder and decoder of
+the codec: ends can only be sent over IPC channels, so they don't implement
+the generic traits which `#[deriving(Encodable, Decodable)]` requires.
+Tuples, vectors and options of ends can be sent as they are. Sending an end
+over a TCP channel fails, and disconnects the channel.
+
+# Example
+
+```rust,no_run
+# #![allow(unused_must_use)]
+extern crate netchan;
+
+use std::io::Command;
+use std::io::process::InheritFd;
+use std::os;
+
+fn main() {
+    if os::args().len() > 1 {
+        // In the child, the end is descriptor 3
+        let tx: netchan::ipc::IpcSender<String> = unsafe {
+            netchan::ipc::IpcSender::from_fd(3)
+        };
+        tx.open().send("hello from the child".to_string());
+        return
+    }
+
+    let (tx, rx) = netchan::ipc::channel::<String>().unwrap();
+    let child = Command::new(os::self_exe_name().unwrap())
+                        .arg("child")
+                        .extra_io(InheritFd(tx.fd()))
+                        .spawn();
+    drop(tx);
+    println!("{}", rx.open().recv());
+    child.unwrap().wait();
+}
+```
+
+*/
+
+use libc::{c_int, c_void, size_t};
+use libc;
+use std::io::{IoResult, IoError, MemWriter, OtherIoError, EndOfFile};
+use std::io::fd_watcher::FdWatcher;
+use std::comm;
+use std::io;
+use std::mem;
+use std::os;
+use serialize::{Encodable, Decodable};
+use serialize::{ebml, json};
+use SerEncoder = serialize::Encoder;
+use SerDecoder = serialize::Decoder;
+
+use super::Message;
+use codec::{Encode, Decode, Ebml, EbmlEncoder, EbmlDecoder, JsonEncoder};
+
+// The most descriptors which can be passed with one message. Linux doesn't
+// pass more than 253 at once.
+static MAX_FDS: uint = 64;
+
+// The most buffers which are sent at once, which is well under IOV_MAX
+static MAX_IOVS: uint = 64;
+
+// The descriptors of the ends sent or received with the message which is
+// being encoded or decoded by this task.
+local_data_key!(OUTGOING: Vec<c_int>)
+local_data_key!(INCOMING: Vec<Fd>)
+
+/// The sending end of an IPC channel, which hasn't been opened yet.
+pub struct IpcSender<T> {
+    fd: Fd,
+}
+
+/// The receiving end of an IPC channel, which hasn't been opened yet.
+pub struct IpcReceiver<T> {
+    fd: Fd,
+}
+
+/// Creates an IPC channel, returning its two ends.
+///
+/// # Error
+///
+/// An error is returned if the socket pair can't be created.
+pub fn channel<T: Send>() -> IoResult<(IpcSender<T>, IpcReceiver<T>)> {
+    let mut fds = [0, ..2];
+    if unsafe { imp::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0,
+                                fds.as_mut_ptr()) } != 0 {
+        return Err(IoError::last_error())
+    }
+    let (tx, rx) = (Fd(fds[0]), Fd(fds[1]));
+    set_cloexec(tx.raw());
+    set_cloexec(rx.raw());
+    Ok((IpcSender { fd: tx }, IpcReceiver { fd: rx }))
+}
+
+impl<T: Send> IpcSender<T> {
+    /// Takes ownership of the end whose descriptor is `fd`, such as one which
+    /// was inherited from the parent process.
+    ///
+    /// This is unsafe because `fd` must be the sending end of an IPC channel
+    /// of `T`, which isn't used by anything else.
+    pub unsafe fn from_fd(fd: c_int) -> IpcSender<T> {
+        IpcSender { fd: Fd(fd) }
+    }
+
+    /// Returns the descriptor of this end, which is still owned by it.
+    pub fn fd(&self) -> c_int { self.fd.raw() }
+
+    /// Opens this end like `open`, with the messages sent in the format of
+    /// `codec`.
+    pub fn open_with<E: Encode<T> + Send>(self, codec: E) -> Sender<T> {
+        let IpcSender { fd } = self;
+        let (tx, rx) = comm::channel();
+        spawn(proc() write_loop(rx, Socket::new(fd, false, true), codec));
+        tx
+    }
+}
+
+impl<T: Message> IpcSender<T> {
+    /// Opens this end, starting the task which writes the messages sent on
+    /// the returned `Sender` to the socket.
+    pub fn open(self) -> Sender<T> {
+        self.open_with(Ebml)
+    }
+}
+
+impl<T: Send> IpcReceiver<T> {
+    /// Takes ownership of the end whose descriptor is `fd`, such as one which
+    /// was inherited from the parent process.
+    ///
+    /// This is unsafe because `fd` must be the receiving end of an IPC
+    /// channel of `T`, which isn't used by anything else.
+    pub unsafe fn from_fd(fd: c_int) -> IpcReceiver<T> {
+        IpcReceiver { fd: Fd(fd) }
+    }
+
+    /// Returns the descriptor of this end, which is still owned by it.
+    pub fn fd(&self) -> c_int { self.fd.raw() }
+
+    /// Opens this end like `open`, with the messages decoded from the format
+    /// of `codec`.
+    pub fn open_with<D: Decode<T> + Send>(self, codec: D) -> Receiver<T> {
+        let IpcReceiver { fd } = self;
+        let (tx, rx) = comm::channel();
+        spawn(proc() read_loop(Socket::new(fd, true, false), tx, codec));
+        rx
+    }
+}
+
+impl<T: Message> IpcReceiver<T> {
+    /// Opens this end, starting the task which reads the messages from the
+    /// socket and sends them on the returned `Receiver`.
+    pub fn open(self) -> Receiver<T> {
+        self.open_with(Ebml)
+    }
+}
+
+// The ends are sent as the index of their descriptor among those passed with
+// the message, with the codecs which are built in
+macro_rules! impl_end(
+    ($ty:ident) => (
+        impl<T> Encodable<EbmlEncoder, IoError> for $ty<T> {
+            fn encode(&self, s: &mut EbmlEncoder) -> IoResult<()> {
+                let i = try!(pass_fd(self.fd.raw()));
+                s.emit_uint(i)
+            }
+        }
+
+        impl<T> Decodable<EbmlDecoder, ebml::Error> for $ty<T> {
+            fn decode(d: &mut EbmlDecoder) -> Result<$ty<T>, ebml::Error> {
+                let i = try!(d.read_uint());
+                match take_fd(i) {
+                    Some(fd) => Ok($ty { fd: fd }),
+                    None => Err(ebml::Expected(format!("descriptor {}", i))),
+                }
+            }
+        }
+
+        impl<T> Encodable<JsonEncoder, IoError> for $ty<T> {
+            fn encode(&self, s: &mut JsonEncoder) -> IoResult<()> {
+                let i = try!(pass_fd(self.fd.raw()));
+                s.emit_uint(i)
+            }
+        }
+
+        impl<T> Decodable<json::Decoder, json::DecoderError> for $ty<T> {
+            fn decode(d: &mut json::Decoder)
+                      -> Result<$ty<T>, json::DecoderError> {
+                let i = try!(d.read_uint());
+                match take_fd(i) {
+                    Some(fd) => Ok($ty { fd: fd }),
+                    None => Err(json::ExpectedError(format!("descriptor {}", i),
+                                                    "no descriptor".to_string())),
+                }
+            }
+        }
+    )
+)
+
+impl_end!(IpcSender)
+impl_end!(IpcReceiver)
+
+// Adds `fd` to the descriptors passed with the message being encoded, and
+// returns its index among them
+fn pass_fd(fd: c_int) -> IoResult<uint> {
+    match OUTGOING.replace(None) {
+        Some(mut fds) => {
+            fds.push(fd);
+            let i = fds.len() - 1;
+            OUTGOING.replace(Some(fds));
+            Ok(i)
+        }
+        None => Err(IoError {
+            kind: OtherIoError,
+            desc: "IPC channel ends can only be sent over IPC channels",
+            detail: None,
+        }),
+    }
+}
+
+// Takes the descriptor at index `i` of those passed with the message being
+// decoded
+fn take_fd(i: uint) -> Option<Fd> {
+    let mut fds = match INCOMING.replace(None) {
+        Some(fds) => fds,
+        None => return None,
+    };
+    let ret = if i < fds.len() && fds.get(i).raw() >= 0 {
+        Some(mem::replace(fds.get_mut(i), Fd(-1)))
+    } else {
+        None
+    };
+    INCOMING.replace(Some(fds));
+    ret
+}
+
+fn write_loop<T: Send, E: Encode<T>>(rx: Receiver<T>, mut socket: Socket,
+                                     codec: E) {
+    for msg in rx.iter() {
+        match write_frame(&mut socket, &codec, &msg) {
+            Ok(()) => {}
+            // A message which is too long is left out, as in `netchan`
+            Err(IoError { kind: io::InvalidInput, .. }) => {}
+            Err(..) => break,
+        }
+        // The ends in `msg` are dropped here, which closes them on this side
+        // now that they've been passed
+    }
+}
+
+fn write_frame<T, E: Encode<T>>(socket: &mut Socket, codec: &E,
+                                msg: &T) -> IoResult<()> {
+    match codec.io_slices(msg) {
+        // Only messages which are encoded can have ends in them
+        Some(slices) => {
+            let len = slices.iter().fold(0, |n, s| n + s.len());
+            try!(::check_sent_frame(codec, len));
+            socket.send_frame(slices.as_slice(), &[])
+        }
+        None => {
+            OUTGOING.replace(Some(Vec::new()));
+            let buf = codec.encode(msg);
+            let fds = OUTGOING.replace(None).unwrap();
+            let buf = try!(buf);
+            try!(::check_sent_frame(codec, buf.len()));
+            socket.send_frame(&[buf.as_slice()], fds.as_slice())
+        }
+    }
+}
+
+fn read_loop<T: Send, D: Decode<T>>(mut socket: Socket, tx: Sender<T>,
+                                    codec: D) {
+    loop {
+        match read_frame(&mut socket, &codec) {
+            Ok(msg) => {
+                if tx.send_opt(msg).is_err() { break }
+            }
+            Err(..) => break,
+        }
+    }
+}
+
+fn read_frame<T, D: Decode<T>>(socket: &mut Socket, codec: &D) -> IoResult<T> {
+    let len = try!(socket.read_be_u32()) as uint;
+    let nfds = try!(socket.read_be_u32()) as uint;
+    try!(::check_frame(codec, len));
+    let buf = try!(socket.read_exact(len));
+
+    // The descriptors were received along with the header
+    if socket.fds.len() < nfds {
+        return Err(IoError {
+            kind: OtherIoError,
+            desc: "descriptors of a message are missing",
+            detail: None,
+        })
+    }
+    let fds = range(0, nfds).map(|_| socket.fds.remove(0).unwrap()).collect();
+    INCOMING.replace(Some(fds));
+    let ret = codec.decode_owned(buf);
+    // Any descriptors which weren't decoded are closed
+    INCOMING.replace(None);
+    ret
+}
+
+// A descriptor which is closed when it's dropped, unless it's -1
+struct Fd(c_int);
+
+impl Fd {
+    fn raw(&self) -> c_int { let Fd(fd) = *self; fd }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        if self.raw() >= 0 {
+            unsafe { libc::close(self.raw()); }
+        }
+    }
+}
+
+fn set_cloexec(fd: c_int) {
+    unsafe { imp::fcntl(fd, imp::F_SETFD, imp::FD_CLOEXEC); }
+}
+
+// One end of the socket pair, which is made non-blocking and waited on with a
+// watcher so that the scheduler isn't blocked
+struct Socket {
+    watcher: FdWatcher,
+    fd: Fd,
+    // The descriptors which have been received but not taken yet
+    fds: Vec<Fd>,
+}
+
+impl Socket {
+    fn new(fd: Fd, readable: bool, writable: bool) -> Socket {
+        unsafe { imp::fcntl(fd.raw(), imp::F_SETFL, imp::O_NONBLOCK); }
+        let watcher = FdWatcher::new(fd.raw(), readable, writable).unwrap();
+        Socket { watcher: watcher, fd: fd, fds: Vec::new() }
+    }
+
+    // Waits for the socket to become ready. The first wait may return right
+    // away, for an event which was sent earlier, in which case the operation
+    // would block again and the next wait is for real.
+    fn wait(&mut self) {
+        let _ = self.watcher.rx.recv();
+        self.watcher.rearm();
+    }
+
+    // Whether the last call failed because it would block, or was interrupted,
+    // and should be retried
+    fn retry(&mut self) -> IoResult<()> {
+        let errno = os::errno() as c_int;
+        if errno == libc::EINTR { return Ok(()) }
+        if errno == libc::EAGAIN || errno == libc::EWOULDBLOCK {
+            self.wait();
+            return Ok(())
+        }
+        Err(IoError::last_error())
+    }
+
+    // Sends a frame whose payload is made of the buffers of `payload`
+    fn send_frame(&mut self, payload: &[&[u8]], fds: &[c_int]) -> IoResult<()> {
+        if fds.len() > MAX_FDS {
+            return Err(IoError {
+                kind: OtherIoError,
+                desc: "too many descriptors are passed with one message",
+                detail: None,
+            })
+        }
+        let len = payload.iter().fold(0, |n, s| n + s.len());
+        let mut header = MemWriter::new();
+        try!(header.write_be_u32(len as u32));
+        try!(header.write_be_u32(fds.len() as u32));
+        let mut bufs = vec![header.get_ref()];
+        bufs.push_all(payload);
+        self.send(bufs.as_slice(), fds)
+    }
+
+    // Sends all of `bufs` with vectored I/O, passing `fds` along with the first
+    // byte
+    fn send(&mut self, bufs: &[&[u8]], fds: &[c_int]) -> IoResult<()> {
+        let mut control = imp::Control::new(fds);
+        let mut bufs: Vec<&[u8]> = bufs.iter().map(|b| *b)
+                                       .filter(|b| b.len() > 0).collect();
+        let mut first = 0;
+        while first < bufs.len() {
+            let mut iov: Vec<imp::iovec> =
+                bufs.slice_from(first).iter().take(MAX_IOVS).map(|b| {
+                    imp::iovec {
+                        iov_base: b.as_ptr() as *mut c_void,
+                        iov_len: b.len() as size_t,
+                    }
+                }).collect();
+            let mut msg: imp::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = iov.as_mut_ptr();
+            msg.msg_iovlen = iov.len() as imp::iovlen_t;
+            control.prepare(&mut msg);
+            match unsafe { imp::sendmsg(self.fd.raw(), &msg, 0) } {
+                -1 => try!(self.retry()),
+                n => {
+                    // Skip what was sent, which may end partway into a buffer
+                    let mut n = n as uint;
+                    while n > 0 {
+                        let b = *bufs.get(first);
+                        if n >= b.len() {
+                            n -= b.len();
+                            first += 1;
+                        } else {
+                            *bufs.get_mut(first) = b.slice_from(n);
+                            n = 0;
+                        }
+                    }
+                    control = imp::Control::new(&[]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Reader for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let mut control = imp::Control::with_capacity(MAX_FDS);
+        loop {
+            let mut iov = imp::iovec {
+                iov_base: buf.as_mut_ptr() as *mut c_void,
+                iov_len: buf.len() as size_t,
+            };
+            let mut msg: imp::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            control.prepare(&mut msg);
+            match unsafe { imp::recvmsg(self.fd.raw(), &mut msg, 0) } {
+                -1 => try!(self.retry()),
+                0 => return Err(io::standard_error(EndOfFile)),
+                n => {
+                    for fd in control.received(&msg).move_iter() {
+                        set_cloexec(fd);
+                        self.fds.push(Fd(fd));
+                    }
+                    return Ok(n as uint)
+                }
+            }
+        }
+    }
+}
+
+mod imp {
+    use libc::{c_int, c_void, size_t, ssize_t};
+    use libc;
+    use std::mem;
+    use std::ptr;
+    use std::slice;
+
+    #[cfg(target_os = "linux")]
+    #[cfg(target_os = "android")]
+    pub static O_NONBLOCK: c_int = 0o4000;
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "ios")]
+    #[cfg(target_os = "freebsd")]
+    pub static O_NONBLOCK: c_int = 0x0004;
+    pub static F_SETFD: c_int = 2;
+    pub static F_SETFL: c_int = 4;
+    pub static FD_CLOEXEC: c_int = 1;
+    static SCM_RIGHTS: c_int = 1;
+
+    #[repr(C)]
+    pub struct iovec {
+        pub iov_base: *mut c_void,
+        pub iov_len: size_t,
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(target_os = "android")]
+    mod os {
+        use libc::{c_int, c_void, size_t};
+        use super::iovec;
+
+        #[repr(C)]
+        pub struct msghdr {
+            pub msg_name: *mut c_void,
+            pub msg_namelen: u32,
+            pub msg_iov: *mut iovec,
+            pub msg_iovlen: size_t,
+            pub msg_control: *mut c_void,
+            pub msg_controllen: size_t,
+            pub msg_flags: c_int,
+        }
+
+        #[repr(C)]
+        pub struct cmsghdr {
+            pub cmsg_len: size_t,
+            pub cmsg_level: c_int,
+            pub cmsg_type: c_int,
+        }
+
+        pub type controllen_t = size_t;
+        pub type iovlen_t = size_t;
+        pub type cmsglen_t = size_t;
+        pub static ALIGN: uint = ::std::uint::BYTES;
+    }
+
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "ios")]
+    #[cfg(target_os = "freebsd")]
+    mod os {
+        use libc::{c_int, c_void};
+        use super::iovec;
+
+        #[repr(C)]
+        pub struct msghdr {
+            pub msg_name: *mut c_void,
+            pub msg_namelen: u32,
+            pub msg_iov: *mut iovec,
+            pub msg_iovlen: c_int,
+            pub msg_control: *mut c_void,
+            pub msg_controllen: u32,
+            pub msg_flags: c_int,
+        }
+
+        #[repr(C)]
+        pub struct cmsghdr {
+            pub cmsg_len: u32,
+            pub cmsg_level: c_int,
+            pub cmsg_type: c_int,
+        }
+
+        pub type controllen_t = u32;
+        pub type iovlen_t = c_int;
+        pub type cmsglen_t = u32;
+        #[cfg(target_os = "macos")]
+        #[cfg(target_os = "ios")]
+        pub static ALIGN: uint = 4;
+        #[cfg(target_os = "freebsd")]
+        pub static ALIGN: uint = ::std::uint::BYTES;
+    }
+
+    pub use self::os::{msghdr, iovlen_t};
+    use self::os::{cmsghdr, controllen_t, cmsglen_t, ALIGN};
+
+    extern {
+        pub fn socketpair(domain: c_int, ty: c_int, protocol: c_int,
+                          sv: *mut c_int) -> c_int;
+        pub fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+        pub fn sendmsg(fd: c_int, msg: *const msghdr, flags: c_int) -> ssize_t;
+        pub fn recvmsg(fd: c_int, msg: *mut msghdr, flags: c_int) -> ssize_t;
+    }
+
+    fn align(n: uint) -> uint { (n + ALIGN - 1) & !(ALIGN - 1) }
+    fn header_len() -> uint { align(mem::size_of::<cmsghdr>()) }
+    fn space(nfds: uint) -> uint {
+        header_len() + align(nfds * mem::size_of::<c_int>())
+    }
+
+    // The ancillary data of a message, in a buffer which is aligned for its
+    // headers
+    pub struct Control {
+        buf: Vec<u64>,
+        len: uint,
+    }
+
+    impl Control {
+        // Ancillary data passing `fds`, or none if it's empty
+        pub fn new(fds: &[c_int]) -> Control {
+            if fds.len() == 0 { return Control { buf: Vec::new(), len: 0 } }
+            let mut ret = Control::with_capacity(fds.len());
+            unsafe {
+                let hdr = ret.buf.as_mut_ptr() as *mut cmsghdr;
+                (*hdr).cmsg_len = (header_len() + fds.len() *
+                                   mem::size_of::<c_int>()) as cmsglen_t;
+                (*hdr).cmsg_level = libc::SOL_SOCKET;
+                (*hdr).cmsg_type = SCM_RIGHTS;
+                let data = (hdr as *mut u8).offset(header_len() as int);
+                ptr::copy_nonoverlapping_memory(data as *mut c_int,
+                                                fds.as_ptr(), fds.len());
+            }
+            ret
+        }
+
+        // Room for receiving up to `nfds` descriptors
+        pub fn with_capacity(nfds: uint) -> Control {
+            let len = space(nfds);
+            Control { buf: Vec::from_elem((len + 7) / 8, 0u64), len: len }
+        }
+
+        pub fn prepare(&mut self, msg: &mut msghdr) {
+            if self.len == 0 { return }
+            msg.msg_control = self.buf.as_mut_ptr() as *mut c_void;
+            msg.msg_controllen = self.len as controllen_t;
+        }
+
+        // The descriptors received with `msg`
+        pub fn received(&self, msg: &msghdr) -> Vec<c_int> {
+            let mut ret = Vec::new();
+            let base = self.buf.as_ptr() as *const u8;
+            let end = msg.msg_controllen as uint;
+            let mut off = 0;
+            while off + header_len() <= end {
+                unsafe {
+                    let hdr = base.offset(off as int) as *const cmsghdr;
+                    let len = (*hdr).cmsg_len as uint;
+                    if len < header_len() { break }
+                    if (*hdr).cmsg_level == libc::SOL_SOCKET &&
+                       (*hdr).cmsg_type == SCM_RIGHTS {
+                        let n = (len - header_len()) / mem::size_of::<c_int>();
+                        let data = base.offset((off + header_len()) as int);
+                        slice::raw::buf_as_slice(data as *const c_int, n, |fds| {
+                            ret.push_all(fds);
+                        });
+                    }
+                    off += align(len);
+                }
+            }
+            ret
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use codec::{Ebml, Json, Raw, Limit};
+
+    use super::{channel, IpcSender, IpcReceiver};
+
+    #[test]
+    fn smoke() {
+        let (tx, rx) = channel::<String>().unwrap();
+        let tx = tx.open();
+        let rx = rx.open();
+        tx.send("hello".to_string());
+        assert_eq!(rx.recv().as_slice(), "hello");
+    }
+
+    #[test]
+    fn sender_dropped() {
+        let (tx, rx) = channel::<int>().unwrap();
+        let tx = tx.open();
+        let rx = rx.open();
+        for i in range(0i, 100) { tx.send(i) }
+        drop(tx);
+        for i in range(0i, 100) { assert_eq!(rx.recv(), i) }
+        assert_eq!(rx.recv_opt(), Err(()));
+    }
+
+    #[test]
+    fn receiver_dropped() {
+        let (tx, rx) = channel::<int>().unwrap();
+        let tx = tx.open();
+        drop(rx);
+        while tx.send_opt(1).is_ok() {}
+    }
+
+    #[test]
+    fn pass_ends() {
+        let (tx, rx) = channel::<(IpcSender<int>, IpcReceiver<int>)>().unwrap();
+        let (tx, rx) = (tx.open(), rx.open());
+        let (tx2, rx2) = channel::<int>().unwrap();
+        tx.send((tx2, rx2));
+
+        let (tx2, rx2) = rx.recv();
+        let (tx2, rx2) = (tx2.open(), rx2.open());
+        tx2.send(10);
+        assert_eq!(rx2.recv(), 10);
+    }
+
+    #[test]
+    fn raw() {
+        let (tx, rx) = channel::<Vec<u8>>().unwrap();
+        let (tx, rx) = (tx.open_with(Raw), rx.open_with(Raw));
+        // Bigger than the socket buffer, so it's sent in pieces
+        let buf = Vec::from_fn(1 << 20, |i| i as u8);
+        tx.send(buf.clone());
+        tx.send(Vec::new());
+        assert!(rx.recv() == buf);
+        assert_eq!(rx.recv(), Vec::new());
+    }
+
+    #[test]
+    fn frame_too_long() {
+        let (tx, rx) = channel::<Vec<u8>>().unwrap();
+        let tx = tx.open_with(Raw);
+        let rx = rx.open_with(Limit { codec: Raw, max_frame: 10 });
+        tx.send(Vec::from_elem(10, 1u8));
+        tx.send(Vec::from_elem(11, 2u8));
+        assert_eq!(rx.recv(), Vec::from_elem(10, 1u8));
+        assert_eq!(rx.recv_opt(), Err(()));
+    }
+
+    #[test]
+    fn pass_many_ends() {
+        let (tx, rx) = channel::<Vec<IpcReceiver<int>>>().unwrap();
+        let (tx, rx) = (tx.open(), rx.open());
+        let (senders, receivers) = Vec::unzip(range(0i, 10).map(|_| {
+            channel::<int>().unwrap()
+        }));
+        tx.send(receivers);
+
+        let receivers = rx.recv();
+        for (i, tx) in senders.move_iter().enumerate() {
+            tx.open().send(i as int);
+        }
+        for (i, rx) in receivers.move_iter().enumerate() {
+            assert_eq!(rx.open().recv(), i as int);
+        }
+    }
+
+    #[test]
+    fn end_over_tcp() {
+        use std::io::MemWriter;
+
+        let (tx, _rx) = channel::<int>().unwrap();
+        let mut w = MemWriter::new();
+        assert!(::write_message(&mut w, &Ebml, &tx).is_err());
+    }
+
+    #[test]
+    fn pass_ends_json() {
+        let (tx, rx) = channel::<Option<IpcSender<int>>>().unwrap();
+        let (tx, rx) = (tx.open_with(Json), rx.open_with(Json));
+        let (tx2, rx2) = channel::<int>().unwrap();
+        tx.send(Some(tx2));
+
+        let tx2 = rx.recv().unwrap();
+        tx2.open_with(Json).send(10);
+        assert_eq!(rx2.open_with(Json).recv(), 10);
+    }
+}
@@ -0,0 +1,561 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Typed channels over the network
+
+A network channel is an ordinary `Sender<T>` and `Receiver<T>` pair whose
+other ends are in another process, at the other end of a TCP connection. Each
+message is encoded with a codec, EBML by default, and sent as a frame of its
+own, prefixed with its length as a big-endian `u32`. The codecs are in the
+`codec` module. A frame which is longer than the codec's `max_frame` (16MB by
+default) is refused without being read, and a message which is too long for
+it isn't sent: the channel drops it (as if it had been lost) rather than
+breaking the connection for the messages after it.
+
+The connection is serviced by two tasks of its own: one receiving the
+messages from the `Sender` and writing them to the connection, and one
+reading the messages from the connection and sending them on the `Receiver`.
+As they're normal channels, they can be used with `select!` like any other.
+
+Disconnection works like it does for local channels:
+
+* Dropping the `Sender` half-closes the connection once everything sent has
+  been written, and the `Receiver` at the other end is disconnected once it
+  has received all of it.
+* When the connection is closed or fails, the `Receiver` is disconnected after
+  the messages read so far, and sending on the `Sender` starts to fail.
+
+A message which can't be decoded is treated as the connection failing.
+
+Channels between the processes of one machine can also be made over
+Unix-domain sockets with the `ipc` module, whose ends can be inherited by child
+processes and passed to other processes.
+
+Channels which cross untrusted networks can be encrypted and authenticated
+with TLS by the `tls` module, whose `TlsConfig` makes and accepts them in the
+same way as `connect` and `listen`. It links to OpenSSL, so it's only built
+with `--cfg tls` (`./configure --enable-netchan-tls`).
+
+Messages can also be broadcast to every subscriber of a UDP multicast group,
+without guarantee of delivery, with `broadcast`.
+
+# Example
+
+```rust,no_run
+extern crate netchan;
+
+fn main() {
+    let mut acceptor = netchan::listen::<String>("127.0.0.1", 8080).unwrap();
+    spawn(proc() {
+        let (tx, rx) = netchan::connect::<String>("127.0.0.1", 8080).unwrap();
+        tx.send("ping".to_string());
+        assert_eq!(rx.recv().as_slice(), "pong");
+    });
+
+    let (tx, rx) = acceptor.accept().unwrap();
+    assert_eq!(rx.recv().as_slice(), "ping");
+    tx.send("pong".to_string());
+}
+```
+
+*/
+
+#![crate_name = "netchan"]
+#![experimental]
+#![crate_type = "rlib"]
+#![crate_type = "dylib"]
+#![license = "MIT/ASL2"]
+#![doc(html_logo_url = "http://www.rust-lang.org/logos/rust-logo-128x128-blk-v2.png",
+       html_favicon_url = "http://www.rust-lang.org/favicon.ico",
+       html_root_url = "http://doc.rust-lang.org/master/",
+       html_playground_url = "http://play.rust-lang.org/")]
+#![feature(default_type_params)]
+
+extern crate libc;
+extern crate serialize;
+
+use std::cmp;
+use std::io::{BufferedReader, MemWriter, IoResult, IoError, InvalidInput};
+use std::io::{TcpStream, TcpListener, TcpAcceptor, Listener, Acceptor};
+use std::u32;
+use serialize::{Encodable, Decodable};
+use serialize::ebml;
+
+use codec::{Codec, Encode, Decode, Ebml, EbmlEncoder, EbmlDecoder};
+
+pub use multicast::{broadcast, broadcast_with};
+
+pub mod codec;
+#[cfg(unix)]
+pub mod ipc;
+pub mod multicast;
+#[cfg(tls)]
+pub mod tls;
+
+/// The types which can be sent over a network channel with the default
+/// codec, `Ebml`.
+///
+/// This is implemented for every sendable type which can be encoded and
+/// decoded, such as those which `#[deriving(Encodable, Decodable)]`.
+pub trait Message: Send + Encodable<EbmlEncoder, IoError> +
+                   Decodable<EbmlDecoder, ebml::Error> {}
+
+impl<T: Send + Encodable<EbmlEncoder, IoError> +
+        Decodable<EbmlDecoder, ebml::Error>> Message for T {}
+
+/// Connects to a network channel listening at `host` and `port`, returning
+/// the ends of the channel on this side of the connection.
+///
+/// # Error
+///
+/// An error is returned if the connection can't be made.
+pub fn connect<T: Message>(host: &str, port: u16) -> IoResult<(Sender<T>, Receiver<T>)> {
+    connect_with(host, port, Ebml)
+}
+
+/// Connects to a network channel like `connect`, with the messages sent in
+/// the format of `codec`.
+pub fn connect_with<T: Send, C: Codec<T>>(host: &str, port: u16, codec: C)
+                                          -> IoResult<(Sender<T>, Receiver<T>)> {
+    let stream = try!(TcpStream::connect(host, port));
+    Ok(spawn_tcp(stream, codec))
+}
+
+/// Listens for network channels at `host` and `port`.
+///
+/// # Error
+///
+/// An error is returned if the address can't be bound to.
+pub fn listen<T: Message>(host: &str, port: u16) -> IoResult<NetAcceptor<T, Ebml>> {
+    listen_with(host, port, Ebml)
+}
+
+/// Listens for network channels like `listen`, with the messages of the
+/// channels which are accepted sent in the format of `codec`.
+pub fn listen_with<T: Send, C: Codec<T>>(host: &str, port: u16, codec: C)
+                                         -> IoResult<NetAcceptor<T, C>> {
+    let acceptor = try!(TcpListener::bind(host, port).listen());
+    Ok(NetAcceptor { acceptor: acceptor, codec: codec })
+}
+
+/// Accepts the network channels connected to by `connect`, as returned by
+/// `listen`.
+pub struct NetAcceptor<T, C = Ebml> {
+    acceptor: TcpAcceptor,
+    codec: C,
+}
+
+impl<T: Send, C: Codec<T>> NetAcceptor<T, C> {
+    /// Waits for a connection, and returns the ends of its channel on this
+    /// side of it.
+    pub fn accept(&mut self) -> IoResult<(Sender<T>, Receiver<T>)> {
+        let stream = try!(self.acceptor.accept());
+        Ok(spawn_tcp(stream, self.codec.clone()))
+    }
+
+    /// Sets a timeout on `accept` in the same way as
+    /// `TcpAcceptor::set_timeout`.
+    #[experimental = "the name and arguments of timeout functions are likely \
+                      to change"]
+    pub fn set_timeout(&mut self, ms: Option<u64>) {
+        self.acceptor.set_timeout(ms)
+    }
+}
+
+/// A writer which can write several buffers at once.
+///
+/// Channels are serviced by one of these, so that the buffers of a message
+/// which is sent as they are (see `Encode::io_slices`) reach the connection
+/// without being copied. By default, the buffers are written one at a time.
+#[doc(hidden)]
+pub trait VectoredWriter: Writer {
+    /// Writes all of `bufs`, one after the other.
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> IoResult<()> {
+        for buf in bufs.iter() {
+            try!(self.write(*buf));
+        }
+        Ok(())
+    }
+}
+
+// The writing half of a TCP connection, which is half-closed when it's dropped
+// so that the other end reads the end of the stream
+struct TcpWriter(TcpStream);
+
+impl Writer for TcpWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        let TcpWriter(ref mut s) = *self;
+        s.write(buf)
+    }
+}
+
+impl VectoredWriter for TcpWriter {
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> IoResult<()> {
+        let TcpWriter(ref mut s) = *self;
+        s.write_vectored(bufs)
+    }
+}
+
+impl Drop for TcpWriter {
+    fn drop(&mut self) {
+        let TcpWriter(ref mut s) = *self;
+        let _ = s.close_write();
+    }
+}
+
+fn spawn_tcp<T: Send, C: Codec<T>>(stream: TcpStream, codec: C)
+                                   -> (Sender<T>, Receiver<T>) {
+    let reader = stream.clone();
+    spawn_transport(reader, TcpWriter(stream), codec)
+}
+
+/// Starts the tasks servicing a channel over `reader` and `writer`, which are
+/// the two directions of a connection.
+///
+/// This is exposed so that channels can be made over other transports. The
+/// other end of the connection must be serviced in the same way, and it reads
+/// the end of the stream once `writer` is dropped.
+#[doc(hidden)]
+pub fn spawn_transport<T: Send, C: Codec<T>, R: Reader + Send,
+                       W: VectoredWriter + Send>(
+    reader: R, writer: W, codec: C) -> (Sender<T>, Receiver<T>) {
+    let (out_tx, out_rx) = channel();
+    let (in_tx, in_rx) = channel();
+    let encoder = codec.clone();
+    spawn(proc() write_loop(out_rx, writer, encoder));
+    spawn(proc() read_loop(reader, in_tx, codec));
+    (out_tx, in_rx)
+}
+
+// The most encoded bytes which are kept before they're written
+static BUF_SIZE: uint = 64 * 1024;
+
+fn write_loop<T: Send, E: Encode<T>, W: VectoredWriter>(rx: Receiver<T>,
+                                                        mut writer: W,
+                                                        codec: E) {
+    // The frames of the messages which were encoded, which are written along
+    // with the next one which isn't, or once nothing else is queued
+    let mut buf = MemWriter::new();
+    'outer: for msg in rx.iter() {
+        let mut msg = msg;
+        loop {
+            match write_frame(&mut writer, &mut buf, &codec, &msg) {
+                Ok(()) => {}
+                // A message which is too long was left out, nothing of it
+                // was written
+                Err(IoError { kind: InvalidInput, .. }) => {}
+                Err(..) => break 'outer,
+            }
+            match rx.try_recv() {
+                Ok(m) => msg = m,
+                Err(..) => break,
+            }
+        }
+        if buf.get_ref().len() > 0 {
+            if writer.write(buf.get_ref()).is_err() { break }
+            buf = MemWriter::new();
+        }
+        if writer.flush().is_err() { break }
+    }
+}
+
+// Adds the frame of `msg` to `buf`, or writes it out along with `buf` if its
+// payload is made of buffers of its own
+fn write_frame<T, E: Encode<T>, W: VectoredWriter>(w: &mut W,
+                                                   buf: &mut MemWriter,
+                                                   codec: &E,
+                                                   msg: &T) -> IoResult<()> {
+    match codec.io_slices(msg) {
+        Some(slices) => {
+            let len = slices.iter().fold(0, |n, s| n + s.len());
+            try!(check_sent_frame(codec, len));
+            try!(buf.write_be_u32(len as u32));
+            let mut bufs = vec![buf.get_ref()];
+            bufs.push_all(slices.as_slice());
+            try!(w.write_vectored(bufs.as_slice()));
+        }
+        None => {
+            try!(write_message(&mut *buf, codec, msg));
+            if buf.get_ref().len() < BUF_SIZE { return Ok(()) }
+            try!(w.write(buf.get_ref()));
+        }
+    }
+    *buf = MemWriter::new();
+    Ok(())
+}
+
+fn read_loop<T: Send, D: Decode<T>, R: Reader>(reader: R, tx: Sender<T>,
+                                               codec: D) {
+    let mut reader = BufferedReader::new(reader);
+    loop {
+        match read_message(&mut reader, &codec) {
+            Ok(msg) => {
+                if tx.send_opt(msg).is_err() { break }
+            }
+            Err(..) => break,
+        }
+    }
+}
+
+/// Writes `msg` as a frame, encoded with `codec`.
+///
+/// If the codec gives the buffers of the payload, they're written one after
+/// the other. The tasks servicing a channel write them along with the header
+/// of the frame with a single vectored write instead.
+///
+/// # Error
+///
+/// Besides I/O and encoding errors, an `InvalidInput` error is returned if
+/// the payload is longer than the codec's `max_frame` (or than a frame can
+/// be), in which case nothing is written.
+pub fn write_message<T, E: Encode<T>, W: Writer>(w: &mut W, codec: &E,
+                                                 msg: &T) -> IoResult<()> {
+    match codec.io_slices(msg) {
+        Some(slices) => {
+            let len = slices.iter().fold(0, |n, s| n + s.len());
+            try!(check_sent_frame(codec, len));
+            try!(w.write_be_u32(len as u32));
+            for s in slices.iter() {
+                try!(w.write(*s));
+            }
+            Ok(())
+        }
+        None => {
+            let buf = try!(codec.encode(msg));
+            try!(check_sent_frame(codec, buf.len()));
+            try!(w.write_be_u32(buf.len() as u32));
+            w.write(buf.as_slice())
+        }
+    }
+}
+
+/// Reads a frame written by `write_message`, and decodes it with `codec`.
+///
+/// # Error
+///
+/// Besides I/O errors, an error is returned if the message can't be decoded,
+/// and an `InvalidInput` error if the frame is longer than the codec's
+/// `max_frame`.
+pub fn read_message<T, D: Decode<T>, R: Reader>(r: &mut R, codec: &D) -> IoResult<T> {
+    let len = try!(r.read_be_u32()) as uint;
+    try!(check_frame(codec, len));
+    let buf = try!(r.read_exact(len));
+    codec.decode_owned(buf)
+}
+
+// Refuses a frame whose payload is `len` bytes if it's too long for `codec`
+fn check_frame<T, D: Decode<T>>(codec: &D, len: uint) -> IoResult<()> {
+    frame_limit(len, codec.max_frame())
+}
+
+// Refuses to send a frame whose payload is `len` bytes if it's too long for
+// `codec`, or for its length to fit in the header
+fn check_sent_frame<T, E: Encode<T>>(codec: &E, len: uint) -> IoResult<()> {
+    frame_limit(len, cmp::min(codec.max_frame(), u32::MAX as uint))
+}
+
+fn frame_limit(len: uint, limit: uint) -> IoResult<()> {
+    if len <= limit { return Ok(()) }
+    Err(IoError {
+        kind: InvalidInput,
+        desc: "frame is too long",
+        detail: Some(format!("{} bytes, the limit is {}", len, limit)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{IoResult, MemReader, MemWriter, InvalidInput};
+    use std::io::test::next_test_ip4;
+    use std::io::timer;
+
+    use codec::{Ebml, Json, Raw, Limit};
+
+    #[deriving(Encodable, Decodable, PartialEq, Show)]
+    struct Point {
+        x: int,
+        y: int,
+    }
+
+    fn listen<T: super::Message>() -> (super::NetAcceptor<T>, String, u16) {
+        let addr = next_test_ip4();
+        let host = addr.ip.to_string();
+        let acceptor = super::listen(host.as_slice(), addr.port).unwrap();
+        (acceptor, host, addr.port)
+    }
+
+    #[test]
+    fn frames() {
+        let mut w = MemWriter::new();
+        super::write_message(&mut w, &Ebml, &Point { x: 1, y: 2 }).unwrap();
+        super::write_message(&mut w, &Ebml, &Point { x: 3, y: 4 }).unwrap();
+        let mut r = MemReader::new(w.unwrap());
+        let p: Point = super::read_message(&mut r, &Ebml).unwrap();
+        assert_eq!(p, Point { x: 1, y: 2 });
+        let p: Point = super::read_message(&mut r, &Ebml).unwrap();
+        assert_eq!(p, Point { x: 3, y: 4 });
+        let p: IoResult<Point> = super::read_message(&mut r, &Ebml);
+        assert!(p.is_err());
+    }
+
+    #[test]
+    fn frame_too_long() {
+        let codec = Limit { codec: Raw, max_frame: 10 };
+        let mut w = MemWriter::new();
+        super::write_message(&mut w, &Raw, &Vec::from_elem(10, 0u8)).unwrap();
+        super::write_message(&mut w, &Raw, &Vec::from_elem(11, 0u8)).unwrap();
+        let mut r = MemReader::new(w.unwrap());
+        let msg: IoResult<Vec<u8>> = super::read_message(&mut r, &codec);
+        assert_eq!(msg.unwrap().len(), 10);
+        let msg: IoResult<Vec<u8>> = super::read_message(&mut r, &codec);
+        assert_eq!(msg.unwrap_err().kind, InvalidInput);
+
+        // Nothing is allocated for a length which is made up
+        let mut w = MemWriter::new();
+        w.write_be_u32(0xffffffff).unwrap();
+        let mut r = MemReader::new(w.unwrap());
+        let msg: IoResult<Vec<u8>> = super::read_message(&mut r, &Raw);
+        assert_eq!(msg.unwrap_err().kind, InvalidInput);
+    }
+
+    #[test]
+    fn frame_too_long_to_send() {
+        let codec = Limit { codec: Raw, max_frame: 10 };
+        let mut w = MemWriter::new();
+        let err = super::write_message(&mut w, &codec, &Vec::from_elem(11, 0u8));
+        assert_eq!(err.unwrap_err().kind, InvalidInput);
+        assert_eq!(w.get_ref().len(), 0);
+        let codec = Limit { codec: Ebml, max_frame: 4 };
+        let err = super::write_message(&mut w, &codec, &Point { x: 1, y: 2 });
+        assert_eq!(err.unwrap_err().kind, InvalidInput);
+        assert_eq!(w.get_ref().len(), 0);
+
+        // The channel leaves the message out, and carries on with the next
+        let addr = next_test_ip4();
+        let host = addr.ip.to_string();
+        let limit = Limit { codec: Raw, max_frame: 10 };
+        let mut acceptor = super::listen_with::<Vec<u8>, Limit<Raw>>(
+            host.as_slice(), addr.port, limit.clone()).unwrap();
+        spawn(proc() {
+            let (tx, _rx) = super::connect_with::<Vec<u8>, Limit<Raw>>(
+                host.as_slice(), addr.port, limit).unwrap();
+            tx.send(Vec::from_elem(11, 1u8));
+            tx.send(vec![2, 3]);
+        });
+
+        let (_tx, rx) = acceptor.accept().unwrap();
+        assert_eq!(rx.recv(), vec![2, 3]);
+    }
+
+    #[test]
+    fn smoke() {
+        let (mut acceptor, host, port) = listen::<Point>();
+        spawn(proc() {
+            let (tx, rx) = super::connect::<Point>(host.as_slice(), port).unwrap();
+            tx.send(Point { x: 1, y: 2 });
+            assert_eq!(rx.recv(), Point { x: 2, y: 4 });
+        });
+
+        let (tx, rx) = acceptor.accept().unwrap();
+        let p = rx.recv();
+        tx.send(Point { x: p.x * 2, y: p.y * 2 });
+    }
+
+    #[test]
+    fn json() {
+        let addr = next_test_ip4();
+        let host = addr.ip.to_string();
+        let mut acceptor = super::listen_with::<Point, Json>(host.as_slice(),
+                                                            addr.port,
+                                                            Json).unwrap();
+        spawn(proc() {
+            let (tx, _rx) = super::connect_with::<Point, Json>(host.as_slice(),
+                                                              addr.port,
+                                                              Json).unwrap();
+            tx.send(Point { x: 1, y: 2 });
+        });
+
+        let (_tx, rx) = acceptor.accept().unwrap();
+        assert_eq!(rx.recv(), Point { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn raw() {
+        let addr = next_test_ip4();
+        let host = addr.ip.to_string();
+        let mut acceptor = super::listen_with::<Vec<u8>, Raw>(host.as_slice(),
+                                                             addr.port,
+                                                             Raw).unwrap();
+        spawn(proc() {
+            let (tx, _rx) = super::connect_with::<Vec<u8>, Raw>(host.as_slice(),
+                                                               addr.port,
+                                                               Raw).unwrap();
+            tx.send(Vec::from_elem(100000, 1u8));
+            tx.send(vec![2, 3]);
+        });
+
+        let (_tx, rx) = acceptor.accept().unwrap();
+        assert!(rx.recv() == Vec::from_elem(100000, 1u8));
+        assert_eq!(rx.recv(), vec![2, 3]);
+    }
+
+    #[test]
+    fn many() {
+        let (mut acceptor, host, port) = listen::<uint>();
+        spawn(proc() {
+            let (tx, _rx) = super::connect::<uint>(host.as_slice(), port).unwrap();
+            for i in range(0u, 1000) { tx.send(i) }
+        });
+
+        let (_tx, rx) = acceptor.accept().unwrap();
+        for i in range(0u, 1000) { assert_eq!(rx.recv(), i) }
+    }
+
+    #[test]
+    fn sender_dropped() {
+        let (mut acceptor, host, port) = listen::<String>();
+        spawn(proc() {
+            let (tx, rx) = super::connect::<String>(host.as_slice(), port).unwrap();
+            tx.send("hello".to_string());
+            drop(tx);
+            // The other direction is still open
+            assert_eq!(rx.recv().as_slice(), "world");
+        });
+
+        let (tx, rx) = acceptor.accept().unwrap();
+        assert_eq!(rx.recv().as_slice(), "hello");
+        assert_eq!(rx.recv_opt(), Err(()));
+        tx.send("world".to_string());
+    }
+
+    #[test]
+    fn connection_closed() {
+        let (mut acceptor, host, port) = listen::<int>();
+        spawn(proc() {
+            let _ = super::connect::<int>(host.as_slice(), port).unwrap();
+        });
+
+        let (tx, rx) = acceptor.accept().unwrap();
+        assert_eq!(rx.recv_opt(), Err(()));
+        // Once the writes fail, so does sending
+        while tx.send_opt(1).is_ok() {
+            timer::sleep(1);
+        }
+    }
+
+    #[test]
+    fn connect_error() {
+        let addr = next_test_ip4();
+        let host = addr.ip.to_string();
+        assert!(super::connect::<int>(host.as_slice(), addr.port).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Broadcast channels over UDP multicast
+//!
+//! A broadcast channel sends each message as a datagram to a multicast group,
+//! and every subscriber of the group receives it, in this process or in
+//! others. Delivery is best-effort: messages can be lost, duplicated or
+//! reordered by the network, and there's no disconnection, as datagrams can't
+//! tell when there are no more messages.
+//!
+//! So that receivers can find out about lost messages, each one is received as
+//! a `Sequenced` message, numbered by its sender. A message is lost when its
+//! number is skipped; the numbers of the messages which can't be sent, such as
+//! those bigger than a datagram, are skipped too.
+//!
+//! The datagrams are sent with the multicast TTL of the system, which is
+//! usually 1 so that they don't leave the local network.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! extern crate netchan;
+//!
+//! use std::io::net::ip::{SocketAddr, Ipv4Addr};
+//!
+//! fn main() {
+//!     let group = SocketAddr { ip: Ipv4Addr(239, 255, 0, 1), port: 8080 };
+//!     let (tx, subscriber) = netchan::broadcast::<String>(group).unwrap();
+//!     let rx = subscriber.subscribe().unwrap();
+//!     tx.send("hello".to_string());
+//!     let msg = rx.recv();
+//!     println!("message {} from {}: {}", msg.seq, msg.source, msg.msg);
+//! }
+//! ```
+
+use std::io::{BufReader, IoResult, IoError, TimedOut, MemWriter};
+use std::io::net::ip::{SocketAddr, Ipv4Addr, Ipv6Addr};
+use std::io::net::udp::UdpSocket;
+use std::rand;
+
+use codec::{Codec, Encode, Decode, Ebml};
+use super::Message;
+
+// The biggest payload of a UDP datagram, over IPv4
+static MAX_DATAGRAM: uint = 65507;
+
+// The source and number which prefix each message
+static HEADER_SIZE: uint = 16;
+
+// How often a subscription with no messages checks that its port is still there
+static POLL_MS: u64 = 1000;
+
+/// A message received from a broadcast channel.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Sequenced<T> {
+    /// Identifies the sender of the message. It's chosen at random by each
+    /// broadcast channel.
+    pub source: u64,
+    /// The number of the message, counting from 0, among those of its source.
+    pub seq: u64,
+    /// The message itself.
+    pub msg: T,
+}
+
+/// Opens a broadcast channel to the multicast group at `group`, returning the
+/// sender of its messages and a subscriber to them.
+///
+/// The subscriber is a convenience, as the group can be subscribed to from
+/// anywhere with `Subscriber::new`. Messages are only received by the
+/// subscriptions which have been made when they are sent.
+///
+/// # Error
+///
+/// An error is returned if the socket which sends the messages can't be made.
+pub fn broadcast<T: Message>(group: SocketAddr) -> IoResult<(Sender<T>, Subscriber<T, Ebml>)> {
+    broadcast_with(group, Ebml)
+}
+
+/// Opens a broadcast channel like `broadcast`, with the messages sent in the
+/// format of `codec`.
+pub fn broadcast_with<T: Send, C: Codec<T>>(group: SocketAddr, codec: C)
+                                            -> IoResult<(Sender<T>, Subscriber<T, C>)> {
+    let any = match group.ip {
+        Ipv4Addr(..) => Ipv4Addr(0, 0, 0, 0),
+        Ipv6Addr(..) => Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 0),
+    };
+    let mut socket = try!(UdpSocket::bind(SocketAddr { ip: any, port: 0 }));
+    try!(socket.set_multicast_loop(true));
+
+    let (tx, rx) = channel();
+    let encoder = codec.clone();
+    spawn(proc() send_loop(rx, socket, group, encoder));
+    Ok((tx, Subscriber::with_codec(group, codec)))
+}
+
+/// Subscribes to the messages broadcast to a multicast group.
+pub struct Subscriber<T, C = Ebml> {
+    group: SocketAddr,
+    codec: C,
+}
+
+impl<T, C: Clone> Clone for Subscriber<T, C> {
+    fn clone(&self) -> Subscriber<T, C> {
+        Subscriber { group: self.group, codec: self.codec.clone() }
+    }
+}
+
+impl<T: Message> Subscriber<T, Ebml> {
+    /// Returns a subscriber to the messages broadcast to `group` with the
+    /// default codec.
+    pub fn new(group: SocketAddr) -> Subscriber<T, Ebml> {
+        Subscriber::with_codec(group, Ebml)
+    }
+}
+
+impl<T: Send, C: Codec<T>> Subscriber<T, C> {
+    /// Returns a subscriber to the messages broadcast to `group` in the format
+    /// of `codec`.
+    pub fn with_codec(group: SocketAddr, codec: C) -> Subscriber<T, C> {
+        Subscriber { group: group, codec: codec }
+    }
+
+    /// Joins the group, and returns the port which receives the messages sent
+    /// to it from now on.
+    ///
+    /// The datagrams which aren't messages of a broadcast channel, or which
+    /// can't be decoded, are ignored. The group is left within a second of the
+    /// port being dropped.
+    ///
+    /// # Error
+    ///
+    /// An error is returned if the group can't be joined.
+    pub fn subscribe(&self) -> IoResult<Receiver<Sequenced<T>>> {
+        let mut socket = try!(UdpSocket::bind(self.group));
+        try!(socket.join_multicast(self.group.ip));
+
+        let (tx, rx) = channel();
+        let codec = self.codec.clone();
+        spawn(proc() recv_loop(socket, tx, codec));
+        Ok(rx)
+    }
+}
+
+fn send_loop<T: Send, E: Encode<T>>(rx: Receiver<T>, mut socket: UdpSocket,
+                                    group: SocketAddr, codec: E) {
+    let source = rand::random::<u64>();
+    let mut seq = 0u64;
+    for msg in rx.iter() {
+        // The messages which can't be sent are lost like any other
+        match encode(source, seq, &msg, &codec) {
+            Ok(buf) => {
+                if buf.len() <= MAX_DATAGRAM {
+                    let _ = socket.send_to(buf.as_slice(), group);
+                }
+            }
+            Err(..) => {}
+        }
+        seq += 1;
+    }
+}
+
+fn encode<T, E: Encode<T>>(source: u64, seq: u64, msg: &T, codec: &E)
+                           -> IoResult<Vec<u8>> {
+    let payload = try!(codec.encode(msg));
+    let mut w = MemWriter::with_capacity(HEADER_SIZE + payload.len());
+    try!(w.write_be_u64(source));
+    try!(w.write_be_u64(seq));
+    try!(w.write(payload.as_slice()));
+    Ok(w.unwrap())
+}
+
+fn recv_loop<T: Send, D: Decode<T>>(mut socket: UdpSocket,
+                                    tx: Sender<Sequenced<T>>, codec: D) {
+    let mut buf = Vec::from_elem(MAX_DATAGRAM, 0u8);
+    loop {
+        // The group may be silent for good, so the port is checked for
+        // periodically in case it has been dropped
+        socket.set_read_timeout(Some(POLL_MS));
+        let n = match socket.recv_from(buf.as_mut_slice()) {
+            Ok((n, _)) => n,
+            Err(IoError { kind: TimedOut, .. }) => {
+                if tx.is_disconnected() { break }
+                continue
+            }
+            Err(..) => break,
+        };
+        match decode(buf.slice_to(n), &codec) {
+            Some(msg) => {
+                if tx.send_opt(msg).is_err() { break }
+            }
+            None => {}
+        }
+    }
+}
+
+fn decode<T, D: Decode<T>>(buf: &[u8], codec: &D) -> Option<Sequenced<T>> {
+    if buf.len() < HEADER_SIZE { return None }
+    let mut r = BufReader::new(buf);
+    let source = r.read_be_u64().unwrap();
+    let seq = r.read_be_u64().unwrap();
+    match codec.decode(buf.slice_from(HEADER_SIZE)) {
+        Ok(msg) => Some(Sequenced { source: source, seq: seq, msg: msg }),
+        Err(..) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::net::ip::{SocketAddr, Ipv4Addr};
+    use std::io::test::next_test_port;
+
+    use codec::{Ebml, Json};
+    use super::{Sequenced, Subscriber};
+
+    fn group() -> SocketAddr {
+        SocketAddr { ip: Ipv4Addr(239, 255, 0, 1), port: next_test_port() }
+    }
+
+    #[test]
+    fn datagrams() {
+        let buf = super::encode(7, 3, &"hello".to_string(), &Ebml).unwrap();
+        let msg: Sequenced<String> = super::decode(buf.as_slice(), &Ebml).unwrap();
+        assert_eq!(msg, Sequenced { source: 7, seq: 3, msg: "hello".to_string() });
+
+        let msg: Option<Sequenced<String>> = super::decode(buf.slice_to(10), &Ebml);
+        assert!(msg.is_none());
+        let msg: Option<Sequenced<String>> = super::decode(buf.as_slice(), &Json);
+        assert!(msg.is_none());
+    }
+
+    #[test]
+    fn smoke() {
+        let group = group();
+        let (tx, subscriber) = super::broadcast::<String>(group).unwrap();
+        let rx1 = subscriber.subscribe().unwrap();
+        let subscriber2: Subscriber<String> = Subscriber::new(group);
+        let rx2 = subscriber2.subscribe().unwrap();
+
+        tx.send("a".to_string());
+        tx.send("b".to_string());
+        for rx in [rx1, rx2].iter() {
+            let a = rx.recv();
+            let b = rx.recv();
+            assert_eq!(a.seq, 0);
+            assert_eq!(a.msg.as_slice(), "a");
+            assert_eq!(b.seq, 1);
+            assert_eq!(b.msg.as_slice(), "b");
+            assert_eq!(a.source, b.source);
+        }
+    }
+
+    #[test]
+    fn sources() {
+        let group = group();
+        let (tx1, subscriber) = super::broadcast::<int>(group).unwrap();
+        let (tx2, _) = super::broadcast::<int>(group).unwrap();
+        let rx = subscriber.subscribe().unwrap();
+
+        tx1.send(1);
+        let a = rx.recv();
+        tx2.send(2);
+        let b = rx.recv();
+        assert_eq!((a.seq, a.msg), (0, 1));
+        assert_eq!((b.seq, b.msg), (0, 2));
+        assert!(a.source != b.source);
+    }
+
+    #[test]
+    fn too_big() {
+        let group = group();
+        let (tx, subscriber) = super::broadcast::<Vec<u8>>(group).unwrap();
+        let rx = subscriber.subscribe().unwrap();
+
+        // The first message doesn't fit in a datagram, and is lost
+        tx.send(Vec::from_elem(super::MAX_DATAGRAM, 0u8));
+        tx.send(vec![1u8]);
+        let msg = rx.recv();
+        assert_eq!(msg.seq, 1);
+        assert_eq!(msg.msg, vec![1u8]);
+    }
+}
@@ -0,0 +1,577 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+Syntax extension to generate request/response protocols over channels.
+
+`rpc!` is given a trait, the interface of a server, and generates everything
+needed to call its methods from another task over a duplex channel. For a
+trait `Calc`, these are:
+
+* `CalcRequest`, an enum with a variant per method holding its arguments. The
+  variant of `add` is `CalcAddRequest`, named after the trait too so that the
+  variants of two interfaces with a method of the same name don't collide. Its
+  `dispatch` method calls the method of a server which the request is for.
+* `CalcResponse`, an enum with a variant per method holding what it returns,
+  such as `CalcAddResponse`. The variants of methods which return nothing have
+  no fields.
+* `CalcClient`, the handle of a server on the client's end of a channel. Its
+  `call` method sends a request and waits for the response, and it has a
+  method for each method of the trait, which calls it and returns the result.
+* `CalcServer`, which serves the requests received on the server's end of a
+  channel with an implementation of `Calc`, until the client is dropped.
+
+The trait itself is kept as it is written, and the generated items have the
+same visibility as it. The methods of the trait must take `self` by reference,
+have no type parameters, and bind each of their arguments to a name. Their
+arguments and results must be sendable.
+
+# Example
+
+```rust,ignore
+#![feature(phase)]
+
+#[phase(plugin)]
+extern crate rpc;
+
+rpc! {
+    trait Calc {
+        fn add(&mut self, a: int, b: int) -> int;
+        fn reset(&mut self);
+    }
+}
+
+struct Adder { calls: uint }
+
+impl Calc for Adder {
+    fn add(&mut self, a: int, b: int) -> int { self.calls += 1; a + b }
+    fn reset(&mut self) { self.calls = 0 }
+}
+
+fn main() {
+    let client = CalcClient::spawn(Adder { calls: 0 });
+    assert_eq!(client.add(1, 2), 3);
+    client.reset();
+}
+```
+
+*/
+
+#![crate_name = "rpc"]
+#![experimental]
+#![crate_type = "rlib"]
+#![crate_type = "dylib"]
+#![license = "MIT/ASL2"]
+#![doc(html_logo_url = "http://www.rust-lang.org/logos/rust-logo-128x128-blk-v2.png",
+       html_favicon_url = "http://www.rust-lang.org/favicon.ico",
+       html_root_url = "http://doc.rust-lang.org/master/")]
+
+#![feature(plugin_registrar, managed_boxes)]
+
+extern crate syntax;
+extern crate rustc;
+
+use syntax::abi;
+use syntax::ast;
+use syntax::ast::P;
+use syntax::ast_util;
+use syntax::codemap::{Span, respan};
+use syntax::ext::base;
+use syntax::ext::base::{ExtCtxt, MacResult};
+use syntax::ext::build::AstBuilder;
+use syntax::owned_slice::OwnedSlice;
+use syntax::parse::token;
+use syntax::parse::token::{InternedString, special_idents};
+use syntax::util::small_vector::SmallVector;
+use rustc::plugin::Registry;
+
+use std::gc::{Gc, GC};
+
+#[plugin_registrar]
+pub fn plugin_registrar(reg: &mut Registry) {
+    reg.register_macro("rpc", expand_syntax_ext);
+}
+
+pub fn expand_syntax_ext(cx: &mut ExtCtxt, sp: Span, tts: &[ast::TokenTree])
+                         -> Box<base::MacResult> {
+    let item = {
+        let p = &mut cx.new_parser_from_tts(tts);
+        let item = p.parse_item_with_outer_attributes();
+        if p.token != token::EOF {
+            p.unexpected();
+        }
+        item
+    };
+    let item = match item {
+        Some(item) => item,
+        None => {
+            cx.span_err(sp, "rpc! expects a trait");
+            return base::DummyResult::any(sp)
+        }
+    };
+
+    let methods = match item.node {
+        ast::ItemTrait(ref generics, _, _, ref methods) => {
+            if generics.is_parameterized() {
+                cx.span_err(item.span, "rpc! interfaces can't have type or \
+                                        lifetime parameters");
+                return base::DummyResult::any(sp)
+            }
+            methods
+        }
+        _ => {
+            cx.span_err(item.span, "rpc! expects a trait");
+            return base::DummyResult::any(sp)
+        }
+    };
+
+    let mut sigs = Vec::new();
+    for method in methods.iter() {
+        match signature(cx, method) {
+            Some(sig) => sigs.push(sig),
+            None => return base::DummyResult::any(sp),
+        }
+    }
+    if sigs.is_empty() {
+        cx.span_err(item.span, "rpc! interfaces need at least one method");
+        return base::DummyResult::any(sp)
+    }
+
+    let generated = generate(cx, item.span, item.ident, item.vis, sigs.as_slice());
+    let mut items = vec![item];
+    items.push_all_move(generated);
+    box MacItems { items: items } as Box<MacResult>
+}
+
+struct MacItems {
+    items: Vec<Gc<ast::Item>>,
+}
+
+impl MacResult for MacItems {
+    fn make_items(&self) -> Option<SmallVector<Gc<ast::Item>>> {
+        Some(SmallVector::many(self.items.clone()))
+    }
+}
+
+// A method of the interface. The types are those of the trait, so that errors
+// in them point into it.
+struct Signature {
+    span: Span,
+    name: ast::Ident,
+    args: Vec<(ast::Ident, P<ast::Ty>)>,
+    ret: Option<P<ast::Ty>>,
+}
+
+fn signature(cx: &ExtCtxt, method: &ast::TraitMethod) -> Option<Signature> {
+    let (ident, generics, explicit_self, decl, span) = match *method {
+        ast::Required(ref m) => {
+            (m.ident, &m.generics, &m.explicit_self, m.decl.clone(), m.span)
+        }
+        ast::Provided(ref m) => match m.node {
+            ast::MethDecl(ident, ref generics, _, ref explicit_self, _, ref decl, _, _) => {
+                (ident, generics, explicit_self, decl.clone(), m.span)
+            }
+            ast::MethMac(..) => {
+                cx.span_err(m.span, "rpc! interfaces can't have macro methods");
+                return None
+            }
+        },
+    };
+
+    if generics.is_parameterized() {
+        cx.span_err(span, "the methods of rpc! interfaces can't have type or \
+                           lifetime parameters");
+        return None
+    }
+    match explicit_self.node {
+        ast::SelfRegion(..) => {}
+        _ => {
+            cx.span_err(span, "the methods of rpc! interfaces must take `self` \
+                               by reference");
+            return None
+        }
+    }
+    if decl.cf == ast::NoReturn {
+        cx.span_err(span, "the methods of rpc! interfaces must return");
+        return None
+    }
+
+    let mut args = Vec::new();
+    // The first input is `self`.
+    for arg in decl.inputs.iter().skip(1) {
+        match arg.pat.node {
+            ast::PatIdent(_, ref path, None) => args.push((path.node, arg.ty)),
+            _ => {
+                cx.span_err(arg.pat.span, "the arguments of rpc! methods must \
+                                           be bound to names");
+                return None
+            }
+        }
+    }
+    let ret = match decl.output.node {
+        ast::TyNil => None,
+        _ => Some(decl.output),
+    };
+
+    Some(Signature {
+        span: span,
+        name: ident,
+        args: args,
+        ret: ret,
+    })
+}
+
+// `add_point` becomes `AddPoint`
+fn camel_case(name: &str) -> String {
+    let mut ret = String::new();
+    for part in name.split('_') {
+        match part.chars().next() {
+            Some(c) => {
+                ret.push_char(c.to_uppercase());
+                ret.push_str(part.slice_from(c.len_utf8_bytes()));
+            }
+            None => {}
+        }
+    }
+    ret
+}
+
+fn generate(cx: &ExtCtxt, sp: Span, trait_name: ast::Ident, vis: ast::Visibility,
+            sigs: &[Signature]) -> Vec<Gc<ast::Item>> {
+    let name = token::get_ident(trait_name);
+    let name = name.get();
+    let request = suffixed(cx, name, "Request");
+    let response = suffixed(cx, name, "Response");
+    let client = suffixed(cx, name, "Client");
+    let server = suffixed(cx, name, "Server");
+    // The request and response variants of each method, named after both the
+    // trait and the method so that two interfaces can live side by side
+    let variants: Vec<(ast::Ident, ast::Ident)> = sigs.iter().map(|sig| {
+        let method = camel_case(token::get_ident(sig.name).get());
+        (suffixed(cx, name, format!("{}Request", method).as_slice()),
+         suffixed(cx, name, format!("{}Response", method).as_slice()))
+    }).collect();
+    let s = cx.ident_of("S");
+    let mut items = Vec::new();
+
+    // The request and response enums
+    let request_enum = cx.item_enum(sp, request, ast::EnumDef {
+        variants: sigs.iter().zip(variants.iter()).map(|(sig, &(req, _))| {
+            let tys = sig.args.iter().map(|&(_, ty)| ty).collect();
+            P(cx.variant(sig.span, req, tys))
+        }).collect(),
+    });
+    items.push(public(cx, request_enum, vis, format!(
+        "The requests of a `{}`, one per method, holding its arguments.", name)));
+
+    let response_enum = cx.item_enum(sp, response, ast::EnumDef {
+        variants: sigs.iter().zip(variants.iter()).map(|(sig, &(_, resp))| {
+            P(cx.variant(sig.span, resp, sig.ret.iter().map(|&ty| ty).collect()))
+        }).collect(),
+    });
+    items.push(public(cx, response_enum, vis, format!(
+        "The responses of a `{}`, one per method, holding what it returns.", name)));
+
+    // Dispatching a request to a server. The server is gensym'd, as arguments
+    // of the methods are bound to their own names next to it.
+    let target = token::gensym_ident("server");
+    let arms = sigs.iter().zip(variants.iter()).map(|(sig, &(req, resp))| {
+        let names: Vec<ast::Ident> = sig.args.iter().map(|&(name, _)| name).collect();
+        let pat = if names.is_empty() {
+            cx.pat_ident(sig.span, req)
+        } else {
+            let pats = names.iter().map(|&name| cx.pat_ident(sig.span, name)).collect();
+            cx.pat_enum(sig.span, cx.path_ident(sig.span, req), pats)
+        };
+        let args = names.iter().map(|&name| cx.expr_ident(sig.span, name)).collect();
+        let call = cx.expr_method_call(sig.span, cx.expr_ident(sig.span, target),
+                                       sig.name, args);
+        let value = match sig.ret {
+            Some(..) => cx.expr_call_ident(sig.span, resp, vec![call]),
+            None => cx.expr_block(cx.block(sig.span, vec![cx.stmt_expr(call)],
+                                           Some(cx.expr_ident(sig.span, resp)))),
+        };
+        cx.arm(sig.span, vec![pat], value)
+    }).collect();
+    let dispatch = method(
+        cx, sp, "Calls the method of `server` which this is a request for, and \
+                 returns its response.",
+        "dispatch", generics(cx, sp, s, vec![trait_name]), ast::SelfValue(special_idents::self_),
+        vec![cx.arg(sp, target, cx.ty_rptr(sp, cx.ty_ident(sp, s), None,
+                                            ast::MutMutable))],
+        cx.ty_ident(sp, response),
+        cx.block_expr(cx.expr_match(sp, cx.expr_self(sp), arms)));
+    items.push(inherent_impl(cx, sp, ast_util::empty_generics(),
+                             cx.ty_ident(sp, request), vec![dispatch]));
+
+    // The client
+    let chan = cx.ident_of("chan");
+    let client_struct = cx.item_struct(sp, client, ast::StructDef {
+        fields: vec![field(sp, chan, duplex(cx, sp, request, response))],
+        ctor_id: None,
+        super_struct: None,
+        is_virtual: false,
+    });
+    items.push(public(cx, client_struct, vis, format!(
+        "Calls the methods of a `{}` served by a `{}`, over a duplex channel.",
+        name, token::get_ident(server))));
+
+    let mut methods = Vec::new();
+    methods.push(method(
+        cx, sp, format!("Wraps the client's end of a channel to a `{}`.",
+                        token::get_ident(server)).as_slice(),
+        "new", ast_util::empty_generics(), ast::SelfStatic,
+        vec![cx.arg(sp, chan, duplex(cx, sp, request, response))],
+        cx.ty_ident(sp, client),
+        cx.block_expr(cx.expr_struct_ident(sp, client, vec![
+            cx.field_imm(sp, chan, cx.expr_ident(sp, chan)),
+        ]))));
+
+    let (client_end, server_end) = (cx.ident_of("client"), cx.ident_of("chan"));
+    let serve = cx.expr_method_call(sp, cx.expr_call(sp,
+        cx.expr_path(cx.path(sp, vec![server, cx.ident_of("new")])),
+        vec![cx.expr_ident(sp, target), cx.expr_ident(sp, server_end)]),
+        cx.ident_of("run"), Vec::new());
+    let task = cx.expr(sp, ast::ExprProc(cx.fn_decl(Vec::new(), cx.ty_infer(sp)),
+                                         cx.block(sp, vec![cx.stmt_expr(serve)], None)));
+    let spawn = cx.expr_call_global(sp, std_path(cx, "task", "spawn"), vec![task]);
+    let wrap = cx.expr_call(sp, cx.expr_path(cx.path(sp, vec![client, cx.ident_of("new")])),
+                            vec![cx.expr_ident(sp, client_end)]);
+    let ends = cx.pat(sp, ast::PatTup(vec![cx.pat_ident(sp, client_end),
+                                           cx.pat_ident(sp, server_end)]));
+    methods.push(method(
+        cx, sp, "Spawns a task serving the requests of the returned client with \
+                 `server`.",
+        "spawn", generics(cx, sp, s, vec![trait_name, cx.ident_of("Send")]),
+        ast::SelfStatic, vec![cx.arg(sp, target, cx.ty_ident(sp, s))],
+        cx.ty_ident(sp, client),
+        cx.block_expr(cx.expr_match(sp, cx.expr_call_global(sp, std_path(cx, "comm", "duplex"),
+                                                            Vec::new()), vec![
+            cx.arm(sp, vec![ends], cx.expr_block(cx.block(sp, vec![cx.stmt_expr(spawn)],
+                                                          Some(wrap)))),
+        ]))));
+
+    let req = cx.ident_of("req");
+    let self_chan = || cx.expr_field_access(sp, cx.expr_self(sp), chan);
+    methods.push(method(
+        cx, sp, "Sends `req` to the server and waits for its response.\n\n\
+                 This fails if the server has gone away.",
+        "call", ast_util::empty_generics(),
+        ast::SelfRegion(None, ast::MutImmutable, special_idents::self_),
+        vec![cx.arg(sp, req, cx.ty_ident(sp, request))],
+        cx.ty_ident(sp, response),
+        cx.block(sp, vec![cx.stmt_expr(cx.expr_method_call(sp, self_chan(),
+                                                           cx.ident_of("send"),
+                                                           vec![cx.expr_ident(sp, req)]))],
+                 Some(cx.expr_method_call(sp, self_chan(), cx.ident_of("recv"),
+                                          Vec::new())))));
+
+    let ret = cx.ident_of("ret");
+    for (sig, &(req, resp)) in sigs.iter().zip(variants.iter()) {
+        let args: Vec<ast::Arg> = sig.args.iter().map(|&(name, ty)| {
+            cx.arg(sig.span, name, ty)
+        }).collect();
+        let names: Vec<Gc<ast::Expr>> = sig.args.iter().map(|&(name, _)| {
+            cx.expr_ident(sig.span, name)
+        }).collect();
+        let sent = if names.is_empty() {
+            cx.expr_ident(sig.span, req)
+        } else {
+            cx.expr_call_ident(sig.span, req, names)
+        };
+        let (output, pat, value) = match sig.ret {
+            Some(ty) => (ty, cx.pat_enum(sig.span, cx.path_ident(sig.span, resp),
+                                         vec![cx.pat_ident(sig.span, ret)]),
+                         cx.expr_ident(sig.span, ret)),
+            None => (cx.ty_nil(), cx.pat_ident(sig.span, resp),
+                     cx.expr_lit(sig.span, ast::LitNil)),
+        };
+        let mut arms = vec![cx.arm(sig.span, vec![pat], value)];
+        // The arm would be unreachable with a single method
+        if sigs.len() > 1 {
+            let msg = format!("mismatched response to `{}::{}`", name,
+                              token::get_ident(sig.name));
+            arms.push(cx.arm(sig.span, vec![cx.pat_wild(sig.span)],
+                             cx.expr_fail(sig.span,
+                                          token::intern_and_get_ident(msg.as_slice()))));
+        }
+        let call = cx.expr_method_call(sig.span, cx.expr_self(sig.span),
+                                       cx.ident_of("call"), vec![sent]);
+        methods.push(method(
+            cx, sig.span, format!("Calls `{}::{}` on the server.", name,
+                                  token::get_ident(sig.name)).as_slice(),
+            token::get_ident(sig.name).get(), ast_util::empty_generics(),
+            ast::SelfRegion(None, ast::MutImmutable, special_idents::self_), args,
+            output, cx.block_expr(cx.expr_match(sig.span, call, arms))));
+    }
+    items.push(inherent_impl(cx, sp, ast_util::empty_generics(),
+                             cx.ty_ident(sp, client), methods));
+
+    // The server
+    let server_field = cx.ident_of("server");
+    let server_ty = cx.ty_path(cx.path_all(sp, false, vec![server], Vec::new(),
+                                           vec![cx.ty_ident(sp, s)]), None);
+    let server_struct = cx.item_struct_poly(sp, server, ast::StructDef {
+        fields: vec![field(sp, server_field, cx.ty_ident(sp, s)),
+                     field(sp, chan, duplex(cx, sp, response, request))],
+        ctor_id: None,
+        super_struct: None,
+        is_virtual: false,
+    }, generics(cx, sp, s, Vec::new()));
+    items.push(public(cx, server_struct, vis, format!(
+        "Serves the requests of a `{}` with an implementation of `{}`.",
+        token::get_ident(client), name)));
+
+    let mut methods = Vec::new();
+    methods.push(method(
+        cx, sp, "Serves the requests received on the server's end of a channel \
+                 with `server`.",
+        "new", ast_util::empty_generics(), ast::SelfStatic,
+        vec![cx.arg(sp, target, cx.ty_ident(sp, s)),
+             cx.arg(sp, chan, duplex(cx, sp, response, request))],
+        server_ty,
+        cx.block_expr(cx.expr_struct_ident(sp, server, vec![
+            cx.field_imm(sp, server_field, cx.expr_ident(sp, target)),
+            cx.field_imm(sp, chan, cx.expr_ident(sp, chan)),
+        ]))));
+
+    // loop {
+    //     let req = match chan.recv_opt() { Ok(req) => req, Err(..) => break };
+    //     if chan.send_opt(req.dispatch(&mut server)).is_err() { break }
+    // }
+    let brk = || cx.expr(sp, ast::ExprBreak(None));
+    let received = cx.expr_match(sp, cx.expr_method_call(sp, cx.expr_ident(sp, chan),
+                                                         cx.ident_of("recv_opt"),
+                                                         Vec::new()), vec![
+        cx.arm(sp, vec![cx.pat_enum(sp, cx.path_ident(sp, cx.ident_of("Ok")),
+                                    vec![cx.pat_ident(sp, req)])],
+               cx.expr_ident(sp, req)),
+        cx.arm(sp, vec![cx.pat(sp, ast::PatEnum(cx.path_ident(sp, cx.ident_of("Err")),
+                                                None))],
+               brk()),
+    ]);
+    let dispatched = cx.expr_method_call(sp, cx.expr_ident(sp, req), cx.ident_of("dispatch"),
+                                         vec![cx.expr_mut_addr_of(sp, cx.expr_ident(sp, target))]);
+    let sent = cx.expr_method_call(sp, cx.expr_method_call(sp, cx.expr_ident(sp, chan),
+                                                           cx.ident_of("send_opt"),
+                                                           vec![dispatched]),
+                                   cx.ident_of("is_err"), Vec::new());
+    let body = cx.block(sp, vec![
+        cx.stmt_let(sp, false, req, received),
+        cx.stmt_expr(cx.expr_if(sp, sent, cx.expr_block(cx.block(sp, vec![
+            cx.stmt_expr(brk()),
+        ], None)), None)),
+    ], None);
+    methods.push(method(
+        cx, sp, "Serves requests until the client is dropped, and returns the server.",
+        "run", ast_util::empty_generics(), ast::SelfValue(special_idents::self_),
+        Vec::new(), cx.ty_ident(sp, s),
+        cx.block(sp, vec![
+            cx.stmt_let(sp, true, target, cx.expr_field_access(sp, cx.expr_self(sp),
+                                                               server_field)),
+            cx.stmt_let(sp, false, chan, cx.expr_field_access(sp, cx.expr_self(sp), chan)),
+            cx.stmt_expr(cx.expr(sp, ast::ExprLoop(body, None))),
+        ], Some(cx.expr_ident(sp, target)))));
+    items.push(inherent_impl(cx, sp, generics(cx, sp, s, vec![trait_name]), server_ty,
+                             methods));
+
+    items
+}
+
+// `<trait><suffix>`, the name of a generated item
+fn suffixed(cx: &ExtCtxt, name: &str, suffix: &str) -> ast::Ident {
+    cx.ident_of(format!("{}{}", name, suffix).as_slice())
+}
+
+// Gives a generated item the visibility of the interface, and documents it
+fn public(cx: &ExtCtxt, item: Gc<ast::Item>, vis: ast::Visibility, doc: String)
+          -> Gc<ast::Item> {
+    box(GC) ast::Item {
+        attrs: vec![doc_attr(cx, item.span, doc.as_slice())],
+        vis: vis,
+        ..(*item).clone()
+    }
+}
+
+fn doc_attr(cx: &ExtCtxt, sp: Span, doc: &str) -> ast::Attribute {
+    cx.attribute(sp, cx.meta_name_value(sp, InternedString::new("doc"),
+                                        ast::LitStr(token::intern_and_get_ident(doc),
+                                                    ast::CookedStr)))
+}
+
+// `::std::<module>::<name>`
+fn std_path(cx: &ExtCtxt, module: &str, name: &str) -> Vec<ast::Ident> {
+    vec![cx.ident_of("std"), cx.ident_of(module), cx.ident_of(name)]
+}
+
+// `::std::comm::DuplexStream<tx, rx>`
+fn duplex(cx: &ExtCtxt, sp: Span, tx: ast::Ident, rx: ast::Ident) -> P<ast::Ty> {
+    cx.ty_path(cx.path_all(sp, true, std_path(cx, "comm", "DuplexStream"), Vec::new(),
+                           vec![cx.ty_ident(sp, tx), cx.ty_ident(sp, rx)]), None)
+}
+
+// A private field of a struct
+fn field(sp: Span, name: ast::Ident, ty: P<ast::Ty>) -> ast::StructField {
+    respan(sp, ast::StructField_ {
+        kind: ast::NamedField(name, ast::Inherited),
+        id: ast::DUMMY_NODE_ID,
+        ty: ty,
+        attrs: Vec::new(),
+    })
+}
+
+// The generics `<S: bounds...>`
+fn generics(cx: &ExtCtxt, sp: Span, s: ast::Ident, bounds: Vec<ast::Ident>)
+            -> ast::Generics {
+    let bounds = bounds.move_iter().map(|b| cx.typarambound(cx.path_ident(sp, b)));
+    ast::Generics {
+        lifetimes: Vec::new(),
+        ty_params: OwnedSlice::from_vec(vec![
+            cx.typaram(sp, s, OwnedSlice::from_vec(bounds.collect()), None, None),
+        ]),
+    }
+}
+
+// A documented public method of an inherent impl
+fn method(cx: &ExtCtxt, sp: Span, doc: &str, name: &str, generics: ast::Generics,
+          explicit_self: ast::ExplicitSelf_, args: Vec<ast::Arg>, output: P<ast::Ty>,
+          body: P<ast::Block>) -> Gc<ast::Method> {
+    let self_arg = match explicit_self {
+        ast::SelfStatic => None,
+        _ => Some(ast::Arg::new_self(sp, ast::MutImmutable, special_idents::self_)),
+    };
+    let inputs = self_arg.move_iter().chain(args.move_iter()).collect();
+    box(GC) ast::Method {
+        attrs: vec![doc_attr(cx, sp, doc)],
+        id: ast::DUMMY_NODE_ID,
+        span: sp,
+        node: ast::MethDecl(cx.ident_of(name), generics, abi::Rust,
+                            respan(sp, explicit_self), ast::NormalFn,
+                            cx.fn_decl(inputs, output), body, ast::Public),
+    }
+}
+
+fn inherent_impl(cx: &ExtCtxt, sp: Span, generics: ast::Generics, ty: P<ast::Ty>,
+                 methods: Vec<Gc<ast::Method>>) -> Gc<ast::Item> {
+    let ident = ast_util::impl_pretty_name(&None, &*ty);
+    cx.item(sp, ident, Vec::new(), ast::ItemImpl(generics, None, ty, methods))
+}
+
+#[cfg(test)]
+mod test {
+    use super::camel_case;
+
+    #[test]
+    fn camel() {
+        assert_eq!(camel_case("add").as_slice(), "Add");
+        assert_eq!(camel_case("add_point").as_slice(), "AddPoint");
+        assert_eq!(camel_case("_get__id").as_slice(), "GetId");
+    }
+}
@@ -426,15 +426,15 @@ pub fn trans_intrinsic_call<'a>(mut bcx: &'a Block<'a>, node: ast::NodeId,
                                     *llargs.get(0), *llargs.get(1)),
 
         // This requires that atomic intrinsics follow a specific naming pattern:
-        // "atomic_<operation>[_<ordering>]", and no ordering means SeqCst
+        // "atomic_<operation>[_<ordering>][_fail<ordering>]", and no ordering
+        // means SeqCst. Only compare-and-exchange takes a failure ordering, and
+        // it defaults to the strongest one allowed for the success ordering.
         (_, name) if name.starts_with("atomic_") => {
             let split: Vec<&str> = name.split('_').collect();
             assert!(split.len() >= 2, "Atomic intrinsic not correct format");
 
-            let order = if split.len() == 2 {
-                llvm::SequentiallyConsistent
-            } else {
-                match *split.get(2) {
+            let parse_ordering = |s: &str| {
+                match s {
                     "relaxed" => llvm::Monotonic,
                     "acq"     => llvm::Acquire,
                     "rel"     => llvm::Release,
@@ -443,8 +443,23 @@ pub fn trans_intrinsic_call<'a>(mut bcx: &'a Block<'a>, node: ast::NodeId,
                 }
             };
 
+            let mut order = llvm::SequentiallyConsistent;
+            let mut failure_order = None;
+            for part in split.iter().skip(2) {
+                if part.starts_with("fail") {
+                    failure_order = Some(parse_ordering(part.slice_from(4)));
+                } else {
+                    order = parse_ordering(*part);
+                }
+            }
+            if failure_order.is_some() && !split.get(1).starts_with("cxchg") {
+                ccx.sess().fatal("only cxchg takes a failure ordering");
+            }
+
             match *split.get(1) {
-                "cxchg" => {
+                "cxchg" | "cxchgweak" => {
+                    let weak = *split.get(1) == "cxchgweak";
+
                     // See include/llvm/IR/Instructions.h for their implementation
                     // of this, I assume that it's good enough for us to use for
                     // now.
@@ -462,13 +477,29 @@ pub fn trans_intrinsic_call<'a>(mut bcx: &'a Block<'a>, node: ast::NodeId,
                             llvm::SequentiallyConsistent
                     };
 
+                    let failure_order =
+                        failure_order.unwrap_or(strongest_failure_ordering);
+
                     let res = AtomicCmpXchg(bcx, *llargs.get(0), *llargs.get(1),
                                             *llargs.get(2), order,
-                                            strongest_failure_ordering);
-                    if unsafe { llvm::LLVMVersionMinor() >= 5 } {
-                        ExtractValue(bcx, res, 0)
+                                            failure_order, weak);
+                    let (val, success) = if unsafe { llvm::LLVMVersionMinor() >= 5 } {
+                        (ExtractValue(bcx, res, 0), ExtractValue(bcx, res, 1))
+                    } else {
+                        // Only the strong form exists, so success means the
+                        // expected value was found
+                        (res, ICmp(bcx, llvm::IntEQ, res, *llargs.get(1)))
+                    };
+
+                    if weak {
+                        // The weak form can fail spuriously, so it also
+                        // returns whether the exchange happened
+                        let success = ZExt(bcx, success, Type::bool(ccx));
+                        let ret = C_undef(type_of::type_of(ccx, ret_ty));
+                        let ret = InsertValue(bcx, ret, val, 0);
+                        InsertValue(bcx, ret, success, 1)
                     } else {
-                        res
+                        val
                     }
                 }
 
@@ -4719,6 +4719,10 @@ pub fn check_intrinsic_type(ccx: &CrateCtxt, it: &ast::ForeignItem) {
                                 param(ccx, 0),
                                 param(ccx, 0)),
                         param(ccx, 0)),
+            "cxchgweak" => (1, vec!(ty::mk_mut_ptr(tcx, param(ccx, 0)),
+                                    param(ccx, 0),
+                                    param(ccx, 0)),
+                            ty::mk_tup(tcx, vec!(param(ccx, 0), ty::mk_bool()))),
             "load" => (1, vec!(ty::mk_imm_ptr(tcx, param(ccx, 0))),
                        param(ccx, 0)),
             "store" => (1, vec!(ty::mk_mut_ptr(tcx, param(ccx, 0)), param(ccx, 0)),
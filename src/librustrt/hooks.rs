@@ -0,0 +1,205 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Idle, exit and shutdown hooks
+//!
+//! An application which buffers work, such as batching up the values it
+//! receives on a channel, needs to know when to flush what it's holding on to.
+//! Three kinds of callbacks can be registered here for this:
+//!
+//! * Idle hooks are called by a scheduler each time it runs out of work and
+//!   goes to sleep. They're called on the scheduler itself, outside of any
+//!   task, so they must be quick and must not block, fail, or use anything
+//!   which needs a local task. Sending on a channel is fine. Native tasks are
+//!   scheduled by the OS, so only the schedulers of libgreen call them.
+//!
+//! * Exit hooks are called once by the entry point of the program, as soon as
+//!   the main task has returned, while the other tasks may still be running.
+//!   They're called on the thread which started the runtime, on a task which
+//!   may have no I/O (libgreen's entry point runs on one), so they must not
+//!   rely on any.
+//!
+//! * Shutdown hooks are run once by the entry point of the program, after the
+//!   main task and all of the other tasks have exited, but before the runtime
+//!   is torn down and the `at_exit` procedures are run. They run on a task of
+//!   the runtime, a native task for libnative and a green task of the pool for
+//!   libgreen, so unlike `at_exit` procedures they can do I/O and use
+//!   channels.
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+use collections::{Vec, MutableSeq};
+use core::mem;
+
+use bookkeeping;
+use mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut HOOKS: *mut Hooks = 0 as *mut Hooks;
+
+struct Hooks {
+    idle: Vec<fn()>,
+    exit: Vec<fn()>,
+    shutdown: Vec<proc():Send>,
+}
+
+/// Registers `f` to be called whenever a scheduler runs out of work.
+///
+/// The hook is a plain function because it may be called by many schedulers
+/// at once, any number of times. Hooks can't be unregistered.
+pub fn on_idle(f: fn()) {
+    with_hooks(|h| h.idle.push(f));
+}
+
+/// Registers `f` to be called when the main task returns, before the runtime
+/// waits for the other tasks to exit. This is where to tell of the tasks which
+/// would keep the program from exiting.
+pub fn on_exit(f: fn()) {
+    with_hooks(|h| h.exit.push(f));
+}
+
+/// Registers `f` to be run at the shutdown of the runtime, after all tasks
+/// have exited. Hooks are run in the order they were registered.
+///
+/// A shutdown hook may register more hooks, which are run after the ones
+/// which were there before.
+pub fn on_shutdown(f: proc():Send) {
+    with_hooks(|h| h.shutdown.push(f));
+}
+
+/// Calls the idle hooks. This is called by schedulers when they go to sleep.
+pub fn run_idle_hooks() {
+    let hooks = unsafe {
+        // Don't take the lock if nothing has ever been registered
+        if HOOKS.is_null() { return }
+        with_hooks(|h| h.idle.clone())
+    };
+    for f in hooks.iter() {
+        (*f)();
+    }
+}
+
+/// Calls the exit hooks, waits for all native tasks to exit, and then runs
+/// the shutdown hooks. This is called by the entry points of programs, on a
+/// task which outlives the others.
+pub fn run_shutdown_hooks() {
+    run_shutdown_hooks_on(|hooks| hooks())
+}
+
+/// Calls the exit hooks and runs the shutdown hooks as `run_shutdown_hooks`
+/// does, but hands the procedure which runs each batch of shutdown hooks to
+/// `run`, which must return once it has been run. This is how a runtime whose
+/// entry point isn't one of its own tasks runs the hooks on one. Native tasks
+/// are waited for before each batch, other tasks are up to `run`.
+pub fn run_shutdown_hooks_on(run: |proc():Send|) {
+    run_exit_hooks();
+    loop {
+        bookkeeping::wait_for_other_tasks();
+        let hooks = queued_shutdown_hooks();
+        if hooks.len() == 0 { break }
+        run(proc() {
+            for f in hooks.move_iter() {
+                f();
+            }
+        });
+    }
+}
+
+/// Calls the exit hooks which haven't been called yet. This is called by the
+/// entry points of programs as soon as the main task returns.
+pub fn run_exit_hooks() {
+    unsafe { if HOOKS.is_null() { return } }
+    let hooks = with_hooks(|h| mem::replace(&mut h.exit, Vec::new()));
+    for f in hooks.iter() {
+        (*f)();
+    }
+}
+
+// Takes the shutdown hooks registered so far
+fn queued_shutdown_hooks() -> Vec<proc():Send> {
+    with_hooks(|h| mem::replace(&mut h.shutdown, Vec::new()))
+}
+
+
+fn with_hooks<T>(f: |&mut Hooks| -> T) -> T {
+    unsafe {
+        let _g = LOCK.lock();
+        if HOOKS.is_null() {
+            HOOKS = mem::transmute(box Hooks {
+                idle: Vec::new(),
+                exit: Vec::new(),
+                shutdown: Vec::new(),
+            });
+        }
+        f(&mut *HOOKS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+    use super::{on_idle, on_exit, on_shutdown, run_idle_hooks, run_exit_hooks};
+    use super::queued_shutdown_hooks;
+    use core::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+
+    static mut IDLE: AtomicUint = INIT_ATOMIC_UINT;
+    static mut EXIT: AtomicUint = INIT_ATOMIC_UINT;
+
+    fn idle() { unsafe { IDLE.fetch_add(1, SeqCst); } }
+    fn exit() { unsafe { EXIT.fetch_add(1, SeqCst); } }
+
+    #[test]
+    fn idle_hooks() {
+        on_idle(idle);
+        let before = unsafe { IDLE.load(SeqCst) };
+        run_idle_hooks();
+        assert!(unsafe { IDLE.load(SeqCst) } > before);
+    }
+
+    #[test]
+    fn exit_hooks_run_once() {
+        on_exit(exit);
+        run_exit_hooks();
+        let after = unsafe { EXIT.load(SeqCst) };
+        assert!(after > 0);
+        run_exit_hooks();
+        assert_eq!(unsafe { EXIT.load(SeqCst) }, after);
+    }
+
+    // `run_shutdown_hooks` would wait for the test's own task to exit, so this
+    // runs the queue directly
+    fn run_queued_shutdown_hooks() -> bool {
+        let hooks = queued_shutdown_hooks();
+        let any = hooks.len() > 0;
+        for f in hooks.move_iter() {
+            f();
+        }
+        any
+    }
+
+    #[test]
+    fn shutdown_hooks_run_once_in_order() {
+        let (tx, rx) = channel();
+        let (tx2, tx3) = (tx.clone(), tx.clone());
+        on_shutdown(proc() {
+            tx.send(1i);
+            on_shutdown(proc() tx3.send(3));
+        });
+        on_shutdown(proc() tx2.send(2));
+        assert!(run_queued_shutdown_hooks());
+        assert!(run_queued_shutdown_hooks());
+        assert!(!run_queued_shutdown_hooks());
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        assert_eq!(rx.recv(), 3);
+        assert!(rx.recv_opt().is_err());
+    }
+}
@@ -46,7 +46,6 @@ mod macros;
 
 mod at_exit_imp;
 mod local_ptr;
-mod thread_local_storage;
 mod util;
 mod libunwind;
 
@@ -54,14 +53,19 @@ pub mod args;
 pub mod bookkeeping;
 pub mod c_str;
 pub mod exclusive;
+pub mod hooks;
 pub mod local;
 pub mod local_data;
 pub mod local_heap;
 pub mod mutex;
 pub mod rtio;
+pub mod sched_stats;
 pub mod stack;
 pub mod task;
 pub mod thread;
+#[doc(hidden)]
+pub mod thread_local_storage;
+pub mod time;
 pub mod unwind;
 
 /// The interface to the current runtime.
@@ -81,6 +85,9 @@ pub trait Runtime {
                   cur_task: Box<Task>,
                   f: |BlockedTask| -> Result<(), BlockedTask>);
     fn reawaken(self: Box<Self>, to_wake: Box<Task>);
+    // Like `reawaken`, but the current task keeps running and the woken task
+    // is only scheduled to run later.
+    fn reawaken_later(self: Box<Self>, to_wake: Box<Task>);
 
     // Miscellaneous calls which are very different depending on what context
     // you're in.
@@ -165,5 +172,5 @@ pub mod shouldnt_be_public {
 
 #[cfg(not(test))]
 mod std {
-    pub use core::{fmt, option, cmp};
+    pub use core::{fmt, option, cmp, clone};
 }
@@ -33,6 +33,23 @@ key_vector.replace(Some(vec![4]));
 assert_eq!(*key_vector.get().unwrap(), vec![4]);
 ```
 
+The `task_local!` macro declares a typed `TaskLocal` instead, which starts out
+holding a value given by an initialization function, and which is reached
+through `get`, `set` and `with` rather than through `Option`s:
+
+```rust
+fn zero() -> uint { 0 }
+task_local!(static COUNTER: uint = zero)
+
+COUNTER.with(|n| *n += 1);
+assert_eq!(COUNTER.get(), 1);
+```
+
+When a task exits, its task-local values are destroyed in the reverse order of
+when they were set, so that a value's destructor may still use the values which
+were there before it. Task-local storage can't be used from these destructors,
+though.
+
 */
 
 // Casting 'Arcane Sight' reveals an overwhelming aura of Transmutation
@@ -42,7 +59,10 @@ use core::prelude::*;
 
 use alloc::boxed::Box;
 use collections::MutableSeq;
+use collections::slice::MutableVectorAllocating;
 use collections::vec::Vec;
+use core::atomics;
+use core::cell::RefCell;
 use core::kinds::marker;
 use core::mem;
 use core::raw;
@@ -90,10 +110,27 @@ impl<T: 'static> LocalData for T {}
 //
 // n.b. If TLS is used heavily in future, this could be made more efficient with
 //      a proper map.
+//
+// Each entry is a key, its value, how many times the value is on loan and
+// when it was set, which decides the order in which values are destroyed.
 #[doc(hidden)]
-pub type Map = Vec<Option<(*const u8, TLSValue, uint)>>;
+pub type Map = Vec<Option<(*const u8, TLSValue, uint, uint)>>;
 type TLSValue = Box<LocalData + Send>;
 
+static mut NEXT_SET: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+/// Destroys the values in a task's map, the most recently set first.
+#[doc(hidden)]
+pub fn destroy(map: Map) {
+    let mut values: Vec<(uint, TLSValue)> = map.move_iter().filter_map(|entry| {
+        entry.map(|(_, data, _, set)| (set, data))
+    }).collect();
+    values.as_mut_slice().sort_by(|&(a, _), &(b, _)| b.cmp(&a));
+    for (_, data) in values.move_iter() {
+        drop(data);
+    }
+}
+
 // Gets the map from the runtime. Lazily initialises if not done so already.
 unsafe fn get_local_map<'a>() -> Option<&'a mut Map> {
     if !Local::exists(None::<Task>) { return None }
@@ -177,7 +214,7 @@ impl<T: 'static> KeyValue<T> {
         let newval = data.map(|d| {
             let d = box d as Box<LocalData>;
             let d: Box<LocalData + Send> = unsafe { mem::transmute(d) };
-            (keyval, d, 0)
+            (keyval, d, 0, unsafe { NEXT_SET.fetch_add(1, atomics::Relaxed) })
         });
 
         let pos = match self.find(map) {
@@ -189,7 +226,7 @@ impl<T: 'static> KeyValue<T> {
 
         match pos {
             Some(i) => {
-                mem::replace(map.get_mut(i), newval).map(|(_, data, _)| {
+                mem::replace(map.get_mut(i), newval).map(|(_, data, _, _)| {
                     // Move `data` into transmute to get out the memory that it
                     // owns, we must free it manually later.
                     let t: raw::TraitObject = unsafe { mem::transmute(data) };
@@ -250,7 +287,7 @@ impl<T: 'static> KeyValue<T> {
         let key_value = key_to_key_value(self);
         map.mut_iter().enumerate().filter_map(|(i, entry)| {
             match *entry {
-                Some((k, ref data, ref mut loan)) if k == key_value => {
+                Some((k, ref data, ref mut loan, _)) if k == key_value => {
                     Some((i, data, loan))
                 }
                 _ => None
@@ -268,11 +305,66 @@ impl<T: 'static> Drop for Ref<T> {
     fn drop(&mut self) {
         let map = unsafe { get_local_map().unwrap() };
 
-        let (_, _, ref mut loan) = *map.get_mut(self._index).get_mut_ref();
+        let (_, _, ref mut loan, _) = *map.get_mut(self._index).get_mut_ref();
         *loan -= 1;
     }
 }
 
+/// A typed task-local value, declared with the `task_local!` macro.
+///
+/// Each task has its own value, which is created by calling the `init`
+/// function the first time the task uses it.
+pub struct TaskLocal<T> {
+    /// The key holding the value. Set by `task_local!`.
+    #[doc(hidden)]
+    pub key: Key<RefCell<T>>,
+    /// Creates the task's value on first use. Set by `task_local!`.
+    #[doc(hidden)]
+    pub init: fn() -> T,
+}
+
+impl<T: 'static> TaskLocal<T> {
+    /// Calls `f` with a mutable reference to the current task's value.
+    ///
+    /// # Failure
+    ///
+    /// This function fails if it's called from within a call to `with` on
+    /// the same value, or if there is no local task.
+    pub fn with<R>(&'static self, f: |&mut T| -> R) -> R {
+        let value = match self.key.get() {
+            Some(value) => value,
+            None => {
+                self.key.replace(Some(RefCell::new((self.init)())));
+                self.key.get().unwrap()
+            }
+        };
+        let mut value = value.borrow_mut();
+        f(&mut *value)
+    }
+
+    /// Replaces the current task's value with `t`.
+    ///
+    /// # Failure
+    ///
+    /// This function fails if it's called from within a call to `with` on
+    /// the same value, or if there is no local task.
+    pub fn set(&'static self, t: T) {
+        self.key.replace(Some(RefCell::new(t)));
+    }
+}
+
+impl<T: Clone + 'static> TaskLocal<T> {
+    /// Returns a copy of the current task's value.
+    ///
+    /// # Failure
+    ///
+    /// This function fails if it's called from within a call to `with` on
+    /// the same value, or if there is no local task.
+    pub fn get(&'static self) -> T {
+        self.with(|t| t.clone())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::prelude::*;
@@ -431,4 +523,54 @@ mod tests {
         let _k = key.get();
         key.replace(Some(4));
     }
+
+    #[test]
+    fn test_destroyed_in_reverse_order() {
+        struct D(uint, Sender<uint>);
+        impl Drop for D {
+            fn drop(&mut self) {
+                let D(n, ref tx) = *self;
+                tx.send(n);
+            }
+        }
+        static key1: Key<D> = &Key;
+        static key2: Key<D> = &Key;
+        static key3: Key<D> = &Key;
+
+        let (tx, rx) = channel();
+        task::spawn(proc() {
+            key1.replace(Some(D(1, tx.clone())));
+            key2.replace(Some(D(2, tx.clone())));
+            key3.replace(Some(D(3, tx.clone())));
+            // setting again counts as the latest
+            key1.replace(Some(D(4, tx.clone())));
+        });
+        let order: Vec<uint> = rx.iter().collect();
+        assert_eq!(order, vec![1, 4, 3, 2]);
+    }
+
+    fn zero() -> int { 0 }
+
+    #[test]
+    fn test_task_local() {
+        static counter: TaskLocal<int> = TaskLocal { key: &Key, init: zero };
+        assert_eq!(counter.get(), 0);
+        counter.with(|n| *n += 2);
+        assert_eq!(counter.get(), 2);
+        let child = task::try_future(proc() {
+            assert_eq!(counter.get(), 0);
+            counter.set(5);
+            assert_eq!(counter.with(|n| *n), 5);
+        });
+        counter.set(3);
+        assert_eq!(counter.get(), 3);
+        assert!(child.unwrap().is_ok());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_task_local_nested_with() {
+        static counter: TaskLocal<int> = TaskLocal { key: &Key, init: zero };
+        counter.with(|_| counter.with(|_| ()));
+    }
 }
@@ -11,7 +11,8 @@
 //! A native mutex and condition variable type.
 //!
 //! This module contains bindings to the platform's native mutex/condition
-//! variable primitives. It provides two types: `StaticNativeMutex`, which can
+//! variable primitives (futexes on Linux, and pthreads or critical sections
+//! elsewhere). It provides two types: `StaticNativeMutex`, which can
 //! be statically initialized via the `NATIVE_MUTEX_INIT` value, and a simple
 //! wrapper `NativeMutex` that has a destructor to clean up after itself. These
 //! objects serve as both mutexes and condition variables simultaneously.
@@ -336,7 +337,7 @@ impl<'a> Drop for LockGuard<'a> {
     }
 }
 
-#[cfg(unix)]
+#[cfg(unix, not(target_os = "linux"))]
 mod imp {
     use libc;
     use self::os::{PTHREAD_MUTEX_INITIALIZER, PTHREAD_COND_INITIALIZER,
@@ -401,52 +402,6 @@ mod imp {
         };
     }
 
-    #[cfg(target_os = "linux")]
-    mod os {
-        use libc;
-
-        // minus 8 because we have an 'align' field
-        #[cfg(target_arch = "x86_64")]
-        static __SIZEOF_PTHREAD_MUTEX_T: uint = 40 - 8;
-        #[cfg(target_arch = "x86")]
-        static __SIZEOF_PTHREAD_MUTEX_T: uint = 24 - 8;
-        #[cfg(target_arch = "arm")]
-        static __SIZEOF_PTHREAD_MUTEX_T: uint = 24 - 8;
-        #[cfg(target_arch = "mips")]
-        static __SIZEOF_PTHREAD_MUTEX_T: uint = 24 - 8;
-        #[cfg(target_arch = "mipsel")]
-        static __SIZEOF_PTHREAD_MUTEX_T: uint = 24 - 8;
-        #[cfg(target_arch = "x86_64")]
-        static __SIZEOF_PTHREAD_COND_T: uint = 48 - 8;
-        #[cfg(target_arch = "x86")]
-        static __SIZEOF_PTHREAD_COND_T: uint = 48 - 8;
-        #[cfg(target_arch = "arm")]
-        static __SIZEOF_PTHREAD_COND_T: uint = 48 - 8;
-        #[cfg(target_arch = "mips")]
-        static __SIZEOF_PTHREAD_COND_T: uint = 48 - 8;
-        #[cfg(target_arch = "mipsel")]
-        static __SIZEOF_PTHREAD_COND_T: uint = 48 - 8;
-
-        #[repr(C)]
-        pub struct pthread_mutex_t {
-            __align: libc::c_longlong,
-            size: [u8, ..__SIZEOF_PTHREAD_MUTEX_T],
-        }
-        #[repr(C)]
-        pub struct pthread_cond_t {
-            __align: libc::c_longlong,
-            size: [u8, ..__SIZEOF_PTHREAD_COND_T],
-        }
-
-        pub static PTHREAD_MUTEX_INITIALIZER: pthread_mutex_t = pthread_mutex_t {
-            __align: 0,
-            size: [0, ..__SIZEOF_PTHREAD_MUTEX_T],
-        };
-        pub static PTHREAD_COND_INITIALIZER: pthread_cond_t = pthread_cond_t {
-            __align: 0,
-            size: [0, ..__SIZEOF_PTHREAD_COND_T],
-        };
-    }
     #[cfg(target_os = "android")]
     mod os {
         use libc;
@@ -515,6 +470,115 @@ mod imp {
     }
 }
 
+// On Linux, the mutex and condition variable are built directly on futexes.
+// Locking and unlocking an uncontended mutex are then a single atomic
+// instruction each, and the kernel is only entered when a thread has to sleep
+// or be woken up.
+#[cfg(target_os = "linux")]
+mod imp {
+    use libc;
+    use core::atomics;
+
+    // The states of the mutex. Threads only sleep on a contended mutex, and
+    // unlocking a mutex which isn't contended doesn't wake anyone up.
+    static UNLOCKED: u32 = 0;
+    static LOCKED: u32 = 1;
+    static CONTENDED: u32 = 2;
+
+    static FUTEX_WAIT: libc::c_int = 0;
+    static FUTEX_WAKE: libc::c_int = 1;
+    static FUTEX_PRIVATE_FLAG: libc::c_int = 128;
+
+    #[cfg(target_arch = "x86_64")]
+    static SYS_FUTEX: libc::c_long = 202;
+    #[cfg(target_arch = "x86")]
+    static SYS_FUTEX: libc::c_long = 240;
+    #[cfg(target_arch = "arm")]
+    static SYS_FUTEX: libc::c_long = 240;
+    #[cfg(target_arch = "mips")]
+    #[cfg(target_arch = "mipsel")]
+    static SYS_FUTEX: libc::c_long = 4238;
+
+    pub struct Mutex {
+        state: atomics::AtomicU32,
+        // bumped on every signal, so a waiter which is about to sleep notices
+        // that it was signaled after it unlocked the mutex
+        seq: atomics::AtomicU32,
+    }
+
+    pub static MUTEX_INIT: Mutex = Mutex {
+        state: atomics::INIT_ATOMIC_U32,
+        seq: atomics::INIT_ATOMIC_U32,
+    };
+
+    impl Mutex {
+        pub unsafe fn new() -> Mutex {
+            Mutex {
+                state: atomics::AtomicU32::new(UNLOCKED),
+                seq: atomics::AtomicU32::new(0),
+            }
+        }
+
+        #[inline]
+        pub unsafe fn lock(&self) {
+            if self.state.compare_and_swap(UNLOCKED, LOCKED,
+                                           atomics::Acquire) != UNLOCKED {
+                self.lock_contended();
+            }
+        }
+
+        #[inline]
+        pub unsafe fn unlock(&self) {
+            if self.state.swap(UNLOCKED, atomics::Release) == CONTENDED {
+                futex(&self.state, FUTEX_WAKE, 1);
+            }
+        }
+
+        pub unsafe fn trylock(&self) -> bool {
+            self.state.compare_and_swap(UNLOCKED, LOCKED,
+                                        atomics::Acquire) == UNLOCKED
+        }
+
+        pub unsafe fn signal(&self) {
+            self.seq.fetch_add(1, atomics::SeqCst);
+            futex(&self.seq, FUTEX_WAKE, 1);
+        }
+
+        pub unsafe fn wait(&self) {
+            let seq = self.seq.load(atomics::SeqCst);
+            self.unlock();
+            // If we've been signaled since unlocking, `seq` has changed and
+            // this returns right away
+            futex(&self.seq, FUTEX_WAIT, seq);
+            // Other threads may have been woken up along with us, so the
+            // mutex has to be assumed to be contended from now on
+            self.lock_contended();
+        }
+
+        pub unsafe fn destroy(&self) {}
+
+        #[inline(never)]
+        unsafe fn lock_contended(&self) {
+            // Flag the mutex as contended before sleeping, so that whoever
+            // holds it wakes us up when unlocking it
+            while self.state.swap(CONTENDED, atomics::Acquire) != UNLOCKED {
+                futex(&self.state, FUTEX_WAIT, CONTENDED);
+            }
+        }
+    }
+
+    unsafe fn futex(word: &atomics::AtomicU32, op: libc::c_int, val: u32) {
+        // An atomic is laid out the same as the integer it contains
+        let addr = word as *const atomics::AtomicU32 as *mut u32;
+        syscall(SYS_FUTEX, addr, op | FUTEX_PRIVATE_FLAG, val as libc::c_int,
+                0 as *const libc::c_void);
+    }
+
+    extern {
+        fn syscall(number: libc::c_long, ...) -> libc::c_long;
+    }
+}
+
 #[cfg(windows)]
 mod imp {
     use alloc::libc_heap::malloc_raw;
@@ -704,6 +768,23 @@ mod test {
         }
     }
 
+    #[test]
+    fn contended_lock() {
+        static mut lock: StaticNativeMutex = NATIVE_MUTEX_INIT;
+        static mut count: uint = 0;
+        static N: uint = 1000;
+        unsafe {
+            let threads = Vec::from_fn(4, |_| Thread::start(proc() {
+                for _ in range(0, N) {
+                    let _guard = lock.lock();
+                    count += 1;
+                }
+            }));
+            for t in threads.move_iter() { t.join(); }
+            assert_eq!(count, 4 * N);
+        }
+    }
+
     #[test]
     fn destroy_immediately() {
         unsafe {
@@ -235,6 +235,9 @@ pub trait IoFactory {
             -> IoResult<Box<RtioTTY + Send>>;
     fn signal(&mut self, signal: int, cb: Box<Callback + Send>)
         -> IoResult<Box<RtioSignal + Send>>;
+    fn fd_watch(&mut self, fd: c_int, readable: bool, writable: bool,
+                cb: Box<FdCallback + Send>)
+        -> IoResult<Box<RtioFdWatcher + Send>>;
 }
 
 pub trait RtioTcpListener : RtioSocket {
@@ -246,11 +249,14 @@ pub trait RtioTcpAcceptor : RtioSocket {
     fn accept_simultaneously(&mut self) -> IoResult<()>;
     fn dont_accept_simultaneously(&mut self) -> IoResult<()>;
     fn set_timeout(&mut self, timeout: Option<u64>);
+    fn watch_readable(&mut self, cb: Box<FdCallback + Send>)
+        -> IoResult<Box<RtioFdWatcher + Send>>;
 }
 
 pub trait RtioTcpStream : RtioSocket {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<uint>;
     fn write(&mut self, buf: &[u8]) -> IoResult<()>;
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> IoResult<()>;
     fn peer_name(&mut self) -> IoResult<SocketAddr>;
     fn control_congestion(&mut self) -> IoResult<()>;
     fn nodelay(&mut self) -> IoResult<()>;
@@ -262,6 +268,8 @@ pub trait RtioTcpStream : RtioSocket {
     fn set_timeout(&mut self, timeout_ms: Option<u64>);
     fn set_read_timeout(&mut self, timeout_ms: Option<u64>);
     fn set_write_timeout(&mut self, timeout_ms: Option<u64>);
+    fn watch_readable(&mut self, cb: Box<FdCallback + Send>)
+        -> IoResult<Box<RtioFdWatcher + Send>>;
 }
 
 pub trait RtioSocket {
@@ -294,6 +302,7 @@ pub trait RtioTimer {
     fn sleep(&mut self, msecs: u64);
     fn oneshot(&mut self, msecs: u64, cb: Box<Callback + Send>);
     fn period(&mut self, msecs: u64, cb: Box<Callback + Send>);
+    fn cancel(&mut self);
 }
 
 pub trait RtioFileStream {
@@ -326,6 +335,8 @@ pub trait RtioPipe {
     fn set_timeout(&mut self, timeout_ms: Option<u64>);
     fn set_read_timeout(&mut self, timeout_ms: Option<u64>);
     fn set_write_timeout(&mut self, timeout_ms: Option<u64>);
+    fn watch_readable(&mut self, cb: Box<FdCallback + Send>)
+        -> IoResult<Box<RtioFdWatcher + Send>>;
 }
 
 pub trait RtioUnixListener {
@@ -335,6 +346,8 @@ pub trait RtioUnixListener {
 pub trait RtioUnixAcceptor {
     fn accept(&mut self) -> IoResult<Box<RtioPipe + Send>>;
     fn set_timeout(&mut self, timeout: Option<u64>);
+    fn watch_readable(&mut self, cb: Box<FdCallback + Send>)
+        -> IoResult<Box<RtioFdWatcher + Send>>;
 }
 
 pub trait RtioTTY {
@@ -352,6 +365,21 @@ pub trait PausableIdleCallback {
 
 pub trait RtioSignal {}
 
+/// A watch on the readiness of a file descriptor. Once the callback of the
+/// watch has returned `false`, the descriptor isn't watched again until
+/// `rearm`.
+pub trait RtioFdWatcher {
+    fn rearm(&mut self);
+}
+
+pub trait FdCallback {
+    /// Called when the descriptor is ready, returning whether to keep watching
+    /// it. A callback which keeps watching must have consumed the readiness
+    /// (by reading until the descriptor would block, for example), or it's
+    /// called again right away.
+    fn call(&mut self, readable: bool, writable: bool) -> bool;
+}
+
 pub struct IoError {
     pub code: uint,
     pub extra: uint,
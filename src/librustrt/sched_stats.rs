@@ -0,0 +1,154 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scheduler statistics
+//!
+//! Each scheduler keeps counters of what it does, which it registers here for
+//! as long as it runs. `stats` takes a snapshot of the counters of all of the
+//! schedulers of the program, so that an application can keep an eye on the
+//! runtime, and notice when some schedulers get much more work than others.
+//!
+//! Native tasks are scheduled by the OS, so only the schedulers of libgreen
+//! show up here.
+
+use core::prelude::*;
+
+use alloc::arc::Arc;
+use collections::{Vec, MutableSeq};
+use core::atomics::{AtomicUint, INIT_ATOMIC_UINT, Relaxed, SeqCst};
+use core::mem;
+
+use mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+
+static mut NEXT_ID: AtomicUint = INIT_ATOMIC_UINT;
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut SCHEDULERS: *mut Vec<(uint, Arc<Counters>)> =
+    0 as *mut Vec<(uint, Arc<Counters>)>;
+
+/// The counters of a scheduler, which it updates as it runs.
+pub struct Counters {
+    /// The number of times the scheduler switched from a task to another.
+    pub context_switches: AtomicUint,
+    /// The number of times the scheduler tried to steal a task from a queue.
+    pub steals_attempted: AtomicUint,
+    /// The number of tasks the scheduler stole.
+    pub steals_succeeded: AtomicUint,
+    /// The number of tasks spawned by the tasks running on the scheduler.
+    pub tasks_spawned: AtomicUint,
+    /// The time the scheduler spent without any work, in milliseconds.
+    pub idle_ms: AtomicUint,
+}
+
+/// A snapshot of the counters of a scheduler.
+#[deriving(Clone, PartialEq, Show)]
+pub struct SchedStats {
+    /// An identifier of the scheduler, unique for the lifetime of the process.
+    pub id: uint,
+    /// The number of times the scheduler switched from a task to another.
+    pub context_switches: uint,
+    /// The number of times the scheduler tried to steal a task from a queue.
+    pub steals_attempted: uint,
+    /// The number of tasks the scheduler stole.
+    pub steals_succeeded: uint,
+    /// The number of tasks spawned by the tasks running on the scheduler.
+    pub tasks_spawned: uint,
+    /// The time the scheduler spent without any work, in milliseconds.
+    pub idle_ms: uint,
+}
+
+/// The registration of a scheduler's counters, which keeps them in the
+/// statistics until it's dropped.
+pub struct Registration {
+    id: uint,
+    counters: Arc<Counters>,
+}
+
+/// Registers the counters of a new scheduler, all at zero.
+pub fn register() -> Registration {
+    let id = unsafe { NEXT_ID.fetch_add(1, SeqCst) };
+    let counters = Arc::new(Counters {
+        context_switches: AtomicUint::new(0),
+        steals_attempted: AtomicUint::new(0),
+        steals_succeeded: AtomicUint::new(0),
+        tasks_spawned: AtomicUint::new(0),
+        idle_ms: AtomicUint::new(0),
+    });
+    with_schedulers(|s| s.push((id, counters.clone())));
+    Registration { id: id, counters: counters }
+}
+
+/// Returns a snapshot of the counters of the schedulers which are running, in
+/// the order they were started.
+///
+/// The counters are read one after the other while the schedulers go on
+/// updating them, so they may be a little inconsistent with one another.
+pub fn stats() -> Vec<SchedStats> {
+    with_schedulers(|s| {
+        s.iter().map(|&(id, ref c)| {
+            SchedStats {
+                id: id,
+                context_switches: c.context_switches.load(Relaxed),
+                steals_attempted: c.steals_attempted.load(Relaxed),
+                steals_succeeded: c.steals_succeeded.load(Relaxed),
+                tasks_spawned: c.tasks_spawned.load(Relaxed),
+                idle_ms: c.idle_ms.load(Relaxed),
+            }
+        }).collect()
+    })
+}
+
+impl Registration {
+    /// The identifier of the scheduler in the statistics.
+    pub fn id(&self) -> uint { self.id }
+
+    /// The counters of the scheduler.
+    pub fn counters<'a>(&'a self) -> &'a Counters { &*self.counters }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let id = self.id;
+        with_schedulers(|s| s.retain(|&(i, _)| i != id));
+    }
+}
+
+fn with_schedulers<T>(f: |&mut Vec<(uint, Arc<Counters>)>| -> T) -> T {
+    unsafe {
+        let _g = LOCK.lock();
+        if SCHEDULERS.is_null() {
+            SCHEDULERS = mem::transmute(box Vec::<(uint, Arc<Counters>)>::new());
+        }
+        f(&mut *SCHEDULERS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+    use super::{register, stats};
+    use core::atomics::Relaxed;
+
+    #[test]
+    fn register_unregister() {
+        let r = register();
+        r.counters().context_switches.fetch_add(3, Relaxed);
+        r.counters().idle_ms.fetch_add(7, Relaxed);
+        let id = r.id();
+        {
+            let s = stats();
+            let s = s.iter().find(|s| s.id == id).unwrap();
+            assert_eq!(s.context_switches, 3);
+            assert_eq!(s.idle_ms, 7);
+            assert_eq!(s.tasks_spawned, 0);
+        }
+        drop(r);
+        assert!(stats().iter().all(|s| s.id != id));
+    }
+}
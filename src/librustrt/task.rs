@@ -24,6 +24,7 @@ use core::kinds::marker;
 use core::mem;
 use core::raw;
 
+use exclusive::Exclusive;
 use local_data;
 use Runtime;
 use local::Local;
@@ -113,6 +114,25 @@ pub struct TaskOpts {
     pub name: Option<SendStr>,
     /// The size of the stack for the spawned task
     pub stack_size: Option<uint>,
+    /// How urgently the task should be run, relative to the tasks it shares a
+    /// scheduler with
+    pub priority: Priority,
+}
+
+/// The scheduling priority of a task.
+///
+/// Priorities are honored by schedulers which run many tasks on one thread,
+/// such as the green scheduler. Tasks of a higher priority are run first, and
+/// the tasks of lower ones only when no other work is available. Tasks with
+/// an OS thread of their own leave scheduling to the OS, which ignores this.
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Show)]
+pub enum Priority {
+    /// Batch work, which runs when there is nothing else to do.
+    Low,
+    /// The priority of tasks which don't ask for another one.
+    Normal,
+    /// Latency sensitive work, which runs before everything else.
+    High,
 }
 
 /// Indicates the manner in which a task exited.
@@ -129,11 +149,34 @@ pub struct LocalStorage(pub Option<local_data::Map>);
 /// A handle to a blocked task. Usually this means having the Box<Task>
 /// pointer by ownership, but if the task is killable, a killer can steal it
 /// at any time.
+///
+/// A task which blocked outside of the runtime, such as on a thread of an
+/// embedder which has no `Task`, is represented by the `Waker` which gets it
+/// going again.
 pub enum BlockedTask {
     Owned(Box<Task>),
     Shared(Arc<AtomicUint>),
+    Foreign(Box<Waker + Send>),
+}
+
+/// The way of waking up a task which isn't a runtime `Task`.
+///
+/// Runtimes and embedders which don't block their tasks through `Runtime`
+/// can still have these tasks wait on channels and the other primitives of
+/// libsync, by handing them a `BlockedTask` made with `BlockedTask::foreign`.
+/// The primitive stores it like any other blocked task, and calls `wake` when
+/// the task should stop waiting.
+pub trait Waker {
+    /// Wakes the task up. This is called at most once, from any thread.
+    fn wake(self: Box<Self>);
 }
 
+// The low bits of the word a blocked task is cast to: the handle is shared,
+// or it's a foreign waker. Both are boxes, which are at least word-aligned.
+static SHARED_TAG: uint = 0x1;
+static FOREIGN_TAG: uint = 0x2;
+static TAGS: uint = SHARED_TAG | FOREIGN_TAG;
+
 /// Per-task state related to task death, killing, failure, etc.
 pub struct Death {
     pub on_exit: Option<proc(Result):Send>,
@@ -296,7 +339,7 @@ impl Task {
             drop(task);
 
             // First, destroy task-local storage. This may run user dtors.
-            drop(tld);
+            tld.map(local_data::destroy);
 
             // Destroy remaining boxes. Also may run user dtors.
             drop(heap);
@@ -419,6 +462,14 @@ impl Task {
         ops.reawaken(self);
     }
 
+    /// Wakes up a previously blocked task without giving up the current task's
+    /// time slice. The woken task is scheduled to run, but unlike `reawaken`
+    /// the scheduler will never switch to it immediately.
+    pub fn reawaken_later(mut self: Box<Task>) {
+        let ops = self.imp.take_unwrap();
+        ops.reawaken_later(self);
+    }
+
     /// Yields control of this task to another task. This function will
     /// eventually return, but possibly not immediately. This is used as an
     /// opportunity to allow other tasks a chance to run.
@@ -464,7 +515,7 @@ impl Drop for Task {
 
 impl TaskOpts {
     pub fn new() -> TaskOpts {
-        TaskOpts { on_exit: None, name: None, stack_size: None }
+        TaskOpts { on_exit: None, name: None, stack_size: None, priority: Normal }
     }
 }
 
@@ -476,24 +527,39 @@ impl Iterator<BlockedTask> for BlockedTasks {
 
 impl BlockedTask {
     /// Returns Some if the task was successfully woken; None if already killed.
+    ///
+    /// A foreign task has no `Task` to return: its waker is called right away,
+    /// and None is returned.
     pub fn wake(self) -> Option<Box<Task>> {
         match self {
             Owned(task) => Some(task),
             Shared(arc) => {
                 match arc.swap(0, SeqCst) {
                     0 => None,
-                    n => Some(unsafe { mem::transmute(n) }),
+                    n => unsafe { wake_word(n) },
                 }
             }
+            Foreign(waker) => { waker.wake(); None }
         }
     }
 
+    /// Creates the handle of a task which is woken up by `waker`.
+    pub fn foreign(waker: Box<Waker + Send>) -> BlockedTask {
+        Foreign(waker)
+    }
+
     /// Reawakens this task if ownership is acquired. If finer-grained control
     /// is desired, use `wake` instead.
     pub fn reawaken(self) {
         self.wake().map(|t| t.reawaken());
     }
 
+    /// Reawakens this task if ownership is acquired, without switching to it
+    /// immediately. See `Task::reawaken_later`.
+    pub fn reawaken_later(self) {
+        self.wake().map(|t| t.reawaken_later());
+    }
+
     // This assertion has two flavours because the wake involves an atomic op.
     // In the faster version, destructors will fail dramatically instead.
     #[cfg(not(test))] pub fn trash(self) { }
@@ -512,6 +578,10 @@ impl BlockedTask {
                 Arc::new(flag)
             }
             Shared(arc) => arc.clone(),
+            Foreign(waker) => {
+                let flag = unsafe { AtomicUint::new(foreign_to_uint(waker)) };
+                Arc::new(flag)
+            }
         };
         BlockedTasks{ inner: arc }.take(num_handles)
     }
@@ -523,14 +593,15 @@ impl BlockedTask {
         match self {
             Owned(task) => {
                 let blocked_task_ptr: uint = mem::transmute(task);
-                rtassert!(blocked_task_ptr & 0x1 == 0);
+                rtassert!(blocked_task_ptr & TAGS == 0);
                 blocked_task_ptr
             }
             Shared(arc) => {
                 let blocked_task_ptr: uint = mem::transmute(box arc);
-                rtassert!(blocked_task_ptr & 0x1 == 0);
-                blocked_task_ptr | 0x1
+                rtassert!(blocked_task_ptr & TAGS == 0);
+                blocked_task_ptr | SHARED_TAG
             }
+            Foreign(waker) => foreign_to_uint(waker),
         }
     }
 
@@ -538,12 +609,90 @@ impl BlockedTask {
     /// flag.
     #[inline]
     pub unsafe fn cast_from_uint(blocked_task_ptr: uint) -> BlockedTask {
-        if blocked_task_ptr & 0x1 == 0 {
-            Owned(mem::transmute(blocked_task_ptr))
-        } else {
-            let ptr: Box<Arc<AtomicUint>> =
-                mem::transmute(blocked_task_ptr & !1);
-            Shared(*ptr)
+        match blocked_task_ptr & TAGS {
+            0 => Owned(mem::transmute(blocked_task_ptr)),
+            SHARED_TAG => {
+                let ptr: Box<Arc<AtomicUint>> =
+                    mem::transmute(blocked_task_ptr & !TAGS);
+                Shared(*ptr)
+            }
+            _ => Foreign(foreign_from_uint(blocked_task_ptr)),
+        }
+    }
+}
+
+// A foreign waker is a fat pointer, so it's boxed once more to fit in a word
+unsafe fn foreign_to_uint(waker: Box<Waker + Send>) -> uint {
+    let ptr: uint = mem::transmute(box waker);
+    rtassert!(ptr & TAGS == 0);
+    ptr | FOREIGN_TAG
+}
+
+unsafe fn foreign_from_uint(ptr: uint) -> Box<Waker + Send> {
+    let ptr: Box<Box<Waker + Send>> = mem::transmute(ptr & !TAGS);
+    *ptr
+}
+
+// Wakes up the task held in the word of a shared handle
+unsafe fn wake_word(n: uint) -> Option<Box<Task>> {
+    if n & FOREIGN_TAG == 0 {
+        Some(mem::transmute(n))
+    } else {
+        foreign_from_uint(n).wake();
+        None
+    }
+}
+
+/// Blocks the current task, invoking `f` `amt` times like `Task::deschedule`.
+///
+/// This is what the blocking primitives of libsync use, so that they also work
+/// on threads which aren't running a task, such as threads created by C code.
+/// Such a thread hands `f` foreign `BlockedTask`s, and sleeps on a condition
+/// variable of its own until one of them is woken.
+pub fn deschedule_current(amt: uint,
+                          f: |BlockedTask| -> ::core::result::Result<(), BlockedTask>) {
+    let task: Option<Box<Task>> = Local::try_take();
+    match task {
+        Some(task) => task.deschedule(amt, f),
+        None => deschedule_thread(amt, f),
+    }
+}
+
+struct ThreadWaker {
+    woken: Arc<Exclusive<bool>>,
+}
+
+impl Waker for ThreadWaker {
+    fn wake(self: Box<ThreadWaker>) {
+        unsafe {
+            let mut woken = self.woken.lock();
+            *woken = true;
+            woken.signal();
+        }
+    }
+}
+
+fn deschedule_thread(amt: uint,
+                     f: |BlockedTask| -> ::core::result::Result<(), BlockedTask>) {
+    let woken = Arc::new(Exclusive::new(false));
+    let task = BlockedTask::foreign(box ThreadWaker { woken: woken.clone() });
+
+    // As for tasks, if `f` gives a handle back we wake ourselves up, unless
+    // someone else took the handle first, in which case they will
+    let canceled = if amt == 1 {
+        f(task).err()
+    } else {
+        task.make_selectable(amt).map(f).filter_map(|a| a.err()).next()
+    };
+    match canceled {
+        Some(task) => { task.wake(); }
+        None => {}
+    }
+
+    unsafe {
+        let woken = woken.lock();
+        while !*woken {
+            woken.wait();
         }
     }
 }
@@ -560,6 +709,8 @@ mod test {
     use std::prelude::*;
     use std::task;
     use std::gc::{Gc, GC};
+    use alloc::arc::Arc;
+    use core::atomics::{AtomicUint, SeqCst};
 
     #[test]
     fn local_heap() {
@@ -642,4 +793,37 @@ mod test {
         let mut task = BlockedTask::block(task).wake().unwrap();
         task.destroyed = true;
     }
+
+    struct CountingWaker(Arc<AtomicUint>);
+
+    impl Waker for CountingWaker {
+        fn wake(self: Box<CountingWaker>) {
+            let CountingWaker(ref count) = *self;
+            count.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    fn foreign_wake() {
+        let count = Arc::new(AtomicUint::new(0));
+        let blocked = BlockedTask::foreign(box CountingWaker(count.clone()));
+        let n = unsafe { blocked.cast_to_uint() };
+        let blocked = unsafe { BlockedTask::cast_from_uint(n) };
+        assert_eq!(count.load(SeqCst), 0);
+        assert!(blocked.wake().is_none());
+        assert_eq!(count.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn foreign_selectable() {
+        let count = Arc::new(AtomicUint::new(0));
+        let blocked = BlockedTask::foreign(box CountingWaker(count.clone()));
+        let handles: Vec<BlockedTask> = blocked.make_selectable(3).map(|h| {
+            unsafe { BlockedTask::cast_from_uint(h.cast_to_uint()) }
+        }).collect();
+        for h in handles.move_iter() {
+            h.reawaken();
+        }
+        assert_eq!(count.load(SeqCst), 1);
+    }
 }
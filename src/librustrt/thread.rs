@@ -118,6 +118,14 @@ impl Thread<()> {
     pub fn yield_now() {
         unsafe { imp::yield_now(); }
     }
+
+    /// Pins the calling thread to the CPUs numbered in `cpus`, so that the OS
+    /// only runs it on those. Returns whether the thread was pinned: this is
+    /// only supported on Linux and Windows, and the OS refuses sets of CPUs
+    /// which are empty or which the process isn't allowed to use.
+    pub fn pin_current(cpus: &[uint]) -> bool {
+        unsafe { imp::pin_current(cpus) }
+    }
 }
 
 impl<T: Send> Thread<T> {
@@ -151,6 +159,7 @@ mod imp {
     use core::cmp;
     use core::mem;
     use core::ptr;
+    use core::uint;
     use libc;
     use libc::types::os::arch::extra::{LPSECURITY_ATTRIBUTES, SIZE_T, BOOL,
                                        LPVOID, DWORD, LPDWORD, HANDLE};
@@ -198,6 +207,16 @@ mod imp {
         SwitchToThread();
     }
 
+    pub unsafe fn pin_current(cpus: &[uint]) -> bool {
+        let mut mask = 0u;
+        for &cpu in cpus.iter() {
+            if cpu >= uint::BITS { return false }
+            mask |= 1 << cpu;
+        }
+        if mask == 0 { return false }
+        SetThreadAffinityMask(GetCurrentThread(), mask as libc::uintptr_t) != 0
+    }
+
     #[allow(non_snake_case_functions)]
     extern "system" {
         fn CreateThread(lpThreadAttributes: LPSECURITY_ATTRIBUTES,
@@ -208,6 +227,10 @@ mod imp {
                         lpThreadId: LPDWORD) -> HANDLE;
         fn WaitForSingleObject(hHandle: HANDLE, dwMilliseconds: DWORD) -> DWORD;
         fn SwitchToThread() -> BOOL;
+        fn GetCurrentThread() -> HANDLE;
+        fn SetThreadAffinityMask(hThread: HANDLE,
+                                 dwThreadAffinityMask: libc::uintptr_t)
+                                 -> libc::uintptr_t;
     }
 }
 
@@ -276,6 +299,22 @@ mod imp {
     }
 
     pub unsafe fn yield_now() { assert_eq!(sched_yield(), 0); }
+
+    #[cfg(target_os = "linux")]
+    pub unsafe fn pin_current(cpus: &[uint]) -> bool {
+        // glibc's cpu_set_t, which has room for 1024 CPUs
+        let mut set = [0u64, ..16];
+        for &cpu in cpus.iter() {
+            if cpu >= 1024 { return false }
+            set[cpu / 64] |= 1 << (cpu % 64);
+        }
+        sched_setaffinity(0, mem::size_of_val(&set) as libc::size_t,
+                          set.as_ptr() as *const libc::c_void) == 0
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub unsafe fn pin_current(_cpus: &[uint]) -> bool { false }
+
     // glibc >= 2.15 has a __pthread_get_minstack() function that returns
     // PTHREAD_STACK_MIN plus however many bytes are needed for thread-local
     // storage.  We need that information to avoid blowing up when a small stack
@@ -322,6 +361,9 @@ mod imp {
                                        state: libc::c_int) -> libc::c_int;
         fn pthread_detach(thread: libc::pthread_t) -> libc::c_int;
         fn sched_yield() -> libc::c_int;
+        #[cfg(target_os = "linux")]
+        fn sched_setaffinity(pid: libc::pid_t, size: libc::size_t,
+                             mask: *const libc::c_void) -> libc::c_int;
     }
 }
 
@@ -338,6 +380,11 @@ mod tests {
     #[test]
     fn detached() { Thread::spawn(proc () {}) }
 
+    #[test]
+    fn pin_to_nothing() {
+        assert!(!Thread::start(proc () Thread::pin_current(&[])).join());
+    }
+
     #[test]
     fn small_stacks() {
         assert_eq!(42i, Thread::start_stack(0, proc () 42i).join());
@@ -22,6 +22,15 @@ pub unsafe fn create(key: &mut Key) {
     assert!(pthread_key_create(key, null()) == 0);
 }
 
+/// Creates a key whose `dtor` is called with the thread's value when a thread
+/// with a non-null value exits. Windows has no such destructors, so this is
+/// only available on unix.
+#[cfg(unix)]
+pub unsafe fn create_with_destructor(key: &mut Key,
+                                     dtor: unsafe extern "C" fn(*mut u8)) {
+    assert!(pthread_key_create(key, dtor as *const u8) == 0);
+}
+
 #[cfg(unix)]
 pub unsafe fn set(key: Key, value: *mut u8) {
     assert!(pthread_setspecific(key, value) == 0);
@@ -0,0 +1,99 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A monotonic clock for the runtime
+//!
+//! This is a minimal version of `time::precise_time_ns` which is available to
+//! the crates underneath libstd (such as libsync) for implementing timeouts
+//! and deadlines.
+
+#![allow(non_camel_case_types)]
+
+/// Returns the current value of a high-resolution monotonic counter in
+/// nanoseconds since an unspecified epoch.
+pub fn precise_time_ns() -> u64 {
+    imp::precise_time_ns()
+}
+
+/// Returns the current value of the monotonic counter in milliseconds, which
+/// is the unit most of the runtime's timeouts are specified in.
+pub fn precise_time_ms() -> u64 {
+    precise_time_ns() / 1000000
+}
+
+#[cfg(windows)]
+mod imp {
+    use libc;
+
+    pub fn precise_time_ns() -> u64 {
+        let mut ticks_per_s = 0;
+        assert_eq!(unsafe {
+            libc::QueryPerformanceFrequency(&mut ticks_per_s)
+        }, 1);
+        let ticks_per_s = if ticks_per_s == 0 {1} else {ticks_per_s};
+        let mut ticks = 0;
+        assert_eq!(unsafe {
+            libc::QueryPerformanceCounter(&mut ticks)
+        }, 1);
+
+        (ticks as u64 * 1000000000) / (ticks_per_s as u64)
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[cfg(target_os = "ios")]
+mod imp {
+    use libc::{c_int, mach_timebase_info};
+
+    extern {
+        fn mach_absolute_time() -> u64;
+        fn mach_timebase_info(info: *mut mach_timebase_info) -> c_int;
+    }
+
+    pub fn precise_time_ns() -> u64 {
+        let mut info = mach_timebase_info { numer: 0, denom: 0 };
+        unsafe {
+            mach_timebase_info(&mut info);
+            mach_absolute_time() * info.numer as u64 / info.denom as u64
+        }
+    }
+}
+
+#[cfg(unix, not(target_os = "macos"), not(target_os = "ios"))]
+mod imp {
+    use libc::{c_int, timespec, CLOCK_MONOTONIC};
+
+    // Apparently android provides this in some other library?
+    #[cfg(not(target_os = "android"))]
+    #[link(name = "rt")]
+    extern {}
+
+    extern {
+        fn clock_gettime(clk_id: c_int, tp: *mut timespec) -> c_int;
+    }
+
+    pub fn precise_time_ns() -> u64 {
+        let mut ts = timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { clock_gettime(CLOCK_MONOTONIC, &mut ts); }
+        (ts.tv_sec as u64) * 1000000000 + (ts.tv_nsec as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::precise_time_ns;
+
+    #[test]
+    fn monotonic() {
+        let a = precise_time_ns();
+        let b = precise_time_ns();
+        assert!(b >= a);
+    }
+}
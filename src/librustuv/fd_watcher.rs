@@ -0,0 +1,151 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use libc::c_int;
+use libc;
+use std::os;
+use std::rt::rtio::{RtioFdWatcher, FdCallback, IoResult};
+
+use homing::{HomingIO, HomeHandle};
+use super::{UvError, UvHandle, Loop, uv_error_to_io_error};
+use uvll;
+use uvio::UvIoFactory;
+
+pub struct FdWatcher {
+    handle: *mut uvll::uv_poll_t,
+    home: HomeHandle,
+    events: c_int,
+    // A duplicate descriptor which is closed along with the watcher
+    dup: Option<c_int>,
+
+    cb: Box<FdCallback + Send>,
+}
+
+impl FdWatcher {
+    pub fn new(io: &mut UvIoFactory, fd: c_int, readable: bool, writable: bool,
+               cb: Box<FdCallback + Send>) -> Result<Box<FdWatcher>, UvError> {
+        let handle = io.make_handle();
+        FdWatcher::new_home(&io.loop_, handle, fd, None, readable, writable, cb)
+    }
+
+    /// Watches the readability of one of the streams of libuv, which must be
+    /// called on the stream's home.
+    ///
+    /// The descriptor of the stream is already watched by the event loop, and
+    /// a loop can't watch a descriptor twice, so a duplicate of it is watched
+    /// instead. Streams on Windows aren't backed by descriptors.
+    pub fn readable_stream<T>(stream: *mut T, home: HomeHandle,
+                              cb: Box<FdCallback + Send>)
+                              -> Result<Box<FdWatcher>, UvError> {
+        let fd = unsafe { uvll::stream_fd(stream) };
+        if fd < 0 { return Err(UvError(uvll::ENOSYS)) }
+        let dup = unsafe { libc::dup(fd) };
+        if dup < 0 { return Err(UvError(-os::errno() as c_int)) }
+        let loop_ = Loop::wrap(unsafe { uvll::get_loop_for_uv_handle(stream) });
+        FdWatcher::new_home(&loop_, home, dup, Some(dup), true, false, cb)
+    }
+
+    fn new_home(loop_: &Loop, home: HomeHandle, fd: c_int, dup: Option<c_int>,
+                readable: bool, writable: bool,
+                cb: Box<FdCallback + Send>) -> Result<Box<FdWatcher>, UvError> {
+        let mut events = 0;
+        if readable { events |= uvll::UV_READABLE as c_int }
+        if writable { events |= uvll::UV_WRITABLE as c_int }
+        let handle = UvHandle::alloc(None::<FdWatcher>, uvll::UV_POLL);
+        match unsafe { poll_init(loop_, handle, fd) } {
+            0 => {}
+            n => {
+                unsafe {
+                    uvll::free_handle(handle);
+                    match dup { Some(fd) => { libc::close(fd); } None => {} }
+                }
+                return Err(UvError(n))
+            }
+        }
+
+        let w = box FdWatcher {
+            handle: handle,
+            home: home,
+            events: events,
+            dup: dup,
+            cb: cb,
+        };
+        let mut w = w.install();
+        match w.start() {
+            0 => Ok(w),
+            n => Err(UvError(n)),
+        }
+    }
+
+    fn start(&mut self) -> c_int {
+        unsafe { uvll::uv_poll_start(self.handle, self.events, poll_cb) }
+    }
+}
+
+// On Windows libuv can only poll sockets, which it does through its completion
+// port, and the descriptor given is taken to be a socket.
+#[cfg(unix)]
+unsafe fn poll_init(loop_: &Loop, handle: *mut uvll::uv_poll_t, fd: c_int) -> c_int {
+    uvll::uv_poll_init(loop_.handle, handle, fd)
+}
+#[cfg(windows)]
+unsafe fn poll_init(loop_: &Loop, handle: *mut uvll::uv_poll_t, fd: c_int) -> c_int {
+    uvll::uv_poll_init_socket(loop_.handle, handle, fd as uvll::uv_os_socket_t)
+}
+
+/// Implements `watch_readable` for the stream `stream` of an I/O object homed
+/// at `home`. This must be called on the home of the object.
+pub fn watch_readable<T>(stream: *mut T, home: &HomeHandle,
+                         cb: Box<FdCallback + Send>)
+                         -> IoResult<Box<RtioFdWatcher + Send>> {
+    match FdWatcher::readable_stream(stream, home.clone(), cb) {
+        Ok(w) => Ok(w as Box<RtioFdWatcher + Send>),
+        Err(e) => Err(uv_error_to_io_error(e)),
+    }
+}
+
+// Unless the callback asks to keep watching, the watch is stopped and it's only
+// started again by `rearm`. An error is reported as the descriptor being ready
+// for everything, so that the next operation on it returns the error.
+extern fn poll_cb(handle: *mut uvll::uv_poll_t, status: c_int, events: c_int) {
+    let w: &mut FdWatcher = unsafe { UvHandle::from_uv_handle(&handle) };
+    let error = status < 0;
+    let keep = w.cb.call(error || events & (uvll::UV_READABLE as c_int) != 0,
+                         error || events & (uvll::UV_WRITABLE as c_int) != 0);
+    if !keep {
+        assert_eq!(unsafe { uvll::uv_poll_stop(handle) }, 0);
+    }
+}
+
+impl HomingIO for FdWatcher {
+    fn home<'r>(&'r mut self) -> &'r mut HomeHandle { &mut self.home }
+}
+
+impl UvHandle<uvll::uv_poll_t> for FdWatcher {
+    fn uv_handle(&self) -> *mut uvll::uv_poll_t { self.handle }
+}
+
+impl RtioFdWatcher for FdWatcher {
+    fn rearm(&mut self) {
+        let _m = self.fire_homing_missile();
+        assert_eq!(self.start(), 0);
+    }
+}
+
+impl Drop for FdWatcher {
+    fn drop(&mut self) {
+        let _m = self.fire_homing_missile();
+        self.close();
+        match self.dup {
+            Some(fd) => unsafe { libc::close(fd); },
+            None => {}
+        }
+    }
+}
@@ -66,6 +66,7 @@ use std::rt::task::{BlockedTask, Task};
 use std::task;
 
 pub use self::async::AsyncWatcher;
+pub use self::fd_watcher::FdWatcher;
 pub use self::file::{FsRequest, FileWatcher};
 pub use self::idle::IdleWatcher;
 pub use self::net::{TcpWatcher, TcpListener, TcpAcceptor, UdpWatcher};
@@ -107,6 +108,7 @@ pub mod pipe;
 pub mod tty;
 pub mod signal;
 pub mod stream;
+pub mod fd_watcher;
 
 /// Creates a new event loop which is powered by libuv
 ///
@@ -16,6 +16,7 @@ use std::rt::rtio;
 use std::rt::rtio::IoError;
 use std::rt::task::BlockedTask;
 
+use fd_watcher;
 use homing::{HomingIO, HomeHandle};
 use rc::Refcount;
 use stream::StreamWatcher;
@@ -241,6 +242,13 @@ impl rtio::RtioTcpStream for TcpWatcher {
         self.stream.write(buf, guard.can_timeout).map_err(uv_error_to_io_error)
     }
 
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<(), IoError> {
+        let m = self.fire_homing_missile();
+        let guard = try!(self.write_access.grant(m));
+        self.stream.write_vectored(bufs, guard.can_timeout)
+                   .map_err(uv_error_to_io_error)
+    }
+
     fn peer_name(&mut self) -> Result<rtio::SocketAddr, IoError> {
         let _m = self.fire_homing_missile();
         socket_name(TcpPeer, self.handle)
@@ -330,6 +338,12 @@ impl rtio::RtioTcpStream for TcpWatcher {
             stream.cancel_write()
         }
     }
+
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> Result<Box<rtio::RtioFdWatcher + Send>, IoError> {
+        let _m = self.fire_homing_missile();
+        fd_watcher::watch_readable(self.stream.handle, &self.home, cb)
+    }
 }
 
 impl UvHandle<uvll::uv_tcp_t> for TcpWatcher {
@@ -469,6 +483,12 @@ impl rtio::RtioTcpAcceptor for TcpAcceptor {
             Some(ms) => self.timeout.set_timeout(ms, &mut *self.listener),
         }
     }
+
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> Result<Box<rtio::RtioFdWatcher + Send>, IoError> {
+        let _m = self.fire_homing_missile();
+        fd_watcher::watch_readable(self.listener.handle, &self.listener.home, cb)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
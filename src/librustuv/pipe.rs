@@ -15,6 +15,7 @@ use std::rt::rtio;
 use std::rt::rtio::IoResult;
 use std::rt::task::BlockedTask;
 
+use fd_watcher;
 use homing::{HomingIO, HomeHandle};
 use net;
 use rc::Refcount;
@@ -200,6 +201,12 @@ impl rtio::RtioPipe for PipeWatcher {
             stream.cancel_write()
         }
     }
+
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        let _m = self.fire_homing_missile();
+        fd_watcher::watch_readable(self.stream.handle, &self.home, cb)
+    }
 }
 
 impl HomingIO for PipeWatcher {
@@ -311,6 +318,12 @@ impl rtio::RtioUnixAcceptor for PipeAcceptor {
             Some(ms) => self.timeout.set_timeout(ms, &mut *self.listener),
         }
     }
+
+    fn watch_readable(&mut self, cb: Box<rtio::FdCallback + Send>)
+                      -> IoResult<Box<rtio::RtioFdWatcher + Send>> {
+        let _m = self.fire_homing_missile();
+        fd_watcher::watch_readable(self.listener.pipe, &self.listener.home, cb)
+    }
 }
 
 impl HomingIO for PipeAcceptor {
@@ -126,6 +126,12 @@ impl StreamWatcher {
     }
 
     pub fn write(&mut self, buf: &[u8], may_timeout: bool) -> Result<(), UvError> {
+        self.write_vectored(&[buf], may_timeout)
+    }
+
+    // Writes all of `bufs` with one write request
+    pub fn write_vectored(&mut self, bufs: &[&[u8]],
+                          may_timeout: bool) -> Result<(), UvError> {
         // The ownership of the write request is dubious if this function
         // unwinds. I believe that if the write_cb fails to re-schedule the task
         // then the write request will be leaked.
@@ -159,18 +165,23 @@ impl StreamWatcher {
         //
         // To do this, the write context has an optionally owned vector of
         // bytes.
-        let data = if may_timeout {Some(Vec::from_slice(buf))} else {None};
-        let uv_buf = if may_timeout {
-            slice_to_uv_buf(data.get_ref().as_slice())
+        let data = if may_timeout {
+            let mut data = Vec::new();
+            for buf in bufs.iter() { data.push_all(*buf) }
+            Some(data)
         } else {
-            slice_to_uv_buf(buf)
+            None
+        };
+        let uv_bufs: Vec<Buf> = match data {
+            Some(ref data) => vec![slice_to_uv_buf(data.as_slice())],
+            None => bufs.iter().map(|b| slice_to_uv_buf(*b)).collect(),
         };
 
         // Send off the request, but be careful to not block until we're sure
         // that the write request is queued. If the request couldn't be queued,
         // then we should return immediately with an error.
         match unsafe {
-            uvll::uv_write(req.handle, self.handle, [uv_buf], write_cb)
+            uvll::uv_write(req.handle, self.handle, uv_bufs.as_slice(), write_cb)
         } {
             0 => {
                 let mut wcx = WriteContext {
@@ -126,6 +126,16 @@ impl RtioTimer for TimerWatcher {
             mem::replace(&mut self.action, Some(CallMany(cb, self.id)))
         };
     }
+
+    fn cancel(&mut self) {
+        // as in `oneshot`, the previous action is dropped once un-homed
+        let _prev_action = {
+            let _m = self.fire_homing_missile();
+            self.id += 1;
+            self.stop();
+            mem::replace(&mut self.action, None)
+        };
+    }
 }
 
 extern fn timer_cb(handle: *mut uvll::uv_timer_t) {
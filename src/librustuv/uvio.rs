@@ -25,6 +25,7 @@ use super::{uv_error_to_io_error, Loop};
 
 use addrinfo::GetAddrInfoRequest;
 use async::AsyncWatcher;
+use fd_watcher::FdWatcher;
 use file::{FsRequest, FileWatcher};
 use queue::QueuePool;
 use homing::HomeHandle;
@@ -323,4 +324,14 @@ impl IoFactory for UvIoFactory {
             Err(e) => Err(uv_error_to_io_error(e)),
         }
     }
+
+    fn fd_watch(&mut self, fd: c_int, readable: bool, writable: bool,
+                cb: Box<rtio::FdCallback + Send>)
+        -> IoResult<Box<rtio::RtioFdWatcher + Send>>
+    {
+        match FdWatcher::new(self, fd, readable, writable, cb) {
+            Ok(w) => Ok(w as Box<rtio::RtioFdWatcher + Send>),
+            Err(e) => Err(uv_error_to_io_error(e)),
+        }
+    }
 }
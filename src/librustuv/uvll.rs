@@ -39,7 +39,7 @@ use libc::uintptr_t;
 
 pub use self::errors::{EACCES, ECONNREFUSED, ECONNRESET, EPIPE, ECONNABORTED,
                        ECANCELED, EBADF, ENOTCONN, ENOENT, EADDRNOTAVAIL,
-                       EADDRINUSE, EPERM};
+                       EADDRINUSE, EPERM, ENOSYS};
 
 pub static OK: c_int = 0;
 pub static EOF: c_int = -4095;
@@ -64,6 +64,7 @@ pub mod errors {
     pub static EADDRNOTAVAIL: c_int = -4090;
     pub static EADDRINUSE: c_int = -4091;
     pub static EPERM: c_int = -4048;
+    pub static ENOSYS: c_int = -4054;
 }
 #[cfg(not(windows))]
 pub mod errors {
@@ -82,6 +83,7 @@ pub mod errors {
     pub static EADDRNOTAVAIL : c_int = -libc::EADDRNOTAVAIL;
     pub static EADDRINUSE : c_int = -libc::EADDRINUSE;
     pub static EPERM: c_int = -libc::EPERM;
+    pub static ENOSYS: c_int = -libc::ENOSYS;
 }
 
 pub static PROCESS_SETUID: c_int = 1 << 0;
@@ -494,6 +496,9 @@ pub unsafe fn populate_stat(req_in: *mut uv_fs_t, stat_out: *mut uv_stat_t) {
 pub unsafe fn guess_handle(handle: c_int) -> c_int {
     rust_uv_guess_handle(handle)
 }
+pub unsafe fn stream_fd<T>(handle: *mut T) -> c_int {
+    rust_uv_stream_fd(handle as *mut uv_stream_t)
+}
 
 
 // uv_support is the result of compiling rust_uv.cpp
@@ -541,6 +546,7 @@ extern {
                                        stream: *mut uv_stream_t);
     fn rust_uv_process_pid(p: *mut uv_process_t) -> c_int;
     fn rust_uv_guess_handle(fd: c_int) -> c_int;
+    fn rust_uv_stream_fd(handle: *mut uv_stream_t) -> c_int;
 
     // generic uv functions
     pub fn uv_loop_delete(l: *mut uv_loop_t);
@@ -668,6 +674,7 @@ extern {
                        file: *const c_char, cb: uv_fs_cb) -> c_int;
 
     // poll bindings
+    pub fn uv_poll_init(l: *mut uv_loop_t, h: *mut uv_poll_t, fd: c_int) -> c_int;
     pub fn uv_poll_init_socket(l: *mut uv_loop_t, h: *mut uv_poll_t, s: uv_os_socket_t) -> c_int;
     pub fn uv_poll_start(h: *mut uv_poll_t, events: c_int, cb: uv_poll_cb) -> c_int;
     pub fn uv_poll_stop(h: *mut uv_poll_t) -> c_int;
@@ -42,3 +42,4 @@ pub mod base64;
 pub mod ebml;
 pub mod hex;
 pub mod json;
+pub mod record;
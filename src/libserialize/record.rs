@@ -0,0 +1,337 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recording and replaying the messages received on channels
+//!
+//! A `Recorder` taps the receivers it's given (see `Receiver::tap_with`) and
+//! logs every message they receive, encoded as JSON, along with the name of
+//! its channel and its position among all of the messages recorded. A
+//! `Replay` reads such a log back and hands out receivers which get the same
+//! messages in the same order, so a consumer which misbehaves on some
+//! sequence of messages can be run again on exactly that sequence, without
+//! the tasks which produced it.
+//!
+//! Messages are recorded as they're received, so the log holds the order in
+//! which the consumer saw them. Replayed channels are unbuffered, and each
+//! message is sent only once the one before it (on any channel) has been
+//! received, so a consumer which takes the messages of several channels is
+//! given them in the recorded order too.
+//!
+//! The log has one message per line, made of four fields separated by tabs:
+//! the sequence number of the message, the name of its channel, the time it
+//! was received at (in nanoseconds since the recorder was created), and the
+//! message itself.
+//!
+//! # Example
+//!
+//! ```
+//! use serialize::record::{Recorder, Replay};
+//! use std::io::{MemWriter, BufReader};
+//!
+//! let (tx, rx) = channel::<int>();
+//! let recorder = Recorder::new(box MemWriter::new());
+//! let rx = recorder.record("numbers", rx);
+//! tx.send(1);
+//! tx.send(2);
+//! assert_eq!(rx.recv(), 1);
+//! assert_eq!(rx.recv(), 2);
+//!
+//! let log = "0\tnumbers\t10\t1\n1\tnumbers\t20\t2\n";
+//! let mut replay = Replay::new(&mut BufReader::new(log.as_bytes())).unwrap();
+//! let rx = replay.channel::<int>("numbers").unwrap();
+//! spawn(proc() replay.run());
+//! assert_eq!(rx.iter().collect::<Vec<int>>(), vec![1, 2]);
+//! ```
+
+use std::collections::HashMap;
+use std::comm::Tap;
+use std::io;
+use std::io::{IoError, IoResult, File, BufferedReader, BufferedWriter};
+use std::rt::time;
+use std::string::String;
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+
+use {Encodable, Decodable};
+use json;
+
+/// Logs the messages received on channels.
+///
+/// The log is shared by all of the receivers being recorded, and is closed
+/// once the recorder and all of the receivers have been dropped.
+pub struct Recorder {
+    log: Arc<Mutex<Log>>,
+}
+
+/// Sends recorded messages to receivers in the order they were recorded.
+pub struct Replay {
+    records: Vec<Record>,
+    feeds: HashMap<String, Box<Feed + Send>>,
+}
+
+/// A message in a log.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Record {
+    /// The position of the message among all of the messages recorded.
+    pub seq: u64,
+    /// The name of the channel it was received on.
+    pub channel: String,
+    /// When it was received, in nanoseconds since the recorder was created.
+    pub time: u64,
+    /// The message, as JSON.
+    pub message: String,
+}
+
+struct Log {
+    out: Box<Writer + Send>,
+    next: u64,
+    start: u64,
+    error: Option<IoError>,
+}
+
+struct Recording<T> {
+    log: Arc<Mutex<Log>>,
+    name: String,
+    encode: fn(&T) -> String,
+}
+
+// A replayed channel, with its messages in reverse order
+struct Replayed<T> {
+    tx: SyncSender<T>,
+    messages: Vec<T>,
+}
+
+trait Feed {
+    // Sends the next message, returning whether it was received
+    fn feed(&mut self) -> bool;
+}
+
+impl Recorder {
+    /// Creates a recorder which writes its log to `out`.
+    pub fn new(out: Box<Writer + Send>) -> Recorder {
+        Recorder {
+            log: Arc::new(Mutex::new(Log {
+                out: out,
+                next: 0,
+                start: time::precise_time_ns(),
+                error: None,
+            })),
+        }
+    }
+
+    /// Creates a recorder which writes its log to the file at `path`,
+    /// replacing the file if it exists.
+    pub fn create(path: &Path) -> IoResult<Recorder> {
+        let file = try!(File::create(path));
+        Ok(Recorder::new(box BufferedWriter::new(file)))
+    }
+
+    /// Records the messages received on `rx` from now on, under `name`,
+    /// returning the receiver back.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `name` contains a tab or a newline.
+    pub fn record<'a, T: Send + Encodable<json::Encoder<'a>, IoError>>(
+            &self, name: &str, rx: Receiver<T>) -> Receiver<T> {
+        assert!(!name.contains_char('\t') && !name.contains_char('\n'),
+                "channel names can't contain tabs or newlines: `{}`", name);
+        rx.tap_with(box Recording {
+            log: self.log.clone(),
+            name: name.to_string(),
+            encode: json::encode::<T>,
+        } as Box<Tap<T> + Send>)
+    }
+
+    /// Writes out what has been recorded so far.
+    pub fn flush(&self) -> IoResult<()> {
+        let mut log = self.log.lock();
+        log.out.flush()
+    }
+
+    /// Returns the first error met while writing the log, if there was one.
+    /// Nothing is recorded after an error.
+    pub fn error(&self) -> Option<IoError> {
+        self.log.lock().error.clone()
+    }
+}
+
+impl Log {
+    fn write(&mut self, name: &str, message: &str) {
+        if self.error.is_some() { return }
+        let seq = self.next;
+        self.next += 1;
+        let time = time::precise_time_ns() - self.start;
+        match writeln!(self.out, "{}\t{}\t{}\t{}", seq, name, time, message) {
+            Ok(()) => {}
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+impl<T: Send> Tap<T> for Recording<T> {
+    fn tap(&self, t: &T) {
+        let message = (self.encode)(t);
+        let mut log = self.log.lock();
+        log.write(self.name.as_slice(), message.as_slice());
+    }
+}
+
+impl Replay {
+    /// Reads a log.
+    pub fn new<B: Buffer>(reader: &mut B) -> IoResult<Replay> {
+        let mut records = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = try!(line);
+            let line = line.as_slice().trim_right_chars('\n');
+            match parse(line) {
+                Some(record) => records.push(record),
+                None => return Err(IoError {
+                    kind: io::InvalidInput,
+                    desc: "malformed recording",
+                    detail: Some(format!("line {}: `{}`", i + 1, line)),
+                }),
+            }
+        }
+        Ok(Replay { records: records, feeds: HashMap::new() })
+    }
+
+    /// Reads the log in the file at `path`.
+    pub fn open(path: &Path) -> IoResult<Replay> {
+        let file = try!(File::open(path));
+        Replay::new(&mut BufferedReader::new(file))
+    }
+
+    /// The messages in the log, in the order they were recorded.
+    pub fn records<'a>(&'a self) -> &'a [Record] {
+        self.records.as_slice()
+    }
+
+    /// Returns a receiver which will be given the messages recorded under
+    /// `name` once the replay is run. The messages are all decoded now, and
+    /// the first one which can't be is returned as an error. A channel which
+    /// was never recorded has no messages.
+    pub fn channel<T: Send + Decodable<json::Decoder, json::DecoderError>>(
+            &mut self, name: &str) -> json::DecodeResult<Receiver<T>> {
+        let mut messages = Vec::new();
+        for r in self.records.iter().filter(|r| r.channel.as_slice() == name) {
+            messages.push(try!(json::decode::<T>(r.message.as_slice())));
+        }
+        messages.reverse();
+        let (tx, rx) = sync_channel(0);
+        self.feeds.insert(name.to_string(), box Replayed {
+            tx: tx,
+            messages: messages,
+        } as Box<Feed + Send>);
+        Ok(rx)
+    }
+
+    /// Sends the messages of the channels handed out by `channel` in the
+    /// order they were recorded, returning once they have all been received.
+    /// The channels are closed afterwards, as if the senders had hung up.
+    ///
+    /// Each message is sent once the one before it has been received, so
+    /// this blocks forever if the consumer waits for a message on another
+    /// channel than the one the next message is on. The messages of a
+    /// receiver which has been dropped are skipped.
+    pub fn run(mut self) {
+        for r in self.records.iter() {
+            let hung_up = match self.feeds.find_mut(&r.channel) {
+                Some(feed) => !feed.feed(),
+                None => false,
+            };
+            if hung_up { self.feeds.remove(&r.channel); }
+        }
+    }
+}
+
+impl<T: Send> Feed for Replayed<T> {
+    fn feed(&mut self) -> bool {
+        match self.messages.pop() {
+            Some(t) => self.tx.send_opt(t).is_ok(),
+            None => true,
+        }
+    }
+}
+
+fn parse(line: &str) -> Option<Record> {
+    let fields: Vec<&str> = line.splitn('\t', 3).collect();
+    if fields.len() != 4 { return None }
+    match (from_str(*fields.get(0)), from_str(*fields.get(2))) {
+        (Some(seq), Some(time)) => Some(Record {
+            seq: seq,
+            channel: fields.get(1).to_string(),
+            time: time,
+            message: fields.get(3).to_string(),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Writer, IoResult};
+    use std::sync::{Arc, Mutex};
+
+    use super::{Recorder, Replay};
+
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Writer for Shared {
+        fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+            let Shared(ref bytes) = *self;
+            let mut bytes = bytes.lock();
+            bytes.push_all(buf);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let bytes = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder::new(box Shared(bytes.clone()));
+        let (tx1, rx1) = channel::<int>();
+        let (tx2, rx2) = channel::<String>();
+        let rx1 = recorder.record("ints", rx1);
+        let rx2 = recorder.record("strings", rx2);
+        tx1.send(1);
+        tx2.send("two".to_string());
+        tx1.send(3);
+        assert_eq!(rx1.recv(), 1);
+        assert_eq!(rx2.recv(), "two".to_string());
+        assert_eq!(rx1.recv(), 3);
+        assert!(recorder.error().is_none());
+
+        let log = bytes.lock().clone();
+        let mut replay = Replay::new(&mut BufReader::new(log.as_slice())).unwrap();
+        let seqs: Vec<u64> = replay.records().iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, vec![0, 1, 2]);
+        assert_eq!(replay.records()[1].message, "\"two\"".to_string());
+
+        let rx1 = replay.channel::<int>("ints").unwrap();
+        let rx2 = replay.channel::<String>("strings").unwrap();
+        spawn(proc() replay.run());
+        assert_eq!(rx1.recv(), 1);
+        assert_eq!(rx2.recv(), "two".to_string());
+        assert_eq!(rx1.recv(), 3);
+        assert!(rx1.recv_opt().is_err());
+        assert!(rx2.recv_opt().is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let log = "0\tints\t5\n";
+        assert!(Replay::new(&mut BufReader::new(log.as_bytes())).is_err());
+
+        let log = "0\tints\t5\t\"one\"\n";
+        let mut replay = Replay::new(&mut BufReader::new(log.as_bytes())).unwrap();
+        assert!(replay.channel::<int>("ints").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Channels for C code
+//!
+//! This module exports a C interface to channels of byte messages, so that an
+//! application embedding Rust can talk to Rust tasks from its own threads. The
+//! C declarations are in `src/rt/rust_comm.h`, which is generated from this
+//! file by `src/etc/mkcommheader.py`.
+//!
+//! On the Rust side, a `Sender<Vec<u8>>` or a `Receiver<Vec<u8>>` is handed to
+//! C with `chan_to_c` or `port_to_c`, and C code can also create channels of
+//! its own with `rust_chan_new`. The threads calling these functions don't need
+//! to be running a task: a thread without one blocks on a condition variable.
+//!
+//! Like a `Sender`, a `rust_chan` must only be used by one thread at a time,
+//! cloning one included. Each thread which sends should be given its own clone
+//! of the handle with `rust_chan_clone` (by the thread the original belongs
+//! to), or else the handle must be guarded with a mutex.
+//!
+//! None of the functions of the C interface fail. They return an error code
+//! instead when the other end of the channel is gone.
+//!
+//! # Example
+//!
+//! ```c
+//! rust_chan *chan;
+//! rust_port *port;
+//! rust_chan_new(&chan, &port);
+//! rust_chan_send(chan, (const uint8_t *) "hi", 2);
+//!
+//! uint8_t *data;
+//! size_t len;
+//! if (rust_port_recv(port, &data, &len) == 0) {
+//!     /* ... */
+//!     free(data);
+//! }
+//! rust_chan_free(chan);
+//! rust_port_free(port);
+//! ```
+
+#![experimental]
+
+use alloc::libc_heap::malloc_raw;
+use boxed::Box;
+use clone::Clone;
+use collections::Collection;
+use comm::{channel, Sender, Receiver, Empty, Disconnected};
+use libc::{c_int, size_t};
+use mem;
+use ptr;
+use result::{Ok, Err};
+use slice;
+use slice::{ImmutableVector, CloneableVector};
+use vec::Vec;
+
+/// The sending half of a channel, as seen from C (`rust_chan`).
+///
+/// Like a `Sender`, a chan must only be used by one thread at a time.
+pub struct Chan {
+    tx: Sender<Vec<u8>>,
+}
+
+/// The receiving half of a channel, as seen from C (`rust_port`).
+///
+/// Like a `Receiver`, a port must only be used by one thread at a time.
+pub struct Port {
+    rx: Receiver<Vec<u8>>,
+}
+
+/// Hands `tx` over to C. The pointer returned must be freed with
+/// `rust_chan_free`.
+pub fn chan_to_c(tx: Sender<Vec<u8>>) -> *mut Chan {
+    unsafe { mem::transmute(box Chan { tx: tx }) }
+}
+
+/// Hands `rx` over to C. The pointer returned must be freed with
+/// `rust_port_free`.
+pub fn port_to_c(rx: Receiver<Vec<u8>>) -> *mut Port {
+    unsafe { mem::transmute(box Port { rx: rx }) }
+}
+
+/// Creates a channel, storing its two halves in `*chan` and `*port`.
+#[no_mangle]
+pub unsafe extern "C" fn rust_chan_new(chan: *mut *mut Chan,
+                                       port: *mut *mut Port) {
+    let (tx, rx) = channel();
+    *chan = chan_to_c(tx);
+    *port = port_to_c(rx);
+}
+
+/// Returns another sending half for the channel of `chan`.
+///
+/// A `rust_chan` must only be used by one thread at a time, so each thread
+/// which sends needs a clone of its own. The clone must be made by the thread
+/// which is using `chan`, and then handed to the new thread.
+#[no_mangle]
+pub unsafe extern "C" fn rust_chan_clone(chan: *const Chan) -> *mut Chan {
+    chan_to_c((*chan).tx.clone())
+}
+
+/// Sends a copy of the `len` bytes at `data`. Returns 0 on success, and -1 if
+/// the port of the channel has been freed.
+///
+/// `chan` must not be used by another thread at the same time, clone it with
+/// `rust_chan_clone` for each thread instead, or guard it with a mutex.
+#[no_mangle]
+pub unsafe extern "C" fn rust_chan_send(chan: *const Chan,
+                                        data: *const u8,
+                                        len: size_t) -> c_int {
+    let msg = if len == 0 {
+        Vec::new()
+    } else {
+        slice::raw::buf_as_slice(data, len as uint, |s| s.to_vec())
+    };
+    match (*chan).tx.send_opt(msg) {
+        Ok(()) => 0,
+        Err(..) => -1,
+    }
+}
+
+/// Frees a sending half of a channel.
+#[no_mangle]
+pub unsafe extern "C" fn rust_chan_free(chan: *mut Chan) {
+    let _: Box<Chan> = mem::transmute(chan);
+}
+
+/// Blocks until a message is received, storing it in `*data` and its length
+/// in `*len`. The message is allocated with `malloc`, and must be released
+/// with `free`. It is null if it's empty.
+///
+/// Returns 0 once a message is received, and -1 if all of the sending halves
+/// of the channel have been freed and no message is left.
+#[no_mangle]
+pub unsafe extern "C" fn rust_port_recv(port: *const Port,
+                                        data: *mut *mut u8,
+                                        len: *mut size_t) -> c_int {
+    match (*port).rx.recv_opt() {
+        Ok(msg) => { give(msg, data, len); 0 }
+        Err(()) => -1,
+    }
+}
+
+/// Receives a message if one is available, like `rust_port_recv`. Returns 0
+/// if a message was received, 1 if there was none, and -1 if all of the
+/// sending halves of the channel have been freed and no message is left.
+#[no_mangle]
+pub unsafe extern "C" fn rust_port_try_recv(port: *const Port,
+                                            data: *mut *mut u8,
+                                            len: *mut size_t) -> c_int {
+    match (*port).rx.try_recv() {
+        Ok(msg) => { give(msg, data, len); 0 }
+        Err(Empty) => 1,
+        Err(Disconnected) => -1,
+    }
+}
+
+/// Frees the receiving half of a channel. The messages it hadn't received are
+/// dropped.
+#[no_mangle]
+pub unsafe extern "C" fn rust_port_free(port: *mut Port) {
+    let _: Box<Port> = mem::transmute(port);
+}
+
+// Copies a message to a buffer which C code can free
+unsafe fn give(msg: Vec<u8>, data: *mut *mut u8, len: *mut size_t) {
+    let buf = malloc_raw(msg.len());
+    if msg.len() > 0 {
+        ptr::copy_nonoverlapping_memory(buf, msg.as_ptr(), msg.len());
+    }
+    *data = buf;
+    *len = msg.len() as size_t;
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use super::*;
+    use libc;
+    use ptr;
+    use slice;
+
+    unsafe fn recv(port: *const Port) -> Option<Vec<u8>> {
+        let mut data = ptr::mut_null();
+        let mut len = 0;
+        match rust_port_recv(port, &mut data, &mut len) {
+            0 => {
+                let v = if len == 0 {
+                    Vec::new()
+                } else {
+                    slice::raw::buf_as_slice(data as *const u8, len as uint,
+                                             |s| s.to_vec())
+                };
+                libc::free(data as *mut libc::c_void);
+                Some(v)
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        unsafe {
+            let mut chan = ptr::mut_null();
+            let mut port = ptr::mut_null();
+            rust_chan_new(&mut chan, &mut port);
+            assert_eq!(rust_chan_send(chan as *const Chan, b"hi".as_ptr(), 2), 0);
+            assert_eq!(rust_chan_send(chan as *const Chan, ptr::null(), 0), 0);
+            assert_eq!(recv(port as *const Port), Some(b"hi".to_vec()));
+            assert_eq!(recv(port as *const Port), Some(Vec::new()));
+
+            let mut data = ptr::mut_null();
+            let mut len = 0;
+            assert_eq!(rust_port_try_recv(port as *const Port, &mut data, &mut len), 1);
+            rust_chan_free(chan);
+            assert_eq!(rust_port_try_recv(port as *const Port, &mut data, &mut len), -1);
+            assert_eq!(recv(port as *const Port), None);
+            rust_port_free(port);
+        }
+    }
+
+    #[test]
+    fn with_rust_tasks() {
+        let (tx, rx) = channel();
+        let (tx2, rx2) = channel();
+        let chan = chan_to_c(tx) as uint;
+        let port = port_to_c(rx2) as uint;
+        spawn(proc() {
+            unsafe {
+                let (chan, port) = (chan as *mut Chan, port as *mut Port);
+                let msg = recv(port as *const Port).unwrap();
+                let other = rust_chan_clone(chan as *const Chan);
+                rust_chan_send(other as *const Chan, msg.as_ptr(), msg.len() as libc::size_t);
+                rust_port_free(port);
+                rust_chan_free(other);
+                rust_chan_free(chan);
+            }
+        });
+        tx2.send(vec![1u8, 2, 3]);
+        assert_eq!(rx.recv(), vec![1u8, 2, 3]);
+        assert!(rx.recv_opt().is_err());
+        assert!(tx2.send_opt(vec![]).is_err());
+    }
+}
@@ -12,21 +12,35 @@
 
 use alloc::boxed::Box;
 use any::{Any, AnyRefExt};
+use clone::Clone;
+use collections::MutableSeq;
+use comm::Sender;
 use fmt;
 use io::{Writer, IoResult};
 use kinds::Send;
 use option::{Some, None};
 use result::Ok;
 use rt::backtrace;
+use rt::backtrace::Backtrace;
 use rt::{Stderr, Stdio};
 use rustrt::local::Local;
 use rustrt::task::Task;
 use str::Str;
+use to_string::ToString;
 use string::String;
+use task::FailureInfo;
+use vec::Vec;
 
 // Defined in this module instead of io::stdio so that the unwinding
 local_data_key!(pub local_stderr: Box<Writer + Send>)
 
+// If set, a backtrace of the task's failure is captured and sent to each of
+// these, on top of being printed if RUST_BACKTRACE asks for it
+local_data_key!(backtrace_sinks: Vec<Sender<Backtrace>>)
+
+// The hooks registered with `task::on_fail`, run in order when the task fails
+local_data_key!(pub fail_hooks: Vec<proc(&FailureInfo):Send>)
+
 impl Writer for Stdio {
     fn write(&mut self, bytes: &[u8]) -> IoResult<()> {
         fn fmt_write<F: fmt::FormatWriter>(f: &mut F, bytes: &[u8]) {
@@ -37,14 +51,26 @@ impl Writer for Stdio {
     }
 }
 
-pub fn on_fail(obj: &Any + Send, file: &'static str, line: uint) {
-    let msg = match obj.as_ref::<&'static str>() {
+/// Has a backtrace of the current task's failure sent to `sink`, if it fails.
+pub fn add_backtrace_sink(sink: Sender<Backtrace>) {
+    let mut sinks = backtrace_sinks.replace(None).unwrap_or(Vec::new());
+    sinks.push(sink);
+    backtrace_sinks.replace(Some(sinks));
+}
+
+/// The message passed to `fail!`, if it was a string.
+pub fn message<'a>(obj: &'a Any + Send) -> &'a str {
+    match obj.as_ref::<&'static str>() {
         Some(s) => *s,
         None => match obj.as_ref::<String>() {
             Some(s) => s.as_slice(),
             None => "Box<Any>",
         }
-    };
+    }
+}
+
+pub fn on_fail(obj: &Any + Send, file: &'static str, line: uint) {
+    let msg = message(obj);
     let mut err = Stderr;
 
     // It is assumed that all reasonable rust code will have a local task at
@@ -71,6 +97,11 @@ pub fn on_fail(obj: &Any + Send, file: &'static str, line: uint) {
         let mut t = Local::borrow(None::<Task>);
         (t.name.take(), t.unwinder.unwinding())
     };
+    let (sinks, hooks) = if unwinding {
+        (None, None)
+    } else {
+        (backtrace_sinks.replace(None), fail_hooks.replace(None))
+    };
     {
         let n = name.as_ref().map(|n| n.as_slice()).unwrap_or("<unnamed>");
 
@@ -100,5 +131,36 @@ pub fn on_fail(obj: &Any + Send, file: &'static str, line: uint) {
             let _ = backtrace::write(&mut err);
         }
     }
+    let trace = if sinks.is_some() || hooks.is_some() {
+        Some(Backtrace::capture())
+    } else {
+        None
+    };
+    match (sinks, &trace) {
+        (Some(sinks), &Some(ref trace)) => {
+            for sink in sinks.move_iter() {
+                let _ = sink.send_opt(trace.clone());
+            }
+        }
+        _ => {}
+    }
+    let task_name = name.as_ref().map(|n| n.as_slice().to_string());
     Local::borrow(None::<Task>).name = name;
+
+    // The hooks run last, once the task is back as it was before failing
+    match hooks {
+        Some(hooks) => {
+            let info = FailureInfo {
+                name: task_name,
+                message: msg.to_string(),
+                file: file,
+                line: line,
+                backtrace: trace.map(|t| t.to_string()).unwrap_or(String::new()),
+            };
+            for hook in hooks.move_iter() {
+                hook(&info);
+            }
+        }
+        None => {}
+    }
 }
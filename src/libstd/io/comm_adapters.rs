@@ -13,11 +13,14 @@ use cmp;
 use collections::Collection;
 use comm::{Sender, Receiver};
 use io;
+use io::extensions::{u64_to_be_bytes, u64_from_be_bytes};
+use mem;
 use option::{None, Option, Some};
 use result::{Ok, Err};
 use slice::{bytes, MutableVector, ImmutableVector};
 use str::StrSlice;
 use super::{Reader, Writer, IoResult};
+use u32;
 use vec::Vec;
 
 /// Allows reading from a rx.
@@ -37,11 +40,25 @@ use vec::Vec;
 ///     Err(e) => println!("read error: {}", e),
 /// }
 /// ```
+///
+/// # Messages
+///
+/// The bytes are read regardless of how they were sent, so the boundaries of
+/// the vectors received are lost. Protocols which need them can write each
+/// message with a length prefix, with `ChanWriter::write_message`, and read
+/// them back with `read_message`.
+///
+/// A framed reader, created with `ChanReader::framed`, reads such frames with
+/// `read` too: the length prefixes are skipped, and a read never returns the
+/// bytes of two messages at once. An empty message is then read as a read of
+/// 0 bytes.
 pub struct ChanReader {
     buf: Option<Vec<u8>>,  // A buffer of bytes received but not consumed.
     pos: uint,             // How many of the buffered bytes have already be consumed.
     rx: Receiver<Vec<u8>>, // The Receiver to pull data from.
     closed: bool,          // Whether the channel this Receiver connects to has been closed.
+    framed: bool,          // Whether `read` reads the payloads of frames.
+    remaining: uint,       // How many bytes of the current frame are left unread.
 }
 
 impl ChanReader {
@@ -52,12 +69,59 @@ impl ChanReader {
             pos: 0,
             rx: rx,
             closed: false,
+            framed: false,
+            remaining: 0,
         }
     }
-}
 
-impl Reader for ChanReader {
-    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+    /// Wraps a `Port` in a framed `ChanReader`, whose `read` returns the
+    /// messages written by `ChanWriter::write_message`.
+    pub fn framed(rx: Receiver<Vec<u8>>) -> ChanReader {
+        ChanReader { framed: true, ..ChanReader::new(rx) }
+    }
+
+    /// Reads a message written by `ChanWriter::write_message`.
+    ///
+    /// If part of the current message has already been read by a framed
+    /// reader, the rest of it is returned.
+    ///
+    /// # Error
+    ///
+    /// An `EndOfFile` error is returned if the channel is closed, including in
+    /// the middle of a message.
+    pub fn read_message(&mut self) -> IoResult<Vec<u8>> {
+        let len = if self.remaining > 0 {
+            mem::replace(&mut self.remaining, 0)
+        } else {
+            try!(self.read_len())
+        };
+        let mut msg = Vec::from_elem(len, 0u8);
+        try!(self.read_exact_bytes(msg.as_mut_slice()));
+        Ok(msg)
+    }
+
+    fn read_len(&mut self) -> IoResult<uint> {
+        let mut len = [0u8, ..4];
+        try!(self.read_exact_bytes(len));
+        Ok(u64_from_be_bytes(len, 0, 4) as uint)
+    }
+
+    fn read_exact_bytes(&mut self, buf: &mut [u8]) -> IoResult<()> {
+        let n = try!(self.read_bytes(buf));
+        if n == buf.len() {
+            Ok(())
+        } else {
+            Err(io::IoError {
+                kind: io::EndOfFile,
+                desc: "channel closed in the middle of a message",
+                detail: None
+            })
+        }
+    }
+
+    // Reads bytes regardless of frames, blocking until `buf` is full or the
+    // channel is closed.
+    fn read_bytes(&mut self, buf: &mut [u8]) -> IoResult<uint> {
         let mut num_read = 0;
         loop {
             match self.buf {
@@ -86,6 +150,19 @@ impl Reader for ChanReader {
     }
 }
 
+impl Reader for ChanReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        if !self.framed { return self.read_bytes(buf) }
+        if self.remaining == 0 {
+            self.remaining = try!(self.read_len());
+        }
+        let count = cmp::min(buf.len(), self.remaining);
+        try!(self.read_exact_bytes(buf.mut_slice_to(count)));
+        self.remaining -= count;
+        Ok(count)
+    }
+}
+
 /// Allows writing to a tx.
 ///
 /// # Example
@@ -99,26 +176,47 @@ impl Reader for ChanReader {
 /// let mut writer = ChanWriter::new(tx);
 /// writer.write("hello, world".as_bytes());
 /// ```
+///
+/// A framed writer, created with `ChanWriter::framed`, writes each buffer it's
+/// given with `write_message`, so that the messages which are written through
+/// the `Writer` trait can be told apart by a `ChanReader`.
 pub struct ChanWriter {
     tx: Sender<Vec<u8>>,
+    framed: bool,
 }
 
 impl ChanWriter {
     /// Wraps a channel in a `ChanWriter` structure
     pub fn new(tx: Sender<Vec<u8>>) -> ChanWriter {
-        ChanWriter { tx: tx }
+        ChanWriter { tx: tx, framed: false }
     }
-}
 
-impl Clone for ChanWriter {
-    fn clone(&self) -> ChanWriter {
-        ChanWriter { tx: self.tx.clone() }
+    /// Wraps a channel in a framed `ChanWriter`, which writes every buffer as
+    /// a message of its own.
+    pub fn framed(tx: Sender<Vec<u8>>) -> ChanWriter {
+        ChanWriter { tx: tx, framed: true }
     }
-}
 
-impl Writer for ChanWriter {
-    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
-        self.tx.send_opt(Vec::from_slice(buf)).map_err(|_| {
+    /// Writes `msg` prefixed with its length, as a big-endian `u32`, so that
+    /// it can be read as a whole by `ChanReader::read_message`.
+    ///
+    /// The frame is sent as a single vector on the channel.
+    pub fn write_message(&mut self, msg: &[u8]) -> IoResult<()> {
+        if msg.len() > u32::MAX as uint {
+            return Err(io::IoError {
+                kind: io::InvalidInput,
+                desc: "message too long",
+                detail: None
+            })
+        }
+        let mut frame = Vec::with_capacity(4 + msg.len());
+        u64_to_be_bytes(msg.len() as u64, 4, |v| frame.push_all(v));
+        frame.push_all(msg);
+        self.send(frame)
+    }
+
+    fn send(&mut self, buf: Vec<u8>) -> IoResult<()> {
+        self.tx.send_opt(buf).map_err(|_| {
             io::IoError {
                 kind: io::BrokenPipe,
                 desc: "Pipe closed",
@@ -128,6 +226,22 @@ impl Writer for ChanWriter {
     }
 }
 
+impl Clone for ChanWriter {
+    fn clone(&self) -> ChanWriter {
+        ChanWriter { tx: self.tx.clone(), framed: self.framed }
+    }
+}
+
+impl Writer for ChanWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        if self.framed {
+            self.write_message(buf)
+        } else {
+            self.send(Vec::from_slice(buf))
+        }
+    }
+}
+
 
 #[cfg(test)]
 mod test {
@@ -194,4 +308,73 @@ mod test {
             Err(e) => assert_eq!(e.kind, io::BrokenPipe),
         }
     }
+
+    #[test]
+    fn test_messages() {
+        let (tx, rx) = channel();
+        let mut writer = ChanWriter::new(tx);
+        writer.write_message([1, 2, 3]).unwrap();
+        writer.write_message([]).unwrap();
+        writer.write_message([4]).unwrap();
+        drop(writer);
+
+        // Frames can be split and merged by whatever carries them
+        let mut bytes = Vec::new();
+        for v in rx.iter() { bytes.push_all(v.as_slice()) }
+        let (tx, rx) = channel();
+        tx.send(Vec::from_slice(bytes.slice_to(5)));
+        tx.send(Vec::from_slice(bytes.slice_from(5)));
+        drop(tx);
+
+        let mut reader = ChanReader::new(rx);
+        assert_eq!(reader.read_message(), Ok(vec![1, 2, 3]));
+        assert_eq!(reader.read_message(), Ok(vec![]));
+        assert_eq!(reader.read_message(), Ok(vec![4]));
+        match reader.read_message() {
+            Ok(..) => fail!(),
+            Err(e) => assert_eq!(e.kind, io::EndOfFile),
+        }
+    }
+
+    #[test]
+    fn test_framed() {
+        let (tx, rx) = channel();
+        task::spawn(proc() {
+            let mut writer = ChanWriter::framed(tx);
+            {
+                let w = &mut writer as &mut Writer;
+                w.write([1, 2, 3, 4, 5]).unwrap();
+                w.write([]).unwrap();
+                w.write([6]).unwrap();
+            }
+            writer.write_message([7, 8]).unwrap();
+        });
+
+        let mut reader = ChanReader::framed(rx);
+        let mut buf = [0u8, ..3];
+        assert_eq!(Ok(3), reader.read(buf));
+        assert_eq!(&[1, 2, 3], buf.as_slice());
+        assert_eq!(reader.read_message(), Ok(vec![4, 5]));
+        assert_eq!(Ok(0), reader.read(buf));
+        assert_eq!(Ok(1), reader.read(buf));
+        assert_eq!(6, buf[0]);
+        assert_eq!(Ok(2), reader.read(buf));
+        assert_eq!(&[7, 8], buf.slice_to(2));
+        match reader.read(buf) {
+            Ok(..) => fail!(),
+            Err(e) => assert_eq!(e.kind, io::EndOfFile),
+        }
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        let (tx, rx) = channel();
+        tx.send(vec![0u8, 0, 0, 2, 1]);
+        drop(tx);
+        let mut reader = ChanReader::new(rx);
+        match reader.read_message() {
+            Ok(..) => fail!(),
+            Err(e) => assert_eq!(e.kind, io::EndOfFile),
+        }
+    }
 }
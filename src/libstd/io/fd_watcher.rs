@@ -0,0 +1,267 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/*!
+
+Readiness notifications for arbitrary file descriptors
+
+Descriptors which aren't wrapped by `std::io`, such as a timerfd, a netlink
+socket or a serial port, can still be waited on without blocking the
+scheduler: an `FdWatcher` registers the descriptor with the local I/O
+factory, and an `IoEvent` is sent on its receiver whenever the descriptor
+becomes ready. The receiver can be used with `select!` along with any other.
+
+Under libgreen the descriptor is watched by the event loop of the scheduler,
+and under libnative by a helper thread.
+
+On Windows only sockets can be watched, as they are by libuv, except that
+listening sockets aren't supported by libnative. Both runtimes wait for the
+sockets with an I/O completion port. Under libnative, waitable handles can be
+watched too, and they are readable once they are signaled. The handle of a
+receiver's `as_fd` is one of them, so that messages and I/O can be waited for
+by the same helper thread.
+
+The readability of `TcpStream`, `TcpAcceptor`, `UnixStream` and `UnixAcceptor`
+can be watched in the same way with their `watch_readable` methods. A task can
+then wait for data on a socket and for messages on its channels at once:
+
+```rust,no_run
+# #![allow(unused_must_use)]
+use std::io::TcpStream;
+
+let (_tx, control) = channel::<()>();
+let mut stream = TcpStream::connect("127.0.0.1", 8080).unwrap();
+let mut watcher = stream.watch_readable().unwrap();
+loop {
+    select! {
+        _ = watcher.rx.recv() => {
+            let mut buf = [0, ..1024];
+            stream.read(buf);
+            watcher.rearm();
+        },
+        () = control.recv() => break
+    }
+}
+```
+
+*/
+
+use comm::{Sender, Receiver, channel};
+use io::{IoResult, IoError};
+use kinds::Send;
+use boxed::Box;
+use libc;
+use option::Some;
+use rt::rtio::{LocalIo, RtioFdWatcher, FdCallback};
+use rt::rtio;
+
+/// The readiness of a watched descriptor.
+///
+/// Errors and hang ups are reported as the descriptor being both readable and
+/// writable, so that the next operation on it returns the error.
+#[deriving(Clone, PartialEq, Show)]
+pub struct IoEvent {
+    /// Whether the descriptor can be read from without blocking.
+    pub readable: bool,
+    /// Whether the descriptor can be written to without blocking.
+    pub writable: bool,
+}
+
+/// A watch on a file descriptor.
+///
+/// Watches are oneshot: once an event has been sent, the descriptor isn't
+/// watched again until `rearm` is called. This is usually done after the
+/// descriptor has been read from (or written to) until it would block, so that
+/// a descriptor which stays ready doesn't flood the receiver with events.
+///
+/// The descriptor isn't owned by the watcher, and it must stay open until the
+/// watcher is dropped.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #![allow(unused_must_use)]
+/// use std::io::fd_watcher::FdWatcher;
+///
+/// # let fd = 0;
+/// let mut watcher = FdWatcher::new(fd, true, false).unwrap();
+/// loop {
+///     let event = watcher.rx.recv();
+///     if event.readable {
+///         // read from `fd` until it would block...
+///     }
+///     watcher.rearm();
+/// }
+/// ```
+pub struct FdWatcher {
+    handle: Box<RtioFdWatcher + Send>,
+
+    /// The receiver on which the events of the descriptor are sent. This is
+    /// exposed to allow selection over it.
+    pub rx: Receiver<IoEvent>,
+}
+
+impl FdWatcher {
+    /// Starts watching `fd` for readability, writability, or both.
+    ///
+    /// # Error
+    ///
+    /// An error is returned if the descriptor can't be watched, for example
+    /// because it isn't a valid descriptor.
+    pub fn new(fd: libc::c_int, readable: bool,
+               writable: bool) -> IoResult<FdWatcher> {
+        FdWatcher::start(|cb| {
+            let mut cb = Some(cb);
+            LocalIo::maybe_raise(|io| {
+                io.fd_watch(fd, readable, writable, cb.take_unwrap())
+            })
+        })
+    }
+
+    /// Creates a watcher with a watch of the local I/O factory, which is made by
+    /// `f` with the callback to call on events.
+    #[doc(hidden)]
+    pub fn start(f: |Box<FdCallback + Send>|
+                    -> rtio::IoResult<Box<RtioFdWatcher + Send>>)
+                 -> IoResult<FdWatcher> {
+        struct EventCallback {
+            tx: Sender<IoEvent>,
+        }
+        impl FdCallback for EventCallback {
+            fn call(&mut self, readable: bool, writable: bool) -> bool {
+                let _ = self.tx.send_opt(IoEvent {
+                    readable: readable,
+                    writable: writable,
+                });
+                false
+            }
+        }
+
+        let (tx, rx) = channel();
+        f(box EventCallback { tx: tx }).map(|handle| {
+            FdWatcher { handle: handle, rx: rx }
+        }).map_err(IoError::from_rtio_error)
+    }
+
+    /// Starts watching the descriptor again after an event was sent. If the
+    /// descriptor is still ready, another event is sent right away.
+    pub fn rearm(&mut self) {
+        self.handle.rearm();
+    }
+}
+
+#[cfg(test, windows)]
+mod test_win32 {
+    use libc;
+    use prelude::*;
+    use super::FdWatcher;
+
+    // Only the native runtime can watch handles
+    #[test]
+    fn channel_handle() {
+        use native;
+        let (done_tx, done_rx) = channel();
+        native::task::spawn(proc() {
+            let (tx, rx) = channel();
+            {
+                let ready = rx.as_fd();
+                let mut w = FdWatcher::new(ready.fd() as libc::c_int, true,
+                                           false).unwrap();
+                tx.send(1i);
+                assert!(w.rx.recv().readable);
+                assert_eq!(rx.recv(), 1);
+                w.rearm();
+            }
+            done_tx.send(());
+        });
+        done_rx.recv();
+    }
+}
+
+#[cfg(test, unix)]
+mod test {
+    use libc;
+    use os;
+
+    pub fn pipe() -> (libc::c_int, libc::c_int) {
+        let os::Pipe { reader, writer } = unsafe { os::pipe().unwrap() };
+        (reader, writer)
+    }
+
+    pub fn write_byte(fd: libc::c_int) {
+        let b = 0u8;
+        assert_eq!(unsafe {
+            libc::write(fd, &b as *const u8 as *const libc::c_void, 1)
+        }, 1);
+    }
+
+    pub fn read_byte(fd: libc::c_int) {
+        let mut b = 0u8;
+        assert_eq!(unsafe {
+            libc::read(fd, &mut b as *mut u8 as *mut libc::c_void, 1)
+        }, 1);
+    }
+
+    pub fn close(fd: libc::c_int) {
+        unsafe { libc::close(fd); }
+    }
+
+    iotest!(fn readable() {
+        let (reader, writer) = pipe();
+        {
+            let mut w = FdWatcher::new(reader, true, false).unwrap();
+            write_byte(writer);
+            assert_eq!(w.rx.recv(), IoEvent { readable: true, writable: false });
+
+            // Not watched again until it's rearmed
+            timer::sleep(10);
+            assert!(w.rx.try_recv().is_err());
+            read_byte(reader);
+            w.rearm();
+            write_byte(writer);
+            assert!(w.rx.recv().readable);
+        }
+        close(reader);
+        close(writer);
+    })
+
+    iotest!(fn rearm_while_ready() {
+        let (reader, writer) = pipe();
+        {
+            let mut w = FdWatcher::new(reader, true, false).unwrap();
+            write_byte(writer);
+            assert!(w.rx.recv().readable);
+            w.rearm();
+            assert!(w.rx.recv().readable);
+        }
+        close(reader);
+        close(writer);
+    })
+
+    iotest!(fn writable() {
+        let (reader, writer) = pipe();
+        {
+            let w = FdWatcher::new(writer, false, true).unwrap();
+            assert!(w.rx.recv().writable);
+        }
+        close(reader);
+        close(writer);
+    })
+
+    iotest!(fn hangup() {
+        let (reader, writer) = pipe();
+        {
+            let w = FdWatcher::new(reader, true, false).unwrap();
+            close(writer);
+            assert!(w.rx.recv().readable);
+        }
+        close(reader);
+    })
+}
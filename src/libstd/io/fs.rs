@@ -74,6 +74,8 @@ use slice::ImmutableVector;
 use string::String;
 use vec::Vec;
 
+pub use io::fs_watcher::{Watcher, FsEvent, FsEventKind, Created, Modified, Removed};
+
 /// Unconstrained file access type that exposes read and write operations
 ///
 /// Can be constructed via `File::open()`, `File::create()`, and
@@ -1631,4 +1633,63 @@ mod test {
         check!(chmod(&path, io::UserRead));
         check!(unlink(&path));
     })
+
+    iotest!(fn watch_file() {
+        let tmpdir = tmpdir();
+        let path = tmpdir.join("file");
+        check!(File::create(&path));
+        let mut watcher = check!(Watcher::new());
+        check!(watcher.watch(&path));
+        check!(File::open_mode(&path, Append, Write).write([0]));
+        assert_eq!(watcher.rx.recv(), FsEvent { path: path.clone(), kind: Modified });
+
+        // The link count changes before the file is gone
+        check!(unlink(&path));
+        loop {
+            let event = watcher.rx.recv();
+            assert_eq!(event.path, path);
+            if event.kind == Removed { break }
+        }
+    } #[cfg(unix)])
+
+    iotest!(fn watch_twice() {
+        let tmpdir = tmpdir();
+        let path = tmpdir.join("file");
+        check!(File::create(&path));
+        let mut watcher = check!(Watcher::new());
+        check!(watcher.watch(&path));
+        check!(watcher.watch(&path));
+        check!(File::open_mode(&path, Append, Write).write([0]));
+        assert_eq!(watcher.rx.recv().kind, Modified);
+
+        watcher.unwatch(&path);
+        check!(File::open_mode(&path, Append, Write).write([0]));
+        timer::sleep(50);
+        assert!(watcher.rx.try_recv().is_err());
+    } #[cfg(unix)])
+
+    iotest!(fn watch_nonexistent() {
+        let tmpdir = tmpdir();
+        let mut watcher = check!(Watcher::new());
+        assert!(watcher.watch(&tmpdir.join("nope")).is_err());
+    } #[cfg(unix)])
+
+    iotest!(fn watch_dir_entries() {
+        let tmpdir = tmpdir();
+        let dir = tmpdir.path().clone();
+        let path = tmpdir.join("file");
+        let mut watcher = check!(Watcher::new());
+        check!(watcher.watch(&dir));
+
+        check!(File::create(&path));
+        assert_eq!(watcher.rx.recv(), FsEvent { path: path.clone(), kind: Created });
+        check!(File::open_mode(&path, Append, Write).write([0]));
+        assert_eq!(watcher.rx.recv(), FsEvent { path: path.clone(), kind: Modified });
+        check!(unlink(&path));
+        loop {
+            let event = watcher.rx.recv();
+            assert_eq!(event.path, path);
+            if event.kind == Removed { break }
+        }
+    } #[cfg(target_os = "linux")] #[cfg(target_os = "android")])
 }
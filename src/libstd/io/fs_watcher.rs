@@ -0,0 +1,732 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! File system watching, re-exported from `std::io::fs`
+//!
+//! The notifications of the OS are read from a single descriptor (an inotify
+//! instance on Linux and Android, a kqueue on OSX, iOS and FreeBSD, and an
+//! event signaled by overlapped directory reads on Windows), whose readiness
+//! is watched by the local I/O factory just like an `FdWatcher`.
+//! Whenever it becomes readable, every pending notification is read and sent
+//! as an `FsEvent`, and the watch stays armed.
+
+use clone::Clone;
+use comm::{Sender, Receiver, channel};
+use fmt;
+use io::{IoResult, IoError};
+use kinds::Send;
+use boxed::Box;
+use option::Some;
+use path::Path;
+use result::{Ok, Err};
+use rt::exclusive::Exclusive;
+use rt::rtio::{LocalIo, RtioFdWatcher, FdCallback};
+use sync::Arc;
+
+/// The kind of change reported by an `FsEvent`.
+#[deriving(Clone, PartialEq, Show)]
+pub enum FsEventKind {
+    /// The path was created, or something was moved to it.
+    Created,
+    /// The contents or the metadata of the path were changed.
+    Modified,
+    /// The path was removed, or moved away.
+    Removed,
+}
+
+/// A change to a watched path, or to an entry of a watched directory.
+#[deriving(Clone, PartialEq)]
+pub struct FsEvent {
+    /// The path which was changed. This is the watched path joined with the
+    /// name of the entry when an entry of a watched directory changed.
+    pub path: Path,
+    /// What happened to the path.
+    pub kind: FsEventKind,
+}
+
+impl fmt::Show for FsEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.path.display())
+    }
+}
+
+/// A watcher of changes to the file system.
+///
+/// Any number of files and directories can be watched by one watcher, and the
+/// changes to all of them are sent as `FsEvent`s on its receiver, which can be
+/// used with `select!` along with any other.
+///
+/// Under inotify (Linux and Android), the entries of a watched directory which
+/// are created, modified or removed are reported by their own path. Under
+/// kqueue (OSX, iOS and FreeBSD), only the watched paths themselves are
+/// reported, so a change to the entries of a directory is reported as the
+/// directory being modified. On Windows, the directories are read with
+/// `ReadDirectoryChangesW`, so the entries of a directory are reported like
+/// under inotify, and a watched file is reported through the changes to its
+/// directory. libuv can only poll sockets on Windows, so there watching is
+/// only supported by native I/O, and `Watcher::new` returns an error in a
+/// green task.
+///
+/// Notifications may be coalesced, and inotify drops them if too many of them
+/// pile up before they're read.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #![allow(unused_must_use)]
+/// use std::io::fs::Watcher;
+///
+/// let mut watcher = Watcher::new().unwrap();
+/// watcher.watch(&Path::new("/tmp"));
+/// loop {
+///     let event = watcher.rx.recv();
+///     println!("{}: {}", event.path.display(), event.kind);
+/// }
+/// ```
+pub struct Watcher {
+    handle: Box<RtioFdWatcher + Send>,
+    state: Arc<Exclusive<imp::State>>,
+
+    /// The receiver on which the changes are sent. This is exposed to allow
+    /// selection over it.
+    pub rx: Receiver<FsEvent>,
+}
+
+struct EventCallback {
+    state: Arc<Exclusive<imp::State>>,
+    tx: Sender<FsEvent>,
+}
+
+impl FdCallback for EventCallback {
+    fn call(&mut self, _readable: bool, _writable: bool) -> bool {
+        // The callback is run by the event loop or the helper thread, which
+        // never deschedule while the lock is held
+        let tx = &self.tx;
+        let mut state = unsafe { self.state.lock() };
+        state.read_events(|event| { let _ = tx.send_opt(event); });
+        true
+    }
+}
+
+impl Watcher {
+    /// Creates a watcher which isn't watching anything yet.
+    pub fn new() -> IoResult<Watcher> {
+        let state = Arc::new(Exclusive::new(try!(imp::State::new())));
+        let fd = unsafe { state.lock().fd() };
+        let (tx, rx) = channel();
+        let cb = box EventCallback { state: state.clone(), tx: tx };
+        let mut cb = Some(cb as Box<FdCallback + Send>);
+        LocalIo::maybe_raise(|io| {
+            io.fd_watch(fd, true, false, cb.take_unwrap())
+        }).map(|handle| {
+            Watcher { handle: handle, state: state, rx: rx }
+        }).map_err(IoError::from_rtio_error)
+    }
+
+    /// Starts watching `path`, which may be a file or a directory. Watching a
+    /// path which is already watched does nothing.
+    ///
+    /// # Error
+    ///
+    /// An error is returned if the path doesn't exist or can't be watched.
+    pub fn watch(&mut self, path: &Path) -> IoResult<()> {
+        // None of the watching calls deschedule while the lock is held
+        let mut state = unsafe { self.state.lock() };
+        state.watch(path)
+    }
+
+    /// Stops watching `path`. Nothing is done if it isn't watched.
+    pub fn unwatch(&mut self, path: &Path) {
+        let mut state = unsafe { self.state.lock() };
+        state.unwatch(path)
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[cfg(target_os = "android")]
+mod imp {
+    use collections::{Collection, MutableSeq};
+    use c_str::ToCStr;
+    use io::{IoResult, IoError};
+    use iter::Iterator;
+    use libc::{c_int, c_char, c_void, size_t};
+    use libc;
+    use mem;
+    use ops::Drop;
+    use option::{Some, None};
+    use os;
+    use path::{Path, GenericPath};
+    use ptr;
+    use result::{Ok, Err};
+    use slice::ImmutableVector;
+    use vec::Vec;
+
+    use super::{FsEvent, Created, Modified, Removed};
+
+    #[cfg(target_arch = "mips")]
+    #[cfg(target_arch = "mipsel")]
+    static IN_NONBLOCK: c_int = 0x80;
+    #[cfg(not(target_arch = "mips"), not(target_arch = "mipsel"))]
+    static IN_NONBLOCK: c_int = 0o4000;
+    static IN_CLOEXEC: c_int = 0o2000000;
+
+    static IN_MODIFY: u32 = 0x2;
+    static IN_ATTRIB: u32 = 0x4;
+    static IN_MOVED_FROM: u32 = 0x40;
+    static IN_MOVED_TO: u32 = 0x80;
+    static IN_CREATE: u32 = 0x100;
+    static IN_DELETE: u32 = 0x200;
+    static IN_DELETE_SELF: u32 = 0x400;
+    static IN_MOVE_SELF: u32 = 0x800;
+    static IN_IGNORED: u32 = 0x8000;
+
+    static MASK: u32 = IN_MODIFY | IN_ATTRIB | IN_MOVED_FROM | IN_MOVED_TO |
+                       IN_CREATE | IN_DELETE | IN_DELETE_SELF | IN_MOVE_SELF;
+
+    #[repr(C)]
+    struct inotify_event {
+        wd: c_int,
+        mask: u32,
+        cookie: u32,
+        len: u32,
+        // followed by `len` bytes of NUL-padded name
+    }
+
+    extern {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int;
+        fn inotify_rm_watch(fd: c_int, wd: c_int) -> c_int;
+    }
+
+    pub struct State {
+        fd: c_int,
+        // The watch descriptors along with the paths they're watching
+        watches: Vec<(c_int, Path)>,
+    }
+
+    impl State {
+        pub fn new() -> IoResult<State> {
+            match unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) } {
+                -1 => Err(IoError::last_error()),
+                fd => Ok(State { fd: fd, watches: Vec::new() }),
+            }
+        }
+
+        pub fn fd(&self) -> c_int { self.fd }
+
+        pub fn watch(&mut self, path: &Path) -> IoResult<()> {
+            let wd = path.with_c_str(|p| unsafe {
+                inotify_add_watch(self.fd, p, MASK)
+            });
+            if wd == -1 { return Err(IoError::last_error()) }
+            // Watching a path twice returns the same watch descriptor
+            if !self.watches.iter().any(|&(w, _)| w == wd) {
+                self.watches.push((wd, path.clone()));
+            }
+            Ok(())
+        }
+
+        pub fn unwatch(&mut self, path: &Path) {
+            match self.watches.iter().position(|&(_, ref p)| p == path) {
+                Some(i) => {
+                    let (wd, _) = self.watches.remove(i).unwrap();
+                    unsafe { inotify_rm_watch(self.fd, wd); }
+                }
+                None => {}
+            }
+        }
+
+        pub fn read_events(&mut self, f: |FsEvent|) {
+            let mut buf = [0u8, ..4096];
+            loop {
+                let n = unsafe {
+                    libc::read(self.fd, buf.as_mut_ptr() as *mut c_void,
+                               buf.len() as size_t)
+                };
+                if n <= 0 {
+                    debug_assert!(n == 0 || os::errno() == libc::EAGAIN as int);
+                    return
+                }
+                self.parse(buf.slice_to(n as uint), |e| f(e));
+            }
+        }
+
+        fn parse(&mut self, mut buf: &[u8], f: |FsEvent|) {
+            let header = mem::size_of::<inotify_event>();
+            while buf.len() >= header {
+                let event: inotify_event = unsafe {
+                    ptr::read(buf.as_ptr() as *const inotify_event)
+                };
+                let name = buf.slice(header, header + event.len as uint);
+                let name = match name.iter().position(|&b| b == 0) {
+                    Some(i) => name.slice_to(i),
+                    None => name,
+                };
+                buf = buf.slice_from(header + event.len as uint);
+
+                let i = match self.watches.iter().position(|&(w, _)| w == event.wd) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                if event.mask & IN_IGNORED != 0 {
+                    // The watched path is gone, and so is its watch
+                    self.watches.remove(i);
+                    continue
+                }
+                let path = {
+                    let &(_, ref watched) = self.watches.get(i);
+                    if name.len() == 0 { watched.clone() } else { watched.join(name) }
+                };
+                let kind = if event.mask & (IN_CREATE | IN_MOVED_TO) != 0 {
+                    Created
+                } else if event.mask & (IN_DELETE | IN_MOVED_FROM |
+                                        IN_DELETE_SELF | IN_MOVE_SELF) != 0 {
+                    Removed
+                } else {
+                    Modified
+                };
+                f(FsEvent { path: path, kind: kind });
+            }
+        }
+    }
+
+    impl Drop for State {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.fd); }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[cfg(target_os = "ios")]
+#[cfg(target_os = "freebsd")]
+mod imp {
+    use collections::{Collection, MutableSeq};
+    use c_str::ToCStr;
+    use io::{IoResult, IoError};
+    use iter::Iterator;
+    use libc::{c_int, c_void, intptr_t, uintptr_t};
+    use libc;
+    use mem;
+    use ops::Drop;
+    use option::{Some, None};
+    use path::Path;
+    use ptr;
+    use result::{Ok, Err};
+    use slice::ImmutableVector;
+    use vec::Vec;
+
+    use super::{FsEvent, Modified, Removed};
+
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "ios")]
+    static O_EVTONLY: c_int = 0x8000;
+    #[cfg(target_os = "freebsd")]
+    static O_EVTONLY: c_int = libc::O_RDONLY;
+
+    static EVFILT_VNODE: i16 = -4;
+    static EV_ADD: u16 = 0x1;
+    static EV_CLEAR: u16 = 0x20;
+
+    static NOTE_DELETE: u32 = 0x1;
+    static NOTE_WRITE: u32 = 0x2;
+    static NOTE_EXTEND: u32 = 0x4;
+    static NOTE_ATTRIB: u32 = 0x8;
+    static NOTE_RENAME: u32 = 0x20;
+
+    #[repr(C)]
+    struct kevent {
+        ident: uintptr_t,
+        filter: i16,
+        flags: u16,
+        fflags: u32,
+        data: intptr_t,
+        udata: *mut c_void,
+    }
+
+    extern {
+        fn kqueue() -> c_int;
+        fn kevent(kq: c_int, changelist: *const kevent, nchanges: c_int,
+                  eventlist: *mut kevent, nevents: c_int,
+                  timeout: *const libc::timespec) -> c_int;
+    }
+
+    pub struct State {
+        fd: c_int,
+        // The descriptors opened for the watched paths, which are closed to
+        // stop watching them
+        watches: Vec<(c_int, Path)>,
+    }
+
+    impl State {
+        pub fn new() -> IoResult<State> {
+            match unsafe { kqueue() } {
+                -1 => Err(IoError::last_error()),
+                fd => Ok(State { fd: fd, watches: Vec::new() }),
+            }
+        }
+
+        pub fn fd(&self) -> c_int { self.fd }
+
+        pub fn watch(&mut self, path: &Path) -> IoResult<()> {
+            if self.watches.iter().any(|&(_, ref p)| p == path) {
+                return Ok(())
+            }
+            let fd = path.with_c_str(|p| unsafe {
+                libc::open(p, O_EVTONLY, 0)
+            });
+            if fd == -1 { return Err(IoError::last_error()) }
+            let change = kevent {
+                ident: fd as uintptr_t,
+                filter: EVFILT_VNODE,
+                flags: EV_ADD | EV_CLEAR,
+                fflags: NOTE_DELETE | NOTE_WRITE | NOTE_EXTEND | NOTE_ATTRIB |
+                        NOTE_RENAME,
+                data: 0,
+                udata: ptr::mut_null(),
+            };
+            if unsafe { kevent(self.fd, &change, 1, ptr::mut_null(), 0,
+                               ptr::null()) } == -1 {
+                let err = IoError::last_error();
+                unsafe { libc::close(fd); }
+                return Err(err)
+            }
+            self.watches.push((fd, path.clone()));
+            Ok(())
+        }
+
+        pub fn unwatch(&mut self, path: &Path) {
+            match self.watches.iter().position(|&(_, ref p)| p == path) {
+                // Closing the descriptor removes it from the kqueue
+                Some(i) => {
+                    let (fd, _) = self.watches.remove(i).unwrap();
+                    unsafe { libc::close(fd); }
+                }
+                None => {}
+            }
+        }
+
+        pub fn read_events(&mut self, f: |FsEvent|) {
+            let zero = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+            let mut events: [kevent, ..16] = unsafe { mem::zeroed() };
+            loop {
+                let n = unsafe {
+                    kevent(self.fd, ptr::null(), 0, events.as_mut_ptr(),
+                           events.len() as c_int, &zero)
+                };
+                if n <= 0 { return }
+                for event in events.slice_to(n as uint).iter() {
+                    let path = match self.watches.iter().find(|&&(fd, _)| {
+                        fd as uintptr_t == event.ident
+                    }) {
+                        Some(&(_, ref path)) => path.clone(),
+                        None => continue,
+                    };
+                    let kind = if event.fflags & (NOTE_DELETE | NOTE_RENAME) != 0 {
+                        Removed
+                    } else {
+                        Modified
+                    };
+                    f(FsEvent { path: path, kind: kind });
+                }
+            }
+        }
+    }
+
+    impl Drop for State {
+        fn drop(&mut self) {
+            unsafe {
+                for &(fd, _) in self.watches.iter() {
+                    libc::close(fd);
+                }
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use boxed::Box;
+    use collections::{Collection, MutableSeq};
+    use io::{IoResult, IoError};
+    use iter::Iterator;
+    use libc::{c_int, c_void, BOOL, DWORD, HANDLE, LPCWSTR, LPOVERLAPPED};
+    use libc;
+    use mem;
+    use ops::Drop;
+    use option::{Option, Some, None};
+    use path::{Path, GenericPath};
+    use ptr;
+    use raw;
+    use result::{Ok, Err};
+    use rt::thread::Thread;
+    use slice::ImmutableVector;
+    use str::StrSlice;
+    use string::String;
+    use vec::Vec;
+
+    use super::{FsEvent, Created, Modified, Removed};
+
+    static FILE_LIST_DIRECTORY: DWORD = 0x1;
+    static FILE_ATTRIBUTE_DIRECTORY: DWORD = 0x10;
+    static INVALID_FILE_ATTRIBUTES: DWORD = 0xffffffff;
+    static ERROR_IO_INCOMPLETE: DWORD = 996;
+
+    static FILE_NOTIFY_CHANGE_FILE_NAME: DWORD = 0x1;
+    static FILE_NOTIFY_CHANGE_DIR_NAME: DWORD = 0x2;
+    static FILE_NOTIFY_CHANGE_ATTRIBUTES: DWORD = 0x4;
+    static FILE_NOTIFY_CHANGE_SIZE: DWORD = 0x8;
+    static FILE_NOTIFY_CHANGE_LAST_WRITE: DWORD = 0x10;
+
+    static FILTER: DWORD = FILE_NOTIFY_CHANGE_FILE_NAME |
+                           FILE_NOTIFY_CHANGE_DIR_NAME |
+                           FILE_NOTIFY_CHANGE_ATTRIBUTES |
+                           FILE_NOTIFY_CHANGE_SIZE |
+                           FILE_NOTIFY_CHANGE_LAST_WRITE;
+
+    static FILE_ACTION_ADDED: DWORD = 1;
+    static FILE_ACTION_REMOVED: DWORD = 2;
+    static FILE_ACTION_RENAMED_OLD_NAME: DWORD = 4;
+    static FILE_ACTION_RENAMED_NEW_NAME: DWORD = 5;
+
+    #[repr(C)]
+    struct FILE_NOTIFY_INFORMATION {
+        next: DWORD,
+        action: DWORD,
+        len: DWORD,
+        // followed by `len` bytes of UTF-16 name, which isn't NUL-terminated
+    }
+
+    extern "system" {
+        fn ReadDirectoryChangesW(dir: HANDLE, buf: *mut c_void, len: DWORD,
+                                 subtree: BOOL, filter: DWORD,
+                                 returned: *mut DWORD, overlapped: LPOVERLAPPED,
+                                 routine: *mut c_void) -> BOOL;
+        fn GetFileAttributesW(path: LPCWSTR) -> DWORD;
+        fn CancelIoEx(file: HANDLE, overlapped: LPOVERLAPPED) -> BOOL;
+        fn ResetEvent(event: HANDLE) -> BOOL;
+    }
+
+    // The directories are read with overlapped reads, which all signal the
+    // same manual-reset event once they complete. That event is what the
+    // local I/O factory watches, as a waitable handle.
+    pub struct State {
+        event: HANDLE,
+        watches: Vec<Box<Watch>>,
+    }
+
+    // A directory being read for changes. It's boxed because the OVERLAPPED
+    // and the buffer are in use by the OS while a read is pending.
+    struct Watch {
+        path: Path,
+        dir: HANDLE,
+        // The name of the file watched in `dir`, if a file is watched rather
+        // than the directory itself
+        file: Option<Vec<u16>>,
+        pending: bool,
+        overlapped: libc::OVERLAPPED,
+        // DWORDs, as the notifications must be DWORD-aligned
+        buf: [u32, ..1024],
+    }
+
+    fn to_utf16(path: &Path) -> Vec<u16> {
+        path.as_str().unwrap().utf16_units().collect::<Vec<u16>>().append_one(0)
+    }
+
+    impl State {
+        pub fn new() -> IoResult<State> {
+            let event = unsafe {
+                libc::CreateEventW(ptr::mut_null(), 1, 0, ptr::null())
+            };
+            if event.is_null() { return Err(IoError::last_error()) }
+            Ok(State { event: event, watches: Vec::new() })
+        }
+
+        pub fn fd(&self) -> c_int { self.event as uint as c_int }
+
+        pub fn watch(&mut self, path: &Path) -> IoResult<()> {
+            if self.watches.iter().any(|w| w.path == *path) {
+                return Ok(())
+            }
+            // Only directories can be read for changes, so a file is watched
+            // through its directory
+            let attrs = unsafe { GetFileAttributesW(to_utf16(path).as_ptr()) };
+            if attrs == INVALID_FILE_ATTRIBUTES {
+                return Err(IoError::last_error())
+            }
+            let (dir, file) = if attrs & FILE_ATTRIBUTE_DIRECTORY != 0 {
+                (path.clone(), None)
+            } else {
+                let name = path.filename_str().unwrap();
+                (path.dir_path(), Some(name.utf16_units().collect()))
+            };
+            let handle = unsafe {
+                libc::CreateFileW(to_utf16(&dir).as_ptr(), FILE_LIST_DIRECTORY,
+                                  libc::FILE_SHARE_READ | libc::FILE_SHARE_WRITE |
+                                  libc::FILE_SHARE_DELETE,
+                                  ptr::mut_null(), libc::OPEN_EXISTING,
+                                  libc::FILE_FLAG_BACKUP_SEMANTICS |
+                                  libc::FILE_FLAG_OVERLAPPED,
+                                  ptr::mut_null())
+            };
+            if handle == libc::INVALID_HANDLE_VALUE as HANDLE {
+                return Err(IoError::last_error())
+            }
+            let mut w = box Watch {
+                path: path.clone(),
+                dir: handle,
+                file: file,
+                pending: false,
+                overlapped: unsafe { mem::zeroed() },
+                buf: [0, ..1024],
+            };
+            w.overlapped.hEvent = self.event;
+            try!(w.read());
+            self.watches.push(w);
+            Ok(())
+        }
+
+        pub fn unwatch(&mut self, path: &Path) {
+            match self.watches.iter().position(|w| w.path == *path) {
+                Some(i) => { self.watches.remove(i); }
+                None => {}
+            }
+        }
+
+        pub fn read_events(&mut self, f: |FsEvent|) {
+            // The event is reset before looking at the reads, so that one
+            // which completes in the meantime signals it again
+            unsafe { ResetEvent(self.event); }
+            let mut i = 0;
+            while i < self.watches.len() {
+                if self.watches.get_mut(i).complete(|e| f(e)) {
+                    i += 1;
+                } else {
+                    self.watches.remove(i);
+                }
+            }
+        }
+    }
+
+    impl Drop for State {
+        fn drop(&mut self) {
+            // The pending reads signal the event as they're cancelled
+            self.watches.clear();
+            unsafe { libc::CloseHandle(self.event); }
+        }
+    }
+
+    impl Watch {
+        fn read(&mut self) -> IoResult<()> {
+            let ok = unsafe {
+                ReadDirectoryChangesW(self.dir,
+                                      self.buf.as_mut_ptr() as *mut c_void,
+                                      (self.buf.len() * 4) as DWORD, 0, FILTER,
+                                      ptr::mut_null(), &mut self.overlapped,
+                                      ptr::mut_null())
+            };
+            if ok == 0 { return Err(IoError::last_error()) }
+            self.pending = true;
+            Ok(())
+        }
+
+        // Sends the changes of the read if it has completed, and starts the
+        // next one. Returns false once the path can't be watched anymore.
+        fn complete(&mut self, f: |FsEvent|) -> bool {
+            let mut bytes = 0;
+            let ok = unsafe {
+                libc::GetOverlappedResult(self.dir, &mut self.overlapped,
+                                          &mut bytes, 0)
+            };
+            let gone = if ok != 0 {
+                if bytes == 0 {
+                    // There were more changes than the buffer could hold, and
+                    // they were dropped
+                    f(FsEvent { path: self.path.clone(), kind: Modified });
+                } else {
+                    self.parse(bytes as uint, |e| f(e));
+                }
+                false
+            } else {
+                match unsafe { libc::GetLastError() } {
+                    ERROR_IO_INCOMPLETE => return true,
+                    // A read is cancelled when the thread which started it
+                    // exits, and is simply started again
+                    n => n != libc::ERROR_OPERATION_ABORTED as DWORD,
+                }
+            };
+            self.pending = false;
+            if gone || self.read().is_err() {
+                // The directory has been removed
+                f(FsEvent { path: self.path.clone(), kind: Removed });
+                return false
+            }
+            true
+        }
+
+        fn parse(&self, len: uint, f: |FsEvent|) {
+            let header = mem::size_of::<FILE_NOTIFY_INFORMATION>();
+            let buf = self.buf.as_ptr() as *const u8;
+            let mut offset = 0;
+            while offset + header <= len {
+                let (info, name) = unsafe {
+                    let info = buf.offset(offset as int);
+                    let info = &*(info as *const FILE_NOTIFY_INFORMATION);
+                    let name: &[u16] = mem::transmute(raw::Slice {
+                        data: buf.offset((offset + header) as int) as *const u16,
+                        len: info.len as uint / 2,
+                    });
+                    (info, name)
+                };
+                let path = match self.file {
+                    Some(ref file) if file.as_slice() == name => {
+                        Some(self.path.clone())
+                    }
+                    Some(..) => None,
+                    None => {
+                        let name = String::from_utf16_lossy(name);
+                        Some(self.path.join(name.as_slice()))
+                    }
+                };
+                let kind = match info.action {
+                    FILE_ACTION_ADDED | FILE_ACTION_RENAMED_NEW_NAME => Created,
+                    FILE_ACTION_REMOVED | FILE_ACTION_RENAMED_OLD_NAME => Removed,
+                    _ => Modified,
+                };
+                match path {
+                    Some(path) => f(FsEvent { path: path, kind: kind }),
+                    None => {}
+                }
+                if info.next == 0 { break }
+                offset += info.next as uint;
+            }
+        }
+    }
+
+    impl Drop for Watch {
+        fn drop(&mut self) {
+            unsafe {
+                if self.pending {
+                    CancelIoEx(self.dir, &mut self.overlapped);
+                    // The event is shared, so it can't be waited on to learn
+                    // when this read in particular is done
+                    let mut bytes = 0;
+                    while libc::GetOverlappedResult(self.dir, &mut self.overlapped,
+                                                    &mut bytes, 0) == 0 &&
+                          libc::GetLastError() == ERROR_IO_INCOMPLETE {
+                        Thread::yield_now();
+                    }
+                }
+                libc::CloseHandle(self.dir);
+            }
+        }
+    }
+}
@@ -269,10 +269,12 @@ pub mod test;
 
 mod buffered;
 mod comm_adapters;
+mod fs_watcher;
 mod mem;
 mod result;
 mod tempfile;
 pub mod extensions;
+pub mod fd_watcher;
 pub mod fs;
 pub mod net;
 pub mod pipe;
@@ -27,6 +27,7 @@ use io::net::addrinfo::get_host_addresses;
 use io::net::ip::SocketAddr;
 use io::{IoError, ConnectionFailed, InvalidInput};
 use io::{Reader, Writer, Listener, Acceptor};
+use io::fd_watcher::FdWatcher;
 use from_str::FromStr;
 use kinds::Send;
 use option::{None, Some, Option};
@@ -262,6 +263,31 @@ impl TcpStream {
     pub fn set_write_timeout(&mut self, timeout_ms: Option<u64>) {
         self.obj.set_write_timeout(timeout_ms)
     }
+
+    /// Returns a watcher whose receiver gets an event whenever this stream has
+    /// data to read, or has been closed. Waiting on the receiver with a
+    /// `Select` (or `select!`) allows waiting for the stream and for other
+    /// channels at once.
+    ///
+    /// The watch is oneshot: once the stream has been read from until there's
+    /// nothing left (a read timeout of zero helps with this), the watcher must
+    /// be rearmed. See `std::io::fd_watcher` for more information.
+    #[experimental = "the readiness interface may change"]
+    pub fn watch_readable(&mut self) -> IoResult<FdWatcher> {
+        FdWatcher::start(|cb| self.obj.watch_readable(cb))
+    }
+
+    /// Writes all of the buffers of `bufs`, one after the other, with
+    /// vectored I/O. The buffers are handed to the OS together rather than
+    /// copied into one, and a single write request is made for all of them
+    /// if they fit.
+    ///
+    /// When a write timeout is set, the buffers are written one at a time
+    /// like `write` would.
+    #[experimental = "vectored I/O may be generalized to other writers"]
+    pub fn write_vectored(&mut self, bufs: &[&[u8]]) -> IoResult<()> {
+        self.obj.write_vectored(bufs).map_err(IoError::from_rtio_error)
+    }
 }
 
 impl Clone for TcpStream {
@@ -432,6 +458,17 @@ impl TcpAcceptor {
     #[experimental = "the type of the argument and name of this function are \
                       subject to change"]
     pub fn set_timeout(&mut self, ms: Option<u64>) { self.obj.set_timeout(ms); }
+
+    /// Returns a watcher whose receiver gets an event whenever a connection is
+    /// waiting to be accepted.
+    ///
+    /// Several connections may be waiting when the event is sent, so they
+    /// should be accepted with a timeout of zero until it times out before the
+    /// watcher is rearmed. See `TcpStream::watch_readable` for more.
+    #[experimental = "the readiness interface may change"]
+    pub fn watch_readable(&mut self) -> IoResult<FdWatcher> {
+        FdWatcher::start(|cb| self.obj.watch_readable(cb))
+    }
 }
 
 impl Acceptor<TcpStream> for TcpAcceptor {
@@ -1401,4 +1438,100 @@ mod test {
         rxdone.recv();
         rxdone.recv();
     })
+
+    iotest!(fn write_vectored() {
+        let addr = next_test_ip4();
+        let ip_str = addr.ip.to_string();
+        let port = addr.port;
+        let mut acceptor = TcpListener::bind(ip_str.as_slice(), port).listen();
+
+        spawn(proc() {
+            let mut s = TcpStream::connect(ip_str.as_slice(), port).unwrap();
+            // More buffers than are sent at once, one of them bigger than the
+            // socket's buffer
+            let (one, big, tail) = ([1u8], Vec::from_elem(1 << 20, 2u8), [3u8, 4]);
+            let mut bufs = Vec::from_elem(100, one.as_slice());
+            bufs.push(&[]);
+            bufs.push(big.as_slice());
+            bufs.push(tail.as_slice());
+            s.write_vectored(bufs.as_slice()).unwrap();
+        });
+
+        let mut s = acceptor.accept().unwrap();
+        let data = s.read_to_end().unwrap();
+        assert_eq!(data.len(), 100 + (1 << 20) + 2);
+        assert!(data.slice_to(100).iter().all(|&b| b == 1));
+        assert!(data.slice(100, 100 + (1 << 20)).iter().all(|&b| b == 2));
+        assert_eq!(data.slice_from(100 + (1 << 20)), [3u8, 4].as_slice());
+    })
+
+    iotest!(fn watch_readable() {
+        let addr = next_test_ip4();
+        let ip_str = addr.ip.to_string();
+        let port = addr.port;
+        let mut a = TcpListener::bind(ip_str.as_slice(), port).listen().unwrap();
+        let mut accepts = a.watch_readable().unwrap();
+        let (tx, rx) = channel::<()>();
+        spawn(proc() {
+            let mut s = TcpStream::connect(ip_str.as_slice(), port).unwrap();
+            rx.recv();
+            s.write([1]).unwrap();
+            rx.recv();
+        });
+
+        assert!(accepts.rx.recv().readable);
+        let mut s = a.accept().unwrap();
+        a.set_timeout(Some(0));
+        assert_eq!(a.accept().err().unwrap().kind, TimedOut);
+        accepts.rearm();
+
+        // Wait for either the data or a control message, as a server would
+        let (ctl_tx, ctl) = channel::<()>();
+        let mut reads = s.watch_readable().unwrap();
+        tx.send(());
+        select! {
+            _ = reads.rx.recv() => {},
+            () = ctl.recv() => fail!("no control message was sent")
+        }
+        assert_eq!(s.read_u8(), Ok(1));
+        reads.rearm();
+        ctl_tx.send(());
+        select! {
+            _ = reads.rx.recv() => fail!("nothing else was written"),
+            () = ctl.recv() => {}
+        }
+        tx.send(());
+    } #[cfg(unix)])
+
+    // Only the native runtime can watch sockets on Windows, and not listening
+    // ones
+    #[test]
+    #[cfg(windows)]
+    fn watch_readable_native() {
+        use native;
+        let (done_tx, done_rx) = channel();
+        native::task::spawn(proc() {
+            let addr = next_test_ip4();
+            let ip_str = addr.ip.to_string();
+            let port = addr.port;
+            let mut a = TcpListener::bind(ip_str.as_slice(), port).listen().unwrap();
+            assert!(a.watch_readable().is_err());
+            let (tx, rx) = channel::<()>();
+            spawn(proc() {
+                let mut s = TcpStream::connect(ip_str.as_slice(), port).unwrap();
+                rx.recv();
+                s.write([1]).unwrap();
+                rx.recv();
+            });
+
+            let mut s = a.accept().unwrap();
+            let reads = s.watch_readable().unwrap();
+            tx.send(());
+            assert!(reads.rx.recv().readable);
+            assert_eq!(s.read_u8(), Ok(1));
+            tx.send(());
+            done_tx.send(());
+        });
+        done_rx.recv();
+    }
 }
@@ -62,6 +62,10 @@ pub struct UdpSocket {
 
 impl UdpSocket {
     /// Creates a UDP socket from the given socket address.
+    ///
+    /// On Unix, several sockets can be bound to the same multicast address,
+    /// and each of them then receives the datagrams sent to the group once it
+    /// has joined it with `join_multicast`.
     pub fn bind(addr: SocketAddr) -> IoResult<UdpSocket> {
         let SocketAddr { ip, port } = addr;
         LocalIo::maybe_raise(|io| {
@@ -596,4 +600,10 @@ mod test {
             }
         }
     })
+
+    iotest!(fn bind_multicast_twice() {
+        let addr = SocketAddr { ip: Ipv4Addr(239, 255, 0, 1), port: next_test_port() };
+        let _a = UdpSocket::bind(addr).unwrap();
+        let _b = UdpSocket::bind(addr).unwrap();
+    } #[ignore(cfg(windows))])
 }
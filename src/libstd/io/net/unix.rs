@@ -29,6 +29,7 @@ use prelude::*;
 use c_str::ToCStr;
 use clone::Clone;
 use io::{Listener, Acceptor, Reader, Writer, IoResult, IoError};
+use io::fd_watcher::FdWatcher;
 use kinds::Send;
 use boxed::Box;
 use rt::rtio::{IoFactory, LocalIo, RtioUnixListener};
@@ -121,6 +122,15 @@ impl UnixStream {
     pub fn set_write_timeout(&mut self, timeout_ms: Option<u64>) {
         self.obj.set_write_timeout(timeout_ms)
     }
+
+    /// Returns a watcher whose receiver gets an event whenever this stream has
+    /// data to read.
+    ///
+    /// For more information, see `TcpStream::watch_readable`
+    #[experimental = "the readiness interface may change"]
+    pub fn watch_readable(&mut self) -> IoResult<FdWatcher> {
+        FdWatcher::start(|cb| self.obj.watch_readable(cb))
+    }
 }
 
 impl Clone for UnixStream {
@@ -208,6 +218,15 @@ impl UnixAcceptor {
     pub fn set_timeout(&mut self, timeout_ms: Option<u64>) {
         self.obj.set_timeout(timeout_ms)
     }
+
+    /// Returns a watcher whose receiver gets an event whenever a connection is
+    /// waiting to be accepted.
+    ///
+    /// For more information, see `TcpAcceptor::watch_readable`
+    #[experimental = "the readiness interface may change"]
+    pub fn watch_readable(&mut self) -> IoResult<FdWatcher> {
+        FdWatcher::start(|cb| self.obj.watch_readable(cb))
+    }
 }
 
 impl Acceptor<UnixStream> for UnixAcceptor {
@@ -686,4 +705,23 @@ mod tests {
 
         rx2.recv();
     })
+
+    iotest!(fn watch_readable() {
+        let addr = next_test_unix();
+        let mut a = UnixListener::bind(&addr).listen().unwrap();
+        let accepts = a.watch_readable().unwrap();
+        let (tx, rx) = channel::<()>();
+        spawn(proc() {
+            let mut s = UnixStream::connect(&addr).unwrap();
+            s.write([1]).unwrap();
+            rx.recv();
+        });
+
+        assert!(accepts.rx.recv().readable);
+        let mut s = a.accept().unwrap();
+        let reads = s.watch_readable().unwrap();
+        assert!(reads.rx.recv().readable);
+        assert_eq!(s.read_u8(), Ok(1));
+        tx.send(());
+    } #[cfg(unix)])
 }
@@ -17,6 +17,20 @@ local I/O factory. There are a number of defined signals which can be caught,
 but not all signals will work across all platforms (windows doesn't have
 definitions for a number of signals.
 
+Signals are received as values on the receiver of a `Listener`, under both
+libgreen and libnative. On Windows, libnative doesn't support signals.
+
+# Coalescing
+
+A signal isn't queued each time it's raised. Like the kernel, which only keeps
+one pending instance of each signal, the runtime may merge several instances
+of a signal which are raised close together into a single value on the
+receiver. A listener only knows that a signal was raised at least once since
+it last received it, so a signal such as `ChildExit` should be treated as a
+hint to check on all of the children rather than as the exit of one of them.
+
+Each listener registered for a signal receives its own copy of the signal.
+
 */
 
 use clone::Clone;
@@ -49,7 +63,14 @@ pub enum Signum {
     /// Equivalent to SIGQUIT, delivered when the user presses Ctrl-\.
     Quit = 3i,
     /// Equivalent to SIGTSTP, delivered when the user presses Ctrl-z.
+    #[cfg(not(target_os = "macos"), not(target_os = "ios"),
+          not(target_os = "freebsd"))]
     StopTemporarily = 20i,
+    /// Equivalent to SIGTSTP, delivered when the user presses Ctrl-z.
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "ios")]
+    #[cfg(target_os = "freebsd")]
+    StopTemporarily = 18i,
     /// Equivalent to SIGUSR1.
     User1 = 10i,
     /// Equivalent to SIGUSR2.
@@ -58,6 +79,17 @@ pub enum Signum {
     /// WindowSizeChange may not be delivered in a timely manner; size change
     /// will only be detected when the cursor is being moved.
     WindowSizeChange = 28i,
+    /// Equivalent to SIGCHLD, delivered when a child process exits, or is
+    /// stopped or continued. See the notes on coalescing above.
+    #[cfg(unix, not(target_os = "macos"), not(target_os = "ios"),
+          not(target_os = "freebsd"))]
+    ChildExit = 17i,
+    /// Equivalent to SIGCHLD, delivered when a child process exits, or is
+    /// stopped or continued. See the notes on coalescing above.
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "ios")]
+    #[cfg(target_os = "freebsd")]
+    ChildExit = 20i,
 }
 
 /// Listener provides a receiver to listen for registered signals.
@@ -148,6 +180,36 @@ impl Listener {
         }
     }
 
+    /// Listen for each of the signals of `signums`. If any of them can't be
+    /// registered, the signals which were registered by this call are
+    /// unregistered and the error is returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # #![allow(unused_must_use)]
+    /// use std::io::signal::{Listener, Interrupt, HangUp, WindowSizeChange};
+    ///
+    /// let mut listener = Listener::new();
+    /// listener.register_all([Interrupt, HangUp, WindowSizeChange]);
+    /// ```
+    pub fn register_all(&mut self, signums: &[Signum]) -> io::IoResult<()> {
+        let mut added = Vec::new();
+        for &signum in signums.iter() {
+            if self.handles.iter().any(|&(sig, _)| sig == signum) { continue }
+            match self.register(signum) {
+                Ok(()) => added.push(signum),
+                Err(e) => {
+                    for &signum in added.iter() {
+                        self.unregister(signum);
+                    }
+                    return Err(e)
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Unregisters a signal. If this listener currently had a handler
     /// registered for the signal, then it will stop receiving any more
     /// notification about the signal. If the signal has already been received,
@@ -166,7 +228,8 @@ mod test_unix {
     use libc;
     use comm::Empty;
     use io::timer;
-    use super::{Listener, Interrupt};
+    use io::process::Command;
+    use super::{Listener, Interrupt, HangUp, ChildExit, WindowSizeChange};
 
     fn sigint() {
         unsafe {
@@ -174,6 +237,18 @@ mod test_unix {
         }
     }
 
+    fn sighup() {
+        unsafe {
+            libc::funcs::posix88::signal::kill(libc::getpid(), libc::SIGHUP);
+        }
+    }
+
+    fn sigwinch() {
+        unsafe {
+            libc::funcs::posix88::signal::kill(libc::getpid(), 28);
+        }
+    }
+
     #[test] #[cfg(not(target_os="android"))] // FIXME(#10378)
     fn test_io_signal_smoketest() {
         let mut signal = Listener::new();
@@ -215,6 +290,38 @@ mod test_unix {
         timer::sleep(10);
         assert_eq!(s2.rx.try_recv(), Err(Empty));
     }
+
+    #[test] #[cfg(not(target_os="android"))] // FIXME(#10378)
+    fn test_io_signal_register_all() {
+        let mut s = Listener::new();
+        s.register_all([Interrupt, HangUp]).unwrap();
+        sighup();
+        assert_eq!(s.rx.recv(), HangUp);
+        sigint();
+        assert_eq!(s.rx.recv(), Interrupt);
+    }
+
+    #[test] #[cfg(not(target_os="android"))] // FIXME(#10378)
+    fn test_io_signal_child_exit() {
+        let mut s = Listener::new();
+        s.register(ChildExit).unwrap();
+        let mut p = Command::new("true").spawn().unwrap();
+        assert_eq!(s.rx.recv(), ChildExit);
+        assert!(p.wait().unwrap().success());
+    }
+
+    #[test] #[cfg(not(target_os="android"))] // FIXME(#10378)
+    fn test_io_signal_native() {
+        use native;
+        let (tx, rx) = channel();
+        native::task::spawn(proc() {
+            let mut s = Listener::new();
+            s.register(WindowSizeChange).unwrap();
+            sigwinch();
+            tx.send(s.rx.recv());
+        });
+        assert_eq!(rx.recv(), WindowSizeChange);
+    }
 }
 
 #[cfg(test, windows)]
@@ -182,6 +182,37 @@ impl Timer {
         self.obj.period(msecs, box TimerCallback { tx: tx });
         return rx
     }
+
+    /// Cancels the outstanding oneshot or periodic notification of this timer,
+    /// if there is one. Its receiver is invalidated (the other end is closed),
+    /// while the timer itself can still be used for other timeouts.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::io::Timer;
+    ///
+    /// let mut timer = Timer::new().unwrap();
+    /// let (tx, rx) = channel::<int>();
+    /// # tx.send(1);
+    ///
+    /// // Wait for a message for at most a second
+    /// let timeout = timer.oneshot(1000);
+    /// select! {
+    ///     n = rx.recv() => {
+    ///         // the timeout isn't needed anymore
+    ///         timer.cancel();
+    ///         println!("got {}", n);
+    ///     },
+    ///     () = timeout.recv() => println!("timed out"),
+    /// }
+    ///
+    /// // The same timer is reused for the next timeout
+    /// timer.oneshot(10).recv();
+    /// ```
+    pub fn cancel(&mut self) {
+        self.obj.cancel();
+    }
 }
 
 impl Callback for TimerCallback {
@@ -192,6 +223,51 @@ impl Callback for TimerCallback {
 
 #[cfg(test)]
 mod test {
+    iotest!(fn cancel_oneshot() {
+        let mut timer = Timer::new().unwrap();
+        let rx = timer.oneshot(100000000000);
+        timer.cancel();
+        assert_eq!(rx.recv_opt(), Err(()));
+        timer.oneshot(1).recv();
+    })
+
+    iotest!(fn cancel_periodic() {
+        let mut timer = Timer::new().unwrap();
+        let rx = timer.periodic(1);
+        rx.recv();
+        timer.cancel();
+        // notifications may have been sent before the cancellation
+        loop {
+            match rx.recv_opt() {
+                Ok(()) => {}
+                Err(()) => break,
+            }
+        }
+    })
+
+    iotest!(fn cancel_nothing() {
+        let mut timer = Timer::new().unwrap();
+        timer.cancel();
+        let rx = timer.oneshot(1);
+        rx.recv();
+        timer.cancel();
+        timer.cancel();
+        timer.sleep(1);
+    })
+
+    iotest!(fn select_with_cancel() {
+        let mut timer = Timer::new().unwrap();
+        let (tx, rx) = channel();
+        tx.send(1i);
+        let timeout = timer.oneshot(100000000000);
+        select! {
+            n = rx.recv() => assert_eq!(n, 1),
+            () = timeout.recv() => fail!("timed out")
+        }
+        timer.cancel();
+        assert_eq!(timeout.recv_opt(), Err(()));
+    })
+
     iotest!(fn test_io_timer_sleep_simple() {
         let mut timer = Timer::new().unwrap();
         timer.sleep(1);
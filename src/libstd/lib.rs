@@ -68,8 +68,8 @@
 //! with file paths.
 //!
 //! `std` also includes modules for interoperating with the
-//! C language: [`c_str`](c_str/index.html) and
-//! [`c_vec`](c_vec/index.html).
+//! C language: [`c_str`](c_str/index.html),
+//! [`c_vec`](c_vec/index.html) and [`c_comm`](c_comm/index.html).
 //!
 //! ## Concurrency, I/O, and the runtime
 //!
@@ -254,6 +254,7 @@ pub mod sync;
 /* Runtime and platform support */
 
 pub mod c_vec;
+pub mod c_comm;
 pub mod dynamic_lib;
 pub mod os;
 pub mod io;
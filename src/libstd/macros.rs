@@ -307,6 +307,32 @@ macro_rules! local_data_key(
     );
 )
 
+/// Declare a typed task-local value, starting out in each task as the value
+/// returned by an initialization function.
+///
+/// See `std::local_data::TaskLocal` for how the value is used.
+///
+/// # Example
+///
+/// ```
+/// fn no_names() -> Vec<String> { Vec::new() }
+/// task_local!(static NAMES: Vec<String> = no_names)
+///
+/// NAMES.with(|names| names.push("ferris".to_string()));
+/// assert_eq!(NAMES.get().len(), 1);
+/// ```
+#[macro_export]
+macro_rules! task_local(
+    (static $name:ident: $ty:ty = $init:expr) => (
+        static $name: ::std::local_data::TaskLocal<$ty> =
+            ::std::local_data::TaskLocal { key: &::std::local_data::Key, init: $init };
+    );
+    (pub static $name:ident: $ty:ty = $init:expr) => (
+        pub static $name: ::std::local_data::TaskLocal<$ty> =
+            ::std::local_data::TaskLocal { key: &::std::local_data::Key, init: $init };
+    );
+)
+
 /// Helper macro for unwrapping `Result` values while returning early with an
 /// error if the value of the expression is `Err`. For more information, see
 /// `std::io`.
@@ -372,6 +398,55 @@ macro_rules! select {
     })
 }
 
+/// A macro to select over receivers of differing types, tagging the result.
+///
+/// Each arm names a receiver, the method to invoke on it, and a variant of a
+/// user-provided enum. The value received from whichever receiver becomes
+/// ready first is wrapped in that variant and returned, so receivers of
+/// unrelated message types can be multiplexed into one value.
+///
+/// # Example
+///
+/// ```
+/// enum Event {
+///     Count(int),
+///     Name(String),
+///     Quit(Result<(), ()>),
+/// }
+///
+/// let (tx1, rx1) = channel();
+/// let (_tx2, rx2) = channel::<String>();
+/// let (_tx3, rx3) = channel::<()>();
+/// tx1.send(3i);
+///
+/// let ev = select_any! {
+///     rx1.recv() => Count,
+///     rx2.recv() => Name,
+///     rx3.recv_opt() => Quit
+/// };
+/// match ev {
+///     Count(n) => assert_eq!(n, 3),
+///     _ => fail!(),
+/// }
+/// ```
+#[macro_export]
+#[experimental]
+macro_rules! select_any {
+    (
+        $($rx:ident.$meth:ident() => $variant:ident),+
+    ) => ({
+        use std::comm::Select;
+        let sel = Select::new();
+        $( let mut $rx = sel.handle(&$rx); )+
+        unsafe {
+            $( $rx.add(); )+
+        }
+        let ret = sel.wait();
+        $( if ret == $rx.id() { $variant($rx.$meth()) } else )+
+        { unreachable!() }
+    })
+}
+
 // When testing the standard library, we link to the liblog crate to get the
 // logging macros. In doing so, the liblog crate was linked against the real
 // version of libstd, and uses a different std::fmt module than the test crate
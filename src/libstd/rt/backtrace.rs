@@ -13,8 +13,9 @@
 #![allow(non_camel_case_types)]
 
 use collections::Collection;
+use fmt;
 use from_str::from_str;
-use io::{IoResult, Writer};
+use io::{IoResult, Writer, MemWriter};
 use iter::Iterator;
 use option::{Some, None};
 use os;
@@ -25,6 +26,39 @@ use unicode::char::UnicodeChar;
 
 pub use self::imp::write;
 
+/// A backtrace of the current task, captured with `Backtrace::capture`.
+///
+/// Capturing a backtrace only records the addresses of its frames, which is
+/// cheap compared to looking up their symbols. That is done when the backtrace
+/// is printed, if it ever is. On Windows, the backtrace is printed as it's
+/// captured instead.
+#[deriving(Clone)]
+pub struct Backtrace {
+    trace: imp::Trace,
+}
+
+impl Backtrace {
+    /// Captures a backtrace of the current task.
+    #[inline(never)]
+    pub fn capture() -> Backtrace {
+        Backtrace { trace: imp::capture() }
+    }
+
+    /// Writes the backtrace to `w`, in the same format as the backtraces
+    /// printed when a task fails.
+    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+        self.trace.write(w)
+    }
+}
+
+impl fmt::Show for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut w = MemWriter::new();
+        let _ = self.write(&mut w);
+        f.write(w.get_ref())
+    }
+}
+
 // For now logging is turned off by default, and this function checks to see
 // whether the magical environment variable is present to see if it's turned on.
 pub fn log_enabled() -> bool {
@@ -237,12 +271,36 @@ fn demangle(writer: &mut Writer, s: &str) -> IoResult<()> {
 #[cfg(unix)]
 mod imp {
     use c_str::CString;
+    use collections::{Collection, MutableSeq};
     use io::{IoResult, Writer};
+    use iter::Iterator;
     use libc;
     use mem;
     use option::{Some, None, Option};
     use result::{Ok, Err};
     use rt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+    use vec::Vec;
+
+    // When using libbacktrace, we use some necessary global state, so we
+    // need to prevent more than one thread from looking up symbols at once.
+    static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+
+    // The addresses of the frames of a captured backtrace
+    #[deriving(Clone)]
+    pub struct Trace {
+        frames: Vec<uint>,
+    }
+
+    impl Trace {
+        pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+            let _g = unsafe { LOCK.lock() };
+            try!(writeln!(w, "stack backtrace:"));
+            for (i, &ip) in self.frames.iter().enumerate() {
+                try!(print(w, i as int + 1, ip as *mut libc::c_void));
+            }
+            Ok(())
+        }
+    }
 
     /// As always - iOS on arm uses SjLj exceptions and
     /// _Unwind_Backtrace is even not available there. Still,
@@ -268,7 +326,6 @@ mod imp {
         // while it doesn't requires lock for work as everything is
         // local, it still displays much nicer backtraces when a
         // couple of tasks fail simultaneously
-        static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
         let _g = unsafe { LOCK.lock() };
 
         try!(writeln!(w, "stack backtrace:"));
@@ -283,6 +340,51 @@ mod imp {
         }))
     }
 
+    #[cfg(target_os = "ios", target_arch = "arm")]
+    #[inline(never)]
+    pub fn capture() -> Trace {
+        use iter::range;
+
+        extern {
+            fn backtrace(buf: *mut *mut libc::c_void,
+                         sz: libc::c_int) -> libc::c_int;
+        }
+
+        static SIZE: libc::c_int = 100;
+        let mut buf: [*mut libc::c_void, ..SIZE] = unsafe {mem::zeroed()};
+        let cnt = unsafe { backtrace(buf.as_mut_ptr(), SIZE) as uint};
+
+        // skipping the first one as it is capture itself
+        Trace { frames: range(1, cnt).map(|i| buf[i] as uint).collect() }
+    }
+
+    #[cfg(not(target_os = "ios", target_arch = "arm"))]
+    #[inline(never)]
+    pub fn capture() -> Trace {
+        let mut trace = Trace { frames: Vec::new() };
+        unsafe {
+            uw::_Unwind_Backtrace(trace_fn,
+                                  &mut trace as *mut Trace as *mut libc::c_void);
+        }
+        return trace;
+
+        extern fn trace_fn(ctx: *mut uw::_Unwind_Context,
+                           arg: *mut libc::c_void) -> uw::_Unwind_Reason_Code {
+            let trace: &mut Trace = unsafe { mem::transmute(arg) };
+            let ip = unsafe { uw::_Unwind_GetIP(ctx) as *mut libc::c_void };
+            // See `write` for why the enclosing function is looked up
+            let ip = if cfg!(target_os = "macos") || cfg!(target_os = "ios") {
+                ip
+            } else {
+                unsafe { uw::_Unwind_FindEnclosingFunction(ip) }
+            };
+            // Keep as many frames as `write` would print
+            if trace.frames.len() >= 100 { return uw::_URC_FAILURE }
+            trace.frames.push(ip as uint);
+            uw::_URC_NO_REASON
+        }
+    }
+
     #[cfg(not(target_os = "ios", target_arch = "arm"))]
     #[inline(never)] // if we know this is a function call, we can skip it when
                      // tracing
@@ -295,12 +397,10 @@ mod imp {
             last_error: Option<IoError>,
         }
 
-        // When using libbacktrace, we use some necessary global state, so we
-        // need to prevent more than one thread from entering this block. This
-        // is semi-reasonable in terms of printing anyway, and we know that all
+        // Only one thread may use libbacktrace at once. This is
+        // semi-reasonable in terms of printing anyway, and we know that all
         // I/O done here is blocking I/O, not green I/O, so we don't have to
         // worry about this being a native vs green mutex.
-        static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
         let _g = unsafe { LOCK.lock() };
 
         try!(writeln!(w, "stack backtrace:"));
@@ -655,7 +755,7 @@ mod imp {
     use c_str::CString;
     use core_collections::Collection;
     use intrinsics;
-    use io::{IoResult, Writer};
+    use io::{IoResult, Writer, MemWriter};
     use libc;
     use mem;
     use ops::Drop;
@@ -664,9 +764,30 @@ mod imp {
     use result::{Ok, Err};
     use rt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
     use slice::ImmutableVector;
-    use str::StrSlice;
+    use str::{StrSlice, StrAllocating};
+    use string::String;
     use dynamic_lib::DynamicLibrary;
 
+    // A captured backtrace, printed as it was captured: dbghelp has to be set
+    // up to walk the stack anyway, so the symbols are looked up at the same time
+    #[deriving(Clone)]
+    pub struct Trace {
+        text: String,
+    }
+
+    impl Trace {
+        pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+            w.write_str(self.text.as_slice())
+        }
+    }
+
+    #[inline(never)]
+    pub fn capture() -> Trace {
+        let mut w = MemWriter::new();
+        let _ = write(&mut w);
+        Trace { text: String::from_utf8_lossy(w.get_ref()).into_string() }
+    }
+
     #[allow(non_snake_case_functions)]
     extern "system" {
         fn GetCurrentProcess() -> libc::HANDLE;
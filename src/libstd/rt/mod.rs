@@ -56,18 +56,29 @@ Several modules in `core` are clients of `rt`:
 // FIXME: this should not be here.
 #![allow(missing_doc)]
 
+use boxed::Box;
+use collections::Collection;
+use comm;
 use failure;
+use fmt::Show;
+use io;
+use io::Writer;
+use iter::Iterator;
+use kinds::{Send, Share};
 use rustrt;
 
 // Reexport some of our utilities which are expected by other crates.
-pub use self::util::{default_sched_threads, min_stack, running_on_valgrind};
+pub use self::util::{default_sched_threads, set_num_schedulers};
+pub use self::util::{min_stack, running_on_valgrind};
 
 // Reexport functionality from librustrt and other crates underneath the
 // standard library which work together to create the entire runtime.
 pub use alloc::{heap, libc_heap};
-pub use rustrt::{task, local, mutex, exclusive, stack, args, rtio, thread};
+pub use rustrt::{task, local, mutex, exclusive, stack, args, rtio, thread, time};
 pub use rustrt::{Stdio, Stdout, Stderr, begin_unwind, begin_unwind_fmt};
 pub use rustrt::{bookkeeping, at_exit, unwind, DEFAULT_ERROR_CODE, Runtime};
+pub use rustrt::{hooks, sched_stats};
+pub use rustrt::sched_stats::{stats, SchedStats};
 
 // Simple backtrace functionality (to print on failure)
 pub mod backtrace;
@@ -84,8 +95,100 @@ mod util;
 pub fn init(argc: int, argv: *const *const u8) {
     rustrt::init(argc, argv);
     unsafe { unwind::register(failure::on_fail); }
+    comm::debug::set_backtrace_hook(capture_backtrace);
+    if comm::debug::enabled() {
+        comm::set_discard_hook(log_discarded);
+        rustrt::hooks::on_exit(report_blocked);
+        report_blocked_on_signal();
+    }
 }
 
+// Captures the creation of channels which are being debugged
+fn capture_backtrace() -> Box<Show + Send + Share> {
+    box backtrace::Backtrace::capture() as Box<Show + Send + Share>
+}
+
+// Tells of the messages lost with a receiver when channels are being debugged
+fn log_discarded(discarded: &comm::Discarded) {
+    let mut err = io::stdio::stderr_raw();
+    let _ = writeln!(err, "{}", discarded);
+}
+
+// Tells of the tasks still blocked on channels as the main task exits, as
+// they're often what keeps the program from terminating. This may be called
+// off of any task, or on one without I/O, so it writes to stderr directly.
+fn report_blocked() {
+    let blocked = comm::debug::blocked();
+    if blocked.len() == 0 { return }
+    let mut err = Stderr;
+    let _ = writeln!(err, "main task exited with {} tasks blocked on channels:",
+                     blocked.len());
+    for info in blocked.iter() {
+        let _ = writeln!(err, "    {}", info);
+    }
+}
+
+// Has the blocked tasks reported each time the process receives the signal
+// numbered in RUST_COMM_DEBUG_SIGNAL. The handler only writes to a pipe, and
+// a thread of its own reads from it and writes the report.
+#[cfg(unix)]
+fn report_blocked_on_signal() {
+    use from_str::from_str;
+    use libc;
+    use libc::types::os::common::posix01::sighandler_t;
+    use libc::funcs::posix01::signal::signal;
+    use option::{Some, None};
+    use os;
+    use str::Str;
+
+    static mut PIPE: libc::c_int = -1;
+
+    extern "C" fn on_signal(_signum: libc::c_int) {
+        let b = 0u8;
+        unsafe { libc::write(PIPE, &b as *const u8 as *const libc::c_void, 1); }
+    }
+
+    let signum: libc::c_int = match os::getenv("RUST_COMM_DEBUG_SIGNAL") {
+        Some(s) => match from_str(s.as_slice()) {
+            Some(n) => n,
+            None => return,
+        },
+        None => return,
+    };
+    let mut fds = [0 as libc::c_int, ..2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 { return }
+        PIPE = fds[1];
+        // SIG_ERR
+        if signal(signum, on_signal as sighandler_t) == !0 { return }
+    }
+    let reader = fds[0];
+    thread::Thread::spawn(proc() {
+        loop {
+            let mut b = 0u8;
+            let n = unsafe {
+                libc::read(reader, &mut b as *mut u8 as *mut libc::c_void, 1)
+            };
+            if n == 1 {
+                let blocked = comm::debug::blocked();
+                let mut err = Stderr;
+                let _ = writeln!(err, "{} tasks blocked on channels:",
+                                 blocked.len());
+                for info in blocked.iter() {
+                    let _ = writeln!(err, "    {}", info);
+                }
+            } else if n < 0 && os::errno() == libc::EINTR as int {
+                continue
+            } else {
+                break
+            }
+        }
+    });
+}
+
+#[cfg(windows)]
+fn report_blocked_on_signal() {}
+
 /// One-time runtime cleanup.
 ///
 /// This function is unsafe because it performs no checks to ensure that the
@@ -54,9 +54,32 @@ pub fn min_stack() -> uint {
     return amt;
 }
 
-/// Get's the number of scheduler threads requested by the environment
-/// either `RUST_THREADS` or `num_cpus`.
+// The number of scheduler threads given to `set_num_schedulers`, 0 if it
+// hasn't been called
+static mut SCHED_THREADS: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+/// Sets the number of scheduler threads which pools of schedulers are created
+/// with from now on, overriding `RUST_THREADS`.
+///
+/// This doesn't resize pools which already exist, including the one running
+/// `main` when it's green: call `resize` on the `SchedPool` for that.
+///
+/// # Failure
+///
+/// This function fails if `n` is 0.
+pub fn set_num_schedulers(n: uint) {
+    assert!(n > 0, "there must be at least one scheduler thread");
+    unsafe { SCHED_THREADS.store(n, atomics::SeqCst); }
+}
+
+/// Get's the number of scheduler threads requested by the program with
+/// `set_num_schedulers`, or else by the environment, either `RUST_THREADS`
+/// or `num_cpus`.
 pub fn default_sched_threads() -> uint {
+    match unsafe { SCHED_THREADS.load(atomics::SeqCst) } {
+        0 => {}
+        n => return n,
+    }
     match os::getenv("RUST_THREADS") {
         Some(nstr) => {
             let opt_n: Option<uint> = FromStr::from_str(nstr.as_slice());
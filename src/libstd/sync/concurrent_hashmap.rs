@@ -0,0 +1,213 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A hash map which can be shared among tasks
+//!
+//! Sharing a `HashMap` behind a single `RWLock` serializes every writer, and
+//! every reader while a write is happening. A `ConcurrentHashMap` instead
+//! splits its keys among a number of shards, each of which is a `HashMap`
+//! with its own lock, so that tasks working with keys in different shards
+//! don't contend with one another.
+//!
+//! Every operation locks exactly one shard, so each one is atomic with
+//! respect to the others. Operations which look at the whole map, like `len`,
+//! lock the shards one after another and don't see a consistent snapshot if
+//! the map is being modified concurrently.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::{Arc, ConcurrentHashMap};
+//!
+//! let counts = Arc::new(ConcurrentHashMap::new());
+//! let (tx, rx) = channel();
+//! for _ in range(0u, 4) {
+//!     let (counts, tx) = (counts.clone(), tx.clone());
+//!     spawn(proc() {
+//!         for word in ["apple", "pear", "apple"].iter() {
+//!             counts.upsert(*word, 1u, |_, n| *n += 1);
+//!         }
+//!         tx.send(());
+//!     });
+//! }
+//! for _ in range(0u, 4) { rx.recv(); }
+//! assert_eq!(counts.find_copy(&"apple"), Some(8));
+//! assert_eq!(counts.find_copy(&"pear"), Some(4));
+//! ```
+
+use core::prelude::*;
+
+use collections::{Collection, Mutable, Map, MutableMap, HashMap};
+use core_sync::RWLock;
+use hash::{Hash, Hasher, RandomSipHasher};
+use vec::Vec;
+
+// The number of shards used by `ConcurrentHashMap::new`
+static DEFAULT_SHARDS: uint = 16;
+
+/// A hash map split into independently locked shards.
+pub struct ConcurrentHashMap<K, V> {
+    // Picks the shard of a key. The shards have their own hashers, so which
+    // shard a key is in doesn't tell anything about where it is in the shard.
+    hasher: RandomSipHasher,
+    shards: Vec<RWLock<HashMap<K, V>>>,
+}
+
+impl<K: Eq + Hash + Send + Share, V: Send + Share> ConcurrentHashMap<K, V> {
+    /// Creates an empty map with the default number of shards.
+    pub fn new() -> ConcurrentHashMap<K, V> {
+        ConcurrentHashMap::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates an empty map with `shards` shards. More shards means less
+    /// contention among tasks, at the cost of some memory.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if `shards` is 0.
+    pub fn with_shards(shards: uint) -> ConcurrentHashMap<K, V> {
+        assert!(shards > 0);
+        ConcurrentHashMap {
+            hasher: RandomSipHasher::new(),
+            shards: Vec::from_fn(shards, |_| RWLock::new(HashMap::new())),
+        }
+    }
+
+    fn shard<'a>(&'a self, k: &K) -> &'a RWLock<HashMap<K, V>> {
+        let i = self.hasher.hash(k) % self.shards.len() as u64;
+        self.shards.get(i as uint)
+    }
+
+    /// Inserts a key-value pair into the map. Returns `true` if the key did
+    /// not already exist in the map, and `false` if it did, in which case its
+    /// value is replaced.
+    pub fn insert(&self, k: K, v: V) -> bool {
+        self.shard(&k).write().insert(k, v)
+    }
+
+    /// Inserts a key-value pair into the map, returning the value the key
+    /// previously had, if any.
+    pub fn swap(&self, k: K, v: V) -> Option<V> {
+        self.shard(&k).write().swap(k, v)
+    }
+
+    /// Inserts `v` if `k` isn't in the map, and otherwise updates the value
+    /// of `k` in place with `f`. The shard holding `k` is locked while `f`
+    /// runs, so `f` must not use the map.
+    pub fn upsert(&self, k: K, v: V, f: |&K, &mut V|) {
+        self.shard(&k).write().insert_or_update_with(k, v, f);
+    }
+
+    /// Removes a key from the map. Returns `true` if the key was in the map.
+    pub fn remove(&self, k: &K) -> bool {
+        self.shard(k).write().remove(k)
+    }
+
+    /// Removes a key from the map, returning its value if it was in the map.
+    pub fn pop(&self, k: &K) -> Option<V> {
+        self.shard(k).write().pop(k)
+    }
+
+    /// Returns `true` if the map contains a value for `k`.
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.shard(k).read().contains_key(k)
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> uint {
+        self.shards.iter().fold(0, |n, shard| n + shard.read().len())
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.read().is_empty())
+    }
+
+    /// Removes all elements from the map.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.write().clear();
+        }
+    }
+}
+
+impl<K: Eq + Hash + Send + Share, V: Clone + Send + Share> ConcurrentHashMap<K, V> {
+    /// Returns a copy of the value of `k`, if it is in the map.
+    pub fn find_copy(&self, k: &K) -> Option<V> {
+        self.shard(k).read().find_copy(k)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    use sync::Arc;
+    use super::ConcurrentHashMap;
+
+    #[test]
+    fn smoke() {
+        let map = ConcurrentHashMap::with_shards(4);
+        assert!(map.is_empty());
+        assert!(map.insert(1i, 2i));
+        assert!(!map.insert(1, 3));
+        assert_eq!(map.find_copy(&1), Some(3));
+        assert_eq!(map.swap(1, 4), Some(3));
+        assert_eq!(map.swap(2, 5), None);
+        assert!(map.contains_key(&2));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.pop(&2), Some(5));
+        assert!(map.remove(&1));
+        assert!(!map.remove(&1));
+        assert!(map.is_empty());
+        assert_eq!(map.find_copy(&1), None);
+    }
+
+    #[test]
+    fn upsert() {
+        let map = ConcurrentHashMap::new();
+        map.upsert("a", 1i, |_, v| *v += 1);
+        map.upsert("a", 1i, |_, v| *v += 1);
+        map.upsert("b", 1i, |_, v| *v += 1);
+        assert_eq!(map.find_copy(&"a"), Some(2));
+        assert_eq!(map.find_copy(&"b"), Some(1));
+        map.clear();
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn many_tasks() {
+        let map = Arc::new(ConcurrentHashMap::new());
+        let counts = Arc::new(ConcurrentHashMap::new());
+        let (tx, rx) = channel();
+        for i in range(0u, 8) {
+            let (map, counts, tx) = (map.clone(), counts.clone(), tx.clone());
+            spawn(proc() {
+                for j in range(0u, 100) {
+                    assert!(map.insert(i * 100 + j, j));
+                    counts.upsert(j, 1u, |_, n| *n += 1);
+                }
+                tx.send(());
+            });
+        }
+        for _ in range(0u, 8) { rx.recv(); }
+
+        assert_eq!(map.len(), 800);
+        assert_eq!(map.find_copy(&799), Some(99));
+        for j in range(0u, 100) {
+            assert_eq!(counts.find_copy(&j), Some(8));
+        }
+    }
+
+    #[test] #[should_fail]
+    fn zero_shards() {
+        let _map: ConcurrentHashMap<int, int> = ConcurrentHashMap::with_shards(0);
+    }
+}
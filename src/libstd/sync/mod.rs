@@ -17,14 +17,22 @@
 
 #![experimental]
 
-pub use core_sync::{atomics, deque, mpmc_bounded_queue, mpsc_queue, spsc_queue};
-pub use core_sync::{Arc, Weak, Mutex, MutexGuard, Condvar, Barrier};
+pub use core_sync::{atomics, backoff, deque, epoch, hazard};
+pub use core_sync::{lockfree, mpmc_bounded_queue, mpsc_queue, spsc_queue};
+pub use core_sync::{Arc, Weak, Mutex, MutexGuard, Condvar, Barrier, WaitGroup};
 pub use core_sync::{RWLock, RWLockReadGuard, RWLockWriteGuard};
+pub use core_sync::{RWLockPreference, NoPreference, PreferWriters};
 pub use core_sync::{Semaphore, SemaphoreGuard};
-pub use core_sync::one::{Once, ONCE_INIT};
+pub use core_sync::{park, unparker, Unparker};
+pub use core_sync::{SeqLock, ArcCell, ShardedCounter};
+pub use core_sync::one::{Once, ONCE_INIT, OnceCell};
 
+pub use self::concurrent_hashmap::ConcurrentHashMap;
 pub use self::future::Future;
 pub use self::task_pool::TaskPool;
 
+pub mod par;
+
+mod concurrent_hashmap;
 mod future;
 mod task_pool;
@@ -0,0 +1,168 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Data-parallel operations on vectors.
+//!
+//! The elements of the vector are split into chunks, which are put on a
+//! work-stealing deque. The calling task and the tasks of a `TaskPool`, one
+//! per scheduler thread, take chunks off the deque until it's empty, so that a
+//! task which is done early with its chunks takes over some of the others'.
+//! The results of the chunks are sent back to the calling task, which puts
+//! them back in order.
+//!
+//! The function applied to the elements is a plain function, as it's called
+//! from several tasks at once.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::par;
+//!
+//! fn square(x: uint) -> uint { x * x }
+//!
+//! let squares = par::map(vec![1u, 2, 3, 4], square);
+//! assert_eq!(squares, vec![1, 4, 9, 16]);
+//! ```
+
+use core::prelude::*;
+
+use cmp;
+use comm::channel;
+use rt;
+use sync::TaskPool;
+use sync::deque::{BufferPool, Data, Abort, Empty};
+use vec::Vec;
+
+// How many chunks the elements are split into for each task, so that tasks
+// which are slowed down can be helped by the others
+static CHUNKS_PER_TASK: uint = 4;
+
+/// Applies `f` to each element of `v` in parallel, returning the results in
+/// the order of the elements.
+///
+/// # Failure
+///
+/// This function fails if `f` fails on some element.
+pub fn map<T: Send, U: Send>(v: Vec<T>, f: fn(T) -> U) -> Vec<U> {
+    let len = v.len();
+    if len == 0 { return Vec::new() }
+    let tasks = cmp::min(rt::default_sched_threads(), len);
+    let chunk_size = cmp::max(len / (tasks * CHUNKS_PER_TASK), 1);
+
+    let deques = BufferPool::new();
+    let (worker, stealer) = deques.deque();
+    let mut chunks = 0u;
+    let mut elements = v.move_iter();
+    loop {
+        let chunk: Vec<T> = elements.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() { break }
+        worker.push((chunks, chunk));
+        chunks += 1;
+    }
+
+    // The calling task works on chunks too, so one task fewer is needed. The
+    // pool's tasks go on with their jobs after it's dropped.
+    let (tx, rx) = channel();
+    if tasks > 1 {
+        let helpers = TaskPool::new(tasks - 1);
+        for _ in range(0, tasks - 1) {
+            let (stealer, tx) = (stealer.clone(), tx.clone());
+            helpers.execute(proc() {
+                loop {
+                    match stealer.steal() {
+                        Data((i, chunk)) => tx.send((i, map_chunk(chunk, f))),
+                        Abort => {}
+                        Empty => break,
+                    }
+                }
+            });
+        }
+    }
+    drop(tx);
+
+    let mut results = Vec::from_fn(chunks, |_| None);
+    loop {
+        match worker.pop() {
+            Some((i, chunk)) => *results.get_mut(i) = Some(map_chunk(chunk, f)),
+            None => break,
+        }
+    }
+    // A job which fails drops its sender without sending the result of its
+    // chunk, which leaves a hole once all of them are done
+    loop {
+        match rx.recv_opt() {
+            Ok((i, result)) => *results.get_mut(i) = Some(result),
+            Err(()) => break,
+        }
+    }
+    if results.iter().any(|r| r.is_none()) {
+        fail!("par::map: the function failed on some element");
+    }
+
+    let mut ret = Vec::with_capacity(len);
+    for result in results.move_iter() {
+        ret.push_all_move(result.unwrap());
+    }
+    ret
+}
+
+/// Calls `f` on each element of `v` in parallel, returning once all of the
+/// calls have returned.
+///
+/// # Failure
+///
+/// This function fails if `f` fails on some element.
+pub fn for_each<T: Send>(v: Vec<T>, f: fn(T)) {
+    map(v, f);
+}
+
+fn map_chunk<T, U>(chunk: Vec<T>, f: fn(T) -> U) -> Vec<U> {
+    chunk.move_iter().map(|x| f(x)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use super::{map, for_each};
+    use sync::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+
+    fn double(x: uint) -> uint { x * 2 }
+
+    #[test]
+    fn test_map_keeps_order() {
+        let v = range(0u, 1000).collect();
+        assert_eq!(map(v, double), range(0u, 1000).map(double).collect());
+        assert_eq!(map(vec![21u], double), vec![42u]);
+        assert_eq!(map(Vec::new(), double), Vec::new());
+    }
+
+    static mut SUM: AtomicUint = INIT_ATOMIC_UINT;
+
+    fn add(x: uint) {
+        unsafe { SUM.fetch_add(x, SeqCst); }
+    }
+
+    #[test]
+    fn test_for_each() {
+        for_each(range(1u, 101).collect(), add);
+        assert_eq!(unsafe { SUM.load(SeqCst) }, 5050);
+    }
+
+    fn fail_on_7(x: uint) -> uint {
+        if x == 7 { fail!("seven") }
+        x
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_failure() {
+        map(range(0u, 100).collect(), fail_on_7);
+    }
+}
@@ -12,91 +12,230 @@
 
 use core::prelude::*;
 
-use task;
+use collections::{Collection, Deque, MutableSeq, RingBuf};
+use comm::{channel, Sender, Receiver};
 use task::spawn;
 use vec::Vec;
-use comm::{channel, Sender};
-
-enum Msg<T> {
-    Execute(proc(&T):Send),
-    Quit
-}
 
 /// A task pool used to execute functions in parallel.
-pub struct TaskPool<T> {
-    channels: Vec<Sender<Msg<T>>>,
-    next_index: uint,
+///
+/// A fixed number of worker tasks run the jobs given to the pool, so a job is
+/// run by whichever worker becomes free first. A job which fails only takes
+/// its own worker down, and the pool spawns a new worker to take its place.
+///
+/// The pool and its workers all send to a single dispatching task over one
+/// shared channel: the pool sends it jobs, and idle workers send it a channel
+/// to hand them their next job over. The dispatcher queues the jobs which
+/// arrive while every worker is busy.
+///
+/// Dropping the pool lets the workers finish the jobs which were already
+/// queued and then exit, without waiting for them. `join` does the same but
+/// waits for the workers to exit.
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::TaskPool;
+///
+/// let pool = TaskPool::new(4u);
+///
+/// let (tx, rx) = channel();
+/// for _ in range(0u, 8) {
+///     let tx = tx.clone();
+///     pool.execute(proc() {
+///         tx.send(1u);
+///     });
+/// }
+///
+/// assert_eq!(rx.iter().take(8u).fold(0, |a, b| a + b), 8u);
+/// ```
+pub struct TaskPool {
+    dispatcher: Sender<Message>,
+    // The dispatcher and every worker hold a sender, so this disconnects once
+    // all of them have exited
+    done: Receiver<()>,
 }
 
-#[unsafe_destructor]
-impl<T> Drop for TaskPool<T> {
-    fn drop(&mut self) {
-        for channel in self.channels.mut_iter() {
-            channel.send(Quit);
-        }
-    }
+enum Message {
+    // A job from the pool
+    Job(proc():Send),
+    // A worker is idle and waiting for a job on this channel
+    Idle(Sender<proc():Send>),
+    // The pool is gone, the workers exit once the queue is empty
+    Shutdown,
 }
 
-impl<T> TaskPool<T> {
-    /// Spawns a new task pool with `n_tasks` tasks. The provided
-    /// `init_fn_factory` returns a function which, given the index of the
-    /// task, should return local data to be kept around in that task.
+impl TaskPool {
+    /// Spawns a new task pool with `n_tasks` tasks.
     ///
     /// # Failure
     ///
     /// This function will fail if `n_tasks` is less than 1.
-    pub fn new(n_tasks: uint,
-               init_fn_factory: || -> proc(uint):Send -> T)
-               -> TaskPool<T> {
+    pub fn new(n_tasks: uint) -> TaskPool {
         assert!(n_tasks >= 1);
 
-        let channels = Vec::from_fn(n_tasks, |i| {
-            let (tx, rx) = channel::<Msg<T>>();
-            let init_fn = init_fn_factory();
-
-            let task_body = proc() {
-                let local_data = init_fn(i);
-                loop {
-                    match rx.recv() {
-                        Execute(f) => f(&local_data),
-                        Quit => break
-                    }
-                }
-            };
-
-            // Run on this scheduler.
-            task::spawn(task_body);
-
-            tx
-        });
-
-        return TaskPool {
-            channels: channels,
-            next_index: 0,
-        };
+        let (tx, rx) = channel::<Message>();
+        let (done_tx, done_rx) = channel::<()>();
+
+        for _ in range(0, n_tasks) {
+            spawn_worker(tx.clone(), done_tx.clone());
+        }
+        spawn(proc() dispatch(rx, n_tasks, done_tx));
+
+        TaskPool { dispatcher: tx, done: done_rx }
     }
 
-    /// Executes the function `f` on a task in the pool. The function
-    /// receives a reference to the local data returned by the `init_fn`.
-    pub fn execute(&mut self, f: proc(&T):Send) {
-        self.channels.get(self.next_index).send(Execute(f));
-        self.next_index += 1;
-        if self.next_index == self.channels.len() { self.next_index = 0; }
+    /// Queues the job `f`, to be run by the next free task of the pool.
+    pub fn execute(&self, f: proc():Send) {
+        self.dispatcher.send(Job(f));
+    }
+
+    /// Shuts the pool down, waiting until its tasks have run all of the jobs
+    /// which were queued and have exited.
+    pub fn join(self) {
+        let _ = self.dispatcher.send_opt(Shutdown);
+        loop {
+            match self.done.recv_opt() {
+                Ok(()) => {}
+                Err(()) => break,
+            }
+        }
     }
 }
 
-#[test]
-fn test_task_pool() {
-    let f: || -> proc(uint):Send -> uint = || { proc(i) i };
-    let mut pool = TaskPool::new(4, f);
-    for _ in range(0u, 8) {
-        pool.execute(proc(i) println!("Hello from thread {}!", *i));
+impl Drop for TaskPool {
+    fn drop(&mut self) {
+        // After a `join` the dispatcher is gone already
+        let _ = self.dispatcher.send_opt(Shutdown);
     }
 }
 
-#[test]
-#[should_fail]
-fn test_zero_tasks_failure() {
-    let f: || -> proc(uint):Send -> uint = || { proc(i) i };
-    TaskPool::new(0, f);
+// Hands the jobs to idle workers, queueing them while there are none. Once
+// the pool is gone and the queue is empty, the idle workers are sent away,
+// and this returns once all `workers` have gone.
+fn dispatch(rx: Receiver<Message>, workers: uint, _done: Sender<()>) {
+    let mut jobs = RingBuf::new();
+    let mut idle = Vec::new();
+    let mut shutdown = false;
+    let mut remaining = workers;
+    while remaining > 0 {
+        match rx.recv() {
+            Job(job) => jobs.push(job),
+            Idle(worker) => idle.push(worker),
+            Shutdown => shutdown = true,
+        }
+        while !jobs.is_empty() && !idle.is_empty() {
+            let job = jobs.pop_front().unwrap();
+            // A worker only asks for a job once it's waiting for it
+            idle.pop().unwrap().send(job);
+        }
+        if shutdown && jobs.is_empty() {
+            // Dropping their channel tells idle workers to exit
+            remaining -= idle.len();
+            idle.truncate(0);
+        }
+    }
+}
+
+// Watches over a worker, and spawns a new one in its place if a job fails
+struct Sentinel {
+    dispatcher: Sender<Message>,
+    done: Sender<()>,
+    active: bool,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if self.active {
+            // The replacement gets its own sender of `done` before this one
+            // goes away, so `join` can't see the pool as empty in between
+            spawn_worker(self.dispatcher.clone(), self.done.clone());
+        }
+    }
+}
+
+fn spawn_worker(dispatcher: Sender<Message>, done: Sender<()>) {
+    spawn(proc() {
+        let mut sentinel = Sentinel {
+            dispatcher: dispatcher,
+            done: done,
+            active: true,
+        };
+        loop {
+            // If the dispatcher was gone, so would be `tx`
+            let (tx, rx) = channel();
+            let _ = sentinel.dispatcher.send_opt(Idle(tx));
+            match rx.recv_opt() {
+                Ok(job) => job(),
+                Err(()) => break,
+            }
+        }
+        sentinel.active = false;
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use super::TaskPool;
+
+    #[test]
+    fn test_task_pool() {
+        let pool = TaskPool::new(4);
+        let (tx, rx) = channel();
+        for i in range(0u, 8) {
+            let tx = tx.clone();
+            pool.execute(proc() tx.send(i));
+        }
+        let mut got: Vec<uint> = rx.iter().take(8).collect();
+        got.sort();
+        assert_eq!(got, range(0u, 8).collect());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_zero_tasks_failure() {
+        TaskPool::new(0);
+    }
+
+    #[test]
+    fn test_failing_jobs_are_isolated() {
+        let pool = TaskPool::new(2);
+        for _ in range(0u, 4) {
+            pool.execute(proc() fail!("job failed"));
+        }
+        let (tx, rx) = channel();
+        for _ in range(0u, 4) {
+            let tx = tx.clone();
+            pool.execute(proc() tx.send(1u));
+        }
+        assert_eq!(rx.iter().take(4).fold(0, |a, b| a + b), 4);
+    }
+
+    #[test]
+    fn test_join_runs_queued_jobs() {
+        let pool = TaskPool::new(1);
+        let (tx, rx) = channel();
+        for i in range(0u, 10) {
+            let tx = tx.clone();
+            pool.execute(proc() tx.send(i));
+        }
+        pool.execute(proc() fail!());
+        drop(tx);
+        pool.join();
+        assert_eq!(rx.iter().count(), 10);
+    }
+
+    #[test]
+    fn test_drop_runs_queued_jobs() {
+        let pool = TaskPool::new(1);
+        let (tx, rx) = channel();
+        for i in range(0u, 10) {
+            let tx = tx.clone();
+            pool.execute(proc() tx.send(i));
+        }
+        drop(pool);
+        drop(tx);
+        assert_eq!(rx.iter().count(), 10);
+    }
 }
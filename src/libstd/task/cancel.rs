@@ -0,0 +1,212 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Cooperative cancellation
+//!
+//! Tasks can't be killed from the outside. Instead, a task which should stop
+//! when asked to is handed a `CancelToken`, which it checks from time to time
+//! with `is_cancelled`. The blocking operations of the token, `recv` and
+//! `sleep`, return early once the token is cancelled, and `cancelled_port`
+//! gives a port which can be waited on with `select!` along with others.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::task::CancelToken;
+//!
+//! let token = CancelToken::new();
+//! let (tx, rx) = channel::<uint>();
+//! let worker = token.clone();
+//! spawn(proc() {
+//!     // Stops either when the channel has a value or when it's cancelled
+//!     loop {
+//!         match worker.recv(&rx) {
+//!             Some(n) => println!("got {}", n),
+//!             None => break,
+//!         }
+//!     }
+//! });
+//!
+//! tx.send(1);
+//! token.cancel();
+//! ```
+
+use clone::Clone;
+use collections::MutableSeq;
+use comm::{channel, Receiver, Select, Sender};
+use io::Timer;
+use kinds::Send;
+use mem;
+use option::{Option, Some, None};
+use sync::Arc;
+use sync::Mutex;
+use sync::atomics::{AtomicBool, AtomicUint, SeqCst};
+use vec::Vec;
+
+/// A handle through which a task can be asked to stop what it's doing.
+///
+/// Clones of a token share their state: cancelling one of them cancels all of
+/// them. Once cancelled, a token stays cancelled.
+#[deriving(Clone)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    cancelled: AtomicBool,
+    next_id: AtomicUint,
+    // The ports waiting for cancellation, by id
+    waiters: Mutex<Vec<(uint, Sender<()>)>>,
+}
+
+impl CancelToken {
+    /// Creates a token which isn't cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                next_id: AtomicUint::new(0),
+                waiters: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    /// Cancels the token, waking up the tasks blocked on it.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, SeqCst) { return }
+        let waiters = {
+            let mut waiters = self.inner.waiters.lock();
+            mem::replace(&mut *waiters, Vec::new())
+        };
+        for (_, tx) in waiters.move_iter() {
+            let _ = tx.send_opt(());
+        }
+    }
+
+    /// Returns whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(SeqCst)
+    }
+
+    /// Returns a port which receives a message once the token is cancelled,
+    /// right away if it already is.
+    ///
+    /// The token keeps a sender for each port it has handed out until it's
+    /// cancelled, so a loop which selects on cancellation should get the port
+    /// once, outside of the loop.
+    pub fn cancelled_port(&self) -> Receiver<()> {
+        let (_, rx) = self.register();
+        rx
+    }
+
+    /// Receives a value from `rx`, unless the token is cancelled first, in
+    /// which case `None` is returned.
+    ///
+    /// # Failure
+    ///
+    /// Like `Receiver::recv`, this fails if the other end of `rx` hangs up.
+    pub fn recv<T: Send>(&self, rx: &Receiver<T>) -> Option<T> {
+        if self.is_cancelled() { return None }
+        let (id, cancelled) = self.register();
+        let sel = Select::new();
+        let mut value = sel.handle(rx);
+        let mut cancel = sel.handle(&cancelled);
+        unsafe {
+            value.add();
+            cancel.add();
+        }
+        let ret = if sel.wait() == value.id() { Some(value.recv()) } else { None };
+        self.unregister(id);
+        ret
+    }
+
+    /// Puts the current task to sleep for `msecs` milliseconds, or until the
+    /// token is cancelled. Returns `false` if the sleep was cut short.
+    pub fn sleep(&self, msecs: u64) -> bool {
+        let timer = Timer::new();
+        let mut timer = timer.ok().expect("CancelToken::sleep: could not create a Timer");
+        let timeout = timer.oneshot(msecs);
+        self.recv(&timeout).is_some()
+    }
+
+    fn register(&self) -> (uint, Receiver<()>) {
+        let (tx, rx) = channel();
+        let id = self.inner.next_id.fetch_add(1, SeqCst);
+        let mut waiters = self.inner.waiters.lock();
+        // Checked under the lock, so that `cancel` either sees this sender or
+        // had set the flag before
+        if self.is_cancelled() {
+            tx.send(());
+        } else {
+            waiters.push((id, tx));
+        }
+        (id, rx)
+    }
+
+    fn unregister(&self, id: uint) {
+        let mut waiters = self.inner.waiters.lock();
+        match waiters.iter().position(|&(i, _)| i == id) {
+            Some(i) => { waiters.swap_remove(i); }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use super::CancelToken;
+
+    #[test]
+    fn test_cancel() {
+        let token = CancelToken::new();
+        let other = token.clone();
+        assert!(!other.is_cancelled());
+        token.cancel();
+        assert!(other.is_cancelled());
+        token.cancel();
+        assert!(other.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancelled_port() {
+        let token = CancelToken::new();
+        let port = token.cancelled_port();
+        assert!(port.try_recv().is_err());
+        let other = token.clone();
+        spawn(proc() other.cancel());
+        port.recv();
+        // already cancelled
+        token.cancelled_port().recv();
+    }
+
+    #[test]
+    fn test_recv() {
+        let token = CancelToken::new();
+        let (tx, rx) = channel();
+        tx.send(1i);
+        assert_eq!(token.recv(&rx), Some(1));
+        assert_eq!(token.inner.waiters.lock().len(), 0);
+
+        let other = token.clone();
+        spawn(proc() other.cancel());
+        assert_eq!(token.recv(&rx), None);
+        drop(tx);
+    }
+
+    #[test]
+    fn test_sleep() {
+        let token = CancelToken::new();
+        assert!(token.sleep(1));
+        let other = token.clone();
+        spawn(proc() other.cancel());
+        assert!(!token.sleep(1000 * 1000));
+    }
+}
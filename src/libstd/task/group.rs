@@ -0,0 +1,226 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Task groups
+//!
+//! A background task keeps running after whoever spawned it is gone, unless
+//! it's told to stop. A `TaskGroup` owns the tasks spawned through it: each of
+//! them is handed the group's `CancelToken`, and dropping the group cancels
+//! the token and waits for all of them to exit.
+//!
+//! A group only keeps track of the tasks which are still running, or which
+//! have failed. The tasks which have exited successfully are forgotten from
+//! time to time as more are spawned, so that a long-lived group spawning
+//! short-lived tasks doesn't grow without bound.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::task::TaskGroup;
+//!
+//! let (tx, rx) = channel();
+//! {
+//!     let mut group = TaskGroup::new();
+//!     group.spawn(proc(token) {
+//!         while token.sleep(10) {
+//!             tx.send("tick");
+//!         }
+//!     });
+//!     rx.recv();
+//! } // the worker is stopped here
+//! ```
+
+use cmp;
+use collections::{Collection, MutableSeq};
+use kinds::Send;
+use mem;
+use ops::Drop;
+use result::{Ok, Err};
+use vec::Vec;
+
+use super::{TaskBuilder, TaskResult, Spawner, JoinHandle, CancelToken};
+
+/// A set of tasks which are cancelled and waited for when the group is
+/// dropped.
+pub struct TaskGroup {
+    token: CancelToken,
+    tasks: Vec<Member>,
+    // The number of tasks at which the exited ones are next looked for, which
+    // keeps the cost of looking proportional to the number of spawns
+    reap_at: uint,
+}
+
+// The groups are never looked through for exited tasks while smaller than this
+static MIN_REAP: uint = 16;
+
+enum Member {
+    Running(JoinHandle),
+    Failed(TaskResult),
+}
+
+impl TaskGroup {
+    /// Creates a group without any task.
+    pub fn new() -> TaskGroup {
+        TaskGroup {
+            token: CancelToken::new(),
+            tasks: Vec::new(),
+            reap_at: MIN_REAP,
+        }
+    }
+
+    /// Spawns a task belonging to the group, which runs `f` with the group's
+    /// token.
+    pub fn spawn(&mut self, f: proc(CancelToken):Send) {
+        self.spawn_with(TaskBuilder::new(), f)
+    }
+
+    /// Spawns a task belonging to the group as `spawn` does, configured by
+    /// `builder`.
+    ///
+    /// # Failure
+    ///
+    /// This method fails if `future_result` was called on the builder, as the
+    /// group needs to know when the task exits.
+    pub fn spawn_with<S: Spawner>(&mut self, builder: TaskBuilder<S>,
+                                  f: proc(CancelToken):Send) {
+        let token = self.token.clone();
+        let task = builder.spawn_joinable(proc() f(token));
+        if self.tasks.len() >= self.reap_at {
+            self.reap();
+            self.reap_at = cmp::max(self.tasks.len() * 2, MIN_REAP);
+        }
+        self.tasks.push(Running(task));
+    }
+
+    // Forgets the tasks which have exited successfully, keeping the results
+    // of those which failed for `join`
+    fn reap(&mut self) {
+        let tasks = mem::replace(&mut self.tasks, Vec::new());
+        for member in tasks.move_iter() {
+            match member {
+                Running(task) => {
+                    let result = task.result.try_recv();
+                    match result {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => self.tasks.push(Failed(Err(e))),
+                        Err(..) => self.tasks.push(Running(task)),
+                    }
+                }
+                failed => self.tasks.push(failed),
+            }
+        }
+    }
+
+    /// Returns the token handed to the tasks of the group. Cancelling it has
+    /// the same effect as `cancel`, without waiting for the tasks.
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+
+    /// Waits for the tasks of the group to exit, without cancelling them,
+    /// returning their results in the order they were spawned.
+    ///
+    /// The results of the tasks which already exited successfully by the time
+    /// the group last forgot about them (see the module documentation) are
+    /// left out, while those of tasks which failed are all there.
+    ///
+    /// The group is left empty, and more tasks can be spawned into it.
+    pub fn join(&mut self) -> Vec<TaskResult> {
+        self.reap_at = MIN_REAP;
+        let tasks = mem::replace(&mut self.tasks, Vec::new());
+        tasks.move_iter().map(|member| {
+            match member {
+                Running(task) => task.join(),
+                Failed(result) => result,
+            }
+        }).collect()
+    }
+
+    /// Asks the tasks of the group to stop, and waits for them to exit.
+    ///
+    /// The token stays cancelled, so tasks spawned into the group afterwards
+    /// are cancelled from the start.
+    pub fn cancel(&mut self) -> Vec<TaskResult> {
+        self.token.cancel();
+        self.join()
+    }
+}
+
+impl Drop for TaskGroup {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use any::Any;
+    use super::{TaskGroup, Running};
+    use task::{TaskBuilder, JoinHandle};
+
+    #[test]
+    fn test_drop_cancels() {
+        let (tx, rx) = channel();
+        {
+            let mut group = TaskGroup::new();
+            for _ in range(0u, 3) {
+                let tx = tx.clone();
+                group.spawn(proc(token) {
+                    token.cancelled_port().recv();
+                    tx.send(());
+                });
+            }
+        }
+        drop(tx);
+        assert_eq!(rx.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_join() {
+        let mut group = TaskGroup::new();
+        group.spawn(proc(_) {});
+        group.spawn_with(TaskBuilder::new().named("failing"), proc(_) fail!());
+        let results = group.join();
+        assert!(results.get(0).is_ok());
+        assert!(results.get(1).is_err());
+        assert!(!group.token().is_cancelled());
+        assert_eq!(group.join().len(), 0);
+    }
+
+    #[test]
+    fn test_reap() {
+        let mut group = TaskGroup::new();
+        let mut running = Vec::new();
+        for i in range(0u, 30) {
+            let (tx, rx) = channel();
+            match i % 3 {
+                0 => tx.send(Ok(())),
+                1 => tx.send(Err(box "failed" as Box<Any + Send>)),
+                _ => running.push(tx),
+            }
+            group.tasks.push(Running(JoinHandle { result: rx }));
+        }
+        group.reap();
+        assert_eq!(group.tasks.len(), 20);
+        for tx in running.move_iter() { tx.send(Ok(())); }
+        let results = group.join();
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 10);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut group = TaskGroup::new();
+        group.spawn(proc(token) { while token.sleep(1000) {} });
+        assert_eq!(group.cancel().len(), 1);
+        group.spawn(proc(token) assert!(token.is_cancelled()));
+        assert!(group.cancel().get(0).is_ok());
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A limit on the number of live tasks
+//!
+//! A program which spawns a task for each request it gets can run out of
+//! memory when requests come in faster than they're dealt with. With a limit
+//! set by `set_max_live_tasks`, spawning a task blocks while there are as many
+//! live tasks as the limit allows, until one of them exits. `try_spawn` gives
+//! up instead of blocking.
+//!
+//! Only the tasks spawned through `TaskBuilder` while a limit is set count
+//! toward it. A task stops counting once it has exited, just before its exit
+//! callback runs.
+
+use collections::{Deque, MutableSeq, RingBuf};
+use comm::{channel, Sender};
+use kinds::marker;
+use mem;
+use ops::Drop;
+use option::{Option, Some, None};
+use rt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+use sync::atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+
+// The limit, or 0 if there's none. It's only changed with LOCK held, but it's
+// read without it so that there's nothing to do when there's no limit.
+static mut LIMIT: AtomicUint = INIT_ATOMIC_UINT;
+
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut STATE: *mut State = 0 as *mut State;
+
+struct State {
+    // The number of tasks counting toward the limit
+    live: uint,
+    // The tasks waiting for a task to exit, so that they can spawn, in the
+    // order they started waiting in
+    waiters: RingBuf<Sender<()>>,
+}
+
+/// A task counting toward the limit, which stops counting when this is
+/// dropped.
+pub struct Live {
+    nocopy: marker::NoCopy,
+}
+
+/// Sets the maximum number of live tasks, or removes the limit if `max` is
+/// `None`. There's no limit by default.
+///
+/// Lowering the limit doesn't affect the tasks which are already live, but new
+/// tasks can only be spawned once enough of them have exited.
+///
+/// # Failure
+///
+/// This function fails if `max` is `Some(0)`.
+pub fn set_max_live_tasks(max: Option<uint>) {
+    let max = match max {
+        Some(0) => fail!("the maximum number of live tasks can't be 0"),
+        Some(n) => n,
+        None => 0,
+    };
+    // Every waiter is woken up, as a higher limit may let them all through
+    let waiters = with_state(|s| {
+        unsafe { LIMIT.store(max, SeqCst); }
+        mem::replace(&mut s.waiters, RingBuf::new())
+    });
+    for waiter in waiters.move_iter() {
+        let _ = waiter.send_opt(());
+    }
+}
+
+/// Returns the maximum number of live tasks, if there's a limit.
+pub fn max_live_tasks() -> Option<uint> {
+    match unsafe { LIMIT.load(SeqCst) } {
+        0 => None,
+        n => Some(n),
+    }
+}
+
+// Blocks until one more task can be live, returning `None` if there's no limit
+pub fn acquire() -> Option<Live> {
+    if unsafe { LIMIT.load(SeqCst) } == 0 { return None }
+    loop {
+        let (tx, rx) = channel();
+        let mut tx = Some(tx);
+        let entered = with_state(|s| {
+            let entered = enter(s);
+            if entered.is_none() {
+                s.waiters.push(tx.take_unwrap());
+            }
+            entered
+        });
+        match entered {
+            Some(live) => return live,
+            None => { let _ = rx.recv_opt(); }
+        }
+    }
+}
+
+// Takes a place for one more live task if there's one, without blocking. The
+// outer option is `None` if the limit has been reached.
+pub fn try_acquire() -> Option<Option<Live>> {
+    if unsafe { LIMIT.load(SeqCst) } == 0 { return Some(None) }
+    with_state(|s| enter(s))
+}
+
+fn enter(s: &mut State) -> Option<Option<Live>> {
+    match unsafe { LIMIT.load(SeqCst) } {
+        0 => Some(None),
+        limit => if s.try_enter(limit) { Some(Some(Live::new())) } else { None },
+    }
+}
+
+impl State {
+    fn try_enter(&mut self, limit: uint) -> bool {
+        if self.live < limit {
+            self.live += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns the waiter to wake up, which tries again to enter
+    fn leave(&mut self) -> Option<Sender<()>> {
+        self.live -= 1;
+        self.waiters.pop_front()
+    }
+}
+
+impl Live {
+    fn new() -> Live {
+        Live { nocopy: marker::NoCopy }
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        match with_state(|s| s.leave()) {
+            Some(waiter) => { let _ = waiter.send_opt(()); }
+            None => {}
+        }
+    }
+}
+
+// The waiters are woken up once the lock is released, as sending may switch
+// to another task
+fn with_state<T>(f: |&mut State| -> T) -> T {
+    unsafe {
+        let _g = LOCK.lock();
+        if STATE.is_null() {
+            STATE = mem::transmute(box State {
+                live: 0,
+                waiters: RingBuf::new(),
+            });
+        }
+        f(&mut *STATE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use collections::RingBuf;
+    use super::State;
+
+    // These drive a `State` of their own, as the global one is shared with the
+    // other tests
+
+    #[test]
+    fn test_enter_leave() {
+        let mut s = State { live: 0, waiters: RingBuf::new() };
+        assert!(s.try_enter(2));
+        assert!(s.try_enter(2));
+        assert!(!s.try_enter(2));
+        assert!(s.leave().is_none());
+        assert!(s.try_enter(2));
+        assert!(s.try_enter(3));
+        assert_eq!(s.live, 3);
+    }
+
+    #[test]
+    fn test_leave_wakes_first_waiter() {
+        let mut s = State { live: 1, waiters: RingBuf::new() };
+        let (tx1, rx1) = channel();
+        let (tx2, rx2) = channel();
+        s.waiters.push(tx1);
+        s.waiters.push(tx2);
+        s.leave().unwrap().send(());
+        assert!(rx1.try_recv().is_ok());
+        assert!(rx2.try_recv().is_err());
+        assert_eq!(s.waiters.len(), 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_zero_limit() {
+        super::set_max_live_tasks(Some(0));
+    }
+}
@@ -56,7 +56,7 @@
 //! Task spawning can also be configured to use a particular scheduler, to
 //! redirect the new task's output, or to yield a `future` representing the
 //! task's final result. The configuration is established using the
-//! `TaskBuilder` API:
+//! `TaskBuilder` API, which `task()` is a shorthand for:
 //!
 //! ## Example
 //!
@@ -90,16 +90,41 @@
 //! pool.shutdown();
 //! # }
 //! ```
+//!
+//! Green and native tasks can be mixed freely in a program, and channels and
+//! the other primitives of `std::comm` and `std::sync` work the same between
+//! tasks of either flavor. A green task blocked on a channel only gives its
+//! scheduler up to other tasks, but one blocked in a foreign function call
+//! holds up its whole scheduler thread. Such calls are better made from a
+//! native task, spawned with `.native()`, which hands its results back over a
+//! channel, while the rest of the program stays on green tasks.
+//!
+//! A named task with a smaller stack, whose outcome is sent on a channel:
+//!
+//! ```rust
+//! use std::task;
+//!
+//! let mut builder = task::task().named("worker").stack_size(64 * 1024);
+//! let result = builder.future_result();
+//! builder.spawn(proc() {
+//!     assert_eq!(task::name(), Some("worker".to_string()));
+//! });
+//! assert!(result.recv().is_ok());
+//! ```
 
 #![stable]
 
 use any::Any;
-use comm::channel;
+use collections::{Collection, MutableSeq};
+use comm::{channel, Sender, Receiver, Select, Handle};
+use failure;
 use io::{Writer, stdio};
 use kinds::{Send, marker};
+use mem;
 use option::{None, Some, Option};
 use boxed::Box;
-use result::Result;
+use result::{Result, Ok, Err};
+use rt::backtrace::Backtrace;
 use rt::local::Local;
 use rt::task;
 use rt::task::Task;
@@ -107,6 +132,113 @@ use str::{Str, SendStr, IntoMaybeOwned};
 use string::String;
 use sync::Future;
 use to_string::ToString;
+use vec::Vec;
+
+pub use rt::task::{Priority, High, Normal, Low};
+pub use self::cancel::CancelToken;
+pub use self::group::TaskGroup;
+pub use self::limit::{set_max_live_tasks, max_live_tasks};
+pub use self::scope::{scope, Scope};
+pub use self::supervisor::{Supervisor, TaskDeath};
+pub use self::supervisor::{RestartPolicy, NoRestart, RestartUpTo, AlwaysRestart};
+
+mod cancel;
+mod group;
+mod limit;
+mod scope;
+mod supervisor;
+
+/// A description of a task failure, given to the hooks registered with
+/// `on_fail`.
+pub struct FailureInfo {
+    /// The name of the failing task, if it has one.
+    pub name: Option<String>,
+    /// The message the task failed with. It is `"Box<Any>"` if the argument to
+    /// `fail!` wasn't a string.
+    pub message: String,
+    /// The file in which the task failed.
+    pub file: &'static str,
+    /// The line at which the task failed.
+    pub line: uint,
+    /// A backtrace of where the task failed. It is empty if no backtrace
+    /// could be captured on this platform.
+    pub backtrace: String,
+}
+
+/// The outcome of a task: `Ok` if it ran to completion, or `Err` holding the
+/// argument to `fail!(...)` if it failed.
+pub type TaskResult = Result<(), Box<Any + Send>>;
+
+/// The argument to `fail!` of a task spawned by a builder on which
+/// `capture_backtrace` was called, along with a backtrace of where it failed.
+///
+/// The `Err` of such a task's `TaskResult` holds a `TaskFailure` in place of
+/// the argument to `fail!`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::boxed::BoxAny;
+/// use std::task::{TaskBuilder, TaskFailure};
+///
+/// let result = TaskBuilder::new().capture_backtrace().try(proc() {
+///     fail!("oops");
+/// });
+/// let failure = result.unwrap_err().downcast::<TaskFailure>().unwrap();
+/// println!("{}", failure.backtrace);
+/// ```
+pub struct TaskFailure {
+    /// The argument to `fail!`.
+    pub cause: Box<Any + Send>,
+    /// A backtrace of where the task failed, if one could be captured. Its
+    /// symbols are only looked up if it's printed.
+    pub backtrace: Option<Backtrace>,
+}
+
+/// A handle to a spawned task, through which its termination is observed.
+///
+/// The termination of the task can be waited for along with messages on other
+/// channels, by adding the handle to a `Select`. The port of a `Supervisor` is
+/// an ordinary receiver, so it can be added as well.
+///
+/// # Example
+///
+/// ```rust
+/// use std::comm::Select;
+/// use std::task::TaskBuilder;
+///
+/// let (tx, rx) = channel::<int>();
+/// let child = TaskBuilder::new().spawn_joinable(proc() {
+///     tx.send(1);
+/// });
+///
+/// let sel = Select::new();
+/// let mut message = sel.handle(&rx);
+/// let mut died = child.select_handle(&sel);
+/// unsafe { message.add(); died.add(); }
+/// let ret = sel.wait();
+/// if ret == message.id() {
+///     println!("got {}", message.recv());
+/// } else if ret == died.id() {
+///     println!("the child exited first: {}", died.recv());
+/// }
+/// ```
+pub struct JoinHandle {
+    result: Receiver<TaskResult>,
+}
+
+impl JoinHandle {
+    /// Blocks until the task terminates, returning its outcome.
+    pub fn join(self) -> TaskResult {
+        self.result.recv()
+    }
+
+    /// Returns a handle through which `sel` waits for the task to terminate.
+    /// Receiving from the handle gives the task's outcome.
+    pub fn select_handle<'a>(&'a self, sel: &'a Select) -> Handle<'a, TaskResult> {
+        sel.handle(&self.result)
+    }
+}
 
 /// A means of spawning a task
 pub trait Spawner {
@@ -144,6 +276,8 @@ pub struct TaskBuilder<S = SiblingSpawner> {
     name: Option<SendStr>,
     // The size of the stack for the spawned task
     stack_size: Option<uint>,
+    // How urgently the spawned task should be scheduled
+    priority: Priority,
     // Task-local stdout
     stdout: Option<Box<Writer + Send>>,
     // Task-local stderr
@@ -152,6 +286,14 @@ pub struct TaskBuilder<S = SiblingSpawner> {
     spawner: S,
     // Optionally wrap the eventual task body
     gen_body: Option<proc(v: proc():Send):Send -> proc():Send>,
+    // Where to send the task's result when it exits
+    notify: Option<Sender<TaskResult>>,
+    // Where to report the task's failure
+    supervisor: Option<Sender<TaskDeath>>,
+    // Hooks to register in the task before it starts
+    on_fail: Vec<proc(&FailureInfo):Send>,
+    // Whether to capture a backtrace into the task's result if it fails
+    backtrace: bool,
     nocopy: marker::NoCopy,
 }
 
@@ -162,10 +304,15 @@ impl TaskBuilder<SiblingSpawner> {
         TaskBuilder {
             name: None,
             stack_size: None,
+            priority: Normal,
             stdout: None,
             stderr: None,
             spawner: SiblingSpawner,
             gen_body: None,
+            notify: None,
+            supervisor: None,
+            on_fail: Vec::new(),
+            backtrace: false,
             nocopy: marker::NoCopy,
         }
     }
@@ -186,6 +333,16 @@ impl<S: Spawner> TaskBuilder<S> {
         self
     }
 
+    /// Set the scheduling priority of the new task, `Normal` by default.
+    ///
+    /// The green scheduler runs the tasks of higher priority first, including
+    /// when they're woken up after blocking. Native tasks are scheduled by the
+    /// OS, which ignores this.
+    pub fn priority(mut self, priority: Priority) -> TaskBuilder<S> {
+        self.priority = priority;
+        self
+    }
+
     /// Redirect task-local stdout.
     #[experimental = "May not want to make stdio overridable here."]
     pub fn stdout(mut self, stdout: Box<Writer + Send>) -> TaskBuilder<S> {
@@ -212,19 +369,55 @@ impl<S: Spawner> TaskBuilder<S> {
     pub fn spawner<T: Spawner>(self, spawner: T) -> TaskBuilder<T> {
         // repackage the entire TaskBuilder since its type is changing.
         let TaskBuilder {
-            name, stack_size, stdout, stderr, spawner: _, gen_body, nocopy
+            name, stack_size, priority, stdout, stderr, spawner: _, gen_body,
+            notify, supervisor, on_fail, backtrace, nocopy
         } = self;
         TaskBuilder {
             name: name,
             stack_size: stack_size,
+            priority: priority,
             stdout: stdout,
             stderr: stderr,
             spawner: spawner,
             gen_body: gen_body,
+            notify: notify,
+            supervisor: supervisor,
+            on_fail: on_fail,
+            backtrace: backtrace,
             nocopy: nocopy,
         }
     }
 
+    /// Get a port which will receive the result of the task once it exits,
+    /// whether it ran to completion or failed.
+    ///
+    /// # Failure
+    ///
+    /// This method fails if called more than once on the same builder.
+    pub fn future_result(&mut self) -> Receiver<TaskResult> {
+        assert!(self.notify.is_none(), "future_result may only be called once");
+        let (tx, rx) = channel();
+        self.notify = Some(tx);
+        rx
+    }
+
+    /// Register a hook to run in the new task if it fails, as with `on_fail`.
+    pub fn on_fail(mut self, hook: proc(&FailureInfo):Send) -> TaskBuilder<S> {
+        self.on_fail.push(hook);
+        self
+    }
+
+    /// Capture a backtrace if the new task fails, to be delivered with its
+    /// result: the `Err` of the result holds a `TaskFailure` wrapping the
+    /// argument to `fail!`.
+    ///
+    /// This applies to the results given by `future_result`, `try_future` and
+    /// `try`. The failures reported to a supervisor carry a backtrace anyway.
+    pub fn capture_backtrace(mut self) -> TaskBuilder<S> {
+        self.backtrace = true;
+        self
+    }
+
     /// Add a wrapper to the body of the spawned task.
     ///
     /// Before the task is spawned it is passed through a 'body generator'
@@ -247,18 +440,79 @@ impl<S: Spawner> TaskBuilder<S> {
 
     // Where spawning actually happens (whether yielding a future or not)
     fn spawn_internal(self, f: proc():Send,
-                      on_exit: Option<proc(Result<(), Box<Any + Send>>):Send>) {
+                      on_exit: Option<proc(TaskResult):Send>) {
+        let live = limit::acquire();
+        self.spawn_live(f, on_exit, live)
+    }
+
+    fn spawn_live(self, f: proc():Send, on_exit: Option<proc(TaskResult):Send>,
+                  live: Option<limit::Live>) {
         let TaskBuilder {
-            name, stack_size, stdout, stderr, spawner, mut gen_body, nocopy: _
+            name, stack_size, priority, stdout, stderr, spawner, mut gen_body,
+            notify, supervisor: deaths, on_fail: hooks, backtrace, nocopy: _
         } = self;
         let f = match gen_body.take() {
             Some(gen) => gen(f),
             None => f
         };
+        let f = if hooks.is_empty() {
+            f
+        } else {
+            proc() {
+                for hook in hooks.move_iter() {
+                    on_fail(hook);
+                }
+                f()
+            }
+        };
+        let on_exit = match (on_exit, notify) {
+            (Some(_), Some(_)) => {
+                fail!("future_result can't be combined with try_future")
+            }
+            (None, Some(tx)) => {
+                let on_exit: proc(TaskResult):Send = proc(res) {
+                    let _ = tx.send_opt(res);
+                };
+                Some(on_exit)
+            }
+            (on_exit, None) => on_exit,
+        };
+        let (f, on_exit) = match on_exit {
+            Some(on_exit) => {
+                let (f, on_exit) = if backtrace {
+                    with_backtrace(f, on_exit)
+                } else {
+                    (f, on_exit)
+                };
+                (f, Some(on_exit))
+            }
+            None => (f, None),
+        };
+        let (f, on_exit) = match deaths {
+            Some(deaths) => {
+                let (f, on_exit) = supervisor::supervise(deaths, &name, f, on_exit);
+                (f, Some(on_exit))
+            }
+            None => (f, on_exit),
+        };
+        let on_exit = match live {
+            Some(live) => {
+                let on_exit: proc(TaskResult):Send = proc(res) {
+                    mem::drop(live);
+                    match on_exit {
+                        Some(f) => f(res),
+                        None => {}
+                    }
+                };
+                Some(on_exit)
+            }
+            None => on_exit,
+        };
         let opts = task::TaskOpts {
             on_exit: on_exit,
             name: name,
             stack_size: stack_size,
+            priority: priority,
         };
         if stdout.is_some() || stderr.is_some() {
             spawner.spawn(opts, proc() {
@@ -276,10 +530,54 @@ impl<S: Spawner> TaskBuilder<S> {
     /// Sets up a new task with its own call stack and schedules it to run
     /// the provided proc. The task has the properties and behavior
     /// specified by the `TaskBuilder`.
+    ///
+    /// If a limit is set on the number of live tasks, this blocks until the
+    /// new task can be spawned within the limit.
     pub fn spawn(self, f: proc():Send) {
         self.spawn_internal(f, None)
     }
 
+    /// Creates and executes a new child task as `spawn` does, unless a limit
+    /// is set on the number of live tasks and it has been reached. The proc
+    /// is given back in that case.
+    pub fn try_spawn(self, f: proc():Send) -> Result<(), proc():Send> {
+        match limit::try_acquire() {
+            Some(live) => Ok(self.spawn_live(f, None, live)),
+            None => Err(f),
+        }
+    }
+
+    /// Creates and executes a new child task, returning a handle through which
+    /// its termination is observed. The task has the properties and behavior
+    /// specified by the `TaskBuilder`.
+    ///
+    /// # Failure
+    ///
+    /// This method fails if `future_result` was called on the builder, as the
+    /// task's result can only be delivered once.
+    pub fn spawn_joinable(mut self, f: proc():Send) -> JoinHandle {
+        let result = self.future_result();
+        self.spawn(f);
+        JoinHandle { result: result }
+    }
+
+    /// Execute a proc in a newly-spawned task and return a future of the value
+    /// it returns. The task has the properties and behavior specified by the
+    /// `TaskBuilder`.
+    ///
+    /// The value is sent back on a channel which is only ever used once, so
+    /// this costs no more than spawning the task. If the child task fails, it
+    /// never sends a value and taking the value of the future fails too; use
+    /// `try_future` to recover from the failure instead.
+    pub fn spawn_with_result<T:Send>(self, f: proc():Send -> T) -> Future<T> {
+        let (tx, rx) = channel();
+        self.spawn(proc() {
+            // Don't fail if the future has been dropped
+            let _ = tx.send_opt(f());
+        });
+        Future::from_receiver(rx)
+    }
+
     /// Execute a proc in a newly-spawned task and return a future representing
     /// the task's result. The task has the properties and behavior
     /// specified by the `TaskBuilder`.
@@ -293,6 +591,11 @@ impl<S: Spawner> TaskBuilder<S> {
     /// future returns `result::Ok` containing the value returned by the
     /// function. If the child task fails then the future returns `result::Err`
     /// containing the argument to `fail!(...)` as an `Any` trait object.
+    ///
+    /// # Failure
+    ///
+    /// This method fails if `future_result` was called on the builder, as the
+    /// task's result can only be delivered once.
     #[experimental = "Futures are experimental."]
     pub fn try_future<T:Send>(self, f: proc():Send -> T)
                               -> Future<Result<T, Box<Any + Send>>> {
@@ -320,8 +623,35 @@ impl<S: Spawner> TaskBuilder<S> {
     }
 }
 
+// Wraps the body and the exit callback of a task, so that the body captures a
+// backtrace if it fails and the callback gets it along with the failure.
+fn with_backtrace(f: proc():Send, on_exit: proc(TaskResult):Send)
+                  -> (proc():Send, proc(TaskResult):Send) {
+    let (bt_tx, bt_rx) = channel();
+    let f = proc() {
+        failure::add_backtrace_sink(bt_tx);
+        f()
+    };
+    let on_exit = proc(res: TaskResult) {
+        on_exit(res.map_err(|cause| {
+            box TaskFailure {
+                cause: cause,
+                backtrace: bt_rx.try_recv().ok(),
+            } as Box<Any + Send>
+        }))
+    };
+    (f, on_exit)
+}
+
 /* Convenience functions */
 
+/// Generate the base configuration for spawning a task.
+///
+/// This function is equivalent to `TaskBuilder::new()`.
+pub fn task() -> TaskBuilder<SiblingSpawner> {
+    TaskBuilder::new()
+}
+
 /// Creates and executes a new child task
 ///
 /// Sets up a new task with its own call stack and schedules it to run
@@ -332,6 +662,22 @@ pub fn spawn(f: proc(): Send) {
     TaskBuilder::new().spawn(f)
 }
 
+/// Creates and executes a new child task, unless the limit on the number of
+/// live tasks has been reached, in which case the proc is given back.
+///
+/// This function is equivalent to `TaskBuilder::new().try_spawn(f)`.
+pub fn try_spawn(f: proc(): Send) -> Result<(), proc(): Send> {
+    TaskBuilder::new().try_spawn(f)
+}
+
+/// Execute a function in a newly-spawned task and return a future of the value
+/// it returns.
+///
+/// This function is equivalent to `TaskBuilder::new().spawn_with_result(f)`.
+pub fn spawn_with_result<T: Send>(f: proc(): Send -> T) -> Future<T> {
+    TaskBuilder::new().spawn_with_result(f)
+}
+
 /// Execute a function in a newly-spawned task and return either the return
 /// value of the function or an error if the task failed.
 ///
@@ -377,6 +723,23 @@ pub fn name() -> Option<String> {
     }
 }
 
+/// Register a hook to run if the current task fails.
+///
+/// Hooks run in the order they were registered, as soon as the task fails and
+/// before its stack is unwound, so destructors haven't run yet. They're given
+/// the failure message and a backtrace of where the task failed, which makes
+/// them a place to log failures from.
+///
+/// A hook which fails itself makes the task fail with its failure instead, and
+/// the hooks which come after it don't run.
+pub fn on_fail(hook: proc(&FailureInfo):Send) {
+    use failure::fail_hooks;
+
+    let mut hooks = fail_hooks.replace(None).unwrap_or(Vec::new());
+    hooks.push(hook);
+    fail_hooks.replace(Some(hooks));
+}
+
 /// Yield control to the task scheduler.
 #[unstable = "Name will change."]
 pub fn deschedule() {
@@ -409,6 +772,33 @@ mod test {
     // !!! These tests are dangerous. If something is buggy, they will hang, !!!
     // !!! instead of exiting cleanly. This might wedge the buildbots.       !!!
 
+    #[test]
+    fn test_green_and_native_tasks() {
+        use green::{SchedPool, PoolConfig, GreenTaskBuilder};
+        use native::NativeTaskBuilder;
+
+        let mut pool = SchedPool::new(PoolConfig { threads: 1, ..PoolConfig::new() });
+        let (tx, rx) = channel();
+        TaskBuilder::new().green(&mut pool).spawn(proc() {
+            let (go_tx, go_rx) = channel();
+            let (done_tx, done_rx) = channel();
+            // The native task blocks its own thread...
+            TaskBuilder::new().native().spawn(proc() {
+                go_rx.recv();
+                done_tx.send(());
+            });
+            // ...while the green tasks carry on on the only scheduler
+            let (sibling_tx, sibling_rx) = channel();
+            spawn(proc() sibling_tx.send(()));
+            sibling_rx.recv();
+            go_tx.send(());
+            done_rx.recv();
+            tx.send(());
+        });
+        rx.recv();
+        pool.shutdown();
+    }
+
     #[test]
     fn test_unnamed_task() {
         try(proc() {
@@ -470,6 +860,152 @@ mod test {
         assert!(result.unwrap().is_err());
     }
 
+    #[test]
+    fn test_future_result() {
+        let mut builder = task();
+        let result = builder.future_result();
+        builder.spawn(proc() {});
+        assert!(result.recv().is_ok());
+
+        let mut builder = task();
+        let result = builder.future_result();
+        builder.spawn(proc() { fail!("boom") });
+        match result.recv() {
+            Err(e) => {
+                type T = &'static str;
+                assert_eq!(*e.downcast::<T>().unwrap(), "boom");
+            }
+            Ok(()) => fail!()
+        }
+    }
+
+    #[test]
+    fn test_future_result_named() {
+        let mut builder = task().named("frob").stack_size(1024 * 1024);
+        let result = builder.future_result();
+        builder.spawn(proc() {
+            assert!(name().unwrap() == "frob".to_string());
+        });
+        assert!(result.recv().is_ok());
+    }
+
+    #[test] #[should_fail]
+    fn test_future_result_twice() {
+        let mut builder = task();
+        let _a = builder.future_result();
+        let _b = builder.future_result();
+    }
+
+    #[test]
+    fn test_spawn_with_result() {
+        let futures = Vec::from_fn(10, |i| spawn_with_result(proc() i * 2));
+        for (i, f) in futures.move_iter().enumerate() {
+            assert_eq!(f.unwrap(), i * 2);
+        }
+    }
+
+    #[test]
+    fn test_spawn_with_result_named() {
+        let f = TaskBuilder::new().named("ada").spawn_with_result(proc() name());
+        assert_eq!(f.unwrap(), Some("ada".to_string()));
+    }
+
+    #[test] #[should_fail]
+    fn test_spawn_with_result_fail() {
+        spawn_with_result(proc() -> int { fail!() }).unwrap();
+    }
+
+    #[test]
+    fn test_capture_backtrace() {
+        use failure;
+
+        let r = TaskBuilder::new().capture_backtrace().try(proc() {
+            fail!("traced")
+        });
+        let f = r.unwrap_err().downcast::<TaskFailure>().ok().unwrap();
+        assert_eq!(failure::message(&*f.cause), "traced");
+        let trace = f.backtrace.unwrap().to_string();
+        assert!(trace.as_slice().starts_with("stack backtrace:"));
+
+        let mut builder = TaskBuilder::new().capture_backtrace();
+        let result = builder.future_result();
+        builder.spawn(proc() {});
+        assert!(result.recv().is_ok());
+
+        let r = TaskBuilder::new().try(proc() fail!("untraced"));
+        assert!(r.unwrap_err().downcast::<TaskFailure>().is_err());
+    }
+
+    #[test]
+    fn test_try_spawn_without_limit() {
+        let (tx, rx) = channel();
+        assert!(try_spawn(proc() tx.send(())).is_ok());
+        rx.recv();
+    }
+
+    #[test]
+    fn test_spawn_joinable() {
+        assert!(task().spawn_joinable(proc() {}).join().is_ok());
+        assert!(task().spawn_joinable(proc() fail!()).join().is_err());
+    }
+
+    #[test]
+    fn test_select_on_termination() {
+        use comm::Select;
+
+        let (tx, rx) = channel::<()>();
+        let (start_tx, start_rx) = channel();
+        let child = task().spawn_joinable(proc() {
+            start_rx.recv();
+            fail!("exiting")
+        });
+        let sel = Select::new();
+        let mut message = sel.handle(&rx);
+        let mut died = child.select_handle(&sel);
+        unsafe {
+            message.add();
+            died.add();
+        }
+        start_tx.send(());
+        assert_eq!(sel.wait(), died.id());
+        assert!(died.recv().is_err());
+        drop(tx);
+    }
+
+    #[test]
+    fn test_on_fail() {
+        let (tx, rx) = channel();
+        let tx2 = tx.clone();
+        let r = TaskBuilder::new().named("doomed").on_fail(proc(info) {
+            tx.send((1u, info.name.clone(), info.message.clone()));
+        }).try(proc() {
+            on_fail(proc(info) {
+                assert!(info.file.ends_with("mod.rs"));
+                tx2.send((2u, None, info.message.clone()));
+            });
+            fail!("boom");
+        });
+        assert!(r.is_err());
+        assert_eq!(rx.recv(), (1, Some("doomed".to_string()), "boom".to_string()));
+        assert_eq!(rx.recv(), (2, None, "boom".to_string()));
+    }
+
+    #[test]
+    fn test_on_fail_before_destructors() {
+        struct D(Sender<&'static str>);
+        impl Drop for D {
+            fn drop(&mut self) { let D(ref tx) = *self; tx.send("drop"); }
+        }
+        let (tx, rx) = channel();
+        let _ = try(proc() {
+            let _d = D(tx.clone());
+            on_fail(proc(_) tx.send("hook"));
+            fail!();
+        });
+        assert_eq!(rx.recv(), "hook");
+        assert_eq!(rx.recv(), "drop");
+    }
+
     #[test]
     fn test_try_success() {
         match try(proc() {
@@ -0,0 +1,207 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scoped tasks
+//!
+//! The body of a task spawned with `spawn` has to own everything it uses,
+//! because the task may outlive the one which spawned it. Tasks spawned within
+//! a `scope` can't: the scope doesn't return until all of them have exited.
+//! This lets them borrow from the stack of the task which opened the scope,
+//! instead of having its data copied or put in an `Arc`.
+//!
+//! Spawning is unsafe, as the type system can't tell by itself that what the
+//! task takes along may be sent to it (see `Scope::spawn`).
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::task;
+//!
+//! let mut squares = [1u, 2, 3, 4, 5, 6];
+//! task::scope(|s| {
+//!     for chunk in squares.mut_chunks(2) {
+//!         // Only a borrowed slice of `uint`s goes along
+//!         unsafe {
+//!             s.spawn(proc() {
+//!                 for x in chunk.mut_iter() { *x = *x * *x; }
+//!             });
+//!         }
+//!     }
+//! });
+//! assert_eq!(squares.as_slice(), &[1, 4, 9, 16, 25, 36]);
+//! ```
+
+use cell::RefCell;
+use collections::MutableSeq;
+use comm::Receiver;
+use failure;
+use kinds::{Share, marker};
+use mem;
+use ops::Drop;
+use option::{Option, Some, None};
+use result::{Ok, Err};
+use string::String;
+use to_string::ToString;
+use vec::Vec;
+
+use super::{TaskBuilder, TaskResult};
+
+/// A handle through which tasks are spawned within a `scope`.
+///
+/// The tasks may borrow anything which lives for `'a`, which the scope
+/// doesn't outlive.
+pub struct Scope<'a> {
+    children: RefCell<Vec<Receiver<TaskResult>>>,
+    // Tasks may borrow for exactly 'a: allowing a shorter lifetime would let
+    // them borrow values dropped before the scope ends
+    marker: marker::InvariantLifetime<'a>,
+}
+
+/// Runs `f` with a `Scope` through which it can spawn tasks, and waits for all
+/// of those tasks to exit before returning what `f` returned.
+///
+/// # Failure
+///
+/// If one of the tasks fails, this function fails too, once all of them have
+/// exited. If `f` fails, the tasks are still waited for while unwinding.
+pub fn scope<'a, T>(f: |&Scope<'a>| -> T) -> T {
+    let scope = Scope {
+        children: RefCell::new(Vec::new()),
+        marker: marker::InvariantLifetime,
+    };
+    let ret = f(&scope);
+    match scope.join() {
+        Some(msg) => fail!("a task of the scope failed: {}", msg),
+        None => ret,
+    }
+}
+
+impl<'a> Scope<'a> {
+    /// Spawns a task running `f`, which may borrow data living for `'a`.
+    ///
+    /// `f` has to be `Share` rather than `Send`, as a `Send` procedure can't
+    /// hold a reference which isn't `'static`. Only `Share` data may be
+    /// shared with the new task through a reference, so the bound covers what
+    /// `f` borrows, but not what it owns.
+    ///
+    /// # Safety
+    ///
+    /// Everything `f` captures by value has to be `Send`, other than the
+    /// references. Types which are `Share` without being `Send` can't be
+    /// moved to another task, and nothing checks that `f` doesn't hold one.
+    pub unsafe fn spawn(&self, f: proc():'a + Share) {
+        // The scope waits for the task to exit before anything `f` borrows
+        // can go away, which is what `Send`'s 'static bound ensures otherwise,
+        // and the caller vouches for what `f` owns
+        let f: proc():Send = mem::transmute(f);
+        let mut builder = TaskBuilder::new();
+        let result = builder.future_result();
+        builder.spawn(f);
+        self.children.borrow_mut().push(result);
+    }
+
+    // Waits for every task spawned so far, returning the message of the first
+    // one which failed
+    fn join(&self) -> Option<String> {
+        let children = mem::replace(&mut *self.children.borrow_mut(), Vec::new());
+        let mut failed = None;
+        for child in children.move_iter() {
+            match child.recv() {
+                Err(ref e) if failed.is_none() => {
+                    failed = Some(failure::message(&**e).to_string());
+                }
+                Ok(()) | Err(..) => {}
+            }
+        }
+        failed
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for Scope<'a> {
+    fn drop(&mut self) {
+        // Only has work left to do if `f` failed, in which case the failures
+        // of the tasks don't matter anymore
+        self.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use super::scope;
+    use sync::Arc;
+    use sync::atomics::{AtomicUint, SeqCst};
+    use io::timer;
+    use task;
+
+    #[test]
+    fn test_borrow_mut_chunks() {
+        let mut v = [0u, ..8];
+        scope(|s| {
+            for (i, chunk) in v.mut_chunks(2).enumerate() {
+                unsafe {
+                    s.spawn(proc() {
+                        for x in chunk.mut_iter() { *x = i; }
+                    });
+                }
+            }
+        });
+        assert_eq!(v.as_slice(), &[0, 0, 1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn test_waits_for_tasks() {
+        let count = AtomicUint::new(0);
+        let count = &count;
+        let r = scope(|s| {
+            for _ in range(0u, 4) {
+                unsafe {
+                    s.spawn(proc() {
+                        timer::sleep(10);
+                        count.fetch_add(1, SeqCst);
+                    });
+                }
+            }
+            "done"
+        });
+        assert_eq!(r, "done");
+        assert_eq!(count.load(SeqCst), 4);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_child_failure_propagates() {
+        scope(|s| unsafe {
+            s.spawn(proc() fail!("child"));
+            s.spawn(proc() {});
+        });
+    }
+
+    #[test]
+    fn test_parent_failure_waits() {
+        let count = Arc::new(AtomicUint::new(0));
+        let count2 = count.clone();
+        let res = task::try(proc() {
+            let count = &*count2;
+            scope(|s| {
+                unsafe {
+                    s.spawn(proc() {
+                        timer::sleep(10);
+                        count.fetch_add(1, SeqCst);
+                    });
+                }
+                fail!("parent");
+            });
+        });
+        assert!(res.is_err());
+        assert_eq!(count.load(SeqCst), 1);
+    }
+}
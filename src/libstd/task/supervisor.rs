@@ -0,0 +1,217 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Task supervision
+//!
+//! Nothing is told when a task spawned with `spawn` fails, apart from the
+//! message printed on stderr. A `Supervisor` collects the failures of the
+//! tasks it supervises: each one is delivered on the supervisor's port as a
+//! `TaskDeath`, holding the task's name, its failure message and a backtrace
+//! of where it failed.
+//!
+//! Tasks are put under supervision with `TaskBuilder::supervised_by`, or
+//! spawned by the supervisor itself with `Supervisor::spawn`, which can also
+//! restart them when they fail.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::task::{TaskBuilder, Supervisor};
+//!
+//! let supervisor = Supervisor::new();
+//! TaskBuilder::new().named("worker").supervised_by(&supervisor).spawn(proc() {
+//!     fail!("out of widgets");
+//! });
+//!
+//! let death = supervisor.port().recv();
+//! assert_eq!(death.name, Some("worker".to_string()));
+//! assert_eq!(death.message.as_slice(), "out of widgets");
+//! ```
+
+use any::Any;
+use clone::Clone;
+use comm::{channel, Sender, Receiver};
+use failure;
+use kinds::Send;
+use option::{None, Some, Option};
+use boxed::Box;
+use result::{Ok, Err};
+use rt::backtrace::Backtrace;
+use str::{Str, SendStr};
+use string::String;
+use to_string::ToString;
+
+use super::{TaskBuilder, TaskResult, Spawner};
+
+/// A report of the failure of a supervised task.
+#[deriving(Clone, Show)]
+pub struct TaskDeath {
+    /// The name of the task, if it had one.
+    pub name: Option<String>,
+    /// The message the task failed with. It is `"Box<Any>"` if the argument to
+    /// `fail!` wasn't a string.
+    pub message: String,
+    /// A backtrace of where the task failed, if one could be captured. Its
+    /// symbols are only looked up if it's printed.
+    pub backtrace: Option<Backtrace>,
+}
+
+/// What a supervisor does when a task it spawned fails.
+#[deriving(Clone, PartialEq, Show)]
+pub enum RestartPolicy {
+    /// Leave the task dead.
+    NoRestart,
+    /// Spawn the task again, at most the given number of times.
+    RestartUpTo(uint),
+    /// Spawn the task again every time it fails.
+    AlwaysRestart,
+}
+
+/// A collector of the failures of other tasks.
+pub struct Supervisor {
+    tx: Sender<TaskDeath>,
+    rx: Receiver<TaskDeath>,
+}
+
+impl Supervisor {
+    /// Creates a supervisor, which doesn't supervise any task yet.
+    pub fn new() -> Supervisor {
+        let (tx, rx) = channel();
+        Supervisor { tx: tx, rx: rx }
+    }
+
+    /// The port on which the failures of supervised tasks are delivered, in
+    /// the order they happen.
+    pub fn port<'a>(&'a self) -> &'a Receiver<TaskDeath> {
+        &self.rx
+    }
+
+    /// Spawns a supervised task named `name`, running `body(arg)`.
+    ///
+    /// If the task fails, its failure is reported as for any other supervised
+    /// task, and then it's spawned again with a fresh clone of `arg` as long as
+    /// `policy` allows it. A task which returns normally isn't restarted.
+    ///
+    /// The body is a plain function rather than a `proc` because it may be run
+    /// more than once.
+    pub fn spawn<T: Clone + Send>(&self, name: &str, policy: RestartPolicy,
+                                  arg: T, body: fn(T)) {
+        let name = name.to_string();
+        let deaths = self.tx.clone();
+        TaskBuilder::new().named(format!("supervisor of '{}'", name)).spawn(proc() {
+            let mut restarts = 0u;
+            loop {
+                let mut builder = TaskBuilder::new().named(name.clone());
+                builder.supervisor = Some(deaths.clone());
+                let result = builder.future_result();
+                let arg = arg.clone();
+                builder.spawn(proc() body(arg));
+                match result.recv() {
+                    Ok(()) => break,
+                    Err(..) => {}
+                }
+                let restart = match policy {
+                    NoRestart => false,
+                    RestartUpTo(n) => restarts < n,
+                    AlwaysRestart => true,
+                };
+                if !restart { break }
+                restarts += 1;
+            }
+        })
+    }
+}
+
+impl<S: Spawner> TaskBuilder<S> {
+    /// Put the task-to-be under the supervision of `supervisor`, which is
+    /// told if the task fails.
+    pub fn supervised_by(mut self, supervisor: &Supervisor) -> TaskBuilder<S> {
+        self.supervisor = Some(supervisor.tx.clone());
+        self
+    }
+}
+
+// Wraps the body and the exit callback of a supervised task, so that the
+// body captures a backtrace if it fails and the callback reports the death.
+pub fn supervise(deaths: Sender<TaskDeath>, name: &Option<SendStr>,
+                 f: proc():Send, on_exit: Option<proc(TaskResult):Send>)
+                 -> (proc():Send, proc(TaskResult):Send) {
+    let name = name.as_ref().map(|n| n.as_slice().to_string());
+    let (bt_tx, bt_rx) = channel();
+    let f = proc() {
+        failure::add_backtrace_sink(bt_tx);
+        f()
+    };
+    let on_exit = proc(res: TaskResult) {
+        match res {
+            Err(ref e) => {
+                let e: &Any + Send = &**e;
+                let _ = deaths.send_opt(TaskDeath {
+                    name: name,
+                    message: failure::message(e).to_string(),
+                    backtrace: bt_rx.try_recv().ok(),
+                });
+            }
+            Ok(()) => {}
+        }
+        match on_exit {
+            Some(f) => f(res),
+            None => {}
+        }
+    };
+    (f, on_exit)
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use super::{Supervisor, NoRestart, RestartUpTo};
+    use task::TaskBuilder;
+
+    #[test]
+    fn test_failure_is_reported() {
+        let s = Supervisor::new();
+        TaskBuilder::new().named("a").supervised_by(&s).spawn(proc() {
+            fail!("oops {}", 1i)
+        });
+        let death = s.port().recv();
+        assert_eq!(death.name, Some("a".to_string()));
+        assert_eq!(death.message, "oops 1".to_string());
+        assert!(death.backtrace.is_some());
+    }
+
+    #[test]
+    fn test_success_is_not_reported() {
+        let s = Supervisor::new();
+        let mut b = TaskBuilder::new().supervised_by(&s);
+        let result = b.future_result();
+        b.spawn(proc() {});
+        assert!(result.recv().is_ok());
+        assert!(s.port().try_recv().is_err());
+    }
+
+    fn fail_if(should_fail: bool) {
+        if should_fail { fail!("failing") }
+    }
+
+    #[test]
+    fn test_restart() {
+        let s = Supervisor::new();
+        s.spawn("restarted", RestartUpTo(2), true, fail_if);
+        for _ in range(0u, 3) {
+            let death = s.port().recv();
+            assert_eq!(death.name, Some("restarted".to_string()));
+            assert_eq!(death.message, "failing".to_string());
+        }
+        s.spawn("fine", RestartUpTo(2), false, fail_if);
+        s.spawn("once", NoRestart, true, fail_if);
+        assert_eq!(s.port().recv().name, Some("once".to_string()));
+    }
+}
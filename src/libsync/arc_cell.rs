@@ -0,0 +1,168 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A cell holding an `Arc` which can be swapped atomically.
+
+use core::prelude::*;
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use core::mem;
+
+use atomics::{AtomicPtr, SeqCst};
+use epoch::Collector;
+
+/// A cell holding an `Arc<T>`, which tasks can read and replace without
+/// taking a lock.
+///
+/// This is a way of publishing snapshots of some value: readers `get` the
+/// current snapshot and keep using it for as long as they need to, while a
+/// writer `set`s a new one for readers which come later.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::{Arc, ArcCell};
+///
+/// let config = Arc::new(ArcCell::new(Arc::new(vec!["a"])));
+/// let snapshot = config.get();
+/// config.set(Arc::new(vec!["a", "b"]));
+/// assert_eq!(snapshot.len(), 1);
+/// assert_eq!(config.get().len(), 2);
+/// ```
+pub struct ArcCell<T> {
+    // Cloning the `Arc` has to read its reference count, so a task which has
+    // loaded the pointer keeps the collector pinned until the clone is made.
+    // The `Arc` is boxed so that it fits in an atomic pointer.
+    arc: AtomicPtr<Arc<T>>,
+    collector: Collector,
+}
+
+impl<T: Send + Share> ArcCell<T> {
+    /// Creates a new cell holding `t`.
+    pub fn new(t: Arc<T>) -> ArcCell<T> {
+        ArcCell {
+            arc: AtomicPtr::new(unsafe { mem::transmute(box t) }),
+            collector: Collector::new(),
+        }
+    }
+
+    /// Returns a clone of the `Arc` in the cell.
+    pub fn get(&self) -> Arc<T> {
+        let _guard = self.collector.pin();
+        unsafe { (*self.arc.load(SeqCst)).clone() }
+    }
+
+    /// Replaces the `Arc` in the cell with `t`, returning the previous one.
+    ///
+    /// Tasks which got the previous `Arc` keep it alive for as long as they
+    /// hold on to it.
+    ///
+    /// The cell's own reference to the previous `Arc` is given up before this
+    /// returns, unless another task is in the middle of a `get`. It is then
+    /// given up by a later `set`, or when the cell is dropped, so until then
+    /// the previous value isn't unique and isn't destroyed.
+    pub fn set(&self, t: Arc<T>) -> Arc<T> {
+        let new: *mut Arc<T> = unsafe { mem::transmute(box t) };
+        let old = self.arc.swap(new, SeqCst);
+        let old: Box<Arc<T>> = unsafe { mem::transmute(old) };
+        // Readers may still be cloning the box's `Arc`, so its reference can't
+        // be given back before they're done
+        let ret = (*old).clone();
+        self.collector.defer_drop(old);
+        // Garbage is only dropped once the epoch has advanced twice, which it
+        // can here unless a reader is pinned
+        self.collector.collect();
+        self.collector.collect();
+        ret
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send + Share> Drop for ArcCell<T> {
+    fn drop(&mut self) {
+        let _: Box<Arc<T>> = unsafe { mem::transmute(self.arc.load(SeqCst)) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use alloc::arc::Arc;
+    use native;
+    use super::ArcCell;
+
+    #[test]
+    fn smoke() {
+        let c = ArcCell::new(Arc::new(1i));
+        assert_eq!(*c.get(), 1);
+        assert_eq!(*c.set(Arc::new(2)), 1);
+        assert_eq!(*c.get(), 2);
+    }
+
+    #[test]
+    fn snapshots_outlive_set() {
+        let first = Arc::new(vec![1i]);
+        let c = ArcCell::new(first.clone());
+        let got = c.get();
+        drop(c.set(Arc::new(vec![2i])));
+        drop(first);
+        assert_eq!(*got, vec![1]);
+        drop(c);
+        assert_eq!(*got, vec![1]);
+    }
+
+    #[test]
+    fn set_drops_eagerly() {
+        let mut first = Arc::new(1i);
+        let c = ArcCell::new(first.clone());
+        drop(c.set(Arc::new(2)));
+        assert!(first.get_mut().is_some());
+    }
+
+    #[test]
+    fn set_waits_for_readers() {
+        let mut first = Arc::new(1i);
+        let c = ArcCell::new(first.clone());
+        {
+            let _guard = c.collector.pin();
+            drop(c.set(Arc::new(2)));
+            assert!(first.get_mut().is_none());
+        }
+        drop(c.set(Arc::new(3)));
+        assert!(first.get_mut().is_some());
+    }
+
+    #[test]
+    fn stress() {
+        static N: uint = 10000;
+        let c = Arc::new(ArcCell::new(Arc::new(0u)));
+        let (tx, rx) = channel();
+        for _ in range(0u, 4) {
+            let (c, tx) = (c.clone(), tx.clone());
+            native::task::spawn(proc() {
+                let mut last = 0;
+                for _ in range(0, N) {
+                    let cur = *c.get();
+                    assert!(cur >= last);
+                    last = cur;
+                }
+                tx.send(());
+            });
+        }
+        for i in range(1, N) {
+            c.set(Arc::new(i));
+        }
+        drop(tx);
+        for _ in range(0u, 4) { rx.recv(); }
+        assert_eq!(*c.get(), N - 1);
+    }
+}
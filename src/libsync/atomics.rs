@@ -16,6 +16,10 @@
 //!
 //! This module defines atomic versions of a select number of primitive
 //! types, including `AtomicBool`, `AtomicInt`, `AtomicUint`, and `AtomicOption`.
+//! Integers of an exact width are covered by `AtomicU8`, `AtomicU16`,
+//! `AtomicU32`, `AtomicU64` and `AtomicI64`, which are useful for packing state
+//! into a word of a known size or sharing it with C. Other small `Copy` types,
+//! such as C-like enums, can be made atomic with `Atomic`.
 //! Atomic types present operations that, when used correctly, synchronize
 //! updates between threads.
 //!
@@ -104,11 +108,22 @@
 use core::prelude::*;
 
 use alloc::boxed::Box;
+use core::kinds::marker;
 use core::mem;
+use core::ptr;
 
+#[cfg(not(test))]
 pub use core::atomics::{AtomicBool, AtomicInt, AtomicUint, AtomicPtr};
-pub use core::atomics::{Ordering, Relaxed, Release, Acquire, AcqRel, SeqCst};
+#[cfg(not(test))]
 pub use core::atomics::{INIT_ATOMIC_BOOL, INIT_ATOMIC_INT, INIT_ATOMIC_UINT};
+#[cfg(test)]
+pub use self::switching::{AtomicBool, AtomicInt, AtomicUint, AtomicPtr};
+#[cfg(test)]
+pub use self::switching::{INIT_ATOMIC_BOOL, INIT_ATOMIC_INT, INIT_ATOMIC_UINT};
+pub use core::atomics::{Ordering, Relaxed, Release, Acquire, AcqRel, SeqCst};
+pub use core::atomics::{AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicI64};
+pub use core::atomics::{INIT_ATOMIC_U8, INIT_ATOMIC_U16, INIT_ATOMIC_U32};
+pub use core::atomics::{INIT_ATOMIC_U64, INIT_ATOMIC_I64};
 pub use core::atomics::fence;
 
 /// An atomic, nullable unique pointer
@@ -182,6 +197,279 @@ impl<T> Drop for AtomicOption<T> {
     }
 }
 
+/// An atomic value of any `Copy` type which fits in a machine word
+///
+/// The value is stored in an `AtomicUint`, so all operations are as cheap as
+/// they are on a `uint`. The compare-and-swap operations compare values with
+/// `PartialEq`, and then swap out the exact word which was compared, so
+/// padding bytes (whose contents are arbitrary) can't make equal values
+/// compare unequal.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::atomics::{Atomic, SeqCst};
+///
+/// #[deriving(PartialEq, Show)]
+/// enum State { Idle, Running, Stopped }
+///
+/// let state = Atomic::new(Idle);
+/// assert_eq!(state.compare_and_swap(Idle, Running, SeqCst), Idle);
+/// assert_eq!(state.swap(Stopped, SeqCst), Running);
+/// ```
+pub struct Atomic<T> {
+    v: AtomicUint,
+    marker: marker::InvariantType<T>,
+}
+
+impl<T: Copy + Send> Atomic<T> {
+    /// Create a new `Atomic`
+    ///
+    /// # Failure
+    ///
+    /// Fails if `T` is larger than a `uint`, or needs a stricter alignment.
+    pub fn new(v: T) -> Atomic<T> {
+        assert!(mem::size_of::<T>() <= mem::size_of::<uint>(),
+                "Atomic can only hold types which fit in a uint");
+        assert!(mem::min_align_of::<T>() <= mem::min_align_of::<uint>());
+        Atomic {
+            v: AtomicUint::new(to_word(v)),
+            marker: marker::InvariantType,
+        }
+    }
+
+    /// Load the value
+    #[inline]
+    pub fn load(&self, order: Ordering) -> T {
+        from_word(self.v.load(order))
+    }
+
+    /// Store the value
+    #[inline]
+    pub fn store(&self, val: T, order: Ordering) {
+        self.v.store(to_word(val), order)
+    }
+
+    /// Store a value, returning the old value
+    #[inline]
+    pub fn swap(&self, val: T, order: Ordering) -> T {
+        from_word(self.v.swap(to_word(val), order))
+    }
+}
+
+impl<T: Copy + Send + PartialEq> Atomic<T> {
+    /// If the current value is the same as expected, store a new value
+    ///
+    /// Compare the current value with `old`; if they are the same then
+    /// replace the current value with `new`. Return the previous value.
+    #[inline]
+    pub fn compare_and_swap(&self, old: T, new: T, order: Ordering) -> T {
+        let new = to_word(new);
+        let mut cur = self.v.load(failure_ordering(order));
+        loop {
+            if from_word::<T>(cur) != old { return from_word(cur) }
+            // The word changed, or only its padding differed from `old`'s
+            let prev = self.v.compare_and_swap(cur, new, order);
+            if prev == cur { return from_word(prev) }
+            cur = prev;
+        }
+    }
+
+    /// Store a value if the current value is the same as `current`, as
+    /// `AtomicUint::compare_exchange` does
+    #[inline]
+    pub fn compare_exchange(&self, current: T, new: T,
+                            success: Ordering, failure: Ordering) -> Result<T, T> {
+        let new = to_word(new);
+        let mut cur = self.v.load(failure);
+        loop {
+            if from_word::<T>(cur) != current { return Err(from_word(cur)) }
+            match self.v.compare_exchange(cur, new, success, failure) {
+                Ok(w) => return Ok(from_word(w)),
+                Err(w) => cur = w,
+            }
+        }
+    }
+
+    /// Store a value if the current value is the same as `current`, possibly
+    /// failing spuriously, as `AtomicUint::compare_exchange_weak` does
+    #[inline]
+    pub fn compare_exchange_weak(&self, current: T, new: T,
+                                 success: Ordering, failure: Ordering) -> Result<T, T> {
+        let cur = self.v.load(failure);
+        if from_word::<T>(cur) != current { return Err(from_word(cur)) }
+        match self.v.compare_exchange_weak(cur, to_word(new), success, failure) {
+            Ok(w) => Ok(from_word(w)),
+            Err(w) => Err(from_word(w)),
+        }
+    }
+}
+
+// The ordering of the load which a compare-and-swap with `order` does when it
+// fails
+#[inline]
+fn failure_ordering(order: Ordering) -> Ordering {
+    match order {
+        Release => Relaxed,
+        AcqRel => Acquire,
+        order => order,
+    }
+}
+
+// Only called on types which `Atomic::new` checked fit in a word. Unused bytes
+// of the word are always zero, but the padding bytes of `T` itself (such as
+// the payload of a dataless enum variant) are copied as they are.
+#[inline]
+fn to_word<T: Copy>(t: T) -> uint {
+    let mut w = 0u;
+    unsafe { ptr::write(&mut w as *mut uint as *mut T, t); }
+    w
+}
+
+#[inline]
+fn from_word<T: Copy>(w: uint) -> T {
+    unsafe { ptr::read(&w as *const uint as *const T) }
+}
+
+// In the tests of this crate, the atomic types are wrapped so that every
+// operation on them is a point at which the model checker can switch threads
+// (see `model`), and at which faults can be injected (see `fault`). They
+// behave the same otherwise, apart from the checks for either.
+#[cfg(test)]
+#[allow(missing_doc)]
+mod switching {
+    use core::prelude::*;
+
+    use core::atomics;
+    use core::atomics::Ordering;
+
+    use fault;
+    use model;
+
+    fn point() {
+        model::switch();
+        fault::inject();
+    }
+
+    macro_rules! atomic(
+        ($atomic:ident, $init:ident, $t:ty) => (
+            pub struct $atomic {
+                inner: atomics::$atomic,
+            }
+
+            pub static $init: $atomic = $atomic { inner: atomics::$init };
+
+            impl $atomic {
+                pub fn new(v: $t) -> $atomic {
+                    $atomic { inner: atomics::$atomic::new(v) }
+                }
+
+                pub fn load(&self, order: Ordering) -> $t {
+                    point();
+                    self.inner.load(order)
+                }
+
+                pub fn store(&self, val: $t, order: Ordering) {
+                    point();
+                    self.inner.store(val, order)
+                }
+
+                pub fn swap(&self, val: $t, order: Ordering) -> $t {
+                    point();
+                    self.inner.swap(val, order)
+                }
+
+                pub fn compare_and_swap(&self, old: $t, new: $t,
+                                        order: Ordering) -> $t {
+                    point();
+                    self.inner.compare_and_swap(old, new, order)
+                }
+
+                pub fn compare_exchange(&self, current: $t, new: $t,
+                                        success: Ordering,
+                                        failure: Ordering) -> Result<$t, $t> {
+                    point();
+                    self.inner.compare_exchange(current, new, success, failure)
+                }
+
+                pub fn compare_exchange_weak(&self, current: $t, new: $t,
+                                             success: Ordering,
+                                             failure: Ordering) -> Result<$t, $t> {
+                    point();
+                    self.inner.compare_exchange_weak(current, new, success, failure)
+                }
+            }
+        )
+    )
+
+    macro_rules! fetch(
+        ($atomic:ident, $t:ty, $($op:ident),+) => (
+            impl $atomic {
+                $(
+                    pub fn $op(&self, val: $t, order: Ordering) -> $t {
+                        point();
+                        self.inner.$op(val, order)
+                    }
+                )+
+            }
+        )
+    )
+
+    atomic!(AtomicBool, INIT_ATOMIC_BOOL, bool)
+    atomic!(AtomicInt, INIT_ATOMIC_INT, int)
+    atomic!(AtomicUint, INIT_ATOMIC_UINT, uint)
+    fetch!(AtomicBool, bool, fetch_and, fetch_nand, fetch_or, fetch_xor)
+    fetch!(AtomicInt, int, fetch_add, fetch_sub, fetch_and, fetch_nand, fetch_or,
+           fetch_xor)
+    fetch!(AtomicUint, uint, fetch_add, fetch_sub, fetch_and, fetch_nand, fetch_or,
+           fetch_xor)
+
+    pub struct AtomicPtr<T> {
+        inner: atomics::AtomicPtr<T>,
+    }
+
+    impl<T> AtomicPtr<T> {
+        pub fn new(p: *mut T) -> AtomicPtr<T> {
+            AtomicPtr { inner: atomics::AtomicPtr::new(p) }
+        }
+
+        pub fn load(&self, order: Ordering) -> *mut T {
+            point();
+            self.inner.load(order)
+        }
+
+        pub fn store(&self, ptr: *mut T, order: Ordering) {
+            point();
+            self.inner.store(ptr, order)
+        }
+
+        pub fn swap(&self, ptr: *mut T, order: Ordering) -> *mut T {
+            point();
+            self.inner.swap(ptr, order)
+        }
+
+        pub fn compare_and_swap(&self, old: *mut T, new: *mut T,
+                                order: Ordering) -> *mut T {
+            point();
+            self.inner.compare_and_swap(old, new, order)
+        }
+
+        pub fn compare_exchange(&self, current: *mut T, new: *mut T,
+                                success: Ordering,
+                                failure: Ordering) -> Result<*mut T, *mut T> {
+            point();
+            self.inner.compare_exchange(current, new, success, failure)
+        }
+
+        pub fn compare_exchange_weak(&self, current: *mut T, new: *mut T,
+                                     success: Ordering,
+                                     failure: Ordering) -> Result<*mut T, *mut T> {
+            point();
+            self.inner.compare_exchange_weak(current, new, success, failure)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::prelude::*;
@@ -217,6 +505,52 @@ mod test {
         assert!(p.take(SeqCst) == Some(box 2));
     }
 
+    #[deriving(PartialEq, Show)]
+    enum State { Idle, Running, Stopped }
+
+    #[test]
+    fn atomic_enum() {
+        let s = Atomic::new(Idle);
+        assert_eq!(s.load(SeqCst), Idle);
+        assert_eq!(s.compare_and_swap(Running, Stopped, SeqCst), Idle);
+        assert_eq!(s.compare_exchange(Idle, Running, SeqCst, SeqCst), Ok(Idle));
+        assert_eq!(s.compare_exchange(Idle, Stopped, SeqCst, SeqCst), Err(Running));
+        s.store(Stopped, SeqCst);
+        assert_eq!(s.swap(Idle, SeqCst), Stopped);
+    }
+
+    #[test]
+    fn atomic_small() {
+        // Values narrower than a word don't compare unequal because of the
+        // unused bytes
+        let s = Atomic::new(Some(3u8));
+        assert_eq!(s.compare_and_swap(Some(3), None, SeqCst), Some(3));
+        assert_eq!(s.load(SeqCst), None);
+
+        static X: int = 4;
+        let p = Atomic::new(None::<&'static int>);
+        assert_eq!(p.swap(Some(&X), SeqCst), None);
+        assert_eq!(p.load(SeqCst), Some(&X));
+    }
+
+    #[test]
+    fn atomic_padding() {
+        // Equal values whose padding bytes differ still compare equal
+        let mut v = (1u8, 2u16);
+        unsafe { *(&mut v as *mut (u8, u16) as *mut u8).offset(1) = 0xff; }
+        let s = Atomic::new(v);
+        assert_eq!(s.compare_and_swap((1, 2), (3, 4), SeqCst), (1, 2));
+        assert_eq!(s.load(SeqCst), (3, 4));
+        let s = Atomic::new(v);
+        assert_eq!(s.compare_exchange((1, 2), (5, 6), SeqCst, SeqCst), Ok((1, 2)));
+        assert_eq!(s.compare_exchange((1, 2), (7, 8), SeqCst, SeqCst), Err((5, 6)));
+    }
+
+    #[test] #[should_fail]
+    fn atomic_too_big() {
+        let _ = Atomic::new((0u, 0u));
+    }
+
     #[test]
     fn option_fill() {
         let p = AtomicOption::new(box 1i);
@@ -0,0 +1,113 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Backoff strategies for contended operations
+//!
+//! Lock-free code occasionally has to wait for another thread to finish what
+//! it is doing, for example when a queue is caught in the middle of a push.
+//! Retrying immediately wastes cycles the other thread could be using, while
+//! yielding the thread right away is expensive when the wait would have been a
+//! few instructions long. A `Backoff` decides how long to wait between
+//! retries.
+//!
+//! Backoff strategies are stateless: the caller keeps track of how many times
+//! it has retried, so a single strategy can be shared among threads.
+
+#![experimental]
+
+use core::prelude::*;
+
+use core::cmp;
+use core::intrinsics;
+use rustrt::thread::Thread;
+
+/// A strategy for waiting before retrying an operation which couldn't make
+/// progress.
+pub trait Backoff {
+    /// Waits before the `step`th retry of an operation, counting from zero.
+    fn snooze(&self, step: uint);
+}
+
+/// Spins for a short, constant, amount of time between retries, and never
+/// yields the thread.
+///
+/// This is only appropriate when every thread involved has a processor to
+/// itself. When there are more threads than processors, the thread being
+/// waited on may have been preempted in the middle of its operation, and a
+/// spinning thread never gives up its processor to let it finish. Neither
+/// makes progress until the OS happens to preempt the spinner, and with
+/// enough spinners that's a livelock. `SpinYield` or `Exponential` are the
+/// safe choices there.
+pub struct Spin;
+
+/// Spins for the first few retries, and then yields the thread on each retry.
+///
+/// This is the strategy used by channels unless told otherwise.
+pub struct SpinYield;
+
+/// Spins for exponentially longer between each retry, for up to `limit`
+/// doublings, and then yields the thread on each retry.
+pub struct Exponential {
+    /// The number of retries after which the thread is yielded.
+    pub limit: uint,
+}
+
+// The number of iterations of a single short spin
+static SPIN: uint = 64;
+// The number of retries `SpinYield` spins for
+static YIELD_AFTER: uint = 4;
+
+impl Backoff for Spin {
+    fn snooze(&self, _step: uint) { spin(SPIN) }
+}
+
+impl Backoff for SpinYield {
+    fn snooze(&self, step: uint) {
+        if step < YIELD_AFTER { spin(SPIN) } else { Thread::yield_now() }
+    }
+}
+
+impl Backoff for Exponential {
+    fn snooze(&self, step: uint) {
+        if step < self.limit {
+            // Keep the shift in range no matter how large the limit is
+            spin(1 << cmp::min(step, 16))
+        } else {
+            Thread::yield_now()
+        }
+    }
+}
+
+// Busy-waits for `n` iterations of a loop which won't be optimized away
+fn spin(n: uint) {
+    let mut i = 0u;
+    while unsafe { intrinsics::volatile_load(&i as *const uint) } < n {
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use super::{Backoff, Spin, SpinYield, Exponential};
+
+    fn exercise(b: &Backoff) {
+        for step in range(0u, 40) { b.snooze(step); }
+    }
+
+    #[test]
+    fn snooze() {
+        exercise(&Spin);
+        exercise(&SpinYield);
+        exercise(&Exponential { limit: 3 });
+        exercise(&Exponential { limit: 100 });
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pluggable queues for stream channels
+//!
+//! A stream channel (one sender, one receiver) normally stores its messages in
+//! an unbounded SPSC queue with a small node cache. This module defines the
+//! interface that the stream's blocking protocol requires of its queue, so
+//! that specialized deployments can supply an alternate data structure with
+//! `channel_with_queue` without forking the protocol itself.
+//!
+//! Only the stream flavor is pluggable. If the sending half of a channel
+//! created with a custom queue is cloned, the channel is upgraded to a shared
+//! channel as usual and subsequent messages go through the shared channel's
+//! own queue.
+//!
+//! # Example
+//!
+//! A queue made of a linked list behind a lock. The elements of a `DList` are
+//! boxed, so a peeked element stays where it is while the producer pushes.
+//!
+//! ```
+//! use std::cell::UnsafeCell;
+//! use std::collections::DList;
+//! use std::comm::{channel_with_queue, MessageQueue, QueueBuilder};
+//! use std::rt::mutex::NativeMutex;
+//!
+//! struct Locked<T> {
+//!     lock: NativeMutex,
+//!     list: UnsafeCell<DList<T>>,
+//! }
+//!
+//! impl<T: Send> MessageQueue<T> for Locked<T> {
+//!     fn push(&self, t: T) {
+//!         unsafe {
+//!             let _g = self.lock.lock();
+//!             (*self.list.get()).push(t);
+//!         }
+//!     }
+//!     fn pop(&self) -> Option<T> {
+//!         unsafe {
+//!             let _g = self.lock.lock();
+//!             (*self.list.get()).pop_front()
+//!         }
+//!     }
+//!     fn peek<'a>(&'a self) -> Option<&'a mut T> {
+//!         unsafe {
+//!             let _g = self.lock.lock();
+//!             (*self.list.get()).front_mut()
+//!         }
+//!     }
+//! }
+//!
+//! struct LockedBuilder;
+//!
+//! impl QueueBuilder for LockedBuilder {
+//!     fn build<T: Send>(&self) -> Box<MessageQueue<T> + Send> {
+//!         box Locked {
+//!             lock: unsafe { NativeMutex::new() },
+//!             list: UnsafeCell::new(DList::new()),
+//!         } as Box<MessageQueue<T> + Send>
+//!     }
+//! }
+//!
+//! let (tx, rx) = channel_with_queue(LockedBuilder);
+//! spawn(proc() {
+//!     for i in range(0i, 10) { tx.send(i); }
+//! });
+//! assert_eq!(rx.iter().collect::<Vec<int>>(), range(0i, 10).collect());
+//! ```
+
+#![experimental]
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+
+use spsc = spsc_queue;
+
+/// The queue operations needed by a stream channel.
+///
+/// Implementations are used concurrently by exactly one producer (calling
+/// `push`) and one consumer (calling `pop` and `peek`). The queue must be
+/// unbounded: `push` must never block or fail.
+pub trait MessageQueue<T>: Send {
+    /// Appends a value to the back of the queue. Only ever called by the
+    /// producer.
+    fn push(&self, t: T);
+
+    /// Removes a value from the front of the queue. Only ever called by the
+    /// consumer.
+    fn pop(&self) -> Option<T>;
+
+    /// Returns the value at the front of the queue, without removing it. Only
+    /// ever called by the consumer, and the returned reference must remain
+    /// valid while the producer continues to push.
+    fn peek<'a>(&'a self) -> Option<&'a mut T>;
+}
+
+/// A factory for the queue of a stream channel.
+///
+/// The element type of the queue is an internal message type of the channel,
+/// so builders must be able to construct a queue of any type.
+pub trait QueueBuilder {
+    /// Creates a new, empty, queue.
+    fn build<T: Send>(&self) -> Box<MessageQueue<T> + Send>;
+}
+
+/// Builds the default SPSC queue, with a node cache bounded to `bound` nodes
+/// (0 means unbounded).
+pub struct SpscBuilder {
+    /// The bound on the cache of nodes
+    pub bound: uint,
+}
+
+impl QueueBuilder for SpscBuilder {
+    fn build<T: Send>(&self) -> Box<MessageQueue<T> + Send> {
+        box spsc::Queue::new(self.bound) as Box<MessageQueue<T> + Send>
+    }
+}
+
+impl<T: Send> MessageQueue<T> for spsc::Queue<T> {
+    fn push(&self, t: T) { spsc::Queue::push(self, t) }
+    fn pop(&self) -> Option<T> { spsc::Queue::pop(self) }
+    fn peek<'a>(&'a self) -> Option<&'a mut T> { spsc::Queue::peek(self) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use collections::{DList, Deque};
+    use core::cell::UnsafeCell;
+    use rustrt::mutex::NativeMutex;
+
+    use super::{MessageQueue, QueueBuilder, SpscBuilder};
+    use comm::{channel_with_queue, Empty};
+
+    // A deliberately simple backend, a linked list behind a lock. Elements of
+    // a DList are boxed, so a peeked element stays put while pushing.
+    struct Locked<T> {
+        lock: NativeMutex,
+        list: UnsafeCell<DList<T>>,
+    }
+
+    impl<T: Send> MessageQueue<T> for Locked<T> {
+        fn push(&self, t: T) {
+            unsafe {
+                let _g = self.lock.lock();
+                (*self.list.get()).push(t);
+            }
+        }
+        fn pop(&self) -> Option<T> {
+            unsafe {
+                let _g = self.lock.lock();
+                (*self.list.get()).pop_front()
+            }
+        }
+        fn peek<'a>(&'a self) -> Option<&'a mut T> {
+            unsafe {
+                let _g = self.lock.lock();
+                (*self.list.get()).front_mut()
+            }
+        }
+    }
+
+    struct LockedBuilder;
+
+    impl QueueBuilder for LockedBuilder {
+        fn build<T: Send>(&self) -> Box<MessageQueue<T> + Send> {
+            box Locked {
+                lock: unsafe { NativeMutex::new() },
+                list: UnsafeCell::new(DList::new()),
+            } as Box<MessageQueue<T> + Send>
+        }
+    }
+
+    test!(fn custom_backend() {
+        let (tx, rx) = channel_with_queue(LockedBuilder);
+        spawn(proc() {
+            for i in range(0i, 1000) { tx.send(i); }
+        });
+        for i in range(0i, 1000) { assert_eq!(rx.recv(), i); }
+        assert_eq!(rx.recv_opt(), Err(()));
+    })
+
+    test!(fn custom_backend_upgrade() {
+        let (tx, rx) = channel_with_queue(LockedBuilder);
+        tx.send(1i);
+        let tx2 = tx.clone();
+        tx2.send(2);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        assert_eq!(rx.try_recv(), Err(Empty));
+    })
+
+    test!(fn spsc_builder() {
+        let (tx, rx) = channel_with_queue(SpscBuilder { bound: 1 });
+        for i in range(0i, 10) { tx.send(i); }
+        for i in range(0i, 10) { assert_eq!(rx.recv(), i); }
+    })
+}
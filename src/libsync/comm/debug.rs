@@ -0,0 +1,368 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A registry of live channels, for debugging
+//!
+//! When the `RUST_COMM_DEBUG` environment variable is set, every channel is
+//! created with statistics enabled (see `channel_with_stats`) and recorded in
+//! a global table, along with the type of its messages and a backtrace of
+//! where it was created. The channels which are still alive can then be
+//! listed with `dump`, which makes it possible to find the one channel that's
+//! filling up with messages no one receives in a large program.
+//!
+//! ```no_run
+//! use std::comm::debug;
+//!
+//! for channel in debug::dump().iter() {
+//!     if channel.depth > 10000 { println!("{}", channel) }
+//! }
+//! ```
+//!
+//! The tasks which are blocked receiving on channels (in `recv` or in
+//! `Select::wait`) are recorded too, and can be listed with `blocked`. The
+//! standard library prints them if the main task returns while there are any,
+//! as they're likely what keeps the program from exiting. On unix, setting
+//! `RUST_COMM_DEBUG_SIGNAL` to the number of a signal (such as 10, `SIGUSR1`
+//! on Linux) also has them printed each time the process receives the signal,
+//! for programs which don't get to the end of their main task.
+//!
+//! Tracking costs a global lock and a backtrace for every channel created, a
+//! global lock each time a receiver blocks, and the statistics of every
+//! message sent, so it's only meant for debugging. The table only holds weak
+//! references to the channels, so a channel disappears from it once all of
+//! its halves have been dropped.
+
+use core::prelude::*;
+
+use alloc::arc::{Arc, Weak};
+use alloc::boxed::Box;
+use collections::{Vec, MutableSeq};
+use collections::str::SendStr;
+use core::atomics;
+use core::fmt;
+use core::intrinsics;
+use core::mem;
+use rustrt::local::Local;
+use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+use rustrt::task::Task;
+
+use comm::ChannelStats;
+use comm::stats::{Stats, Counters};
+use comm::trace;
+
+// Whether tracking is enabled: unknown until the environment has been looked
+// at, and then either on or off
+static UNKNOWN: uint = 0;
+static OFF: uint = 1;
+static ON: uint = 2;
+static mut ENABLED: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut CHANNELS: *mut Vec<Entry> = 0 as *mut Vec<Entry>;
+static mut CAPTURE: Option<fn() -> Box<fmt::Show + Send + Share>> = None;
+static mut WAITERS: *mut Vec<Waiter> = 0 as *mut Vec<Waiter>;
+static mut NEXT_KEY: uint = 1;
+
+struct Entry {
+    id: uint,
+    type_name: &'static str,
+    backtrace: Option<Backtrace>,
+    counters: Weak<Counters>,
+}
+
+/// A live channel, as listed by `dump`.
+#[experimental]
+pub struct ChannelInfo {
+    /// A number identifying the channel, which goes up in the order the
+    /// channels were created. This is also the channel of its trace events.
+    pub id: uint,
+    /// The type of the messages of the channel.
+    pub type_name: &'static str,
+    /// The number of messages which have been sent but not yet received.
+    pub depth: uint,
+    /// The counters of the channel.
+    pub stats: ChannelStats,
+    /// Where the channel was created, if backtraces can be captured. They can
+    /// be whenever the standard library is in use.
+    pub backtrace: Option<Backtrace>,
+}
+
+struct Waiter {
+    key: uint,
+    name: Option<SendStr>,
+    channels: Vec<uint>,
+}
+
+/// A task blocked receiving on channels, as listed by `blocked`.
+#[experimental]
+pub struct BlockedInfo {
+    /// The name of the task, if it has one.
+    pub name: Option<SendStr>,
+    /// The channels the task is waiting on, by the identifiers of `dump`.
+    /// There's more than one if it's waiting in `Select::wait`.
+    pub channels: Vec<uint>,
+}
+
+/// Keeps a task in the list of blocked tasks until it's dropped.
+#[doc(hidden)]
+pub struct Waiting {
+    key: uint,
+}
+
+/// The backtrace of the creation of a channel.
+#[deriving(Clone)]
+#[experimental]
+pub struct Backtrace {
+    inner: Arc<Box<fmt::Show + Send + Share>>,
+}
+
+/// Returns whether channels are being tracked, which is when the
+/// `RUST_COMM_DEBUG` environment variable is set.
+#[inline]
+pub fn enabled() -> bool {
+    match unsafe { ENABLED.load(atomics::Relaxed) } {
+        UNKNOWN => check_env(),
+        n => n == ON,
+    }
+}
+
+fn check_env() -> bool {
+    extern { fn getenv(name: *const u8) -> *const u8; }
+    let on = unsafe { !getenv(b"RUST_COMM_DEBUG\0".as_ptr()).is_null() };
+    unsafe { ENABLED.store(if on {ON} else {OFF}, atomics::Relaxed); }
+    on
+}
+
+/// Lists the channels which are alive, in the order they were created.
+///
+/// The list is empty unless channels are being tracked, see `enabled`.
+#[experimental]
+pub fn dump() -> Vec<ChannelInfo> {
+    with_channels(|channels| {
+        channels.retain(|e| e.counters.upgrade().is_some());
+        channels.iter().filter_map(|e| {
+            e.counters.upgrade().map(|c| {
+                let stats = c.snapshot();
+                ChannelInfo {
+                    id: e.id,
+                    type_name: e.type_name,
+                    depth: if stats.sends > stats.recvs {
+                        stats.sends - stats.recvs
+                    } else {
+                        0
+                    },
+                    stats: stats,
+                    backtrace: e.backtrace.clone(),
+                }
+            })
+        }).collect()
+    })
+}
+
+/// Lists the tasks which are blocked receiving on channels, in the order they
+/// blocked.
+///
+/// The list is empty unless channels are being tracked, see `enabled`.
+#[experimental]
+pub fn blocked() -> Vec<BlockedInfo> {
+    with_waiters(|waiters| {
+        waiters.iter().map(|w| {
+            BlockedInfo { name: w.name.clone(), channels: w.channels.clone() }
+        }).collect()
+    })
+}
+
+/// Records the current task as blocked on `channels` until the returned
+/// value is dropped, if channels are being tracked.
+#[doc(hidden)]
+pub fn waiting(channels: &[uint]) -> Waiting {
+    if enabled() { wait_on(channels) } else { Waiting::none() }
+}
+
+fn wait_on(channels: &[uint]) -> Waiting {
+    let task: Option<*mut Task> = unsafe { Local::try_unsafe_borrow() };
+    let name = task.and_then(|t| unsafe { (*t).name.clone() });
+    with_waiters(|waiters| {
+        let key = unsafe { NEXT_KEY };
+        unsafe { NEXT_KEY += 1; }
+        waiters.push(Waiter {
+            key: key,
+            name: name,
+            channels: Vec::from_slice(channels),
+        });
+        Waiting { key: key }
+    })
+}
+
+/// Sets the function which captures the backtraces of channels as they are
+/// created. This is called by the standard library when the runtime starts.
+#[doc(hidden)]
+pub fn set_backtrace_hook(f: fn() -> Box<fmt::Show + Send + Share>) {
+    unsafe {
+        let _g = LOCK.lock();
+        CAPTURE = Some(f);
+    }
+}
+
+/// Records a new channel of `T`s whose packet has `stats`, if channels are
+/// being tracked. The statistics are turned on if they aren't already, so the
+/// packet must be given the returned ones. They're also turned on when there's
+/// a tracer, for it to be handed the events of the channel.
+#[doc(hidden)]
+pub fn track<T>(stats: Stats) -> Stats {
+    if enabled() {
+        register::<T>(stats)
+    } else if trace::tracing() && !stats.is_on() {
+        Stats::on()
+    } else {
+        stats
+    }
+}
+
+fn register<T>(stats: Stats) -> Stats {
+    let stats = if stats.is_on() { stats } else { Stats::on() };
+    let capture = unsafe {
+        let _g = LOCK.lock();
+        CAPTURE
+    };
+    // The backtrace is captured outside of the lock, as it may take a while
+    let backtrace = capture.map(|f| Backtrace { inner: Arc::new(f()) });
+    let entry = Entry {
+        id: stats.id().unwrap(),
+        type_name: unsafe { (*intrinsics::get_tydesc::<T>()).name },
+        backtrace: backtrace,
+        counters: stats.downgrade().unwrap(),
+    };
+    with_channels(|channels| {
+        // Forget about dead channels every so often, so that a program which
+        // creates lots of them doesn't grow the table forever
+        if entry.id % 1024 == 0 {
+            channels.retain(|e| e.counters.upgrade().is_some());
+        }
+        channels.push(entry);
+    });
+    stats
+}
+
+fn with_channels<T>(f: |&mut Vec<Entry>| -> T) -> T {
+    unsafe {
+        let _g = LOCK.lock();
+        if CHANNELS.is_null() {
+            CHANNELS = mem::transmute(box Vec::<Entry>::new());
+        }
+        f(&mut *CHANNELS)
+    }
+}
+
+fn with_waiters<T>(f: |&mut Vec<Waiter>| -> T) -> T {
+    unsafe {
+        let _g = LOCK.lock();
+        if WAITERS.is_null() {
+            WAITERS = mem::transmute(box Vec::<Waiter>::new());
+        }
+        f(&mut *WAITERS)
+    }
+}
+
+impl Waiting {
+    /// A task which isn't being recorded.
+    pub fn none() -> Waiting { Waiting { key: 0 } }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if self.key == 0 { return }
+        let key = self.key;
+        with_waiters(|waiters| waiters.retain(|w| w.key != key));
+    }
+}
+
+impl fmt::Show for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self.inner).fmt(f)
+    }
+}
+
+impl fmt::Show for ChannelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "channel {} of `{}`: {} queued ({} sent, {} received)",
+                      self.id, self.type_name, self.depth, self.stats.sends,
+                      self.stats.recvs));
+        match self.backtrace {
+            Some(ref bt) => write!(f, "{}", bt),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Show for BlockedInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.name {
+            Some(ref name) => write!(f, "task `{}`", name),
+            None => write!(f, "an unnamed task"),
+        });
+        try!(write!(f, " is blocked on channel"));
+        if self.channels.len() != 1 { try!(write!(f, "s")); }
+        for (i, id) in self.channels.iter().enumerate() {
+            try!(write!(f, "{} {}", if i == 0 {""} else {","}, id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use std::uint;
+
+    use comm::stats::Stats;
+    use super::{dump, register, blocked, wait_on};
+
+    struct Marker;
+
+    // This registers a channel directly, so it doesn't depend on the
+    // environment variable
+
+    #[test]
+    fn live_channels() {
+        let stats = register::<Marker>(Stats::off());
+        stats.sent();
+        stats.sent();
+        stats.received();
+
+        let info = dump().move_iter().find(|c| c.type_name.contains("Marker"));
+        let info = info.unwrap();
+        assert_eq!(info.depth, 1);
+        assert_eq!(info.stats.sends, 2);
+        assert!(format!("{}", info).as_slice().contains("1 queued"));
+
+        let id = info.id;
+        drop(stats);
+        assert!(dump().iter().all(|c| c.id != id));
+    }
+
+    // The channels are made up, so that no other task can be waiting on them
+    #[test]
+    fn blocked_tasks() {
+        let channels = [uint::MAX - 1, uint::MAX];
+        let ours = || {
+            blocked().move_iter().find(|b| b.channels.as_slice() == channels.as_slice())
+        };
+
+        let waiting = wait_on(channels.as_slice());
+        let info = ours().unwrap();
+        assert!(format!("{}", info).as_slice()
+                    .ends_with(format!("blocked on channels {}, {}",
+                                       channels[0], channels[1]).as_slice()));
+        drop(waiting);
+        assert!(ours().is_none());
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reporting of the messages destroyed along with a receiver
+//!
+//! When a receiver is dropped, the messages still queued on its channel are
+//! destroyed with it, which is often how a message goes missing. Once a hook
+//! is set with `set_discard_hook`, every receiver which is dropped with
+//! messages left is reported to it. Until then, the only cost is that of
+//! counting the messages as they're destroyed.
+//!
+//! Only the messages which the receiver finds on the queue are counted. The
+//! sends which race with the drop fail instead, and their messages are handed
+//! back to the senders.
+
+use core::prelude::*;
+
+use core::atomics;
+use core::fmt;
+use core::intrinsics;
+use core::mem;
+
+use comm::stats::Stats;
+
+// The hook, as a `fn(&Discarded)`, or 0 if there's none
+static mut HOOK: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+/// The messages a receiver was dropped with, as given to the discard hook.
+#[deriving(PartialEq, Clone)]
+#[experimental]
+pub struct Discarded {
+    /// The channel, by the same identifier as in `debug::dump` and in trace
+    /// events, if it has one. Only the channels with statistics (which all
+    /// are when `RUST_COMM_DEBUG` is set, or when there's a tracer) do.
+    pub channel: Option<uint>,
+    /// The type of the messages of the channel.
+    pub type_name: &'static str,
+    /// The number of messages which were destroyed.
+    pub messages: uint,
+}
+
+/// Sets the function which is called whenever a receiver is dropped while
+/// there are messages queued on its channel, replacing the previous one.
+///
+/// The hook is called on the task dropping the receiver, after the messages
+/// have been destroyed. When `RUST_COMM_DEBUG` is set, the standard library
+/// sets a hook which prints the discarded messages to stderr.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::{Discarded, set_discard_hook};
+///
+/// fn report(d: &Discarded) {
+///     println!("lost {} messages of `{}`", d.messages, d.type_name);
+/// }
+///
+/// set_discard_hook(report);
+/// let (tx, rx) = channel();
+/// tx.send(1i);
+/// drop(rx); // prints "lost 1 messages of `int`"
+/// ```
+#[experimental]
+pub fn set_discard_hook(hook: fn(&Discarded)) {
+    unsafe { HOOK.store(mem::transmute(hook), atomics::Release) }
+}
+
+/// Reports `messages` messages of `T` which were destroyed as the receiver of
+/// the channel with `stats` was dropped, if there were any.
+pub fn discarded<T>(stats: &Stats, messages: uint) {
+    if messages == 0 { return }
+    let hook = unsafe { HOOK.load(atomics::Acquire) };
+    if hook == 0 { return }
+    let hook: fn(&Discarded) = unsafe { mem::transmute(hook) };
+    hook(&Discarded {
+        channel: stats.id(),
+        type_name: unsafe { (*intrinsics::get_tydesc::<T>()).name },
+        messages: messages,
+    });
+}
+
+impl fmt::Show for Discarded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.channel {
+            Some(id) => write!(f, "channel {}", id),
+            None => write!(f, "a channel"),
+        });
+        write!(f, " of `{}` was dropped with {} messages", self.type_name,
+               self.messages)
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The task which runs the callbacks of `Receiver::on_recv`
+//!
+//! All of the receivers with a callback are watched by a single dispatcher
+//! task through a `Poller`, so waiting for them costs one blocked task rather
+//! than one per receiver. New callbacks are handed to the dispatcher over a
+//! channel of its own, which is also in the poller.
+//!
+//! The dispatcher is spawned by the first callback to be registered, and it
+//! exits once it has no callbacks left, so that it doesn't keep the runtime
+//! alive. The sender of its channel is kept in a global, which is cleared
+//! (under the global's lock) by the dispatcher as it exits. A callback being
+//! registered therefore either reaches a dispatcher which will run it, or
+//! finds that there is none and spawns another.
+//!
+//! Each callback is run in a task of its own, spawned by the dispatcher once
+//! the message has been received, so that a callback which fails or blocks
+//! doesn't take down or hold up the callbacks of every other receiver.
+//!
+//! Nothing which can switch tasks may happen under the lock: `send_opt` never
+//! reschedules, but spawning a task may run it immediately.
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+use collections::{Vec, MutableSeq};
+use collections::str::Slice;
+use core::mem;
+use rustrt::local::Local;
+use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+use rustrt::task::{Task, TaskOpts};
+
+use comm::{Sender, Receiver, Poller, Empty, Disconnected, channel};
+
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut DISPATCHER: *mut Sender<Box<Callback + Send>> =
+    0 as *mut Sender<Box<Callback + Send>>;
+
+// The number of receivers the dispatcher's poller starts out with room for
+static INITIAL_CAPACITY: uint = 64;
+
+// A receiver along with the callback for its next message
+trait Callback {
+    // Adds the receiver to `poller`, returning its token. The receiver must
+    // be removed from the poller before the callback is moved or dropped.
+    unsafe fn add(&self, poller: &mut Poller<'static>) -> uint;
+
+    // Spawns a task running the callback if its receiver has a message, or
+    // drops it if the receiver has hung up, handing it back if neither is the
+    // case.
+    fn fire(self: Box<Self>) -> Option<Box<Callback + Send>>;
+}
+
+struct OnRecv<T> {
+    rx: Receiver<T>,
+    f: proc(T, Receiver<T>):Send,
+}
+
+impl<T: Send> Callback for OnRecv<T> {
+    unsafe fn add(&self, poller: &mut Poller<'static>) -> uint {
+        poller.add(mem::transmute::<&Receiver<T>, &'static Receiver<T>>(&self.rx))
+    }
+
+    fn fire(self: Box<OnRecv<T>>) -> Option<Box<Callback + Send>> {
+        let OnRecv { rx, f } = *self;
+        match rx.try_recv() {
+            Ok(t) => { spawn("<comm callback>", proc() f(t, rx)); None }
+            Err(Disconnected) => None,
+            Err(Empty) => Some(box OnRecv { rx: rx, f: f } as Box<Callback + Send>),
+        }
+    }
+}
+
+/// Arranges for the dispatcher to run `f` with the next message received on
+/// `rx`.
+pub fn register<T: Send>(rx: Receiver<T>, f: proc(T, Receiver<T>):Send) {
+    let mut cb = box OnRecv { rx: rx, f: f } as Box<Callback + Send>;
+    let control = unsafe {
+        let _g = LOCK.lock();
+        if !DISPATCHER.is_null() {
+            match (*DISPATCHER).send_opt(cb) {
+                Ok(()) => return,
+                // The dispatcher unpublishes itself before exiting, so it can
+                // only have gone away by failing
+                Err(c) => { cb = c; unpublish(); }
+            }
+        }
+        let (tx, rx) = channel();
+        let _ = tx.send_opt(cb);
+        DISPATCHER = mem::transmute(box tx);
+        rx
+    };
+
+    spawn("<comm dispatcher>", proc() dispatch(control));
+}
+
+fn spawn(name: &'static str, f: proc():Send) {
+    let task: Box<Task> = Local::take();
+    let mut opts = TaskOpts::new();
+    opts.name = Some(Slice(name));
+    task.spawn_sibling(opts, f);
+}
+
+// Must be called with the lock held
+unsafe fn unpublish() {
+    drop(mem::transmute::<_, Box<Sender<Box<Callback + Send>>>>(DISPATCHER));
+    DISPATCHER = 0 as *mut Sender<Box<Callback + Send>>;
+}
+
+fn dispatch(control: Receiver<Box<Callback + Send>>) {
+    // Callbacks by their token in the poller. Every callback in here is in the
+    // poller, which must therefore be dropped first.
+    let mut cbs: Vec<Option<Box<Callback + Send>>> = Vec::new();
+    let mut cap = INITIAL_CAPACITY;
+    let mut poller = Poller::new(cap);
+    let mut ctl = unsafe { add_control(&control, &mut poller) };
+    let mut live = 0u;
+
+    loop {
+        if live == 0 {
+            // The control channel is drained under the lock, which excludes
+            // any new registrations, before the dispatcher is unpublished.
+            unsafe {
+                let _g = LOCK.lock();
+                match control.try_recv() {
+                    Ok(cb) => { add(&mut cbs, &mut poller, cb); live += 1; }
+                    Err(..) => { unpublish(); return }
+                }
+            }
+            continue
+        }
+
+        let token = poller.wait();
+        if token == ctl {
+            loop {
+                match control.try_recv() {
+                    Ok(cb) => {
+                        if poller.len() == cap {
+                            cap *= 2;
+                            ctl = grow(&control, &mut cbs, &mut poller, cap);
+                        }
+                        add(&mut cbs, &mut poller, cb);
+                        live += 1;
+                    }
+                    Err(..) => break,
+                }
+            }
+            continue
+        }
+
+        poller.remove(token);
+        let cb = cbs.get_mut(token).take().unwrap();
+        match cb.fire() {
+            Some(cb) => add(&mut cbs, &mut poller, cb),
+            None => live -= 1,
+        }
+    }
+}
+
+fn add(cbs: &mut Vec<Option<Box<Callback + Send>>>, poller: &mut Poller<'static>,
+       cb: Box<Callback + Send>) {
+    let token = unsafe { cb.add(poller) };
+    while cbs.len() <= token { cbs.push(None) }
+    *cbs.get_mut(token) = Some(cb);
+}
+
+unsafe fn add_control(control: &Receiver<Box<Callback + Send>>,
+                      poller: &mut Poller<'static>) -> uint {
+    poller.add(mem::transmute::<_, &'static Receiver<Box<Callback + Send>>>(control))
+}
+
+// Replaces the poller with one which can hold `capacity` receivers, returning
+// the new token of the control channel.
+fn grow(control: &Receiver<Box<Callback + Send>>,
+        cbs: &mut Vec<Option<Box<Callback + Send>>>,
+        poller: &mut Poller<'static>, capacity: uint) -> uint {
+    let old = mem::replace(cbs, Vec::new());
+    // Dropping the old poller unregisters all of the receivers
+    drop(mem::replace(poller, Poller::new(capacity)));
+    let ctl = unsafe { add_control(control, poller) };
+    for cb in old.move_iter() {
+        match cb {
+            Some(cb) => add(cbs, poller, cb),
+            None => {}
+        }
+    }
+    ctl
+}
@@ -0,0 +1,185 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Channels whose messages expire
+//!
+//! An expiring channel attaches a deadline to every message that is sent on
+//! it. Messages whose deadline has passed by the time they are received are
+//! silently dropped by the receiver (and counted), which keeps stale work away
+//! from slow consumers.
+//!
+//! Deadlines are measured with the runtime's monotonic clock, in milliseconds.
+
+#![experimental]
+
+use core::prelude::*;
+
+use core::cell::Cell;
+use core::u64;
+use rustrt::time;
+
+use comm::{Sender, Receiver, TryRecvError, channel};
+
+/// The sending half of an expiring channel.
+pub struct ExpiringSender<T> {
+    tx: Sender<(u64, T)>,
+    ttl: u64,
+}
+
+/// The receiving half of an expiring channel.
+pub struct ExpiringReceiver<T> {
+    rx: Receiver<(u64, T)>,
+    expired: Cell<uint>,
+}
+
+/// Creates a new asynchronous channel whose messages expire `ttl` milliseconds
+/// after being sent, unless sent with an explicit time-to-live.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::expiring_channel;
+///
+/// let (tx, rx) = expiring_channel(1000);
+/// tx.send(1i);
+/// tx.send_with_ttl(2i, 0);
+/// assert_eq!(rx.recv(), 1);
+/// ```
+pub fn expiring_channel<T: Send>(ttl: u64)
+                                 -> (ExpiringSender<T>, ExpiringReceiver<T>) {
+    let (tx, rx) = channel();
+    (ExpiringSender { tx: tx, ttl: ttl },
+     ExpiringReceiver { rx: rx, expired: Cell::new(0) })
+}
+
+fn deadline(ttl: u64) -> u64 {
+    let now = time::precise_time_ms();
+    if ttl > u64::MAX - now {u64::MAX} else {now + ttl}
+}
+
+impl<T: Send> ExpiringSender<T> {
+    /// Sends a value which expires after this channel's default time-to-live.
+    /// This has the same failure semantics as `Sender::send`.
+    pub fn send(&self, t: T) {
+        self.tx.send((deadline(self.ttl), t))
+    }
+
+    /// Sends a value which expires after this channel's default time-to-live,
+    /// returning it back if the receiver has hung up.
+    pub fn send_opt(&self, t: T) -> Result<(), T> {
+        self.send_opt_with_ttl(t, self.ttl)
+    }
+
+    /// Sends a value which expires `ttl` milliseconds from now.
+    pub fn send_with_ttl(&self, t: T, ttl: u64) {
+        self.tx.send((deadline(ttl), t))
+    }
+
+    /// Sends a value which expires `ttl` milliseconds from now, returning it
+    /// back if the receiver has hung up.
+    pub fn send_opt_with_ttl(&self, t: T, ttl: u64) -> Result<(), T> {
+        match self.tx.send_opt((deadline(ttl), t)) {
+            Ok(()) => Ok(()),
+            Err((_, t)) => Err(t),
+        }
+    }
+}
+
+impl<T: Send> Clone for ExpiringSender<T> {
+    fn clone(&self) -> ExpiringSender<T> {
+        ExpiringSender { tx: self.tx.clone(), ttl: self.ttl }
+    }
+}
+
+impl<T: Send> ExpiringReceiver<T> {
+    /// Blocks waiting for an unexpired value, with the same failure semantics
+    /// as `Receiver::recv`.
+    pub fn recv(&self) -> T {
+        match self.recv_opt() {
+            Ok(t) => t,
+            Err(()) => fail!("receiving on a closed channel"),
+        }
+    }
+
+    /// Blocks waiting for an unexpired value, returning `Err` if the channel
+    /// has hung up.
+    pub fn recv_opt(&self) -> Result<T, ()> {
+        loop {
+            let (deadline, t) = try!(self.rx.recv_opt());
+            match self.check(deadline, t) {
+                Some(t) => return Ok(t),
+                None => {}
+            }
+        }
+    }
+
+    /// Attempts to return a pending unexpired value without blocking. Any
+    /// expired messages found along the way are discarded.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        loop {
+            let (deadline, t) = try!(self.rx.try_recv());
+            match self.check(deadline, t) {
+                Some(t) => return Ok(t),
+                None => {}
+            }
+        }
+    }
+
+    /// Returns the number of messages which have been discarded by this
+    /// receiver because they had expired.
+    pub fn expired(&self) -> uint { self.expired.get() }
+
+    fn check(&self, deadline: u64, t: T) -> Option<T> {
+        if time::precise_time_ms() > deadline {
+            self.expired.set(self.expired.get() + 1);
+            None
+        } else {
+            Some(t)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    test!(fn smoke() {
+        let (tx, rx) = expiring_channel(100000);
+        tx.send(1i);
+        tx.send_with_ttl(2, 100000);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(Empty));
+        assert_eq!(rx.expired(), 0);
+    })
+
+    test!(fn expired_are_dropped() {
+        use std::io::timer;
+
+        let (tx, rx) = expiring_channel(100000);
+        let tx2 = tx.clone();
+        tx.send_with_ttl(1i, 0);
+        tx2.send_with_ttl(2i, 0);
+        tx.send(3);
+        timer::sleep(10);
+        assert_eq!(rx.recv(), 3);
+        assert_eq!(rx.expired(), 2);
+    })
+
+    test!(fn disconnected() {
+        use std::io::timer;
+
+        let (tx, rx) = expiring_channel::<int>(0);
+        tx.send(1);
+        drop(tx);
+        timer::sleep(10);
+        assert_eq!(rx.try_recv(), Err(Disconnected));
+        assert_eq!(rx.recv_opt(), Err(()));
+        assert_eq!(rx.expired(), 1);
+    })
+}
@@ -0,0 +1,280 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Receivers as file descriptors
+//!
+//! An event loop which isn't part of the runtime, such as the main loop of a
+//! GUI toolkit, can only wait on the file descriptors (or, on Windows, the
+//! handles) it's given. `Receiver::as_fd` makes a descriptor which becomes
+//! readable when the receiver has something to receive, so that the receiver
+//! can be watched by such a loop.
+//!
+//! The receiver is put in a `Poller` of its own, which is armed with a foreign
+//! `BlockedTask`. When a sender flags the receiver, it wakes that task, which
+//! signals a pipe (or an event on Windows) instead of rescheduling anything.
+
+use core::prelude::*;
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use rustrt::task::{BlockedTask, Waker};
+
+use comm::{Receiver, Poller};
+
+/// A file descriptor which is readable whenever a receiver may have something
+/// to receive, as returned by `Receiver::as_fd`.
+///
+/// The descriptor stays readable until `rearm` is called, which should be done
+/// once everything has been received with `try_recv`. If the receiver still
+/// isn't empty at that point, the descriptor becomes readable again right away.
+///
+/// While this is alive, the receiver can't be added to a `Poller`.
+///
+/// # Example
+///
+/// ```
+/// let (tx, rx) = channel();
+/// let mut fd = rx.as_fd();
+/// // fd.fd() is handed to the event loop, which finds it readable...
+/// tx.send(1i);
+/// // ...and then calls back into Rust
+/// loop {
+///     match rx.try_recv() {
+///         Ok(n) => println!("received {}", n),
+///         Err(..) => break,
+///     }
+/// }
+/// fd.rearm();
+/// ```
+#[experimental]
+pub struct ReadyFd<'rx> {
+    poller: Poller<'rx>,
+    signal: Arc<imp::Signal>,
+}
+
+struct SignalWaker {
+    signal: Arc<imp::Signal>,
+}
+
+impl<'rx> ReadyFd<'rx> {
+    /// Creates the descriptor of `rx`.
+    pub fn new<T: Send>(rx: &'rx Receiver<T>) -> ReadyFd<'rx> {
+        let mut poller = Poller::new(1);
+        poller.add(rx);
+        let mut ret = ReadyFd { poller: poller, signal: Arc::new(imp::Signal::new()) };
+        ret.rearm();
+        ret
+    }
+
+    /// Returns the file descriptor on Unix, and the handle of an event on
+    /// Windows. It's owned by this `ReadyFd`, and closed when it's dropped.
+    pub fn fd(&self) -> int {
+        self.signal.raw()
+    }
+
+    /// Makes the descriptor unreadable until the receiver may have something to
+    /// receive again.
+    pub fn rearm(&mut self) {
+        self.signal.clear();
+        let waker = box SignalWaker { signal: self.signal.clone() };
+        match self.poller.arm(BlockedTask::foreign(waker)) {
+            Ok(()) => {}
+            Err(task) => { task.wake(); }
+        }
+    }
+}
+
+impl Waker for SignalWaker {
+    fn wake(self: Box<SignalWaker>) {
+        self.signal.notify();
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use core::prelude::*;
+
+    #[cfg(target_os = "linux")]
+    #[cfg(target_os = "android")]
+    static O_NONBLOCK: i32 = 0o4000;
+    #[cfg(target_os = "macos")]
+    #[cfg(target_os = "ios")]
+    #[cfg(target_os = "freebsd")]
+    #[cfg(target_os = "dragonfly")]
+    static O_NONBLOCK: i32 = 0x0004;
+    static F_SETFL: i32 = 4;
+
+    extern {
+        fn pipe(fds: *mut i32) -> i32;
+        fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+        fn read(fd: i32, buf: *mut u8, count: uint) -> int;
+        fn write(fd: i32, buf: *const u8, count: uint) -> int;
+        fn close(fd: i32) -> i32;
+    }
+
+    // A pipe which is readable once it's been notified. Both of its ends are
+    // non-blocking, so notifying a full pipe doesn't block, and clearing it
+    // stops once it's empty.
+    pub struct Signal {
+        read: i32,
+        write: i32,
+    }
+
+    impl Signal {
+        pub fn new() -> Signal {
+            let mut fds = [0, ..2];
+            unsafe {
+                if pipe(fds.as_mut_ptr()) != 0 {
+                    fail!("could not create a pipe for a receiver");
+                }
+                fcntl(fds[0], F_SETFL, O_NONBLOCK);
+                fcntl(fds[1], F_SETFL, O_NONBLOCK);
+            }
+            Signal { read: fds[0], write: fds[1] }
+        }
+
+        pub fn raw(&self) -> int { self.read as int }
+
+        pub fn notify(&self) {
+            let b = 0u8;
+            unsafe { write(self.write, &b, 1); }
+        }
+
+        pub fn clear(&self) {
+            let mut buf = [0u8, ..64];
+            unsafe {
+                while read(self.read, buf.as_mut_ptr(), buf.len()) > 0 {}
+            }
+        }
+    }
+
+    impl Drop for Signal {
+        fn drop(&mut self) {
+            unsafe {
+                close(self.read);
+                close(self.write);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use core::prelude::*;
+    use core::ptr;
+
+    type HANDLE = *mut u8;
+
+    extern "system" {
+        fn CreateEventW(attrs: *mut u8, manual_reset: i32, initial_state: i32,
+                        name: *const u16) -> HANDLE;
+        fn SetEvent(event: HANDLE) -> i32;
+        fn ResetEvent(event: HANDLE) -> i32;
+        fn CloseHandle(handle: HANDLE) -> i32;
+    }
+
+    // A manual-reset event, which stays signaled until it's cleared
+    pub struct Signal {
+        event: HANDLE,
+    }
+
+    impl Signal {
+        pub fn new() -> Signal {
+            let event = unsafe { CreateEventW(ptr::mut_null(), 1, 0, ptr::null()) };
+            if event.is_null() {
+                fail!("could not create an event for a receiver");
+            }
+            Signal { event: event }
+        }
+
+        pub fn raw(&self) -> int { self.event as int }
+
+        pub fn notify(&self) {
+            unsafe { SetEvent(self.event); }
+        }
+
+        pub fn clear(&self) {
+            unsafe { ResetEvent(self.event); }
+        }
+    }
+
+    impl Drop for Signal {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.event); }
+        }
+    }
+}
+
+#[cfg(test, unix)]
+mod test {
+    use std::prelude::*;
+
+    use super::super::*;
+
+    extern {
+        fn poll(fds: *mut PollFd, nfds: uint, timeout: i32) -> i32;
+    }
+
+    #[repr(C)]
+    pub struct PollFd {
+        fd: i32,
+        events: i16,
+        revents: i16,
+    }
+
+    static POLLIN: i16 = 1;
+
+    pub fn readable(fd: int, timeout: i32) -> bool {
+        let mut pfd = PollFd { fd: fd as i32, events: POLLIN, revents: 0 };
+        unsafe { poll(&mut pfd, 1, timeout) == 1 }
+    }
+
+    test!(fn smoke() {
+        let (tx, rx) = channel::<int>();
+        let mut fd = rx.as_fd();
+        assert!(!readable(fd.fd(), 0));
+        tx.send(1);
+        assert!(readable(fd.fd(), -1));
+        assert_eq!(rx.try_recv(), Ok(1));
+        fd.rearm();
+        assert!(!readable(fd.fd(), 0));
+    })
+
+    test!(fn sent_before() {
+        let (tx, rx) = channel::<int>();
+        tx.send(1);
+        tx.send(2);
+        let mut fd = rx.as_fd();
+        assert!(readable(fd.fd(), 0));
+        assert_eq!(rx.recv(), 1);
+        // Still not empty, so rearming signals again
+        fd.rearm();
+        assert!(readable(fd.fd(), 0));
+        assert_eq!(rx.recv(), 2);
+        fd.rearm();
+        assert!(!readable(fd.fd(), 0));
+    })
+
+    test!(fn from_other_task() {
+        let (tx, rx) = channel::<int>();
+        let fd = rx.as_fd();
+        spawn(proc() tx.send(1));
+        assert!(readable(fd.fd(), -1));
+        assert_eq!(rx.recv(), 1);
+    })
+
+    test!(fn hangup() {
+        let (tx, rx) = channel::<int>();
+        let fd = rx.as_fd();
+        drop(tx);
+        assert!(readable(fd.fd(), -1));
+        assert_eq!(rx.recv_opt(), Err(()));
+    })
+}
@@ -0,0 +1,276 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Oneshot channels in caller-provided storage
+//!
+//! Creating a channel allocates its packet on the heap. When a task hands a
+//! single value to another task and then waits for it, that allocation can
+//! dominate the cost of the exchange. A `OneshotSlot` instead holds the packet
+//! of a oneshot channel inline, so it can live on the stack of the receiving
+//! task (or anywhere else the caller owns), and it can be reused for one
+//! exchange after another without ever touching the heap.
+//!
+//! # Lifetimes
+//!
+//! Splitting a slot borrows it for as long as the receiver is alive, so the
+//! slot can't be moved or destroyed while the receiver exists. The sender has
+//! to be sent to another task, so it can't borrow the slot. Instead, the
+//! receiver's destructor blocks until the sender has been used or dropped. The
+//! sender's last access to the slot is a single atomic swap, after which the
+//! receiver (and then the slot) are free to go away.
+//!
+//! This also means that forgetting a receiver with `mem::forget` and then
+//! moving or destroying its slot leaves the sender with a dangling pointer.
+//!
+//! # Example
+//!
+//! ```
+//! use std::comm::OneshotSlot;
+//!
+//! let mut slot = OneshotSlot::new();
+//! for i in range(0i, 3) {
+//!     let (tx, rx) = slot.split();
+//!     spawn(proc() tx.send(i));
+//!     assert_eq!(rx.recv(), i);
+//! }
+//! ```
+
+#![experimental]
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::kinds::marker;
+use rustrt::task;
+use rustrt::task::BlockedTask;
+
+use atomics;
+use comm::{TryRecvError, Empty, Disconnected};
+use comm::oneshot;
+
+// States of a slot's `sender` word. Anything else is a receiver which is
+// blocked waiting for the sender to go away.
+static SENDER_ALIVE: uint = 0;
+static SENDER_GONE: uint = 1;
+
+/// Storage for a oneshot channel which is created without allocating.
+pub struct OneshotSlot<T> {
+    packet: UnsafeCell<Option<oneshot::Packet<T>>>,
+    sender: atomics::AtomicUint,
+}
+
+/// The sending half of a channel in a `OneshotSlot`.
+///
+/// The sender may be sent to another task, and it is consumed by sending.
+pub struct SlotSender<T> {
+    slot: *const OneshotSlot<T>,
+}
+
+/// The receiving half of a channel in a `OneshotSlot`.
+///
+/// # Blocking
+///
+/// Dropping the receiver blocks the current task until the sender has been
+/// used or dropped, as the sender may still be about to touch the slot. This
+/// includes the drop at the end of `recv` and `recv_opt`, although there the
+/// sender is already done sending, so the wait is short. The drop deadlocks if
+/// the sender can't go away without the dropping task: for instance if the
+/// dropping task owns the sender itself, or if the task which owns the sender
+/// is waiting for the dropping task.
+pub struct SlotReceiver<'a, T> {
+    slot: &'a OneshotSlot<T>,
+    marker: marker::NoShare,
+}
+
+impl<T: Send> OneshotSlot<T> {
+    /// Creates a new slot, with no channel in it yet.
+    pub fn new() -> OneshotSlot<T> {
+        OneshotSlot {
+            packet: UnsafeCell::new(None),
+            sender: atomics::AtomicUint::new(SENDER_GONE),
+        }
+    }
+
+    /// Creates a new oneshot channel in this slot.
+    ///
+    /// The slot remains borrowed until the receiver is dropped, after which it
+    /// may be split again. No allocation is performed. Note that dropping the
+    /// receiver blocks until the sender is gone, see `SlotReceiver`.
+    pub fn split<'a>(&'a mut self) -> (SlotSender<T>, SlotReceiver<'a, T>) {
+        // The previous receiver waited for its sender, so no one else can be
+        // looking at the slot.
+        assert_eq!(self.sender.load(atomics::SeqCst), SENDER_GONE);
+        unsafe { *self.packet.get() = Some(oneshot::Packet::new()); }
+        self.sender.store(SENDER_ALIVE, atomics::SeqCst);
+        (SlotSender { slot: &*self as *const OneshotSlot<T> },
+         SlotReceiver { slot: &*self, marker: marker::NoShare })
+    }
+
+    fn packet<'a>(&'a self) -> &'a mut oneshot::Packet<T> {
+        unsafe {
+            match *self.packet.get() {
+                Some(ref mut p) => p,
+                None => unreachable!(),
+            }
+        }
+    }
+}
+
+impl<T: Send> SlotSender<T> {
+    /// Sends a value to the receiver, failing if the receiver has hung up.
+    pub fn send(self, t: T) {
+        if self.send_opt(t).is_err() {
+            fail!("sending on a closed channel");
+        }
+    }
+
+    /// Sends a value to the receiver, returning it back if the receiver has
+    /// hung up.
+    pub fn send_opt(self, t: T) -> Result<(), T> {
+        match unsafe { (*self.slot).packet().send(t) } {
+            Ok(Some(task)) => { task.reawaken_later(); Ok(()) }
+            Ok(None) => Ok(()),
+            Err(t) => Err(t),
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for SlotSender<T> {
+    fn drop(&mut self) {
+        let slot = unsafe { &*self.slot };
+        slot.packet().drop_chan();
+
+        // This is the last time the slot is touched, the receiver may destroy
+        // it as soon as this swap is visible.
+        match slot.sender.swap(SENDER_GONE, atomics::SeqCst) {
+            SENDER_ALIVE => {}
+            n => unsafe {
+                let t = BlockedTask::cast_from_uint(n);
+                t.wake().map(|t| t.reawaken());
+            }
+        }
+    }
+}
+
+impl<'a, T: Send> SlotReceiver<'a, T> {
+    /// Blocks waiting for the value, failing if the sender hung up without
+    /// sending anything.
+    pub fn recv(self) -> T {
+        match self.recv_opt() {
+            Ok(t) => t,
+            Err(()) => fail!("receiving on a closed channel"),
+        }
+    }
+
+    /// Blocks waiting for the value, returning `Err` if the sender hung up
+    /// without sending anything.
+    pub fn recv_opt(self) -> Result<T, ()> {
+        match self.slot.packet().recv() {
+            Ok(t) => Ok(t),
+            Err(oneshot::Disconnected) => Err(()),
+            Err(oneshot::Empty) | Err(oneshot::Upgraded(..)) => unreachable!(),
+        }
+    }
+
+    /// Attempts to receive the value without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.slot.packet().try_recv() {
+            Ok(t) => Ok(t),
+            Err(oneshot::Empty) => Err(Empty),
+            Err(oneshot::Disconnected) => Err(Disconnected),
+            Err(oneshot::Upgraded(..)) => unreachable!(),
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'a, T: Send> Drop for SlotReceiver<'a, T> {
+    fn drop(&mut self) {
+        self.slot.packet().drop_port();
+
+        // The slot can't go away until the sender is done with it, so this
+        // blocks (see the docs of `SlotReceiver`)
+        if self.slot.sender.load(atomics::SeqCst) == SENDER_GONE { return }
+        task::deschedule_current(1, |task| {
+            let n = unsafe { task.cast_to_uint() };
+            match self.slot.sender.compare_and_swap(SENDER_ALIVE, n,
+                                                    atomics::SeqCst) {
+                SENDER_ALIVE => Ok(()),
+                _ => unsafe { Err(BlockedTask::cast_from_uint(n)) }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports)]
+mod test {
+    use std::prelude::*;
+
+    use super::super::*;
+    use comm::test::stress_factor;
+    use comm::OneshotSlot;
+
+    test!(fn smoke() {
+        let mut slot = OneshotSlot::new();
+        let (tx, rx) = slot.split();
+        spawn(proc() { tx.send(box 1i) });
+        assert_eq!(rx.recv(), box 1i);
+    })
+
+    test!(fn try_recv() {
+        let mut slot = OneshotSlot::new();
+        let (tx, rx) = slot.split();
+        assert_eq!(rx.try_recv(), Err(Empty));
+        tx.send(1i);
+        assert_eq!(rx.try_recv(), Ok(1));
+    })
+
+    test!(fn sender_gone() {
+        let mut slot = OneshotSlot::<int>::new();
+        let (tx, rx) = slot.split();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(Disconnected));
+        assert!(rx.recv_opt().is_err());
+    })
+
+    test!(fn receiver_waits_for_sender() {
+        let mut slot = OneshotSlot::<Box<int>>::new();
+        let (tx, rx) = slot.split();
+        let (stx, srx) = channel();
+        spawn(proc() {
+            srx.recv();
+            drop(tx);
+        });
+        stx.send(());
+        drop(rx);
+        // the slot must be free to split again
+        let (tx, rx) = slot.split();
+        tx.send(box 2);
+        assert_eq!(rx.recv(), box 2);
+    })
+
+    test!(fn reuse_stress() {
+        let mut slot = OneshotSlot::new();
+        for i in range(0, stress_factor() * 1000) {
+            let (tx, rx) = slot.split();
+            spawn(proc() { tx.send(box i) });
+            assert_eq!(rx.recv(), box i);
+        }
+        let mut slot = OneshotSlot::new();
+        for i in range(0, stress_factor() * 1000) {
+            let (tx, rx) = slot.split();
+            spawn(proc() { tx.send(i as u8) });
+            assert_eq!(rx.recv(), i as u8);
+        }
+    })
+}
@@ -0,0 +1,202 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Histograms of the time messages spend in a channel
+//!
+//! A channel created by `channel_with_latency` or `sync_channel_with_latency`
+//! stamps every message with the time it's sent at, in the queue node next to
+//! the message (these channels have queues of their own, with room for the
+//! stamps), and the receiver records how long ago that was as it takes the
+//! message off the queue. Timing a channel from the outside would add the
+//! cost of the timing to what's being timed, and couldn't tell the time a
+//! message spent queued from the time the receiver took to get to it.
+//!
+//! The histogram buckets latencies like HDR histograms do: the values below
+//! 16ns each have a bucket, and every power of two above that is split into
+//! 16 buckets, so a latency is known to within 1/16th (about 6%) of its
+//! value, at the cost of a thousand counters per channel.
+
+use core::prelude::*;
+
+use collections::{Vec, MutableSeq};
+// Like the other counters, these are left out of the model checker's reach
+use core::atomics;
+use core::fmt;
+use core::iter;
+use core::slice;
+use core::u64;
+use rustrt::time;
+
+// Each power of two is split into `1 << SUB_BITS` buckets
+static SUB_BITS: uint = 4;
+static SUB_BUCKETS: uint = 1 << SUB_BITS;
+static BUCKETS: uint = (64 - SUB_BITS + 1) * SUB_BUCKETS;
+
+/// The counters of a channel's latencies, updated by its receiver.
+pub struct Histogram {
+    counts: Vec<atomics::AtomicUint>,
+}
+
+/// A snapshot of the latencies of a channel, returned by `Sender::latency`,
+/// `SyncSender::latency` and `Receiver::latency`. The latencies are in
+/// nanoseconds.
+#[deriving(PartialEq, Clone)]
+#[experimental]
+pub struct Latency {
+    counts: Vec<uint>,
+}
+
+/// An iterator over the buckets of a `Latency` which have latencies in them,
+/// as `(lowest, highest, count)`: the latencies from `lowest` to `highest`
+/// (inclusive) were seen `count` times.
+#[experimental]
+pub struct Buckets<'a> {
+    iter: iter::Enumerate<slice::Items<'a, uint>>,
+}
+
+/// Returns the time to stamp a message with.
+pub fn now() -> u64 {
+    // 0 is what messages are stamped with when there's no histogram
+    match time::precise_time_ns() { 0 => 1, t => t }
+}
+
+// The bucket of `value`
+fn index(value: u64) -> uint {
+    if value < SUB_BUCKETS as u64 { return value as uint }
+    let top = 63 - value.leading_zeros() as uint;
+    let sub = (value >> (top - SUB_BITS)) as uint & (SUB_BUCKETS - 1);
+    (top - SUB_BITS + 1) * SUB_BUCKETS + sub
+}
+
+// The lowest value in bucket `i`
+fn lowest(i: uint) -> u64 {
+    if i < SUB_BUCKETS { return i as u64 }
+    let (group, sub) = (i / SUB_BUCKETS, i % SUB_BUCKETS);
+    ((SUB_BUCKETS + sub) as u64) << (group - 1)
+}
+
+// The highest value in bucket `i`
+fn highest(i: uint) -> u64 {
+    if i + 1 == BUCKETS { u64::MAX } else { lowest(i + 1) - 1 }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            counts: Vec::from_fn(BUCKETS, |_| atomics::AtomicUint::new(0)),
+        }
+    }
+
+    // Records a message stamped with `sent`, which has just been received
+    pub fn record(&self, sent: u64) {
+        let now = time::precise_time_ns();
+        let latency = if now > sent { now - sent } else { 0 };
+        self.counts.get(index(latency)).fetch_add(1, atomics::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Latency {
+        Latency {
+            counts: self.counts.iter().map(|c| c.load(atomics::Relaxed)).collect(),
+        }
+    }
+}
+
+impl Latency {
+    /// The number of messages whose latency was recorded.
+    pub fn count(&self) -> uint {
+        self.counts.iter().fold(0, |a, &b| a + b)
+    }
+
+    /// The lowest latency seen, or 0 if none was.
+    pub fn min(&self) -> u64 {
+        self.counts.iter().position(|&c| c > 0).map(lowest).unwrap_or(0)
+    }
+
+    /// The highest latency seen (to within the precision of the buckets), or
+    /// 0 if none was.
+    pub fn max(&self) -> u64 {
+        self.counts.iter().rposition(|&c| c > 0).map(highest).unwrap_or(0)
+    }
+
+    /// The latency which `percentile` percent of the messages didn't exceed
+    /// (to within the precision of the buckets), or 0 if none were seen. For
+    /// instance `percentile(99.0)` is the latency of the slowest of the
+    /// fastest 99% of the messages.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let count = self.count();
+        if count == 0 { return 0 }
+        let rank = (percentile / 100.0 * count as f64).ceil() as uint;
+        let rank = if rank < 1 { 1 } else if rank > count { count } else { rank };
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank { return highest(i) }
+        }
+        unreachable!()
+    }
+
+    /// Returns an iterator over the buckets which have latencies in them, from
+    /// the lowest latency to the highest.
+    pub fn buckets<'a>(&'a self) -> Buckets<'a> {
+        Buckets { iter: self.counts.iter().enumerate() }
+    }
+}
+
+impl<'a> Iterator<(u64, u64, uint)> for Buckets<'a> {
+    fn next(&mut self) -> Option<(u64, u64, uint)> {
+        for (i, &c) in self.iter.by_ref() {
+            if c > 0 { return Some((lowest(i), highest(i), c)) }
+        }
+        None
+    }
+}
+
+impl fmt::Show for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} messages, min {}ns, median {}ns, 99% {}ns, max {}ns",
+               self.count(), self.min(), self.percentile(50.0),
+               self.percentile(99.0), self.max())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+    use std::u64;
+
+    use super::{Latency, index, lowest, highest, BUCKETS};
+
+    #[test]
+    fn buckets() {
+        for i in range(0, BUCKETS) {
+            assert_eq!(index(lowest(i)), i);
+            assert_eq!(index(highest(i)), i);
+        }
+        assert_eq!(index(u64::MAX), BUCKETS - 1);
+        // Each bucket is within a sixteenth of its values
+        for &v in [17u64, 100, 1000, 123456, 1 << 40].iter() {
+            let i = index(v);
+            assert!(highest(i) - lowest(i) <= v / 16);
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut counts = Vec::from_elem(BUCKETS, 0u);
+        for v in range(1u64, 101) { *counts.get_mut(index(v)) += 1; }
+        let latency = Latency { counts: counts };
+        assert_eq!(latency.count(), 100);
+        assert_eq!(latency.min(), 1);
+        assert_eq!(latency.percentile(10.0), 10);
+        assert_eq!(latency.percentile(50.0), 51);
+        assert_eq!(latency.max(), 103);
+        assert_eq!(latency.buckets().fold(0, |n, (_, _, c)| n + c), 100);
+    }
+}
@@ -56,19 +56,31 @@
 //!
 //! ## Runtime Requirements
 //!
-//! The channel types defined in this module generally have very few runtime
-//! requirements in order to operate. The major requirement they have is for a
-//! local rust `Task` to be available if any *blocking* operation is performed.
+//! The channel types defined in this module have very few runtime requirements
+//! in order to operate. They can be used from threads which aren't running a
+//! Rust task, such as threads created by C code or by `rt::thread::Thread`: a
+//! blocking operation on such a thread puts the whole thread to sleep on a
+//! condition variable until it can go on.
 //!
-//! If a local `Task` is not available (for example an FFI callback), then the
-//! `send` operation is safe on a `Sender` (as well as a `send_opt`) as well as
-//! the `try_send` method on a `SyncSender`, but no other operations are
-//! guaranteed to be safe.
+//! Failing requires a local `Task`, though, so such threads should stick to
+//! the operations which don't fail, like `send_opt` and `recv_opt`.
 //!
 //! Additionally, channels can interoperate between runtimes. If one task in a
 //! program is running on libnative and another is running on libgreen, they can
 //! still communicate with one another using channels.
 //!
+//! ## Scheduling
+//!
+//! Sending a message may wake up a task which is blocked waiting for it. A
+//! plain `send` never switches to the woken task: it is scheduled to run, but
+//! it only runs once the scheduler gets to it. This favors throughput, as a
+//! producer can fill a channel without a context switch per message.
+//! `send_yield` instead lets the scheduler switch to the woken task
+//! immediately, which favors the latency of each message. Either way, a task
+//! which sends in a loop occasionally yields so that it doesn't starve the
+//! others. With native tasks the OS does the scheduling, so the two are the
+//! same.
+//!
 //! # Example
 //!
 //! Simple usage:
@@ -198,6 +210,23 @@
 //            can be, but the previous two types mentioned are much faster for
 //            their use-cases.
 //
+// ## Upgrades
+//
+// A channel only ever moves "forward" through the flavors above, and it skips
+// any flavors it doesn't need: cloning a oneshot sender goes straight to a
+// shared channel, and cloning a shared sender merely bumps the number of
+// senders on the shared packet. The upgrade chain a receiver can observe is
+// therefore at most oneshot -> stream -> shared, regardless of how many times
+// the sender has been cloned.
+//
+// An upgrade is communicated in-band (the `GoUp` message of a stream, or the
+// upgrade state of a oneshot) so that the data sent before the upgrade is
+// still received first. When the receiver reaches the upgrade, it swaps its
+// flavor with the upgraded one in O(1) and drops its reference to the old
+// packet. The sender dropped its own reference when it upgraded, so the old
+// packet (along with a stream's node cache) is freed right away rather than
+// lingering for the lifetime of the channel.
+//
 // ## Concurrent queues
 //
 // The basic idea of Rust's Sender/Receiver types is that send() never blocks, but
@@ -282,6 +311,51 @@
 // The implication of this is that if a sender sees a -1 count, then there's
 // guaranteed to be a waiter waiting!
 //
+// In stream channels, disconnection is recorded in the lowest bit of the same
+// word, and the count lives in the remaining bits as a wrapping unsigned
+// integer. Increments and decrements never disturb the flag, so the count stays
+// meaningful no matter how many messages have gone through the channel, and
+// there is no window in which a disconnection has to be re-flagged after a
+// racing update clobbered it.
+//
+// ### Memory orderings
+//
+// All of the synchronization between the two halves of a stream or shared
+// channel goes through read-modify-write operations on `cnt`. These are
+// totally ordered (they're all on the same location), so they only need to be
+// `AcqRel`, not `SeqCst`:
+//
+// * A sender pushes onto the queue and then increments `cnt`, and a receiver
+//   which observes the increment (by not blocking, or by waking up) is
+//   guaranteed to see the data that was pushed.
+// * A receiver stores its task into `to_wake` before decrementing `cnt`, so a
+//   sender which observes the -1 is guaranteed to see the task.
+// * Disconnection is flagged by setting DISCONNECTED in `cnt` (swapping it in,
+//   for shared channels), which will be observed by the next operation on
+//   `cnt` from the other half.
+//
+// The `port_dropped` flag is stored with `Release` when a receiver is dropped
+// and loaded with `Acquire` by senders, so a sender which sees the flag also
+// sees everything the receiver did before dropping. Code paths which are cold
+// (assertions, channel creation, destructors) keep `SeqCst` for simplicity.
+//
+// ### Tasks on the same scheduler
+//
+// It is tempting to drop down to non-atomic queue operations when both halves
+// of a channel are used by tasks on the same scheduler thread, but there's no
+// point at which that can be known to be true. A task which is sitting in a
+// scheduler's run queue can be stolen by another scheduler at any moment, so
+// a sender can't rule out its receiver running concurrently on another thread
+// without synchronizing with the work stealing, which costs as much as the
+// atomics which it would save. There is ultimately no fast path of this kind.
+//
+// What is true is that the atomics are cheap in this case: they're
+// uncontended, and the cache lines they touch are already local to the
+// thread. The expensive part of a same-scheduler handoff is the wakeup, and
+// that does stay local. Waking up a task from the same scheduler pool puts it
+// on the local run queue (`send`) or switches to it directly (`send_yield`),
+// rather than going through the scheduler's remote message queue.
+//
 // ## Native Implementation
 //
 // A major goal of these channels is to work seamlessly on and off the runtime.
@@ -322,14 +396,31 @@ use core::prelude::*;
 use alloc::arc::Arc;
 use alloc::boxed::Box;
 use core::cell::Cell;
+use core::fmt;
 use core::kinds::marker;
 use core::mem;
+use core::ptr;
 use core::cell::UnsafeCell;
 use rustrt::local::Local;
 use rustrt::task::{Task, BlockedTask};
 
+use backoff::{Backoff, SpinYield};
+
 pub use comm::select::{Select, Handle};
+pub use comm::poll::Poller;
 pub use comm::duplex::{DuplexStream, duplex};
+pub use comm::backend::{MessageQueue, QueueBuilder, SpscBuilder};
+pub use comm::expiring::{ExpiringSender, ExpiringReceiver, expiring_channel};
+pub use comm::inplace::{OneshotSlot, SlotSender, SlotReceiver};
+pub use comm::fd::ReadyFd;
+pub use comm::pipeline::{Pipeline, Stage, Control, Link, Unbounded, Bounded};
+pub use comm::pipeline::pipeline;
+pub use comm::stats::ChannelStats;
+pub use comm::latency::{Latency, Buckets};
+pub use comm::discard::{Discarded, set_discard_hook};
+pub use comm::trace::{Tracer, Event, EventKind, set_tracer};
+pub use comm::trace::{SendEvent, RecvEvent, BlockEvent, WakeEvent, UpgradeEvent};
+pub use comm::trace::{SenderGoneEvent, ReceiverGoneEvent};
 
 macro_rules! test (
     { fn $name:ident() $b:block $(#[$a:meta])*} => (
@@ -357,23 +448,53 @@ macro_rules! test (
     )
 )
 
+mod backend;
+pub mod debug;
+mod discard;
+mod dispatch;
 mod duplex;
+mod expiring;
+mod fd;
+mod inplace;
+mod latency;
 mod oneshot;
+mod pipeline;
+mod poll;
 mod select;
 mod shared;
+mod stats;
 mod stream;
 mod sync;
+mod trace;
+mod watermark;
 
 // Use a power of 2 to allow LLVM to optimize to something that's not a
 // division, this is hit pretty regularly.
 static RESCHED_FREQ: int = 256;
 
+// Wakes up a task which was blocked waiting for a send. If `resched` is set,
+// the woken task may be switched to immediately (see `Sender::send_yield`).
+fn handoff(task: BlockedTask, resched: bool) {
+    if resched {
+        task.reawaken();
+    } else {
+        task.reawaken_later();
+    }
+}
+
+// The contention strategy of shared channels which weren't given one
+fn default_backoff() -> Box<Backoff + Send + Share> {
+    box SpinYield as Box<Backoff + Send + Share>
+}
+
 /// The receiving-half of Rust's channel type. This half can only be owned by
 /// one task
 #[unstable]
 pub struct Receiver<T> {
     inner: UnsafeCell<Flavor<T>>,
     receives: Cell<uint>,
+    watermark: Option<Arc<watermark::Watermark>>,
+    tap: Option<Box<Tap<T> + Send>>,
     // can't share in an arc
     marker: marker::NoShare,
 }
@@ -386,16 +507,41 @@ pub struct Messages<'a, T> {
     rx: &'a Receiver<T>
 }
 
+/// The internal state of a channel, as returned by `Receiver::debug_state`,
+/// which is meant to be printed with `{}`.
+#[experimental]
+pub struct DebugState<'a, T> {
+    rx: &'a Receiver<T>
+}
+
 /// The sending-half of Rust's asynchronous channel type. This half can only be
 /// owned by one task, but it can be cloned to send to other tasks.
 #[unstable]
 pub struct Sender<T> {
     inner: UnsafeCell<Flavor<T>>,
     sends: Cell<uint>,
+    watermark: Option<Arc<watermark::Watermark>>,
     // can't share in an arc
     marker: marker::NoShare,
 }
 
+/// A message slot reserved on an asynchronous channel, created by
+/// `Sender::reserve`.
+///
+/// The slot lives inside of a node of the channel's internal queue, so a value
+/// written into it does not need to be moved again to be sent.
+#[experimental]
+pub struct SendSlot<'a, T> {
+    tx: &'a mut Sender<T>,
+    slot: Option<SlotFlavor<T>>,
+    filled: bool,
+}
+
+enum SlotFlavor<T> {
+    StreamSlot(stream::Slot<T>),
+    SharedSlot(shared::Slot<T>),
+}
+
 /// The sending-half of Rust's synchronous channel type. This half can only be
 /// owned by one task, but it can be cloned to send to other tasks.
 #[unstable = "this type may be renamed, but it will always exist"]
@@ -442,6 +588,18 @@ enum Flavor<T> {
     Sync(Arc<UnsafeCell<sync::Packet<T>>>),
 }
 
+impl<T: Send> Flavor<T> {
+    // The counters shared by all of the packets of this channel
+    unsafe fn stats<'a>(&'a self) -> &'a stats::Stats {
+        match *self {
+            Oneshot(ref p) => &(*p.get()).stats,
+            Stream(ref p) => &(*p.get()).stats,
+            Shared(ref p) => &(*p.get()).stats,
+            Sync(ref p) => &(*p.get()).stats,
+        }
+    }
+}
+
 #[doc(hidden)]
 trait UnsafeFlavor<T> {
     fn inner_unsafe<'a>(&'a self) -> &'a UnsafeCell<Flavor<T>>;
@@ -486,7 +644,9 @@ impl<T> UnsafeFlavor<T> for Receiver<T> {
 /// ```
 #[unstable]
 pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
-    let a = Arc::new(UnsafeCell::new(oneshot::Packet::new()));
+    let mut p = oneshot::Packet::new();
+    p.stats = debug::track::<T>(stats::Stats::off());
+    let a = Arc::new(UnsafeCell::new(p));
     (Sender::new(Oneshot(a.clone())), Receiver::new(Oneshot(a)))
 }
 
@@ -524,10 +684,270 @@ pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
 #[unstable = "this function may be renamed to more accurately reflect the type \
               of channel that is is creating"]
 pub fn sync_channel<T: Send>(bound: uint) -> (SyncSender<T>, Receiver<T>) {
-    let a = Arc::new(UnsafeCell::new(sync::Packet::new(bound)));
+    let mut p = sync::Packet::new(bound);
+    p.stats = debug::track::<T>(stats::Stats::off());
+    let a = Arc::new(UnsafeCell::new(p));
+    (SyncSender::new(a.clone()), Receiver::new(Sync(a)))
+}
+
+/// Creates a new asynchronous channel which is set up for multiple senders
+/// from the start.
+///
+/// A channel created with `channel()` starts out optimized for a single
+/// message, and is upgraded to a stream and then to a shared channel as it is
+/// used and cloned. Each upgrade costs an allocation and an extra hop for the
+/// receiver. If the `Sender` is known to be cloned across many producers, this
+/// function can be used to allocate the shared channel directly. The
+/// semantics of the returned channel are otherwise identical to `channel()`.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::shared_channel;
+///
+/// let (tx, rx) = shared_channel();
+/// for i in range(0i, 10) {
+///     let tx = tx.clone();
+///     spawn(proc() { tx.send(i); });
+/// }
+/// drop(tx);
+/// assert_eq!(rx.iter().fold(0, |a, b| a + b), 45);
+/// ```
+#[experimental]
+pub fn shared_channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    shared_channel_with_queue(LinkedQueue)
+}
+
+/// The queue used by a channel with multiple senders.
+#[experimental]
+pub enum SharedQueue {
+    /// A queue which allocates a node for every message. Messages can be sent
+    /// with `Sender::reserve` without any copying. This is the queue used by
+    /// `shared_channel` and when a `Sender` is cloned.
+    LinkedQueue,
+    /// A queue which allocates space for many messages at a time, greatly
+    /// reducing the number of allocations on busy channels.
+    ///
+    /// Sends aren't lock-free on this queue: the sender which fills a block
+    /// allocates the next one, and any other sender which gets there in the
+    /// meantime waits for it to be linked in, so a sender which is preempted
+    /// then holds up all of the others. Every channel also allocates room for
+    /// a whole block of messages up front, even if it's never sent that many.
+    BlockQueue,
+}
+
+/// Creates a new asynchronous channel set up for multiple senders, like
+/// `shared_channel`, which uses a specific kind of queue.
+#[experimental]
+pub fn shared_channel_with_queue<T: Send>(queue: SharedQueue)
+                                          -> (Sender<T>, Receiver<T>) {
+    shared_channel_with_backoff(queue, SpinYield)
+}
+
+/// Creates a new asynchronous channel set up for multiple senders, like
+/// `shared_channel_with_queue`, which uses a specific strategy to wait out
+/// contention among its senders and receiver.
+///
+/// The strategy only applies to this channel, other channels (including those
+/// which are upgraded to shared channels when their sender is cloned) use
+/// `SpinYield`.
+#[experimental]
+pub fn shared_channel_with_backoff<T: Send, B: Backoff + Send + Share>(
+    queue: SharedQueue, backoff: B) -> (Sender<T>, Receiver<T>) {
+    let backoff = box backoff as Box<Backoff + Send + Share>;
+    let mut p = shared::Packet::new(1, queue, backoff);
+    p.stats = debug::track::<T>(stats::Stats::off());
+    let a = Arc::new(UnsafeCell::new(p));
+    unsafe {
+        (*a.get()).postinit_lock();
+        (*a.get()).inherit_blocker(None);
+    }
+    (Sender::new(Shared(a.clone())), Receiver::new(Shared(a)))
+}
+
+/// Creates a new asynchronous channel whose senders are soft-bounded.
+///
+/// This channel behaves like one created with `channel()`, except that once
+/// `high` messages are queued up, every send will block (after enqueueing its
+/// message) until the receiver has drained the channel down to `low` pending
+/// messages. This provides backpressure to fast producers without imposing a
+/// hard capacity on the channel, and receivers are used exactly as before.
+///
+/// # Failure
+///
+/// This function will fail if `low` is not strictly less than `high`.
+///
+/// # Example
+///
+/// ```
+/// let (tx, rx) = channel_with_watermarks(1024, 128);
+///
+/// spawn(proc() {
+///     // this will periodically block while the receiver catches up
+///     for i in range(0i, 100000) { tx.send(i); }
+/// });
+///
+/// for _ in range(0i, 100000) { rx.recv(); }
+/// ```
+#[experimental]
+pub fn channel_with_watermarks<T: Send>(high: uint, low: uint)
+                                        -> (Sender<T>, Receiver<T>) {
+    let wm = Arc::new(watermark::Watermark::new(high, low));
+    let (mut tx, mut rx) = channel();
+    tx.watermark = Some(wm.clone());
+    rx.watermark = Some(wm);
+    (tx, rx)
+}
+
+/// Creates a new asynchronous channel which counts what happens on it.
+///
+/// The channel is otherwise the same as one created by `channel()`. Its
+/// counters are shared by all of its halves, and a snapshot of them can be
+/// taken with `Sender::stats` or `Receiver::stats` to see how the channel is
+/// being used. Counting costs an atomic increment for each message and
+/// wakeup, which is why the other channels don't.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::channel_with_stats;
+///
+/// let (tx, rx) = channel_with_stats();
+/// tx.send(1i);
+/// tx.send(2i);
+/// assert!(rx.try_recv().is_ok());
+/// let stats = rx.stats().unwrap();
+/// assert_eq!(stats.sends, 2);
+/// assert_eq!(stats.recvs, 1);
+/// ```
+#[experimental]
+pub fn channel_with_stats<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let mut p = oneshot::Packet::new();
+    p.stats = debug::track::<T>(stats::Stats::on());
+    let a = Arc::new(UnsafeCell::new(p));
+    (Sender::new(Oneshot(a.clone())), Receiver::new(Oneshot(a)))
+}
+
+/// Creates a new synchronous, bounded channel which counts what happens on
+/// it, like `channel_with_stats`.
+///
+/// Blocking and wakeups are only counted for the receiver, not for senders
+/// waiting for room in the buffer.
+#[experimental]
+pub fn sync_channel_with_stats<T: Send>(bound: uint) -> (SyncSender<T>, Receiver<T>) {
+    let mut p = sync::Packet::new(bound);
+    p.stats = debug::track::<T>(stats::Stats::on());
+    let a = Arc::new(UnsafeCell::new(p));
+    (SyncSender::new(a.clone()), Receiver::new(Sync(a)))
+}
+
+/// Creates a new asynchronous channel which measures how long its messages
+/// take to be received, in a histogram.
+///
+/// The channel is otherwise the same as one created by `channel_with_stats`.
+/// Every message is stamped with the time it's sent at, which is kept in the
+/// queue along with it, and its latency from then until it's taken off the
+/// queue by the receiver is counted in the histogram. A snapshot of the
+/// histogram can be taken with `Sender::latency` or `Receiver::latency`.
+/// Measuring costs reading the clock twice for each message, and the 8 bytes
+/// of the stamp in each queued message. Only these channels have queues with
+/// room for the stamps, the queues of other channels are unchanged.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::channel_with_latency;
+///
+/// let (tx, rx) = channel_with_latency();
+/// for i in range(0i, 100) { tx.send(i); }
+/// for _ in range(0i, 100) { rx.recv(); }
+/// let latency = rx.latency().unwrap();
+/// assert_eq!(latency.count(), 100);
+/// println!("99% of the messages took at most {}ns", latency.percentile(99.0));
+/// ```
+#[experimental]
+pub fn channel_with_latency<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let mut p = oneshot::Packet::new();
+    p.stats = debug::track::<T>(stats::Stats::with_latency());
+    let a = Arc::new(UnsafeCell::new(p));
+    (Sender::new(Oneshot(a.clone())), Receiver::new(Oneshot(a)))
+}
+
+/// Creates a new synchronous, bounded channel which measures how long its
+/// messages take to be received, like `channel_with_latency`.
+///
+/// The time a sender spends waiting for room in the buffer isn't counted, as
+/// a message is only stamped once it's in the buffer.
+#[experimental]
+pub fn sync_channel_with_latency<T: Send>(bound: uint) -> (SyncSender<T>, Receiver<T>) {
+    let mut p = sync::Packet::new(bound);
+    p.stats = debug::track::<T>(stats::Stats::with_latency());
+    p.stamp_messages();
+    let a = Arc::new(UnsafeCell::new(p));
     (SyncSender::new(a.clone()), Receiver::new(Sync(a)))
 }
 
+/// Creates a new asynchronous channel which stores its messages in a queue
+/// created by `builder`.
+///
+/// This is otherwise the same as `channel()`, but the queue backing the
+/// channel can be tuned for specialized workloads. See the `MessageQueue`
+/// trait for the requirements on the queue. Note that the custom queue is
+/// only used while the channel has one sender; if the `Sender` is cloned the
+/// channel will switch to its usual shared queue.
+#[experimental]
+pub fn channel_with_queue<T: Send, B: QueueBuilder>(builder: B)
+                                                    -> (Sender<T>, Receiver<T>) {
+    let q = builder.build();
+    let mut p = stream::Packet::with_custom_queue(q);
+    p.stats = debug::track::<T>(stats::Stats::off());
+    let a = Arc::new(UnsafeCell::new(p));
+    (Sender::new(Stream(a.clone())), Receiver::new(Stream(a)))
+}
+
+/// The policy for caching queue nodes in a channel, see `channel_with_cache`.
+#[experimental]
+pub enum NodeCache {
+    /// Cache at most this many nodes, or any number of nodes if 0.
+    FixedCache(uint),
+    /// Resize the cache to fit the number of messages typically in flight,
+    /// with lower and upper limits on its size (neither may be 0).
+    AdaptiveCache(uint, uint),
+}
+
+/// Creates a new asynchronous channel with a specific node cache policy.
+///
+/// The queue behind an asynchronous channel allocates a node per message, and
+/// recycles nodes through a cache to avoid most of those allocations. The
+/// cache of a `channel()` holds up to 128 nodes, which can be too small for a
+/// high-throughput channel, or a waste of memory for thousands of channels
+/// which are mostly idle. This function allows choosing the cache size, or a
+/// cache which grows and shrinks based on the observed depth of the channel.
+///
+/// As with `channel_with_queue`, the policy only applies while the channel
+/// has a single sender.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::{channel_with_cache, AdaptiveCache};
+///
+/// let (tx, rx) = channel_with_cache(AdaptiveCache(1, 1024));
+/// tx.send(1i);
+/// assert_eq!(rx.recv(), 1);
+/// ```
+///
+/// # Failure
+///
+/// Fails if the limits of an `AdaptiveCache` are 0 or out of order.
+#[experimental]
+pub fn channel_with_cache<T: Send>(cache: NodeCache) -> (Sender<T>, Receiver<T>) {
+    let mut p = stream::Packet::with_cache(cache);
+    p.stats = debug::track::<T>(stats::Stats::off());
+    let a = Arc::new(UnsafeCell::new(p));
+    (Sender::new(Stream(a.clone())), Receiver::new(Stream(a)))
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Sender
 ////////////////////////////////////////////////////////////////////////////////
@@ -537,6 +957,7 @@ impl<T: Send> Sender<T> {
         Sender {
             inner: UnsafeCell::new(inner),
             sends: Cell::new(0),
+            watermark: None,
             marker: marker::NoShare,
         }
     }
@@ -560,6 +981,11 @@ impl<T: Send> Sender<T> {
     ///
     /// The purpose of this functionality is to propagate failure among tasks.
     /// If failure is not desired, then consider using the `send_opt` method
+    ///
+    /// If a task is blocked waiting for this message it is woken up, but the
+    /// sending task keeps running. See `send_yield` for the alternative. Every
+    /// so often this yields anyway, so that a task which sends in a loop
+    /// doesn't starve the other tasks on its scheduler.
     #[experimental = "this function is being considered candidate for removal \
                       to adhere to the general guidelines of rust"]
     pub fn send(&self, t: T) {
@@ -568,6 +994,31 @@ impl<T: Send> Sender<T> {
         }
     }
 
+    /// Sends a value along this channel, giving the receiving task a chance to
+    /// run right away.
+    ///
+    /// If a task is blocked waiting for this message, the scheduler may switch
+    /// to it immediately instead of letting the sending task carry on. This
+    /// lowers the latency of each message at the cost of context switches,
+    /// while `send` favors throughput.
+    ///
+    /// For green tasks this is a direct handoff: a receiver from the same
+    /// scheduler pool runs on the sender's scheduler right away, with the
+    /// sender queued up locally behind it, so a ping-pong between two tasks
+    /// doesn't bounce through other schedulers. A receiver from another pool
+    /// (or a native one) is woken up remotely as usual.
+    ///
+    /// # Failure
+    ///
+    /// Like `send`, this function will fail if the other end of the channel
+    /// has hung up.
+    #[experimental]
+    pub fn send_yield(&self, t: T) {
+        if self.send_with(t, true).is_err() {
+            fail!("sending on a closed channel");
+        }
+    }
+
     /// Attempts to send a value on this channel, returning it back if it could
     /// not be sent.
     ///
@@ -600,21 +1051,92 @@ impl<T: Send> Sender<T> {
     /// ```
     #[unstable = "this function may be renamed to send() in the future"]
     pub fn send_opt(&self, t: T) -> Result<(), T> {
-        // In order to prevent starvation of other tasks in situations where
-        // a task sends repeatedly without ever receiving, we occasionally
-        // yield instead of doing a send immediately.
-        //
-        // Don't unconditionally attempt to yield because the TLS overhead can
-        // be a bit much, and also use `try_take` instead of `take` because
-        // there's no reason that this send shouldn't be usable off the
-        // runtime.
+        self.send_with(t, false)
+    }
+
+    /// Tests whether the receiving half of this channel has been dropped, in
+    /// which case nothing sent on it can be received any more.
+    ///
+    /// This lets a producer which has nothing to send yet find out that it
+    /// can stop. A `false` answer may be out of date as soon as it's returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (tx, rx) = channel::<int>();
+    /// assert!(!tx.is_disconnected());
+    /// drop(rx);
+    /// assert!(tx.is_disconnected());
+    /// ```
+    #[experimental]
+    pub fn is_disconnected(&self) -> bool {
+        match *unsafe { self.inner() } {
+            Oneshot(ref p) => unsafe { (*p.get()).port_dropped() },
+            Stream(ref p) => unsafe { (*p.get()).port_dropped() },
+            Shared(ref p) => unsafe { (*p.get()).port_dropped() },
+            Sync(..) => unreachable!(),
+        }
+    }
+
+    /// Returns a snapshot of the counters of this channel, or `None` if it
+    /// wasn't created by `channel_with_stats`.
+    #[experimental]
+    pub fn stats(&self) -> Option<ChannelStats> {
+        unsafe { self.inner().stats().snapshot() }
+    }
+
+    /// Returns a snapshot of the latency histogram of this channel, or `None`
+    /// if it wasn't created by `channel_with_latency`.
+    #[experimental]
+    pub fn latency(&self) -> Option<Latency> {
+        unsafe { self.inner().stats().latency() }
+    }
+
+    fn send_with(&self, t: T, resched: bool) -> Result<(), T> {
+        self.count_send();
+        let ret = self.do_send(t);
+        self.finish_send(ret, resched)
+    }
+
+    // In order to prevent starvation of other tasks in situations where a
+    // task sends repeatedly without ever receiving, we occasionally yield
+    // instead of doing a send immediately. This is called before each send,
+    // including the commit of a reserved slot.
+    //
+    // Don't unconditionally attempt to yield because the TLS overhead can be a
+    // bit much, and also use `try_take` instead of `take` because there's no
+    // reason that this send shouldn't be usable off the runtime.
+    fn count_send(&self) {
         let cnt = self.sends.get() + 1;
         self.sends.set(cnt);
         if cnt % (RESCHED_FREQ as uint) == 0 {
             let task: Option<Box<Task>> = Local::try_take();
             task.map(|t| t.maybe_yield());
         }
+    }
+
+    // Wakes up the task which was waiting for a send (if any), and accounts
+    // for the message if it was sent.
+    fn finish_send(&self, ret: Result<Option<BlockedTask>, T>,
+                   resched: bool) -> Result<(), T> {
+        let ret = match ret {
+            Ok(Some(task)) => { handoff(task, resched); Ok(()) }
+            Ok(None) => Ok(()),
+            Err(t) => Err(t),
+        };
+        if ret.is_ok() {
+            unsafe { self.inner().stats().sent() }
+            match self.watermark {
+                Some(ref wm) => wm.pushed(),
+                None => {}
+            }
+        }
+        ret
+    }
 
+    // Sends the data, returning the task which was waiting for it (if any),
+    // which is for the caller to wake up.
+    fn do_send(&self, t: T) -> Result<Option<BlockedTask>, T> {
         let (new_inner, ret) = match *unsafe { self.inner() } {
             Oneshot(ref p) => {
                 unsafe {
@@ -623,6 +1145,7 @@ impl<T: Send> Sender<T> {
                         return (*p).send(t);
                     } else {
                         let a = Arc::new(UnsafeCell::new(stream::Packet::new()));
+                        (*a.get()).inherit_stats(&(*p).stats);
                         match (*p).upgrade(Receiver::new(Stream(a.clone()))) {
                             oneshot::UpSuccess => {
                                 let ret = (*a.get()).send(t);
@@ -634,8 +1157,8 @@ impl<T: Send> Sender<T> {
                                 // asleep (we're looking at it), so the receiver
                                 // can't go away.
                                 (*a.get()).send(t).ok().unwrap();
-                                task.wake().map(|t| t.reawaken());
-                                (a, Ok(()))
+                                (*a.get()).stats.woke();
+                                (a, Ok(Some(task)))
                             }
                         }
                     }
@@ -652,6 +1175,116 @@ impl<T: Send> Sender<T> {
         }
         return ret;
     }
+
+    /// Blocks until every message sent on this sender before this call has
+    /// been received, or until the receiver has been dropped.
+    ///
+    /// This is implemented by enqueueing a marker behind all previously sent
+    /// messages and waiting for the receiver to acknowledge it, so it can be
+    /// used by producers to implement checkpointing. Note that messages sent
+    /// by *other* senders concurrently with this call may or may not have been
+    /// received when this returns.
+    ///
+    /// The marker itself is never seen by the receiver, although it may
+    /// cause a `Select` to wake up spuriously.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (tx, rx) = channel();
+    /// spawn(proc() {
+    ///     for _ in rx.iter() {}
+    /// });
+    /// tx.send(1i);
+    /// tx.send(2i);
+    /// // both messages have been received once this returns
+    /// tx.flush();
+    /// ```
+    #[experimental]
+    pub fn flush(&self) {
+        let (ack_tx, ack_rx) = channel();
+        match *unsafe { self.inner() } {
+            // Nothing has been sent, so there's nothing to wait for
+            Oneshot(ref p) if unsafe { !(*p.get()).sent() } => return,
+            _ => {}
+        }
+        // Otherwise a oneshot is upgraded just like a second send would do,
+        // and the marker is placed on the new stream.
+        self.upgrade_oneshot();
+        match *unsafe { self.inner() } {
+            Stream(ref p) => unsafe { (*p.get()).flush(ack_tx) },
+            Shared(ref p) => unsafe { (*p.get()).flush(ack_tx) },
+            Oneshot(..) | Sync(..) => unreachable!(),
+        }
+        let _ = ack_rx.recv_opt();
+    }
+
+    /// Reserves space for a message in this channel's internal queue.
+    ///
+    /// The returned slot can be filled in place, and its contents are sent by
+    /// committing the slot. This avoids moving a large value through the call
+    /// to `send`. The value does not become visible to the receiver until the
+    /// slot is committed, and messages are received in the order in which
+    /// they are committed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::ptr;
+    ///
+    /// let (mut tx, rx) = channel();
+    /// {
+    ///     let mut slot = tx.reserve();
+    ///     unsafe {
+    ///         ptr::write(slot.as_mut_ptr(), [0u8, ..4096]);
+    ///         (*slot.as_mut_ptr())[0] = 1;
+    ///         slot.commit().ok().unwrap();
+    ///     }
+    /// }
+    /// assert_eq!(rx.recv()[0], 1);
+    /// ```
+    #[experimental]
+    pub fn reserve<'a>(&'a mut self) -> SendSlot<'a, T> {
+        // Oneshots have no queue to reserve space in, so go to a stream
+        self.upgrade_oneshot();
+        let slot = match *unsafe { self.inner() } {
+            Stream(ref p) => StreamSlot(unsafe { (*p.get()).reserve() }),
+            Shared(ref p) => SharedSlot(unsafe { (*p.get()).reserve() }),
+            Oneshot(..) | Sync(..) => unreachable!(),
+        };
+        SendSlot { tx: self, slot: Some(slot), filled: false }
+    }
+
+    // Upgrades a oneshot sender to a stream, without sending any data. This
+    // is a noop for other flavors.
+    fn upgrade_oneshot(&self) {
+        let a = match *unsafe { self.inner() } {
+            Oneshot(ref p) => {
+                let a = Arc::new(UnsafeCell::new(stream::Packet::new()));
+                unsafe {
+                    (*a.get()).inherit_stats(&(*p.get()).stats);
+                    match (*p.get()).upgrade(Receiver::new(Stream(a.clone()))) {
+                        oneshot::UpSuccess | oneshot::UpDisconnected => {}
+                        oneshot::UpWoke(task) => {
+                            task.wake().map(|t| t.reawaken());
+                        }
+                    }
+                }
+                a
+            }
+            _ => return,
+        };
+        unsafe {
+            let tmp = Sender::new(Stream(a));
+            mem::swap(self.mut_inner(), tmp.mut_inner());
+        }
+    }
+
+    // Shares this sender's watermark (if any) with a freshly cloned sender
+    fn with_watermark(&self, mut tx: Sender<T>) -> Sender<T> {
+        tx.watermark = self.watermark.clone();
+        tx
+    }
 }
 
 #[unstable]
@@ -659,9 +1292,11 @@ impl<T: Send> Clone for Sender<T> {
     fn clone(&self) -> Sender<T> {
         let (packet, sleeper) = match *unsafe { self.inner() } {
             Oneshot(ref p) => {
-                let a = Arc::new(UnsafeCell::new(shared::Packet::new()));
+                let a = Arc::new(UnsafeCell::new(shared::Packet::new(
+                    2, LinkedQueue, default_backoff())));
                 unsafe {
                     (*a.get()).postinit_lock();
+                    (*a.get()).inherit_stats(&(*p.get()).stats);
                     match (*p.get()).upgrade(Receiver::new(Shared(a.clone()))) {
                         oneshot::UpSuccess | oneshot::UpDisconnected => (a, None),
                         oneshot::UpWoke(task) => (a, Some(task))
@@ -669,9 +1304,11 @@ impl<T: Send> Clone for Sender<T> {
                 }
             }
             Stream(ref p) => {
-                let a = Arc::new(UnsafeCell::new(shared::Packet::new()));
+                let a = Arc::new(UnsafeCell::new(shared::Packet::new(
+                    2, LinkedQueue, default_backoff())));
                 unsafe {
                     (*a.get()).postinit_lock();
+                    (*a.get()).inherit_stats(&(*p.get()).stats);
                     match (*p.get()).upgrade(Receiver::new(Shared(a.clone()))) {
                         stream::UpSuccess | stream::UpDisconnected => (a, None),
                         stream::UpWoke(task) => (a, Some(task)),
@@ -680,7 +1317,7 @@ impl<T: Send> Clone for Sender<T> {
             }
             Shared(ref p) => {
                 unsafe { (*p.get()).clone_chan(); }
-                return Sender::new(Shared(p.clone()));
+                return self.with_watermark(Sender::new(Shared(p.clone())));
             }
             Sync(..) => unreachable!(),
         };
@@ -691,13 +1328,14 @@ impl<T: Send> Clone for Sender<T> {
             let tmp = Sender::new(Shared(packet.clone()));
             mem::swap(self.mut_inner(), tmp.mut_inner());
         }
-        Sender::new(Shared(packet))
+        self.with_watermark(Sender::new(Shared(packet)))
     }
 }
 
 #[unsafe_destructor]
 impl<T: Send> Drop for Sender<T> {
     fn drop(&mut self) {
+        unsafe { self.inner().stats().sender_gone() }
         match *unsafe { self.mut_inner() } {
             Oneshot(ref mut p) => unsafe { (*p.get()).drop_chan(); },
             Stream(ref mut p) => unsafe { (*p.get()).drop_chan(); },
@@ -707,6 +1345,83 @@ impl<T: Send> Drop for Sender<T> {
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// SendSlot
+////////////////////////////////////////////////////////////////////////////////
+
+impl<'a, T: Send> SendSlot<'a, T> {
+    /// Returns a pointer to the storage for this slot's message.
+    ///
+    /// The storage is uninitialized, and it must be initialized (for example
+    /// with `ptr::write`) before this slot is committed. Depending on the
+    /// channel's queue, the storage may live inside of the slot itself, so
+    /// the pointer is only valid for as long as the slot isn't moved.
+    pub unsafe fn as_mut_ptr(&mut self) -> *mut T {
+        self.filled = true;
+        match *self.slot.as_mut().unwrap() {
+            StreamSlot(ref mut s) => {
+                if s.value().is_none() {
+                    *s.value() = Some(stream::Data(mem::uninitialized()));
+                }
+                match *s.value() {
+                    Some(stream::Data(ref mut t)) => t as *mut T,
+                    _ => unreachable!(),
+                }
+            }
+            SharedSlot(ref mut s) => {
+                if s.value().is_none() {
+                    *s.value() = Some(shared::Data(mem::uninitialized()));
+                }
+                match *s.value() {
+                    Some(shared::Data(ref mut t)) => t as *mut T,
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Sends the message stored in this slot, with the same semantics as
+    /// `Sender::send_opt`, including its occasional yields.
+    ///
+    /// This is unsafe because the storage returned by `as_mut_ptr` must have
+    /// been initialized.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if `as_mut_ptr` was never called.
+    pub unsafe fn commit(mut self) -> Result<(), T> {
+        assert!(self.filled, "committing a slot which was never filled");
+        self.tx.count_send();
+        let ret = match (self.slot.take_unwrap(), self.tx.inner()) {
+            (StreamSlot(s), &Stream(ref p)) => (*p.get()).commit(s),
+            (SharedSlot(s), &Shared(ref p)) => (*p.get()).commit(s),
+            _ => unreachable!(),
+        };
+        self.tx.finish_send(ret, false)
+    }
+
+    /// Writes `t` into this slot and commits it.
+    pub fn send(mut self, t: T) -> Result<(), T> {
+        unsafe {
+            ptr::write(self.as_mut_ptr(), t);
+            self.commit()
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'a, T: Send> Drop for SendSlot<'a, T> {
+    fn drop(&mut self) {
+        // An uncommitted slot's storage can't be assumed to be initialized, so
+        // it is leaked rather than dropped.
+        match self.slot.take() {
+            Some(StreamSlot(mut s)) => unsafe { mem::forget(s.value().take()) },
+            Some(SharedSlot(mut s)) => unsafe { mem::forget(s.value().take()) },
+            None => {}
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // SyncSender
 ////////////////////////////////////////////////////////////////////////////////
@@ -757,7 +1472,9 @@ impl<T: Send> SyncSender<T> {
     /// This function cannot fail.
     #[unstable = "this function may be renamed to send() in the future"]
     pub fn send_opt(&self, t: T) -> Result<(), T> {
-        unsafe { (*self.inner.get()).send(t) }
+        let ret = unsafe { (*self.inner.get()).send(t) };
+        if ret.is_ok() { unsafe { (*self.inner.get()).stats.sent() } }
+        ret
     }
 
     /// Attempts to send a value on this channel without blocking.
@@ -776,7 +1493,23 @@ impl<T: Send> SyncSender<T> {
     #[unstable = "the return type of this function is candidate for \
                   modification"]
     pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
-        unsafe { (*self.inner.get()).try_send(t) }
+        let ret = unsafe { (*self.inner.get()).try_send(t) };
+        if ret.is_ok() { unsafe { (*self.inner.get()).stats.sent() } }
+        ret
+    }
+
+    /// Returns a snapshot of the counters of this channel, or `None` if it
+    /// wasn't created by `sync_channel_with_stats`.
+    #[experimental]
+    pub fn stats(&self) -> Option<ChannelStats> {
+        unsafe { (*self.inner.get()).stats.snapshot() }
+    }
+
+    /// Returns a snapshot of the latency histogram of this channel, or `None`
+    /// if it wasn't created by `sync_channel_with_latency`.
+    #[experimental]
+    pub fn latency(&self) -> Option<Latency> {
+        unsafe { (*self.inner.get()).stats.latency() }
     }
 }
 
@@ -791,6 +1524,7 @@ impl<T: Send> Clone for SyncSender<T> {
 #[unsafe_destructor]
 impl<T: Send> Drop for SyncSender<T> {
     fn drop(&mut self) {
+        unsafe { (*self.inner.get()).stats.sender_gone() }
         unsafe { (*self.inner.get()).drop_chan(); }
     }
 }
@@ -801,7 +1535,29 @@ impl<T: Send> Drop for SyncSender<T> {
 
 impl<T: Send> Receiver<T> {
     fn new(inner: Flavor<T>) -> Receiver<T> {
-        Receiver { inner: UnsafeCell::new(inner), receives: Cell::new(0), marker: marker::NoShare }
+        Receiver {
+            inner: UnsafeCell::new(inner),
+            receives: Cell::new(0),
+            watermark: None,
+            tap: None,
+            marker: marker::NoShare,
+        }
+    }
+
+    // Invoked on every message which is handed out by this receiver, this
+    // records that it was dequeued for watermarked channels and copies it to
+    // a tap if one is attached.
+    fn received(&self, t: T) -> T {
+        unsafe { self.inner().stats().received() }
+        match self.watermark {
+            Some(ref wm) => wm.popped(),
+            None => {}
+        }
+        match self.tap {
+            Some(ref tap) => tap.tap(&t),
+            None => {}
+        }
+        t
     }
 
     /// Blocks waiting for a value on this receiver
@@ -855,11 +1611,17 @@ impl<T: Send> Receiver<T> {
             task.map(|t| t.maybe_yield());
         }
 
+        let ret = self.do_try_recv();
+        if ret.is_err() { unsafe { self.inner().stats().try_recv_failed() } }
+        ret
+    }
+
+    fn do_try_recv(&self) -> Result<T, TryRecvError> {
         loop {
             let new_port = match *unsafe { self.inner() } {
                 Oneshot(ref p) => {
                     match unsafe { (*p.get()).try_recv() } {
-                        Ok(t) => return Ok(t),
+                        Ok(t) => return Ok(self.received(t)),
                         Err(oneshot::Empty) => return Err(Empty),
                         Err(oneshot::Disconnected) => return Err(Disconnected),
                         Err(oneshot::Upgraded(rx)) => rx,
@@ -867,7 +1629,7 @@ impl<T: Send> Receiver<T> {
                 }
                 Stream(ref p) => {
                     match unsafe { (*p.get()).try_recv() } {
-                        Ok(t) => return Ok(t),
+                        Ok(t) => return Ok(self.received(t)),
                         Err(stream::Empty) => return Err(Empty),
                         Err(stream::Disconnected) => return Err(Disconnected),
                         Err(stream::Upgraded(rx)) => rx,
@@ -875,14 +1637,14 @@ impl<T: Send> Receiver<T> {
                 }
                 Shared(ref p) => {
                     match unsafe { (*p.get()).try_recv() } {
-                        Ok(t) => return Ok(t),
+                        Ok(t) => return Ok(self.received(t)),
                         Err(shared::Empty) => return Err(Empty),
                         Err(shared::Disconnected) => return Err(Disconnected),
                     }
                 }
                 Sync(ref p) => {
                     match unsafe { (*p.get()).try_recv() } {
-                        Ok(t) => return Ok(t),
+                        Ok(t) => return Ok(self.received(t)),
                         Err(sync::Empty) => return Err(Empty),
                         Err(sync::Disconnected) => return Err(Disconnected),
                     }
@@ -913,7 +1675,7 @@ impl<T: Send> Receiver<T> {
             let new_port = match *unsafe { self.inner() } {
                 Oneshot(ref p) => {
                     match unsafe { (*p.get()).recv() } {
-                        Ok(t) => return Ok(t),
+                        Ok(t) => return Ok(self.received(t)),
                         Err(oneshot::Empty) => return unreachable!(),
                         Err(oneshot::Disconnected) => return Err(()),
                         Err(oneshot::Upgraded(rx)) => rx,
@@ -921,7 +1683,7 @@ impl<T: Send> Receiver<T> {
                 }
                 Stream(ref p) => {
                     match unsafe { (*p.get()).recv() } {
-                        Ok(t) => return Ok(t),
+                        Ok(t) => return Ok(self.received(t)),
                         Err(stream::Empty) => return unreachable!(),
                         Err(stream::Disconnected) => return Err(()),
                         Err(stream::Upgraded(rx)) => rx,
@@ -929,12 +1691,14 @@ impl<T: Send> Receiver<T> {
                 }
                 Shared(ref p) => {
                     match unsafe { (*p.get()).recv() } {
-                        Ok(t) => return Ok(t),
+                        Ok(t) => return Ok(self.received(t)),
                         Err(shared::Empty) => return unreachable!(),
                         Err(shared::Disconnected) => return Err(()),
                     }
                 }
-                Sync(ref p) => return unsafe { (*p.get()).recv() }
+                Sync(ref p) => {
+                    return unsafe { (*p.get()).recv() }.map(|t| self.received(t))
+                }
             };
             unsafe {
                 mem::swap(self.mut_inner(), new_port.mut_inner());
@@ -948,9 +1712,146 @@ impl<T: Send> Receiver<T> {
     pub fn iter<'a>(&'a self) -> Messages<'a, T> {
         Messages { rx: self }
     }
+
+    /// Returns a file descriptor (a handle on Windows) which is readable when
+    /// this receiver has something to receive, for event loops which can only
+    /// wait on those. See `ReadyFd` for how it's used.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if the receiver is in a `Poller`, or if the
+    /// descriptor can't be created.
+    #[experimental]
+    pub fn as_fd<'a>(&'a self) -> ReadyFd<'a> {
+        ReadyFd::new(self)
+    }
+
+    /// Runs `f` with the next message received on this receiver, once it
+    /// arrives, instead of blocking a task to wait for it.
+    ///
+    /// All of the receivers with a callback are waited on together by one
+    /// dispatcher task, which spawns a task to run each callback, so a
+    /// callback which blocks or fails doesn't affect the callbacks of other
+    /// receivers. The receiver is handed back to the callback along with
+    /// the message, which it can use to wait for the next message the same
+    /// way. If the channel hangs up before a message arrives, the callback is
+    /// dropped without being run.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// fn count(total: int, rx: Receiver<int>, done: Sender<int>) {
+    ///     rx.on_recv(proc(n, rx) {
+    ///         if n == 0 { done.send(total) } else { count(total + n, rx, done) }
+    ///     });
+    /// }
+    ///
+    /// let (tx, rx) = channel();
+    /// let (done_tx, done_rx) = channel();
+    /// count(0, rx, done_tx);
+    ///
+    /// tx.send(1);
+    /// tx.send(2);
+    /// tx.send(0);
+    /// assert_eq!(done_rx.recv(), 3);
+    /// ```
+    #[experimental]
+    pub fn on_recv(self, f: proc(T, Receiver<T>):Send) {
+        dispatch::register(self, f)
+    }
+
+    /// Returns a snapshot of the counters of this channel, or `None` if it
+    /// wasn't created by `channel_with_stats` or `sync_channel_with_stats`.
+    #[experimental]
+    pub fn stats(&self) -> Option<ChannelStats> {
+        unsafe { self.inner().stats().snapshot() }
+    }
+
+    /// Returns a snapshot of the latency histogram of this channel, or `None`
+    /// if it wasn't created by `channel_with_latency` or
+    /// `sync_channel_with_latency`.
+    #[experimental]
+    pub fn latency(&self) -> Option<Latency> {
+        unsafe { self.inner().stats().latency() }
+    }
+
+    /// Returns the internal state of this channel, which shows which flavor
+    /// it currently has along with its state word (or counts) decoded, such
+    /// as whether a task is blocked on it and how many messages are queued.
+    ///
+    /// This is for debugging hung programs: the state is read without any
+    /// synchronization with the senders, so it may be out of date by the
+    /// time it's printed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (tx, rx) = channel::<int>();
+    /// tx.send(1);
+    /// tx.send(2);
+    /// println!("{}", rx.debug_state());
+    /// ```
+    #[experimental]
+    pub fn debug_state<'a>(&'a self) -> DebugState<'a, T> {
+        DebugState { rx: self }
+    }
 }
 
-impl<T: Send> select::Packet for Receiver<T> {
+impl<T: Send + Clone> Receiver<T> {
+    /// Attaches a tap to this receiver, returning the receiver back.
+    ///
+    /// Every message subsequently received on the returned receiver will also
+    /// have a copy sent along `tx`, which makes it possible to record or
+    /// inspect the messages flowing through a channel without modifying
+    /// either of its endpoints. A tap that has hung up is ignored, and
+    /// attaching a second tap replaces the first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (tx, rx) = channel();
+    /// let (debug_tx, debug_rx) = channel();
+    /// let rx = rx.tap(debug_tx);
+    ///
+    /// tx.send(1i);
+    /// assert_eq!(rx.recv(), 1);
+    /// assert_eq!(debug_rx.recv(), 1);
+    /// ```
+    #[experimental]
+    pub fn tap(self, tx: Sender<T>) -> Receiver<T> {
+        self.tap_with(box tx as Box<Tap<T> + Send>)
+    }
+}
+
+impl<T: Send> Receiver<T> {
+    /// Attaches a tap to this receiver, like `tap`, except that the messages
+    /// are handed to `tap` by reference as they're received, so they don't
+    /// need to be cloned. This is how `serialize::record` records the
+    /// messages of a channel.
+    #[experimental]
+    pub fn tap_with(mut self, tap: Box<Tap<T> + Send>) -> Receiver<T> {
+        self.tap = Some(tap);
+        self
+    }
+}
+
+/// A destination for the messages received on a receiver, see
+/// `Receiver::tap_with`.
+///
+/// Taps hold onto receivers without requiring `T: Clone` everywhere, which is
+/// what `Receiver::tap` needs to send copies along a channel.
+#[experimental]
+pub trait Tap<T> {
+    /// Called with each message received, on the receiving task, before the
+    /// message is returned.
+    fn tap(&self, t: &T);
+}
+
+impl<T: Send + Clone> Tap<T> for Sender<T> {
+    fn tap(&self, t: &T) { let _ = self.send_opt(t.clone()); }
+}
+
+impl<T: Send> select::Packet for Receiver<T> {
     fn can_recv(&self) -> bool {
         loop {
             let new_port = match *unsafe { self.inner() } {
@@ -1035,6 +1936,20 @@ impl<T: Send> select::Packet for Receiver<T> {
             }
         }
     }
+
+    fn watch(&self, watcher: Option<poll::Watcher>) -> bool {
+        let watch = match *unsafe { self.inner() } {
+            Oneshot(ref p) => unsafe { (*p.get()).watch() },
+            Stream(ref p) => unsafe { (*p.get()).watch() },
+            Shared(ref p) => unsafe { (*p.get()).watch() },
+            Sync(ref p) => unsafe { (*p.get()).watch() },
+        };
+        watch.set(watcher)
+    }
+
+    fn channel(&self) -> Option<uint> {
+        unsafe { self.inner().stats().id() }
+    }
 }
 
 #[unstable]
@@ -1042,9 +1957,27 @@ impl<'a, T: Send> Iterator<T> for Messages<'a, T> {
     fn next(&mut self) -> Option<T> { self.rx.recv_opt().ok() }
 }
 
+impl<'a, T: Send> fmt::Show for DebugState<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        unsafe {
+            match *self.rx.inner() {
+                Oneshot(ref p) => write!(f, "{}", *p.get()),
+                Stream(ref p) => write!(f, "{}", *p.get()),
+                Shared(ref p) => write!(f, "{}", *p.get()),
+                Sync(ref p) => write!(f, "{}", *p.get()),
+            }
+        }
+    }
+}
+
 #[unsafe_destructor]
 impl<T: Send> Drop for Receiver<T> {
     fn drop(&mut self) {
+        unsafe { self.inner().stats().receiver_gone() }
+        match self.watermark {
+            Some(ref wm) => wm.close(),
+            None => {}
+        }
         match *unsafe { self.mut_inner() } {
             Oneshot(ref mut p) => unsafe { (*p.get()).drop_port(); },
             Stream(ref mut p) => unsafe { (*p.get()).drop_port(); },
@@ -1080,6 +2013,99 @@ mod test {
         tx.send(box 1i);
     })
 
+    test!(fn on_recv() {
+        let (tx, rx) = channel();
+        let (done_tx, done_rx) = channel();
+        rx.on_recv(proc(n: int, _rx) done_tx.send(n));
+        tx.send(1);
+        assert_eq!(done_rx.recv(), 1);
+
+        // Sent before the callback is registered
+        let (tx, rx) = channel();
+        let (done_tx, done_rx) = channel();
+        tx.send(2i);
+        rx.on_recv(proc(n, _rx) done_tx.send(n));
+        assert_eq!(done_rx.recv(), 2);
+    })
+
+    test!(fn on_recv_again() {
+        fn sum(total: int, rx: Receiver<int>, done: Sender<int>) {
+            rx.on_recv(proc(n, rx) {
+                if n == 0 { done.send(total) } else { sum(total + n, rx, done) }
+            });
+        }
+        let (tx, rx) = channel();
+        let (done_tx, done_rx) = channel();
+        sum(0, rx, done_tx);
+        for i in range(1i, 101) { tx.send(i); }
+        tx.send(0);
+        assert_eq!(done_rx.recv(), 5050);
+    })
+
+    test!(fn on_recv_disconnected() {
+        let (tx, rx) = channel::<int>();
+        let (done_tx, done_rx) = channel::<()>();
+        rx.on_recv(proc(_, _) done_tx.send(()));
+        drop(tx);
+        assert_eq!(done_rx.recv_opt(), Err(()));
+    })
+
+    test!(fn on_recv_failure() {
+        let (tx1, rx1) = channel::<int>();
+        let (tx2, rx2) = channel::<int>();
+        let (failed_tx, failed_rx) = channel();
+        let (done_tx, done_rx) = channel();
+        rx1.on_recv(proc(_, _) { failed_tx.send(()); fail!() });
+        rx2.on_recv(proc(n, _rx) done_tx.send(n));
+        tx1.send(1);
+        failed_rx.recv();
+        // The other callback still runs, and so do new ones
+        tx2.send(2);
+        assert_eq!(done_rx.recv(), 2);
+        let (tx3, rx3) = channel::<int>();
+        let (done_tx, done_rx) = channel();
+        rx3.on_recv(proc(n, _rx) done_tx.send(n));
+        tx3.send(3);
+        assert_eq!(done_rx.recv(), 3);
+    })
+
+    test!(fn on_recv_many() {
+        let (done_tx, done_rx) = channel();
+        let txs = Vec::from_fn(200, |i| {
+            let (tx, rx) = channel();
+            let done_tx = done_tx.clone();
+            rx.on_recv(proc(n: uint, _rx) done_tx.send(n + i));
+            tx
+        });
+        for tx in txs.iter() { tx.send(1); }
+        let mut total = 0;
+        for _ in range(0u, 200) { total += done_rx.recv(); }
+        assert_eq!(total, 200 + 199 * 200 / 2);
+    })
+
+    test!(fn is_disconnected() {
+        let (tx, rx) = channel::<int>();
+        assert!(!tx.is_disconnected());
+        drop(rx);
+        assert!(tx.is_disconnected());
+
+        // Once upgraded to a stream
+        let (tx, rx) = channel::<int>();
+        tx.send(1);
+        tx.send(2);
+        assert!(!tx.is_disconnected());
+        drop(rx);
+        assert!(tx.is_disconnected());
+
+        // Once shared
+        let (tx, rx) = channel::<int>();
+        let tx2 = tx.clone();
+        assert!(!tx2.is_disconnected());
+        drop(rx);
+        assert!(tx.is_disconnected());
+        assert!(tx2.is_disconnected());
+    })
+
     test!(fn drop_full_shared() {
         let (tx, _rx) = channel();
         drop(tx.clone());
@@ -1096,6 +2122,22 @@ mod test {
         assert_eq!(rx.recv(), 1);
     })
 
+    #[test]
+    fn block_on_foreign_thread() {
+        use rustrt::thread::Thread;
+
+        // Threads started with `Thread` don't have a `Task`
+        let (tx, rx) = channel::<int>();
+        let (stx, srx) = sync_channel::<int>(0);
+        let t = Thread::start(proc() {
+            let n = rx.recv_opt().unwrap();
+            stx.send_opt(n + 1).unwrap();
+        });
+        tx.send(1);
+        assert_eq!(srx.recv(), 2);
+        t.join();
+    }
+
     test!(fn smoke_threads() {
         let (tx, rx) = channel::<int>();
         spawn(proc() {
@@ -1343,6 +2385,58 @@ mod test {
         assert_eq!(rx.try_recv(), Err(Empty));
     })
 
+    test!(fn oneshot_inline_data() {
+        let (tx, rx) = channel::<u8>();
+        assert_eq!(rx.try_recv(), Err(Empty));
+        tx.send(7);
+        assert_eq!(rx.try_recv(), Ok(7));
+        assert_eq!(rx.try_recv(), Err(Disconnected));
+
+        let (tx, rx) = channel::<bool>();
+        spawn(proc() { tx.send(true) });
+        assert_eq!(rx.recv(), true);
+
+        let (tx, rx) = channel::<()>();
+        drop(tx);
+        assert_eq!(rx.recv_opt(), Err(()));
+    })
+
+    test!(fn oneshot_inline_upgrade() {
+        // the pending inline value must survive the upgrade to a stream
+        let (tx, rx) = channel::<u16>();
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+
+        // and the upgrade to a shared channel
+        let (tx, rx) = channel::<u16>();
+        tx.send(1);
+        let tx2 = tx.clone();
+        tx2.send(2);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+    })
+
+    test!(fn oneshot_inline_port_gone() {
+        let (tx, rx) = channel::<u8>();
+        drop(rx);
+        assert_eq!(tx.send_opt(3), Err(3));
+
+        let (tx, rx) = channel::<u8>();
+        tx.send(3);
+        drop(rx);
+        drop(tx);
+    })
+
+    test!(fn oneshot_inline_stress() {
+        for _ in range(0u, stress_factor() * 100) {
+            let (tx, rx) = channel::<u32>();
+            spawn(proc() { tx.send(5) });
+            assert_eq!(rx.recv(), 5);
+        }
+    })
+
     test!(fn oneshot_multi_task_recv_then_send() {
         let (tx, rx) = channel::<Box<int>>();
         spawn(proc() {
@@ -1566,6 +2660,449 @@ mod test {
         t.join();
     })
 
+    test!(fn flush_waits_for_receiver() {
+        let (tx, rx) = channel::<int>();
+        let (donetx, donerx) = channel();
+        spawn(proc() {
+            for _ in range(0u, 100) { task::deschedule(); }
+            assert_eq!(rx.recv(), 1);
+            assert_eq!(rx.recv(), 2);
+            assert_eq!(rx.recv(), 3);
+            donetx.send(rx);
+        });
+        tx.send(1);
+        tx.send(2);
+        tx.send(3);
+        tx.flush();
+        let rx = donerx.recv();
+        assert_eq!(rx.try_recv(), Err(Empty));
+    })
+
+    test!(fn flush_nothing_sent() {
+        let (tx, _rx) = channel::<int>();
+        tx.flush();
+        tx.flush();
+    })
+
+    test!(fn flush_shared() {
+        let (tx, rx) = channel::<int>();
+        let tx2 = tx.clone();
+        spawn(proc() {
+            for i in range(0i, 10) { tx2.send(i); }
+            tx2.flush();
+        });
+        for i in range(0i, 10) { assert_eq!(rx.recv(), i); }
+        assert_eq!(rx.try_recv(), Err(Empty));
+    })
+
+    test!(fn flush_port_gone() {
+        let (tx, rx) = channel::<int>();
+        tx.send(1);
+        tx.send(2);
+        drop(rx);
+        tx.flush();
+    })
+
+    test!(fn flush_oneshot_port_gone() {
+        let (tx, rx) = channel::<int>();
+        tx.send(1);
+        drop(rx);
+        tx.flush();
+    })
+
+    test!(fn reserve_commit() {
+        use std::ptr;
+
+        let (mut tx, rx) = channel::<Box<int>>();
+        tx.reserve().send(box 1).ok().unwrap();
+        {
+            let mut slot = tx.reserve();
+            unsafe {
+                ptr::write(slot.as_mut_ptr(), box 2);
+                slot.commit().ok().unwrap();
+            }
+        }
+        assert_eq!(rx.recv(), box 1);
+        assert_eq!(rx.recv(), box 2);
+    })
+
+    test!(fn reserve_shared() {
+        let (mut tx, rx) = channel::<int>();
+        let _tx2 = tx.clone();
+        let a = tx.reserve();
+        drop(a);
+        tx.reserve().send(3).ok().unwrap();
+        assert_eq!(rx.recv(), 3);
+        assert_eq!(rx.try_recv(), Err(Empty));
+    })
+
+    test!(fn reserve_port_gone() {
+        let (mut tx, rx) = channel::<int>();
+        tx.send(1);
+        drop(rx);
+        assert_eq!(tx.reserve().send(2), Err(2));
+    })
+
+    test!(fn tap() {
+        let (tx, rx) = channel::<int>();
+        let (ttx, trx) = channel();
+        let rx = rx.tap(ttx);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(Empty));
+        assert_eq!(trx.recv(), 1);
+        assert_eq!(trx.recv(), 2);
+        assert_eq!(trx.try_recv(), Err(Empty));
+    })
+
+    test!(fn tap_hung_up() {
+        let (tx, rx) = channel::<int>();
+        let (ttx, trx) = channel();
+        let rx = rx.tap(ttx);
+        drop(trx);
+        tx.send(1);
+        assert_eq!(rx.recv(), 1);
+    })
+
+    test!(fn stats_off() {
+        use comm::trace;
+
+        // The tracing test gives the channels created meanwhile statistics
+        let _g = unsafe { trace::test::LOCK.lock() };
+        let (tx, rx) = channel::<int>();
+        tx.send(1);
+        assert!(tx.stats().is_none());
+        assert!(rx.stats().is_none());
+    })
+
+    test!(fn stats_survive_upgrades() {
+        let (tx, rx) = channel_with_stats::<int>();
+        tx.send(1);
+        tx.send(2);
+        let tx2 = tx.clone();
+        tx2.send(3);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Err(Empty));
+        let stats = rx.stats().unwrap();
+        assert_eq!(stats, ChannelStats {
+            sends: 3, recvs: 3, blocking_recvs: 0, failed_try_recvs: 1,
+            wakeups: 0,
+        });
+        assert_eq!(tx.stats(), Some(stats.clone()));
+        assert_eq!(tx2.stats(), Some(stats));
+    })
+
+    test!(fn stats_wakeups() {
+        let (tx, rx) = channel_with_stats::<int>();
+        let (done_tx, done_rx) = channel();
+        spawn(proc() {
+            for _ in range(0u, 100) { rx.recv(); }
+            done_tx.send(rx);
+        });
+        for i in range(0i, 100) { tx.send(i); }
+        let rx = done_rx.recv();
+
+        // Every time the receiver blocked, a send woke it up
+        let stats = rx.stats().unwrap();
+        assert_eq!(stats.sends, 100);
+        assert_eq!(stats.recvs, 100);
+        assert_eq!(stats.blocking_recvs, stats.wakeups);
+    })
+
+    test!(fn stats_sync() {
+        let (tx, rx) = sync_channel_with_stats::<int>(1);
+        assert_eq!(tx.try_send(1), Ok(()));
+        assert_eq!(tx.try_send(2), Err(Full(2)));
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.try_recv(), Err(Empty));
+        let stats = tx.stats().unwrap();
+        assert_eq!((stats.sends, stats.recvs, stats.failed_try_recvs), (1, 1, 1));
+    })
+
+    test!(fn latency_survives_upgrades() {
+        let (tx, rx) = channel_with_latency::<int>();
+        tx.send(1);
+        tx.send(2);
+        let tx2 = tx.clone();
+        tx2.send(3);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        assert_eq!(rx.recv(), 3);
+        let latency = rx.latency().unwrap();
+        assert_eq!(latency.count(), 3);
+        assert!(latency.min() <= latency.percentile(50.0));
+        assert!(latency.percentile(50.0) <= latency.max());
+        assert_eq!(tx.latency(), Some(latency));
+        assert_eq!(rx.stats().unwrap().recvs, 3);
+    })
+
+    test!(fn latency_sync() {
+        let (tx, rx) = sync_channel_with_latency::<int>(1);
+        assert_eq!(tx.try_send(1), Ok(()));
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.latency().unwrap().count(), 1);
+
+        let (tx, rx) = sync_channel_with_latency::<int>(0);
+        spawn(proc() { tx.send(1); tx.send(2); });
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        assert_eq!(rx.latency().unwrap().count(), 2);
+
+        let (tx, rx) = channel_with_stats::<int>();
+        tx.send(1);
+        rx.recv();
+        assert!(rx.latency().is_none());
+    })
+
+    #[test]
+    fn discard_hook() {
+        use atomics::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+
+        static mut LOST: AtomicUint = INIT_ATOMIC_UINT;
+        struct Lost;
+
+        // The hook sees the receivers of all of the other tests too
+        fn hook(d: &Discarded) {
+            if d.type_name.contains("Lost") {
+                unsafe { LOST.fetch_add(d.messages, SeqCst); }
+            }
+        }
+        set_discard_hook(hook);
+
+        let (tx, rx) = channel();
+        tx.send(Lost);
+        drop(rx);
+        assert_eq!(unsafe { LOST.load(SeqCst) }, 1);
+
+        let (tx, rx) = channel();
+        tx.send(Lost);
+        rx.recv();
+        tx.send(Lost);
+        tx.send(Lost);
+        rx.recv();
+        drop(rx);
+        assert_eq!(unsafe { LOST.load(SeqCst) }, 2);
+
+        let (tx, rx) = channel();
+        let tx2 = tx.clone();
+        tx.send(Lost);
+        tx2.send(Lost);
+        drop(rx);
+        assert_eq!(unsafe { LOST.load(SeqCst) }, 4);
+
+        let (tx, rx) = sync_channel(2);
+        tx.send(Lost);
+        tx.send(Lost);
+        drop(rx);
+        assert_eq!(unsafe { LOST.load(SeqCst) }, 6);
+
+        // A rendezvous hands a blocked sender its message back instead
+        let (tx, rx) = sync_channel(0);
+        let (done_tx, done_rx) = channel();
+        native::task::spawn(proc() {
+            done_tx.send(tx.send_opt(Lost).is_err());
+        });
+        drop(rx);
+        assert!(done_rx.recv());
+        assert_eq!(unsafe { LOST.load(SeqCst) }, 6);
+    }
+
+    test!(fn debug_state() {
+        fn state<T: Send>(rx: &Receiver<T>) -> String {
+            format!("{}", rx.debug_state())
+        }
+
+        let (tx, rx) = channel::<int>();
+        assert_eq!(state(&rx).as_slice(), "oneshot packet: EMPTY, nothing sent");
+        tx.send(1);
+        assert_eq!(state(&rx).as_slice(), "oneshot packet: DATA, sent");
+        assert_eq!(rx.recv(), 1);
+        tx.send(2);
+        tx.send(3);
+        assert_eq!(rx.recv(), 2);
+        assert!(state(&rx).as_slice().starts_with("stream packet: 1 queued"));
+        drop(tx);
+        assert!(state(&rx).as_slice().contains("DISCONNECTED"));
+
+        let (tx, rx) = channel::<int>();
+        let _tx2 = tx.clone();
+        tx.send(1);
+        assert!(state(&rx).as_slice().starts_with("shared packet: 1 queued"));
+        assert!(state(&rx).as_slice().contains("2 senders"));
+
+        let (tx, rx) = sync_channel::<int>(2);
+        tx.send(1);
+        assert_eq!(state(&rx).as_slice(),
+                   "sync packet: 1 of 2 buffered, 0 senders waiting, 1 senders");
+    })
+
+    test!(fn clones_upgrade_once() {
+        use comm::{Shared, UnsafeFlavor};
+
+        let (tx, rx) = channel::<int>();
+        tx.send(1);
+        tx.send(2);
+        let txs = Vec::from_fn(100, |_| tx.clone());
+        for tx in txs.iter() { tx.send(3); }
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        assert_eq!(rx.recv(), 3);
+        match *unsafe { rx.inner() } {
+            Shared(..) => {}
+            _ => fail!("receiver should be on the shared packet")
+        }
+        for _ in range(1u, 100) { assert_eq!(rx.recv(), 3); }
+    })
+
+    // These stress the blocking and disconnection protocols, which are where
+    // any missing synchronization from weaker memory orderings would show up.
+    // They're slow, so they only run when the tests are built with
+    // `--cfg stress` (or with `--ignored`); scale them up with
+    // RUST_TEST_STRESS on weakly ordered platforms.
+    test!(fn stress_stream_ping_pong() {
+        let (tx1, rx1) = channel::<uint>();
+        let (tx2, rx2) = channel::<uint>();
+        let n = stress_factor() * 1000 + 1000;
+        spawn(proc() {
+            for i in range(0, n) {
+                assert_eq!(rx1.recv(), i);
+                tx2.send(i);
+            }
+        });
+        for i in range(0, n) {
+            tx1.send(i);
+            assert_eq!(rx2.recv(), i);
+        }
+    } #[ignore(cfg(not(stress)))])
+
+    test!(fn stress_shared_blocking_recv() {
+        for _ in range(0u, stress_factor() + 10) {
+            let (tx, rx) = channel::<uint>();
+            for _ in range(0u, 4) {
+                let tx = tx.clone();
+                spawn(proc() {
+                    for _ in range(0u, 50) {
+                        tx.send(1);
+                        task::deschedule();
+                    }
+                });
+            }
+            drop(tx);
+            let mut total = 0;
+            for n in rx.iter() { total += n; }
+            assert_eq!(total, 200);
+        }
+    } #[ignore(cfg(not(stress)))])
+
+    test!(fn stress_disconnect_races() {
+        for _ in range(0u, stress_factor() * 10 + 100) {
+            let (tx, rx) = channel::<Box<uint>>();
+            let tx2 = tx.clone();
+            spawn(proc() {
+                for _ in range(0u, 10) { let _ = tx.send_opt(box 1); }
+            });
+            spawn(proc() {
+                for _ in range(0u, 10) { let _ = tx2.send_opt(box 2); }
+            });
+            // drop the port at some point in the middle of the sends, all of
+            // the boxes must still be freed
+            task::deschedule();
+            drop(rx);
+        }
+    } #[ignore(cfg(not(stress)))])
+
+    test!(fn shared_channel_smoke() {
+        let (tx, rx) = shared_channel();
+        tx.send(1i);
+        assert_eq!(rx.recv(), 1);
+        let tx2 = tx.clone();
+        tx2.send(2);
+        drop(tx);
+        assert_eq!(rx.recv(), 2);
+        drop(tx2);
+        assert_eq!(rx.recv_opt(), Err(()));
+    })
+
+    test!(fn shared_channel_many_senders() {
+        let (tx, rx) = shared_channel();
+        for _ in range(0u, 10) {
+            let tx = tx.clone();
+            spawn(proc() {
+                for _ in range(0u, 100) { tx.send(1i); }
+            });
+        }
+        drop(tx);
+        assert_eq!(rx.iter().fold(0, |a, b| a + b), 1000);
+    })
+
+    test!(fn shared_channel_queues() {
+        for &queue in [LinkedQueue, BlockQueue].iter() {
+            let (mut tx, rx) = shared_channel_with_queue(queue);
+            let txs = Vec::from_fn(4, |_| tx.clone());
+            for tx in txs.move_iter() {
+                spawn(proc() {
+                    for i in range(0u, 100) { tx.send(i); }
+                });
+            }
+            tx.reserve().send(1000).ok().unwrap();
+            drop(tx);
+            assert_eq!(rx.iter().fold(0, |a, b| a + b), 4 * 4950 + 1000);
+        }
+    })
+
+    test!(fn shared_channel_port_gone() {
+        let (tx, rx) = shared_channel::<int>();
+        drop(rx);
+        assert!(tx.send_opt(1).is_err());
+    })
+
+    test!(fn shared_channel_backoffs() {
+        use backoff::{Backoff, Spin, SpinYield, Exponential};
+
+        fn run<B: Backoff + Send + Share>(backoff: B) {
+            let (tx, rx) = shared_channel_with_backoff(BlockQueue, backoff);
+            let (dtx, drx) = channel();
+            for _ in range(0u, 4) {
+                let tx = tx.clone();
+                let dtx = dtx.clone();
+                spawn(proc() {
+                    for i in range(0u, 1000) { tx.send(i); }
+                    // keep sending until the port has been dropped
+                    while tx.send_opt(0).is_ok() {}
+                    dtx.send(());
+                });
+            }
+            drop(tx);
+            for _ in range(0u, 2000) { rx.recv(); }
+            drop(rx);
+            for _ in range(0u, 4) { drx.recv(); }
+        }
+        run(Spin);
+        run(SpinYield);
+        run(Exponential { limit: 6 });
+    })
+
+    test!(fn cache_policies() {
+        let (tx, rx) = channel_with_cache(FixedCache(0));
+        for i in range(0i, 100) { tx.send(i); }
+        for i in range(0i, 100) { assert_eq!(rx.recv(), i); }
+
+        let (tx, rx) = channel_with_cache(AdaptiveCache(1, 16));
+        spawn(proc() {
+            for i in range(0i, 10000) { tx.send(i); }
+        });
+        for i in range(0i, 10000) { assert_eq!(rx.recv(), i); }
+        assert_eq!(rx.recv_opt(), Err(()));
+    })
+
+    test!(fn bad_adaptive_cache() {
+        let (_tx, _rx) = channel_with_cache::<int>(AdaptiveCache(0, 16));
+    } #[should_fail])
+
     test!(fn try_recvs_off_the_runtime() {
         use std::rt::thread::Thread;
 
@@ -1588,6 +3125,67 @@ mod test {
         t.join();
         pdone.recv();
     })
+
+    test!(fn send_yield_smoke() {
+        let (tx, rx) = channel::<int>();
+        tx.send_yield(1);
+        tx.send_yield(2);
+        let tx2 = tx.clone();
+        tx2.send_yield(3);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        assert_eq!(rx.recv(), 3);
+    })
+
+    test!(fn send_yield_wakes_receiver() {
+        let (tx1, rx1) = channel::<int>();
+        let (tx2, rx2) = channel::<int>();
+        spawn(proc() {
+            for _ in range(0u, 3) {
+                let n = rx1.recv();
+                tx2.send_yield(n + 1);
+            }
+        });
+        for i in range(0i, 3) {
+            tx1.send_yield(i);
+            assert_eq!(rx2.recv(), i + 1);
+        }
+    })
+
+    test!(fn send_yield_port_gone() {
+        let (tx, rx) = channel::<int>();
+        drop(rx);
+        tx.send_yield(1);
+    } #[should_fail])
+
+    test!(fn stream_blocking_ping_pong() {
+        // Every blocking receive takes the stream count below zero and the
+        // following send brings it back, wrapping the counter each time.
+        let (tx1, rx1) = channel::<int>();
+        let (tx2, rx2) = channel::<int>();
+        spawn(proc() {
+            for i in range(0, 1000i) {
+                assert_eq!(rx1.recv(), i);
+                tx2.send(i);
+            }
+        });
+        for i in range(0, 1000i) {
+            tx1.send(i);
+            assert_eq!(rx2.recv(), i);
+        }
+    })
+
+    test!(fn stream_sends_racing_port_drop() {
+        for _ in range(0, stress_factor() * 100) {
+            let (tx, rx) = channel::<Box<int>>();
+            for _ in range(0, 3i) { tx.send(box 1); }
+            spawn(proc() {
+                while tx.send_opt(box 2).is_ok() {}
+            });
+            rx.recv();
+            drop(rx);
+        }
+    })
 }
 
 #[cfg(test)]
@@ -15,10 +15,19 @@
 /// this type is to have one and exactly one allocation when the chan/port pair
 /// is created.
 ///
-/// Another possible optimization would be to not use an Arc box because
-/// in theory we know when the shared packet can be deallocated (no real need
-/// for the atomic reference counting), but I was having trouble how to destroy
-/// the data early in a drop of a Port.
+/// Note that the Arc around the packet is that one allocation: the reference
+/// counts live in the same heap block as the packet itself, so there is no
+/// separate box to eliminate. In theory we know when the shared packet can be
+/// deallocated (both halves have been dropped, which the state word already
+/// tracks), so the atomic reference counting could be replaced with a manual
+/// two-party scheme. That would save two words per channel and one atomic
+/// decrement per half, but not an allocation, and the packet must outlive the
+/// port whenever the channel has been upgraded, which is what complicates
+/// destroying the data early in a drop of a Port.
+///
+/// Where even that one allocation is too many, `OneshotSlot` (in `inplace`)
+/// embeds a packet in storage owned by the caller and manages the lifetimes of
+/// the two halves itself.
 ///
 /// # Implementation
 ///
@@ -31,30 +40,71 @@
 /// consuming the port). This upgrade is then also stored in the shared packet.
 /// The one caveat to consider is that when a port sees a disconnected channel
 /// it must check for data because there is no "data plus upgrade" state.
+///
+/// ## Inline data
+///
+/// Values which are smaller than a word (small integers, `bool`, `()`, etc.)
+/// are stored directly in the state word instead of the `data` field. The low
+/// two bits of the state word tell its contents apart:
+///
+/// * `EMPTY`, `DATA` and `DISCONNECTED` are the words 0, 1 and 2.
+/// * A blocked task is a pointer to a box, which is at least word-aligned, so
+///   its low two bits are free for the tag of `BlockedTask::cast_to_uint`:
+///   `0b00` for a task which is owned, `0b01` for a shared handle, and `0b10`
+///   for a foreign waker (`FOREIGN_TAG`). None of these are 0, 1 or 2, as
+///   the pointer above the tag is never null.
+/// * Inline data is tagged with `INLINE_TAG`, both low bits set (`0b11`),
+///   which no blocked task uses. The value is shifted up past the two tag bits
+///   (`INLINE_SHIFT`), which is why only values smaller than a word fit.
+///
+/// Sending and receiving such a value is then a single
+/// atomic operation which both transfers the data and synchronizes with the
+/// other half. Whether a type is inlined is decided by its size, which is a
+/// constant after monomorphization, so the unused paths are optimized away.
+///
+/// Because an upgrade or a disconnect from the sender overwrites the state
+/// word, the sender first "spills" any inline data it sent into the `data`
+/// field, moving the channel to the DATA state. The rest of the protocol is
+/// then unchanged.
 
 use core::prelude::*;
 
 use alloc::boxed::Box;
+use core::fmt;
 use core::mem;
-use rustrt::local::Local;
-use rustrt::task::{Task, BlockedTask};
+use core::ptr;
+use rustrt::task;
+use rustrt::task::BlockedTask;
 
 use atomics;
 use comm::Receiver;
+use comm::discard;
+use comm::poll::Watch;
+use comm::stats::Stats;
 
 // Various states you can find a port in.
 static EMPTY: uint = 0;
 static DATA: uint = 1;
 static DISCONNECTED: uint = 2;
 
+// Tag in the low bits of the state word for inline data, see above
+static INLINE_TAG: uint = 3;
+static INLINE_SHIFT: uint = 2;
+
 pub struct Packet<T> {
     // Internal state of the chan/port pair (stores the blocked task as well)
     state: atomics::AtomicUint,
     // One-shot data slot location
     data: Option<T>,
+    // when the data was sent, if the channel measures its latencies
+    sent_at: u64,
     // when used for the second time, a oneshot channel must be upgraded, and
     // this contains the slot for the upgrade
     upgrade: MyUpgrade<T>,
+    // the poller watching the port, if any
+    watch: Watch,
+    // the counters of the channel, if it has them
+    pub stats: Stats,
 }
 
 pub enum Failure<T> {
@@ -85,24 +135,37 @@ impl<T: Send> Packet<T> {
     pub fn new() -> Packet<T> {
         Packet {
             data: None,
+            sent_at: 0,
             upgrade: NothingSent,
             state: atomics::AtomicUint::new(EMPTY),
+            watch: Watch::new(),
+            stats: Stats::off(),
         }
     }
 
-    pub fn send(&mut self, t: T) -> Result<(), T> {
+    // Sends the data, returning the task which was waiting for it (if any),
+    // which is for the caller to wake up.
+    pub fn send(&mut self, t: T) -> Result<Option<BlockedTask>, T> {
+        let ret = self.send_data(t);
+        self.watch.notify();
+        ret
+    }
+
+    fn send_data(&mut self, t: T) -> Result<Option<BlockedTask>, T> {
         // Sanity check
         match self.upgrade {
             NothingSent => {}
             _ => fail!("sending on a oneshot that's already sent on "),
         }
         assert!(self.data.is_none());
-        self.data = Some(t);
         self.upgrade = SendUsed;
+        self.sent_at = self.stats.stamp();
+        if is_inline::<T>() { return self.send_inline(t) }
+        self.data = Some(t);
 
         match self.state.swap(DATA, atomics::SeqCst) {
             // Sent the data, no one was waiting
-            EMPTY => Ok(()),
+            EMPTY => Ok(None),
 
             // Couldn't send the data, the port hung up first. Return the data
             // back up the stack.
@@ -117,13 +180,53 @@ impl<T: Send> Packet<T> {
             // end. We leave the 'DATA' state inside so it'll pick it up on the
             // other end.
             n => unsafe {
-                let t = BlockedTask::cast_from_uint(n);
-                t.wake().map(|t| t.reawaken());
-                Ok(())
+                self.stats.woke();
+                Ok(Some(BlockedTask::cast_from_uint(n)))
             }
         }
     }
 
+    fn send_inline(&mut self, t: T) -> Result<Option<BlockedTask>, T> {
+        match self.state.swap(unsafe { encode(t) }, atomics::SeqCst) {
+            EMPTY => Ok(None),
+
+            // The port hung up first, so take the data back out and restore
+            // the disconnected state (no one else will look at it).
+            DISCONNECTED => {
+                let s = self.state.swap(DISCONNECTED, atomics::SeqCst);
+                Err(unsafe { decode(s) })
+            }
+
+            DATA => unreachable!(),
+            s if is_inline_state(s) => unreachable!(),
+
+            n => unsafe {
+                self.stats.woke();
+                Ok(Some(BlockedTask::cast_from_uint(n)))
+            }
+        }
+    }
+
+    // Moves inline data which hasn't been received yet out of the state word
+    // and into the data slot, so that the state word can be overwritten by an
+    // upgrade or a disconnect. This is only called by the sender.
+    fn spill(&mut self) {
+        if !is_inline::<T>() { return }
+        loop {
+            let s = self.state.load(atomics::SeqCst);
+            if !is_inline_state(s) { return }
+
+            // The data has to be in place before the DATA state is visible.
+            // If the receiver takes (or the port drops) the inline copy in the
+            // meantime, then our copy must be forgotten instead.
+            self.data = Some(unsafe { decode(s) });
+            if self.state.compare_and_swap(s, DATA, atomics::SeqCst) == s {
+                return
+            }
+            unsafe { mem::forget(self.data.take()) }
+        }
+    }
+
     // Just tests whether this channel has been sent on or not, this is only
     // safe to use from the sender.
     pub fn sent(&self) -> bool {
@@ -133,13 +236,21 @@ impl<T: Send> Packet<T> {
         }
     }
 
+    // Tests whether the port has gone away. Upgrading also disconnects the
+    // channel, so this is only meaningful from a sender which hasn't upgraded.
+    pub fn port_dropped(&self) -> bool {
+        self.state.load(atomics::Acquire) == DISCONNECTED
+    }
+
     pub fn recv(&mut self) -> Result<T, Failure<T>> {
         // Attempt to not block the task (it's a little expensive). If it looks
         // like we're not empty, then immediately go through to `try_recv`.
         if self.state.load(atomics::SeqCst) == EMPTY {
-            let t: Box<Task> = Local::take();
-            t.deschedule(1, |task| {
+            let _waiting = self.stats.blocking();
+            task::deschedule_current(1, |task| {
                 let n = unsafe { task.cast_to_uint() };
+                // Counted before the task is published, see `Stats::blocked`
+                self.stats.blocked();
                 match self.state.compare_and_swap(EMPTY, n, atomics::SeqCst) {
                     // Nothing on the channel, we legitimately block
                     EMPTY => Ok(()),
@@ -147,6 +258,11 @@ impl<T: Send> Packet<T> {
                     // If there's data or it's a disconnected channel, then we
                     // failed the cmpxchg, so we just wake ourselves back up
                     DATA | DISCONNECTED => {
+                        self.stats.unblocked();
+                        unsafe { Err(BlockedTask::cast_from_uint(n)) }
+                    }
+                    s if is_inline_state(s) => {
+                        self.stats.unblocked();
                         unsafe { Err(BlockedTask::cast_from_uint(n)) }
                     }
 
@@ -163,6 +279,17 @@ impl<T: Send> Packet<T> {
         match self.state.load(atomics::SeqCst) {
             EMPTY => Err(Empty),
 
+            // Inline data is taken with a cmpxchg as the sender may be
+            // spilling it into the data slot, in which case we try again.
+            s if is_inline_state(s) => {
+                if self.state.compare_and_swap(s, EMPTY, atomics::SeqCst) == s {
+                    self.stats.arrived(self.sent_at);
+                    Ok(unsafe { decode(s) })
+                } else {
+                    self.try_recv()
+                }
+            }
+
             // We saw some data on the channel, but the channel can be used
             // again to send us an upgrade. As a result, we need to re-insert
             // into the channel that there's no data available (otherwise we'll
@@ -172,7 +299,7 @@ impl<T: Send> Packet<T> {
             DATA => {
                 self.state.compare_and_swap(DATA, EMPTY, atomics::SeqCst);
                 match self.data.take() {
-                    Some(data) => Ok(data),
+                    Some(data) => { self.stats.arrived(self.sent_at); Ok(data) }
                     None => unreachable!(),
                 }
             }
@@ -183,7 +310,7 @@ impl<T: Send> Packet<T> {
             // we go through and process the upgrade.
             DISCONNECTED => {
                 match self.data.take() {
-                    Some(data) => Ok(data),
+                    Some(data) => { self.stats.arrived(self.sent_at); Ok(data) }
                     None => {
                         match mem::replace(&mut self.upgrade, SendUsed) {
                             SendUsed | NothingSent => Err(Disconnected),
@@ -205,9 +332,10 @@ impl<T: Send> Packet<T> {
             SendUsed => SendUsed,
             _ => fail!("upgrading again"),
         };
+        self.spill();
         self.upgrade = GoUp(up);
 
-        match self.state.swap(DISCONNECTED, atomics::SeqCst) {
+        let ret = match self.state.swap(DISCONNECTED, atomics::SeqCst) {
             // If the channel is empty or has data on it, then we're good to go.
             // Senders will check the data before the upgrade (in case we
             // plastered over the DATA state).
@@ -219,10 +347,13 @@ impl<T: Send> Packet<T> {
 
             // If someone's waiting, we gotta wake them up
             n => UpWoke(unsafe { BlockedTask::cast_from_uint(n) })
-        }
+        };
+        self.watch.notify();
+        ret
     }
 
     pub fn drop_chan(&mut self) {
+        self.spill();
         match self.state.swap(DISCONNECTED, atomics::SeqCst) {
             DATA | DISCONNECTED | EMPTY => {}
 
@@ -232,6 +363,7 @@ impl<T: Send> Packet<T> {
                 t.wake().map(|t| t.reawaken());
             }
         }
+        self.watch.notify();
     }
 
     pub fn drop_port(&mut self) {
@@ -244,7 +376,14 @@ impl<T: Send> Packet<T> {
             // There's data on the channel, so make sure we destroy it promptly.
             // This is why not using an arc is a little difficult (need the box
             // to stay valid while we take the data).
-            DATA => { self.data.take_unwrap(); }
+            DATA => {
+                self.data.take_unwrap();
+                discard::discarded::<T>(&self.stats, 1);
+            }
+            s if is_inline_state(s) => {
+                unsafe { decode::<T>(s); }
+                discard::discarded::<T>(&self.stats, 1);
+            }
 
             // We're the only ones that can block on this port
             _ => unreachable!()
@@ -255,12 +394,15 @@ impl<T: Send> Packet<T> {
     // select implementation
     ////////////////////////////////////////////////////////////////////////////
 
+    pub fn watch<'a>(&'a self) -> &'a Watch { &self.watch }
+
     // If Ok, the value is whether this port has data, if Err, then the upgraded
     // port needs to be checked instead of this one.
     pub fn can_recv(&mut self) -> Result<bool, Receiver<T>> {
         match self.state.load(atomics::SeqCst) {
             EMPTY => Ok(false), // Welp, we tried
             DATA => Ok(true),   // we have some un-acquired data
+            s if is_inline_state(s) => Ok(true),
             DISCONNECTED if self.data.is_some() => Ok(true), // we have data
             DISCONNECTED => {
                 match mem::replace(&mut self.upgrade, SendUsed) {
@@ -286,6 +428,9 @@ impl<T: Send> Packet<T> {
         match self.state.compare_and_swap(EMPTY, n, atomics::SeqCst) {
             EMPTY => SelSuccess,
             DATA => SelCanceled(unsafe { BlockedTask::cast_from_uint(n) }),
+            s if is_inline_state(s) => {
+                SelCanceled(unsafe { BlockedTask::cast_from_uint(n) })
+            }
             DISCONNECTED if self.data.is_some() => {
                 SelCanceled(unsafe { BlockedTask::cast_from_uint(n) })
             }
@@ -323,6 +468,7 @@ impl<T: Send> Packet<T> {
             s @ EMPTY |
             s @ DATA |
             s @ DISCONNECTED => s,
+            s if is_inline_state(s) => s,
 
             // If we've got a blocked task, then use an atomic to gain ownership
             // of it (may fail)
@@ -335,6 +481,7 @@ impl<T: Send> Packet<T> {
             EMPTY => unreachable!(),
             // our task used for select was stolen
             DATA => Ok(true),
+            s if is_inline_state(s) => Ok(true),
 
             // If the other end has hung up, then we have complete ownership
             // of the port. First, check if there was data waiting for us. This
@@ -364,6 +511,63 @@ impl<T: Send> Packet<T> {
     }
 }
 
+// Whether values of type T are stored in the state word. There must be room
+// for the tag bits above the value.
+#[inline]
+fn is_inline<T>() -> bool {
+    mem::size_of::<T>() < mem::size_of::<uint>()
+}
+
+#[inline]
+fn is_inline_state(s: uint) -> bool { s & INLINE_TAG == INLINE_TAG }
+
+// The value is placed in the least significant bytes of the word, leaving the
+// most significant byte free for the shift.
+#[inline]
+fn inline_offset<T>() -> int {
+    if cfg!(target_endian = "big") {
+        (mem::size_of::<uint>() - mem::size_of::<T>()) as int
+    } else {
+        0
+    }
+}
+
+// Moves `t` into a tagged state word
+unsafe fn encode<T>(t: T) -> uint {
+    let mut raw = 0u;
+    let dst = (&mut raw as *mut uint as *mut u8).offset(inline_offset::<T>());
+    ptr::write(dst as *mut T, t);
+    (raw << INLINE_SHIFT) | INLINE_TAG
+}
+
+// Moves a value out of a tagged state word. Each encoded value must be decoded
+// exactly once.
+unsafe fn decode<T>(s: uint) -> T {
+    let raw = s >> INLINE_SHIFT;
+    let src = (&raw as *const uint as *const u8).offset(inline_offset::<T>());
+    ptr::read(src as *const T)
+}
+
+// The state word is decoded, along with whether the channel has been used
+// (or upgraded)
+impl<T: Send> fmt::Show for Packet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "oneshot packet: "));
+        match self.state.load(atomics::SeqCst) {
+            EMPTY => try!(write!(f, "EMPTY")),
+            DATA => try!(write!(f, "DATA")),
+            DISCONNECTED => try!(write!(f, "DISCONNECTED")),
+            n if n & INLINE_TAG == INLINE_TAG => try!(write!(f, "DATA (inline)")),
+            n => try!(write!(f, "blocked task {:#x}", n)),
+        }
+        match self.upgrade {
+            NothingSent => write!(f, ", nothing sent"),
+            SendUsed => write!(f, ", sent"),
+            GoUp(..) => write!(f, ", upgraded"),
+        }
+    }
+}
+
 #[unsafe_destructor]
 impl<T: Send> Drop for Packet<T> {
     fn drop(&mut self) {
@@ -0,0 +1,393 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pipelines of tasks connected by channels
+//!
+//! A pipeline is a chain of stages, each of which is run by one or more
+//! tasks. Every task of a stage receives its messages from a port and sends
+//! its results on a channel into the next stage. The pipeline is described
+//! with the `pipeline` builder, which spawns all of the tasks and connects
+//! them once `build` is called.
+//!
+//! Messages flow through a stage until its input is disconnected, so a
+//! pipeline shuts down from front to back: once the sender into the pipeline
+//! has been dropped, or `Control::shutdown` has been called, each stage
+//! finishes the messages it has been sent, and then its tasks exit.
+//!
+//! # Example
+//!
+//! ```
+//! use std::comm::{pipeline, Stage};
+//!
+//! let (tx, rx, control) = pipeline::<int>()
+//!     .stage(Stage::new(proc(rx: Receiver<int>, tx: Sender<int>) {
+//!         for i in rx.iter() { tx.send(i * 2) }
+//!     }))
+//!     .stage(Stage::workers(4, |_| proc(rx: Receiver<int>, tx: Sender<String>) {
+//!         for i in rx.iter() { tx.send(i.to_string()) }
+//!     }).bounded(16))
+//!     .build();
+//!
+//! for i in range(0i, 10) { tx.send(i); }
+//! drop(tx);
+//!
+//! let mut results: Vec<String> = rx.iter().collect();
+//! results.sort();
+//! control.join();
+//! ```
+
+#![experimental]
+
+use core::prelude::*;
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use collections::{Vec, MutableSeq};
+use rustrt::local::Local;
+use rustrt::task::{Task, TaskOpts};
+
+use comm::{Sender, Receiver, Select, channel, channel_with_watermarks};
+use lock::Mutex;
+
+/// The kind of channel connecting a stage to the one before it.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum Link {
+    /// An asynchronous channel, which buffers as many messages as are sent.
+    Unbounded,
+    /// A channel which holds about this many messages. Once it's full, the
+    /// previous stage blocks in `send` until the stage has caught up.
+    Bounded(uint),
+}
+
+impl Link {
+    fn channel<T: Send>(self) -> (Sender<T>, Receiver<T>) {
+        match self {
+            Unbounded => channel(),
+            Bounded(n) => channel_with_watermarks(n, n / 2),
+        }
+    }
+}
+
+/// A stage of a pipeline, receiving messages of type `A` and sending messages
+/// of type `B` to the next stage.
+pub struct Stage<A, B> {
+    workers: Vec<proc(Receiver<A>, Sender<B>):Send>,
+    link: Link,
+}
+
+impl<A: Send, B: Send> Stage<A, B> {
+    /// Creates a stage which is run by a single task, running `f`.
+    ///
+    /// The stage is connected to the one before it with an unbounded channel.
+    pub fn new(f: proc(Receiver<A>, Sender<B>):Send) -> Stage<A, B> {
+        Stage { workers: vec![f], link: Unbounded }
+    }
+
+    /// Creates a stage which is run by `n` tasks, each running the proc which
+    /// `f` returns for its index.
+    ///
+    /// The messages sent into the stage are handed to its tasks as they ask
+    /// for them, so a busy task doesn't hold back the others. The order of
+    /// the messages sent out by the stage is therefore unspecified.
+    ///
+    /// # Failure
+    ///
+    /// This function fails if `n` is 0.
+    pub fn workers(n: uint,
+                   f: |uint| -> proc(Receiver<A>, Sender<B>):Send) -> Stage<A, B> {
+        assert!(n > 0, "a stage needs at least one worker");
+        Stage { workers: range(0, n).map(f).collect(), link: Unbounded }
+    }
+
+    /// Connects this stage to the one before it with `link`.
+    pub fn link(mut self, link: Link) -> Stage<A, B> {
+        self.link = link;
+        self
+    }
+
+    /// Connects this stage to the one before it with a channel holding about
+    /// `capacity` messages.
+    ///
+    /// # Failure
+    ///
+    /// This function fails if `capacity` is 0.
+    pub fn bounded(self, capacity: uint) -> Stage<A, B> {
+        assert!(capacity > 0, "a bounded stage needs some capacity");
+        self.link(Bounded(capacity))
+    }
+
+    fn spawn(self, rx: Receiver<A>, tx: Sender<B>, done: Sender<()>) {
+        let Stage { mut workers, .. } = self;
+        if workers.len() == 1 {
+            let f = workers.pop().unwrap();
+            spawn(proc() { let _done = done; f(rx, tx) });
+            return
+        }
+
+        // The port can only be used by one task at a time, so each worker is
+        // fed by a task which takes a message from it whenever the worker has
+        // finished with the last one.
+        let rx = Arc::new(Mutex::new(rx));
+        for f in workers.move_iter() {
+            let (wtx, wrx) = channel_with_watermarks(1, 0);
+            let (rx, d) = (rx.clone(), done.clone());
+            spawn(proc() { let _done = d; feed(rx, wtx) });
+            let (tx, d) = (tx.clone(), done.clone());
+            spawn(proc() { let _done = d; f(wrx, tx) });
+        }
+    }
+}
+
+/// Builds a pipeline which is sent messages of type `I`, and whose last stage
+/// sends messages of type `O`.
+pub struct Pipeline<I, O> {
+    // Spawns the stages built so far, given the link into the next stage,
+    // returning the sender into the pipeline and the port of the next stage.
+    start: proc(Link, Sender<()>):Send -> (Sender<I>, Receiver<O>),
+    stop: Sender<()>,
+    done: Sender<()>,
+    finished: Receiver<()>,
+}
+
+/// Controls a running pipeline.
+pub struct Control {
+    stop: Sender<()>,
+    finished: Receiver<()>,
+}
+
+/// Creates a builder for a pipeline which is sent messages of type `T`.
+///
+/// The pipeline has no stages until some are added with `stage`, and a
+/// pipeline without any just sends back what it is sent.
+pub fn pipeline<T: Send>() -> Pipeline<T, T> {
+    let (stop, stopped) = channel();
+    let (done, finished) = channel();
+    Pipeline {
+        start: proc(link, done) {
+            // The sender into the pipeline has the first stage's link, and
+            // the gate hands on one message at a time so as not to double it
+            let (input, rx) = link.channel();
+            let (tx, output) = match link {
+                Unbounded => channel(),
+                Bounded(..) => channel_with_watermarks(1, 0),
+            };
+            spawn(proc() { let _done = done; gate(rx, stopped, tx) });
+            (input, output)
+        },
+        stop: stop,
+        done: done,
+        finished: finished,
+    }
+}
+
+impl<I: Send, O: Send> Pipeline<I, O> {
+    /// Adds `stage` to the end of the pipeline.
+    pub fn stage<P: Send>(self, stage: Stage<O, P>) -> Pipeline<I, P> {
+        let Pipeline { start, stop, done, finished } = self;
+        Pipeline {
+            start: proc(link, done) {
+                let (input, rx) = start(stage.link, done.clone());
+                let (tx, output) = link.channel();
+                stage.spawn(rx, tx, done);
+                (input, output)
+            },
+            stop: stop,
+            done: done,
+            finished: finished,
+        }
+    }
+
+    /// Spawns the tasks of the pipeline, returning the sender into its first
+    /// stage, and the port of messages from its last. The port is sent on by
+    /// an unbounded channel.
+    pub fn build(self) -> (Sender<I>, Receiver<O>, Control) {
+        let Pipeline { start, stop, done, finished } = self;
+        let (input, output) = start(Unbounded, done);
+        (input, output, Control { stop: stop, finished: finished })
+    }
+}
+
+impl Control {
+    /// Stops the pipeline from taking any more messages. The messages which
+    /// have been sent but not yet taken by the first stage are dropped, and
+    /// sending to the pipeline fails from now on.
+    ///
+    /// The stages aren't interrupted: the messages they have been sent are
+    /// still processed, and sent on, before their tasks exit.
+    pub fn shutdown(&self) {
+        let _ = self.stop.send_opt(());
+    }
+
+    /// Blocks until all of the tasks of the pipeline have exited.
+    ///
+    /// This doesn't stop the pipeline by itself, so it only returns once the
+    /// senders into the pipeline have been dropped, or `shutdown` has been
+    /// called.
+    pub fn join(self) {
+        let Control { stop, finished } = self;
+        drop(stop);
+        let _ = finished.recv_opt();
+    }
+}
+
+// Forwards the messages sent into the pipeline to the first stage, until
+// the pipeline is shut down.
+fn gate<T: Send>(input: Receiver<T>, stopped: Receiver<()>, tx: Sender<T>) {
+    {
+        let sel = Select::new();
+        let mut msg = sel.handle(&input);
+        let mut stop = sel.handle(&stopped);
+        unsafe { msg.add(); stop.add(); }
+        loop {
+            let ret = sel.wait();
+            if ret == stop.id() {
+                match stop.recv_opt() {
+                    Ok(()) => return,
+                    // The control is gone, and can't stop anything now
+                    Err(()) => break,
+                }
+            }
+            match msg.recv_opt() {
+                Ok(m) => if tx.send_opt(m).is_err() { return },
+                Err(()) => return,
+            }
+        }
+    }
+    for m in input.iter() {
+        if tx.send_opt(m).is_err() { return }
+    }
+}
+
+// Takes messages from the port shared by the workers of a stage for one of
+// them.
+fn feed<T: Send>(rx: Arc<Mutex<Receiver<T>>>, tx: Sender<T>) {
+    loop {
+        let msg = match rx.lock().recv_opt() {
+            Ok(m) => m,
+            Err(()) => return,
+        };
+        if tx.send_opt(msg).is_err() { return }
+    }
+}
+
+fn spawn(f: proc():Send) {
+    let task: Box<Task> = Local::take();
+    task.spawn_sibling(TaskOpts::new(), f);
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use super::{pipeline, Stage, Bounded};
+
+    fn double(rx: Receiver<int>, tx: Sender<int>) {
+        for i in rx.iter() { tx.send(i * 2) }
+    }
+
+    #[test]
+    fn no_stages() {
+        let (tx, rx, control) = pipeline::<int>().build();
+        tx.send(1);
+        tx.send(2);
+        drop(tx);
+        assert_eq!(rx.iter().collect::<Vec<int>>(), vec![1, 2]);
+        control.join();
+    }
+
+    #[test]
+    fn stages() {
+        let (tx, rx, control) = pipeline::<int>()
+            .stage(Stage::new(proc(rx, tx) double(rx, tx)))
+            .stage(Stage::new(proc(rx: Receiver<int>, tx: Sender<String>) {
+                for i in rx.iter() { tx.send(i.to_string()) }
+            }))
+            .build();
+        for i in range(0i, 5) { tx.send(i); }
+        drop(tx);
+        let results: Vec<String> = rx.iter().collect();
+        assert_eq!(results, vec!["0".to_string(), "2".to_string(),
+                                 "4".to_string(), "6".to_string(),
+                                 "8".to_string()]);
+        control.join();
+    }
+
+    #[test]
+    fn workers() {
+        let (tx, rx, control) = pipeline::<int>()
+            .stage(Stage::workers(4, |_| proc(rx, tx) double(rx, tx)))
+            .stage(Stage::workers(3, |_| proc(rx, tx) double(rx, tx)).bounded(2))
+            .build();
+        for i in range(0i, 100) { tx.send(i); }
+        drop(tx);
+        let mut results: Vec<int> = rx.iter().collect();
+        results.sort();
+        assert_eq!(results, range(0i, 100).map(|i| i * 4).collect());
+        control.join();
+    }
+
+    #[test]
+    fn worker_indices() {
+        let (tx, rx, control) = pipeline::<()>()
+            .stage(Stage::workers(3, |i| proc(rx: Receiver<()>, tx: Sender<uint>) {
+                for () in rx.iter() {}
+                tx.send(i)
+            }))
+            .build();
+        drop(tx);
+        let mut indices: Vec<uint> = rx.iter().collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 1, 2]);
+        control.join();
+    }
+
+    #[test]
+    fn bounded() {
+        let (tx, rx, control) = pipeline::<int>()
+            .stage(Stage::new(proc(rx, tx) double(rx, tx)).link(Bounded(1)))
+            .build();
+        for i in range(0i, 10) { tx.send(i); }
+        drop(tx);
+        assert_eq!(rx.iter().collect::<Vec<int>>(),
+                   range(0i, 10).map(|i| i * 2).collect());
+        control.join();
+    }
+
+    #[test]
+    fn shutdown() {
+        let (tx, rx, control) = pipeline::<int>()
+            .stage(Stage::new(proc(rx, tx) double(rx, tx)))
+            .build();
+        tx.send(1);
+        assert_eq!(rx.recv(), 2);
+        control.shutdown();
+        control.join();
+        assert_eq!(rx.recv_opt(), Err(()));
+        assert!(tx.send_opt(3).is_err());
+    }
+
+    #[test]
+    fn failed_worker() {
+        let (tx, rx, control) = pipeline::<int>()
+            .stage(Stage::new(proc(rx: Receiver<int>, _tx: Sender<int>) {
+                rx.recv();
+                fail!()
+            }))
+            .build();
+        tx.send(1);
+        assert_eq!(rx.recv_opt(), Err(()));
+        control.join();
+    }
+
+    #[test] #[should_fail]
+    fn no_workers() {
+        let _ = Stage::workers(0, |_| proc(rx, tx) double(rx, tx));
+    }
+}
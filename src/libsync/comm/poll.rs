@@ -0,0 +1,600 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Readiness-based selection over large numbers of receivers
+//!
+//! Every call to `Select::wait` registers a blocking context with each of the
+//! receivers in the set and then unregisters it again, so a wakeup costs time
+//! proportional to the number of receivers being selected over. That is fine
+//! for a handful of receivers, but not for a server with thousands of
+//! connections which each own a receiver.
+//!
+//! A `Poller` instead registers each receiver once. Every packet has a `Watch`
+//! slot which points back at the poller, and senders flag their receiver as
+//! ready in a bitmap whenever they send data, upgrade the channel, or hang up.
+//! The bitmap has two levels: one bit per receiver, and a summary bit for each
+//! word of receiver bits. A poller therefore only looks at the words which
+//! contain ready receivers, and then only at the ready receivers within them.
+//!
+//! Readiness is level-triggered. A receiver's bit is only cleared once the
+//! poller has seen that the receiver has nothing to receive, so a receiver
+//! which still has data (or which was never received from) will be returned
+//! again by the next wait.
+//!
+//! # Upgrades
+//!
+//! A channel's packet may be swapped out from under a receiver when the
+//! channel is upgraded. The old packet is always flagged when this happens, and
+//! the poller registers the receiver again with its current packet every time
+//! it examines it, before it decides that the receiver isn't ready.
+
+use core::prelude::*;
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use collections::Vec;
+use collections::Collection;
+use core::cell::UnsafeCell;
+use core::kinds::marker;
+use core::mem;
+use core::uint;
+use rustrt::task;
+use rustrt::task::BlockedTask;
+use rustrt::thread::Thread;
+
+use atomics::{AtomicBool, AtomicUint, Acquire, Release, SeqCst};
+use comm::Receiver;
+use comm::select::Packet;
+
+/// A set of receivers which can be waited on for readiness.
+///
+/// Receivers are added once and stay in the poller until they're removed or
+/// the poller is dropped, and each one is identified by the token returned
+/// when it was added. Waiting on the poller costs time proportional to the
+/// number of ready receivers, not to the number of receivers in the set.
+///
+/// A receiver can be in at most one poller at a time.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::Poller;
+///
+/// let (tx1, rx1) = channel();
+/// let (tx2, rx2) = channel();
+///
+/// let mut poller = Poller::new(2);
+/// let t1 = poller.add(&rx1);
+/// let t2 = poller.add(&rx2);
+///
+/// tx2.send(2i);
+/// assert_eq!(poller.wait(), t2);
+/// assert_eq!(rx2.recv(), 2);
+///
+/// tx1.send(1i);
+/// assert_eq!(poller.wait(), t1);
+/// assert_eq!(rx1.recv(), 1);
+/// ```
+#[experimental]
+pub struct Poller<'rx> {
+    set: Arc<ReadySet>,
+    rxs: Vec<Option<&'rx Packet>>,
+    free: Vec<uint>,
+    cap: uint,
+    len: uint,
+    marker: marker::NoSend,
+}
+
+// Readiness bits shared between a poller and the packets it watches
+struct ReadySet {
+    summary: Vec<AtomicUint>, // bit i is set if words[i] may be nonzero
+    words: Vec<AtomicUint>,   // one bit per token
+    to_wake: AtomicUint,      // the blocked poller, if any
+}
+
+/// A registration of a receiver with a poller, stored in its packet.
+#[doc(hidden)]
+#[deriving(Clone)]
+pub struct Watcher {
+    set: Arc<ReadySet>,
+    token: uint,
+}
+
+/// The slot which links a packet to the poller watching its receiver.
+///
+/// The slot is written by the receiver and read by the senders. Senders check
+/// the `watched` flag first, so channels which aren't being polled only pay
+/// for a load on every send.
+#[doc(hidden)]
+pub struct Watch {
+    watched: AtomicBool,
+    lock: AtomicBool,
+    watcher: UnsafeCell<Option<Watcher>>,
+}
+
+impl<'rx> Poller<'rx> {
+    /// Creates a new poller which can hold up to `capacity` receivers.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if `capacity` is 0.
+    pub fn new(capacity: uint) -> Poller<'rx> {
+        assert!(capacity > 0, "a poller must be able to hold a receiver");
+        let words = (capacity + uint::BITS - 1) / uint::BITS;
+        let summary = (words + uint::BITS - 1) / uint::BITS;
+        Poller {
+            set: Arc::new(ReadySet {
+                summary: Vec::from_fn(summary, |_| AtomicUint::new(0)),
+                words: Vec::from_fn(words, |_| AtomicUint::new(0)),
+                to_wake: AtomicUint::new(0),
+            }),
+            rxs: Vec::new(),
+            free: Vec::new(),
+            cap: capacity,
+            len: 0,
+            marker: marker::NoSend,
+        }
+    }
+
+    /// Adds a receiver to this poller, returning the token which `wait` will
+    /// return when the receiver is ready.
+    ///
+    /// Tokens are less than the poller's capacity, and the tokens of removed
+    /// receivers are reused.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if the poller is full, or if the receiver is
+    /// already in a poller.
+    pub fn add<T: Send>(&mut self, rx: &'rx Receiver<T>) -> uint {
+        assert!(self.len < self.cap, "poller is full");
+        let token = match self.free.pop() {
+            Some(token) => token,
+            None => { self.rxs.push(None); self.rxs.len() - 1 }
+        };
+        let watcher = Watcher { set: self.set.clone(), token: token };
+        if rx.watch(Some(watcher)) {
+            fail!("receiver is already in a poller");
+        }
+        *self.rxs.get_mut(token) = Some(rx as &'rx Packet);
+        self.len += 1;
+
+        // Anything sent before the receiver was registered hasn't flagged it,
+        // so the first wait has to take a look at it regardless.
+        self.set.flag(token);
+        token
+    }
+
+    /// Removes the receiver with the given token from this poller.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if no receiver has the given token.
+    pub fn remove(&mut self, token: uint) {
+        let rx = if token < self.rxs.len() {
+            mem::replace(self.rxs.get_mut(token), None)
+        } else {
+            None
+        };
+        match rx {
+            Some(rx) => { rx.watch(None); }
+            None => fail!("no receiver with token {}", token),
+        }
+        self.free.push(token);
+        self.len -= 1;
+    }
+
+    /// Returns the number of receivers in this poller.
+    pub fn len(&self) -> uint { self.len }
+
+    /// Returns the token of a receiver which is ready, if there is one, without
+    /// blocking.
+    ///
+    /// A receiver is ready when receiving from it will not block, either
+    /// because it has data or because its channel has hung up.
+    pub fn poll(&mut self) -> Option<uint> {
+        for (i, summary) in self.set.summary.iter().enumerate() {
+            let mut bits = summary.load(SeqCst);
+            while bits != 0 {
+                let bit = bits.trailing_zeros();
+                bits &= !(1 << bit);
+                match self.poll_word(i * uint::BITS + bit) {
+                    Some(token) => return Some(token),
+                    None => {}
+                }
+            }
+        }
+        None
+    }
+
+    /// Blocks until one of the receivers in this poller is ready, returning its
+    /// token.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if the poller is empty.
+    pub fn wait(&mut self) -> uint {
+        assert!(self.len > 0, "waiting on an empty poller");
+        loop {
+            match self.poll() {
+                Some(token) => return token,
+                None => {}
+            }
+
+            let set = &*self.set;
+            task::deschedule_current(1, |task| set.publish(task));
+        }
+    }
+
+    /// Arranges for `task` to be woken up once one of the receivers in this
+    /// poller may be ready, without blocking. If one already is, the task is
+    /// handed back right away.
+    ///
+    /// This is for waiting on a poller along with other things, such as with
+    /// a `BlockedTask` from `BlockedTask::foreign`. A task armed this way
+    /// replaces the one which was armed before, if it wasn't woken up yet.
+    pub fn arm(&mut self, task: BlockedTask) -> Result<(), BlockedTask> {
+        match self.poll() {
+            Some(..) => Err(task),
+            None => self.set.publish(task),
+        }
+    }
+
+    // Looks for a ready receiver among the flagged ones in the given word of
+    // the bitmap, clearing the flags of those which aren't ready.
+    fn poll_word(&self, word: uint) -> Option<uint> {
+        let bits = self.set.words.get(word);
+        let mut ready = bits.load(SeqCst);
+        while ready != 0 {
+            let bit = ready.trailing_zeros();
+            ready &= !(1 << bit);
+            let token = word * uint::BITS + bit;
+
+            // A sender may flag the receiver again between the check and
+            // clearing the flag, in which case the flag it set is lost, so
+            // check once more after clearing it.
+            if self.check(token) { return Some(token) }
+            bits.fetch_and(!(1 << bit), SeqCst);
+            if self.check(token) {
+                self.set.flag(token);
+                return Some(token)
+            }
+        }
+
+        // The summary bit can only be cleared once the word is empty, and it
+        // must be set again if a sender raced with us.
+        if bits.load(SeqCst) == 0 {
+            let summary = self.set.summary.get(word / uint::BITS);
+            let bit = 1 << (word % uint::BITS);
+            summary.fetch_and(!bit, SeqCst);
+            if bits.load(SeqCst) != 0 {
+                summary.fetch_or(bit, SeqCst);
+            }
+        }
+        None
+    }
+
+    // Tests whether a receiver is ready, following any upgrades of its channel
+    // and registering it with the packet it ends up on.
+    fn check(&self, token: uint) -> bool {
+        let rx = match self.rxs.as_slice().get(token) {
+            Some(&Some(rx)) => rx,
+            _ => return false,
+        };
+        let ready = rx.can_recv();
+        rx.watch(Some(Watcher { set: self.set.clone(), token: token }));
+        ready
+    }
+}
+
+#[unsafe_destructor]
+impl<'rx> Drop for Poller<'rx> {
+    fn drop(&mut self) {
+        for rx in self.rxs.iter() {
+            match *rx {
+                Some(rx) => { rx.watch(None); }
+                None => {}
+            }
+        }
+        // A task armed with `arm` may never have been woken up
+        match self.set.to_wake.swap(0, SeqCst) {
+            0 => {}
+            n => drop(unsafe { BlockedTask::cast_from_uint(n) }),
+        }
+    }
+}
+
+impl ReadySet {
+    // Publishes the task waiting on this set and then looks for flags again.
+    // A sender flags its receiver before it looks for the task, so one of
+    // them is guaranteed to notice the other.
+    fn publish(&self, task: BlockedTask) -> Result<(), BlockedTask> {
+        let n = unsafe { task.cast_to_uint() };
+        match self.to_wake.swap(n, SeqCst) {
+            0 => {}
+            old => drop(unsafe { BlockedTask::cast_from_uint(old) }),
+        }
+        if !self.is_flagged() { return Ok(()) }
+        match self.to_wake.swap(0, SeqCst) {
+            // A sender took the task, and it will wake it up
+            0 => Ok(()),
+            m => {
+                assert_eq!(m, n);
+                Err(unsafe { BlockedTask::cast_from_uint(m) })
+            }
+        }
+    }
+
+    // Flags a token as possibly being ready, without waking up the poller
+    fn flag(&self, token: uint) {
+        let word = token / uint::BITS;
+        let bit = 1 << (token % uint::BITS);
+        if self.words.get(word).fetch_or(bit, SeqCst) & bit == 0 {
+            let summary = self.summary.get(word / uint::BITS);
+            summary.fetch_or(1 << (word % uint::BITS), SeqCst);
+        }
+    }
+
+    fn is_flagged(&self) -> bool {
+        self.summary.iter().any(|s| s.load(SeqCst) != 0)
+    }
+
+    // Flags a token, returning the poller's task if it needs to be woken up
+    fn mark(&self, token: uint) -> Option<BlockedTask> {
+        self.flag(token);
+        if self.to_wake.load(SeqCst) == 0 { return None }
+        match self.to_wake.swap(0, SeqCst) {
+            0 => None,
+            n => Some(unsafe { BlockedTask::cast_from_uint(n) }),
+        }
+    }
+}
+
+impl Watch {
+    pub fn new() -> Watch {
+        Watch {
+            watched: AtomicBool::new(false),
+            lock: AtomicBool::new(false),
+            watcher: UnsafeCell::new(None),
+        }
+    }
+
+    // Whether a poller is watching this packet's receiver
+    pub fn watched(&self) -> bool { self.watched.load(SeqCst) }
+
+    // Registers (or unregisters) the receiver with a poller, returning whether
+    // it was previously registered.
+    pub fn set(&self, watcher: Option<Watcher>) -> bool {
+        let watched = watcher.is_some();
+        self.acquire();
+        let prev = unsafe { mem::replace(&mut *self.watcher.get(), watcher) };
+        self.watched.store(watched, SeqCst);
+        self.release();
+        prev.is_some()
+    }
+
+    // Flags the receiver as ready, returning the poller's task if it needs to
+    // be woken up. This is for callers which have to drop a lock first.
+    pub fn mark(&self) -> Option<BlockedTask> {
+        if !self.watched() { return None }
+        self.acquire();
+        let task = match unsafe { &*self.watcher.get() } {
+            &Some(ref w) => w.set.mark(w.token),
+            &None => None,
+        };
+        self.release();
+        task
+    }
+
+    // Flags the receiver as ready and wakes up its poller if necessary. The
+    // poller is never switched to immediately, as it may well have to look
+    // at other receivers first.
+    pub fn notify(&self) {
+        match self.mark() {
+            Some(task) => task.reawaken_later(),
+            None => {}
+        }
+    }
+
+    // The lock only protects swapping the watcher out from under a sender
+    // which is using it, so it is never held for long.
+    fn acquire(&self) {
+        while self.lock.compare_and_swap(false, true, Acquire) {
+            Thread::yield_now();
+        }
+    }
+
+    fn release(&self) {
+        self.lock.store(false, Release);
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports)]
+mod test {
+    use std::prelude::*;
+
+    use super::super::*;
+    use comm::Poller;
+
+    test!(fn smoke() {
+        let (tx1, rx1) = channel::<int>();
+        let (tx2, rx2) = channel::<int>();
+        let mut poller = Poller::new(2);
+        let t1 = poller.add(&rx1);
+        let t2 = poller.add(&rx2);
+        assert!(poller.poll().is_none());
+        tx1.send(1);
+        assert_eq!(poller.wait(), t1);
+        assert_eq!(rx1.recv(), 1);
+        assert!(poller.poll().is_none());
+        tx2.send(2);
+        assert_eq!(poller.wait(), t2);
+        assert_eq!(rx2.recv(), 2);
+        drop(tx1);
+        assert_eq!(poller.wait(), t1);
+        assert_eq!(rx1.recv_opt(), Err(()));
+    })
+
+    test!(fn sent_before_add() {
+        let (tx, rx) = channel::<int>();
+        tx.send(1);
+        let mut poller = Poller::new(1);
+        let t = poller.add(&rx);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv(), 1);
+    })
+
+    test!(fn level_triggered() {
+        let (tx, rx) = channel::<int>();
+        let mut poller = Poller::new(1);
+        let t = poller.add(&rx);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv(), 2);
+        assert!(poller.poll().is_none());
+    })
+
+    test!(fn upgrades() {
+        let (tx, rx) = channel::<int>();
+        let mut poller = Poller::new(1);
+        let t = poller.add(&rx);
+        tx.send(1);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv(), 1);
+
+        // stream, and then shared
+        tx.send(2);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv(), 2);
+        let tx2 = tx.clone();
+        tx2.send(3);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv(), 3);
+        drop(tx);
+        assert!(poller.poll().is_none());
+        drop(tx2);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv_opt(), Err(()));
+    })
+
+    test!(fn upgrade_outside_poller() {
+        let (tx, rx) = channel::<int>();
+        let mut poller = Poller::new(1);
+        let t = poller.add(&rx);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        tx.send(3);
+        assert_eq!(poller.wait(), t);
+        assert_eq!(rx.recv(), 3);
+    })
+
+    test!(fn remove() {
+        let (tx1, rx1) = channel::<int>();
+        let (tx2, rx2) = channel::<int>();
+        let mut poller = Poller::new(1);
+        let t1 = poller.add(&rx1);
+        poller.remove(t1);
+        tx1.send(1);
+        assert!(poller.poll().is_none());
+        let t2 = poller.add(&rx2);
+        assert_eq!(t1, t2);
+        assert!(poller.poll().is_none());
+        tx2.send(2);
+        assert_eq!(poller.wait(), t2);
+        assert_eq!(poller.len(), 1);
+    })
+
+    test!(fn full() {
+        let (_tx1, rx1) = channel::<int>();
+        let (_tx2, rx2) = channel::<int>();
+        let mut poller = Poller::new(1);
+        poller.add(&rx1);
+        poller.add(&rx2);
+    } #[should_fail])
+
+    test!(fn twice() {
+        let (_tx, rx) = channel::<int>();
+        let mut poller1 = Poller::new(1);
+        let mut poller2 = Poller::new(1);
+        poller1.add(&rx);
+        poller2.add(&rx);
+    } #[should_fail])
+
+    test!(fn sync_channels() {
+        let (tx1, rx1) = sync_channel::<int>(1);
+        let (tx2, rx2) = sync_channel::<int>(0);
+        let mut poller = Poller::new(2);
+        let t1 = poller.add(&rx1);
+        let t2 = poller.add(&rx2);
+        tx1.send(1);
+        assert_eq!(poller.wait(), t1);
+        assert_eq!(rx1.recv(), 1);
+        spawn(proc() { tx2.send(2); });
+        assert_eq!(poller.wait(), t2);
+        assert_eq!(rx2.recv(), 2);
+        assert_eq!(poller.wait(), t2);
+        assert_eq!(rx2.recv_opt(), Err(()));
+    })
+
+    test!(fn many_receivers() {
+        let n = 2000u;
+        let mut txs = Vec::new();
+        let mut rxs = Vec::new();
+        for _ in range(0, n) {
+            let (tx, rx) = channel::<uint>();
+            txs.push(tx);
+            rxs.push(rx);
+        }
+        let mut poller = Poller::new(n);
+        for rx in rxs.iter() {
+            poller.add(rx);
+        }
+        for i in range(0, 3u) {
+            let token = (i * 997) % n;
+            txs.get(token).send(token);
+            assert_eq!(poller.wait(), token);
+            assert_eq!(rxs.get(token).recv(), token);
+            assert!(poller.poll().is_none());
+        }
+    })
+
+    test!(fn stress() {
+        let n = 100u;
+        let amt = 10u;
+        let mut rxs = Vec::new();
+        for _ in range(0, n) {
+            let (tx, rx) = channel::<uint>();
+            rxs.push(rx);
+            spawn(proc() {
+                for i in range(0, amt) { tx.send(i); }
+            });
+        }
+        let mut poller = Poller::new(n);
+        for rx in rxs.iter() {
+            poller.add(rx);
+        }
+        let mut live = n;
+        let mut got = 0u;
+        while live > 0 {
+            let token = poller.wait();
+            match rxs.get(token).recv_opt() {
+                Ok(..) => got += 1,
+                Err(()) => { poller.remove(token); live -= 1; }
+            }
+        }
+        assert_eq!(got, n * amt);
+    })
+}
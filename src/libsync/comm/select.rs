@@ -24,6 +24,14 @@
 //! received values of receivers in a much more natural syntax then usage of the
 //! `Select` structure directly.
 //!
+//! Every wait on a `Select` visits each of the receivers in the set, so for
+//! sets of thousands of receivers a `Poller` should be used instead.
+//!
+//! I/O can be selected over along with channels: the `watch_readable` methods
+//! of sockets in `std::io` (and `std::io::fd_watcher` for other descriptors)
+//! give a receiver of readiness events, which is fed by the event loop of the
+//! runtime rather than by a task dedicated to the socket.
+//!
 //! # Example
 //!
 //! ```rust
@@ -55,14 +63,17 @@
 use core::prelude::*;
 
 use alloc::boxed::Box;
+use collections::Vec;
 use core::cell::Cell;
 use core::kinds::marker;
 use core::mem;
 use core::uint;
-use rustrt::local::Local;
-use rustrt::task::{Task, BlockedTask};
+use rustrt::task;
+use rustrt::task::BlockedTask;
 
 use comm::Receiver;
+use comm::debug;
+use comm::poll::Watcher;
 
 /// The "receiver set" of the select interface. This structure is used to manage
 /// a set of receivers which are being selected over.
@@ -98,6 +109,11 @@ pub trait Packet {
     fn can_recv(&self) -> bool;
     fn start_selection(&self, task: BlockedTask) -> Result<(), BlockedTask>;
     fn abort_selection(&self) -> bool;
+    // Registers this receiver with a poller (or unregisters it), returning
+    // whether it was previously registered
+    fn watch(&self, watcher: Option<Watcher>) -> bool;
+    // The identifier of the channel, if it has statistics
+    fn channel(&self) -> Option<uint>;
 }
 
 impl Select {
@@ -178,11 +194,21 @@ impl Select {
             let mut ready_id = uint::MAX;
             let mut iter = self.iter().enumerate();
 
+            // While it's blocked, the task is listed as waiting on all of the
+            // receivers at once
+            let _waiting = if debug::enabled() {
+                let channels: Vec<uint> = self.iter().filter_map(|p| {
+                    (*p).packet.channel()
+                }).collect();
+                debug::waiting(channels.as_slice())
+            } else {
+                debug::Waiting::none()
+            };
+
             // Acquire a number of blocking contexts, and block on each one
             // sequentially until one fails. If one fails, then abort
             // immediately so we can go unblock on all the other receivers.
-            let task: Box<Task> = Local::take();
-            task.deschedule(amt, |task| {
+            task::deschedule_current(amt, |task| {
                 // Prepare for the block
                 let (i, handle) = iter.next().unwrap();
                 match (*handle).packet.start_selection(task) {
@@ -328,6 +354,7 @@ mod test {
     use std::prelude::*;
 
     use super::super::*;
+    use comm::test::stress_factor;
 
     // Don't use the libstd version so we can pull in the right Select structure
     // (std::comm points at the wrong one)
@@ -371,6 +398,53 @@ mod test {
         )
     })
 
+    test!(fn stress_select_wakeups() {
+        for _ in range(0u, stress_factor() + 10) {
+            let (tx1, rx1) = channel::<uint>();
+            let (tx2, rx2) = channel::<uint>();
+            tx1.send(0);
+            tx2.send(0);
+            rx1.recv();
+            rx2.recv();
+            let tx3 = tx2.clone();
+            spawn(proc() {
+                for i in range(0u, 100) { tx1.send(i); task::deschedule(); }
+            });
+            spawn(proc() {
+                for i in range(0u, 100) { tx3.send(i); task::deschedule(); }
+            });
+            drop(tx2);
+            let mut got = 0u;
+            while got < 200 {
+                select! {
+                    r = rx1.recv_opt() => if r.is_ok() { got += 1 },
+                    r = rx2.recv_opt() => if r.is_ok() { got += 1 }
+                }
+            }
+        }
+    } #[ignore(cfg(not(stress)))])
+
+    test!(fn shared_abort_races_senders() {
+        // Both ports are sent to while their selections are aborted, so
+        // senders find the tombstones of aborted selections
+        let (tx1, rx1) = channel::<uint>();
+        let (tx2, rx2) = channel::<uint>();
+        for tx in vec![tx1.clone(), tx1, tx2.clone(), tx2].move_iter() {
+            spawn(proc() {
+                for i in range(0u, 1000) { tx.send(i) }
+            });
+        }
+        let mut got = 0u;
+        while got < 4000 {
+            select! {
+                r = rx1.recv_opt() => if r.is_ok() { got += 1 },
+                r = rx2.recv_opt() => if r.is_ok() { got += 1 }
+            }
+        }
+        assert_eq!(rx1.recv_opt(), Err(()));
+        assert_eq!(rx2.recv_opt(), Err(()));
+    })
+
     test!(fn smoke2() {
         let (_tx1, rx1) = channel::<int>();
         let (_tx2, rx2) = channel::<int>();
@@ -21,17 +21,30 @@
 use core::prelude::*;
 
 use alloc::boxed::Box;
+use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 use core::int;
-use rustrt::local::Local;
 use rustrt::mutex::NativeMutex;
-use rustrt::task::{Task, BlockedTask};
+use rustrt::task;
+use rustrt::task::BlockedTask;
 use rustrt::thread::Thread;
 
 use atomics;
+use backoff::Backoff;
+use comm::{Sender, SharedQueue, LinkedQueue, BlockQueue};
+use comm::discard;
+use comm::latency;
+use comm::poll::Watch;
+use comm::stats::Stats;
 use mpsc = mpsc_queue;
+use mpsc_block_queue;
 
 static DISCONNECTED: int = int::MIN;
+// Left in `to_wake` by a selection which was aborted while a sender was about
+// to take its task, for that sender to clear. No blocked task is ever 1, as it
+// would be a shared task at address 0.
+static DEAD: uint = 1;
 static FUDGE: int = 1024;
 #[cfg(test)]
 static MAX_STEALS: int = 5;
@@ -39,9 +52,9 @@ static MAX_STEALS: int = 5;
 static MAX_STEALS: int = 1 << 20;
 
 pub struct Packet<T> {
-    queue: mpsc::Queue<T>,
+    queue: Queue<Message<T>>,
+
     cnt: atomics::AtomicInt, // How many items are on this channel
-    steals: int, // How many times has a port received without blocking?
     to_wake: atomics::AtomicUint, // Task to wake up
 
     // The number of channels which are currently using this packet.
@@ -55,6 +68,18 @@ pub struct Packet<T> {
     // this lock protects various portions of this implementation during
     // select()
     select_lock: NativeMutex,
+    // flagged once `inherit_blocker` has released `select_lock`, after which
+    // the lock no longer needs to be bounced on
+    initialized: atomics::AtomicBool,
+    // the poller watching the port, if any
+    watch: Watch,
+    // the counters of the channel, if it has them
+    pub stats: Stats,
+    // how to wait out the other halves when they're in the middle of an
+    // operation on the queue
+    backoff: Box<Backoff + Send + Share>,
+
+    steals: int, // How many times has a port received without blocking?
 }
 
 pub enum Failure {
@@ -62,23 +87,70 @@ pub enum Failure {
     Disconnected,
 }
 
+// The node-based queue is used directly for `LinkedQueue` channels (the
+// default), and the block-based one for `BlockQueue` channels. Channels which
+// measure their latencies have nodes of their own, with the time each message
+// was sent at next to it (see the stream implementation), and whoever pops
+// keeps the stamp of the message popped last.
+enum Queue<T> {
+    Linked(mpsc::Queue<T>),
+    Blocks(mpsc_block_queue::Queue<T>),
+    Stamped(mpsc::Queue<(T, u64)>, Cell<u64>),
+}
+
+/// A reserved message slot, see `Packet::reserve`. Blocks can't be handed out
+/// piecemeal, and stamped nodes are only stamped when they're pushed, so with
+/// those queues the message is stored in the slot itself and pushed when
+/// committed.
+pub enum Slot<T> {
+    NodeSlot(mpsc::Slot<Message<T>>),
+    InlineSlot(Option<Message<T>>),
+}
+
+// Senders may interleave flush markers with their data, see `flush` below
+pub enum Message<T> {
+    Data(T),
+    Flush(Sender<()>),
+}
+
 impl<T: Send> Packet<T> {
     // Creation of a packet *must* be followed by a call to postinit_lock
     // and later by inherit_blocker
-    pub fn new() -> Packet<T> {
+    // Creates a packet with `channels` senders. Upgrades start out with two,
+    // the sender being upgraded and its clone.
+    pub fn new(channels: int, queue: SharedQueue,
+               backoff: Box<Backoff + Send + Share>) -> Packet<T> {
         let p = Packet {
-            queue: mpsc::Queue::new(),
+            queue: match queue {
+                LinkedQueue => Linked(mpsc::Queue::new()),
+                BlockQueue => Blocks(mpsc_block_queue::Queue::new()),
+            },
             cnt: atomics::AtomicInt::new(0),
-            steals: 0,
             to_wake: atomics::AtomicUint::new(0),
-            channels: atomics::AtomicInt::new(2),
+            channels: atomics::AtomicInt::new(channels),
             port_dropped: atomics::AtomicBool::new(false),
             sender_drain: atomics::AtomicInt::new(0),
             select_lock: unsafe { NativeMutex::new() },
+            initialized: atomics::AtomicBool::new(false),
+            watch: Watch::new(),
+            stats: Stats::off(),
+            backoff: backoff,
+            steals: 0,
         };
         return p;
     }
 
+    // Takes over the counters of the packet being upgraded to this one, which
+    // must not have been sent anything yet. If the channel measures its
+    // latencies, the messages are stamped from now on.
+    pub fn inherit_stats(&mut self, stats: &Stats) {
+        if stats.measures_latency() {
+            self.queue = Stamped(mpsc::Queue::new(), Cell::new(0));
+        }
+        self.stats = stats.clone();
+        self.stats.upgraded();
+    }
+
     // This function should be used after newly created Packet
     // was wrapped with an Arc
     // In other case mutex data will be duplicated while cloning
@@ -134,11 +206,68 @@ impl<T: Send> Packet<T> {
         // signifying that we're done modifying self.cnt and self.to_wake and
         // the port is ready for the world to continue using it.
         unsafe { self.select_lock.unlock_noguard() }
+        self.initialized.store(true, atomics::Release);
+    }
+
+    // Sends the data, returning the task which was waiting for it (if any),
+    // which is for the caller to wake up.
+    pub fn send(&mut self, t: T) -> Result<Option<BlockedTask>, T> {
+        match self.do_send(Data(t)) {
+            Ok(task) => Ok(task),
+            Err(Data(t)) => Err(t),
+            Err(Flush(..)) => unreachable!(),
+        }
     }
 
-    pub fn send(&mut self, t: T) -> Result<(), T> {
+    // Enqueues a flush marker which will be acknowledged on `ack` once the
+    // port has received everything sent before it. If the port is gone, the
+    // ack is dropped instead.
+    pub fn flush(&mut self, ack: Sender<()>) {
+        match self.do_send(Flush(ack)) {
+            Ok(Some(task)) => task.reawaken_later(),
+            Ok(None) | Err(..) => {}
+        }
+    }
+
+    // Hands out a queue node which can be filled in place and later published
+    // with `commit`.
+    pub fn reserve(&mut self) -> Slot<T> {
+        match self.queue {
+            Linked(ref q) => NodeSlot(q.reserve()),
+            Blocks(..) | Stamped(..) => InlineSlot(None),
+        }
+    }
+
+    // Publishes a reserved node, which must contain `Data`. Like `send`, this
+    // returns the data if it will never be received, and otherwise the task
+    // which was waiting for it (if any).
+    pub fn commit(&mut self, mut slot: Slot<T>)
+                  -> Result<Option<BlockedTask>, T> {
+        if !self.can_send() {
+            match slot.value().take() {
+                Some(Data(t)) => return Err(t),
+                _ => unreachable!(),
+            }
+        }
+        match (&self.queue, slot) {
+            (&Linked(ref q), NodeSlot(slot)) => q.commit(slot),
+            (&Linked(..), InlineSlot(..)) | (_, NodeSlot(..)) => unreachable!(),
+            (_, InlineSlot(msg)) => self.queue.push(msg.unwrap(), &self.backoff),
+        }
+        Ok(self.pushed())
+    }
+
+    fn do_send(&mut self,
+               t: Message<T>) -> Result<Option<BlockedTask>, Message<T>> {
+        if !self.can_send() { return Err(t) }
+        self.queue.push(t, &self.backoff);
+        Ok(self.pushed())
+    }
+
+    // Preflight checks for whether the data being sent may be received.
+    fn can_send(&self) -> bool {
         // See Port::drop for what's going on
-        if self.port_dropped.load(atomics::SeqCst) { return Err(t) }
+        if self.port_dropped.load(atomics::Acquire) { return false }
 
         // Note that the multiple sender case is a little trickier
         // semantically than the single sender case. The logic for
@@ -165,14 +294,24 @@ impl<T: Send> Packet<T> {
         // preflight check serves as the definitive "this will never be
         // received". Once we get beyond this check, we have permanently
         // entered the realm of "this may be received"
-        if self.cnt.load(atomics::SeqCst) < DISCONNECTED + FUDGE {
-            return Err(t)
-        }
+        self.cnt.load(atomics::Acquire) >= DISCONNECTED + FUDGE
+    }
+
+    // Accounts for a message which was just placed on the queue, draining the
+    // queue as necessary. If the port was blocked waiting for the message,
+    // its task is returned for the caller to wake up.
+    fn pushed(&mut self) -> Option<BlockedTask> {
+        let task = self.account();
+        self.watch.notify();
+        task
+    }
 
-        self.queue.push(t);
-        match self.cnt.fetch_add(1, atomics::SeqCst) {
+    fn account(&mut self) -> Option<BlockedTask> {
+        match self.cnt.fetch_add(1, atomics::AcqRel) {
             -1 => {
-                self.take_to_wake().wake().map(|t| t.reawaken());
+                let task = self.take_to_wake();
+                if task.is_some() { self.stats.woke() }
+                return task
             }
 
             // In this case, we have possibly failed to send our data, and
@@ -187,22 +326,26 @@ impl<T: Send> Packet<T> {
             n if n < DISCONNECTED + FUDGE => {
                 // see the comment in 'try' for a shared channel for why this
                 // window of "not disconnected" is ok.
-                self.cnt.store(DISCONNECTED, atomics::SeqCst);
+                self.cnt.store(DISCONNECTED, atomics::Release);
 
-                if self.sender_drain.fetch_add(1, atomics::SeqCst) == 0 {
+                if self.sender_drain.fetch_add(1, atomics::AcqRel) == 0 {
+                    let mut step = 0;
                     loop {
-                        // drain the queue, for info on the thread yield see the
+                        // drain the queue, for info on why we wait see the
                         // discussion in try_recv
                         loop {
                             match self.queue.pop() {
                                 mpsc::Data(..) => {}
                                 mpsc::Empty => break,
-                                mpsc::Inconsistent => Thread::yield_now(),
+                                mpsc::Inconsistent => {
+                                    self.backoff.snooze(step);
+                                    step += 1;
+                                }
                             }
                         }
                         // maybe we're done, if we're not the last ones
                         // here, then we need to go try again.
-                        if self.sender_drain.fetch_sub(1, atomics::SeqCst) == 1 {
+                        if self.sender_drain.fetch_sub(1, atomics::AcqRel) == 1 {
                             break
                         }
                     }
@@ -217,40 +360,63 @@ impl<T: Send> Packet<T> {
             // Can't make any assumptions about this case like in the SPSC case.
             _ => {}
         }
-
-        Ok(())
+        None
     }
 
     pub fn recv(&mut self) -> Result<T, Failure> {
         // This code is essentially the exact same as that found in the stream
         // case (see stream.rs)
-        match self.try_recv() {
-            Err(Empty) => {}
-            data => return data,
-        }
-
-        let task: Box<Task> = Local::take();
-        task.deschedule(1, |task| {
-            self.decrement(task)
-        });
+        loop {
+            match self.try_recv() {
+                Err(Empty) => {}
+                data => return data,
+            }
 
-        match self.try_recv() {
-            data @ Ok(..) => { self.steals -= 1; data }
-            data => data,
+            let _waiting = self.stats.blocking();
+            task::deschedule_current(1, |task| {
+                // Counted before the task is published, see `Stats::blocked`
+                self.stats.blocked();
+                let ret = self.decrement(task);
+                if ret.is_err() { self.stats.unblocked() }
+                ret
+            });
+
+            match self.try_recv_msg() {
+                Ok(msg) => {
+                    self.steals -= 1;
+                    match msg {
+                        Data(t) => {
+                            self.stats.arrived(self.queue.sent_at());
+                            return Ok(t)
+                        }
+                        Flush(ack) => { let _ = ack.send_opt(()); }
+                    }
+                }
+                Err(e) => return Err(e),
+            }
         }
     }
 
     // Essentially the exact same thing as the stream decrement function.
     fn decrement(&mut self, task: BlockedTask) -> Result<(), BlockedTask> {
-        assert_eq!(self.to_wake.load(atomics::SeqCst), 0);
+        // A sender may not have cleared the tombstone of an aborted selection
+        // yet (see abort_selection), and it would take this task instead if
+        // the tombstone were overwritten. It's about to, as it has already
+        // bumped the count.
         let n = unsafe { task.cast_to_uint() };
-        self.to_wake.store(n, atomics::SeqCst);
+        loop {
+            match self.to_wake.compare_and_swap(0, n, atomics::AcqRel) {
+                0 => break,
+                DEAD => Thread::yield_now(),
+                _ => fail!("a task is already waiting on this port"),
+            }
+        }
 
         let steals = self.steals;
         self.steals = 0;
 
-        match self.cnt.fetch_sub(1 + steals, atomics::SeqCst) {
-            DISCONNECTED => { self.cnt.store(DISCONNECTED, atomics::SeqCst); }
+        match self.cnt.fetch_sub(1 + steals, atomics::AcqRel) {
+            DISCONNECTED => { self.cnt.store(DISCONNECTED, atomics::Release); }
             // If we factor in our steals and notice that the channel has no
             // data, we successfully sleep
             n => {
@@ -259,11 +425,24 @@ impl<T: Send> Packet<T> {
             }
         }
 
-        self.to_wake.store(0, atomics::SeqCst);
+        self.to_wake.store(0, atomics::Release);
         Err(unsafe { BlockedTask::cast_from_uint(n) })
     }
 
     pub fn try_recv(&mut self) -> Result<T, Failure> {
+        loop {
+            match self.try_recv_msg() {
+                Ok(Data(t)) => {
+                    self.stats.arrived(self.queue.sent_at());
+                    return Ok(t)
+                }
+                Ok(Flush(ack)) => { let _ = ack.send_opt(()); }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn try_recv_msg(&mut self) -> Result<Message<T>, Failure> {
         let ret = match self.queue.pop() {
             mpsc::Data(t) => Some(t),
             mpsc::Empty => None,
@@ -311,9 +490,9 @@ impl<T: Send> Packet<T> {
             // might decrement steals.
             Some(data) => {
                 if self.steals > MAX_STEALS {
-                    match self.cnt.swap(0, atomics::SeqCst) {
+                    match self.cnt.swap(0, atomics::AcqRel) {
                         DISCONNECTED => {
-                            self.cnt.store(DISCONNECTED, atomics::SeqCst);
+                            self.cnt.store(DISCONNECTED, atomics::Release);
                         }
                         n => {
                             let m = cmp::min(n, self.steals);
@@ -330,7 +509,7 @@ impl<T: Send> Packet<T> {
             // See the discussion in the stream implementation for why we try
             // again.
             None => {
-                match self.cnt.load(atomics::SeqCst) {
+                match self.cnt.load(atomics::Acquire) {
                     n if n != DISCONNECTED => Err(Empty),
                     _ => {
                         match self.queue.pop() {
@@ -348,53 +527,67 @@ impl<T: Send> Packet<T> {
     // Prepares this shared packet for a channel clone, essentially just bumping
     // a refcount.
     pub fn clone_chan(&mut self) {
-        self.channels.fetch_add(1, atomics::SeqCst);
+        self.channels.fetch_add(1, atomics::Relaxed);
     }
 
     // Decrement the reference count on a channel. This is called whenever a
     // Chan is dropped and may end up waking up a receiver. It's the receiver's
     // responsibility on the other end to figure out that we've disconnected.
     pub fn drop_chan(&mut self) {
-        match self.channels.fetch_sub(1, atomics::SeqCst) {
+        match self.channels.fetch_sub(1, atomics::AcqRel) {
             1 => {}
             n if n > 1 => return,
             n => fail!("bad number of channels left {}", n),
         }
 
-        match self.cnt.swap(DISCONNECTED, atomics::SeqCst) {
-            -1 => { self.take_to_wake().wake().map(|t| t.reawaken()); }
+        match self.cnt.swap(DISCONNECTED, atomics::AcqRel) {
+            -1 => match self.take_to_wake() {
+                Some(task) => { task.wake().map(|t| t.reawaken()); }
+                None => {}
+            },
             DISCONNECTED => {}
             n => { assert!(n >= 0); }
         }
+        self.watch.notify();
     }
 
     // See the long discussion inside of stream.rs for why the queue is drained,
     // and why it is done in this fashion.
     pub fn drop_port(&mut self) {
-        self.port_dropped.store(true, atomics::SeqCst);
+        self.port_dropped.store(true, atomics::Release);
         let mut steals = self.steals;
+        let mut step = 0;
+        let mut discarded = 0;
         while {
             let cnt = self.cnt.compare_and_swap(
-                            steals, DISCONNECTED, atomics::SeqCst);
+                            steals, DISCONNECTED, atomics::AcqRel);
             cnt != DISCONNECTED && cnt != steals
         } {
-            // See the discussion in 'try_recv' for why we yield
-            // control of this thread.
             loop {
                 match self.queue.pop() {
+                    mpsc::Data(Data(..)) => { steals += 1; discarded += 1; }
                     mpsc::Data(..) => { steals += 1; }
                     mpsc::Empty | mpsc::Inconsistent => break,
                 }
             }
+            // If the count is still off, senders are in the middle of pushing
+            // (see the discussion in 'try_recv'), so give them a chance to
+            // finish before trying again.
+            self.backoff.snooze(step);
+            step += 1;
         }
+        discard::discarded::<T>(&self.stats, discarded);
     }
 
-    // Consumes ownership of the 'to_wake' field.
-    fn take_to_wake(&mut self) -> BlockedTask {
-        let task = self.to_wake.load(atomics::SeqCst);
-        self.to_wake.store(0, atomics::SeqCst);
-        assert!(task != 0);
-        unsafe { BlockedTask::cast_from_uint(task) }
+    // Consumes ownership of the 'to_wake' field. This is only called by whoever
+    // moved the count off of -1, so the task can only be missing if the
+    // selection it was waiting in has been aborted since.
+    fn take_to_wake(&mut self) -> Option<BlockedTask> {
+        match self.to_wake.swap(0, atomics::AcqRel) {
+            0 => fail!("no task waiting on this port"),
+            DEAD => None,
+            task => Some(unsafe { BlockedTask::cast_from_uint(task) }),
+        }
     }
 
     ////////////////////////////////////////////////////////////////////////////
@@ -404,18 +597,24 @@ impl<T: Send> Packet<T> {
     // Helper function for select, tests whether this port can receive without
     // blocking (obviously not an atomic decision).
     //
+    pub fn watch<'a>(&'a self) -> &'a Watch { &self.watch }
+
+    pub fn port_dropped(&self) -> bool {
+        self.port_dropped.load(atomics::Acquire)
+    }
+
     // This is different than the stream version because there's no need to peek
     // at the queue, we can just look at the local count.
     pub fn can_recv(&mut self) -> bool {
-        let cnt = self.cnt.load(atomics::SeqCst);
+        let cnt = self.cnt.load(atomics::Acquire);
         cnt == DISCONNECTED || cnt - self.steals > 0
     }
 
     // increment the count on the channel (used for selection)
     fn bump(&mut self, amt: int) -> int {
-        match self.cnt.fetch_add(amt, atomics::SeqCst) {
+        match self.cnt.fetch_add(amt, atomics::AcqRel) {
             DISCONNECTED => {
-                self.cnt.store(DISCONNECTED, atomics::SeqCst);
+                self.cnt.store(DISCONNECTED, atomics::Release);
                 DISCONNECTED
             }
             n => n
@@ -450,44 +649,120 @@ impl<T: Send> Packet<T> {
         // done with. Without this bounce, we can race with inherit_blocker
         // about looking at and dealing with to_wake. Once we have acquired the
         // lock, we are guaranteed that inherit_blocker is done.
-        unsafe {
-            let _guard = self.select_lock.lock();
+        //
+        // The lock is only ever held during the initialization of the packet,
+        // so once that's known to be over there's no need to touch the lock.
+        // This keeps aborting a selection down to an atomic increment on the
+        // channel count and a swap of `to_wake`, neither of which are retried,
+        // which matters when tearing down a large `Select`.
+        if !self.initialized.load(atomics::Acquire) {
+            unsafe {
+                let _guard = self.select_lock.lock();
+            }
         }
 
-        // Like the stream implementation, we want to make sure that the count
-        // on the channel goes non-negative. We don't know how negative the
-        // stream currently is, so instead of using a steal value of 1, we load
-        // the channel count and figure out what we should do to make it
-        // positive.
-        let steals = {
-            let cnt = self.cnt.load(atomics::SeqCst);
-            if cnt < 0 && cnt != DISCONNECTED {-cnt} else {0}
-        };
-        let prev = self.bump(steals + 1);
+        // A blocked port always leaves the count at -1, as it only blocks
+        // once everything which was sent has been received, so bumping it by
+        // 2 (with 1 steal for our decrement) always makes it non-negative, and
+        // leaves out exactly the messages which were sent since. This is done
+        // in one increment, so that no sender can slip in between a load and
+        // an increment.
+        let prev = self.bump(2);
+
+        if prev == -1 {
+            // Nobody else moved the count off of -1, so nobody else is going to
+            // look at `to_wake`, and the task is still ours to destroy.
+            self.take_to_wake().map(|task| task.trash());
+        } else {
+            // Whoever moved the count off of -1 (a sender, or the last sender
+            // disconnecting) is going to take the task out of `to_wake`, or has
+            // already. Rather than waiting for them, the task is taken back
+            // and a tombstone left in its place, which they clear instead of
+            // waking anything. If they've already taken the task, there's
+            // nobody left to clear the tombstone.
+            match self.to_wake.swap(DEAD, atomics::AcqRel) {
+                0 => self.to_wake.store(0, atomics::Release),
+                DEAD => unreachable!(),
+                task => unsafe { BlockedTask::cast_from_uint(task).trash() },
+            }
+        }
 
         if prev == DISCONNECTED {
-            assert_eq!(self.to_wake.load(atomics::SeqCst), 0);
             true
         } else {
-            let cur = prev + steals + 1;
-            assert!(cur >= 0);
-            if prev < 0 {
-                self.take_to_wake().trash();
-            } else {
-                while self.to_wake.load(atomics::SeqCst) != 0 {
-                    Thread::yield_now();
-                }
-            }
+            assert!(prev >= -1);
             // if the number of steals is -1, it was the pre-emptive -1 steal
             // count from when we inherited a blocker. This is fine because
             // we're just going to overwrite it with a real value.
             assert!(self.steals == 0 || self.steals == -1);
-            self.steals = steals;
+            self.steals = 1;
             prev >= 0
         }
     }
 }
 
+impl<T: Send> Queue<T> {
+    fn push(&self, t: T, backoff: &Box<Backoff + Send + Share>) {
+        match *self {
+            Linked(ref q) => q.push(t),
+            Blocks(ref q) => q.push_with(t, |step| backoff.snooze(step)),
+            Stamped(ref q, _) => q.push((t, latency::now())),
+        }
+    }
+
+    fn pop(&self) -> mpsc::PopResult<T> {
+        match *self {
+            Linked(ref q) => q.pop(),
+            Blocks(ref q) => q.pop(),
+            Stamped(ref q, ref last) => match q.pop() {
+                mpsc::Data((t, sent_at)) => { last.set(sent_at); mpsc::Data(t) }
+                mpsc::Empty => mpsc::Empty,
+                mpsc::Inconsistent => mpsc::Inconsistent,
+            },
+        }
+    }
+
+    // The time the message popped last was sent at, or 0 if it wasn't stamped
+    fn sent_at(&self) -> u64 {
+        match *self {
+            Stamped(_, ref last) => last.get(),
+            Linked(..) | Blocks(..) => 0,
+        }
+    }
+}
+
+impl<T: Send> Slot<T> {
+    pub fn value<'a>(&'a mut self) -> &'a mut Option<Message<T>> {
+        match *self {
+            NodeSlot(ref mut s) => s.value(),
+            InlineSlot(ref mut v) => v,
+        }
+    }
+}
+
+// As with streams, this must be called from the receiving task
+impl<T: Send> fmt::Show for Packet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cnt = self.cnt.load(atomics::SeqCst);
+        try!(write!(f, "shared packet: "));
+        if cnt < DISCONNECTED + FUDGE {
+            try!(write!(f, "DISCONNECTED"));
+        } else {
+            try!(write!(f, "{} queued", cmp::max(cnt - self.steals, 0)));
+        }
+        try!(write!(f, ", {} steals, {} senders", self.steals,
+                    self.channels.load(atomics::SeqCst)));
+        let to_wake = self.to_wake.load(atomics::SeqCst);
+        if to_wake != 0 && to_wake != DEAD {
+            try!(write!(f, ", receiver blocked"));
+        }
+        if self.port_dropped.load(atomics::SeqCst) {
+            try!(write!(f, ", port dropped"));
+        }
+        Ok(())
+    }
+}
+
 #[unsafe_destructor]
 impl<T: Send> Drop for Packet<T> {
     fn drop(&mut self) {
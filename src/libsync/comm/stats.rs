@@ -0,0 +1,228 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Counters for channels created with statistics enabled
+//!
+//! Every packet has a `Stats`, which is off unless the channel was created by
+//! `channel_with_stats` or `sync_channel_with_stats`. When a channel is
+//! upgraded, the new packet is given the counters of the old one before the
+//! upgrade is published, so the counters cover the whole life of the channel.
+//!
+//! The packets count what only they can see (the receiver blocking and being
+//! woken up), while the halves count what they hand over. Each counter is
+//! updated on its own, so a snapshot taken while the channel is in use may be
+//! slightly inconsistent (a message can be counted as received before it's
+//! counted as sent, for example).
+//!
+//! The counters are also where the events of a tracer, if there is one, come
+//! from, so only the channels with statistics are traced, and they hold the
+//! latency histogram of the channels which measure their latencies.
+
+use core::prelude::*;
+
+use alloc::arc::{Arc, Weak};
+// The counters aren't part of any channel protocol, so they're kept out of
+// reach of the model checker which runs the tests by using core's atomics
+use core::atomics;
+
+use comm::debug;
+use comm::latency;
+use comm::latency::{Histogram, Latency};
+use comm::trace;
+use comm::trace::{EventKind, SendEvent, RecvEvent, BlockEvent, WakeEvent};
+use comm::trace::{UpgradeEvent, SenderGoneEvent, ReceiverGoneEvent};
+
+// The identifier of the next channel to be given counters
+static mut NEXT_ID: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+/// A snapshot of the counters of a channel, returned by `Sender::stats`,
+/// `SyncSender::stats` and `Receiver::stats`.
+#[deriving(PartialEq, Clone, Show)]
+#[experimental]
+pub struct ChannelStats {
+    /// The number of messages which have been sent successfully.
+    pub sends: uint,
+    /// The number of messages which have been received, by any method.
+    pub recvs: uint,
+    /// The number of times the receiver has blocked waiting for a message.
+    pub blocking_recvs: uint,
+    /// The number of calls to `try_recv` which didn't return a message.
+    pub failed_try_recvs: uint,
+    /// The number of times a sender has woken up the blocked receiver.
+    pub wakeups: uint,
+}
+
+pub struct Counters {
+    pub id: uint,
+    sends: atomics::AtomicUint,
+    recvs: atomics::AtomicUint,
+    blocking_recvs: atomics::AtomicUint,
+    failed_try_recvs: atomics::AtomicUint,
+    wakeups: atomics::AtomicUint,
+    latency: Option<Histogram>,
+}
+
+pub struct Stats {
+    counters: Option<Arc<Counters>>,
+}
+
+fn bump(counter: &atomics::AtomicUint) {
+    counter.fetch_add(1, atomics::Relaxed);
+}
+
+fn traced(c: &Counters, kind: EventKind) {
+    if trace::tracing() { trace::event(kind, c.id) }
+}
+
+impl Stats {
+    pub fn off() -> Stats { Stats { counters: None } }
+
+    pub fn on() -> Stats { Stats::with(None) }
+
+    pub fn with_latency() -> Stats { Stats::with(Some(Histogram::new())) }
+
+    fn with(latency: Option<Histogram>) -> Stats {
+        Stats {
+            counters: Some(Arc::new(Counters {
+                id: unsafe { NEXT_ID.fetch_add(1, atomics::Relaxed) },
+                sends: atomics::AtomicUint::new(0),
+                recvs: atomics::AtomicUint::new(0),
+                blocking_recvs: atomics::AtomicUint::new(0),
+                failed_try_recvs: atomics::AtomicUint::new(0),
+                wakeups: atomics::AtomicUint::new(0),
+                latency: latency,
+            })),
+        }
+    }
+
+    pub fn sent(&self) {
+        match self.counters {
+            Some(ref c) => { bump(&c.sends); traced(&**c, SendEvent) }
+            None => {}
+        }
+    }
+
+    pub fn received(&self) {
+        match self.counters {
+            Some(ref c) => { bump(&c.recvs); traced(&**c, RecvEvent) }
+            None => {}
+        }
+    }
+
+    // Announces that the receiver is about to block. This is separate from
+    // `blocked`, which is only called once it's certain that the receiver
+    // blocks, because that's in the middle of descheduling the task, where
+    // tracers can't be called. The task is listed as blocked on the channel
+    // until the returned guard is dropped, which is once it has woken up.
+    pub fn blocking(&self) -> debug::Waiting {
+        match self.counters {
+            Some(ref c) => { traced(&**c, BlockEvent); debug::waiting(&[c.id]) }
+            None => debug::Waiting::none(),
+        }
+    }
+
+    // Counts a blocking receive. This has to happen before the blocked task
+    // is published to the sender, as the sender may then wake the receiver
+    // and free the packet (and these stats) at any moment, so if it turns out
+    // that the receiver doesn't block after all, it's taken back with
+    // `unblocked`.
+    pub fn blocked(&self) {
+        match self.counters { Some(ref c) => bump(&c.blocking_recvs), None => {} }
+    }
+
+    pub fn unblocked(&self) {
+        match self.counters {
+            Some(ref c) => { c.blocking_recvs.fetch_sub(1, atomics::Relaxed); }
+            None => {}
+        }
+    }
+
+    pub fn try_recv_failed(&self) {
+        match self.counters { Some(ref c) => bump(&c.failed_try_recvs), None => {} }
+    }
+
+    pub fn woke(&self) {
+        match self.counters {
+            Some(ref c) => { bump(&c.wakeups); traced(&**c, WakeEvent) }
+            None => {}
+        }
+    }
+
+    pub fn upgraded(&self) {
+        match self.counters { Some(ref c) => traced(&**c, UpgradeEvent), None => {} }
+    }
+
+    pub fn sender_gone(&self) {
+        match self.counters { Some(ref c) => traced(&**c, SenderGoneEvent), None => {} }
+    }
+
+    pub fn receiver_gone(&self) {
+        match self.counters { Some(ref c) => traced(&**c, ReceiverGoneEvent), None => {} }
+    }
+
+    // The time to stamp a message with as it's sent, or 0 if the channel
+    // doesn't measure its latencies
+    pub fn stamp(&self) -> u64 {
+        match self.counters {
+            Some(ref c) if c.latency.is_some() => latency::now(),
+            _ => 0,
+        }
+    }
+
+    // Records the latency of a message stamped with `sent`, which has just
+    // been taken off the queue
+    pub fn arrived(&self, sent: u64) {
+        if sent == 0 { return }
+        match self.counters.as_ref().and_then(|c| c.latency.as_ref()) {
+            Some(h) => h.record(sent),
+            None => {}
+        }
+    }
+
+    pub fn measures_latency(&self) -> bool {
+        self.counters.as_ref().map_or(false, |c| c.latency.is_some())
+    }
+
+    pub fn latency(&self) -> Option<Latency> {
+        self.counters.as_ref().and_then(|c| c.latency.as_ref()).map(|h| h.snapshot())
+    }
+
+    pub fn is_on(&self) -> bool { self.counters.is_some() }
+
+    pub fn id(&self) -> Option<uint> { self.counters.as_ref().map(|c| c.id) }
+
+    pub fn snapshot(&self) -> Option<ChannelStats> {
+        self.counters.as_ref().map(|c| c.snapshot())
+    }
+
+    // A reference to the counters which doesn't keep them alive, so that
+    // whether the channel is still around can be told from it
+    pub fn downgrade(&self) -> Option<Weak<Counters>> {
+        self.counters.as_ref().map(|c| c.downgrade())
+    }
+}
+
+impl Counters {
+    pub fn snapshot(&self) -> ChannelStats {
+        ChannelStats {
+            sends: self.sends.load(atomics::Relaxed),
+            recvs: self.recvs.load(atomics::Relaxed),
+            blocking_recvs: self.blocking_recvs.load(atomics::Relaxed),
+            failed_try_recvs: self.failed_try_recvs.load(atomics::Relaxed),
+            wakeups: self.wakeups.load(atomics::Relaxed),
+        }
+    }
+}
+
+impl Clone for Stats {
+    fn clone(&self) -> Stats {
+        Stats { counters: self.counters.clone() }
+    }
+}
@@ -20,30 +20,46 @@
 use core::prelude::*;
 
 use alloc::boxed::Box;
+use core::cell::Cell;
 use core::cmp;
-use core::int;
-use rustrt::local::Local;
-use rustrt::task::{Task, BlockedTask};
+use core::fmt;
+use rustrt::task;
+use rustrt::task::BlockedTask;
 use rustrt::thread::Thread;
 
 use atomics;
-use comm::Receiver;
+use comm::{Sender, Receiver, NodeCache, FixedCache, AdaptiveCache};
+use comm::backend::MessageQueue;
+use comm::discard;
+use comm::latency;
+use comm::poll::Watch;
+use comm::stats::Stats;
 use spsc = spsc_queue;
 
-static DISCONNECTED: int = int::MIN;
+// The count of messages on the channel and whether the channel is disconnected
+// share the `cnt` word. The disconnected flag is the lowest bit, and the count
+// is kept in the rest of the word as a wrapping unsigned integer. Adding to or
+// subtracting from the count never touches the flag, so nothing ever has to
+// restore the flag after racing with a disconnect, and the count is always
+// read back as a (small) signed difference, so it doesn't matter where the
+// word as a whole wraps around.
+static DISCONNECTED: uint = 1;
+static ONE: uint = 2;
 #[cfg(test)]
 static MAX_STEALS: int = 5;
 #[cfg(not(test))]
 static MAX_STEALS: int = 1 << 20;
 
 pub struct Packet<T> {
-    queue: spsc::Queue<Message<T>>, // internal queue for all message
+    queue: Queue<Message<T>>, // internal queue for all message
 
-    cnt: atomics::AtomicInt, // How many items are on this channel
-    steals: int, // How many times has a port received without blocking?
+    cnt: atomics::AtomicUint, // How many items are on this channel
     to_wake: atomics::AtomicUint, // Task to wake up
-
     port_dropped: atomics::AtomicBool, // flag if the channel has been destroyed.
+    watch: Watch, // the poller watching the port, if any
+    pub stats: Stats, // the counters of the channel, if it has them
+
+    steals: int, // How many times has a port received without blocking?
 }
 
 pub enum Failure<T> {
@@ -64,74 +80,185 @@ pub enum SelectionResult<T> {
     SelUpgraded(BlockedTask, Receiver<T>),
 }
 
+// The default queue is used directly to keep virtual calls off of the common
+// path, while alternate queues are supplied by `channel_with_queue`. Channels
+// which measure their latencies have nodes of their own, with the time each
+// message was sent at next to it, so that no other channel pays for the stamp.
+// The port keeps the stamp of the message it popped last.
+enum Queue<T> {
+    Spsc(spsc::Queue<T>),
+    Custom(Box<MessageQueue<T> + Send>),
+    Stamped(spsc::Queue<(T, u64)>, Cell<u64>),
+}
+
+/// A reserved message slot, see `Packet::reserve`. Queues other than the
+/// default one can't hand out their nodes, so the message is stored in the
+/// slot itself and pushed when committed.
+pub enum Slot<T> {
+    NodeSlot(spsc::Slot<Message<T>>),
+    InlineSlot(Option<Message<T>>),
+}
+
 // Any message could contain an "upgrade request" to a new shared port, so the
-// internal queue it's a queue of T, but rather Message<T>
-enum Message<T> {
+// internal queue it's a queue of T, but rather Message<T>. A message may also be
+// a flush marker, which is acknowledged (and otherwise ignored) by the port.
+pub enum Message<T> {
     Data(T),
     GoUp(Receiver<T>),
+    Flush(Sender<()>),
 }
 
 impl<T: Send> Packet<T> {
     pub fn new() -> Packet<T> {
+        Packet::with_cache(FixedCache(128))
+    }
+
+    pub fn with_cache(cache: NodeCache) -> Packet<T> {
+        let q = match cache {
+            FixedCache(bound) => spsc::Queue::new(bound),
+            AdaptiveCache(min, max) => spsc::Queue::adaptive(min, max),
+        };
+        Packet::with_queue(Spsc(q))
+    }
+
+    pub fn with_custom_queue(q: Box<MessageQueue<Message<T>> + Send>)
+                             -> Packet<T> {
+        Packet::with_queue(Custom(q))
+    }
+
+    // Takes over the counters of the packet being upgraded to this one, which
+    // must not have been sent anything yet. If the channel measures its
+    // latencies, the messages are stamped from now on.
+    pub fn inherit_stats(&mut self, stats: &Stats) {
+        if stats.measures_latency() {
+            self.queue = Stamped(spsc::Queue::new(128), Cell::new(0));
+        }
+        self.stats = stats.clone();
+        self.stats.upgraded();
+    }
+
+    fn with_queue(queue: Queue<Message<T>>) -> Packet<T> {
         Packet {
-            queue: spsc::Queue::new(128),
+            queue: queue,
 
-            cnt: atomics::AtomicInt::new(0),
-            steals: 0,
+            cnt: atomics::AtomicUint::new(0),
             to_wake: atomics::AtomicUint::new(0),
-
             port_dropped: atomics::AtomicBool::new(false),
+            watch: Watch::new(),
+            stats: Stats::off(),
+
+            steals: 0,
         }
     }
 
 
-    pub fn send(&mut self, t: T) -> Result<(), T> {
+    // Sends the data, returning the task which was waiting for it (if any),
+    // which is for the caller to wake up.
+    pub fn send(&mut self, t: T) -> Result<Option<BlockedTask>, T> {
         // If the other port has deterministically gone away, then definitely
         // must return the data back up the stack. Otherwise, the data is
         // considered as being sent.
-        if self.port_dropped.load(atomics::SeqCst) { return Err(t) }
+        if self.port_dropped.load(atomics::Acquire) { return Err(t) }
 
         match self.do_send(Data(t)) {
-            UpSuccess | UpDisconnected => {},
-            UpWoke(task) => { task.wake().map(|t| t.reawaken()); }
+            UpSuccess | UpDisconnected => Ok(None),
+            UpWoke(task) => { self.stats.woke(); Ok(Some(task)) }
         }
-        Ok(())
     }
     pub fn upgrade(&mut self, up: Receiver<T>) -> UpgradeResult {
         // If the port has gone away, then there's no need to proceed any
         // further.
-        if self.port_dropped.load(atomics::SeqCst) { return UpDisconnected }
+        if self.port_dropped.load(atomics::Acquire) { return UpDisconnected }
 
         self.do_send(GoUp(up))
     }
 
+    // Enqueues a flush marker which will be acknowledged on `ack` once the
+    // port has received everything before it. If the port is gone, the ack is
+    // dropped (which is how the other end learns that we've disconnected).
+    pub fn flush(&mut self, ack: Sender<()>) {
+        if self.port_dropped.load(atomics::Acquire) { return }
+
+        match self.do_send(Flush(ack)) {
+            UpSuccess | UpDisconnected => {},
+            UpWoke(task) => {
+                self.stats.woke();
+                task.wake().map(|t| t.reawaken());
+            }
+        }
+    }
+
+    // Hands out a queue node which can be filled in place and later published
+    // with `commit`.
+    pub fn reserve(&mut self) -> Slot<T> {
+        match self.queue {
+            Spsc(ref q) => NodeSlot(q.reserve()),
+            Custom(..) | Stamped(..) => InlineSlot(None),
+        }
+    }
+
+    // Publishes a reserved node, which must contain `Data`. Like `send`, this
+    // returns the data if the port has gone away, and otherwise the task which
+    // was waiting for it (if any).
+    pub fn commit(&mut self, mut slot: Slot<T>)
+                  -> Result<Option<BlockedTask>, T> {
+        if self.port_dropped.load(atomics::Acquire) {
+            match slot.value().take() {
+                Some(Data(t)) => return Err(t),
+                _ => unreachable!(),
+            }
+        }
+
+        match (&self.queue, slot) {
+            (&Spsc(ref q), NodeSlot(slot)) => q.commit(slot),
+            (&Spsc(..), InlineSlot(..)) | (_, NodeSlot(..)) => unreachable!(),
+            (_, InlineSlot(msg)) => self.queue.push(msg.unwrap()),
+        }
+        match self.pushed() {
+            UpSuccess | UpDisconnected => Ok(None),
+            UpWoke(task) => { self.stats.woke(); Ok(Some(task)) }
+        }
+    }
+
     fn do_send(&mut self, t: Message<T>) -> UpgradeResult {
         self.queue.push(t);
-        match self.cnt.fetch_add(1, atomics::SeqCst) {
+        self.pushed()
+    }
+
+    // Accounts for a message which was just placed on the queue
+    fn pushed(&mut self) -> UpgradeResult {
+        let ret = self.account();
+        self.watch.notify();
+        ret
+    }
+
+    fn account(&mut self) -> UpgradeResult {
+        let prev = self.cnt.fetch_add(ONE, atomics::AcqRel);
+
+        // If the port is gone, the return value is going to be whether our
+        // data was received or not. This manifests itself on whether we have
+        // an empty queue or not.
+        //
+        // Primarily, are required to drain the queue here because the port
+        // will never remove this data. We can only have at most one item to
+        // drain (the port drains the rest).
+        if is_disconnected(prev) {
+            let first = self.queue.pop();
+            let second = self.queue.pop();
+            assert!(second.is_none());
+
+            return match first {
+                Some(..) => UpSuccess,  // we failed to send the data
+                None => UpDisconnected, // we successfully sent data
+            }
+        }
+
+        match count(prev) {
             // As described in the mod's doc comment, -1 == wakeup
             -1 => UpWoke(self.take_to_wake()),
             // As as described before, SPSC queues must be >= -2
             -2 => UpSuccess,
 
-            // Be sure to preserve the disconnected state, and the return value
-            // in this case is going to be whether our data was received or not.
-            // This manifests itself on whether we have an empty queue or not.
-            //
-            // Primarily, are required to drain the queue here because the port
-            // will never remove this data. We can only have at most one item to
-            // drain (the port drains the rest).
-            DISCONNECTED => {
-                self.cnt.store(DISCONNECTED, atomics::SeqCst);
-                let first = self.queue.pop();
-                let second = self.queue.pop();
-                assert!(second.is_none());
-
-                match first {
-                    Some(..) => UpSuccess,  // we failed to send the data
-                    None => UpDisconnected, // we successfully sent data
-                }
-            }
-
             // Otherwise we just sent some data on a non-waiting queue, so just
             // make sure the world is sane and carry on!
             n => { assert!(n >= 0); UpSuccess }
@@ -140,8 +267,8 @@ impl<T: Send> Packet<T> {
 
     // Consumes ownership of the 'to_wake' field.
     fn take_to_wake(&mut self) -> BlockedTask {
-        let task = self.to_wake.load(atomics::SeqCst);
-        self.to_wake.store(0, atomics::SeqCst);
+        let task = self.to_wake.load(atomics::Acquire);
+        self.to_wake.store(0, atomics::Release);
         assert!(task != 0);
         unsafe { BlockedTask::cast_from_uint(task) }
     }
@@ -152,54 +279,83 @@ impl<T: Send> Packet<T> {
     fn decrement(&mut self, task: BlockedTask) -> Result<(), BlockedTask> {
         assert_eq!(self.to_wake.load(atomics::SeqCst), 0);
         let n = unsafe { task.cast_to_uint() };
-        self.to_wake.store(n, atomics::SeqCst);
+        self.to_wake.store(n, atomics::Release);
 
         let steals = self.steals;
         self.steals = 0;
 
-        match self.cnt.fetch_sub(1 + steals, atomics::SeqCst) {
-            DISCONNECTED => { self.cnt.store(DISCONNECTED, atomics::SeqCst); }
-            // If we factor in our steals and notice that the channel has no
-            // data, we successfully sleep
-            n => {
-                assert!(n >= 0);
-                if n - steals <= 0 { return Ok(()) }
-            }
+        // If we factor in our steals and notice that the channel has no data
+        // (and hasn't been disconnected), we successfully sleep
+        let prev = self.cnt.fetch_sub(messages(1 + steals), atomics::AcqRel);
+        if !is_disconnected(prev) {
+            let n = count(prev);
+            assert!(n >= 0);
+            if n - steals <= 0 { return Ok(()) }
         }
 
-        self.to_wake.store(0, atomics::SeqCst);
+        self.to_wake.store(0, atomics::Release);
         Err(unsafe { BlockedTask::cast_from_uint(n) })
     }
 
     pub fn recv(&mut self) -> Result<T, Failure<T>> {
-        // Optimistic preflight check (scheduling is expensive).
-        match self.try_recv() {
-            Err(Empty) => {}
-            data => return data,
-        }
+        loop {
+            // Optimistic preflight check (scheduling is expensive).
+            match self.try_recv() {
+                Err(Empty) => {}
+                data => return data,
+            }
 
-        // Welp, our channel has no data. Deschedule the current task and
-        // initiate the blocking protocol.
-        let task: Box<Task> = Local::take();
-        task.deschedule(1, |task| {
-            self.decrement(task)
-        });
+            // Welp, our channel has no data. Deschedule the current task and
+            // initiate the blocking protocol.
+            let _waiting = self.stats.blocking();
+            task::deschedule_current(1, |task| {
+                // Counted before the task is published, see `Stats::blocked`
+                self.stats.blocked();
+                let ret = self.decrement(task);
+                if ret.is_err() { self.stats.unblocked() }
+                ret
+            });
 
-        match self.try_recv() {
             // Messages which actually popped from the queue shouldn't count as
             // a steal, so offset the decrement here (we already have our
             // "steal" factored into the channel count above).
-            data @ Ok(..) |
-            data @ Err(Upgraded(..)) => {
-                self.steals -= 1;
-                data
+            //
+            // If we were woken up for a flush marker, then acknowledge it and
+            // go back to sleep; any further messages are ordinary steals.
+            match self.try_recv_msg() {
+                Ok(msg) => {
+                    self.steals -= 1;
+                    match msg {
+                        Data(t) => {
+                            self.stats.arrived(self.queue.sent_at());
+                            return Ok(t)
+                        }
+                        GoUp(up) => return Err(Upgraded(up)),
+                        Flush(ack) => { let _ = ack.send_opt(()); }
+                    }
+                }
+                Err(e) => return Err(e),
             }
-
-            data => data,
         }
     }
 
     pub fn try_recv(&mut self) -> Result<T, Failure<T>> {
+        loop {
+            match self.try_recv_msg() {
+                Ok(Data(t)) => {
+                    self.stats.arrived(self.queue.sent_at());
+                    return Ok(t)
+                }
+                Ok(GoUp(up)) => return Err(Upgraded(up)),
+                Ok(Flush(ack)) => { let _ = ack.send_opt(()); }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Pops the next message off the queue (of any kind), or returns why there
+    // wasn't one. The returned failure is never `Upgraded`.
+    fn try_recv_msg(&mut self) -> Result<Message<T>, Failure<T>> {
         match self.queue.pop() {
             // If we stole some data, record to that effect (this will be
             // factored into cnt later on).
@@ -208,34 +364,27 @@ impl<T: Send> Packet<T> {
             // prevent eventual overflow of either steals or cnt as an overflow
             // would have catastrophic results. Sometimes, steals > cnt, but
             // other times cnt > steals, so we don't know the relation between
-            // steals and cnt. This code path is executed only rarely, so we do
-            // a pretty slow operation, of swapping 0 into cnt, taking steals
-            // down as much as possible (without going negative), and then
-            // adding back in whatever we couldn't factor into steals.
+            // steals and cnt. This code path is executed only rarely, so we
+            // take steals down as much as possible (without taking the count
+            // negative). Only senders touch the count concurrently, and they
+            // only ever increase it, so it's fine to read it first.
             Some(data) => {
                 if self.steals > MAX_STEALS {
-                    match self.cnt.swap(0, atomics::SeqCst) {
-                        DISCONNECTED => {
-                            self.cnt.store(DISCONNECTED, atomics::SeqCst);
-                        }
-                        n => {
-                            let m = cmp::min(n, self.steals);
-                            self.steals -= m;
-                            self.bump(n - m);
-                        }
+                    let cnt = self.cnt.load(atomics::Acquire);
+                    if !is_disconnected(cnt) {
+                        let m = cmp::min(count(cnt), self.steals);
+                        self.steals -= m;
+                        self.bump(-m);
                     }
                     assert!(self.steals >= 0);
                 }
                 self.steals += 1;
-                match data {
-                    Data(t) => Ok(t),
-                    GoUp(up) => Err(Upgraded(up)),
-                }
+                Ok(data)
             }
 
             None => {
-                match self.cnt.load(atomics::SeqCst) {
-                    n if n != DISCONNECTED => Err(Empty),
+                match self.cnt.load(atomics::Acquire) {
+                    n if !is_disconnected(n) => Err(Empty),
 
                     // This is a little bit of a tricky case. We failed to pop
                     // data above, and then we have viewed that the channel is
@@ -250,8 +399,7 @@ impl<T: Send> Packet<T> {
                     // steals again.
                     _ => {
                         match self.queue.pop() {
-                            Some(Data(t)) => Ok(t),
-                            Some(GoUp(up)) => Err(Upgraded(up)),
+                            Some(msg) => Ok(msg),
                             None => Err(Disconnected),
                         }
                     }
@@ -263,11 +411,14 @@ impl<T: Send> Packet<T> {
     pub fn drop_chan(&mut self) {
         // Dropping a channel is pretty simple, we just flag it as disconnected
         // and then wakeup a blocker if there is one.
-        match self.cnt.swap(DISCONNECTED, atomics::SeqCst) {
-            -1 => { self.take_to_wake().wake().map(|t| t.reawaken()); }
-            DISCONNECTED => {}
-            n => { assert!(n >= 0); }
+        let prev = self.cnt.fetch_or(DISCONNECTED, atomics::AcqRel);
+        if !is_disconnected(prev) {
+            match count(prev) {
+                -1 => { self.take_to_wake().wake().map(|t| t.reawaken()); }
+                n => { assert!(n >= 0); }
+            }
         }
+        self.watch.notify();
     }
 
     pub fn drop_port(&mut self) {
@@ -290,7 +441,7 @@ impl<T: Send> Packet<T> {
         // sends are gated on this flag, so we're immediately guaranteed that
         // there are a bounded number of active sends that we'll have to deal
         // with.
-        self.port_dropped.store(true, atomics::SeqCst);
+        self.port_dropped.store(true, atomics::Release);
 
         // Now that we're guaranteed to deal with a bounded number of senders,
         // we need to drain the queue. This draining process happens atomically
@@ -301,18 +452,22 @@ impl<T: Send> Packet<T> {
         // data, but eventually we're guaranteed to break out of this loop
         // (because there is a bounded number of senders).
         let mut steals = self.steals;
+        let mut discarded = 0;
         while {
+            let expected = messages(steals);
             let cnt = self.cnt.compare_and_swap(
-                            steals, DISCONNECTED, atomics::SeqCst);
-            cnt != DISCONNECTED && cnt != steals
+                            expected, expected | DISCONNECTED, atomics::AcqRel);
+            !is_disconnected(cnt) && cnt != expected
         } {
             loop {
                 match self.queue.pop() {
+                    Some(Data(..)) => { steals += 1; discarded += 1; }
                     Some(..) => { steals += 1; }
                     None => break
                 }
             }
         }
+        discard::discarded::<T>(&self.stats, discarded);
 
         // At this point in time, we have gated all future senders from sending,
         // and we have flagged the channel as being disconnected. The senders
@@ -325,6 +480,12 @@ impl<T: Send> Packet<T> {
     // select implementation
     ////////////////////////////////////////////////////////////////////////////
 
+    pub fn watch<'a>(&'a self) -> &'a Watch { &self.watch }
+
+    pub fn port_dropped(&self) -> bool {
+        self.port_dropped.load(atomics::Acquire)
+    }
+
     // Tests to see whether this port can receive without blocking. If Ok is
     // returned, then that's the answer. If Err is returned, then the returned
     // port needs to be queried instead (an upgrade happened)
@@ -334,27 +495,38 @@ impl<T: Send> Packet<T> {
         // upgrade this channel immediately. If it looks like we've got an
         // upgrade pending, then go through the whole recv rigamarole to update
         // the internal state.
-        match self.queue.peek() {
-            Some(&GoUp(..)) => {
-                match self.recv() {
-                    Err(Upgraded(port)) => Err(port),
-                    _ => unreachable!(),
+        //
+        // Flush markers at the front of the queue are acknowledged here so they
+        // don't make this port look readable when it's not.
+        loop {
+            match self.queue.peek() {
+                Some(&GoUp(..)) => {
+                    match self.recv() {
+                        Err(Upgraded(port)) => return Err(port),
+                        _ => unreachable!(),
+                    }
+                }
+                Some(&Flush(..)) => {
+                    match self.try_recv_msg() {
+                        Ok(Flush(ack)) => { let _ = ack.send_opt(()); }
+                        _ => unreachable!(),
+                    }
+                }
+                Some(..) => return Ok(true),
+                // A hung up channel can also be received from without
+                // blocking, which matters to pollers (selection finds out
+                // about it when it tries to block).
+                None => {
+                    return Ok(is_disconnected(self.cnt.load(atomics::Acquire)))
                 }
             }
-            Some(..) => Ok(true),
-            None => Ok(false)
         }
     }
 
-    // increment the count on the channel (used for selection)
-    fn bump(&mut self, amt: int) -> int {
-        match self.cnt.fetch_add(amt, atomics::SeqCst) {
-            DISCONNECTED => {
-                self.cnt.store(DISCONNECTED, atomics::SeqCst);
-                DISCONNECTED
-            }
-            n => n
-        }
+    // increment the count on the channel (used for selection), returning the
+    // previous state of the channel
+    fn bump(&mut self, amt: int) -> uint {
+        self.cnt.fetch_add(messages(amt), atomics::AcqRel)
     }
 
     // Attempts to start selecting on this port. Like a oneshot, this can fail
@@ -376,7 +548,7 @@ impl<T: Send> Packet<T> {
                 // Undo our decrement above, and we should be guaranteed that the
                 // previous value is positive because we're not going to sleep
                 let prev = self.bump(1);
-                assert!(prev == DISCONNECTED || prev >= 0);
+                assert!(is_disconnected(prev) || count(prev) >= 0);
                 return ret;
             }
         }
@@ -410,12 +582,16 @@ impl<T: Send> Packet<T> {
         let steals = 1;
         let prev = self.bump(steals + 1);
 
-        // If we were previously disconnected, then we know for sure that there
-        // is no task in to_wake, so just keep going
-        let has_data = if prev == DISCONNECTED {
-            assert_eq!(self.to_wake.load(atomics::SeqCst), 0);
+        // If we were previously disconnected, then the sender which hung up
+        // took care of any task in to_wake (it may not have gotten around to
+        // taking it yet), so just keep going
+        let has_data = if is_disconnected(prev) {
+            while self.to_wake.load(atomics::Acquire) != 0 {
+                Thread::yield_now();
+            }
             true // there is data, that data is that we're disconnected
         } else {
+            let prev = count(prev);
             let cur = prev + steals + 1;
             assert!(cur >= 0);
 
@@ -436,7 +612,7 @@ impl<T: Send> Packet<T> {
             if prev < 0 {
                 self.take_to_wake().trash();
             } else {
-                while self.to_wake.load(atomics::SeqCst) != 0 {
+                while self.to_wake.load(atomics::Acquire) != 0 {
                     Thread::yield_now();
                 }
             }
@@ -468,6 +644,69 @@ impl<T: Send> Packet<T> {
     }
 }
 
+impl<T: Send> Queue<T> {
+    fn push(&self, t: T) {
+        match *self {
+            Spsc(ref q) => q.push(t),
+            Custom(ref q) => q.push(t),
+            Stamped(ref q, _) => q.push((t, latency::now())),
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        match *self {
+            Spsc(ref q) => q.pop(),
+            Custom(ref q) => q.pop(),
+            Stamped(ref q, ref last) => {
+                q.pop().map(|(t, sent_at)| { last.set(sent_at); t })
+            }
+        }
+    }
+
+    fn peek<'a>(&'a self) -> Option<&'a mut T> {
+        match *self {
+            Spsc(ref q) => q.peek(),
+            Custom(ref q) => q.peek(),
+            Stamped(ref q, _) => q.peek().map(|m| m.mut0()),
+        }
+    }
+
+    // The time the message popped last was sent at, or 0 if it wasn't stamped
+    fn sent_at(&self) -> u64 {
+        match *self {
+            Stamped(_, ref last) => last.get(),
+            Spsc(..) | Custom(..) => 0,
+        }
+    }
+}
+
+impl<T: Send> Slot<T> {
+    pub fn value<'a>(&'a mut self) -> &'a mut Option<Message<T>> {
+        match *self {
+            NodeSlot(ref mut s) => s.value(),
+            InlineSlot(ref mut v) => v,
+        }
+    }
+}
+
+// Only the receiver can make sense of the steals, so this must be called from
+// the receiving task
+impl<T: Send> fmt::Show for Packet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cnt = self.cnt.load(atomics::SeqCst);
+        try!(write!(f, "stream packet: {} queued, {} steals",
+                    cmp::max(count(cnt) - self.steals, 0), self.steals));
+        if self.to_wake.load(atomics::SeqCst) != 0 {
+            try!(write!(f, ", receiver blocked"));
+        }
+        if is_disconnected(cnt) { try!(write!(f, ", DISCONNECTED")); }
+        if self.port_dropped.load(atomics::SeqCst) {
+            try!(write!(f, ", port dropped"));
+        }
+        Ok(())
+    }
+}
+
 #[unsafe_destructor]
 impl<T: Send> Drop for Packet<T> {
     fn drop(&mut self) {
@@ -475,7 +714,95 @@ impl<T: Send> Drop for Packet<T> {
         // disconnection, but also a proper fence before the read of
         // `to_wake`, so this assert cannot be removed with also removing
         // the `to_wake` assert.
-        assert_eq!(self.cnt.load(atomics::SeqCst), DISCONNECTED);
+        assert!(is_disconnected(self.cnt.load(atomics::SeqCst)));
         assert_eq!(self.to_wake.load(atomics::SeqCst), 0);
     }
 }
+
+// The signed count of messages in a `cnt` word
+fn count(cnt: uint) -> int { (cnt as int) >> 1 }
+
+fn is_disconnected(cnt: uint) -> bool { cnt & DISCONNECTED != 0 }
+
+// The amount to add to a `cnt` word to count `n` more messages
+fn messages(n: int) -> uint { (n as uint) << 1 }
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use std::int;
+    use std::uint;
+    use rustrt::local::Local;
+    use rustrt::task::{BlockedTask, Task};
+
+    use atomics;
+    use super::{count, is_disconnected, messages, DISCONNECTED, ONE};
+    use super::{Packet, MAX_STEALS, SelSuccess, Empty, Disconnected};
+
+    #[test]
+    fn count_wraps() {
+        // a blocked receiver leaves the word just below zero
+        let blocked = 0u - messages(1);
+        assert_eq!(count(blocked), -1);
+        assert_eq!(blocked + ONE, 0);
+        assert_eq!(count(blocked - messages(10)), -11);
+        assert_eq!(count(messages(-5) + messages(7)), 2);
+        assert_eq!(count(uint::MAX), -1);
+        assert!(is_disconnected(uint::MAX));
+    }
+
+    #[test]
+    fn flag_survives_wraparound() {
+        let big = messages(int::MAX >> 1);
+        assert_eq!(count(big), int::MAX >> 1);
+        let cnt = (big | DISCONNECTED) + ONE;
+        assert!(is_disconnected(cnt));
+        assert_eq!(count(cnt), int::MIN >> 1);
+
+        let cnt = (0 | DISCONNECTED) - messages(3);
+        assert!(is_disconnected(cnt));
+        assert_eq!(count(cnt), -3);
+        assert!(!is_disconnected(cnt + messages(3) - DISCONNECTED));
+    }
+
+    #[test]
+    fn packet_across_wraparound() {
+        // As if enough messages had been stolen that the next receive folds
+        // the steals back into the count, taking it down to where the word
+        // wraps around when the receiver blocks
+        let mut p = Packet::<int>::new();
+        p.cnt.store(messages(MAX_STEALS + 1), atomics::SeqCst);
+        p.steals = MAX_STEALS + 1;
+
+        assert!(p.send(1).ok().unwrap().is_none());
+        match p.try_recv() { Ok(1) => {} _ => fail!() }
+        assert_eq!(count(p.cnt.load(atomics::SeqCst)), 1);
+        assert_eq!(p.steals, 1);
+        match p.try_recv() { Err(Empty) => {} _ => fail!() }
+
+        // Selecting takes the word below zero, and the next send brings it
+        // back, handing over the task
+        let task: Box<Task> = Local::take();
+        match p.start_selection(BlockedTask::block(task)) {
+            SelSuccess => {}
+            _ => fail!(),
+        }
+        assert_eq!(p.cnt.load(atomics::SeqCst), 0u - ONE);
+        match p.send(2) {
+            Ok(Some(task)) => Local::put(task.wake().unwrap()),
+            _ => fail!(),
+        }
+        assert_eq!(p.cnt.load(atomics::SeqCst), 0);
+        assert!(p.abort_selection(false).ok().unwrap());
+        match p.recv() { Ok(2) => {} _ => fail!() }
+        match p.try_recv() { Err(Empty) => {} _ => fail!() }
+
+        // Hanging up leaves the count alone
+        p.send(3).ok().unwrap();
+        p.drop_chan();
+        assert!(is_disconnected(p.cnt.load(atomics::SeqCst)));
+        match p.try_recv() { Ok(3) => {} _ => fail!() }
+        match p.try_recv() { Err(Disconnected) => {} _ => fail!() }
+    }
+}
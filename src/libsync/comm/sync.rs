@@ -38,13 +38,18 @@ use core::prelude::*;
 use alloc::boxed::Box;
 use collections::Vec;
 use collections::Collection;
+use core::fmt;
 use core::mem;
 use core::cell::UnsafeCell;
-use rustrt::local::Local;
 use rustrt::mutex::{NativeMutex, LockGuard};
-use rustrt::task::{Task, BlockedTask};
+use rustrt::task;
+use rustrt::task::BlockedTask;
 
 use atomics;
+use comm::discard;
+use comm::latency;
+use comm::poll::Watch;
+use comm::stats::Stats;
 
 pub struct Packet<T> {
     /// Only field outside of the mutex. Just done for kicks, but mainly because
@@ -54,14 +59,22 @@ pub struct Packet<T> {
     /// The state field is protected by this mutex
     lock: NativeMutex,
     state: UnsafeCell<State<T>>,
+
+    /// The poller watching the port, if any. This is only notified outside of
+    /// the mutex, as notifying may wake up (and switch to) the poller.
+    watch: Watch,
+
+    /// The counters of the channel, if it has them
+    pub stats: Stats,
 }
 
 struct State<T> {
-    disconnected: bool, // Is the channel disconnected yet?
-    queue: Queue,       // queue of senders waiting to send data
-    blocker: Blocker,   // currently blocked task on this channel
-    buf: Buffer<T>,     // storage for buffered messages
-    cap: uint,          // capacity of this channel
+    disconnected: bool,  // Is the channel disconnected yet?
+    queue: Queue,        // queue of senders waiting to send data
+    blocker: Blocker,    // currently blocked task on this channel
+    buf: Buffer<T>,      // storage for buffered messages
+    stamps: Buffer<u64>, // when they were sent, if latencies are measured
+    cap: uint,           // capacity of this channel
 
     /// A curious flag used to indicate whether a sender failed or succeeded in
     /// blocking. This is used to transmit information back to the task that it
@@ -70,6 +83,11 @@ struct State<T> {
     /// safely constructed, but it's guaranteed to always have a valid pointer
     /// value.
     canceled: Option<&'static mut bool>,
+
+    /// The number of senders on an unbuffered channel which have told a poller
+    /// that they're about to block with some data. Until their data is in the
+    /// buffer, the port still counts as having something to receive.
+    pending: uint,
 }
 
 /// Possible flavors of tasks who can be blocked on this channel.
@@ -108,8 +126,7 @@ pub enum Failure {
 /// in the meantime. This re-locks the mutex upon returning.
 fn wait(slot: &mut Blocker, f: fn(BlockedTask) -> Blocker,
         lock: &NativeMutex) {
-    let me: Box<Task> = Local::take();
-    me.deschedule(1, |task| {
+    task::deschedule_current(1, |task| {
         match mem::replace(slot, f(task)) {
             NoneBlocked => {}
             _ => unreachable!(),
@@ -138,6 +155,7 @@ impl<T: Send> Packet<T> {
                 blocker: NoneBlocked,
                 cap: cap,
                 canceled: None,
+                pending: 0,
                 queue: Queue {
                     head: 0 as *mut Node,
                     tail: 0 as *mut Node,
@@ -147,10 +165,20 @@ impl<T: Send> Packet<T> {
                     start: 0,
                     size: 0,
                 },
+                stamps: Buffer { buf: Vec::new(), start: 0, size: 0 },
             }),
+            watch: Watch::new(),
+            stats: Stats::off(),
         }
     }
 
+    // Stamps every message with the time it's sent at from now on, for
+    // channels which measure their latencies. Only called on a new packet.
+    pub fn stamp_messages(&mut self) {
+        let state = unsafe { &mut *self.state.get() };
+        state.stamps.buf = Vec::from_fn(state.buf.cap(), |_| None);
+    }
+
     // Locks this channel, returning a guard for the state and the mutable state
     // itself. Care should be taken to ensure that the state does not escape the
     // guard!
@@ -166,14 +194,23 @@ impl<T: Send> Packet<T> {
     }
 
     pub fn send(&self, t: T) -> Result<(), T> {
+        let announced = self.announce();
         let (guard, state) = self.lock();
+        if announced {
+            state.pending -= 1;
+        } else if state.cap == 0 && !state.disconnected && self.watch.watched() {
+            // The port started being polled after we looked, and the poller
+            // has to hear about us before we block.
+            mem::drop(guard);
+            return self.send(t)
+        }
 
         // wait for a slot to become available, and enqueue the data
         while !state.disconnected && state.buf.size() == state.buf.cap() {
             state.queue.enqueue(&self.lock);
         }
         if state.disconnected { return Err(t) }
-        state.buf.enqueue(t);
+        state.enqueue(t);
 
         match mem::replace(&mut state.blocker, NoneBlocked) {
             // if our capacity is 0, then we need to wait for a receiver to be
@@ -185,14 +222,22 @@ impl<T: Send> Packet<T> {
                 assert!(state.canceled.is_none());
                 state.canceled = Some(unsafe { mem::transmute(&mut canceled) });
                 wait(&mut state.blocker, BlockedSender, &self.lock);
-                if canceled {Err(state.buf.dequeue())} else {Ok(())}
+                if canceled {Err(state.dequeue().val0())} else {Ok(())}
             }
 
             // success, we buffered some data
-            NoneBlocked => Ok(()),
+            NoneBlocked => {
+                mem::drop(guard);
+                self.watch.notify();
+                Ok(())
+            }
 
             // success, someone's about to receive our buffered data.
-            BlockedReceiver(task) => { wakeup(task, guard); Ok(()) }
+            BlockedReceiver(task) => {
+                self.stats.woke();
+                wakeup(task, guard);
+                Ok(())
+            }
 
             BlockedSender(..) => fail!("lolwut"),
         }
@@ -211,7 +256,8 @@ impl<T: Send> Packet<T> {
                 NoneBlocked => Err(super::Full(t)),
                 BlockedSender(..) => unreachable!(),
                 BlockedReceiver(task) => {
-                    state.buf.enqueue(t);
+                    state.enqueue(t);
+                    self.stats.woke();
                     wakeup(task, guard);
                     Ok(())
                 }
@@ -221,16 +267,37 @@ impl<T: Send> Packet<T> {
             // just enqueue the data for later retrieval, ensuring to wake up
             // any blocked receiver if there is one.
             assert!(state.buf.size() < state.buf.cap());
-            state.buf.enqueue(t);
+            state.enqueue(t);
             match mem::replace(&mut state.blocker, NoneBlocked) {
-                BlockedReceiver(task) => wakeup(task, guard),
-                NoneBlocked => {}
+                BlockedReceiver(task) => {
+                    self.stats.woke();
+                    wakeup(task, guard)
+                }
+                NoneBlocked => {
+                    mem::drop(guard);
+                    self.watch.notify();
+                }
                 BlockedSender(..) => unreachable!(),
             }
             Ok(())
         }
     }
 
+    // An unbuffered sender blocks with its data in the buffer, and a poller can't
+    // be woken up while the mutex is held. So before sending on an unbuffered
+    // channel whose port is being polled, the sender registers itself as
+    // pending and notifies the poller with the mutex released. Returns whether
+    // the sender was registered.
+    fn announce(&self) -> bool {
+        if !self.watch.watched() { return false }
+        let (guard, state) = self.lock();
+        if state.cap != 0 || state.disconnected { return false }
+        state.pending += 1;
+        mem::drop(guard);
+        self.watch.notify();
+        true
+    }
+
     // Receives a message from this channel
     //
     // When reading this, remember that there can only ever be one receiver at
@@ -242,6 +309,8 @@ impl<T: Send> Packet<T> {
         // because we're the only receiver.
         let mut waited = false;
         if !state.disconnected && state.buf.size() == 0 {
+            let _waiting = self.stats.blocking();
+            self.stats.blocked();
             wait(&mut state.blocker, BlockedReceiver, &self.lock);
             waited = true;
         }
@@ -249,8 +318,9 @@ impl<T: Send> Packet<T> {
 
         // Pick up the data, wake up our neighbors, and carry on
         assert!(state.buf.size() > 0);
-        let ret = state.buf.dequeue();
+        let (ret, sent_at) = state.dequeue();
         self.wakeup_senders(waited, guard, state);
+        self.stats.arrived(sent_at);
         return Ok(ret);
     }
 
@@ -262,10 +332,11 @@ impl<T: Send> Packet<T> {
         if state.buf.size() == 0 { return Err(Empty) }
 
         // Be sure to wake up neighbors
-        let ret = Ok(state.buf.dequeue());
+        let (ret, sent_at) = state.dequeue();
         self.wakeup_senders(false, guard, state);
+        self.stats.arrived(sent_at);
 
-        return ret;
+        return Ok(ret);
     }
 
     // Wake up pending senders after some data has been received
@@ -318,10 +389,11 @@ impl<T: Send> Packet<T> {
         if state.disconnected { return }
         state.disconnected = true;
         match mem::replace(&mut state.blocker, NoneBlocked) {
-            NoneBlocked => {}
+            NoneBlocked => mem::drop(guard),
             BlockedSender(..) => unreachable!(),
             BlockedReceiver(task) => wakeup(task, guard),
         }
+        self.watch.notify();
     }
 
     pub fn drop_port(&self) {
@@ -340,6 +412,7 @@ impl<T: Send> Packet<T> {
         } else {
             Vec::new()
         };
+        let discarded = if state.cap != 0 { state.buf.size() } else { 0 };
         let mut queue = mem::replace(&mut state.queue, Queue {
             head: 0 as *mut Node,
             tail: 0 as *mut Node,
@@ -362,6 +435,7 @@ impl<T: Send> Packet<T> {
             }
         }
         waiter.map(|t| t.wake().map(|t| t.reawaken()));
+        discard::discarded::<T>(&self.stats, discarded);
     }
 
     ////////////////////////////////////////////////////////////////////////////
@@ -370,16 +444,18 @@ impl<T: Send> Packet<T> {
 
     // If Ok, the value is whether this port has data, if Err, then the upgraded
     // port needs to be checked instead of this one.
+    pub fn watch<'a>(&'a self) -> &'a Watch { &self.watch }
+
     pub fn can_recv(&self) -> bool {
         let (_g, state) = self.lock();
-        state.disconnected || state.buf.size() > 0
+        state.disconnected || state.buf.size() > 0 || state.pending > 0
     }
 
     // Attempts to start selection on this port. This can either succeed or fail
     // because there is data waiting.
     pub fn start_selection(&self, task: BlockedTask) -> Result<(), BlockedTask>{
         let (_g, state) = self.lock();
-        if state.disconnected || state.buf.size() > 0 {
+        if state.disconnected || state.buf.size() > 0 || state.pending > 0 {
             Err(task)
         } else {
             match mem::replace(&mut state.blocker, BlockedReceiver(task)) {
@@ -408,6 +484,23 @@ impl<T: Send> Packet<T> {
     }
 }
 
+// Everything is read under the lock, so this is consistent
+impl<T: Send> fmt::Show for Packet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (_g, state) = self.lock();
+        try!(write!(f, "sync packet: {} of {} buffered, {} senders waiting, {} senders",
+                    state.buf.size(), state.cap, state.queue.len(),
+                    self.channels.load(atomics::SeqCst)));
+        match state.blocker {
+            BlockedSender(..) => try!(write!(f, ", sender blocked")),
+            BlockedReceiver(..) => try!(write!(f, ", receiver blocked")),
+            NoneBlocked => {}
+        }
+        if state.disconnected { try!(write!(f, ", DISCONNECTED")); }
+        Ok(())
+    }
+}
+
 #[unsafe_destructor]
 impl<T: Send> Drop for Packet<T> {
     fn drop(&mut self) {
@@ -419,6 +512,22 @@ impl<T: Send> Drop for Packet<T> {
 }
 
 
+impl<T> State<T> {
+    // Buffers a message, along with the time it's sent at if the channel
+    // measures its latencies
+    fn enqueue(&mut self, t: T) {
+        self.buf.enqueue(t);
+        if self.stamps.cap() > 0 { self.stamps.enqueue(latency::now()) }
+    }
+
+    // Takes the next message out of the buffer, with the time it was sent at
+    // (or 0 if it wasn't stamped)
+    fn dequeue(&mut self) -> (T, u64) {
+        let sent_at = if self.stamps.size() > 0 { self.stamps.dequeue() } else { 0 };
+        (self.buf.dequeue(), sent_at)
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Buffer, a simple ring buffer backed by Vec<T>
 ////////////////////////////////////////////////////////////////////////////////
@@ -448,12 +557,11 @@ impl<T> Buffer<T> {
 
 impl Queue {
     fn enqueue(&mut self, lock: &NativeMutex) {
-        let task: Box<Task> = Local::take();
         let mut node = Node {
             task: None,
             next: 0 as *mut Node,
         };
-        task.deschedule(1, |task| {
+        task::deschedule_current(1, |task| {
             node.task = Some(task);
             if self.tail.is_null() {
                 self.head = &mut node as *mut Node;
@@ -485,4 +593,14 @@ impl Queue {
             Some((*node).task.take_unwrap())
         }
     }
+
+    fn len(&self) -> uint {
+        let mut n = 0;
+        let mut node = self.head;
+        while !node.is_null() {
+            n += 1;
+            node = unsafe { (*node).next };
+        }
+        n
+    }
 }
@@ -0,0 +1,194 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Tracing of channel operations
+//!
+//! Once a tracer is set with `set_tracer`, the channels created from then on
+//! are created with statistics (see `channel_with_stats`), and the counters
+//! hand each event to the tracer as they're updated. Channels created before
+//! that aren't traced, unless they were created with statistics already.
+//! Setting the tracer to `None` stops the tracing, although the channels
+//! created in the meantime keep their statistics.
+//!
+//! The tracer is kept behind a global pointer, and checking for it costs one
+//! atomic load per counter update. Tracers are never freed, as there's no way
+//! to tell that no task is still calling one which has been replaced.
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+// Like the counters, the tracer is left out of the model checker's reach
+use core::atomics;
+use core::mem;
+use rustrt::local::Local;
+use rustrt::task::Task;
+use rustrt::time;
+
+static mut TRACER: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+/// Something which happened on a channel, as given to a `Tracer`.
+#[deriving(PartialEq, Clone, Show)]
+#[experimental]
+pub struct Event {
+    /// What happened.
+    pub kind: EventKind,
+    /// The channel it happened on. This is the same identifier as that of
+    /// the channel in `debug::dump`.
+    pub channel: uint,
+    /// The task it happened on, or 0 if it happened outside of a task. The
+    /// identifier is unique among the tasks which are running.
+    pub task: uint,
+    /// When it happened, in nanoseconds, from the same clock as
+    /// `precise_time_ns`.
+    pub time: u64,
+}
+
+/// The kinds of `Event`.
+#[deriving(PartialEq, Clone, Show)]
+#[experimental]
+pub enum EventKind {
+    /// A message was sent.
+    SendEvent,
+    /// A message was received.
+    RecvEvent,
+    /// The receiver is about to block waiting for a message. It sometimes
+    /// carries on without blocking, when a message arrives just then, in
+    /// which case there's no `WakeEvent` to go with it.
+    BlockEvent,
+    /// A sender woke up the blocked receiver.
+    WakeEvent,
+    /// The channel switched to a flavor which can hold more messages, or
+    /// which can have more senders.
+    UpgradeEvent,
+    /// A sender was dropped.
+    SenderGoneEvent,
+    /// The receiver was dropped.
+    ReceiverGoneEvent,
+}
+
+/// A sink for the events of channel operations.
+#[experimental]
+pub trait Tracer {
+    /// Called with each event, on the task it happened on.
+    ///
+    /// Events can be traced while a channel's internal lock is held, so this
+    /// must be quick, must not block and must not use channels itself (nor
+    /// the locks of this crate, which wait on channels). It's best to store
+    /// the event in a buffer which is processed elsewhere, behind a native
+    /// mutex if need be.
+    fn event(&self, event: &Event);
+}
+
+/// Sets the tracer which is given the events of channel operations from now
+/// on, replacing the previous one, or stops tracing with `None`.
+///
+/// # Example
+///
+/// ```
+/// use std::comm::{Tracer, Event, set_tracer};
+/// use std::sync::atomics::{AtomicUint, SeqCst};
+///
+/// struct Count(AtomicUint);
+///
+/// impl Tracer for Count {
+///     fn event(&self, _: &Event) {
+///         let Count(ref n) = *self;
+///         n.fetch_add(1, SeqCst);
+///     }
+/// }
+///
+/// set_tracer(Some(box Count(AtomicUint::new(0))));
+/// let (tx, rx) = channel();
+/// tx.send(1i);
+/// rx.recv();
+/// set_tracer(None);
+/// ```
+#[experimental]
+pub fn set_tracer(tracer: Option<Box<Tracer + Send + Share>>) {
+    let tracer = match tracer {
+        Some(tracer) => {
+            let tracer: Box<Box<Tracer + Send + Share>> = box tracer;
+            unsafe { mem::transmute(tracer) }
+        }
+        None => 0u,
+    };
+    unsafe { TRACER.store(tracer, atomics::SeqCst) }
+}
+
+/// Returns whether a tracer has been set.
+pub fn tracing() -> bool {
+    unsafe { TRACER.load(atomics::Relaxed) != 0 }
+}
+
+/// Hands an event which just happened on `channel` to the tracer, if there is
+/// one.
+pub fn event(kind: EventKind, channel: uint) {
+    let tracer = unsafe { TRACER.load(atomics::Acquire) };
+    if tracer == 0 { return }
+    let task: Option<*mut Task> = unsafe { Local::try_unsafe_borrow() };
+    let event = Event {
+        kind: kind,
+        channel: channel,
+        task: task.map(|t| t as uint).unwrap_or(0),
+        time: time::precise_time_ns(),
+    };
+    unsafe {
+        let tracer: &Box<Tracer + Send + Share> = mem::transmute(tracer);
+        tracer.event(&event);
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::prelude::*;
+
+    use Arc;
+    use comm::{UnsafeFlavor, channel_with_stats};
+    use rustrt::exclusive::Exclusive;
+    use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+    use super::{Tracer, Event, EventKind, set_tracer};
+    use super::{SendEvent, RecvEvent, SenderGoneEvent, ReceiverGoneEvent};
+
+    struct Record(Arc<Exclusive<Vec<Event>>>);
+
+    impl Tracer for Record {
+        fn event(&self, event: &Event) {
+            let Record(ref events) = *self;
+            unsafe { events.lock().push(event.clone()); }
+        }
+    }
+
+    // Held while there's a tracer, by the tests which depend on whether there
+    // is one
+    pub static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+
+    // There's only one tracer for the whole process, which sees the channels
+    // of the other tests too, so only this test's channel is looked at
+    #[test]
+    fn events() {
+        let _g = unsafe { LOCK.lock() };
+        let events = Arc::new(Exclusive::new(Vec::new()));
+        set_tracer(Some(box Record(events.clone())));
+
+        let (tx, rx) = channel_with_stats::<int>();
+        let id = unsafe { rx.inner().stats().id().unwrap() };
+        tx.send(1);
+        assert_eq!(rx.recv(), 1);
+        drop(tx);
+        drop(rx);
+
+        set_tracer(None);
+        let kinds: Vec<EventKind> = unsafe {
+            events.lock().iter().filter(|e| e.channel == id).map(|e| e.kind).collect()
+        };
+        assert_eq!(kinds, vec![SendEvent, RecvEvent, SenderGoneEvent,
+                               ReceiverGoneEvent]);
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/// Soft bounds for asynchronous channels
+///
+/// A watermarked channel is an ordinary asynchronous channel (it still goes
+/// through the oneshot/stream/shared upgrades) whose senders will block once
+/// the number of queued messages reaches a high watermark. Blocked senders are
+/// released once the receiver has drained the queue down to the low
+/// watermark. Unlike a `sync_channel`, a send always enqueues its message
+/// before (possibly) blocking, so the receiving half is entirely unaware of
+/// the bound.
+///
+/// The packets' own `cnt` fields are not suitable for measuring the depth of
+/// the queue because they do not take the receiver's steals into account, so
+/// a separate depth counter is shared among all the halves of the channel.
+///
+/// The blocking protocol is a slow path guarded by a native mutex. A sender
+/// which observes the high watermark registers itself as a waiter *before*
+/// re-checking the depth, and a receiver checks for waiters *after*
+/// decrementing the depth, so one of the two is guaranteed to see the other.
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+use collections::{Vec, MutableSeq};
+use core::cell::UnsafeCell;
+use core::mem;
+use rustrt::mutex::NativeMutex;
+use rustrt::task;
+use rustrt::task::BlockedTask;
+
+use atomics;
+
+pub struct Watermark {
+    high: uint,
+    low: uint,
+
+    // Number of messages which have been sent but not yet received
+    depth: atomics::AtomicUint,
+    // Number of senders which are (or are about to start) waiting on `lock`
+    waiters: atomics::AtomicUint,
+    // Flagged once the receiver has gone away, no one will ever block again
+    closed: atomics::AtomicBool,
+
+    // protects `blocked`
+    lock: NativeMutex,
+    blocked: UnsafeCell<Vec<BlockedTask>>,
+}
+
+impl Watermark {
+    pub fn new(high: uint, low: uint) -> Watermark {
+        assert!(low < high, "the low watermark must be below the high one");
+        Watermark {
+            high: high,
+            low: low,
+            depth: atomics::AtomicUint::new(0),
+            waiters: atomics::AtomicUint::new(0),
+            closed: atomics::AtomicBool::new(false),
+            lock: unsafe { NativeMutex::new() },
+            blocked: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// Invoked by a sender after it has successfully enqueued a message. This
+    /// will block the current task if the high watermark has been reached.
+    pub fn pushed(&self) {
+        let depth = self.depth.fetch_add(1, atomics::SeqCst) + 1;
+        if depth < self.high { return }
+
+        unsafe {
+            self.lock.lock_noguard();
+            self.waiters.fetch_add(1, atomics::SeqCst);
+            while self.depth.load(atomics::SeqCst) > self.low &&
+                  !self.closed.load(atomics::SeqCst) {
+                task::deschedule_current(1, |task| {
+                    (*self.blocked.get()).push(task);
+                    self.lock.unlock_noguard();
+                    Ok(())
+                });
+                self.lock.lock_noguard();
+            }
+            self.waiters.fetch_sub(1, atomics::SeqCst);
+            self.lock.unlock_noguard();
+        }
+    }
+
+    /// Invoked by the receiver whenever it has dequeued a message. If the low
+    /// watermark has been reached, all blocked senders are woken up.
+    pub fn popped(&self) {
+        let depth = self.depth.fetch_sub(1, atomics::SeqCst) - 1;
+        if depth <= self.low && self.waiters.load(atomics::SeqCst) > 0 {
+            self.wake_all();
+        }
+    }
+
+    /// Invoked when the receiver is dropped. No sender will block on this
+    /// channel after this returns.
+    pub fn close(&self) {
+        self.closed.store(true, atomics::SeqCst);
+        if self.waiters.load(atomics::SeqCst) > 0 {
+            self.wake_all();
+        }
+    }
+
+    /// Returns the number of messages currently sitting in the channel.
+    pub fn depth(&self) -> uint { self.depth.load(atomics::SeqCst) }
+
+    fn wake_all(&self) {
+        // As with sync channels, the tasks are woken up *outside* of the mutex
+        // in case a context switch is incurred.
+        let tasks = unsafe {
+            let _g = self.lock.lock();
+            mem::replace(&mut *self.blocked.get(), Vec::new())
+        };
+        for task in tasks.move_iter() {
+            task.wake().map(|t| t.reawaken());
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl Drop for Watermark {
+    fn drop(&mut self) {
+        assert_eq!(self.waiters.load(atomics::SeqCst), 0);
+        assert!(unsafe { (*self.blocked.get()).len() } == 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use super::super::*;
+
+    test!(fn smoke() {
+        let (tx, rx) = channel_with_watermarks(2, 0);
+        tx.send(1i);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.try_recv(), Err(Empty));
+    })
+
+    test!(fn blocks_at_high_watermark() {
+        let (tx, rx) = channel_with_watermarks(4, 1);
+        let (donetx, donerx) = channel();
+        spawn(proc() {
+            for i in range(0i, 4) { tx.send(i); }
+            donetx.send(());
+        });
+
+        // The sender can't finish until we've drained down to the low
+        // watermark.
+        for _ in range(0u, 100) { task::deschedule(); }
+        assert_eq!(donerx.try_recv(), Err(Empty));
+        assert_eq!(rx.recv(), 0);
+        assert_eq!(rx.recv(), 1);
+        assert_eq!(rx.recv(), 2);
+        donerx.recv();
+        assert_eq!(rx.recv(), 3);
+    })
+
+    test!(fn shared_senders_block() {
+        let (tx, rx) = channel_with_watermarks(8, 2);
+        for _ in range(0u, 4) {
+            let tx = tx.clone();
+            spawn(proc() {
+                for _ in range(0u, 100) { tx.send(1i); }
+            });
+        }
+        drop(tx);
+        assert_eq!(rx.iter().fold(0, |a, b| a + b), 400);
+    })
+
+    test!(fn port_gone_unblocks_senders() {
+        let (tx, rx) = channel_with_watermarks(1, 0);
+        let (donetx, donerx) = channel();
+        spawn(proc() {
+            // This send blocks until the receiver goes away
+            tx.send(1i);
+            assert!(tx.send_opt(2).is_err());
+            donetx.send(());
+        });
+        for _ in range(0u, 100) { task::deschedule(); }
+        drop(rx);
+        donerx.recv();
+    })
+}
@@ -0,0 +1,236 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Deadlock detection
+//!
+//! When the `RUST_DEADLOCK_DETECT` environment variable is set, mutexes keep
+//! track of which task holds them and which tasks are waiting for them. This
+//! makes up a "wait-for" graph, which has a cycle exactly when a number of
+//! tasks are each waiting for a mutex held by the next one. The task which is
+//! about to close such a cycle fails instead of blocking, with a description
+//! of the cycle, and leaves the mutex as it found it.
+//!
+//! The mutexes tracked are those of `Mutex` and `raw::Mutex`, including while
+//! they're reacquired after waiting on one of their condition variables. Only
+//! those are tracked: tasks waiting on a condition variable, a semaphore, an
+//! rwlock or a channel (`recv`, `select`, a full `sync_channel`) may be woken
+//! up by any other task, so they aren't part of the graph, and a deadlock
+//! which goes through one of them isn't detected.
+//!
+//! Tracking has to take a global lock on every lock and unlock, so it's only
+//! meant for debugging.
+
+use core::prelude::*;
+
+use collections::{Vec, MutableSeq, String};
+use core::atomics;
+use core::fmt;
+use core::mem;
+use rustrt::local::Local;
+use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+use rustrt::task::Task;
+
+// Whether tracking is enabled: unknown until the environment has been looked
+// at, and then either on or off
+static UNKNOWN: uint = 0;
+static OFF: uint = 1;
+static ON: uint = 2;
+static mut ENABLED: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut GRAPH: *mut Graph = 0 as *mut Graph;
+
+struct Graph {
+    // (mutex, owning task, name of the owning task)
+    owners: Vec<(uint, uint, String)>,
+    // (task, mutex it's waiting for)
+    waiting: Vec<(uint, uint)>,
+}
+
+/// A cycle in the graph, as the (task name, mutex) pairs along it
+pub struct Cycle {
+    steps: Vec<(String, uint)>,
+}
+
+/// Returns whether deadlock detection is enabled.
+#[inline]
+pub fn enabled() -> bool {
+    match unsafe { ENABLED.load(atomics::Relaxed) } {
+        UNKNOWN => check_env(),
+        n => n == ON,
+    }
+}
+
+fn check_env() -> bool {
+    extern { fn getenv(name: *const u8) -> *const u8; }
+    let on = unsafe { !getenv(b"RUST_DEADLOCK_DETECT\0".as_ptr()).is_null() };
+    unsafe { ENABLED.store(if on {ON} else {OFF}, atomics::Relaxed); }
+    on
+}
+
+/// Records that the current task is about to block on `mutex`, or returns the
+/// cycle blocking would close without recording anything. The caller is then
+/// expected to fail, after undoing whatever it did towards blocking.
+pub fn waiting(mutex: uint) -> Result<(), Cycle> {
+    let me = current();
+    with_graph(|g| {
+        match g.cycle_from(me, mutex) {
+            Some(cycle) => Err(cycle),
+            None => { g.waiting.push((me, mutex)); Ok(()) }
+        }
+    })
+}
+
+/// Records that the current task now holds `mutex`.
+pub fn acquired(mutex: uint) {
+    let me = current();
+    let name = name();
+    with_graph(|g| {
+        match g.waiting.iter().position(|&(t, _)| t == me) {
+            Some(i) => { g.waiting.swap_remove(i); }
+            None => {}
+        }
+        g.owners.push((mutex, me, name));
+    });
+}
+
+/// Records that `mutex` was unlocked.
+pub fn released(mutex: uint) {
+    with_graph(|g| {
+        match g.owners.iter().position(|&(m, _, _)| m == mutex) {
+            Some(i) => { g.owners.swap_remove(i); }
+            None => {}
+        }
+    });
+}
+
+impl Graph {
+    // Follows the graph from `mutex`, which `me` wants to lock
+    fn cycle_from(&self, me: uint, mutex: uint) -> Option<Cycle> {
+        let mut steps = Vec::new();
+        let mut mutex = mutex;
+        steps.push((name(), mutex));
+        // No cycle can go through more tasks than are waiting, and one which
+        // doesn't reach `me` would have been noticed as it was formed
+        for _ in range(0, self.waiting.len() + 1) {
+            let (owner, owner_name) = match self.owner(mutex) {
+                Some((owner, name)) => (owner, name),
+                None => return None,
+            };
+            if owner == me { return Some(Cycle { steps: steps }) }
+            mutex = match self.waiting.iter().find(|&&(t, _)| t == owner) {
+                Some(&(_, m)) => m,
+                None => return None,
+            };
+            steps.push((owner_name.clone(), mutex));
+        }
+        None
+    }
+
+    fn owner<'a>(&'a self, mutex: uint) -> Option<(uint, &'a String)> {
+        self.owners.iter().find(|&&(m, _, _)| m == mutex).map(|&(_, t, ref n)| {
+            (t, n)
+        })
+    }
+}
+
+impl fmt::Show for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, &(ref name, mutex)) in self.steps.iter().enumerate() {
+            let &(ref next, _) = self.steps.get((i + 1) % self.steps.len());
+            try!(writeln!(f, "  task '{}' is waiting for mutex {:#x}, held by \
+                              task '{}'", name, mutex, next));
+        }
+        Ok(())
+    }
+}
+
+fn with_graph<T>(f: |&mut Graph| -> T) -> T {
+    unsafe {
+        let _g = LOCK.lock();
+        if GRAPH.is_null() {
+            GRAPH = mem::transmute(box Graph {
+                owners: Vec::new(),
+                waiting: Vec::new(),
+            });
+        }
+        f(&mut *GRAPH)
+    }
+}
+
+fn current() -> uint {
+    let task: Option<*mut Task> = unsafe { Local::try_unsafe_borrow() };
+    task.map(|t| t as uint).unwrap_or(0)
+}
+
+fn name() -> String {
+    let task: Option<*mut Task> = unsafe { Local::try_unsafe_borrow() };
+    let name = task.and_then(|t| unsafe { (*t).name.as_ref() });
+    String::from_str(name.map(|n| n.as_slice()).unwrap_or("<unnamed>"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use super::{with_graph, waiting, acquired, released, current};
+
+    // These drive the graph directly, pretending that other tasks are locking
+    // mutexes, so they don't depend on the environment variable
+
+    #[test]
+    fn no_cycle() {
+        let me = current();
+        released(1);
+        acquired(1);
+        waiting(2);
+        acquired(2);
+        released(2);
+        released(1);
+        with_graph(|g| assert!(g.owners.iter().all(|&(_, t, _)| t != me)));
+    }
+
+    #[test]
+    fn two_task_cycle() {
+        let me = current();
+        let other = me + 8;
+        acquired(3);
+        with_graph(|g| {
+            g.owners.push((4, other, "other".to_string()));
+            g.waiting.push((other, 3));
+        });
+        let msg = with_graph(|g| g.cycle_from(me, 4).map(|c| format!("{}", c)));
+        let msg = msg.unwrap();
+        assert!(msg.as_slice().contains("'other'"));
+        with_graph(|g| {
+            g.owners.retain(|&(m, _, _)| m != 4);
+            g.waiting.retain(|&(t, _)| t != other);
+        });
+        released(3);
+    }
+
+    #[test]
+    fn cycle_isnt_recorded() {
+        let me = current();
+        let other = me + 8;
+        acquired(5);
+        with_graph(|g| {
+            g.owners.push((6, other, "other".to_string()));
+            g.waiting.push((other, 5));
+        });
+        assert!(waiting(6).is_err());
+        with_graph(|g| {
+            assert!(g.waiting.iter().all(|&(t, _)| t != me));
+            g.owners.retain(|&(m, _, _)| m != 6);
+            g.waiting.retain(|&(t, _)| t != other);
+        });
+        released(5);
+    }
+}
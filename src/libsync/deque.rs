@@ -41,6 +41,40 @@
 //!     worker.push(1i);
 //!     let mut stealer2 = stealer.clone();
 //!     stealer2.steal();
+//!
+//! # Balancing work among tasks
+//!
+//! A pool of tasks can give each task a deque of its own. Each task pushes the
+//! jobs it creates onto its own worker, and pops jobs off of it as long as
+//! there are any. A task which has run out of work steals jobs from the other
+//! tasks' stealers, which take the oldest jobs, and so usually the largest
+//! ones. Stealers are cheap to clone and may be sent to other tasks, while a
+//! worker stays with the task which owns the deque.
+//!
+//!     use std::sync::deque::{BufferPool, Data, Abort, Empty};
+//!
+//!     let pool = BufferPool::new();
+//!     let (worker, stealer) = pool.deque();
+//!     for job in range(0u, 100) { worker.push(job); }
+//!
+//!     let (tx, rx) = channel();
+//!     spawn(proc() {
+//!         let mut done = 0u;
+//!         loop {
+//!             match stealer.steal() {
+//!                 Data(..) => done += 1,
+//!                 // Lost a race for a job with the worker, try again
+//!                 Abort => {}
+//!                 Empty => break,
+//!             }
+//!         }
+//!         tx.send(done);
+//!     });
+//!
+//!     let mut done = 0u;
+//!     while worker.pop().is_some() { done += 1; }
+//!     // Every job was taken exactly once, by one task or the other
+//!     assert_eq!(done + rx.recv(), 100);
 
 #![experimental]
 
@@ -193,6 +227,25 @@ impl<T: Send> Worker<T> {
         unsafe { self.deque.pop() }
     }
 
+    /// Returns the number of values in the deque. Stealers may take values at
+    /// any time, so the deque may hold fewer values by the time this returns.
+    pub fn len(&self) -> uint {
+        let b = self.deque.bottom.load(SeqCst);
+        let t = self.deque.top.load(SeqCst);
+        if b > t { (b - t) as uint } else { 0 }
+    }
+
+    /// Returns whether the deque is empty. Only the worker may push values, so
+    /// an empty deque stays empty until the worker pushes onto it.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Creates another stealer for this deque.
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer { deque: self.deque.clone(), noshare: marker::NoShare }
+    }
+
     /// Gets access to the buffer pool that this worker is attached to. This can
     /// be used to create more deques which share the same buffer pool as this
     /// deque.
@@ -432,6 +485,22 @@ mod tests {
         assert_eq!(s.clone().steal(), Data(1));
     }
 
+    #[test]
+    fn worker_len() {
+        let pool = BufferPool::new();
+        let (w, s) = pool.deque();
+        assert!(w.is_empty());
+        for i in range(0i, 300) { w.push(i); }
+        assert_eq!(w.len(), 300);
+        assert_eq!(s.steal(), Data(0));
+        assert_eq!(w.stealer().steal(), Data(1));
+        assert_eq!(w.pop(), Some(299));
+        assert_eq!(w.len(), 297);
+        while w.pop().is_some() {}
+        assert!(w.is_empty());
+        assert_eq!(w.stealer().steal(), Empty);
+    }
+
     #[test]
     fn stealpush() {
         static AMT: int = 100000;
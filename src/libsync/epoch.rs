@@ -0,0 +1,284 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Epoch-based memory reclamation
+//!
+//! A lock-free structure can unlink a node while other tasks are still
+//! looking at it, so it can't free the node right away. A `Collector` tracks
+//! which tasks may still be looking at unlinked nodes, and destroys them once
+//! none can be.
+//!
+//! Tasks *pin* the collector for as long as they hold on to pointers into the
+//! structure, which is usually the duration of a single operation. Unlinked
+//! nodes are handed to the collector with `defer_drop`, and they are dropped
+//! once every task which was pinned at that time has unpinned.
+//!
+//! # Epochs
+//!
+//! The collector has a global epoch, and pinned tasks register themselves with
+//! one of two counters, chosen by the parity of the epoch they saw. The epoch
+//! only advances when the counter of the previous epoch has drained, so pinned
+//! tasks have always seen either the current epoch or the one before it.
+//! Garbage deferred in some epoch can then be dropped once the epoch has
+//! advanced twice, as every task which could have seen it has unpinned by
+//! then.
+//!
+//! A task which stays pinned keeps the epoch from advancing, so garbage piles
+//! up until it unpins. Pins should be short.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::atomics::{AtomicPtr, SeqCst};
+//! use std::sync::epoch::Collector;
+//! use std::mem;
+//!
+//! let collector = Collector::new();
+//! let shared = AtomicPtr::new(unsafe { mem::transmute(box 1i) });
+//!
+//! // Readers pin the collector while they look at the value
+//! {
+//!     let _guard = collector.pin();
+//!     let p = shared.load(SeqCst);
+//!     assert_eq!(unsafe { *p }, 1);
+//! }
+//!
+//! // Writers swap the value and leave the old one to the collector
+//! let old = shared.swap(unsafe { mem::transmute(box 2i) }, SeqCst);
+//! collector.defer_drop(unsafe { mem::transmute::<*mut int, Box<int>>(old) });
+//! # let last = shared.load(SeqCst);
+//! # collector.defer_drop(unsafe { mem::transmute::<*mut int, Box<int>>(last) });
+//! ```
+
+#![experimental]
+
+use core::prelude::*;
+
+use collections::{Vec, MutableSeq};
+use core::mem;
+use rustrt::exclusive::Exclusive;
+
+use atomics::{AtomicUint, SeqCst};
+
+// Deferred garbage is looked at once this much of it has piled up
+static COLLECT_THRESHOLD: uint = 32;
+
+/// Tracks pinned tasks and defers the destruction of garbage until no pinned
+/// task can be looking at it.
+pub struct Collector {
+    epoch: AtomicUint,
+    pinned0: AtomicUint,    // tasks pinned in even epochs
+    pinned1: AtomicUint,    // tasks pinned in odd epochs
+    garbage: Exclusive<Vec<(uint, proc():Send)>>,
+}
+
+/// An RAII guard which keeps a collector pinned. Garbage deferred while a
+/// guard is alive isn't dropped before the guard is.
+pub struct Guard<'a> {
+    collector: &'a Collector,
+    epoch: uint,
+}
+
+impl Collector {
+    /// Creates a new collector, with no garbage.
+    pub fn new() -> Collector {
+        Collector {
+            epoch: AtomicUint::new(0),
+            pinned0: AtomicUint::new(0),
+            pinned1: AtomicUint::new(0),
+            garbage: Exclusive::new(Vec::new()),
+        }
+    }
+
+    /// Pins the current task until the returned guard is dropped. Pointers
+    /// loaded from a structure using this collector may be used until then.
+    pub fn pin<'a>(&'a self) -> Guard<'a> {
+        loop {
+            let e = self.epoch.load(SeqCst);
+            self.pinned(e).fetch_add(1, SeqCst);
+            if self.epoch.load(SeqCst) == e {
+                return Guard { collector: self, epoch: e }
+            }
+            self.pinned(e).fetch_sub(1, SeqCst);
+        }
+    }
+
+    /// Drops `t` once no task which is currently pinned can be looking at it.
+    ///
+    /// `t` must already be unreachable for tasks which pin the collector from
+    /// now on, usually because it has been unlinked from a structure.
+    pub fn defer_drop<T: Send>(&self, t: T) {
+        self.defer(proc() drop(t))
+    }
+
+    /// Runs `f` once no task which is currently pinned can be looking at the
+    /// garbage it destroys.
+    pub fn defer(&self, f: proc():Send) {
+        let pending = unsafe {
+            let mut garbage = self.garbage.lock();
+            garbage.push((self.epoch.load(SeqCst), f));
+            garbage.len()
+        };
+        if pending >= COLLECT_THRESHOLD {
+            self.collect();
+        }
+    }
+
+    /// Advances the epoch if possible, and then destroys the garbage which no
+    /// pinned task can be looking at anymore. This happens on its own as
+    /// garbage piles up, but may be called to clean up sooner.
+    pub fn collect(&self) {
+        self.try_advance();
+        let epoch = self.epoch.load(SeqCst);
+        let ready = unsafe {
+            let mut garbage = self.garbage.lock();
+            let all = mem::replace(&mut *garbage, Vec::new());
+            let (ready, rest) = all.partition(|&(e, _)| epoch - e >= 2);
+            *garbage = rest;
+            ready
+        };
+        // Destructors may defer more garbage, so they run without the lock
+        for (_, f) in ready.move_iter() {
+            f();
+        }
+    }
+
+    // Tasks which pinned the previous epoch may still be looking at garbage
+    // deferred in it, so the epoch can't move on until they're gone. Those
+    // which pinned the current epoch are still allowed in the next one.
+    fn try_advance(&self) {
+        let e = self.epoch.load(SeqCst);
+        if self.pinned(e - 1).load(SeqCst) == 0 {
+            self.epoch.compare_and_swap(e, e + 1, SeqCst);
+        }
+    }
+
+    fn pinned<'a>(&'a self, epoch: uint) -> &'a AtomicUint {
+        if epoch & 1 == 0 {&self.pinned0} else {&self.pinned1}
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        // No one can be pinned while we're being destroyed
+        let garbage = unsafe {
+            mem::replace(&mut *self.garbage.lock(), Vec::new())
+        };
+        for (_, f) in garbage.move_iter() {
+            f();
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for Guard<'a> {
+    fn drop(&mut self) {
+        self.collector.pinned(self.epoch).fetch_sub(1, SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use std::mem;
+    use alloc::arc::Arc;
+    use native;
+    use atomics::{AtomicPtr, AtomicUint, SeqCst};
+    use super::Collector;
+
+    struct Counted(Arc<AtomicUint>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            let Counted(ref count) = *self;
+            count.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    fn waits_for_pins() {
+        let c = Collector::new();
+        let dropped = Arc::new(AtomicUint::new(0));
+        let g = c.pin();
+        c.defer_drop(Counted(dropped.clone()));
+        for _ in range(0u, 10) { c.collect(); }
+        assert_eq!(dropped.load(SeqCst), 0);
+
+        drop(g);
+        c.collect();
+        c.collect();
+        assert_eq!(dropped.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn pins_nest() {
+        let c = Collector::new();
+        let dropped = Arc::new(AtomicUint::new(0));
+        let g1 = c.pin();
+        c.defer_drop(Counted(dropped.clone()));
+        c.collect();
+        // a task which pins after the garbage was deferred can't be looking
+        // at it, so it doesn't hold it up
+        let g2 = c.pin();
+        drop(g1);
+        c.collect();
+        c.collect();
+        assert_eq!(dropped.load(SeqCst), 1);
+        drop(g2);
+    }
+
+    #[test]
+    fn drop_collector() {
+        let dropped = Arc::new(AtomicUint::new(0));
+        {
+            let c = Collector::new();
+            let _g = c.pin();
+            for _ in range(0u, 100) {
+                c.defer_drop(Counted(dropped.clone()));
+            }
+        }
+        assert_eq!(dropped.load(SeqCst), 100);
+    }
+
+    #[test]
+    fn stress() {
+        static NTHREADS: uint = 8;
+        static NSWAPS: uint = 10000;
+        let c = Arc::new(Collector::new());
+        let shared = Arc::new(AtomicPtr::new(unsafe {
+            mem::transmute(box 0u)
+        }));
+        let (tx, rx) = channel();
+
+        for _ in range(0, NTHREADS) {
+            let (c, shared, tx) = (c.clone(), shared.clone(), tx.clone());
+            native::task::spawn(proc() {
+                let mut last = 0;
+                for _ in range(0, NSWAPS) {
+                    let _g = c.pin();
+                    let cur = unsafe { *shared.load(SeqCst) };
+                    assert!(cur >= last);
+                    last = cur;
+                }
+                tx.send(());
+            });
+        }
+
+        for i in range(1, NSWAPS) {
+            let old = shared.swap(unsafe { mem::transmute(box i) }, SeqCst);
+            c.defer_drop(unsafe { mem::transmute::<*mut uint, Box<uint>>(old) });
+        }
+        drop(tx);
+        for _ in range(0, NTHREADS) { rx.recv(); }
+        let last = shared.load(SeqCst);
+        c.defer_drop(unsafe { mem::transmute::<*mut uint, Box<uint>>(last) });
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fault injection for the tests of this crate
+//!
+//! When the tests are run with `RUST_COMM_FAULT_SEED` set to a number, every
+//! atomic operation (and so every transition of the state of a channel
+//! packet) may first yield the thread or spin for a while, which widens the
+//! windows in which races happen, such as that between `DATA` and
+//! `DISCONNECTED` in `try_recv`. Whether an operation is delayed, and for how
+//! long, is decided by a hash of the seed, of the number of the thread (in the
+//! order the threads first got here) and of the number of operations which
+//! went before it on that thread. The threads of a test don't change each
+//! other's faults, so a run can be repeated with the same seed to get the same
+//! faults on each thread (though the OS still has its say in how the threads
+//! interleave, and in which thread gets which number).
+//!
+//! The stress tests are the ones which get something from this, for instance
+//! with `RUST_COMM_FAULT_SEED=1 make check-stage1-sync`. A seed which isn't a
+//! number fails the `seed` test below, and no faults are injected.
+
+use std::prelude::*;
+
+use core::atomics;
+use core::uint;
+use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+use rustrt::thread::Thread;
+use tls = rustrt::thread_local_storage;
+
+use backoff::{Backoff, Exponential};
+
+// One in this many operations yields the thread, and as many again spin
+static ONE_IN: u64 = 16;
+// The longest spin is `1 << MAX_DOUBLINGS` iterations
+static MAX_DOUBLINGS: u64 = 12;
+
+// Whether injection is enabled: unknown until the environment has been looked
+// at, and then either on, with the seed in `SEED`, or off
+static UNKNOWN: uint = 0;
+static OFF: uint = 1;
+static ON: uint = 2;
+static mut ENABLED: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+static mut SEED: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+// Each thread's value of `KEY` holds its number in the upper half, and the
+// number of operations it went through in the lower half, plus one so that
+// null means the thread hasn't been numbered yet
+static HALF: uint = uint::BITS / 2;
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut KEY: tls::Key = 0;
+static mut KEY_CREATED: atomics::AtomicBool = atomics::INIT_ATOMIC_BOOL;
+static mut NEXT_THREAD: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+#[deriving(PartialEq, Show)]
+enum Fault { NoFault, YieldFault, SpinFault(uint) }
+
+/// Maybe delays the calling thread, if faults are being injected.
+pub fn inject() {
+    let seed = match seed() {
+        Some(seed) => seed,
+        None => return,
+    };
+    let (thread, n) = unsafe { next_operation() };
+    match fault(seed ^ thread, n) {
+        NoFault => {}
+        YieldFault => Thread::yield_now(),
+        // A limit past the step spins without ever yielding
+        SpinFault(step) => Exponential { limit: step + 1 }.snooze(step),
+    }
+}
+
+fn seed() -> Option<uint> {
+    match unsafe { ENABLED.load(atomics::Acquire) } {
+        UNKNOWN => check_env(),
+        ON => Some(unsafe { SEED.load(atomics::Relaxed) }),
+        _ => None,
+    }
+}
+
+fn check_env() -> Option<uint> {
+    // A seed which isn't a number leaves injection off rather than failing
+    // whichever operation first gets here (see the `seed` test)
+    let seed = ::std::os::getenv("RUST_COMM_FAULT_SEED").and_then(|s| {
+        from_str::<uint>(s.as_slice())
+    });
+    unsafe {
+        match seed {
+            Some(seed) => {
+                SEED.store(seed, atomics::Relaxed);
+                ENABLED.store(ON, atomics::Release);
+            }
+            None => ENABLED.store(OFF, atomics::Release),
+        }
+    }
+    seed
+}
+
+// The number of the calling thread, and the number of operations it went
+// through before this one
+unsafe fn next_operation() -> (uint, uint) {
+    if !KEY_CREATED.load(atomics::Acquire) {
+        let _g = LOCK.lock();
+        if !KEY_CREATED.load(atomics::Relaxed) {
+            tls::create(&mut KEY);
+            KEY_CREATED.store(true, atomics::Release);
+        }
+    }
+    let v = match tls::get(KEY) as uint {
+        0 => NEXT_THREAD.fetch_add(1, atomics::Relaxed) << HALF,
+        v => v - 1,
+    };
+    let (thread, n) = (v >> HALF, v & ((1 << HALF) - 1));
+    // The count wraps around within the lower half
+    let next = (thread << HALF) | ((n + 1) & ((1 << HALF) - 1));
+    tls::set(KEY, (next + 1) as *mut u8);
+    (thread, n)
+}
+
+// The fault of the `n`th operation under `seed`
+fn fault(seed: uint, n: uint) -> Fault {
+    let h = mix(mix(seed as u64) ^ n as u64);
+    match h % (2 * ONE_IN) {
+        0 => YieldFault,
+        1 => SpinFault((h / (2 * ONE_IN) % (MAX_DOUBLINGS + 1)) as uint),
+        _ => NoFault,
+    }
+}
+
+// The finalizer of MurmurHash3, which spreads consecutive inputs far apart
+fn mix(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h *= 0xff51afd7ed558ccd;
+    h ^= h >> 33;
+    h *= 0xc4ceb9fe1a85ec53;
+    h ^= h >> 33;
+    h
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use super::{fault, next_operation, NoFault, YieldFault, SpinFault};
+
+    #[test]
+    fn reproducible() {
+        let a: Vec<_> = range(0u, 1000).map(|n| fault(7, n)).collect();
+        let b: Vec<_> = range(0u, 1000).map(|n| fault(7, n)).collect();
+        let c: Vec<_> = range(0u, 1000).map(|n| fault(8, n)).collect();
+        assert_eq!(a, b);
+        assert!(a != c);
+    }
+
+    // Fails once if the faults asked for can't be injected
+    #[test]
+    fn seed() {
+        match ::std::os::getenv("RUST_COMM_FAULT_SEED") {
+            Some(s) => assert!(from_str::<uint>(s.as_slice()).is_some(),
+                               "RUST_COMM_FAULT_SEED must be a number, not `{}`", s),
+            None => {}
+        }
+    }
+
+    #[test]
+    fn per_thread() {
+        let (thread, n) = unsafe { next_operation() };
+        assert_eq!(unsafe { next_operation() }, (thread, n + 1));
+        let (tx, rx) = channel();
+        ::native::task::spawn(proc() tx.send(unsafe { next_operation() }));
+        let (other, _) = rx.recv();
+        assert!(other != thread);
+    }
+
+    #[test]
+    fn some_of_each() {
+        let faults: Vec<_> = range(0u, 1000).map(|n| fault(1, n)).collect();
+        assert!(faults.iter().any(|f| *f == YieldFault));
+        assert!(faults.iter().any(|f| match *f { SpinFault(..) => true, _ => false }));
+        let quiet = faults.iter().filter(|f| **f == NoFault).count();
+        assert!(quiet > 800);
+    }
+}
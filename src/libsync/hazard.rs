@@ -0,0 +1,337 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hazard pointers
+//!
+//! Like `epoch`, this module lets lock-free structures free nodes which other
+//! tasks may still be looking at. Instead of pinning a whole epoch, a task
+//! publishes the one pointer it is about to use in a *hazard*, and a retired
+//! node is freed as soon as no hazard holds its address.
+//!
+//! A task which stops in the middle of an operation only keeps the nodes its
+//! hazards point to alive, so the number of nodes waiting to be freed is
+//! bounded by a small multiple of the number of hazards. The cost is that a
+//! hazard has to be published for every pointer loaded, and that a node can
+//! only be used once the structure has been checked to still contain it.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::atomics::{AtomicPtr, SeqCst};
+//! use std::sync::hazard::Domain;
+//! use std::mem;
+//!
+//! let domain = Domain::new();
+//! let shared = AtomicPtr::new(unsafe { mem::transmute(box 1i) });
+//!
+//! // Readers protect the pointer they load for as long as they use it
+//! {
+//!     let hazard = domain.acquire();
+//!     let p = hazard.protect(&shared);
+//!     assert_eq!(unsafe { *p }, 1);
+//! }
+//!
+//! // Writers unlink the old value and retire it
+//! let old = shared.swap(unsafe { mem::transmute(box 2i) }, SeqCst);
+//! unsafe { domain.retire(old) }
+//! # unsafe { domain.retire(shared.load(SeqCst)) }
+//! ```
+
+#![experimental]
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+use collections::{Vec, MutableSeq};
+use core::cmp;
+use core::mem;
+use rustrt::exclusive::Exclusive;
+
+use atomics::{AtomicPtr, AtomicUint, AtomicBool, SeqCst, Relaxed};
+
+/// Retired nodes are scanned for once at least this many of them (or twice as
+/// many as there are hazards, if that's more) are waiting to be freed.
+pub static RETIRE_THRESHOLD: uint = 64;
+
+// A single hazard. Records are never freed before the domain, so a list of
+// them can be walked without any synchronization.
+struct Record {
+    hazard: AtomicUint,
+    active: AtomicBool,
+    next: *mut Record,
+}
+
+/// A set of hazards, and of the nodes retired while those hazards may have
+/// been pointing at them.
+pub struct Domain {
+    records: AtomicPtr<Record>,
+    nrecords: AtomicUint,
+    retired: Exclusive<Vec<(uint, proc():Send)>>,
+}
+
+/// A hazard owned by the current task. Dropping it clears it and gives it back
+/// to its domain for another task to use.
+pub struct Hazard<'a> {
+    record: &'a Record,
+}
+
+impl Domain {
+    /// Creates a new domain, with no hazards.
+    pub fn new() -> Domain {
+        Domain {
+            records: AtomicPtr::new(0 as *mut Record),
+            nrecords: AtomicUint::new(0),
+            retired: Exclusive::new(Vec::new()),
+        }
+    }
+
+    /// Acquires a hazard for use by the current task, reusing one which has
+    /// been given back if possible.
+    pub fn acquire<'a>(&'a self) -> Hazard<'a> {
+        unsafe {
+            let mut cur = self.records.load(SeqCst);
+            while !cur.is_null() {
+                if !(*cur).active.load(Relaxed) &&
+                   !(*cur).active.swap(true, SeqCst) {
+                    return Hazard { record: &*cur }
+                }
+                cur = (*cur).next;
+            }
+
+            let r: *mut Record = mem::transmute(box Record {
+                hazard: AtomicUint::new(0),
+                active: AtomicBool::new(true),
+                next: 0 as *mut Record,
+            });
+            let mut head = self.records.load(SeqCst);
+            loop {
+                (*r).next = head;
+                match self.records.compare_exchange(head, r, SeqCst, SeqCst) {
+                    Ok(..) => break,
+                    Err(cur) => head = cur,
+                }
+            }
+            self.nrecords.fetch_add(1, SeqCst);
+            Hazard { record: &*r }
+        }
+    }
+
+    /// Retires a node which was allocated as a `Box<T>`. It is freed once no
+    /// hazard points to it.
+    ///
+    /// This is unsafe because `p` must have come from a `Box<T>`, and must
+    /// already be unreachable for tasks which load pointers from the
+    /// structure from now on.
+    pub unsafe fn retire<T: Send>(&self, p: *mut T) {
+        let b: Box<T> = mem::transmute(p);
+        let pending = {
+            let mut retired = self.retired.lock();
+            retired.push((p as uint, proc() drop(b)));
+            retired.len()
+        };
+        let threshold = cmp::max(RETIRE_THRESHOLD,
+                                 2 * self.nrecords.load(Relaxed));
+        if pending >= threshold {
+            self.scan();
+        }
+    }
+
+    /// Frees every retired node which no hazard points to. This happens on its
+    /// own as retired nodes pile up, but may be called to clean up sooner.
+    pub fn scan(&self) {
+        // Nodes retired after this point aren't looked at, so hazards which
+        // are set from now on can't be protecting any of the ones which are.
+        let retired = unsafe {
+            mem::replace(&mut *self.retired.lock(), Vec::new())
+        };
+
+        let mut hazards = Vec::new();
+        unsafe {
+            let mut cur = self.records.load(SeqCst);
+            while !cur.is_null() {
+                match (*cur).hazard.load(SeqCst) {
+                    0 => {}
+                    p => hazards.push(p),
+                }
+                cur = (*cur).next;
+            }
+        }
+        hazards.sort();
+
+        let (protected, free) = retired.partition(|&(p, _)| {
+            hazards.as_slice().bsearch_elem(&p).is_some()
+        });
+        if protected.len() > 0 {
+            unsafe { self.retired.lock().push_all_move(protected); }
+        }
+        // Destructors may retire more nodes, so they run without the lock
+        for (_, f) in free.move_iter() {
+            f();
+        }
+    }
+}
+
+impl Drop for Domain {
+    fn drop(&mut self) {
+        // No task can be holding a hazard while we're being destroyed
+        let retired = unsafe {
+            mem::replace(&mut *self.retired.lock(), Vec::new())
+        };
+        for (_, f) in retired.move_iter() {
+            f();
+        }
+        unsafe {
+            let mut cur = self.records.load(SeqCst);
+            while !cur.is_null() {
+                let r: Box<Record> = mem::transmute(cur);
+                cur = r.next;
+            }
+        }
+    }
+}
+
+impl<'a> Hazard<'a> {
+    /// Loads a pointer from `src` and protects it. The pointee won't be freed
+    /// by the domain until this hazard is set to something else.
+    pub fn protect<T>(&self, src: &AtomicPtr<T>) -> *mut T {
+        let mut p = src.load(SeqCst);
+        loop {
+            self.set(p);
+            // If the pointer is still there, it hadn't been retired when the
+            // hazard was published
+            let cur = src.load(SeqCst);
+            if cur == p { return p }
+            p = cur;
+        }
+    }
+
+    /// Sets this hazard to `p`. This only protects the pointee if it's known
+    /// to not have been retired yet after the hazard has been set.
+    pub fn set<T>(&self, p: *mut T) {
+        self.record.hazard.store(p as uint, SeqCst);
+    }
+
+    /// Clears this hazard, so it doesn't protect anything.
+    pub fn clear(&self) {
+        self.record.hazard.store(0, SeqCst);
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for Hazard<'a> {
+    fn drop(&mut self) {
+        self.clear();
+        self.record.active.store(false, SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use std::mem;
+    use alloc::arc::Arc;
+    use native;
+    use atomics::{AtomicPtr, AtomicUint, SeqCst};
+    use super::{Domain, RETIRE_THRESHOLD};
+
+    struct Counted(Arc<AtomicUint>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            let Counted(ref count) = *self;
+            count.fetch_add(1, SeqCst);
+        }
+    }
+
+    fn counted(count: &Arc<AtomicUint>) -> *mut Counted {
+        unsafe { mem::transmute(box Counted(count.clone())) }
+    }
+
+    #[test]
+    fn protect_blocks_free() {
+        let d = Domain::new();
+        let dropped = Arc::new(AtomicUint::new(0));
+        let shared = AtomicPtr::new(counted(&dropped));
+
+        let h = d.acquire();
+        let p = h.protect(&shared);
+        shared.store(counted(&dropped), SeqCst);
+        unsafe { d.retire(p); }
+        d.scan();
+        assert_eq!(dropped.load(SeqCst), 0);
+
+        h.clear();
+        d.scan();
+        assert_eq!(dropped.load(SeqCst), 1);
+        unsafe { d.retire(shared.load(SeqCst)); }
+        drop(h);
+        drop(d);
+        assert_eq!(dropped.load(SeqCst), 2);
+    }
+
+    #[test]
+    fn hazards_are_reused() {
+        let d = Domain::new();
+        drop(d.acquire());
+        drop(d.acquire());
+        let a = d.acquire();
+        let b = d.acquire();
+        assert_eq!(d.nrecords.load(SeqCst), 2);
+        drop(a);
+        drop(b);
+    }
+
+    #[test]
+    fn bounded_garbage() {
+        let d = Domain::new();
+        let dropped = Arc::new(AtomicUint::new(0));
+        let _h = d.acquire();
+        for _ in range(0, RETIRE_THRESHOLD * 4) {
+            unsafe { d.retire(counted(&dropped)); }
+        }
+        // nothing was protected, so every scan freed everything
+        assert!(dropped.load(SeqCst) >= RETIRE_THRESHOLD * 3);
+    }
+
+    #[test]
+    fn stress() {
+        static NTHREADS: uint = 8;
+        static NSWAPS: uint = 10000;
+        let d = Arc::new(Domain::new());
+        let shared = Arc::new(AtomicPtr::new(unsafe {
+            mem::transmute(box 0u)
+        }));
+        let (tx, rx) = channel();
+
+        for _ in range(0, NTHREADS) {
+            let (d, shared, tx) = (d.clone(), shared.clone(), tx.clone());
+            native::task::spawn(proc() {
+                let h = d.acquire();
+                let mut last = 0;
+                for _ in range(0, NSWAPS) {
+                    let cur = unsafe { *h.protect(&*shared) };
+                    assert!(cur >= last);
+                    last = cur;
+                }
+                drop(h);
+                tx.send(());
+            });
+        }
+
+        for i in range(1, NSWAPS) {
+            let old = shared.swap(unsafe { mem::transmute(box i) }, SeqCst);
+            unsafe { d.retire(old); }
+        }
+        drop(tx);
+        for _ in range(0, NTHREADS) { rx.recv(); }
+        unsafe { d.retire(shared.load(SeqCst)); }
+    }
+}
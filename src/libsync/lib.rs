@@ -41,21 +41,34 @@ extern crate rustrt;
 #[cfg(test)] #[phase(plugin, link)] extern crate std;
 
 pub use alloc::arc::{Arc, Weak};
-pub use lock::{Mutex, MutexGuard, Condvar, Barrier,
+pub use lock::{Mutex, MutexGuard, Condvar, Barrier, WaitGroup,
                RWLock, RWLockReadGuard, RWLockWriteGuard};
 
 // The mutex/rwlock in this module are not meant for reexport
 pub use raw::{Semaphore, SemaphoreGuard};
+pub use raw::{RWLockPreference, NoPreference, PreferWriters};
+
+pub use park::{park, unparker, Unparker};
+pub use seqlock::SeqLock;
+pub use arc_cell::ArcCell;
+pub use sharded_counter::ShardedCounter;
 
 // Core building blocks for all primitives in this crate
 
 pub mod atomics;
+pub mod backoff;
+pub mod epoch;
+pub mod hazard;
 
 // Concurrent data structures
 
 mod mpsc_intrusive;
+mod node_pool;
+mod treiber_stack;
+pub mod lockfree;
 pub mod spsc_queue;
 pub mod mpsc_queue;
+pub mod mpsc_block_queue;
 pub mod mpmc_bounded_queue;
 pub mod deque;
 
@@ -64,6 +77,10 @@ pub mod deque;
 pub mod raw;
 pub mod mutex;
 pub mod one;
+mod park;
+mod deadlock;
+#[cfg(test)] mod model;
+#[cfg(test)] mod fault;
 
 // Message-passing based communication
 
@@ -72,6 +89,9 @@ pub mod comm;
 // Higher level primitives based on those above
 
 mod lock;
+mod seqlock;
+mod arc_cell;
+mod sharded_counter;
 
 #[cfg(not(test))]
 mod std {
@@ -120,6 +120,31 @@ impl<'a> Condvar<'a> {
         PoisonOnFail::check(*self.poison.flag, self.name);
     }
 
+    /// Atomically exit the associated lock and block until a signal is sent or
+    /// `ms` milliseconds have passed, returning whether a signal was received.
+    /// A signal which races with the timeout is never lost (as
+    /// sync::raw::Condvar::wait_timeout).
+    ///
+    /// wait_timeout(ms) is equivalent to wait_timeout_on(0, ms).
+    ///
+    /// # Failure
+    ///
+    /// As wait(), and this will also fail if the current task's runtime
+    /// doesn't provide timers.
+    #[inline]
+    pub fn wait_timeout(&self, ms: u64) -> bool { self.wait_timeout_on(0, ms) }
+
+    /// Atomically exit the associated lock and block on a specified condvar
+    /// until a signal is sent on that same condvar or `ms` milliseconds have
+    /// passed, returning whether a signal was received.
+    #[inline]
+    pub fn wait_timeout_on(&self, condvar_id: uint, ms: u64) -> bool {
+        assert!(!*self.poison.flag);
+        let signalled = self.inner.cond().wait_timeout_on(condvar_id, ms);
+        PoisonOnFail::check(*self.poison.flag, self.name);
+        signalled
+    }
+
     /// Wake up a blocked task. Returns false if there was no blocked task.
     #[inline]
     pub fn signal(&self) -> bool { self.signal_on(0) }
@@ -219,10 +244,42 @@ impl<T: Send> Mutex<T> {
     /// that other tasks won't block forever. It will also poison the Mutex:
     /// any tasks that subsequently try to access it (including those already
     /// blocked on the mutex) will also fail immediately.
+    ///
+    /// When the `RUST_DEADLOCK_DETECT` environment variable is set, this also
+    /// fails if blocking would deadlock the task, because the mutex is held by
+    /// a task which is itself waiting, directly or not, for a mutex held by
+    /// this one.
     #[inline]
     pub fn lock<'a>(&'a self) -> MutexGuard<'a, T> {
-        let guard = self.lock.lock();
+        self.guard(self.lock.lock())
+    }
 
+    /// Attempts to access the underlying data without blocking. If the mutex
+    /// is unlocked, this locks it and returns a guard as `lock` does, and
+    /// otherwise this returns `None`.
+    ///
+    /// # Failure
+    ///
+    /// As `lock`, this fails if the mutex is poisoned and it could be locked.
+    #[inline]
+    pub fn try_lock<'a>(&'a self) -> Option<MutexGuard<'a, T>> {
+        self.lock.try_lock().map(|guard| self.guard(guard))
+    }
+
+    /// Access the underlying data, blocking for at most `ms` milliseconds
+    /// while the mutex is locked by another task. Returns a guard as `lock`
+    /// does if the mutex could be locked in time.
+    ///
+    /// # Failure
+    ///
+    /// As `lock`, and this also fails if it has to wait and the current
+    /// task's runtime doesn't provide timers.
+    #[inline]
+    pub fn lock_timeout<'a>(&'a self, ms: u64) -> Option<MutexGuard<'a, T>> {
+        self.lock.lock_timeout(ms).map(|guard| self.guard(guard))
+    }
+
+    fn guard<'a>(&'a self, guard: raw::MutexGuard<'a>) -> MutexGuard<'a, T> {
         // These two accesses are safe because we're guranteed at this point
         // that we have exclusive access to this mutex. We are indeed able to
         // promote ourselves from &Mutex to `&mut T`
@@ -307,8 +364,16 @@ impl<T: Send + Share> RWLock<T> {
     /// Create a reader/writer lock with the supplied data and a specified number
     /// of condvars (as sync::RWLock::new_with_condvars).
     pub fn new_with_condvars(user_data: T, num_condvars: uint) -> RWLock<T> {
+        RWLock::new_with_preference(user_data, num_condvars, raw::NoPreference)
+    }
+
+    /// Create a reader/writer lock with the supplied data, a specified number
+    /// of condvars, and a policy for arbitrating between waiting readers and
+    /// writers (as sync::raw::RWLock::new_with_preference).
+    pub fn new_with_preference(user_data: T, num_condvars: uint,
+                               preference: raw::RWLockPreference) -> RWLock<T> {
         RWLock {
-            lock: raw::RWLock::new_with_condvars(num_condvars),
+            lock: raw::RWLock::new_with_preference(num_condvars, preference),
             failed: UnsafeCell::new(false),
             data: UnsafeCell::new(user_data),
         }
@@ -392,6 +457,9 @@ impl<'a, T: Send + Share> DerefMut<T> for RWLockWriteGuard<'a, T> {
 /// A barrier enables multiple tasks to synchronize the beginning
 /// of some computation.
 ///
+/// A barrier can be reused: once all of the tasks have been released, the
+/// next `num_tasks` calls to `wait` make up a new generation.
+///
 /// ```rust
 /// use sync::{Arc, Barrier};
 ///
@@ -431,7 +499,11 @@ impl Barrier {
     }
 
     /// Block the current task until a certain number of tasks is waiting.
-    pub fn wait(&self) {
+    ///
+    /// Returns `true` in exactly one of the tasks of each generation (the last
+    /// one to arrive, which releases the others), so that it may take care of
+    /// any work which should only be done once per generation.
+    pub fn wait(&self) -> bool {
         let mut lock = self.lock.lock();
         let local_gen = lock.generation_id;
         lock.count += 1;
@@ -442,10 +514,85 @@ impl Barrier {
                   lock.count < self.num_tasks {
                 lock.cond.wait();
             }
+            false
         } else {
             lock.count = 0;
             lock.generation_id += 1;
             lock.cond.broadcast();
+            true
+        }
+    }
+}
+
+/****************************************************************************
+ * WaitGroup
+ ****************************************************************************/
+
+/// A wait group lets a task wait for a number of other tasks to finish, when
+/// that number isn't known up front.
+///
+/// Each unit of work is added to the group with `add` before it is started,
+/// and marked with `done` once it has finished. `wait` blocks until every
+/// unit added so far is done.
+///
+/// ```rust
+/// use sync::{Arc, WaitGroup};
+///
+/// let wg = Arc::new(WaitGroup::new());
+/// for _ in range(0u, 10) {
+///     wg.add(1);
+///     let wg = wg.clone();
+///     spawn(proc() {
+///         println!("working");
+///         wg.done();
+///     });
+/// }
+/// wg.wait();
+/// ```
+pub struct WaitGroup {
+    lock: Mutex<uint>,
+}
+
+impl WaitGroup {
+    /// Create a new wait group with no outstanding work.
+    pub fn new() -> WaitGroup {
+        WaitGroup { lock: Mutex::new(0) }
+    }
+
+    /// Add `n` units of outstanding work to the group.
+    pub fn add(&self, n: uint) {
+        *self.lock.lock() += n;
+    }
+
+    /// Mark one unit of work as done, waking up the waiting tasks if it was
+    /// the last one.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if there is no outstanding work.
+    pub fn done(&self) {
+        // Fail after the lock is released, so that the lock isn't poisoned
+        // for everyone else
+        let underflow = {
+            let mut count = self.lock.lock();
+            if *count == 0 {
+                true
+            } else {
+                *count -= 1;
+                if *count == 0 {
+                    count.cond.broadcast();
+                }
+                false
+            }
+        };
+        assert!(!underflow, "WaitGroup::done called more times than add");
+    }
+
+    /// Block the current task until there is no outstanding work.
+    pub fn wait(&self) {
+        let count = self.lock.lock();
+        while *count > 0 {
+            count.cond.wait();
         }
     }
 }
@@ -462,7 +609,8 @@ mod tests {
     use std::task::try_future;
 
     use Arc;
-    use super::{Mutex, Barrier, RWLock};
+    use super::{Mutex, Barrier, RWLock, WaitGroup};
+    use raw::PreferWriters;
 
     #[test]
     fn test_mutex_arc_condvar() {
@@ -518,6 +666,37 @@ mod tests {
         assert_eq!(*lock, 1);
     }
 
+    #[test]
+    fn test_mutex_arc_try_lock() {
+        let arc = Arc::new(Mutex::new(1i));
+        let arc2 = arc.clone();
+        let mut lock = arc.try_lock().unwrap();
+        *lock = 2;
+        let (tx, rx) = channel();
+        let child = try_future(proc() {
+            assert!(arc2.try_lock().is_none());
+            assert!(arc2.lock_timeout(10).is_none());
+            tx.send(());
+            let lock = arc2.lock_timeout(60 * 1000).unwrap();
+            assert_eq!(*lock, 3);
+        });
+        rx.recv();
+        *lock = 3;
+        drop(lock);
+        assert!(child.unwrap().is_ok());
+    }
+
+    #[test] #[should_fail]
+    fn test_mutex_arc_try_lock_poison() {
+        let arc = Arc::new(Mutex::new(1i));
+        let arc2 = arc.clone();
+        let _ = task::try(proc() {
+            let _lock = arc2.lock();
+            fail!();
+        });
+        let _lock = arc.try_lock();
+    }
+
     #[test]
     fn test_mutex_arc_nested() {
         // Tests nested mutexes and access
@@ -646,6 +825,34 @@ mod tests {
         assert_eq!(*lock, 10);
     }
 
+    #[test]
+    fn test_rw_arc_prefer_writers() {
+        let arc = Arc::new(RWLock::new_with_preference(0i, 1, PreferWriters));
+        let (tx, rx) = channel();
+        for i in range(0u, 10) {
+            let arc = arc.clone();
+            let tx = tx.clone();
+            task::spawn(proc() {
+                for _ in range(0u, 10) {
+                    if i % 2 == 0 {
+                        let mut lock = arc.write();
+                        let tmp = *lock;
+                        *lock = -1;
+                        task::deschedule();
+                        *lock = tmp + 1;
+                        lock.cond.signal();
+                    } else {
+                        assert!(*arc.read() >= 0);
+                    }
+                }
+                tx.send(());
+            });
+        }
+        drop(tx);
+        for _ in range(0u, 10) { rx.recv(); }
+        assert_eq!(*arc.read(), 50);
+    }
+
     #[test]
     fn test_rw_arc_access_in_unwind() {
         let arc = Arc::new(RWLock::new(1i));
@@ -823,4 +1030,89 @@ mod tests {
             rx.recv();
         }
     }
+
+    #[test]
+    fn test_barrier_generations() {
+        let barrier = Arc::new(Barrier::new(4));
+        let (tx, rx) = channel();
+
+        for _ in range(0u, 3) {
+            let c = barrier.clone();
+            let tx = tx.clone();
+            spawn(proc() {
+                for _ in range(0u, 10) { tx.send(c.wait()); }
+            });
+        }
+
+        // Exactly one task leads each generation
+        for _ in range(0u, 10) {
+            let mut leaders = if barrier.wait() {1u} else {0};
+            for _ in range(0u, 3) {
+                if rx.recv() { leaders += 1; }
+            }
+            assert_eq!(leaders, 1);
+        }
+    }
+
+    /************************************************************************
+     * WaitGroup tests
+     ************************************************************************/
+    #[test]
+    fn test_wait_group() {
+        let wg = Arc::new(WaitGroup::new());
+        let count = Arc::new(Mutex::new(0u));
+        // nothing to wait for yet
+        wg.wait();
+
+        for _ in range(0u, 10) {
+            wg.add(1);
+            let (wg, count) = (wg.clone(), count.clone());
+            spawn(proc() {
+                *count.lock() += 1;
+                wg.done();
+            });
+        }
+        wg.wait();
+        assert_eq!(*count.lock(), 10);
+    }
+
+    #[test]
+    fn test_wait_group_blocks() {
+        let wg = Arc::new(WaitGroup::new());
+        let (tx, rx) = channel();
+        wg.add(2);
+        let wg2 = wg.clone();
+        spawn(proc() {
+            wg2.wait();
+            tx.send(());
+        });
+
+        wg.done();
+        task::deschedule();
+        assert!(match rx.try_recv() {
+            Err(Empty) => true,
+            _ => false,
+        });
+        wg.done();
+        rx.recv();
+    }
+
+    #[test] #[should_fail]
+    fn test_wait_group_too_many_done() {
+        let wg = WaitGroup::new();
+        wg.add(1);
+        wg.done();
+        wg.done();
+    }
+
+    #[test]
+    fn test_wait_group_too_many_done_no_poison() {
+        let wg = Arc::new(WaitGroup::new());
+        let wg2 = wg.clone();
+        assert!(task::try(proc() wg2.done()).is_err());
+        // The failure didn't poison the lock
+        wg.add(1);
+        wg.done();
+        wg.wait();
+    }
 }
@@ -0,0 +1,70 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Lock-free data structures
+//!
+//! This module collects the lock-free structures which the rest of this crate
+//! is built on, for use in other concurrent code. None of them ever block:
+//! an operation which can't make progress returns instead, and it is up to
+//! the caller to retry, block, or give up.
+//!
+//! # Safety
+//!
+//! These structures are shared by reference among tasks, usually in an `Arc`.
+//! Each one names the operations which may run concurrently, and which must be
+//! called by at most one task at a time:
+//!
+//! * `Stack` allows any number of concurrent `push` and `pop_all` calls. Its
+//!   `pop` is unsafe, and may not run concurrently with another `pop` or with
+//!   a `pop_all`.
+//! * `MpscQueue` allows any number of concurrent `push` calls, but only one
+//!   task may ever be popping. Breaking this rule isn't caught, and corrupts
+//!   the queue.
+//! * `SpscQueue` allows one pusher and one popper at a time.
+//! * `MpmcQueue` allows any number of concurrent pushers and poppers. Rather
+//!   than allocating nodes, it has a fixed capacity. It is itself a handle
+//!   which can be cloned, so it doesn't need to be put in an `Arc`.
+//!
+//! Values are moved into these structures when they're pushed, and are owned
+//! by the structure until they're popped. Values which are never popped are
+//! destroyed along with the structure.
+//!
+//! The node-based structures free a node as soon as its value has been
+//! popped, which is why none of them lets several tasks pop one value at a
+//! time.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::sync::lockfree::Stack;
+//!
+//! let free_list = Arc::new(Stack::new());
+//! let (tx, rx) = channel();
+//! for i in range(0u, 4) {
+//!     let (free_list, tx) = (free_list.clone(), tx.clone());
+//!     spawn(proc() {
+//!         free_list.push(i);
+//!         tx.send(());
+//!     });
+//! }
+//! for _ in range(0u, 4) { rx.recv(); }
+//! let mut ids: Vec<uint> = free_list.pop_all().collect();
+//! ids.sort();
+//! assert_eq!(ids, vec![0, 1, 2, 3]);
+//! ```
+
+#![experimental]
+
+pub use treiber_stack::{Stack, Items};
+pub use mpsc_queue::{PopResult, Data, Empty, Inconsistent};
+pub use MpscQueue = mpsc_queue::Queue;
+pub use SpscQueue = spsc_queue::Queue;
+pub use MpmcQueue = mpmc_bounded_queue::Queue;
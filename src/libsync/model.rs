@@ -0,0 +1,702 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A model checker for the lock-free protocols of this crate
+//!
+//! Stress tests only see the interleavings which the OS happens to produce,
+//! which for code like the steal/cnt dance of stream channels rarely includes
+//! the interesting ones. `check` instead runs a test again and again under a
+//! scheduler of its own, which lets a single thread run at a time and decides
+//! which one runs next at every atomic operation, until it has tried every
+//! interleaving (up to the limits below).
+//!
+//! The threads of a model are OS threads running tasks whose runtime is the
+//! model's, so that blocking on a channel hands the model over to another
+//! thread instead of blocking the process. In the tests of this crate, the
+//! atomic types of `atomics` call `switch` before each operation, which is
+//! where a thread can be preempted. A thread is recognized from the address
+//! of its stack, as it can be preempted while its task is out of TLS.
+//!
+//! The interleavings are explored depth-first. Each execution records the
+//! choices of thread it made, and the next one replays them up to the last
+//! choice which still has an alternative, which it takes instead. This needs
+//! the tests to be deterministic apart from the scheduling. A few limits keep
+//! the search finite:
+//!
+//! * Only schedules which preempt a thread at most `PREEMPTION_BOUND` times
+//!   are explored. Switching threads because one blocked or finished doesn't
+//!   count. Most concurrency bugs only take one or two preemptions.
+//! * A thread which goes through `SPIN_LIMIT` atomic operations in a row is
+//!   assumed to be spinning on another one, and is made to yield to it.
+//! * At most `MAX_EXECUTIONS` executions are run, of at most `MAX_STEPS`
+//!   atomic operations each. A model which has more interleavings than that
+//!   is only partly checked, which `check` reports on stderr (bypassing the
+//!   test harness's capture, so that it isn't lost when the test passes).
+//!
+//! Memory is assumed to be sequentially consistent, so bugs which only show
+//! with weaker orderings aren't found. An execution fails if one of its
+//! threads fails, or if all of the threads which haven't finished are blocked.
+//! The failure is reported along with the schedule of the execution, to which
+//! the threads which didn't get to finish are left blocked.
+//!
+//! # Native mutexes
+//!
+//! Only atomic operations are switch points. A thread which is preempted while
+//! it holds a native mutex keeps holding it, and if the thread the model
+//! switches to then tries to take the same mutex, it blocks its OS thread
+//! without ever reaching a switch point, so the model hangs. The model has no
+//! way of telling this apart from a thread which is just running.
+//!
+//! In this crate, that's the `select_lock` of shared packets (held by the
+//! thread which upgrades a channel to a shared one, and waited for by a
+//! selection which is being aborted at the same time), and the lock of sync
+//! packets (held around most of their operations). Models must not have two
+//! threads contend for those, which rules out sync channels altogether, and
+//! aborting a selection over a channel which another thread is upgrading.
+
+use std::prelude::*;
+
+use std::any::{Any, AnyRefExt};
+use std::io::stdio;
+use std::mem;
+use std::task;
+use core::atomics;
+use rustrt::Runtime;
+use rustrt::exclusive::Exclusive;
+use rustrt::local::Local;
+use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+use rustrt::rtio;
+use rustrt::stack;
+use rustrt::task::{Task, TaskOpts, BlockedTask};
+use rustrt::thread::Thread;
+use TaskResult = rustrt::task::Result;
+
+use Arc;
+
+static PREEMPTION_BOUND: uint = 2;
+static SPIN_LIMIT: uint = 64;
+static MAX_EXECUTIONS: uint = 20000;
+static MAX_STEPS: uint = 100000;
+static STACK_SIZE: uint = 1 << 20;
+
+// Models are run one at a time, under `SERIAL`. `LOCK` covers the state of
+// the running model, which is only changed by the thread which is running,
+// but is looked at by every thread which goes through `switch`.
+static mut SERIAL: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut ACTIVE: atomics::AtomicBool = atomics::INIT_ATOMIC_BOOL;
+static mut STATE: *mut State = 0 as *mut State;
+
+// A flag to wait on until someone else sets it
+type Baton = Arc<Exclusive<bool>>;
+
+struct State {
+    threads: Vec<ModelThread>,
+    // The choices made so far, and those to replay
+    choices: Vec<Choice>,
+    prefix: Vec<uint>,
+    preemptions: uint,
+    // The number of switch points the current thread has gone through since
+    // it was scheduled
+    streak: uint,
+    steps: uint,
+    failure: Option<String>,
+    // Set once the execution is over
+    done: Baton,
+}
+
+struct ModelThread {
+    status: Status,
+    // Whether the thread has been reawakened since it last started to block
+    awoken: bool,
+    stack: (uint, uint),
+    baton: Baton,
+    handle: Option<Thread<()>>,
+}
+
+#[deriving(PartialEq)]
+enum Status { Runnable, Blocked, Finished }
+
+// Why a thread gives the scheduler a chance to switch to another one
+#[deriving(PartialEq)]
+enum Why { Switch, Yield, Block, Exit }
+
+enum Next { Keep, Run(Baton), End(Baton) }
+
+struct Choice {
+    // The position of the chosen thread among the `len` options
+    pos: uint,
+    len: uint,
+    thread: uint,
+}
+
+// The runtime of the tasks of a model
+struct ModelRuntime {
+    id: uint,
+    stack: (uint, uint),
+}
+
+/// Runs `f` under every interleaving of the threads it spawns, failing on the
+/// first execution which fails or deadlocks. `f` itself runs on the first
+/// thread of each execution.
+///
+/// If there are more than `MAX_EXECUTIONS` interleavings, the ones after that
+/// aren't run, and a warning says so. See the module docs for the models which
+/// hang instead of being checked.
+pub fn check(f: fn()) {
+    unsafe {
+        let _serial = SERIAL.lock();
+        let mut prefix = Vec::new();
+        let mut executions = 0u;
+        loop {
+            let (choices, failure) = execute(f, prefix);
+            executions += 1;
+            match failure {
+                Some(msg) => {
+                    let schedule: Vec<uint> = choices.iter().map(|c| c.thread).collect();
+                    fail!("{} in execution {} of the model, with the schedule {}",
+                          msg, executions, schedule);
+                }
+                None => {}
+            }
+            prefix = match backtrack(choices) {
+                Some(prefix) => prefix,
+                None => break,
+            };
+            if executions == MAX_EXECUTIONS {
+                let name = task::name().unwrap_or("<unnamed>".to_string());
+                let _ = writeln!(&mut stdio::stderr_raw(),
+                                 "warning: the model of {} was only partly checked, \
+                                  it has more than {} interleavings",
+                                 name, MAX_EXECUTIONS);
+                break
+            }
+        }
+    }
+}
+
+/// Spawns a thread in the model which is being checked.
+pub fn spawn(f: proc():Send) {
+    let baton = Arc::new(Exclusive::new(false));
+    let ready = Arc::new(Exclusive::new(false));
+    let id = unsafe {
+        let _g = LOCK.lock();
+        assert!(!STATE.is_null(), "model::spawn called outside of a model");
+        let s = &mut *STATE;
+        s.threads.push(ModelThread {
+            status: Runnable,
+            awoken: false,
+            stack: (0, 0),
+            baton: baton.clone(),
+            handle: None,
+        });
+        s.threads.len() - 1
+    };
+
+    // The thread is only given the baton once it has said where its stack
+    // is, so that it's known by then
+    let r = ready.clone();
+    let handle = Thread::start_stack(STACK_SIZE, proc() run(id, baton, r, f));
+    unsafe {
+        take(&ready);
+        let _g = LOCK.lock();
+        (*STATE).threads.get_mut(id).handle = Some(handle);
+    }
+}
+
+/// Gives the scheduler a chance to switch to another thread, if the calling
+/// thread is in a model.
+pub fn switch() {
+    if !unsafe { ACTIVE.load(atomics::Relaxed) } { return }
+    let here = 0u;
+    let sp = &here as *const uint as uint;
+    let me = unsafe {
+        let _g = LOCK.lock();
+        if STATE.is_null() { return }
+        let found = (*STATE).threads.iter().position(|t| {
+            let (lo, hi) = t.stack;
+            lo <= sp && sp <= hi
+        });
+        match found {
+            Some(id) => id,
+            None => return,
+        }
+    };
+    reschedule(me, Switch);
+}
+
+unsafe fn execute(f: fn(), prefix: Vec<uint>) -> (Vec<Choice>, Option<String>) {
+    let done = Arc::new(Exclusive::new(false));
+    {
+        let _g = LOCK.lock();
+        STATE = mem::transmute(box State {
+            threads: Vec::new(),
+            choices: Vec::new(),
+            prefix: prefix,
+            preemptions: 0,
+            streak: 0,
+            steps: 0,
+            failure: None,
+            done: done.clone(),
+        });
+    }
+    ACTIVE.store(true, atomics::SeqCst);
+    spawn(proc() f());
+    let first = {
+        let _g = LOCK.lock();
+        (*STATE).threads.get(0).baton.clone()
+    };
+    give(&first);
+    take(&done);
+    ACTIVE.store(false, atomics::SeqCst);
+
+    let state: Box<State> = {
+        let _g = LOCK.lock();
+        let state = mem::transmute(STATE);
+        STATE = 0 as *mut State;
+        state
+    };
+    let State { threads, choices, failure, .. } = *state;
+    for t in threads.move_iter() {
+        // The threads which didn't finish will never run again
+        match failure {
+            None => { t.handle.unwrap().join(); }
+            Some(..) => mem::forget(t),
+        }
+    }
+    (choices, failure)
+}
+
+// The prefix of choices of the next execution to run, if there's one left
+fn backtrack(mut choices: Vec<Choice>) -> Option<Vec<uint>> {
+    loop {
+        match choices.pop() {
+            Some(c) => {
+                if c.pos + 1 < c.len {
+                    let mut prefix: Vec<uint> = choices.iter().map(|c| c.pos).collect();
+                    prefix.push(c.pos + 1);
+                    return Some(prefix)
+                }
+            }
+            None => return None,
+        }
+    }
+}
+
+fn run(id: uint, baton: Baton, ready: Baton, f: proc():Send) {
+    let something_around_the_top_of_the_stack = 1u;
+    let hi = &something_around_the_top_of_the_stack as *const uint as uint;
+    let lo = hi - STACK_SIZE + 1024;
+    unsafe {
+        stack::record_stack_bounds(lo, hi);
+        {
+            let _g = LOCK.lock();
+            (*STATE).threads.get_mut(id).stack = (lo, hi);
+        }
+        give(&ready);
+        take(&baton);
+    }
+
+    let mut task = box Task::new();
+    task.death.on_exit = Some(proc(result: TaskResult) {
+        match result {
+            Ok(()) => {}
+            Err(cause) => {
+                let msg = match cause.as_ref::<&'static str>() {
+                    Some(s) => s.to_string(),
+                    None => match cause.as_ref::<String>() {
+                        Some(s) => s.clone(),
+                        None => "Box<Any>".to_string(),
+                    },
+                };
+                unsafe {
+                    let _g = LOCK.lock();
+                    let s = &mut *STATE;
+                    if s.failure.is_none() {
+                        s.failure = Some(format!("thread {} failed with '{}'", id, msg));
+                    }
+                }
+            }
+        }
+    });
+    task.put_runtime(box ModelRuntime { id: id, stack: (lo, hi) });
+    let mut f = Some(f);
+    drop(task.run(|| { f.take_unwrap()() }).destroy());
+    reschedule(id, Exit);
+}
+
+// Lets the scheduler pick the thread to run next, and waits for the calling
+// thread to be picked in turn unless it has finished
+fn reschedule(me: uint, why: Why) {
+    let (next, baton) = unsafe {
+        let _g = LOCK.lock();
+        let s = &mut *STATE;
+        (s.pick(me, why), s.threads.get(me).baton.clone())
+    };
+    match next {
+        Keep => return,
+        Run(b) | End(b) => unsafe { give(&b) },
+    }
+    if why != Exit {
+        unsafe { take(&baton) }
+    }
+}
+
+unsafe fn give(baton: &Baton) {
+    let mut g = baton.lock();
+    *g = true;
+    g.signal();
+}
+
+unsafe fn take(baton: &Baton) {
+    let mut g = baton.lock();
+    while !*g { g.wait() }
+    *g = false;
+}
+
+impl State {
+    fn pick(&mut self, me: uint, why: Why) -> Next {
+        self.steps += 1;
+        if self.steps > MAX_STEPS && self.failure.is_none() {
+            self.failure = Some(format!("livelock after {} steps", MAX_STEPS));
+        }
+        match why {
+            Block if self.threads.get(me).awoken => return Keep,
+            Block => self.threads.get_mut(me).status = Blocked,
+            Exit => self.threads.get_mut(me).status = Finished,
+            Switch | Yield => {}
+        }
+        if self.failure.is_some() { return End(self.done.clone()) }
+
+        let others: Vec<uint> = range(0, self.threads.len()).filter(|&i| {
+            i != me && self.threads.get(i).status == Runnable
+        }).collect();
+        if why == Switch { self.streak += 1 }
+        let next = match why {
+            _ if others.is_empty() && (why == Switch || why == Yield) => me,
+            Switch if self.streak > SPIN_LIMIT => round_robin(me, others.as_slice()),
+            Switch if self.preemptions >= PREEMPTION_BOUND => me,
+            Switch => {
+                let mut options = vec![me];
+                options.push_all(others.as_slice());
+                let next = self.choose(options);
+                if next != me { self.preemptions += 1 }
+                next
+            }
+            Yield => round_robin(me, others.as_slice()),
+            Block | Exit if others.is_empty() => {
+                let blocked: Vec<uint> = range(0, self.threads.len()).filter(|&i| {
+                    self.threads.get(i).status == Blocked
+                }).collect();
+                if !blocked.is_empty() {
+                    self.failure = Some(format!("deadlock of threads {}", blocked));
+                }
+                return End(self.done.clone())
+            }
+            Block | Exit => self.choose(others),
+        };
+        if next == me { return Keep }
+        self.streak = 0;
+        Run(self.threads.get(next).baton.clone())
+    }
+
+    // Picks one of `options`, which start with the one to run by default
+    fn choose(&mut self, options: Vec<uint>) -> uint {
+        let depth = self.choices.len();
+        let mut pos = if depth < self.prefix.len() { *self.prefix.get(depth) } else { 0 };
+        if pos >= options.len() {
+            if self.failure.is_none() {
+                self.failure = Some("nondeterministic execution".to_string());
+            }
+            pos = 0;
+        }
+        let thread = *options.get(pos);
+        self.choices.push(Choice { pos: pos, len: options.len(), thread: thread });
+        thread
+    }
+}
+
+// The first of `others` after `me` in order, wrapping around
+fn round_robin(me: uint, others: &[uint]) -> uint {
+    match others.iter().find(|&&i| i > me) {
+        Some(&i) => i,
+        None => others[0],
+    }
+}
+
+fn set_awoken(id: uint, awoken: bool) {
+    unsafe {
+        let _g = LOCK.lock();
+        let t = (*STATE).threads.get_mut(id);
+        t.awoken = awoken;
+        if awoken && t.status == Blocked { t.status = Runnable }
+    }
+}
+
+// This follows the runtime of native tasks, with the waits on a condition
+// variable replaced by giving the model to another thread
+impl Runtime for ModelRuntime {
+    fn yield_now(self: Box<ModelRuntime>, mut cur_task: Box<Task>) {
+        let id = self.id;
+        cur_task.put_runtime(self);
+        Local::put(cur_task);
+        reschedule(id, Yield);
+    }
+
+    fn maybe_yield(self: Box<ModelRuntime>, mut cur_task: Box<Task>) {
+        cur_task.put_runtime(self);
+        Local::put(cur_task);
+    }
+
+    fn deschedule(self: Box<ModelRuntime>,
+                  times: uint,
+                  mut cur_task: Box<Task>,
+                  f: |BlockedTask| -> Result<(), BlockedTask>) {
+        let id = self.id;
+        cur_task.put_runtime(self);
+
+        unsafe {
+            let cur_task_dupe = &mut *cur_task as *mut Task;
+            let task = BlockedTask::block(cur_task);
+
+            // The thread can be reawakened by another one while `f` runs, in
+            // which case it doesn't block
+            set_awoken(id, false);
+            if times == 1 {
+                match f(task) {
+                    Ok(()) => {}
+                    Err(task) => { mem::forget(task.wake()); set_awoken(id, true); }
+                }
+            } else {
+                let iter = task.make_selectable(times);
+                match iter.map(f).filter_map(|a| a.err()).next() {
+                    None => {}
+                    Some(task) => {
+                        match task.wake() {
+                            Some(task) => { mem::forget(task); set_awoken(id, true); }
+                            None => {}
+                        }
+                    }
+                }
+            }
+            reschedule(id, Block);
+            cur_task = mem::transmute(cur_task_dupe);
+        }
+
+        Local::put(cur_task);
+    }
+
+    fn reawaken(self: Box<ModelRuntime>, mut to_wake: Box<Task>) {
+        let id = self.id;
+        to_wake.put_runtime(self);
+        unsafe { mem::forget(to_wake) }
+        set_awoken(id, true);
+    }
+
+    fn reawaken_later(self: Box<ModelRuntime>, to_wake: Box<Task>) {
+        self.reawaken(to_wake)
+    }
+
+    fn spawn_sibling(self: Box<ModelRuntime>,
+                     mut cur_task: Box<Task>,
+                     _opts: TaskOpts,
+                     f: proc():Send) {
+        cur_task.put_runtime(self);
+        Local::put(cur_task);
+        spawn(f);
+    }
+
+    fn local_io<'a>(&'a mut self) -> Option<rtio::LocalIo<'a>> { None }
+
+    fn stack_bounds(&self) -> (uint, uint) { self.stack }
+
+    fn can_block(&self) -> bool { true }
+
+    fn wrap(self: Box<ModelRuntime>) -> Box<Any> {
+        self as Box<Any>
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use comm::{channel, Select, Empty, Disconnected};
+    use super::{check, spawn};
+
+    #[test]
+    fn oneshot_send_recv() {
+        fn body() {
+            let (tx, rx) = channel();
+            spawn(proc() tx.send(1i));
+            assert_eq!(rx.recv(), 1);
+        }
+        check(body);
+    }
+
+    #[test]
+    fn oneshot_sender_gone() {
+        fn body() {
+            let (tx, rx) = channel::<int>();
+            spawn(proc() drop(tx));
+            assert!(rx.recv_opt().is_err());
+        }
+        check(body);
+    }
+
+    #[test]
+    fn oneshot_receiver_gone() {
+        fn body() {
+            let (tx, rx) = channel();
+            spawn(proc() drop(rx));
+            let _ = tx.send_opt(1i);
+        }
+        check(body);
+    }
+
+    #[test]
+    fn stream_send_recv() {
+        fn body() {
+            let (tx, rx) = channel();
+            spawn(proc() {
+                tx.send(1i);
+                tx.send(2);
+                tx.send(3);
+            });
+            assert_eq!(rx.recv(), 1);
+            assert_eq!(rx.recv(), 2);
+            assert_eq!(rx.recv(), 3);
+            assert!(rx.recv_opt().is_err());
+        }
+        check(body);
+    }
+
+    // The receiver steals messages with `try_recv`, which `recv` then has to
+    // account for as it blocks
+    #[test]
+    fn stream_steals() {
+        fn body() {
+            let (tx, rx) = channel();
+            spawn(proc() {
+                tx.send(1i);
+                tx.send(2);
+                tx.send(3);
+            });
+            let mut got = 0;
+            while got < 3 {
+                match rx.try_recv() {
+                    Ok(_) => got += 1,
+                    Err(Empty) => { assert!(rx.recv() > got); got += 1 }
+                    Err(Disconnected) => fail!(),
+                }
+            }
+        }
+        check(body);
+    }
+
+    #[test]
+    fn stream_receiver_gone() {
+        fn body() {
+            let (tx, rx) = channel();
+            spawn(proc() {
+                let _ = tx.send_opt(1i);
+                let _ = tx.send_opt(2);
+                let _ = tx.send_opt(3);
+            });
+            let _ = rx.recv_opt();
+            drop(rx);
+        }
+        check(body);
+    }
+
+    #[test]
+    fn shared_send_recv() {
+        fn body() {
+            let (tx, rx) = channel();
+            let tx2 = tx.clone();
+            spawn(proc() tx.send(1i));
+            spawn(proc() tx2.send(2i));
+            let (a, b) = (rx.recv(), rx.recv());
+            assert_eq!(a + b, 3);
+            assert!(rx.recv_opt().is_err());
+        }
+        check(body);
+    }
+
+    // Cloning a sender on one thread while the receiver blocks on another
+    // upgrades the channel under the blocked receiver
+    #[test]
+    fn shared_upgrade_while_blocked() {
+        fn body() {
+            let (tx, rx) = channel();
+            spawn(proc() {
+                let tx2 = tx.clone();
+                tx.send(1i);
+                tx2.send(2i);
+            });
+            assert_eq!(rx.recv(), 1);
+            assert_eq!(rx.recv(), 2);
+        }
+        check(body);
+    }
+
+    #[test]
+    fn select_two_channels() {
+        fn body() {
+            let (tx1, rx1) = channel();
+            let (tx2, rx2) = channel();
+            spawn(proc() tx1.send(1i));
+            spawn(proc() tx2.send(2i));
+            let sel = Select::new();
+            let mut h1 = sel.handle(&rx1);
+            let mut h2 = sel.handle(&rx2);
+            unsafe { h1.add(); h2.add(); }
+            let id = sel.wait();
+            if id == h1.id() {
+                assert_eq!(h1.recv(), 1);
+                assert_eq!(h2.recv(), 2);
+            } else {
+                assert_eq!(id, h2.id());
+                assert_eq!(h2.recv(), 2);
+                assert_eq!(h1.recv(), 1);
+            }
+        }
+        check(body);
+    }
+
+    #[test]
+    fn select_sender_gone() {
+        fn body() {
+            let (tx1, rx1) = channel::<int>();
+            let (_tx2, rx2) = channel::<int>();
+            spawn(proc() drop(tx1));
+            let sel = Select::new();
+            let mut h1 = sel.handle(&rx1);
+            let mut h2 = sel.handle(&rx2);
+            unsafe { h1.add(); h2.add(); }
+            assert_eq!(sel.wait(), h1.id());
+            assert!(h1.recv_opt().is_err());
+        }
+        check(body);
+    }
+
+    #[test]
+    #[should_fail]
+    fn finds_deadlocks() {
+        fn body() {
+            let (tx1, rx1) = channel::<int>();
+            let (tx2, rx2) = channel::<int>();
+            spawn(proc() { rx2.recv(); tx1.send(1); });
+            rx1.recv();
+            tx2.send(2);
+        }
+        check(body);
+    }
+}
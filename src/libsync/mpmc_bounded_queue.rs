@@ -25,8 +25,21 @@
  * policies, either expressed or implied, of Dmitry Vyukov.
  */
 
+//! A lock-free, bounded, multi-producer, multi-consumer queue.
+//!
+//! The queue is a fixed-size ring buffer in which each slot carries a sequence
+//! number. A pusher claims a slot by bumping the enqueue position once the
+//! slot's sequence number says it's free, and publishes its value by bumping
+//! the sequence number again. Poppers do the opposite, so pushers and poppers
+//! only contend with each other when the queue is nearly empty or nearly full.
+//!
+//! Unlike channels, both ends of the queue may be cloned, and operations never
+//! block: pushing onto a full queue or popping from an empty one fails right
+//! away. This makes the queue suitable for pools of tasks working on a shared
+//! set of jobs.
+
 #![experimental]
-#![allow(missing_doc, dead_code)]
+#![allow(dead_code)]
 
 // http://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue
 
@@ -45,6 +58,7 @@ struct Node<T> {
 }
 
 struct State<T> {
+    // Keep the positions on cache lines of their own
     pad0: [u8, ..64],
     buffer: Vec<UnsafeCell<Node<T>>>,
     mask: uint,
@@ -55,6 +69,8 @@ struct State<T> {
     pad3: [u8, ..64],
 }
 
+/// A handle to a bounded queue. Cloning the handle gives another handle to the
+/// same queue, which can be sent to another task.
 pub struct Queue<T> {
     state: Arc<State<T>>,
 }
@@ -86,7 +102,7 @@ impl<T: Send> State<T> {
         }
     }
 
-    fn push(&self, value: T) -> bool {
+    fn push(&self, value: T) -> Result<(), T> {
         let mask = self.mask;
         let mut pos = self.enqueue_pos.load(Relaxed);
         loop {
@@ -106,12 +122,12 @@ impl<T: Send> State<T> {
                     pos = enqueue_pos;
                 }
             } else if diff < 0 {
-                return false
+                return Err(value)
             } else {
                 pos = self.enqueue_pos.load(Relaxed);
             }
         }
-        true
+        Ok(())
     }
 
     fn pop(&self) -> Option<T> {
@@ -142,16 +158,26 @@ impl<T: Send> State<T> {
 }
 
 impl<T: Send> Queue<T> {
+    /// Creates a new queue which holds at least `capacity` values. The
+    /// capacity is rounded up to a power of two, and is at least two.
     pub fn with_capacity(capacity: uint) -> Queue<T> {
         Queue{
             state: Arc::new(State::with_capacity(capacity))
         }
     }
 
-    pub fn push(&self, value: T) -> bool {
+    /// Returns the number of values the queue can hold at once.
+    pub fn capacity(&self) -> uint {
+        self.state.mask + 1
+    }
+
+    /// Pushes a value onto the queue, giving it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
         self.state.push(value)
     }
 
+    /// Pops the oldest value off of the queue, returning `None` if the queue
+    /// is empty.
     pub fn pop(&self) -> Option<T> {
         self.state.pop()
     }
@@ -169,6 +195,23 @@ mod tests {
     use super::Queue;
     use native;
 
+    #[test]
+    fn full() {
+        let q = Queue::with_capacity(3);
+        assert_eq!(q.capacity(), 4);
+        for i in range(0i, 4) {
+            assert_eq!(q.push(i), Ok(()));
+        }
+        assert_eq!(q.push(4), Err(4));
+        assert_eq!(q.pop(), Some(0));
+        assert_eq!(q.push(4), Ok(()));
+        for i in range(1i, 5) {
+            assert_eq!(q.pop(), Some(i));
+        }
+        assert_eq!(q.pop(), None);
+        assert_eq!(Queue::<int>::with_capacity(0).capacity(), 2);
+    }
+
     #[test]
     fn test() {
         let nthreads = 8u;
@@ -183,7 +226,7 @@ mod tests {
             native::task::spawn(proc() {
                 let q = q;
                 for i in range(0, nmsgs) {
-                    assert!(q.push(i));
+                    assert!(q.push(i).is_ok());
                 }
                 tx.send(());
             });
@@ -0,0 +1,286 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A multi-producer, single consumer queue which allocates in blocks.
+//!
+//! The node-based queue in `mpsc_queue` allocates (and frees) a node for every
+//! value which is pushed. This queue instead stores values in blocks of
+//! `BLOCK_SIZE` slots. Producers claim a slot in the last block with a single
+//! atomic increment, and only the producer which claims the first slot past
+//! the end of a block allocates and links in the next one. The consumer frees
+//! whole blocks once it has moved past them.
+//!
+//! Like `mpsc_queue`, a pop may find the queue in an inconsistent state where
+//! a producer has claimed a slot but not yet filled it in. Unlike it, pushes
+//! aren't lock-free: producers which run off the end of a block wait for the
+//! one which claimed its first slot past the end to link in the next block.
+//!
+//! # Reclamation
+//!
+//! A producer can load the last block and then be preempted before claiming a
+//! slot in it, so the consumer can't free a block as soon as it has been
+//! drained. Instead producers pin an `epoch::Collector` for the duration of a
+//! push, and the consumer hands drained blocks to the collector. A block is
+//! only drained once a later block has been linked in as the last one, so
+//! producers which pin after that can't be holding on to it.
+
+#![experimental]
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::mem;
+use core::ptr;
+
+use atomics::{AtomicPtr, AtomicUint, AtomicBool, Acquire, Release, Relaxed};
+use atomics::SeqCst;
+use backoff::{Backoff, SpinYield};
+use epoch::Collector;
+use mpsc_queue::{PopResult, Data, Empty, Inconsistent};
+
+/// The number of values stored in each block of the queue.
+pub static BLOCK_SIZE: uint = 32;
+
+struct Slot<T> {
+    ready: AtomicBool,
+    value: UnsafeCell<Option<T>>,
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    // number of slots which have been claimed by producers, this will grow
+    // past BLOCK_SIZE while the next block is being linked in
+    claimed: AtomicUint,
+    slots: [Slot<T>, ..BLOCK_SIZE],
+}
+
+/// The multi-producer single-consumer structure. This is not cloneable, but it
+/// may be safely shared so long as it is guaranteed that there is only one
+/// popper at a time (many pushers are allowed).
+pub struct Queue<T> {
+    // producer fields
+    tail: AtomicPtr<Block<T>>,  // the block being pushed onto
+    collector: Collector,       // pinned by producers, frees drained blocks
+
+    pad0: [u8, ..64],
+
+    // consumer fields
+    head: UnsafeCell<*mut Block<T>>,     // the block being popped from
+    index: UnsafeCell<uint>,             // the next slot to pop in `head`
+}
+
+impl<T: Send> Block<T> {
+    unsafe fn new() -> *mut Block<T> {
+        let mut b = box Block {
+            next: AtomicPtr::new(0 as *mut Block<T>),
+            claimed: AtomicUint::new(0),
+            slots: mem::uninitialized(),
+        };
+        for slot in b.slots.mut_iter() {
+            ptr::write(slot, Slot {
+                ready: AtomicBool::new(false),
+                value: UnsafeCell::new(None),
+            });
+        }
+        mem::transmute(b)
+    }
+}
+
+impl<T: Send> Queue<T> {
+    /// Creates a new queue that is safe to share among multiple producers and
+    /// one consumer.
+    pub fn new() -> Queue<T> {
+        let b = unsafe { Block::new() };
+        Queue {
+            tail: AtomicPtr::new(b),
+            collector: Collector::new(),
+            pad0: [0, ..64],
+            head: UnsafeCell::new(b),
+            index: UnsafeCell::new(0),
+        }
+    }
+
+    /// Pushes a new value onto this queue.
+    pub fn push(&self, t: T) {
+        self.push_with(t, |step| SpinYield.snooze(step))
+    }
+
+    /// Pushes a new value onto this queue. While another producer is linking
+    /// in a new block, `snooze` is called with the number of times it has
+    /// been called before, as with `Backoff::snooze`.
+    pub fn push_with(&self, t: T, snooze: |uint|) {
+        let _guard = self.collector.pin();
+        let mut t = Some(t);
+        unsafe {
+            let mut step = 0;
+            loop {
+                let b = self.tail.load(SeqCst);
+                let i = (*b).claimed.fetch_add(1, SeqCst);
+                if i < BLOCK_SIZE {
+                    let slot = &(*b).slots[i];
+                    *slot.value.get() = t.take();
+                    slot.ready.store(true, Release);
+                    break
+                }
+
+                // The first producer to run off the end of the block links in
+                // the next one (keeping the first slot for itself), and
+                // everyone else waits for that to happen.
+                if i == BLOCK_SIZE {
+                    let n = Block::new();
+                    (*n).claimed.store(1, Relaxed);
+                    *(*n).slots[0].value.get() = t.take();
+                    (*n).slots[0].ready.store(true, Relaxed);
+                    self.tail.store(n, SeqCst);
+                    (*b).next.store(n, SeqCst);
+                    break
+                }
+                while self.tail.load(SeqCst) == b {
+                    snooze(step);
+                    step += 1;
+                }
+            }
+        }
+    }
+
+    /// Pops some data from this queue.
+    ///
+    /// As with `mpsc_queue::Queue::pop`, this returns `Inconsistent` if a
+    /// producer has claimed the next slot but has yet to fill it in.
+    pub fn pop(&self) -> PopResult<T> {
+        unsafe {
+            let b = *self.head.get();
+            let i = *self.index.get();
+            if i == BLOCK_SIZE {
+                let next = (*b).next.load(SeqCst);
+                if next.is_null() {
+                    return if (*b).claimed.load(SeqCst) > BLOCK_SIZE {
+                        Inconsistent
+                    } else {
+                        Empty
+                    }
+                }
+                *self.head.get() = next;
+                *self.index.get() = 0;
+                // Producers may still be looking at the drained block, but
+                // ones which pin from now on will see a later one
+                let b: Box<Block<T>> = mem::transmute(b);
+                self.collector.defer_drop(b);
+                return self.pop()
+            }
+
+            let slot = &(*b).slots[i];
+            if slot.ready.load(Acquire) {
+                *self.index.get() = i + 1;
+                return Data((*slot.value.get()).take_unwrap())
+            }
+            if (*b).claimed.load(SeqCst) > i {Inconsistent} else {Empty}
+        }
+    }
+
+    /// Attempts to pop data from this queue, but doesn't attempt too hard. This
+    /// will canonicalize inconsistent states to a `None` value.
+    pub fn casual_pop(&self) -> Option<T> {
+        match self.pop() {
+            Data(t) => Some(t),
+            Empty | Inconsistent => None,
+        }
+    }
+
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Queue<T> {
+    fn drop(&mut self) {
+        // Popped values have been taken out of their slots, so freeing the
+        // blocks drops exactly the values which are still in the queue. The
+        // drained blocks before `head` are freed by the collector.
+        unsafe {
+            let mut cur = *self.head.get();
+            while !cur.is_null() {
+                let next = (*cur).next.load(Relaxed);
+                let _: Box<Block<T>> = mem::transmute(cur);
+                cur = next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::*;
+
+    use alloc::arc::Arc;
+
+    use native;
+    use super::{Queue, BLOCK_SIZE};
+    use mpsc_queue::{Data, Empty, Inconsistent};
+
+    #[test]
+    fn test_full() {
+        let q = Queue::new();
+        for i in range(0, BLOCK_SIZE * 3 + 1) {
+            q.push(box i);
+        }
+        for _ in range(0, BLOCK_SIZE + 1) {
+            assert!(q.casual_pop().is_some());
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        let q = Queue::new();
+        match q.pop() { Empty => {}, _ => fail!() }
+        for round in range(0u, 4) {
+            for i in range(0, BLOCK_SIZE * round + 5) { q.push(i); }
+            for i in range(0, BLOCK_SIZE * round + 5) {
+                match q.pop() { Data(j) => assert_eq!(i, j), _ => fail!() }
+            }
+            match q.pop() { Empty => {}, _ => fail!() }
+        }
+    }
+
+    #[test]
+    fn test() {
+        let nthreads = 8u;
+        let nmsgs = 1000u;
+        let q = Queue::new();
+        match q.pop() {
+            Empty => {}
+            Inconsistent | Data(..) => fail!()
+        }
+        let (tx, rx) = channel();
+        let q = Arc::new(q);
+
+        for _ in range(0, nthreads) {
+            let tx = tx.clone();
+            let q = q.clone();
+            native::task::spawn(proc() {
+                for i in range(0, nmsgs) {
+                    q.push(i);
+                }
+                tx.send(());
+            });
+        }
+
+        let mut i = 0u;
+        while i < nthreads * nmsgs {
+            match q.pop() {
+                Empty | Inconsistent => {},
+                Data(_) => { i += 1 }
+            }
+        }
+        drop(tx);
+        for _ in range(0, nthreads) {
+            rx.recv();
+        }
+    }
+}
@@ -70,8 +70,14 @@ struct Node<T> {
 /// may be safely shared so long as it is guaranteed that there is only one
 /// popper at a time (many pushers are allowed).
 pub struct Queue<T> {
-    head: AtomicPtr<Node<T>>,
-    tail: UnsafeCell<*mut Node<T>>,
+    head: AtomicPtr<Node<T>>, // pushed onto by the producers
+    tail: UnsafeCell<*mut Node<T>>, // only touched by the consumer
+}
+
+/// A node which has been allocated for a push but not yet published to the
+/// consumer. See `Queue::reserve`.
+pub struct Slot<T> {
+    node: *mut Node<T>,
 }
 
 impl<T> Node<T> {
@@ -103,6 +109,25 @@ impl<T: Send> Queue<T> {
         }
     }
 
+    /// Allocates a node for a future push without publishing it to the
+    /// consumer. The node's value can be filled in place through the returned
+    /// slot, and it is made visible to the consumer by `commit`.
+    pub fn reserve(&self) -> Slot<T> {
+        Slot { node: unsafe { Node::new(None) } }
+    }
+
+    /// Publishes a previously reserved slot. The slot's value must have been
+    /// filled in.
+    pub fn commit(&self, slot: Slot<T>) {
+        unsafe {
+            let n = slot.node;
+            mem::forget(slot);
+            assert!((*n).value.is_some());
+            let prev = self.head.swap(n, AcqRel);
+            (*prev).next.store(n, Release);
+        }
+    }
+
     /// Pops some data from this queue.
     ///
     /// Note that the current implementation means that this function cannot
@@ -141,6 +166,21 @@ impl<T: Send> Queue<T> {
     }
 }
 
+impl<T: Send> Slot<T> {
+    /// Returns the storage for the value of this reserved node.
+    pub fn value<'a>(&'a mut self) -> &'a mut Option<T> {
+        unsafe { &mut (*self.node).value }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Slot<T> {
+    fn drop(&mut self) {
+        // Uncommitted slots were never linked into the queue
+        let _: Box<Node<T>> = unsafe { mem::transmute(self.node) };
+    }
+}
+
 #[unsafe_destructor]
 impl<T: Send> Drop for Queue<T> {
     fn drop(&mut self) {
@@ -171,6 +211,23 @@ mod tests {
         q.push(box 2i);
     }
 
+    #[test]
+    fn reserve_commit() {
+        let q = Queue::new();
+        let mut a = q.reserve();
+        *a.value() = Some(1i);
+        q.push(2);
+        q.commit(a);
+        match q.pop() { Data(2) => {}, _ => fail!() }
+        match q.pop() { Data(1) => {}, _ => fail!() }
+        match q.pop() { Empty => {}, _ => fail!() }
+
+        let mut b = q.reserve();
+        *b.value() = Some(3);
+        drop(b);
+        match q.pop() { Empty => {}, _ => fail!() }
+    }
+
     #[test]
     fn test() {
         let nthreads = 8u;
@@ -53,6 +53,11 @@
 // leading to fairly decent performance for both native threads and green
 // threads on various workloads (uncontended and contended).
 //
+// The os mutex is whatever `rustrt::mutex` picks for the platform at compile
+// time. On Linux that's a futex, so native tasks which don't contend with one
+// another never enter the kernel, and elsewhere it's a pthread mutex (or a
+// critical section on windows).
+//
 // The crux of this implementation is an atomic work which is CAS'd on many
 // times in order to manage a few flags about who's blocking where and whether
 // it's locked or not.
@@ -60,6 +65,7 @@
 use core::prelude::*;
 
 use alloc::boxed::Box;
+use collections::{Vec, MutableSeq};
 use core::atomics;
 use core::mem;
 use core::cell::UnsafeCell;
@@ -68,6 +74,8 @@ use rustrt::mutex;
 use rustrt::task::{BlockedTask, Task};
 use rustrt::thread::Thread;
 
+use backoff::{Backoff, Spin};
+use park::{park, unparker, Unparker};
 use q = mpsc_intrusive;
 
 pub static LOCKED: uint = 1 << 0;
@@ -521,11 +529,130 @@ impl Drop for Mutex {
     }
 }
 
+/// A fair mutex, which is granted to tasks in the order they asked for it.
+///
+/// `Mutex` lets whichever task gets there first take the lock when it's
+/// unlocked, which is fast but may starve some tasks under heavy contention.
+/// A ticket mutex instead hands out tickets to the tasks locking it, and lets
+/// them in one after the other. This costs some throughput, as the lock can't
+/// be taken by a task which happens to be running while the next one in line
+/// is being woken up.
+///
+/// Like `Mutex`, this works with both green and native tasks.
+///
+/// # Example
+///
+/// ```rust
+/// use sync::mutex::TicketMutex;
+///
+/// let m = TicketMutex::new();
+/// let guard = m.lock();
+/// // do some work
+/// drop(guard); // unlock the lock
+/// ```
+pub struct TicketMutex {
+    /// The next ticket to be handed out
+    next: atomics::AtomicUint,
+    /// The ticket of the task holding the lock
+    serving: atomics::AtomicUint,
+    /// Tasks which are waiting for their turn, with their tickets
+    waiters: Mutex,
+    parked: UnsafeCell<Vec<(uint, Unparker)>>,
+}
+
+/// An RAII guard of a locked `TicketMutex`, which unlocks it when dropped.
+#[must_use]
+pub struct TicketGuard<'a> {
+    lock: &'a TicketMutex,
+}
+
+// How many times a task checks whether it's its turn before parking
+static TICKET_SPINS: uint = 16;
+
+impl TicketMutex {
+    /// Creates a new ticket mutex in an unlocked state.
+    pub fn new() -> TicketMutex {
+        TicketMutex {
+            next: atomics::AtomicUint::new(0),
+            serving: atomics::AtomicUint::new(0),
+            waiters: Mutex::new(),
+            parked: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// Attempts to acquire this lock, returning `None` if it's held or if
+    /// other tasks are waiting for it.
+    ///
+    /// This function does not block.
+    pub fn try_lock<'a>(&'a self) -> Option<TicketGuard<'a>> {
+        let ticket = self.serving.load(atomics::Acquire);
+        match self.next.compare_and_swap(ticket, ticket + 1, atomics::Acquire) {
+            n if n == ticket => Some(TicketGuard { lock: self }),
+            _ => None,
+        }
+    }
+
+    /// Acquires this lock, blocking the current task until every task which
+    /// asked for it earlier has had it.
+    pub fn lock<'a>(&'a self) -> TicketGuard<'a> {
+        let ticket = self.next.fetch_add(1, atomics::Relaxed);
+        for step in range(0, TICKET_SPINS) {
+            if self.serving.load(atomics::Acquire) == ticket {
+                return TicketGuard { lock: self }
+            }
+            Spin.snooze(step);
+        }
+
+        // Our turn may have come while we were registering, but the unlocker
+        // looks for us with the waiters lock held, so checking again after
+        // registering is enough to not miss it.
+        unsafe {
+            let _g = self.waiters.lock();
+            (*self.parked.get()).push((ticket, unparker()));
+        }
+        while self.serving.load(atomics::Acquire) != ticket {
+            park();
+        }
+        unsafe {
+            let _g = self.waiters.lock();
+            let parked = &mut *self.parked.get();
+            let i = parked.iter().position(|&(t, _)| t == ticket).unwrap();
+            parked.swap_remove(i);
+        }
+        TicketGuard { lock: self }
+    }
+
+    fn unlock(&self) {
+        let next = self.serving.load(atomics::Relaxed) + 1;
+        self.serving.store(next, atomics::Release);
+        let _g = self.waiters.lock();
+        let parked = unsafe { &*self.parked.get() };
+        for &(ticket, ref unparker) in parked.iter() {
+            if ticket == next {
+                unparker.unpark();
+                break
+            }
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for TicketGuard<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::prelude::*;
-    use super::{Mutex, StaticMutex, MUTEX_INIT};
+    use std::task;
+    use super::{Mutex, StaticMutex, MUTEX_INIT, TicketMutex};
+    use alloc::arc::Arc;
+    use atomics::{AtomicUint, SeqCst, Relaxed};
     use native;
+    use test::Bencher;
 
     #[test]
     fn smoke() {
@@ -583,4 +710,98 @@ mod test {
         let m = Mutex::new();
         assert!(m.try_lock().is_some());
     }
+
+    #[test]
+    fn ticket_smoke() {
+        let m = TicketMutex::new();
+        drop(m.lock());
+        let g = m.try_lock();
+        assert!(g.is_some());
+        assert!(m.try_lock().is_none());
+        drop(g);
+        drop(m.lock());
+    }
+
+    #[test]
+    fn ticket_fifo() {
+        let m = Arc::new(TicketMutex::new());
+        let g = m.lock();
+        let (tx, rx) = channel();
+        for i in range(0u, 4) {
+            let (m, tx) = (m.clone(), tx.clone());
+            native::task::spawn(proc() {
+                let _g = m.lock();
+                tx.send(i);
+            });
+            // Let the task take its ticket before spawning the next one
+            while m.next.load(SeqCst) != i + 2 {
+                task::deschedule();
+            }
+        }
+        drop(g);
+        for i in range(0u, 4) {
+            assert_eq!(rx.recv(), i);
+        }
+    }
+
+    #[test]
+    fn ticket_lots_and_lots() {
+        static M: uint = 1000;
+        static N: uint = 3;
+        let m = Arc::new(TicketMutex::new());
+        let cnt = Arc::new(AtomicUint::new(0));
+
+        let (tx, rx) = channel();
+        for i in range(0, 2 * N) {
+            let (m, cnt, tx) = (m.clone(), cnt.clone(), tx.clone());
+            let f = proc() {
+                for _ in range(0, M) {
+                    let _g = m.lock();
+                    // not an atomic increment, so races would lose updates
+                    let n = cnt.load(Relaxed);
+                    cnt.store(n + 1, Relaxed);
+                }
+                tx.send(());
+            };
+            if i % 2 == 0 { native::task::spawn(f) } else { spawn(f) }
+        }
+        drop(tx);
+        for _ in range(0, 2 * N) { rx.recv(); }
+        assert_eq!(cnt.load(SeqCst), M * N * 2);
+    }
+
+    struct Locks {
+        mutex: Mutex,
+        ticket: TicketMutex,
+    }
+
+    // Has a few native tasks take turns with one of the locks
+    fn contended(lock_unlock: fn(&Locks)) {
+        let locks = Arc::new(Locks {
+            mutex: Mutex::new(),
+            ticket: TicketMutex::new(),
+        });
+        let (tx, rx) = channel();
+        for _ in range(0u, 4) {
+            let (locks, tx) = (locks.clone(), tx.clone());
+            native::task::spawn(proc() {
+                for _ in range(0u, 1000) { lock_unlock(&*locks); }
+                tx.send(());
+            });
+        }
+        drop(tx);
+        for _ in range(0u, 4) { rx.recv(); }
+    }
+
+    #[bench]
+    fn bench_mutex_contended(b: &mut Bencher) {
+        fn f(l: &Locks) { drop(l.mutex.lock()); }
+        b.iter(|| contended(f));
+    }
+
+    #[bench]
+    fn bench_ticket_contended(b: &mut Bencher) {
+        fn f(l: &Locks) { drop(l.ticket.lock()); }
+        b.iter(|| contended(f));
+    }
 }
@@ -0,0 +1,229 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pools of queue nodes shared among all channels
+//!
+//! Each SPSC queue keeps a cache of nodes, but when the queue is destroyed all
+//! of those nodes are freed, only for the next queue to allocate them all
+//! over again. Short-lived channels (as in request/reply patterns) would then
+//! spend most of their time in the allocator. Queues instead return their
+//! nodes to this pool when they're done with them, and draw from it when
+//! their own cache is empty.
+//!
+//! Nodes of different types are pooled by size class, so this is essentially a
+//! small allocator of raw memory. Each thread has its own pool, so taking from
+//! or returning to it needs no synchronization, and a node freed by one thread
+//! is reused by the next queue created on that thread. A thread's pool is freed
+//! when the thread exits. Windows has no destructors for thread-local data, so
+//! there the pool is a single one for the whole process, guarded by a mutex,
+//! which is only consulted when a queue's own cache misses or overflows and
+//! when a queue is destroyed. Pools are bounded, so they never hold on to more
+//! than a fixed amount of memory.
+
+use core::prelude::*;
+
+use alloc::heap;
+
+// Every pooled allocation has this alignment, and sizes are rounded up to a
+// multiple of it.
+static ALIGN: uint = 16;
+// Allocations larger than this are not pooled
+static MAX_SIZE: uint = 256;
+static NUM_CLASSES: uint = MAX_SIZE / ALIGN;
+// The maximum number of free allocations kept in each size class of a pool
+static MAX_POOLED: uint = 128;
+
+struct Pool {
+    // Each free list is threaded through the first word of its allocations
+    free: [*mut u8, ..NUM_CLASSES],
+    counts: [uint, ..NUM_CLASSES],
+}
+
+impl Pool {
+    #[cfg(unix)]
+    fn new() -> Pool {
+        Pool {
+            free: [0 as *mut u8, ..NUM_CLASSES],
+            counts: [0, ..NUM_CLASSES],
+        }
+    }
+
+    unsafe fn take(&mut self, c: uint) -> Option<*mut u8> {
+        let p = self.free[c];
+        if p.is_null() { return None }
+        self.free[c] = *(p as *mut *mut u8);
+        self.counts[c] -= 1;
+        Some(p)
+    }
+
+    unsafe fn give(&mut self, c: uint, p: *mut u8) -> bool {
+        if self.counts[c] >= MAX_POOLED { return false }
+        *(p as *mut *mut u8) = self.free[c];
+        self.free[c] = p;
+        self.counts[c] += 1;
+        true
+    }
+
+    // Frees all of the pooled allocations
+    #[cfg(unix)]
+    unsafe fn clear(&mut self) {
+        for c in range(0, NUM_CLASSES) {
+            loop {
+                match self.take(c) {
+                    Some(p) => heap::deallocate(p, (c + 1) * ALIGN, ALIGN),
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use core::prelude::*;
+
+    use alloc::boxed::Box;
+    use core::mem;
+    use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+    use tls = rustrt::thread_local_storage;
+
+    use atomics;
+    use super::Pool;
+
+    static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+    static mut KEY: tls::Key = 0;
+    static mut KEY_CREATED: atomics::AtomicBool = atomics::INIT_ATOMIC_BOOL;
+
+    unsafe fn key() -> tls::Key {
+        if !KEY_CREATED.load(atomics::Acquire) {
+            let _g = LOCK.lock();
+            if !KEY_CREATED.load(atomics::Relaxed) {
+                tls::create_with_destructor(&mut KEY, destroy);
+                KEY_CREATED.store(true, atomics::Release);
+            }
+        }
+        KEY
+    }
+
+    // Called as the thread exits
+    unsafe extern "C" fn destroy(pool: *mut u8) {
+        let mut pool: Box<Pool> = mem::transmute(pool);
+        pool.clear();
+    }
+
+    pub unsafe fn with_pool<R>(f: |&mut Pool| -> R) -> R {
+        let key = key();
+        let mut pool = tls::get(key) as *mut Pool;
+        if pool.is_null() {
+            pool = mem::transmute(box Pool::new());
+            tls::set(key, pool as *mut u8);
+        }
+        f(&mut *pool)
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+
+    use super::{Pool, NUM_CLASSES};
+
+    static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+    static mut POOL: Pool = Pool {
+        free: [0 as *mut u8, ..NUM_CLASSES],
+        counts: [0, ..NUM_CLASSES],
+    };
+
+    pub unsafe fn with_pool<R>(f: |&mut Pool| -> R) -> R {
+        let _g = LOCK.lock();
+        f(&mut POOL)
+    }
+}
+
+fn class(size: uint, align: uint) -> Option<uint> {
+    if size == 0 || size > MAX_SIZE || align > ALIGN {
+        None
+    } else {
+        Some((size + ALIGN - 1) / ALIGN - 1)
+    }
+}
+
+/// Allocates memory for a node, preferring to reuse a pooled allocation.
+pub unsafe fn allocate(size: uint, align: uint) -> *mut u8 {
+    let c = match class(size, align) {
+        Some(c) => c,
+        None => return heap::allocate(size, align),
+    };
+    match imp::with_pool(|pool| pool.take(c)) {
+        Some(p) => p,
+        None => heap::allocate((c + 1) * ALIGN, ALIGN),
+    }
+}
+
+/// Returns memory obtained from `allocate` to the pool. The `size` and
+/// `align` must be the same as those passed to `allocate`.
+pub unsafe fn deallocate(p: *mut u8, size: uint, align: uint) {
+    let c = match class(size, align) {
+        Some(c) => c,
+        None => return heap::deallocate(p, size, align),
+    };
+    if !imp::with_pool(|pool| pool.give(c, p)) {
+        heap::deallocate(p, (c + 1) * ALIGN, ALIGN)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use native;
+    use super::{allocate, deallocate, MAX_SIZE};
+
+    #[test]
+    fn reuse() {
+        unsafe {
+            let a = allocate(40, 8);
+            *(a as *mut uint) = 1;
+            deallocate(a, 40, 8);
+            // 40 and 48 bytes are in the same size class
+            let b = allocate(48, 8);
+            if cfg!(unix) { assert_eq!(a, b); }
+            *(b as *mut [u8, ..48]) = [2, ..48];
+            deallocate(b, 48, 8);
+        }
+    }
+
+    #[test]
+    fn thread_exit() {
+        // The pool of each of these threads is freed as it exits
+        let (tx, rx) = channel();
+        for _ in range(0u, 10) {
+            let tx = tx.clone();
+            native::task::spawn(proc() {
+                unsafe {
+                    let ps = Vec::from_fn(20, |_| allocate(32, 8));
+                    for &p in ps.iter() { deallocate(p, 32, 8); }
+                }
+                tx.send(());
+            });
+        }
+        for _ in range(0u, 10) { rx.recv(); }
+    }
+
+    #[test]
+    fn unpooled() {
+        unsafe {
+            let a = allocate(MAX_SIZE + 1, 8);
+            deallocate(a, MAX_SIZE + 1, 8);
+            let b = allocate(16, 64);
+            deallocate(b, 16, 64);
+        }
+    }
+}
@@ -12,9 +12,13 @@
 //!
 //! This primitive is meant to be used to run one-time initialization. An
 //! example use case would be for initializing an FFI library.
+//!
+//! `OnceCell` builds on it to lazily initialize a value which is then shared,
+//! such as a global table.
 
 use core::prelude::*;
 
+use core::cell::UnsafeCell;
 use core::int;
 use core::atomics;
 
@@ -118,13 +122,73 @@ impl Once {
             unsafe { self.mutex.destroy() }
         }
     }
+
+    // Whether some initialization routine has run and completed
+    fn is_done(&self) -> bool {
+        self.cnt.load(atomics::SeqCst) < 0
+    }
+}
+
+/// A value which is initialized the first time it's asked for.
+///
+/// A `OnceCell` may be a `static`, in which case it's initialized with
+/// `OnceCell { once: ONCE_INIT, value: UnsafeCell { value: None } }`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::OnceCell;
+///
+/// let table = OnceCell::new();
+/// assert!(table.get().is_none());
+/// assert_eq!(table.call_once_init(|| vec![1i, 2, 3]).len(), 3);
+/// // later calls don't run their closure
+/// assert_eq!(table.call_once_init(|| vec![]).len(), 3);
+/// ```
+pub struct OnceCell<T> {
+    /// Guards the initialization. This field is public for static
+    /// initializers, and shouldn't be used otherwise.
+    pub once: Once,
+    /// The value, once initialized. This field is public for static
+    /// initializers, and shouldn't be used otherwise.
+    pub value: UnsafeCell<Option<T>>,
+}
+
+impl<T: Send + Share> OnceCell<T> {
+    /// Creates a new cell, with no value yet.
+    pub fn new() -> OnceCell<T> {
+        OnceCell { once: ONCE_INIT, value: UnsafeCell::new(None) }
+    }
+
+    /// Returns the value of the cell, initializing it with `f` if this is the
+    /// first call.
+    ///
+    /// If another task is initializing the cell, this blocks until it is done
+    /// and returns the value it came up with, without calling `f`.
+    pub fn call_once_init<'a>(&'a self, f: || -> T) -> &'a T {
+        // The value is only written to before `once` is done, by a single
+        // task, and only read from after, so it never changes while it's
+        // being looked at
+        self.once.doit(|| unsafe { *self.value.get() = Some(f()) });
+        unsafe { (*self.value.get()).get_ref() }
+    }
+
+    /// Returns the value of the cell, if it has already been initialized.
+    pub fn get<'a>(&'a self) -> Option<&'a T> {
+        if self.once.is_done() {
+            unsafe { (*self.value.get()).as_ref() }
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::prelude::*;
     use std::task;
-    use super::{ONCE_INIT, Once};
+    use core::cell::UnsafeCell;
+    use super::{ONCE_INIT, Once, OnceCell};
 
     #[test]
     fn smoke_once() {
@@ -169,4 +233,35 @@ mod test {
             rx.recv();
         }
     }
+
+    #[test]
+    fn once_cell() {
+        let c = OnceCell::new();
+        assert!(c.get().is_none());
+        assert_eq!(*c.call_once_init(|| 1i), 1);
+        assert_eq!(*c.call_once_init(|| fail!()), 1);
+        assert_eq!(c.get(), Some(&1));
+    }
+
+    #[test]
+    fn once_cell_static_stampede() {
+        static mut c: OnceCell<uint> = OnceCell {
+            once: ONCE_INIT,
+            value: UnsafeCell { value: None },
+        };
+
+        let (tx, rx) = channel();
+        for i in range(0u, 10) {
+            let tx = tx.clone();
+            spawn(proc() {
+                for _ in range(0u, 4) { task::deschedule() }
+                tx.send(*unsafe { c.call_once_init(|| i) });
+            });
+        }
+        let first = rx.recv();
+        for _ in range(1u, 10) {
+            assert_eq!(rx.recv(), first);
+        }
+        assert_eq!(unsafe { c.get() }, Some(&first));
+    }
 }
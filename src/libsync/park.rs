@@ -0,0 +1,192 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Task parking
+//!
+//! Parking is the primitive which channels and the other blocking types in
+//! this crate are built on: the current task goes to sleep until another task
+//! wakes it up. It works the same for green and native tasks, so other
+//! synchronization primitives can be built on it without caring which runtime
+//! they're used from.
+//!
+//! Every task has a *permit*, which it doesn't hold to start with. `park`
+//! consumes the permit if the task holds it, and otherwise blocks until it is
+//! given one. `Unparker::unpark` gives the permit to its task, waking the task
+//! up if it's parked. Permits don't add up, so unparking a task several times
+//! before it parks only lets one `park` return right away.
+//!
+//! A parked task may also wake up with no permit for other reasons, so `park`
+//! is meant to be called in a loop which checks whether the task can go on.
+//!
+//! # Example
+//!
+//! ```
+//! use std::sync::{Arc, park, unparker};
+//! use std::sync::atomics::{AtomicBool, SeqCst};
+//!
+//! let done = Arc::new(AtomicBool::new(false));
+//! let (unparker, done2) = (unparker(), done.clone());
+//! spawn(proc() {
+//!     done2.store(true, SeqCst);
+//!     unparker.unpark();
+//! });
+//! while !done.load(SeqCst) {
+//!     park();
+//! }
+//! ```
+
+use core::prelude::*;
+
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use rustrt::local::Local;
+use rustrt::local_data::Key;
+use rustrt::task::{Task, BlockedTask};
+
+use atomics::{AtomicUint, SeqCst};
+
+// The values of a parker's state, which is otherwise its parked task
+static EMPTY: uint = 0;
+static NOTIFIED: uint = 1;
+
+struct Parker {
+    state: AtomicUint,
+}
+
+/// A handle which gives the permit to the task it was created in.
+#[deriving(Clone)]
+pub struct Unparker {
+    inner: Arc<Parker>,
+}
+
+static PARKER: Key<Arc<Parker>> = &Key;
+
+fn parker() -> Arc<Parker> {
+    match PARKER.get() {
+        Some(p) => return (*p).clone(),
+        None => {}
+    }
+    let p = Arc::new(Parker { state: AtomicUint::new(EMPTY) });
+    PARKER.replace(Some(p.clone()));
+    p
+}
+
+/// Blocks the current task until it holds the permit, and then consumes it.
+/// If the task already holds the permit, this returns right away.
+///
+/// This may also return before the permit has been given, see the module
+/// documentation.
+pub fn park() {
+    let p = parker();
+    if p.state.compare_and_swap(NOTIFIED, EMPTY, SeqCst) == NOTIFIED {
+        return
+    }
+    let t: Box<Task> = Local::take();
+    t.deschedule(1, |task| {
+        let task = unsafe { task.cast_to_uint() };
+        match p.state.compare_and_swap(EMPTY, task, SeqCst) {
+            EMPTY => Ok(()),
+            n => {
+                // We were unparked in the meantime, so the permit is consumed
+                // right away
+                assert_eq!(n, NOTIFIED);
+                p.state.store(EMPTY, SeqCst);
+                Err(unsafe { BlockedTask::cast_from_uint(task) })
+            }
+        }
+    });
+}
+
+/// Returns a handle which unparks the current task.
+pub fn unparker() -> Unparker {
+    Unparker { inner: parker() }
+}
+
+impl Unparker {
+    /// Gives the permit to this handle's task, waking it up if it's parked.
+    pub fn unpark(&self) {
+        let state = &self.inner.state;
+        let mut cur = state.load(SeqCst);
+        loop {
+            let new = match cur {
+                NOTIFIED => return,
+                EMPTY => NOTIFIED,
+                // Taking the parked task out of the state consumes the
+                // permit on its behalf
+                _ => EMPTY,
+            };
+            match state.compare_and_swap(cur, new, SeqCst) {
+                n if n == cur => break,
+                n => cur = n,
+            }
+        }
+        if cur != EMPTY {
+            unsafe { BlockedTask::cast_from_uint(cur) }.reawaken();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use native;
+    use super::{park, unparker};
+
+    #[test]
+    fn permit_is_kept() {
+        let u = unparker();
+        u.unpark();
+        u.unpark();
+        // permits don't add up, but the first park doesn't block
+        park();
+    }
+
+    #[test]
+    fn unpark_wakes_parked() {
+        let (tx, rx) = channel();
+        spawn(proc() {
+            tx.send(unparker());
+            park();
+        });
+        rx.recv().unpark();
+    }
+
+    #[test]
+    fn ping_pong() {
+        fn go() {
+            let (tx1, rx1) = channel();
+            let (tx2, rx2) = channel();
+            let main = unparker();
+            spawn(proc() {
+                tx1.send(unparker());
+                for i in range(0u, 100) {
+                    park();
+                    tx2.send(i);
+                    main.unpark();
+                }
+            });
+            let other = rx1.recv();
+            for i in range(0u, 100) {
+                other.unpark();
+                loop {
+                    match rx2.try_recv() {
+                        Ok(j) => { assert_eq!(i, j); break }
+                        Err(..) => park(),
+                    }
+                }
+            }
+        }
+        go();
+        let (tx, rx) = channel();
+        native::task::spawn(proc() { go(); tx.send(()); });
+        rx.recv();
+    }
+}
@@ -17,15 +17,18 @@
 
 use core::prelude::*;
 
+use alloc::boxed::Box;
 use core::atomics;
 use core::finally::Finally;
 use core::kinds::marker;
 use core::mem;
 use core::cell::UnsafeCell;
 use collections::{Vec, MutableSeq};
+use rustrt::rtio::{LocalIo, Callback};
 
+use deadlock;
 use mutex;
-use comm::{Receiver, Sender, channel};
+use comm::{Receiver, Sender, Select, channel};
 
 /****************************************************************************
  * Internals
@@ -84,6 +87,39 @@ impl WaitQueue {
     }
 }
 
+// Fires the timeout of a timed wait
+struct TimeoutCallback {
+    tx: Sender<()>,
+}
+
+impl Callback for TimeoutCallback {
+    fn call(&mut self) { let _ = self.tx.send_opt(()); }
+}
+
+// Blocks until `wait_end` is signalled or `ms` milliseconds have passed,
+// returning whether it was signalled (in which case the signal is yet to be
+// received). A timeout can race with a signal, so a waiter which timed out
+// must check its wait end again with the lock held before giving up. The
+// timer comes from the local I/O services, so blocked tasks don't tie up a
+// thread each.
+fn wait_timeout(wait_end: &WaitEnd, ms: u64) -> bool {
+    let mut timer = match LocalIo::maybe_raise(|io| io.timer_init()) {
+        Ok(timer) => timer,
+        Err(..) => fail!("timed waits require a runtime which provides timers"),
+    };
+    let (tx, rx) = channel();
+    timer.oneshot(ms, box TimeoutCallback { tx: tx } as Box<Callback + Send>);
+
+    let sel = Select::new();
+    let mut signal = sel.handle(wait_end);
+    let mut timeout = sel.handle(&rx);
+    unsafe {
+        signal.add();
+        timeout.add();
+    }
+    sel.wait() == signal.id()
+}
+
 // The building-block used to make semaphores, mutexes, and rwlocks.
 struct Sem<Q> {
     lock: mutex::Mutex,
@@ -91,7 +127,9 @@ struct Sem<Q> {
     //      (for good reason). We have an internal invariant on this semaphore,
     //      however, that the queue is never accessed outside of a locked
     //      context.
-    inner: UnsafeCell<SemInner<Q>>
+    inner: UnsafeCell<SemInner<Q>>,
+    // Whether this is a mutex, which deadlock detection keeps track of
+    tracked: bool,
 }
 
 struct SemInner<Q> {
@@ -117,10 +155,15 @@ impl<Q: Send> Sem<Q> {
                 waiters: WaitQueue::new(),
                 count: count,
                 blocked: q,
-            })
+            }),
+            tracked: false,
         }
     }
 
+    fn tracked(&self) -> bool {
+        self.tracked && deadlock::enabled()
+    }
+
     unsafe fn with(&self, f: |&mut SemInner<Q>|) {
         let _g = self.lock.lock();
         // This &mut is safe because, due to the lock, we are the only one who can touch the data
@@ -130,7 +173,17 @@ impl<Q: Send> Sem<Q> {
     pub fn acquire(&self) {
         unsafe {
             let mut waiter_nobe = None;
+            let mut cycle = None;
             self.with(|state| {
+                // Whether we'll block is only known with the lock held, so
+                // this is where a deadlock is detected, before the semaphore
+                // is touched
+                if state.count <= 0 && self.tracked() {
+                    match deadlock::waiting(self as *const _ as uint) {
+                        Ok(()) => {}
+                        Err(c) => { cycle = Some(c); return }
+                    }
+                }
                 state.count -= 1;
                 if state.count < 0 {
                     // Create waiter nobe, enqueue ourself, and tell
@@ -138,6 +191,10 @@ impl<Q: Send> Sem<Q> {
                     waiter_nobe = Some(state.waiters.wait_end());
                 }
             });
+            match cycle {
+                Some(cycle) => fail!("deadlock detected:\n{}", cycle),
+                None => {}
+            }
             // Uncomment if you wish to test for sem races. Not
             // valgrind-friendly.
             /* for _ in range(0u, 1000) { task::deschedule(); } */
@@ -145,10 +202,77 @@ impl<Q: Send> Sem<Q> {
             if waiter_nobe.is_some() {
                 let _ = waiter_nobe.unwrap().recv();
             }
+            if self.tracked() { deadlock::acquired(self as *const _ as uint) }
         }
     }
 
+    pub fn try_acquire(&self) -> bool {
+        let mut acquired = false;
+        unsafe {
+            self.with(|state| {
+                if state.count > 0 {
+                    state.count -= 1;
+                    acquired = true;
+                }
+            })
+        }
+        if acquired && self.tracked() {
+            deadlock::acquired(self as *const _ as uint)
+        }
+        acquired
+    }
+
+    pub fn acquire_timeout(&self, ms: u64) -> bool {
+        let mut waiter_nobe = None;
+        unsafe {
+            self.with(|state| {
+                state.count -= 1;
+                if state.count < 0 {
+                    waiter_nobe = Some(state.waiters.wait_end());
+                }
+            })
+        }
+        // Waits which time out can't deadlock, so they aren't reported as
+        // waiting for the mutex
+        let wait_end = match waiter_nobe {
+            Some(wait_end) => wait_end,
+            None => {
+                if self.tracked() {
+                    deadlock::acquired(self as *const _ as uint)
+                }
+                return true
+            }
+        };
+        if wait_timeout(&wait_end, ms) {
+            let _ = wait_end.recv();
+            if self.tracked() { deadlock::acquired(self as *const _ as uint) }
+            return true
+        }
+
+        // Signals are only sent with the lock held, so with the lock held we
+        // can tell for sure whether we were signalled after timing out. If
+        // not, give up our place in line. Our wait end has to be closed before
+        // the lock is released, so that the next signal skips over us.
+        let mut acquired = false;
+        let mut wait_end = Some(wait_end);
+        unsafe {
+            self.with(|state| {
+                let wait_end = wait_end.take_unwrap();
+                if wait_end.try_recv().is_ok() {
+                    acquired = true;
+                } else {
+                    state.count += 1;
+                }
+            })
+        }
+        if acquired && self.tracked() {
+            deadlock::acquired(self as *const _ as uint)
+        }
+        acquired
+    }
+
     pub fn release(&self) {
+        if self.tracked() { deadlock::released(self as *const _ as uint) }
         unsafe {
             self.with(|state| {
                 state.count += 1;
@@ -182,8 +306,23 @@ impl Sem<Vec<WaitQueue>> {
     // The only other places that condvars get built are rwlock.write_cond()
     // and rwlock_write_mode.
     pub fn access_cond<'a>(&'a self) -> SemCondGuard<'a> {
+        self.acquire();
+        self.cond_guard()
+    }
+
+    pub fn try_access_cond<'a>(&'a self) -> Option<SemCondGuard<'a>> {
+        if self.try_acquire() {Some(self.cond_guard())} else {None}
+    }
+
+    pub fn access_cond_timeout<'a>(&'a self, ms: u64)
+                                   -> Option<SemCondGuard<'a>> {
+        if self.acquire_timeout(ms) {Some(self.cond_guard())} else {None}
+    }
+
+    // Must only be called once the semaphore has been acquired
+    fn cond_guard<'a>(&'a self) -> SemCondGuard<'a> {
         SemCondGuard {
-            guard: self.access(),
+            guard: SemGuard { sem: self },
             cvar: Condvar { sem: self, order: Nothing, nocopy: marker::NoCopy },
         }
     }
@@ -216,6 +355,10 @@ pub struct Condvar<'a> {
 impl<'a> Condvar<'a> {
     /// Atomically drop the associated lock, and block until a signal is sent.
     ///
+    /// A signal only ever wakes up a task which was already waiting when the
+    /// signal was sent, and waiting tasks are woken up in the order in which
+    /// they started waiting.
+    ///
     /// # Failure
     ///
     /// A task which is killed while waiting on a condition variable will wake
@@ -235,6 +378,9 @@ impl<'a> Condvar<'a> {
         let mut wait_end = None;
         let mut out_of_bounds = None;
         // Release lock, 'atomically' enqueuing ourselves in so doing.
+        if self.sem.tracked() {
+            deadlock::released(self.sem as *const _ as uint)
+        }
         unsafe {
             self.sem.with(|state| {
                 if condvar_id < state.blocked.len() {
@@ -260,19 +406,86 @@ impl<'a> Condvar<'a> {
             // with acquire().)
             (|| {
                 let _ = wait_end.take_unwrap().recv();
-            }).finally(|| {
-                // Reacquire the condvar.
-                match self.order {
-                    Just(lock) => {
-                        let _g = lock.access();
-                        self.sem.acquire();
+            }).finally(|| self.reacquire())
+        })
+    }
+
+    /// Atomically drop the associated lock, and block until a signal is sent
+    /// or `ms` milliseconds have passed. The lock is reacquired either way.
+    /// Returns whether a signal was received.
+    ///
+    /// No signal is lost to a waiter which times out: a signal which is sent
+    /// while a waiter is timing out either wakes up that waiter (and this
+    /// returns `true`), or it is passed on to the next waiting task, exactly
+    /// as if the waiter had stopped waiting before the signal was sent.
+    ///
+    /// # Failure
+    ///
+    /// As `wait`, and this will also fail if the current task's runtime
+    /// doesn't provide timers.
+    pub fn wait_timeout(&self, ms: u64) -> bool {
+        self.wait_timeout_on(0, ms)
+    }
+
+    /// As wait_timeout(), but can specify which of multiple condition
+    /// variables to wait on, as wait_on().
+    pub fn wait_timeout_on(&self, condvar_id: uint, ms: u64) -> bool {
+        let mut wait_end = None;
+        let mut out_of_bounds = None;
+        // Release lock, 'atomically' enqueuing ourselves in so doing, exactly
+        // as wait_on() does.
+        if self.sem.tracked() {
+            deadlock::released(self.sem as *const _ as uint)
+        }
+        unsafe {
+            self.sem.with(|state| {
+                if condvar_id < state.blocked.len() {
+                    state.count += 1;
+                    if state.count <= 0 {
+                        state.waiters.signal();
                     }
-                    Nothing => self.sem.acquire(),
+                    wait_end = Some(state.blocked[condvar_id].wait_end());
+                } else {
+                    out_of_bounds = Some(state.blocked.len());
                 }
             })
+        }
+
+        check_cvar_bounds(out_of_bounds, condvar_id, "cond.wait_timeout_on()", || {
+            let mut signalled = false;
+            (|| {
+                let end = wait_end.take_unwrap();
+                if wait_timeout(&end, ms) {
+                    let _ = end.recv();
+                    signalled = true;
+                } else {
+                    // Signals are only sent with the lock held, so a signal
+                    // which raced with the timeout can be detected with the
+                    // lock held. Our wait end is closed before the lock is
+                    // released, so any later signal skips over us.
+                    let mut end = Some(end);
+                    unsafe {
+                        self.sem.with(|_| {
+                            signalled = end.take_unwrap().try_recv().is_ok();
+                        })
+                    }
+                }
+            }).finally(|| self.reacquire());
+            signalled
         })
     }
 
+    // Reacquire the condvar's lock after waiting.
+    fn reacquire(&self) {
+        match self.order {
+            Just(lock) => {
+                let _g = lock.access();
+                self.sem.acquire();
+            }
+            Nothing => self.sem.acquire(),
+        }
+    }
+
     /// Wake up a blocked task. Returns false if there was no blocked task.
     pub fn signal(&self) -> bool { self.signal_on(0) }
 
@@ -382,11 +595,50 @@ impl Semaphore {
     /// contending task, if any exist. Won't block the caller.
     pub fn release(&self) { self.sem.release() }
 
+    /// Acquire a resource represented by the semaphore if one is available
+    /// right away, returning whether it was acquired. Never blocks.
+    pub fn try_acquire(&self) -> bool { self.sem.try_acquire() }
+
+    /// Acquire a resource represented by the semaphore, blocking for at most
+    /// `ms` milliseconds until one becomes available. Returns whether the
+    /// resource was acquired.
+    ///
+    /// Waiting tasks don't occupy a thread each, the timeout is implemented
+    /// with a timer from the local I/O services.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if it has to wait and the current task's
+    /// runtime doesn't provide timers.
+    pub fn acquire_timeout(&self, ms: u64) -> bool {
+        self.sem.acquire_timeout(ms)
+    }
+
     /// Acquire a resource of this semaphore, returning an RAII guard which will
     /// release the resource when dropped.
     pub fn access<'a>(&'a self) -> SemaphoreGuard<'a> {
         SemaphoreGuard { _guard: self.sem.access() }
     }
+
+    /// As `try_acquire`, but returns an RAII guard for the resource if it was
+    /// acquired.
+    pub fn try_access<'a>(&'a self) -> Option<SemaphoreGuard<'a>> {
+        if self.try_acquire() {
+            Some(SemaphoreGuard { _guard: SemGuard { sem: &self.sem } })
+        } else {
+            None
+        }
+    }
+
+    /// As `acquire_timeout`, but returns an RAII guard for the resource if it
+    /// was acquired.
+    pub fn access_timeout<'a>(&'a self, ms: u64) -> Option<SemaphoreGuard<'a>> {
+        if self.acquire_timeout(ms) {
+            Some(SemaphoreGuard { _guard: SemGuard { sem: &self.sem } })
+        } else {
+            None
+        }
+    }
 }
 
 /****************************************************************************
@@ -423,7 +675,9 @@ impl Mutex {
     /// between 0 and num_condvars-1. (If num_condvars is 0, lock_cond will be
     /// allowed but any operations on the condvar will fail.)
     pub fn new_with_condvars(num_condvars: uint) -> Mutex {
-        Mutex { sem: Sem::new_and_signal(1, num_condvars) }
+        let mut sem = Sem::new_and_signal(1, num_condvars);
+        sem.tracked = true;
+        Mutex { sem: sem }
     }
 
     /// Acquires ownership of this mutex, returning an RAII guard which will
@@ -433,6 +687,28 @@ impl Mutex {
         let SemCondGuard { guard, cvar } = self.sem.access_cond();
         MutexGuard { _guard: guard, cond: cvar }
     }
+
+    /// Attempts to acquire ownership of this mutex without blocking, returning
+    /// an RAII guard as `lock` does if the mutex was unlocked.
+    pub fn try_lock<'a>(&'a self) -> Option<MutexGuard<'a>> {
+        self.sem.try_access_cond().map(|SemCondGuard { guard, cvar }| {
+            MutexGuard { _guard: guard, cond: cvar }
+        })
+    }
+
+    /// Acquires ownership of this mutex, blocking for at most `ms`
+    /// milliseconds. Returns an RAII guard as `lock` does if the mutex was
+    /// acquired in time.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if it has to wait and the current task's
+    /// runtime doesn't provide timers.
+    pub fn lock_timeout<'a>(&'a self, ms: u64) -> Option<MutexGuard<'a>> {
+        self.sem.access_cond_timeout(ms).map(|SemCondGuard { guard, cvar }| {
+            MutexGuard { _guard: guard, cond: cvar }
+        })
+    }
 }
 
 /****************************************************************************
@@ -443,6 +719,9 @@ impl Mutex {
 
 /// A blocking, no-starvation, reader-writer lock with an associated condvar.
 ///
+/// By default, no task can be starved by this lock. A lock created with the
+/// `PreferWriters` preference lets writers overtake waiting readers instead.
+///
 /// # Failure
 ///
 /// A task which fails while holding an rwlock will unlock the rwlock as it
@@ -450,6 +729,16 @@ impl Mutex {
 pub struct RWLock {
     order_lock:  Semaphore,
     access_lock: Sem<Vec<WaitQueue>>,
+    preference: RWLockPreference,
+
+    // With `PreferWriters`, this is the number of writers which are waiting
+    // for or holding the lock. Writers hold the order lock as a group: the
+    // first one to arrive acquires it on behalf of all of them and the last
+    // one to leave releases it, so no reader can get in as long as there are
+    // writers around. The count is only modified with `writer_lock` held, so
+    // that a writer can't get in before the group has the order lock.
+    writer_lock: Semaphore,
+    writers: atomics::AtomicUint,
 
     // The only way the count flag is ever accessed is with xadd. Since it is
     // a read-modify-write operation, multiple xadds on different cores will
@@ -461,6 +750,18 @@ pub struct RWLock {
     read_count: atomics::AtomicUint,
 }
 
+/// How an `RWLock` arbitrates between the readers and the writers which are
+/// waiting for it.
+pub enum RWLockPreference {
+    /// Tasks acquire the lock in the order in which they asked for it, so
+    /// neither readers nor writers can be starved. This is the default.
+    NoPreference,
+    /// Writers overtake any readers which are waiting. This keeps writes
+    /// prompt on data which is mostly read, but a steady stream of writers
+    /// will starve readers.
+    PreferWriters,
+}
+
 /// An RAII helper which is created by acquiring a read lock on an RWLock. When
 /// dropped, this will unlock the RWLock.
 #[must_use]
@@ -487,9 +788,19 @@ impl RWLock {
     /// Create a new rwlock, with a specified number of associated condvars.
     /// Similar to mutex_with_condvars.
     pub fn new_with_condvars(num_condvars: uint) -> RWLock {
+        RWLock::new_with_preference(num_condvars, NoPreference)
+    }
+
+    /// Create a new rwlock with a specified number of associated condvars,
+    /// which arbitrates between readers and writers as specified.
+    pub fn new_with_preference(num_condvars: uint,
+                               preference: RWLockPreference) -> RWLock {
         RWLock {
             order_lock: Semaphore::new(1),
             access_lock: Sem::new_and_signal(1, num_condvars),
+            preference: preference,
+            writer_lock: Semaphore::new(1),
+            writers: atomics::AtomicUint::new(0),
             read_count: atomics::AtomicUint::new(0),
         }
     }
@@ -527,6 +838,11 @@ impl RWLock {
     /// drop(read);
     /// ```
     pub fn write<'a>(&'a self) -> RWLockWriteGuard<'a> {
+        match self.preference {
+            PreferWriters => return self.write_preferred(),
+            NoPreference => {}
+        }
+
         let _g = self.order_lock.access();
         self.access_lock.acquire();
 
@@ -565,6 +881,43 @@ impl RWLock {
             }
         }
     }
+
+    fn write_preferred<'a>(&'a self) -> RWLockWriteGuard<'a> {
+        {
+            let _g = self.writer_lock.access();
+            if self.writers.fetch_add(1, atomics::SeqCst) == 0 {
+                self.order_lock.acquire();
+            }
+        }
+        self.access_lock.acquire();
+
+        // The order lock is held by the writers for as long as this writer is
+        // around (even while it waits on the condvar), so no reader can be
+        // waiting on the access lock when it's reacquired. The race described
+        // above therefore can't happen, and the condvar must not touch the
+        // order lock.
+        RWLockWriteGuard {
+            lock: self,
+            cond: Condvar {
+                sem: &self.access_lock,
+                order: Nothing,
+                nocopy: marker::NoCopy,
+            }
+        }
+    }
+
+    // Leaves the group of writers holding the order lock, see `writers`
+    fn leave_writers(&self) {
+        match self.preference {
+            PreferWriters => {
+                let _g = self.writer_lock.access();
+                if self.writers.fetch_sub(1, atomics::SeqCst) == 1 {
+                    self.order_lock.release();
+                }
+            }
+            NoPreference => {}
+        }
+    }
 }
 
 impl<'a> RWLockWriteGuard<'a> {
@@ -586,6 +939,8 @@ impl<'a> RWLockWriteGuard<'a> {
             // the comment in write_cond for more justification.
             lock.access_lock.release();
         }
+        // Let readers waiting behind this writer join in
+        lock.leave_writers();
         RWLockReadGuard { lock: lock }
     }
 }
@@ -594,6 +949,7 @@ impl<'a> RWLockWriteGuard<'a> {
 impl<'a> Drop for RWLockWriteGuard<'a> {
     fn drop(&mut self) {
         self.lock.access_lock.release();
+        self.lock.leave_writers();
     }
 }
 
@@ -621,7 +977,8 @@ mod tests {
     use std::prelude::*;
 
     use Arc;
-    use super::{Semaphore, Mutex, RWLock, Condvar};
+    use super::{Semaphore, Mutex, RWLock, Condvar, Sem};
+    use super::{NoPreference, PreferWriters};
 
     use std::mem;
     use std::result;
@@ -720,6 +1077,55 @@ mod tests {
         }
         rx.recv(); // wait for child to be done
     }
+    #[test]
+    fn test_sem_try_acquire() {
+        let s = Semaphore::new(1);
+        assert!(s.try_acquire());
+        assert!(!s.try_acquire());
+        assert!(s.try_access().is_none());
+        s.release();
+        {
+            let _g = s.try_access().unwrap();
+            assert!(!s.try_acquire());
+        }
+        assert!(s.try_acquire());
+    }
+    #[test]
+    fn test_sem_acquire_timeout() {
+        let s = Semaphore::new(0);
+        assert!(!s.acquire_timeout(10));
+        assert!(s.access_timeout(0).is_none());
+        // the timed out waiters gave up their places in line
+        s.release();
+        assert!(s.acquire_timeout(10));
+        assert!(!s.try_acquire());
+    }
+    #[test]
+    fn test_sem_acquire_timeout_released() {
+        let s = Arc::new(Semaphore::new(0));
+        let s2 = s.clone();
+        let (tx, rx) = channel();
+        task::spawn(proc() {
+            rx.recv();
+            s2.release();
+        });
+        tx.send(());
+        assert!(s.acquire_timeout(60 * 1000));
+    }
+    #[test]
+    fn test_sem_acquire_timeout_races_release() {
+        // Whether or not each timed acquire wins the race with the release,
+        // no resource may be lost or duplicated.
+        let s = Arc::new(Semaphore::new(0));
+        for _ in range(0u, 50) {
+            let s2 = s.clone();
+            task::spawn(proc() { s2.release(); });
+            if !s.acquire_timeout(1) {
+                s.acquire();
+            }
+            assert!(!s.try_acquire());
+        }
+    }
     /************************************************************************
      * Mutex tests
      ************************************************************************/
@@ -755,6 +1161,36 @@ mod tests {
         }
     }
     #[test]
+    fn test_mutex_try_lock() {
+        let m = Mutex::new();
+        {
+            let lock = m.try_lock().unwrap();
+            assert!(m.try_lock().is_none());
+            assert!(m.lock_timeout(10).is_none());
+            // the guard comes with a working condvar
+            assert!(!lock.cond.signal());
+        }
+        assert!(m.try_lock().is_some());
+    }
+    #[test]
+    fn test_mutex_lock_timeout() {
+        let m = Arc::new(Mutex::new());
+        let m2 = m.clone();
+        let (tx, rx) = channel();
+        let lock = m.lock();
+        let child = task::try_future(proc() {
+            assert!(m2.lock_timeout(10).is_none());
+            tx.send(());
+            let lock = m2.lock_timeout(60 * 1000).unwrap();
+            lock.cond.signal();
+        });
+        rx.recv();
+        // the child eventually gets the lock once it's released
+        lock.cond.wait();
+        drop(lock);
+        assert!(child.unwrap().is_ok());
+    }
+    #[test]
     fn test_mutex_cond_wait() {
         let m = Arc::new(Mutex::new());
 
@@ -832,6 +1268,81 @@ mod tests {
         assert!(!lock.cond.signal());
     }
     #[test]
+    fn test_mutex_cond_wait_timeout() {
+        let m = Arc::new(Mutex::new());
+        {
+            let lock = m.lock();
+            assert!(!lock.cond.wait_timeout(10));
+            // the waiter which timed out is gone
+            assert!(!lock.cond.signal());
+        }
+
+        let lock = m.lock();
+        let m2 = m.clone();
+        task::spawn(proc() {
+            let lock = m2.lock();
+            assert!(lock.cond.signal());
+        });
+        assert!(lock.cond.wait_timeout(60 * 1000));
+    }
+    #[test]
+    fn test_mutex_cond_wait_timeout_races_signal() {
+        // A signal racing with the timeout is either received by the waiter or
+        // reported as not having woken anyone up.
+        let m = Arc::new(Mutex::new());
+        for _ in range(0u, 50) {
+            let m2 = m.clone();
+            let (tx, rx) = channel();
+            task::spawn(proc() {
+                let lock = m2.lock();
+                tx.send(None);
+                tx.send(Some(lock.cond.wait_timeout(1)));
+            });
+            assert!(rx.recv().is_none());
+            let woken = {
+                let lock = m.lock();
+                lock.cond.signal()
+            };
+            assert_eq!(rx.recv(), Some(woken));
+        }
+    }
+    #[test]
+    fn test_mutex_cond_wait_timeout_passes_on_signal() {
+        // A signal is never lost to a waiter which times out, another waiter
+        // gets it instead.
+        let m = Arc::new(Mutex::new());
+        for _ in range(0u, 20) {
+            let (atx, arx) = channel();
+            let m2 = m.clone();
+            task::spawn(proc() {
+                let lock = m2.lock();
+                atx.send(None);
+                let woken = lock.cond.wait_timeout(1);
+                drop(lock);
+                atx.send(Some(woken));
+            });
+            assert!(arx.recv().is_none());
+            let (btx, brx) = channel();
+            let m2 = m.clone();
+            task::spawn(proc() {
+                let lock = m2.lock();
+                btx.send(None);
+                lock.cond.wait();
+                drop(lock);
+                btx.send(Some(()));
+            });
+            assert!(brx.recv().is_none());
+
+            assert!(m.lock().cond.signal());
+            if arx.recv().unwrap() {
+                // the timed waiter got the signal, so wake up the other one
+                assert!(m.lock().cond.signal());
+            }
+            // otherwise the other waiter must have gotten the signal
+            assert!(brx.recv().is_some());
+        }
+    }
+    #[test]
     fn test_mutex_killed_simple() {
         use std::any::Any;
 
@@ -943,6 +1454,70 @@ mod tests {
         test_rwlock_exclusion(Arc::new(RWLock::new()), Downgrade, Write);
         test_rwlock_exclusion(Arc::new(RWLock::new()), Downgrade, Downgrade);
     }
+    #[test]
+    fn test_rwlock_prefer_writers_exclusion() {
+        let modes = [(Read, Write), (Write, Read), (Write, Write),
+                     (Read, Downgrade), (DowngradeRead, Write),
+                     (Downgrade, Downgrade)];
+        for &(mode1, mode2) in modes.iter() {
+            let x = Arc::new(RWLock::new_with_preference(1, PreferWriters));
+            test_rwlock_exclusion(x, mode1, mode2);
+        }
+    }
+    #[cfg(test)]
+    fn sem_count<Q: Send>(sem: &Sem<Q>) -> int {
+        let mut count = 0;
+        unsafe { sem.with(|state| count = state.count) }
+        count
+    }
+    #[test]
+    fn test_rwlock_prefer_writers() {
+        // A reader starts waiting on a locked rwlock before a writer does, and
+        // only gets in first if writers aren't preferred.
+        let cases = [(NoPreference, "read"), (PreferWriters, "write")];
+        for &(preference, first) in cases.iter() {
+            let x = Arc::new(RWLock::new_with_preference(1, preference));
+            let (tx, rx) = channel();
+            let write = x.write();
+
+            // Every blocked task takes one of the semaphores' counts down by
+            // one, wherever it ends up waiting.
+            let blocked = || {
+                -(sem_count(&x.order_lock.sem) + sem_count(&x.access_lock))
+            };
+            let (x2, tx2) = (x.clone(), tx.clone());
+            task::spawn(proc() {
+                let _g = x2.read();
+                tx2.send("read");
+            });
+            while blocked() < 1 { task::deschedule(); }
+            let (x2, tx2) = (x.clone(), tx.clone());
+            task::spawn(proc() {
+                let _g = x2.write();
+                tx2.send("write");
+            });
+            while blocked() < 2 { task::deschedule(); }
+
+            drop(write);
+            assert_eq!(rx.recv(), first);
+            rx.recv();
+        }
+    }
+    #[test]
+    fn test_rwlock_prefer_writers_cond() {
+        let x = Arc::new(RWLock::new_with_preference(1, PreferWriters));
+        let lock = x.write();
+        let x2 = x.clone();
+        task::spawn(proc() {
+            let lock = x2.write();
+            assert!(lock.cond.signal());
+            // readers can get in once the writers are done
+            drop(lock.downgrade());
+        });
+        lock.cond.wait();
+        drop(lock);
+        drop(x.read());
+    }
     #[cfg(test)]
     fn test_rwlock_handshake(x: Arc<RWLock>,
                              mode1: RWLockMode,
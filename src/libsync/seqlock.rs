@@ -0,0 +1,137 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A sequence lock, for small values which are read far more often than they
+//! are written.
+//!
+//! Writers take a lock and bump a sequence number before and after writing,
+//! so that it's odd while a write is in progress. Readers never take the lock:
+//! they copy the value out, and start over if the sequence number was odd or
+//! changed while they were copying. Readers never hold up writers, or each
+//! other, but a reader may have to retry as long as writes keep coming in.
+
+use core::prelude::*;
+
+use core::cell::UnsafeCell;
+use core::ptr;
+
+use atomics::{AtomicUint, fence, Acquire, Release, Relaxed};
+use backoff::{Backoff, SpinYield};
+use mutex;
+
+/// A sequence lock protecting a value of type `T`.
+///
+/// Reads copy the value out, which is why `T` has to be `Copy`.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::{Arc, SeqLock};
+///
+/// let time = Arc::new(SeqLock::new((0u, 0u)));
+/// let time2 = time.clone();
+/// spawn(proc() {
+///     for i in range(1u, 100) {
+///         time2.write((i, i * 60));
+///     }
+/// });
+/// let (minutes, seconds) = time.read();
+/// assert_eq!(minutes * 60, seconds);
+/// ```
+pub struct SeqLock<T> {
+    seq: AtomicUint,
+    lock: mutex::Mutex,
+    data: UnsafeCell<T>,
+}
+
+impl<T: Copy + Send> SeqLock<T> {
+    /// Creates a new sequence lock holding `t`.
+    pub fn new(t: T) -> SeqLock<T> {
+        SeqLock {
+            seq: AtomicUint::new(0),
+            lock: mutex::Mutex::new(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    /// Returns a copy of the value, retrying for as long as it is being
+    /// written to.
+    pub fn read(&self) -> T {
+        let mut step = 0;
+        loop {
+            match self.try_read() {
+                Some(t) => return t,
+                None => { SpinYield.snooze(step); step += 1; }
+            }
+        }
+    }
+
+    /// Attempts to copy the value once, returning `None` if it was written to
+    /// in the meantime.
+    pub fn try_read(&self) -> Option<T> {
+        let seq = self.seq.load(Acquire);
+        if seq & 1 != 0 { return None }
+        // The copy may be torn if a write is happening, in which case the
+        // sequence number will have changed and it's thrown away
+        let t = unsafe { ptr::read(self.data.get() as *const T) };
+        fence(Acquire);
+        if self.seq.load(Relaxed) == seq { Some(t) } else { None }
+    }
+
+    /// Replaces the value with `t`, waiting for other writers to finish first.
+    pub fn write(&self, t: T) {
+        let _g = self.lock.lock();
+        let seq = self.seq.load(Relaxed);
+        self.seq.store(seq + 1, Relaxed);
+        fence(Release);
+        unsafe { *self.data.get() = t; }
+        self.seq.store(seq + 2, Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use alloc::arc::Arc;
+    use native;
+    use super::SeqLock;
+
+    #[test]
+    fn smoke() {
+        let l = SeqLock::new(1i);
+        assert_eq!(l.read(), 1);
+        l.write(2);
+        assert_eq!(l.try_read(), Some(2));
+    }
+
+    #[test]
+    fn never_torn() {
+        static N: uint = 10000;
+        let l = Arc::new(SeqLock::new((0u, 0u, 0u)));
+        let (tx, rx) = channel();
+        for _ in range(0u, 2) {
+            let (l, tx) = (l.clone(), tx.clone());
+            native::task::spawn(proc() {
+                for i in range(0, N) {
+                    l.write((i, i, i));
+                }
+                tx.send(());
+            });
+        }
+        for _ in range(0, N) {
+            let (a, b, c) = l.read();
+            assert!(a == b && b == c);
+        }
+        drop(tx);
+        for _ in range(0u, 2) { rx.recv(); }
+        assert_eq!(l.read(), (N - 1, N - 1, N - 1));
+    }
+}
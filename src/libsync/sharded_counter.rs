@@ -0,0 +1,191 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A counter which many tasks can increment without contending.
+
+use core::prelude::*;
+
+use collections::Vec;
+use rustrt::mutex::{StaticNativeMutex, NATIVE_MUTEX_INIT};
+use tls = rustrt::thread_local_storage;
+
+use atomics;
+use atomics::{AtomicUint, Relaxed};
+
+// The number of cells used by `ShardedCounter::new`
+static DEFAULT_SHARDS: uint = 16;
+
+// Each cell is on a cache line of its own, so that incrementing one doesn't
+// take the others' cache lines away from the tasks using them
+struct Cell {
+    count: AtomicUint,
+    _pad: [u8, ..64],
+}
+
+/// A counter split into a number of cells, which tasks update independently.
+///
+/// Incrementing a single atomic integer from many tasks at once makes its
+/// cache line bounce between processors. A `ShardedCounter` instead has each
+/// thread increment one of several cells, and only adds the cells up when it's
+/// read. A green scheduler runs all of its tasks on a single thread, so its
+/// tasks share a cell, while each native task has a thread of its own. Threads
+/// are numbered as they first use a counter, and spread over the cells in that
+/// order. Reads are more expensive, so this suits statistics which are updated
+/// all the time and seldom looked at.
+///
+/// Reads aren't atomic with respect to concurrent updates: a read sees every
+/// update which happened before it started, and some of the ones which
+/// happened while it was adding the cells up.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::{Arc, ShardedCounter};
+///
+/// let sends = Arc::new(ShardedCounter::new());
+/// let (tx, rx) = channel();
+/// for _ in range(0u, 4) {
+///     let (sends, tx) = (sends.clone(), tx.clone());
+///     spawn(proc() {
+///         for i in range(0u, 10) {
+///             tx.send(i);
+///             sends.incr();
+///         }
+///     });
+/// }
+/// for _ in range(0u, 40) { rx.recv(); }
+/// assert_eq!(sends.get(), 40);
+/// ```
+pub struct ShardedCounter {
+    cells: Vec<Cell>,
+}
+
+impl ShardedCounter {
+    /// Creates a counter at zero, with the default number of cells.
+    pub fn new() -> ShardedCounter {
+        ShardedCounter::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates a counter at zero, with `shards` cells. More cells means less
+    /// contention among tasks, at the cost of memory and of slower reads.
+    ///
+    /// # Failure
+    ///
+    /// This function will fail if `shards` is 0.
+    pub fn with_shards(shards: uint) -> ShardedCounter {
+        assert!(shards > 0);
+        ShardedCounter {
+            cells: Vec::from_fn(shards, |_| {
+                Cell { count: AtomicUint::new(0), _pad: [0, ..64] }
+            }),
+        }
+    }
+
+    /// Adds one to the counter.
+    #[inline]
+    pub fn incr(&self) {
+        self.add(1)
+    }
+
+    /// Adds `n` to the counter.
+    pub fn add(&self, n: uint) {
+        self.cell().count.fetch_add(n, Relaxed);
+    }
+
+    /// Returns the sum of the cells.
+    pub fn get(&self) -> uint {
+        self.cells.iter().fold(0, |sum, c| sum + c.count.load(Relaxed))
+    }
+
+    /// Resets the counter to zero, returning the sum of what the cells held.
+    pub fn take(&self) -> uint {
+        self.cells.iter().fold(0, |sum, c| sum + c.count.swap(0, Relaxed))
+    }
+
+    fn cell<'a>(&'a self) -> &'a Cell {
+        self.cells.get(unsafe { thread_number() } % self.cells.len())
+    }
+}
+
+static mut LOCK: StaticNativeMutex = NATIVE_MUTEX_INIT;
+static mut KEY: tls::Key = 0;
+static mut KEY_CREATED: atomics::AtomicBool = atomics::INIT_ATOMIC_BOOL;
+static mut NEXT_THREAD: atomics::AtomicUint = atomics::INIT_ATOMIC_UINT;
+
+// The number of the current thread, handed out the first time it asks. It is
+// kept in the thread's value of the key, off by one as a null value means
+// none was handed out yet, so there is nothing to free when the thread exits.
+unsafe fn thread_number() -> uint {
+    if !KEY_CREATED.load(atomics::Acquire) {
+        let _g = LOCK.lock();
+        if !KEY_CREATED.load(atomics::Relaxed) {
+            tls::create(&mut KEY);
+            KEY_CREATED.store(true, atomics::Release);
+        }
+    }
+    match tls::get(KEY) as uint {
+        0 => {
+            let n = NEXT_THREAD.fetch_add(1, Relaxed);
+            tls::set(KEY, (n + 1) as *mut u8);
+            n
+        }
+        n => n - 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::prelude::*;
+
+    use alloc::arc::Arc;
+    use native;
+    use super::{ShardedCounter, thread_number};
+
+    #[test]
+    fn smoke() {
+        let c = ShardedCounter::with_shards(4);
+        assert_eq!(c.get(), 0);
+        c.incr();
+        c.add(4);
+        assert_eq!(c.get(), 5);
+        assert_eq!(c.take(), 5);
+        assert_eq!(c.get(), 0);
+    }
+
+    #[test]
+    fn many_tasks() {
+        let c = Arc::new(ShardedCounter::new());
+        let (tx, rx) = channel();
+        for i in range(0u, 8) {
+            let (c, tx) = (c.clone(), tx.clone());
+            let f = proc() {
+                for _ in range(0u, 1000) { c.incr(); }
+                tx.send(());
+            };
+            if i % 2 == 0 { native::task::spawn(f) } else { spawn(f) }
+        }
+        for _ in range(0u, 8) { rx.recv(); }
+        assert_eq!(c.get(), 8000);
+    }
+
+    #[test]
+    fn cells_per_thread() {
+        let me = unsafe { thread_number() };
+        assert_eq!(unsafe { thread_number() }, me);
+        let (tx, rx) = channel();
+        native::task::spawn(proc() tx.send(unsafe { thread_number() }));
+        assert!(rx.recv() != me);
+    }
+
+    #[test] #[should_fail]
+    fn zero_shards() {
+        ShardedCounter::with_shards(0);
+    }
+}
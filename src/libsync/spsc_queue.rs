@@ -37,11 +37,13 @@
 
 use core::prelude::*;
 
-use alloc::boxed::Box;
 use core::mem;
+use core::num;
+use core::ptr;
 use core::cell::UnsafeCell;
 
 use atomics::{AtomicPtr, Relaxed, AtomicUint, Acquire, Release};
+use node_pool;
 
 // Node within the linked list queue of messages to send
 struct Node<T> {
@@ -60,28 +62,70 @@ pub struct Queue<T> {
     // consumer fields
     tail: UnsafeCell<*mut Node<T>>, // where to pop from
     tail_prev: AtomicPtr<Node<T>>, // where to pop from
+    cache_additions: AtomicUint, // nodes returned to the cache
 
     // producer fields
     head: UnsafeCell<*mut Node<T>>,      // where to push to
     first: UnsafeCell<*mut Node<T>>,     // where to get new nodes from
     tail_copy: UnsafeCell<*mut Node<T>>, // between first/tail
+    cache_subtractions: AtomicUint,      // nodes taken from the cache
 
     // Cache maintenance fields. Additions and subtractions are stored
-    // separately in order to allow them to use nonatomic addition/subtraction.
-    cache_bound: uint,
-    cache_additions: AtomicUint,
-    cache_subtractions: AtomicUint,
+    // separately, each next to the fields of the side which writes it, in
+    // order to allow them to use nonatomic addition/subtraction. The bound is
+    // only ever modified by the consumer (when adaptive), and it is never
+    // modified to or from 0, so the producer only needs `bounded`.
+    bounded: bool,
+    cache_bound: AtomicUint,
+
+    adaptive: Option<Adaptive>,
+}
+
+// State for resizing the node cache based on the depth of the queue. The
+// producer counts its pushes, and the consumer tracks the deepest the queue
+// has been over the last `ADAPT_PERIOD` pops. At the end of each period the
+// cache bound is set to cover that depth, within the configured limits.
+struct Adaptive {
+    min: uint,
+    max: uint,
+    pushes: AtomicUint,       // written by the producer
+    pops: UnsafeCell<uint>,   // remaining fields are owned by the consumer
+    peak: UnsafeCell<uint>,
+    period: UnsafeCell<uint>,
+}
+
+#[cfg(test)]
+static ADAPT_PERIOD: uint = 16;
+#[cfg(not(test))]
+static ADAPT_PERIOD: uint = 1024;
+
+/// A node which has been allocated for a push but not yet published to the
+/// consumer. See `Queue::reserve`.
+pub struct Slot<T> {
+    node: *mut Node<T>,
 }
 
 impl<T: Send> Node<T> {
+    // Nodes are allocated from (and returned to) the pool shared by all
+    // queues, so that a queue which is destroyed doesn't waste its cache.
     fn new() -> *mut Node<T> {
         unsafe {
-            mem::transmute(box Node {
+            let n = node_pool::allocate(mem::size_of::<Node<T>>(),
+                                        mem::min_align_of::<Node<T>>());
+            let n = n as *mut Node<T>;
+            ptr::write(n, Node {
                 value: None,
                 next: AtomicPtr::new(0 as *mut Node<T>),
-            })
+            });
+            n
         }
     }
+
+    unsafe fn free(n: *mut Node<T>) {
+        drop(ptr::read(n as *const Node<T>));
+        node_pool::deallocate(n as *mut u8, mem::size_of::<Node<T>>(),
+                              mem::min_align_of::<Node<T>>());
+    }
 }
 
 impl<T: Send> Queue<T> {
@@ -99,21 +143,55 @@ impl<T: Send> Queue<T> {
     ///               no bound. Otherwise, the cache will never grow larger than
     ///               `bound` (although the queue itself could be much larger.
     pub fn new(bound: uint) -> Queue<T> {
+        Queue::with_cache(bound, None)
+    }
+
+    /// Creates a new queue whose node cache resizes itself to fit the
+    /// observed depth of the queue.
+    ///
+    /// The cache starts out holding at most `min` nodes. It grows if the
+    /// queue regularly holds more elements than that, up to `max` nodes, and
+    /// shrinks back down once the queue stays shallow. This is useful for
+    /// channels which are idle most of the time but see occasional bursts of
+    /// traffic.
+    ///
+    /// # Failure
+    ///
+    /// Fails if `min` is 0 or is greater than `max`.
+    pub fn adaptive(min: uint, max: uint) -> Queue<T> {
+        assert!(min > 0 && min <= max, "invalid adaptive cache limits");
+        Queue::with_cache(min, Some(Adaptive {
+            min: min,
+            max: max,
+            pushes: AtomicUint::new(0),
+            pops: UnsafeCell::new(0),
+            peak: UnsafeCell::new(0),
+            period: UnsafeCell::new(0),
+        }))
+    }
+
+    fn with_cache(bound: uint, adaptive: Option<Adaptive>) -> Queue<T> {
         let n1 = Node::new();
         let n2 = Node::new();
         unsafe { (*n1).next.store(n2, Relaxed) }
         Queue {
             tail: UnsafeCell::new(n2),
             tail_prev: AtomicPtr::new(n1),
+            cache_additions: AtomicUint::new(0),
             head: UnsafeCell::new(n2),
             first: UnsafeCell::new(n1),
             tail_copy: UnsafeCell::new(n1),
-            cache_bound: bound,
-            cache_additions: AtomicUint::new(0),
             cache_subtractions: AtomicUint::new(0),
+            bounded: bound > 0,
+            cache_bound: AtomicUint::new(bound),
+            adaptive: adaptive,
         }
     }
 
+    /// Returns the current limit on the number of cached nodes, 0 meaning
+    /// unbounded. This only changes over time for adaptive queues.
+    pub fn cache_bound(&self) -> uint { self.cache_bound.load(Relaxed) }
+
     /// Pushes a new value onto this queue. Note that to use this function
     /// safely, it must be externally guaranteed that there is only one pusher.
     pub fn push(&self, t: T) {
@@ -124,6 +202,48 @@ impl<T: Send> Queue<T> {
             assert!((*n).value.is_none());
             (*n).value = Some(t);
             (*n).next.store(0 as *mut Node<T>, Relaxed);
+            self.count_push();
+            (**self.head.get()).next.store(n, Release);
+            *self.head.get() = n;
+        }
+    }
+
+    // The push count must be updated before the node is published so the
+    // consumer never sees more pops than pushes.
+    fn count_push(&self) {
+        match self.adaptive {
+            Some(ref a) => {
+                let n = a.pushes.load(Relaxed);
+                a.pushes.store(n + 1, Relaxed);
+            }
+            None => {}
+        }
+    }
+
+    /// Acquires a node for a future push without publishing it to the
+    /// consumer. The node's value can be filled in place through the returned
+    /// slot, and it is made visible to the consumer by `commit`.
+    ///
+    /// Like `push`, it must be externally guaranteed that there is only one
+    /// pusher. Values become visible in the order in which they are
+    /// committed, not the order in which they were reserved.
+    pub fn reserve(&self) -> Slot<T> {
+        unsafe {
+            let n = self.alloc();
+            assert!((*n).value.is_none());
+            (*n).next.store(0 as *mut Node<T>, Relaxed);
+            Slot { node: n }
+        }
+    }
+
+    /// Publishes a previously reserved slot. The slot's value must have been
+    /// filled in.
+    pub fn commit(&self, slot: Slot<T>) {
+        unsafe {
+            let n = slot.node;
+            mem::forget(slot);
+            assert!((*n).value.is_some());
+            self.count_push();
             (**self.head.get()).next.store(n, Release);
             *self.head.get() = n;
         }
@@ -135,7 +255,7 @@ impl<T: Send> Queue<T> {
         // the addition to cache_subtractions is not atomic (plus we're the
         // only one subtracting from the cache).
         if *self.first.get() != *self.tail_copy.get() {
-            if self.cache_bound > 0 {
+            if self.bounded {
                 let b = self.cache_subtractions.load(Relaxed);
                 self.cache_subtractions.store(b + 1, Relaxed);
             }
@@ -147,7 +267,7 @@ impl<T: Send> Queue<T> {
         // again.
         *self.tail_copy.get() = self.tail_prev.load(Acquire);
         if *self.first.get() != *self.tail_copy.get() {
-            if self.cache_bound > 0 {
+            if self.bounded {
                 let b = self.cache_subtractions.load(Relaxed);
                 self.cache_subtractions.store(b + 1, Relaxed);
             }
@@ -175,28 +295,53 @@ impl<T: Send> Queue<T> {
             let ret = (*next).value.take();
 
             *self.tail.get() = next;
-            if self.cache_bound == 0 {
+            if !self.bounded {
                 self.tail_prev.store(tail, Release);
             } else {
+                self.adapt();
+
                 // FIXME: this is dubious with overflow.
                 let additions = self.cache_additions.load(Relaxed);
                 let subtractions = self.cache_subtractions.load(Relaxed);
                 let size = additions - subtractions;
 
-                if size < self.cache_bound {
+                if size < self.cache_bound.load(Relaxed) {
                     self.tail_prev.store(tail, Release);
                     self.cache_additions.store(additions + 1, Relaxed);
                 } else {
                     (*self.tail_prev.load(Relaxed)).next.store(next, Relaxed);
                     // We have successfully erased all references to 'tail', so
                     // now we can safely drop it.
-                    let _: Box<Node<T>> = mem::transmute(tail);
+                    Node::free(tail);
                 }
             }
             return ret;
         }
     }
 
+    // Invoked by the consumer on each pop. If the cache shrinks, nodes beyond
+    // the new bound are freed as they're popped rather than all at once.
+    unsafe fn adapt(&self) {
+        let a = match self.adaptive {
+            Some(ref a) => a,
+            None => return,
+        };
+        // The popped value was pushed before we loaded it, so this is at
+        // least 1 (the depth including the element being popped).
+        let depth = a.pushes.load(Relaxed) - *a.pops.get();
+        *a.pops.get() += 1;
+        if depth > *a.peak.get() { *a.peak.get() = depth; }
+        *a.period.get() += 1;
+        if *a.period.get() < ADAPT_PERIOD { return }
+
+        let mut bound = num::next_power_of_two(*a.peak.get());
+        if bound < a.min { bound = a.min; }
+        if bound > a.max { bound = a.max; }
+        self.cache_bound.store(bound, Relaxed);
+        *a.peak.get() = 0;
+        *a.period.get() = 0;
+    }
+
     /// Attempts to peek at the head of the queue, returning `None` if the queue
     /// has no data currently
     pub fn peek<'a>(&'a self) -> Option<&'a mut T> {
@@ -211,6 +356,22 @@ impl<T: Send> Queue<T> {
     }
 }
 
+impl<T: Send> Slot<T> {
+    /// Returns the storage for the value of this reserved node.
+    pub fn value<'a>(&'a mut self) -> &'a mut Option<T> {
+        unsafe { &mut (*self.node).value }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Slot<T> {
+    fn drop(&mut self) {
+        // A slot which was never committed is not reachable from the queue
+        // (it's been removed from the cache), so it can just be freed.
+        unsafe { Node::free(self.node) }
+    }
+}
+
 #[unsafe_destructor]
 impl<T: Send> Drop for Queue<T> {
     fn drop(&mut self) {
@@ -218,7 +379,7 @@ impl<T: Send> Drop for Queue<T> {
             let mut cur = *self.first.get();
             while !cur.is_null() {
                 let next = (*cur).next.load(Relaxed);
-                let _n: Box<Node<T>> = mem::transmute(cur);
+                Node::free(cur);
                 cur = next;
             }
         }
@@ -271,10 +432,92 @@ mod test {
         assert_eq!(q.pop(), None);
     }
 
+    #[test]
+    fn reserve_commit() {
+        let q = Queue::new(1);
+        let mut a = q.reserve();
+        let mut b = q.reserve();
+        *b.value() = Some(2i);
+        *a.value() = Some(1);
+        q.push(0);
+        assert_eq!(q.pop(), Some(0));
+        assert_eq!(q.pop(), None);
+        q.commit(b);
+        q.commit(a);
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(q.pop(), None);
+
+        // uncommitted slots are simply discarded
+        let mut c = q.reserve();
+        *c.value() = Some(box 3i);
+        drop(c);
+        assert_eq!(q.pop(), None);
+    }
+
+    #[test]
+    fn churn() {
+        // nodes of dead queues are recycled through the pool for new ones
+        for i in range(0u, 100) {
+            let q = Queue::new(16);
+            for j in range(0u, 20) { q.push(box (i + j)); }
+            for j in range(0u, 10) { assert_eq!(q.pop(), Some(box (i + j))); }
+        }
+    }
+
+    #[test]
+    fn adaptive() {
+        let q = Queue::adaptive(1, 64);
+        assert_eq!(q.cache_bound(), 1);
+
+        // a burst of 40 grows the cache to cover it
+        for i in range(0i, 40) { q.push(i); }
+        for i in range(0i, 16) { assert_eq!(q.pop(), Some(i)); }
+        assert_eq!(q.cache_bound(), 64);
+        for i in range(16i, 40) { assert_eq!(q.pop(), Some(i)); }
+
+        // and lockstep traffic shrinks it back down
+        for i in range(0i, 100) {
+            q.push(i);
+            assert_eq!(q.pop(), Some(i));
+        }
+        assert_eq!(q.cache_bound(), 1);
+        assert_eq!(q.pop(), None);
+    }
+
+    #[test]
+    #[should_fail]
+    fn adaptive_bad_limits() {
+        let _q: Queue<int> = Queue::adaptive(8, 4);
+    }
+
     #[test]
     fn stress() {
         stress_bound(0);
         stress_bound(1);
+        stress_adaptive();
+
+        fn stress_adaptive() {
+            let a = Arc::new(Queue::adaptive(1, 1024));
+            let b = a.clone();
+            let (tx, rx) = channel();
+            native::task::spawn(proc() {
+                for _ in range(0u, 100000) {
+                    loop {
+                        match b.pop() {
+                            Some(1i) => break,
+                            Some(_) => fail!(),
+                            None => {}
+                        }
+                    }
+                }
+                tx.send(());
+            });
+            for _ in range(0i, 100000) {
+                a.push(1);
+            }
+            rx.recv();
+        }
 
         fn stress_bound(bound: uint) {
             let a = Arc::new(Queue::new(bound));
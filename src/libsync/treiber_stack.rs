@@ -0,0 +1,188 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A lock-free stack (Treiber's algorithm).
+//!
+//! Any number of tasks may push onto the stack at once, and any number may
+//! take everything off of it at once with `pop_all`. Popping a single value
+//! has to read the node on the top of the stack before unlinking it, and with
+//! no way of knowing when other poppers are done reading a node, a popped node
+//! could be freed while another popper still looks at it. So `pop` is unsafe,
+//! and only one task may be popping at a time.
+
+#![experimental]
+
+use core::prelude::*;
+
+use alloc::boxed::Box;
+use core::mem;
+
+use atomics::{AtomicPtr, Acquire, Release, Relaxed};
+
+struct Node<T> {
+    value: T,
+    next: *mut Node<T>,
+}
+
+/// A lock-free stack. This is not cloneable, but it may be shared among tasks
+/// (in an `Arc` for example).
+pub struct Stack<T> {
+    head: AtomicPtr<Node<T>>,
+}
+
+/// The values taken off of a stack by `pop_all`, from the most recently pushed
+/// one to the least.
+pub struct Items<T> {
+    head: *mut Node<T>,
+}
+
+impl<T: Send> Stack<T> {
+    /// Creates a new, empty, stack.
+    pub fn new() -> Stack<T> {
+        Stack { head: AtomicPtr::new(0 as *mut Node<T>) }
+    }
+
+    /// Pushes a value onto the stack.
+    pub fn push(&self, t: T) {
+        unsafe {
+            let n: *mut Node<T> = mem::transmute(box Node {
+                value: t,
+                next: 0 as *mut Node<T>,
+            });
+            let mut head = self.head.load(Relaxed);
+            loop {
+                (*n).next = head;
+                match self.head.compare_exchange_weak(head, n, Release, Relaxed) {
+                    Ok(..) => return,
+                    Err(cur) => head = cur,
+                }
+            }
+        }
+    }
+
+    /// Pops the most recently pushed value off of the stack.
+    ///
+    /// This is unsafe because no other task may call `pop` or `pop_all` on
+    /// this stack until it returns. Pushes may happen concurrently.
+    pub unsafe fn pop(&self) -> Option<T> {
+        let mut head = self.head.load(Acquire);
+        loop {
+            if head.is_null() { return None }
+            // Only we may unlink nodes, so `head` is still alive
+            let next = (*head).next;
+            match self.head.compare_exchange_weak(head, next, Acquire, Acquire) {
+                Ok(..) => {
+                    let node: Box<Node<T>> = mem::transmute(head);
+                    return Some(node.value)
+                }
+                Err(cur) => head = cur,
+            }
+        }
+    }
+
+    /// Takes every value off of the stack at once.
+    pub fn pop_all(&self) -> Items<T> {
+        Items { head: self.head.swap(0 as *mut Node<T>, Acquire) }
+    }
+
+    /// Returns whether the stack was empty when it was looked at. Other tasks
+    /// may push or pop at any time, so this is only a hint.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Relaxed).is_null()
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Stack<T> {
+    fn drop(&mut self) {
+        drop(self.pop_all());
+    }
+}
+
+impl<T: Send> Iterator<T> for Items<T> {
+    fn next(&mut self) -> Option<T> {
+        if self.head.is_null() { return None }
+        let node: Box<Node<T>> = unsafe { mem::transmute(self.head) };
+        self.head = node.next;
+        Some(node.value)
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Items<T> {
+    fn drop(&mut self) {
+        while self.next().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::prelude::*;
+
+    use alloc::arc::Arc;
+
+    use native;
+    use super::Stack;
+
+    #[test]
+    fn smoke() {
+        let s = Stack::new();
+        assert!(s.is_empty());
+        s.push(box 1i);
+        s.push(box 2i);
+        assert_eq!(unsafe { s.pop() }, Some(box 2));
+        s.push(box 3i);
+        assert_eq!(s.pop_all().collect::<Vec<Box<int>>>(), vec![box 3, box 1]);
+        assert_eq!(unsafe { s.pop() }, None);
+    }
+
+    #[test]
+    fn drop_full() {
+        let s = Stack::new();
+        s.push(box 1i);
+        s.push(box 2i);
+        let mut items = s.pop_all();
+        s.push(box 3i);
+        assert_eq!(items.next(), Some(box 2));
+    }
+
+    #[test]
+    fn stress() {
+        let nthreads = 8u;
+        let nmsgs = 1000u;
+        let s = Arc::new(Stack::new());
+        let (tx, rx) = channel();
+
+        for _ in range(0, nthreads) {
+            let tx = tx.clone();
+            let s = s.clone();
+            native::task::spawn(proc() {
+                for i in range(0, nmsgs) {
+                    s.push(i);
+                }
+                tx.send(());
+            });
+        }
+
+        // This is the only popper, so it may mix `pop` and `pop_all`
+        let mut i = 0u;
+        while i < nthreads * nmsgs {
+            match unsafe { s.pop() } {
+                Some(..) => i += 1,
+                None => i += s.pop_all().count(),
+            }
+        }
+        drop(tx);
+        for _ in range(0, nthreads) {
+            rx.recv();
+        }
+        assert!(s.is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// This file was generated by src/etc/mkcommheader.py from
+// src/libstd/c_comm.rs. Do not edit it by hand.
+
+#ifndef RUST_COMM_H
+#define RUST_COMM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Neither half of a channel may be used by two threads at the same time.
+ * Give each sending thread its own `rust_chan` with `rust_chan_clone`, or
+ * guard a shared one with a mutex.
+ */
+typedef struct rust_chan rust_chan;
+typedef struct rust_port rust_port;
+
+/*
+ * Creates a channel, storing its two halves in `*chan` and `*port`.
+ */
+void rust_chan_new(rust_chan **chan, rust_port **port);
+
+/*
+ * Returns another sending half for the channel of `chan`.
+ *
+ * A `rust_chan` must only be used by one thread at a time, so each thread
+ * which sends needs a clone of its own. The clone must be made by the thread
+ * which is using `chan`, and then handed to the new thread.
+ */
+rust_chan *rust_chan_clone(const rust_chan *chan);
+
+/*
+ * Sends a copy of the `len` bytes at `data`. Returns 0 on success, and -1 if
+ * the port of the channel has been freed.
+ *
+ * `chan` must not be used by another thread at the same time, clone it with
+ * `rust_chan_clone` for each thread instead, or guard it with a mutex.
+ */
+int rust_chan_send(const rust_chan *chan, const uint8_t *data, size_t len);
+
+/*
+ * Frees a sending half of a channel.
+ */
+void rust_chan_free(rust_chan *chan);
+
+/*
+ * Blocks until a message is received, storing it in `*data` and its length
+ * in `*len`. The message is allocated with `malloc`, and must be released
+ * with `free`. It is null if it's empty.
+ *
+ * Returns 0 once a message is received, and -1 if all of the sending halves
+ * of the channel have been freed and no message is left.
+ */
+int rust_port_recv(const rust_port *port, uint8_t **data, size_t *len);
+
+/*
+ * Receives a message if one is available, like `rust_port_recv`. Returns 0
+ * if a message was received, 1 if there was none, and -1 if all of the
+ * sending halves of the channel have been freed and no message is left.
+ */
+int rust_port_try_recv(const rust_port *port, uint8_t **data, size_t *len);
+
+/*
+ * Frees the receiving half of a channel. The messages it hadn't received are
+ * dropped.
+ */
+void rust_port_free(rust_port *port);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUST_COMM_H */
@@ -167,3 +167,14 @@ int
 rust_uv_guess_handle(int fd) {
   return uv_guess_handle(fd);
 }
+
+// Returns the descriptor of a stream, or -1 where streams aren't backed by
+// descriptors
+int
+rust_uv_stream_fd(uv_stream_t* handle) {
+#ifdef __WIN32__
+  return -1;
+#else
+  return handle->io_watcher.fd;
+#endif
+}
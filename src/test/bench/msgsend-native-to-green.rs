@@ -0,0 +1,86 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Measures messages sent from native threads to a green task. Whenever the
+// green task is blocked, a send has to wake up its scheduler's event loop
+// from another thread, so this is dominated by the cost of remote wakeups:
+// the stream and shared cases show how well bursts of them are batched, and
+// the ping-pong case the latency of a single one.
+
+#![no_start]
+
+extern crate green;
+extern crate native;
+extern crate rustuv;
+extern crate time;
+
+use std::os;
+
+#[start]
+fn start(argc: int, argv: *const *const u8) -> int {
+    green::start(argc, argv, rustuv::event_loop, main)
+}
+
+fn stream(n: uint) -> f64 {
+    let (tx, rx) = channel();
+    let start = time::precise_time_s();
+    native::task::spawn(proc() {
+        for i in range(0, n) { tx.send(i); }
+    });
+    for _ in range(0, n) { rx.recv(); }
+    time::precise_time_s() - start
+}
+
+fn shared(n: uint, senders: uint) -> f64 {
+    let (tx, rx) = channel();
+    let start = time::precise_time_s();
+    for _ in range(0, senders) {
+        let tx = tx.clone();
+        native::task::spawn(proc() {
+            for i in range(0, n / senders) { tx.send(i); }
+        });
+    }
+    drop(tx);
+    for _ in rx.iter() {}
+    time::precise_time_s() - start
+}
+
+fn pingpong(n: uint) -> f64 {
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    let start = time::precise_time_s();
+    native::task::spawn(proc() {
+        for i in range(0, n) {
+            tx1.send(i);
+            rx2.recv();
+        }
+    });
+    for _ in range(0, n) { tx2.send(rx1.recv()); }
+    time::precise_time_s() - start
+}
+
+fn main() {
+    let args = os::args();
+    let args = args.as_slice();
+    let n = if os::getenv("RUST_BENCH").is_some() {
+        1000000
+    } else if args.len() > 1 {
+        from_str::<uint>(args[1].as_slice()).unwrap()
+    } else {
+        10000
+    };
+
+    let elapsed = stream(n);
+    println!("stream: {} msgs/sec", (n as f64) / elapsed);
+    let elapsed = shared(n, 4);
+    println!("shared: {} msgs/sec", (n as f64) / elapsed);
+    let elapsed = pingpong(n);
+    println!("ping-pong: {} round trips/sec", (n as f64) / elapsed);
+}
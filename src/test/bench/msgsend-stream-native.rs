@@ -0,0 +1,60 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Measures the throughput of stream and shared channels between native
+// threads. Unlike the green ping-pong benchmarks, the two halves of each
+// channel run on different cores here, so this is sensitive to false sharing
+// between the fields of the channel touched by the sender and the receiver.
+
+extern crate time;
+extern crate native;
+
+use std::os;
+
+fn stream(n: uint) -> f64 {
+    let (tx, rx) = channel();
+    let start = time::precise_time_s();
+    native::task::spawn(proc() {
+        for i in range(0, n) { tx.send(i); }
+    });
+    for _ in range(0, n) { rx.recv(); }
+    time::precise_time_s() - start
+}
+
+fn shared(n: uint, senders: uint) -> f64 {
+    let (tx, rx) = channel();
+    let start = time::precise_time_s();
+    for _ in range(0, senders) {
+        let tx = tx.clone();
+        native::task::spawn(proc() {
+            for i in range(0, n / senders) { tx.send(i); }
+        });
+    }
+    drop(tx);
+    for _ in rx.iter() {}
+    time::precise_time_s() - start
+}
+
+fn main() {
+    let args = os::args();
+    let args = args.as_slice();
+    let n = if os::getenv("RUST_BENCH").is_some() {
+        10000000
+    } else if args.len() > 1 {
+        from_str::<uint>(args[1].as_slice()).unwrap()
+    } else {
+        100000
+    };
+
+    let elapsed = stream(n);
+    println!("stream: {} msgs/sec", (n as f64) / elapsed);
+    let elapsed = shared(n, 4);
+    println!("shared: {} msgs/sec", (n as f64) / elapsed);
+}
@@ -24,11 +24,14 @@ use std::uint;
 // tasks ping-pong back and forth over a pair of streams. This is a
 // cannonical message-passing benchmark as it heavily strains message
 // passing and almost nothing else.
+//
+// Passing `handoff` as a third argument sends with `send_yield`, so every
+// message hands the rest of the sender's time slice to its receiver.
 
-fn ping_pong_bench(n: uint, m: uint) {
+fn ping_pong_bench(n: uint, m: uint, handoff: bool) {
 
     // Create pairs of tasks that pingpong back and forth.
-    fn run_pair(n: uint) {
+    fn run_pair(n: uint, handoff: bool) {
         // Create a stream A->B
         let (atx, arx) = channel::<()>();
         // Create a stream B->A
@@ -37,7 +40,7 @@ fn ping_pong_bench(n: uint, m: uint) {
         spawn(proc() {
             let (tx, rx) = (atx, brx);
             for _ in range(0, n) {
-                tx.send(());
+                if handoff { tx.send_yield(()) } else { tx.send(()) }
                 rx.recv();
             }
         });
@@ -46,13 +49,13 @@ fn ping_pong_bench(n: uint, m: uint) {
             let (tx, rx) = (btx, arx);
             for _ in range(0, n) {
                 rx.recv();
-                tx.send(());
+                if handoff { tx.send_yield(()) } else { tx.send(()) }
             }
         });
     }
 
     for _ in range(0, m) {
-        run_pair(n)
+        run_pair(n, handoff)
     }
 }
 
@@ -62,18 +65,20 @@ fn main() {
 
     let args = os::args();
     let args = args.as_slice();
-    let n = if args.len() == 3 {
+    let n = if args.len() >= 3 {
         from_str::<uint>(args[1].as_slice()).unwrap()
     } else {
         10000
     };
 
-    let m = if args.len() == 3 {
+    let m = if args.len() >= 3 {
         from_str::<uint>(args[2].as_slice()).unwrap()
     } else {
         4
     };
 
-    ping_pong_bench(n, m);
+    let handoff = args.len() == 4 && args[3].as_slice() == "handoff";
+
+    ping_pong_bench(n, m, handoff);
 
 }
@@ -0,0 +1,37 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// ignore-stage1
+// ignore-pretty
+
+#![feature(phase)]
+
+#[phase(plugin)]
+extern crate rpc;
+
+rpc! {
+    trait Generic {
+        fn get<T>(&self) -> int; //~ ERROR can't have type or lifetime parameters
+    }
+}
+
+rpc! {
+    trait Static {
+        fn new() -> int; //~ ERROR must take `self` by reference
+    }
+}
+
+rpc! {
+    trait Pattern {
+        fn add(&self, (a, b): (int, int)) -> int; //~ ERROR must be bound to names
+    }
+}
+
+fn main() {}
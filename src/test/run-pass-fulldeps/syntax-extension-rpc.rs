@@ -0,0 +1,105 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// ignore-stage1
+// ignore-pretty
+
+#![feature(phase)]
+
+#[phase(plugin)]
+extern crate rpc;
+
+use std::comm::duplex;
+
+#[deriving(PartialEq, Show)]
+struct Point {
+    x: int,
+    y: int,
+}
+
+rpc! {
+    /// A store of points
+    trait Points {
+        fn push(&mut self, p: Point);
+        fn get(&self, i: uint) -> Option<Point>;
+        fn translate(&mut self, dx: int, dy: int) -> uint;
+        fn clear(&mut self);
+    }
+}
+
+struct Store {
+    points: Vec<Point>,
+}
+
+impl Points for Store {
+    fn push(&mut self, p: Point) { self.points.push(p) }
+    fn get(&self, i: uint) -> Option<Point> {
+        self.points.as_slice().get(i).map(|p| Point { x: p.x, y: p.y })
+    }
+    fn translate(&mut self, dx: int, dy: int) -> uint {
+        for p in self.points.mut_iter() {
+            p.x += dx;
+            p.y += dy;
+        }
+        self.points.len()
+    }
+    fn clear(&mut self) { self.points.clear() }
+}
+
+// An argument named like the server, and a method named like one of `Points`
+rpc! {
+    pub trait Echo {
+        fn echo(&mut self, server: String) -> String;
+        fn clear(&mut self);
+    }
+}
+
+struct Echoer;
+
+impl Echo for Echoer {
+    fn echo(&mut self, server: String) -> String { server }
+    fn clear(&mut self) {}
+}
+
+fn main() {
+    let client = PointsClient::spawn(Store { points: Vec::new() });
+    client.push(Point { x: 1, y: 2 });
+    client.push(Point { x: 3, y: 4 });
+    assert_eq!(client.translate(1, -1), 2);
+    assert_eq!(client.get(1), Some(Point { x: 4, y: 3 }));
+    assert_eq!(client.get(2), None);
+
+    // Requests can be made and dispatched by hand
+    match client.call(PointsGetRequest(0)) {
+        PointsGetResponse(p) => assert_eq!(p, Some(Point { x: 2, y: 1 })),
+        _ => fail!(),
+    }
+    let mut store = Store { points: Vec::new() };
+    match PointsPushRequest(Point { x: 0, y: 0 }).dispatch(&mut store) {
+        PointsPushResponse => {}
+        _ => fail!(),
+    }
+    assert_eq!(store.points.len(), 1);
+
+    // The server is given back once the client is dropped
+    let (client_end, server_end) = duplex();
+    let client = PointsClient::new(client_end);
+    let (tx, rx) = channel();
+    spawn(proc() {
+        tx.send(PointsServer::new(store, server_end).run());
+    });
+    client.clear();
+    drop(client);
+    assert_eq!(rx.recv().points.len(), 0);
+
+    let echo = EchoClient::spawn(Echoer);
+    assert_eq!(echo.echo("hello".to_string()).as_slice(), "hello");
+    echo.clear();
+}
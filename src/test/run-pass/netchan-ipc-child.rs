@@ -0,0 +1,46 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// ignore-windows
+
+// A child inherits the receiving end of an IPC channel, and is then sent the
+// sending end of another one to reply on.
+
+extern crate netchan;
+
+use std::io::Command;
+use std::io::process::InheritFd;
+use std::os;
+
+use netchan::ipc::{channel, IpcSender, IpcReceiver};
+
+fn main() {
+    let args = os::args();
+    if args.len() > 1 && args.get(1).as_slice() == "child" {
+        let rx: IpcReceiver<(IpcSender<String>, int)> = unsafe {
+            IpcReceiver::from_fd(3)
+        };
+        let (reply, n) = rx.open().recv();
+        reply.open().send(format!("got {}", n));
+        return
+    }
+
+    let (tx, rx) = channel::<(IpcSender<String>, int)>().unwrap();
+    let mut child = Command::new(args.get(0).as_slice())
+                            .arg("child")
+                            .extra_io(InheritFd(rx.fd()))
+                            .spawn().unwrap();
+    drop(rx);
+
+    let (reply_tx, reply_rx) = channel::<String>().unwrap();
+    tx.open().send((reply_tx, 3));
+    assert_eq!(reply_rx.open().recv().as_slice(), "got 3");
+    assert!(child.wait().unwrap().success());
+}
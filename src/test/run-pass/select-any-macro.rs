@@ -0,0 +1,51 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![feature(macro_rules)]
+
+enum Event {
+    Number(int),
+    Text(String),
+    Done(Result<(), ()>),
+}
+
+fn main() {
+    let (tx1, rx1) = channel::<int>();
+    let (tx2, rx2) = channel::<String>();
+    let (tx3, rx3) = channel::<()>();
+
+    tx2.send("hello".to_string());
+    match select_any! {
+        rx1.recv() => Number,
+        rx2.recv() => Text,
+        rx3.recv_opt() => Done
+    } {
+        Text(s) => assert_eq!(s.as_slice(), "hello"),
+        _ => fail!(),
+    }
+
+    spawn(proc() { tx1.send(4) });
+    match select_any! {
+        rx1.recv() => Number,
+        rx2.recv() => Text
+    } {
+        Number(n) => assert_eq!(n, 4),
+        _ => fail!(),
+    }
+
+    drop(tx3);
+    match select_any! {
+        rx2.recv() => Text,
+        rx3.recv_opt() => Done
+    } {
+        Done(r) => assert_eq!(r, Err(())),
+        _ => fail!(),
+    }
+}
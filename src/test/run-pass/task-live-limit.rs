@@ -0,0 +1,45 @@
+// Copyright 2014 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// The limit on live tasks is global, so this runs in a process of its own
+
+use std::io::timer;
+use std::task;
+
+pub fn main() {
+    task::set_max_live_tasks(Some(1));
+    assert_eq!(task::max_live_tasks(), Some(1));
+
+    let (go_tx, go_rx) = channel::<()>();
+    let (order_tx, order_rx) = channel();
+    let order_tx2 = order_tx.clone();
+    task::spawn(proc() {
+        go_rx.recv();
+        timer::sleep(100);
+        order_tx.send("first");
+    });
+
+    // The first task takes the only place, so this gives the proc back
+    let (ran_tx, ran_rx) = channel();
+    match task::try_spawn(proc() ran_tx.send(())) {
+        Ok(()) => fail!("spawned a task past the limit"),
+        Err(f) => f(),
+    }
+    ran_rx.recv();
+
+    // This blocks until the first task has exited, which is after it sent
+    go_tx.send(());
+    task::spawn(proc() order_tx2.send("second"));
+    assert_eq!(order_rx.try_recv(), Ok("first"));
+    assert_eq!(order_rx.recv(), "second");
+
+    task::set_max_live_tasks(None);
+    assert!(task::try_spawn(proc() {}).is_ok());
+}